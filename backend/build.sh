@@ -9,6 +9,7 @@ echo "--- Building plugin backend ---"
 cargo build --release
 mkdir -p out
 cp target/release/wine-cask out/backend
+cp target/release/wine-cellar-cli out/wine-cellar-cli
 
 echo " --- Cleaning up ---"
 # remove root-owned target folder
@@ -0,0 +1,271 @@
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+/// Steam sets the top bit on every non-Steam shortcut id.
+const SHORTCUT_BIT: u32 = 0x8000_0000;
+/// Low bits of the 64-bit game id Steam used to key shortcuts with.
+const LEGACY_SHORTCUT_FLAGS: u64 = 0x0200_0000;
+
+/// App id of a Steam store app, as found in appmanifest files.
+#[derive(Serialize, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy, Debug)]
+#[serde(transparent)]
+pub struct AppId(u32);
+
+/// Id of a non-Steam shortcut, as found in shortcuts.vdf.
+#[derive(Serialize, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy, Debug)]
+#[serde(transparent)]
+pub struct ShortcutId(u32);
+
+/// Key of a CompatToolMapping entry in config.vdf.
+///
+/// Depending on the entry this is the default tool (`0`), an app id, a shortcut id or a legacy
+/// shortcut game id.
+#[derive(Serialize, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy, Debug)]
+#[serde(transparent)]
+pub struct CompatAppId(u64);
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum CompatAppIdKind {
+    /// The tool used for every app without an entry of its own.
+    Default,
+    App(AppId),
+    Shortcut(ShortcutId),
+    LegacyShortcut(ShortcutId),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppIdError {
+    /// The value is zero or has the shortcut bit set.
+    InvalidAppId(u64),
+    /// The value doesn't have the shortcut bit set.
+    InvalidShortcutId(u64),
+    /// The value is neither an app id, a shortcut id nor a legacy shortcut game id.
+    InvalidCompatAppId(u64),
+    /// The value isn't a number.
+    ParsingError(String),
+}
+
+impl AppId {
+    pub fn new(value: u32) -> Result<Self, AppIdError> {
+        if value == 0 || value & SHORTCUT_BIT != 0 {
+            return Err(AppIdError::InvalidAppId(value as u64));
+        }
+        Ok(Self(value))
+    }
+
+    pub fn value(self) -> u32 {
+        self.0
+    }
+}
+
+impl ShortcutId {
+    pub fn new(value: u32) -> Result<Self, AppIdError> {
+        if value & SHORTCUT_BIT == 0 {
+            return Err(AppIdError::InvalidShortcutId(value as u64));
+        }
+        Ok(Self(value))
+    }
+
+    /// Converts the signed integer shortcuts.vdf stores the id as.
+    pub fn from_signed(value: i32) -> Result<Self, AppIdError> {
+        Self::new(value as u32)
+    }
+
+    pub fn value(self) -> u32 {
+        self.0
+    }
+
+    /// The 64-bit game id older Steam clients keyed shortcuts with.
+    pub fn legacy_game_id(self) -> u64 {
+        ((self.0 as u64) << 32) | LEGACY_SHORTCUT_FLAGS
+    }
+}
+
+impl CompatAppId {
+    pub const DEFAULT: CompatAppId = CompatAppId(0);
+
+    pub fn new(value: u64) -> Result<Self, AppIdError> {
+        let compat_app_id = Self(value);
+        if value > u32::MAX as u64
+            && (value & 0xFFFF_FFFF != LEGACY_SHORTCUT_FLAGS
+                || ShortcutId::new((value >> 32) as u32).is_err())
+        {
+            return Err(AppIdError::InvalidCompatAppId(value));
+        }
+        Ok(compat_app_id)
+    }
+
+    pub fn value(self) -> u64 {
+        self.0
+    }
+
+    pub fn kind(self) -> CompatAppIdKind {
+        if self.0 == 0 {
+            CompatAppIdKind::Default
+        } else if self.0 > u32::MAX as u64 {
+            CompatAppIdKind::LegacyShortcut(ShortcutId((self.0 >> 32) as u32))
+        } else if self.0 as u32 & SHORTCUT_BIT != 0 {
+            CompatAppIdKind::Shortcut(ShortcutId(self.0 as u32))
+        } else {
+            CompatAppIdKind::App(AppId(self.0 as u32))
+        }
+    }
+
+    /// The store app this entry maps, `None` for shortcuts and the default entry.
+    pub fn app_id(self) -> Option<AppId> {
+        match self.kind() {
+            CompatAppIdKind::App(app_id) => Some(app_id),
+            _ => None,
+        }
+    }
+
+    /// The shortcut this entry maps, in either the current or the legacy form.
+    pub fn shortcut_id(self) -> Option<ShortcutId> {
+        match self.kind() {
+            CompatAppIdKind::Shortcut(shortcut_id)
+            | CompatAppIdKind::LegacyShortcut(shortcut_id) => Some(shortcut_id),
+            _ => None,
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, AppIdError> {
+        let value: u64 = value
+            .parse()
+            .map_err(|_| AppIdError::ParsingError(value.to_string()))?;
+        Self::new(value)
+    }
+}
+
+impl From<AppId> for CompatAppId {
+    fn from(app_id: AppId) -> Self {
+        Self(app_id.0 as u64)
+    }
+}
+
+impl From<ShortcutId> for CompatAppId {
+    fn from(shortcut_id: ShortcutId) -> Self {
+        Self(shortcut_id.0 as u64)
+    }
+}
+
+impl<'de> Deserialize<'de> for AppId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        AppId::new(u32::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for ShortcutId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        ShortcutId::new(u32::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for CompatAppId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        CompatAppId::new(u64::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+impl Display for AppId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Display for ShortcutId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Display for CompatAppId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Display for AppIdError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AppIdError::InvalidAppId(value) => write!(f, "Invalid app id: {}", value),
+            AppIdError::InvalidShortcutId(value) => write!(f, "Invalid shortcut id: {}", value),
+            AppIdError::InvalidCompatAppId(value) => {
+                write!(f, "Invalid compatibility tool mapping id: {}", value)
+            }
+            AppIdError::ParsingError(value) => write!(f, "Failed to parse id: {}", value),
+        }
+    }
+}
+
+impl Error for AppIdError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shortcut_conversions() {
+        // (signed id in shortcuts.vdf, unsigned id, legacy game id)
+        let conversions: [(i32, u32, u64); 3] = [
+            (-1066383326, 3228583970, 13866662563573399552),
+            (-2147483647, 2147483649, 9223372041183297536),
+            (-1, 4294967295, 18446744069448138752),
+        ];
+        for (signed, unsigned, legacy_game_id) in conversions {
+            let shortcut_id = ShortcutId::from_signed(signed).unwrap();
+            assert_eq!(shortcut_id.value(), unsigned);
+            assert_eq!(shortcut_id.legacy_game_id(), legacy_game_id);
+
+            let current = CompatAppId::from(shortcut_id);
+            assert_eq!(current.value(), unsigned as u64);
+            assert_eq!(current.kind(), CompatAppIdKind::Shortcut(shortcut_id));
+
+            let legacy = CompatAppId::new(legacy_game_id).unwrap();
+            assert_eq!(legacy.kind(), CompatAppIdKind::LegacyShortcut(shortcut_id));
+            assert_eq!(legacy.shortcut_id(), Some(shortcut_id));
+            assert_eq!(legacy.app_id(), None);
+        }
+    }
+
+    #[test]
+    fn test_compat_app_id_kinds() {
+        assert_eq!(
+            CompatAppId::parse("0").unwrap().kind(),
+            CompatAppIdKind::Default
+        );
+        let elden_ring = CompatAppId::parse("1245620").unwrap();
+        assert_eq!(elden_ring.app_id(), Some(AppId::new(1245620).unwrap()));
+        assert_eq!(elden_ring.shortcut_id(), None);
+
+        assert!(CompatAppId::parse("not a number").is_err());
+        // Above 32 bits but not a legacy shortcut game id
+        assert!(CompatAppId::new(1 << 40).is_err());
+        assert!(CompatAppId::new((1245620 << 32) | LEGACY_SHORTCUT_FLAGS).is_err());
+    }
+
+    #[test]
+    fn test_app_id_validation() {
+        assert!(AppId::new(0).is_err());
+        assert!(AppId::new(3228583970).is_err());
+        assert!(ShortcutId::new(1245620).is_err());
+    }
+
+    #[test]
+    fn test_serialized_as_plain_numbers() {
+        let app_id = AppId::new(1245620).unwrap();
+        assert_eq!(serde_json::to_string(&app_id).unwrap(), "1245620");
+        assert_eq!(serde_json::from_str::<AppId>("1245620").unwrap(), app_id);
+        assert!(serde_json::from_str::<AppId>("3228583970").is_err());
+        assert!(serde_json::from_str::<ShortcutId>("1245620").is_err());
+        assert_eq!(
+            serde_json::to_string(&CompatAppId::new(3228583970).unwrap()).unwrap(),
+            "3228583970"
+        );
+    }
+}
@@ -0,0 +1,643 @@
+use log::warn;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+/// appinfo.vdf before Steam started storing a checksum of the binary data.
+const MAGIC_V27: u32 = 0x0756_4427;
+const MAGIC_V28: u32 = 0x0756_4428;
+/// Keys are indices into a string table at the end of the file.
+const MAGIC_V29: u32 = 0x0756_4429;
+
+/// App whose data lists the compatibility tools Steam ships itself.
+const STEAM_PLAY_MANIFESTS_APP_ID: u32 = 891390;
+
+const TYPE_OBJECT: u8 = 0x00;
+const TYPE_STRING: u8 = 0x01;
+const TYPE_INT32: u8 = 0x02;
+const TYPE_FLOAT32: u8 = 0x03;
+const TYPE_POINTER: u8 = 0x04;
+const TYPE_COLOR: u8 = 0x06;
+const TYPE_UINT64: u8 = 0x07;
+const TYPE_END: u8 = 0x08;
+const TYPE_INT64: u8 = 0x0a;
+
+/// Value of Steam's binary KeyValues format.
+#[derive(PartialEq, Clone, Debug)]
+pub enum KeyValue {
+    Object(Vec<(String, KeyValue)>),
+    String(String),
+    Int32(i32),
+    Float32(f32),
+    UInt64(u64),
+    Int64(i64),
+}
+
+impl KeyValue {
+    pub fn get(&self, key: &str) -> Option<&KeyValue> {
+        match self {
+            KeyValue::Object(entries) => entries
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut KeyValue> {
+        match self {
+            KeyValue::Object(entries) => entries
+                .iter_mut()
+                .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Replaces the value named `key`, or appends it to the object if there's none yet.
+    pub fn set(&mut self, key: &str, value: KeyValue) {
+        if let Some(existing) = self.get_mut(key) {
+            *existing = value;
+        } else if let KeyValue::Object(entries) = self {
+            entries.push((key.to_string(), value));
+        }
+    }
+
+    /// Finds the first value named `key` at any depth.
+    pub fn find(&self, key: &str) -> Option<&KeyValue> {
+        let KeyValue::Object(entries) = self else {
+            return None;
+        };
+        self.get(key)
+            .or_else(|| entries.iter().find_map(|(_, value)| value.find(key)))
+    }
+
+    pub fn entries(&self) -> &[(String, KeyValue)] {
+        match self {
+            KeyValue::Object(entries) => entries,
+            _ => &[],
+        }
+    }
+
+    pub fn entries_mut(&mut self) -> &mut [(String, KeyValue)] {
+        match self {
+            KeyValue::Object(entries) => entries,
+            _ => &mut [],
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            KeyValue::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    /// Steam stores numbers as strings in some sections and as integers in others.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            KeyValue::String(string) => string.parse().ok(),
+            KeyValue::Int32(value) => u64::try_from(*value).ok(),
+            KeyValue::UInt64(value) => Some(*value),
+            KeyValue::Int64(value) => u64::try_from(*value).ok(),
+            KeyValue::Object(_) | KeyValue::Float32(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppInfoError {
+    /// The file doesn't start with a known appinfo.vdf magic number.
+    UnsupportedVersion(u32),
+    /// The file ended in the middle of an entry.
+    UnexpectedEnd,
+    UnknownType(u8),
+    /// A key refers to a string outside the string table.
+    InvalidStringIndex(u32),
+}
+
+impl Display for AppInfoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AppInfoError::UnsupportedVersion(magic) => {
+                write!(f, "Unsupported appinfo.vdf version: {:#x}", magic)
+            }
+            AppInfoError::UnexpectedEnd => write!(f, "Unexpected end of appinfo.vdf"),
+            AppInfoError::UnknownType(r#type) => {
+                write!(f, "Unknown appinfo.vdf value type: {:#x}", r#type)
+            }
+            AppInfoError::InvalidStringIndex(index) => {
+                write!(f, "Invalid appinfo.vdf string index: {}", index)
+            }
+        }
+    }
+}
+
+impl Error for AppInfoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], AppInfoError> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(AppInfoError::UnexpectedEnd)?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, AppInfoError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, AppInfoError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, AppInfoError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn c_string(&mut self) -> Result<String, AppInfoError> {
+        let length = self.bytes[self.position..]
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or(AppInfoError::UnexpectedEnd)?;
+        let string = String::from_utf8_lossy(self.take(length)?).to_string();
+        self.position += 1;
+        Ok(string)
+    }
+}
+
+/// Reads key value pairs until the end marker of the enclosing object.
+fn read_object(reader: &mut Reader, strings: Option<&[String]>) -> Result<KeyValue, AppInfoError> {
+    let mut entries = Vec::new();
+    loop {
+        let r#type = reader.u8()?;
+        if r#type == TYPE_END {
+            return Ok(KeyValue::Object(entries));
+        }
+        let key = match strings {
+            Some(strings) => {
+                let index = reader.u32()?;
+                strings
+                    .get(index as usize)
+                    .cloned()
+                    .ok_or(AppInfoError::InvalidStringIndex(index))?
+            }
+            None => reader.c_string()?,
+        };
+        let value = match r#type {
+            TYPE_OBJECT => read_object(reader, strings)?,
+            TYPE_STRING => KeyValue::String(reader.c_string()?),
+            TYPE_INT32 | TYPE_POINTER | TYPE_COLOR => KeyValue::Int32(reader.u32()? as i32),
+            TYPE_FLOAT32 => KeyValue::Float32(f32::from_bits(reader.u32()?)),
+            TYPE_UINT64 => KeyValue::UInt64(reader.u64()?),
+            TYPE_INT64 => KeyValue::Int64(reader.u64()? as i64),
+            r#type => return Err(AppInfoError::UnknownType(r#type)),
+        };
+        entries.push((key, value));
+    }
+}
+
+fn read_string_table(bytes: &[u8], offset: u64) -> Result<Vec<String>, AppInfoError> {
+    let mut reader = Reader {
+        bytes,
+        position: usize::try_from(offset).map_err(|_| AppInfoError::UnexpectedEnd)?,
+    };
+    let count = reader.u32()?;
+    (0..count).map(|_| reader.c_string()).collect()
+}
+
+/// The apps of an appinfo.vdf, each entry is only decoded when asked for.
+struct AppEntries<'a> {
+    reader: Reader<'a>,
+    magic: u32,
+    strings: Option<Vec<String>>,
+}
+
+impl<'a> AppEntries<'a> {
+    fn new(bytes: &'a [u8]) -> Result<Self, AppInfoError> {
+        let mut reader = Reader { bytes, position: 0 };
+        let magic = reader.u32()?;
+        if !matches!(magic, MAGIC_V27 | MAGIC_V28 | MAGIC_V29) {
+            return Err(AppInfoError::UnsupportedVersion(magic));
+        }
+        let _universe = reader.u32()?;
+        let strings = match magic {
+            MAGIC_V29 => Some(read_string_table(bytes, reader.u64()?)?),
+            _ => None,
+        };
+        Ok(Self {
+            reader,
+            magic,
+            strings,
+        })
+    }
+
+    /// Returns the app id and undecoded bytes of the next app, `None` after the last one.
+    fn next_entry(&mut self) -> Result<Option<(u32, &'a [u8])>, AppInfoError> {
+        let app_id = self.reader.u32()?;
+        if app_id == 0 {
+            return Ok(None);
+        }
+        let size = self.reader.u32()? as usize;
+        Ok(Some((app_id, self.reader.take(size)?)))
+    }
+
+    fn decode(&self, entry: &[u8]) -> Result<KeyValue, AppInfoError> {
+        let mut reader = Reader {
+            bytes: entry,
+            position: 0,
+        };
+        // info state, last updated, access token, checksum and change number
+        reader.take(4 + 4 + 8 + 20 + 4)?;
+        if self.magic != MAGIC_V27 {
+            // Checksum of the binary data
+            reader.take(20)?;
+        }
+        read_object(&mut reader, self.strings.as_deref())
+    }
+}
+
+/// Returns the data of one app from the contents of appcache/appinfo.vdf, skipping over the others.
+pub fn find_app(bytes: &[u8], app_id: u32) -> Result<Option<KeyValue>, AppInfoError> {
+    let mut entries = AppEntries::new(bytes)?;
+    while let Some((entry_app_id, entry)) = entries.next_entry()? {
+        if entry_app_id == app_id {
+            return entries.decode(entry).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Reads the internal names Steam registered for the compatibility tools it ships itself, e.g.
+/// `proton_9`, by the app id each tool is installed as.
+pub fn read_compat_tool_names(bytes: &[u8]) -> Result<HashMap<u32, String>, AppInfoError> {
+    let manifests = find_app(bytes, STEAM_PLAY_MANIFESTS_APP_ID)?;
+    Ok(manifests
+        .as_ref()
+        .and_then(|manifests| manifests.find("compat_tools"))
+        .map(KeyValue::entries)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|(name, tool)| {
+            let app_id = u32::try_from(tool.get("appid")?.as_u64()?).ok()?;
+            Some((app_id, name.clone()))
+        })
+        .collect())
+}
+
+/// Reads the name of every app in the contents of appcache/appinfo.vdf. Apps that can't be decoded
+/// are left out, only a broken header or entry list fails the whole file.
+pub fn read_app_names(bytes: &[u8]) -> Result<HashMap<u32, String>, AppInfoError> {
+    let mut entries = AppEntries::new(bytes)?;
+    let mut names = HashMap::new();
+    while let Some((app_id, entry)) = entries.next_entry()? {
+        let data = match entries.decode(entry) {
+            Ok(data) => data,
+            Err(err) => {
+                warn!("Skipping app {} of appinfo.vdf: {}", app_id, err);
+                continue;
+            }
+        };
+        let name = data
+            .get("appinfo")
+            .and_then(|appinfo| appinfo.get("common"))
+            .and_then(|common| common.get("name"))
+            .and_then(KeyValue::as_str);
+        if let Some(name) = name {
+            names.insert(app_id, name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// Writes the entries of `value` in the binary KeyValues format, followed by its end marker.
+/// Keys go into `strings` instead when the format has a string table.
+fn write_object(bytes: &mut Vec<u8>, strings: &mut Option<Vec<String>>, value: &KeyValue) {
+    for (key, value) in value.entries() {
+        let r#type = match value {
+            KeyValue::Object(_) => TYPE_OBJECT,
+            KeyValue::String(_) => TYPE_STRING,
+            KeyValue::Int32(_) => TYPE_INT32,
+            KeyValue::Float32(_) => TYPE_FLOAT32,
+            KeyValue::UInt64(_) => TYPE_UINT64,
+            KeyValue::Int64(_) => TYPE_INT64,
+        };
+        bytes.push(r#type);
+        match strings {
+            Some(strings) => {
+                let index = strings
+                    .iter()
+                    .position(|string| string == key)
+                    .unwrap_or_else(|| {
+                        strings.push(key.clone());
+                        strings.len() - 1
+                    });
+                bytes.extend((index as u32).to_le_bytes());
+            }
+            None => {
+                bytes.extend(key.as_bytes());
+                bytes.push(0);
+            }
+        }
+        match value {
+            KeyValue::Object(_) => write_object(bytes, strings, value),
+            KeyValue::String(string) => {
+                bytes.extend(string.as_bytes());
+                bytes.push(0);
+            }
+            KeyValue::Int32(value) => bytes.extend(value.to_le_bytes()),
+            KeyValue::Float32(value) => bytes.extend(value.to_le_bytes()),
+            KeyValue::UInt64(value) => bytes.extend(value.to_le_bytes()),
+            KeyValue::Int64(value) => bytes.extend(value.to_le_bytes()),
+        }
+    }
+    bytes.push(TYPE_END);
+}
+
+/// Serializes `value` like Steam writes binary VDF files without a header, such as shortcuts.vdf.
+/// Pointers and colors were read as 32-bit integers and are written back as those.
+pub fn write_key_values(value: &KeyValue) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_object(&mut bytes, &mut None, value);
+    bytes
+}
+
+/// Parses a binary VDF file without a header, such as shortcuts.vdf.
+pub fn read_key_values(bytes: &[u8]) -> Result<KeyValue, AppInfoError> {
+    read_object(&mut Reader { bytes, position: 0 }, None)
+}
+
+#[cfg(test)]
+pub mod fixtures {
+    use super::*;
+
+    /// A shortcuts.vdf with a single shortcut, as Steam writes it.
+    pub const SHORTCUTS_VDF: &[u8] = b"\x00shortcuts\x00\
+        \x000\x00\
+        \x02appid\x00\x15\xcd\x5b\x87\
+        \x01AppName\x00Heroic Games Launcher\x00\
+        \x01Exe\x00\"/usr/bin/heroic\"\x00\
+        \x01LaunchOptions\x00\x00\
+        \x02IsHidden\x00\x00\x00\x00\x00\
+        \x00tags\x00\x010\x00favorite\x00\x08\
+        \x08\
+        \x08\
+        \x08";
+
+    /// Builds an appinfo.vdf with the given apps in the format of `magic`.
+    pub fn build_appinfo(magic: u32, apps: &[(u32, KeyValue)]) -> Vec<u8> {
+        let mut strings = (magic == MAGIC_V29).then(Vec::new);
+        let mut bytes = Vec::new();
+        bytes.extend(magic.to_le_bytes());
+        bytes.extend(1u32.to_le_bytes());
+        if magic == MAGIC_V29 {
+            // String table offset, filled in at the end
+            bytes.extend(0u64.to_le_bytes());
+        }
+        for (app_id, data) in apps {
+            let mut entry = vec![0u8; 4 + 4 + 8 + 20 + 4];
+            if magic != MAGIC_V27 {
+                entry.extend([0u8; 20]);
+            }
+            write_object(&mut entry, &mut strings, data);
+            bytes.extend(app_id.to_le_bytes());
+            bytes.extend((entry.len() as u32).to_le_bytes());
+            bytes.extend(entry);
+        }
+        bytes.extend(0u32.to_le_bytes());
+        if let Some(strings) = strings {
+            let offset = bytes.len() as u64;
+            bytes[8..16].copy_from_slice(&offset.to_le_bytes());
+            bytes.extend((strings.len() as u32).to_le_bytes());
+            for string in strings {
+                bytes.extend(string.as_bytes());
+                bytes.push(0);
+            }
+        }
+        bytes
+    }
+
+    pub fn object(entries: &[(&str, KeyValue)]) -> KeyValue {
+        KeyValue::Object(
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+        )
+    }
+
+    pub fn string(value: &str) -> KeyValue {
+        KeyValue::String(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::*;
+    use super::*;
+
+    fn app(app_id: u32, name: &str) -> KeyValue {
+        object(&[(
+            "appinfo",
+            object(&[
+                ("appid", KeyValue::Int32(app_id as i32)),
+                (
+                    "common",
+                    object(&[
+                        ("name", string(name)),
+                        ("type", string("Game")),
+                        ("size", KeyValue::UInt64(1 << 33)),
+                    ]),
+                ),
+            ]),
+        )])
+    }
+
+    #[test]
+    fn test_find_app_in_every_version() {
+        let apps = [
+            (730, app(730, "Counter-Strike 2")),
+            (1245620, app(1245620, "ELDEN RING")),
+        ];
+        for magic in [MAGIC_V27, MAGIC_V28, MAGIC_V29] {
+            let appinfo = build_appinfo(magic, &apps);
+            let elden_ring = find_app(&appinfo, 1245620).unwrap().unwrap();
+            assert_eq!(elden_ring, apps[1].1);
+            assert_eq!(
+                elden_ring.find("name").and_then(KeyValue::as_str),
+                Some("ELDEN RING")
+            );
+            assert_eq!(
+                elden_ring.find("appid").and_then(KeyValue::as_u64),
+                Some(1245620)
+            );
+            assert_eq!(find_app(&appinfo, 440).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_read_app_names_in_every_version() {
+        let apps = [
+            (730, app(730, "Counter-Strike 2")),
+            (1245620, app(1245620, "ELDEN RING")),
+            // Tools and other apps without a name are left out
+            (891390, object(&[("appinfo", object(&[]))])),
+        ];
+        for magic in [MAGIC_V27, MAGIC_V28, MAGIC_V29] {
+            assert_eq!(
+                read_app_names(&build_appinfo(magic, &apps)).unwrap(),
+                HashMap::from([
+                    (730, "Counter-Strike 2".to_string()),
+                    (1245620, "ELDEN RING".to_string())
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_compat_tool_names() {
+        let compat_tool = |app_id: &str, display_name: &str| {
+            object(&[
+                ("appid", string(app_id)),
+                ("display_name", string(display_name)),
+                ("from_oslist", string("windows")),
+                ("to_oslist", string("linux")),
+            ])
+        };
+        let manifests = object(&[(
+            "appinfo",
+            object(&[
+                ("appid", KeyValue::Int32(891390)),
+                (
+                    "extended",
+                    object(&[(
+                        "compat_tools",
+                        object(&[
+                            ("proton_9", compat_tool("2805730", "Proton 9.0-4")),
+                            (
+                                "proton_experimental",
+                                compat_tool("1493710", "Proton Experimental"),
+                            ),
+                            // Listed without an app of its own
+                            (
+                                "proton_legacy",
+                                object(&[("display_name", string("Legacy"))]),
+                            ),
+                        ]),
+                    )]),
+                ),
+            ]),
+        )]);
+        let appinfo = build_appinfo(
+            MAGIC_V29,
+            &[(730, app(730, "Counter-Strike 2")), (891390, manifests)],
+        );
+
+        assert_eq!(
+            read_compat_tool_names(&appinfo).unwrap(),
+            HashMap::from([
+                (2805730, "proton_9".to_string()),
+                (1493710, "proton_experimental".to_string())
+            ])
+        );
+        let without_manifests = build_appinfo(MAGIC_V29, &[(730, app(730, "Counter-Strike 2"))]);
+        assert_eq!(
+            read_compat_tool_names(&without_manifests).unwrap(),
+            HashMap::new()
+        );
+    }
+
+    #[test]
+    fn test_undecodable_apps_are_skipped() {
+        let mut appinfo = build_appinfo(
+            MAGIC_V28,
+            &[
+                (730, app(730, "Counter-Strike 2")),
+                (1245620, app(1245620, "ELDEN RING")),
+            ],
+        );
+        // Counter-Strike's data starts with an unknown value type, the entry size still says
+        // where ELDEN RING starts
+        let data_start = 4 + 4 + 4 + 4 + (4 + 4 + 8 + 20 + 4) + 20;
+        appinfo[data_start] = 0x0f;
+        assert_eq!(
+            read_app_names(&appinfo).unwrap(),
+            HashMap::from([(1245620, "ELDEN RING".to_string())])
+        );
+        assert_eq!(
+            find_app(&appinfo, 730),
+            Err(AppInfoError::UnknownType(0x0f))
+        );
+    }
+
+    /// shortcuts.vdf with one shortcut, as Steam writes it.
+
+    #[test]
+    fn test_key_values_round_trip() {
+        let shortcuts = read_key_values(SHORTCUTS_VDF).unwrap();
+        let shortcut = shortcuts
+            .get("shortcuts")
+            .and_then(|shortcuts| shortcuts.get("0"));
+        assert_eq!(
+            shortcut.and_then(|shortcut| shortcut.get("AppName")),
+            Some(&string("Heroic Games Launcher"))
+        );
+        assert_eq!(
+            shortcut.and_then(|shortcut| shortcut.get("appid")),
+            Some(&KeyValue::Int32(0x875b_cd15_u32 as i32))
+        );
+
+        let written = write_key_values(&shortcuts);
+        assert_eq!(written, SHORTCUTS_VDF);
+        assert_eq!(read_key_values(&written).unwrap(), shortcuts);
+    }
+
+    #[test]
+    fn test_set_key_values() {
+        let mut shortcuts = read_key_values(SHORTCUTS_VDF).unwrap();
+        let shortcut = shortcuts
+            .get_mut("shortcuts")
+            .and_then(|shortcuts| shortcuts.get_mut("0"))
+            .unwrap();
+        shortcut.set("launchoptions", string("mangohud %command%"));
+        shortcut.set("LastPlayTime", KeyValue::Int32(1700000000));
+        assert_eq!(
+            shortcut.get("LaunchOptions"),
+            Some(&string("mangohud %command%"))
+        );
+        assert_eq!(
+            shortcut.entries().last(),
+            Some(&("LastPlayTime".to_string(), KeyValue::Int32(1700000000)))
+        );
+
+        let written = read_key_values(&write_key_values(&shortcuts)).unwrap();
+        assert_eq!(written, shortcuts);
+    }
+
+    #[test]
+    fn test_malformed_files_are_errors() {
+        assert_eq!(
+            find_app(&0x0756_4426u32.to_le_bytes(), 730),
+            Err(AppInfoError::UnsupportedVersion(0x0756_4426))
+        );
+        let appinfo = build_appinfo(MAGIC_V28, &[(730, app(730, "Counter-Strike 2"))]);
+        assert_eq!(
+            find_app(&appinfo[..appinfo.len() - 10], 730),
+            Err(AppInfoError::UnexpectedEnd)
+        );
+    }
+}
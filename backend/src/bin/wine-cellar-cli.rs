@@ -0,0 +1,515 @@
+//! Drives a running backend from a terminal, e.g. over SSH:
+//!
+//! ```text
+//! wine-cellar-cli list
+//! wine-cellar-cli queue
+//! wine-cellar-cli install ge-proton 9-20
+//! wine-cellar-cli uninstall GE-Proton8-25
+//! ```
+//!
+//! Exits with 0 once done, 1 if the task failed and 2 if the backend couldn't be reached or
+//! didn't accept the session token.
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::io::Write;
+use std::time::Duration;
+use std::{env, fmt, fs, io, process};
+use tokio::net::TcpStream;
+use tokio::time::{timeout_at, Instant};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use wine_cask::runtime_directory;
+use wine_cask::wine_cask::app::{AppState, Request, RequestType, Task, TaskType};
+use wine_cask::wine_cask::app_error::AppErrorCode;
+use wine_cask::wine_cask::flavors::{CompatibilityToolFlavor, SteamCompatibilityTool};
+use wine_cask::wine_cask::history::HistoryTrigger;
+use wine_cask::wine_cask::install::Install;
+use wine_cask::wine_cask::naming::{naming_schemes, release_matches};
+use wine_cask::wine_cask::protocol::PROTOCOL_VERSION;
+use wine_cask::wine_cask::session_token::SESSION_TOKEN_FILE;
+use wine_cask::wine_cask::state_delta::apply_deltas;
+use wine_cask::wine_cask::uninstall::Uninstall;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8887";
+/// How long an install may take to show up in the queue, refused ones never do.
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(10);
+const USAGE: &str = "Usage: wine-cellar-cli [--address HOST:PORT] COMMAND
+
+Commands:
+  list                          Installed compatibility tools
+  queue                         Running and queued tasks
+  install FLAVOR RELEASE        Install a release, e.g. `install ge-proton 9-20`
+  uninstall NAME                Uninstall a tool, e.g. `uninstall GE-Proton8-25`
+
+The session token is read from $DECKY_PLUGIN_RUNTIME_DIR/session-token.";
+
+enum Command {
+    List,
+    Queue,
+    Install { flavor: String, release: String },
+    Uninstall { name: String },
+}
+
+enum Failure {
+    Task(String),
+    Connection(String),
+}
+
+impl Failure {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Task(_) => 1,
+            Failure::Connection(_) => 2,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Task(message) | Failure::Connection(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Returns the address to connect to along with the command, `None` if the arguments don't
+/// make one.
+fn parse_args(args: &[String]) -> Option<(String, Command)> {
+    let (address, args) = match args {
+        [flag, address, rest @ ..] if flag == "--address" => (address.clone(), rest),
+        _ => (DEFAULT_ADDRESS.to_string(), args),
+    };
+    let command = match args {
+        [command] if command == "list" => Command::List,
+        [command] if command == "queue" => Command::Queue,
+        [command, flavor, release] if command == "install" => Command::Install {
+            flavor: flavor.clone(),
+            release: release.clone(),
+        },
+        [command, name] if command == "uninstall" => Command::Uninstall { name: name.clone() },
+        _ => return None,
+    };
+    Some((address, command))
+}
+
+fn normalized(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Whether `name` stands for `flavor`, by the flavor's name or one of its naming schemes like
+/// `ge-proton`.
+fn flavor_matches(name: &str, flavor: &CompatibilityToolFlavor) -> bool {
+    let name = normalized(name);
+    normalized(&flavor.to_string()) == name
+        || naming_schemes(flavor)
+            .iter()
+            .any(|scheme| normalized(scheme.name) == name)
+}
+
+fn installed_tools(app_state: &AppState) -> impl Iterator<Item = &SteamCompatibilityTool> {
+    app_state
+        .installed_compatibility_tools
+        .iter()
+        .chain(&app_state.installed_runners)
+        .chain(&app_state.installed_components)
+}
+
+/// A connection that keeps a copy of the backend's state up to date.
+struct Session {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    state: Option<Value>,
+    revision: u64,
+}
+
+impl Session {
+    async fn connect(address: &str, session_token: String) -> Result<Session, Failure> {
+        let (socket, _) = connect_async(format!("ws://{}", address))
+            .await
+            .map_err(|err| {
+                Failure::Connection(format!("Failed to connect to {}: {}", address, err))
+            })?;
+        let mut session = Session {
+            socket,
+            state: None,
+            revision: 0,
+        };
+        session
+            .send(&Request {
+                protocol_version: Some(PROTOCOL_VERSION),
+                ..Request::new(RequestType::Hello)
+            })
+            .await?;
+        session
+            .send(&Request {
+                session_token: Some(session_token),
+                ..Request::new(RequestType::Authenticate)
+            })
+            .await?;
+        // Accepted peers are told their permissions after the backend's Hello, rejected ones why
+        let mut handshake = session.next().await?;
+        while handshake.r#type == RequestType::Hello {
+            handshake = session.next().await?;
+        }
+        match handshake.r#type {
+            RequestType::Permissions => Ok(session),
+            _ => Err(Failure::Connection(match handshake.app_error {
+                Some(app_error) => app_error.message,
+                None => format!("Unexpected {:?} from the backend", handshake.r#type),
+            })),
+        }
+    }
+
+    async fn send(&mut self, request: &Request) -> Result<(), Failure> {
+        self.socket
+            .send(Message::text(serde_json::to_string(request).unwrap()))
+            .await
+            .map_err(|err| Failure::Connection(format!("Failed to send: {}", err)))
+    }
+
+    /// Waits for the next message, state updates are applied before it's returned.
+    async fn next(&mut self) -> Result<Request, Failure> {
+        loop {
+            let message = match self.socket.next().await {
+                Some(Ok(message)) => message,
+                Some(Err(err)) => {
+                    return Err(Failure::Connection(format!("Connection lost: {}", err)))
+                }
+                None => return Err(Failure::Connection("The backend hung up".to_string())),
+            };
+            let Ok(text) = message.to_text() else {
+                continue;
+            };
+            let Ok(request) = serde_json::from_str::<Request>(text) else {
+                continue;
+            };
+            match (&request.app_state, request.revision, &request.state_delta) {
+                (Some(app_state), Some(revision), _) => {
+                    self.state = serde_json::to_value(app_state).ok();
+                    self.revision = revision;
+                }
+                (_, _, Some(state_delta)) => {
+                    let applied = match &mut self.state {
+                        Some(state) if state_delta.base_revision == self.revision => {
+                            apply_deltas(state, &state_delta.deltas).is_ok()
+                        }
+                        _ => false,
+                    };
+                    if applied {
+                        self.revision = state_delta.revision;
+                    } else if self.state.take().is_some() {
+                        self.send(&Request::new(RequestType::RequestSnapshot))
+                            .await?;
+                    }
+                }
+                _ => {}
+            }
+            let not_ready = request
+                .app_error
+                .as_ref()
+                .is_some_and(|app_error| app_error.code == AppErrorCode::NotReady);
+            if request.r#type == RequestType::Error && not_ready {
+                return Err(Failure::Connection(
+                    "The backend is still starting, try again in a bit".to_string(),
+                ));
+            }
+            return Ok(request);
+        }
+    }
+
+    fn app_state(&self) -> Option<AppState> {
+        serde_json::from_value(self.state.clone()?).ok()
+    }
+
+    async fn snapshot(&mut self) -> Result<AppState, Failure> {
+        self.send(&Request::new(RequestType::RequestSnapshot))
+            .await?;
+        loop {
+            self.next().await?;
+            if let Some(app_state) = self.app_state() {
+                return Ok(app_state);
+            }
+        }
+    }
+}
+
+/// Fails on the messages that end any task, with what to tell the user.
+fn check_failure(request: &Request) -> Result<(), Failure> {
+    match request.r#type {
+        RequestType::ValidationError => Err(Failure::Task(format!(
+            "The backend rejected the request: {}",
+            serde_json::to_string(&request.validation_errors).unwrap()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+async fn list(session: &mut Session) -> Result<(), Failure> {
+    let app_state = session.snapshot().await?;
+    for tool in installed_tools(&app_state) {
+        println!(
+            "{:<32} {:<18} used by {} games",
+            tool.display_name,
+            tool.flavor.to_string(),
+            tool.used_by_games.len()
+        );
+    }
+    Ok(())
+}
+
+async fn queue(session: &mut Session) -> Result<(), Failure> {
+    let app_state = session.snapshot().await?;
+    if app_state.in_progress.is_empty() && app_state.task_queue.is_empty() {
+        println!("Nothing queued");
+    }
+    for install in &app_state.in_progress {
+        println!(
+            "{:<32} {:?} {}%",
+            install.name, install.state, install.progress
+        );
+    }
+    for task in &app_state.task_queue {
+        match &task.install {
+            Some(install) => println!("{:<32} Queued", install.release.tag_name),
+            None => println!("{:<32} Queued", format!("{:?}", task.r#type)),
+        }
+    }
+    Ok(())
+}
+
+async fn install(session: &mut Session, flavor: &str, release: &str) -> Result<(), Failure> {
+    let app_state = session.snapshot().await?;
+    let flavor = app_state
+        .available_flavors
+        .iter()
+        .find(|candidate| flavor_matches(flavor, &candidate.flavor))
+        .ok_or_else(|| Failure::Task(format!("There is no flavor called {}", flavor)))?;
+    let release = flavor
+        .releases
+        .iter()
+        .find(|candidate| release_matches(&flavor.flavor, release, candidate))
+        .ok_or_else(|| Failure::Task(format!("{} has no release {}", flavor.flavor, release)))?;
+    let flavor = flavor.flavor.clone();
+    let release = release.clone();
+    let tag_name = release.tag_name.clone();
+    session
+        .send(&Request {
+            task: Some(Task {
+                install: Some(Install {
+                    flavor: flavor.clone(),
+                    release,
+                    ignore_network_cap: false,
+                    ignore_disk_space: false,
+                    background: false,
+                    accept_local_changes_loss: false,
+                    copy_install: false,
+                    replaces: Vec::new(),
+                    migrate_mappings: false,
+                    max_size: None,
+                    local_path: None,
+                    restart_steam: false,
+                    trigger: HistoryTrigger::Manual,
+                }),
+                ..Task::new(TaskType::InstallCompatibilityTool)
+            }),
+            ..Request::new(RequestType::Task)
+        })
+        .await?;
+
+    // Known once the task shows up in the queue
+    let mut task_id = None;
+    let queued_by = Instant::now() + QUEUE_TIMEOUT;
+    // Installs the backend refuses are only explained in a notification
+    let mut last_notification = None;
+    loop {
+        let request = match task_id {
+            Some(_) => session.next().await?,
+            None => timeout_at(queued_by, session.next()).await.map_err(|_| {
+                Failure::Task(
+                    last_notification
+                        .take()
+                        .unwrap_or_else(|| format!("{} was never queued", tag_name)),
+                )
+            })??,
+        };
+        check_failure(&request)?;
+        match request.r#type {
+            RequestType::Notification => {
+                let notification = request.notification.unwrap_or_default();
+                println!("\r\x1b[K{}", notification);
+                last_notification = Some(notification);
+            }
+            RequestType::Error => {
+                // Failed installs are reported with the release they were installing
+                let ours = request.app_error.filter(|app_error| {
+                    app_error.task_id.is_some()
+                        && (app_error.task_id == task_id
+                            || app_error.message.starts_with(&tag_name))
+                });
+                if let Some(app_error) = ours {
+                    return Err(Failure::Task(app_error.message));
+                }
+            }
+            RequestType::TaskCancelled
+                if request.task_id.is_some() && request.task_id == task_id =>
+            {
+                return Err(Failure::Task(format!(
+                    "The install of {} was cancelled",
+                    tag_name
+                )));
+            }
+            _ => {}
+        }
+
+        let Some(app_state) = session.app_state() else {
+            continue;
+        };
+        let queued = app_state.task_queue.iter().find(|task| {
+            task.install.as_ref().is_some_and(|install| {
+                install.flavor == flavor && install.release.tag_name == tag_name
+            })
+        });
+        let running = app_state
+            .in_progress
+            .iter()
+            .find(|install| install.flavor == flavor && install.name == tag_name);
+        if let Some(task) = queued {
+            task_id = Some(task.id);
+            print!("\r{}: Queued", tag_name);
+        } else if let Some(install) = running {
+            task_id = Some(install.id);
+            print!(
+                "\r{}: {:?} {}%\x1b[K",
+                tag_name, install.state, install.progress
+            );
+        } else if task_id.is_some() {
+            let installed = installed_tools(&app_state).any(|tool| {
+                tool.flavor == flavor
+                    && tool
+                        .github_release
+                        .as_ref()
+                        .is_some_and(|release| release.tag_name == tag_name)
+            });
+            if installed {
+                println!("\r{}: Installed\x1b[K", tag_name);
+                return Ok(());
+            }
+            return Err(Failure::Task(format!(
+                "The install of {} stopped",
+                tag_name
+            )));
+        }
+        let _ = io::stdout().flush();
+    }
+}
+
+async fn uninstall(session: &mut Session, name: &str) -> Result<(), Failure> {
+    let app_state = session.snapshot().await?;
+    let tool = installed_tools(&app_state)
+        .find(|tool| tool.internal_name == name || tool.display_name == name)
+        .cloned()
+        .ok_or_else(|| Failure::Task(format!("{} isn't installed", name)))?;
+    let path = tool.path.clone();
+    session
+        .send(&Request {
+            task: Some(Task {
+                uninstall: Some(Uninstall {
+                    flavor: tool.flavor.clone(),
+                    steam_compatibility_tool: tool,
+                    force: false,
+                    accept_local_changes_loss: false,
+                    replacement: None,
+                    keep_in_trash: false,
+                }),
+                ..Task::new(TaskType::UninstallCompatibilityTool)
+            }),
+            ..Request::new(RequestType::Task)
+        })
+        .await?;
+
+    loop {
+        let request = session.next().await?;
+        check_failure(&request)?;
+        match request.r#type {
+            // Uninstalls are only refused with notifications
+            RequestType::Notification => {
+                return Err(Failure::Task(request.notification.unwrap_or_default()))
+            }
+            RequestType::Error => {
+                let message = request.app_error.map(|app_error| app_error.message);
+                return Err(Failure::Task(message.unwrap_or_default()));
+            }
+            _ => {}
+        }
+        if let Some(app_state) = session.app_state() {
+            if !installed_tools(&app_state).any(|tool| tool.path == path) {
+                println!("Uninstalled {}", name);
+                return Ok(());
+            }
+        }
+    }
+}
+
+async fn run(address: &str, command: Command) -> Result<(), Failure> {
+    let token_path = runtime_directory().join(SESSION_TOKEN_FILE);
+    let session_token = fs::read_to_string(&token_path).map_err(|err| {
+        Failure::Connection(format!(
+            "Failed to read the session token from {}: {}",
+            token_path.display(),
+            err
+        ))
+    })?;
+    let mut session = Session::connect(address, session_token.trim().to_string()).await?;
+    match command {
+        Command::List => list(&mut session).await,
+        Command::Queue => queue(&mut session).await,
+        Command::Install { flavor, release } => install(&mut session, &flavor, &release).await,
+        Command::Uninstall { name } => uninstall(&mut session, &name).await,
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some((address, command)) = parse_args(&args) else {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    };
+    if let Err(failure) = run(&address, command).await {
+        // Replaces the progress line of an install
+        eprintln!("\r\x1b[K{}", failure);
+        process::exit(failure.exit_code());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flavors_are_matched_loosely() {
+        let proton_ge = CompatibilityToolFlavor::ProtonGE;
+        assert!(flavor_matches("ge-proton", &proton_ge));
+        assert!(flavor_matches("ProtonGE", &proton_ge));
+        assert!(!flavor_matches("wine-ge", &proton_ge));
+    }
+
+    #[test]
+    fn test_arguments_make_commands() {
+        let args = |strings: &[&str]| -> Vec<String> {
+            strings.iter().map(|string| string.to_string()).collect()
+        };
+        assert!(matches!(
+            parse_args(&args(&["install", "ge-proton", "9-20"])),
+            Some((address, Command::Install { .. })) if address == DEFAULT_ADDRESS
+        ));
+        assert!(matches!(
+            parse_args(&args(&["--address", "steamdeck:8887", "queue"])),
+            Some((address, Command::Queue)) if address == "steamdeck:8887"
+        ));
+        assert!(parse_args(&args(&["install", "ge-proton"])).is_none());
+    }
+}
@@ -1,7 +1,17 @@
+use crate::wine_cask::proxy::ProxyConfig;
+use log::warn;
+use reqwest::header::{HeaderMap, AUTHORIZATION, ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::fmt;
 use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+const GITHUB_API_URL: &str = "https://api.github.com";
+/// Longer waits aren't worth holding up a refresh for, cached releases are shown instead.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+const MAX_RETRIES: u32 = 2;
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Release {
@@ -11,6 +21,9 @@ pub struct Release {
     pub prerelease: bool,
     pub name: String,
     pub tag_name: String,
+    // Missing from releases cached by older versions
+    #[serde(default)]
+    pub target_commitish: String,
     pub assets: Vec<Asset>,
     pub created_at: String,
     pub published_at: String,
@@ -37,28 +50,137 @@ pub struct Response {
     pub message: String,
 }
 
+/// Why GitHub refused a request for exceeding a rate limit.
+#[derive(PartialEq, Debug)]
+pub struct RateLimit {
+    /// Unix timestamp the limit resets at, from `X-RateLimit-Reset`.
+    pub reset_at: Option<u64>,
+    /// How long GitHub asks to wait before retrying, from `Retry-After`.
+    pub retry_after: Option<Duration>,
+}
+
+fn header_number(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// Returns the rate limit a response ran into, `None` if it isn't rate limited.
+///
+/// GitHub answers 403 for an exhausted primary limit and 403 or 429 with `Retry-After` for
+/// secondary limits, other 403s are permission problems.
+pub fn rate_limit(status: StatusCode, headers: &HeaderMap) -> Option<RateLimit> {
+    let retry_after = header_number(headers, "retry-after").map(Duration::from_secs);
+    let exhausted = header_number(headers, "x-ratelimit-remaining") == Some(0);
+    let limited = match status {
+        StatusCode::TOO_MANY_REQUESTS => true,
+        StatusCode::FORBIDDEN => exhausted || retry_after.is_some(),
+        _ => false,
+    };
+    limited.then(|| RateLimit {
+        reset_at: header_number(headers, "x-ratelimit-reset"),
+        retry_after,
+    })
+}
+
+/// Releases of a repository, unless they didn't change.
+pub enum ListedReleases {
+    /// Nothing changed since the response the ETag was taken from.
+    NotModified,
+    Modified {
+        releases: Vec<Release>,
+        /// ETag of the first page, which changes along with any release.
+        etag: Option<String>,
+    },
+}
+
+/// Lists every release of the repository along with the number of response bytes received.
+///
+/// A personal access `token` raises the rate limit from 60 to 5000 requests an hour. Nothing is
+/// listed if the releases still match `etag`, which doesn't count against the rate limit.
 pub async fn list_all_releases(
     owner: &str,
     repository: &str,
-) -> Result<Vec<Release>, GitHubUtilError> {
-    let client = reqwest::Client::builder()
-        .user_agent("FlashyReese/decky-wine-cellar")
+    token: Option<&str>,
+    etag: Option<&str>,
+) -> Result<(ListedReleases, u64), GitHubUtilError> {
+    let proxy = ProxyConfig::current();
+    list_releases_from(GITHUB_API_URL, &proxy, owner, repository, token, etag).await
+}
+
+async fn list_releases_from(
+    api_url: &str,
+    proxy: &ProxyConfig,
+    owner: &str,
+    repository: &str,
+    token: Option<&str>,
+    etag: Option<&str>,
+) -> Result<(ListedReleases, u64), GitHubUtilError> {
+    let client = proxy
+        .client_builder()
         .build()
         .expect("Failed to create HTTP client");
 
     let mut releases: Vec<Release> = Vec::new();
+    let mut bytes_received: u64 = 0;
     let mut page = 1;
+    let mut retries = 0;
+    let mut first_page_etag: Option<String> = None;
 
     loop {
         let url = format!(
-            "https://api.github.com/repos/{}/{}/releases?per_page=100&page={}",
-            owner, repository, page
+            "{}/repos/{}/{}/releases?per_page=100&page={}",
+            api_url, owner, repository, page
         );
 
-        let response = client.get(&url).send().await?;
+        let mut request = client.get(&url);
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        if let (1, Some(etag)) = (page, etag) {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|err| GitHubUtilError::RequestError(proxy.describe_error(&err)))?;
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok((ListedReleases::NotModified, bytes_received));
+        }
+
+        if let Some(rate_limit) = rate_limit(response.status(), response.headers()) {
+            match rate_limit.retry_after {
+                Some(retry_after) if retry_after <= MAX_RETRY_AFTER && retries < MAX_RETRIES => {
+                    warn!(
+                        "{}/{} is rate limited, retrying in {} seconds",
+                        owner,
+                        repository,
+                        retry_after.as_secs()
+                    );
+                    retries += 1;
+                    tokio::time::sleep(retry_after).await;
+                    continue;
+                }
+                _ => {
+                    return Err(GitHubUtilError::RateLimited {
+                        reset_at: rate_limit.reset_at,
+                    })
+                }
+            }
+        }
 
         if response.status().is_success() {
-            let response_text = response.text().await?;
+            if page == 1 {
+                first_page_etag = response
+                    .headers()
+                    .get(ETAG)
+                    .and_then(|etag| etag.to_str().ok())
+                    .map(str::to_string);
+            }
+            let response_text = response
+                .text()
+                .await
+                .map_err(|err| GitHubUtilError::RequestError(proxy.describe_error(&err)))?;
+            bytes_received += response_text.len() as u64;
             if let Ok(page_releases) = serde_json::from_str::<Vec<Release>>(&response_text) {
                 if page_releases.is_empty() {
                     break; // No more releases, exit the loop
@@ -81,7 +203,13 @@ pub async fn list_all_releases(
         }
     }
 
-    Ok(releases)
+    Ok((
+        ListedReleases::Modified {
+            releases,
+            etag: first_page_etag,
+        },
+        bytes_received,
+    ))
 }
 
 #[derive(Debug)]
@@ -89,6 +217,10 @@ pub enum GitHubUtilError {
     RequestError(String),
     JsonParsingError(String),
     ResponseError(String),
+    /// Unauthenticated requests share a limit of 60 an hour.
+    RateLimited {
+        reset_at: Option<u64>,
+    },
 }
 
 impl Display for GitHubUtilError {
@@ -101,6 +233,12 @@ impl Display for GitHubUtilError {
             GitHubUtilError::ResponseError(json) => {
                 write!(f, "Response error: {}", json)
             }
+            GitHubUtilError::RateLimited { .. } => {
+                write!(
+                    f,
+                    "GitHub API rate limit exceeded, adding a GitHub token raises it"
+                )
+            }
         }
     }
 }
@@ -111,14 +249,141 @@ impl Error for GitHubUtilError {
     }
 }
 
-impl From<reqwest::Error> for GitHubUtilError {
-    fn from(err: reqwest::Error) -> GitHubUtilError {
-        GitHubUtilError::RequestError(err.to_string())
-    }
-}
-
 impl From<serde_json::Error> for GitHubUtilError {
     fn from(err: serde_json::Error) -> GitHubUtilError {
         GitHubUtilError::JsonParsingError(err.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::release;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let exhausted = headers(&[
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "1700003600"),
+        ]);
+        assert_eq!(
+            rate_limit(StatusCode::FORBIDDEN, &exhausted),
+            Some(RateLimit {
+                reset_at: Some(1700003600),
+                retry_after: None,
+            })
+        );
+        assert_eq!(
+            rate_limit(
+                StatusCode::TOO_MANY_REQUESTS,
+                &headers(&[("retry-after", "30")])
+            ),
+            Some(RateLimit {
+                reset_at: None,
+                retry_after: Some(Duration::from_secs(30)),
+            })
+        );
+
+        // A 403 with requests left is a permission problem, not a rate limit
+        let remaining = headers(&[("x-ratelimit-remaining", "59")]);
+        assert_eq!(rate_limit(StatusCode::FORBIDDEN, &remaining), None);
+        assert_eq!(rate_limit(StatusCode::OK, &exhausted), None);
+    }
+
+    /// Serves one page of releases tagged `"v2"`, or `304 Not Modified` to requests sending
+    /// that tag.
+    fn releases_api() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let page = serde_json::to_string(&[release("GE-Proton9-22")]).unwrap();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().filter_map(Result::ok) {
+                let mut buffer = [0; 4096];
+                let length = stream.read(&mut buffer).unwrap_or(0);
+                let request = String::from_utf8_lossy(&buffer[..length]).to_lowercase();
+                let response = if request.contains("if-none-match: \"v2\"") {
+                    "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n".to_string()
+                } else {
+                    let body = if request.contains("page=1 ") {
+                        &page
+                    } else {
+                        "[]"
+                    };
+                    format!(
+                        "HTTP/1.1 200 OK\r\nETag: \"v2\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                };
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_releases_are_revalidated_with_their_etag() {
+        let api_url = releases_api();
+
+        // A changed release list comes with a new ETag
+        let (listed, _) = list_releases_from(
+            &api_url,
+            &ProxyConfig::default(),
+            "owner",
+            "repo",
+            None,
+            Some("\"v1\""),
+        )
+        .await
+        .unwrap();
+        match listed {
+            ListedReleases::Modified { releases, etag } => {
+                assert_eq!(releases.len(), 1);
+                assert_eq!(releases[0].tag_name, "GE-Proton9-22");
+                assert_eq!(etag.as_deref(), Some("\"v2\""));
+            }
+            ListedReleases::NotModified => panic!("Releases changed since \"v1\""),
+        }
+
+        let (listed, bytes_received) = list_releases_from(
+            &api_url,
+            &ProxyConfig::default(),
+            "owner",
+            "repo",
+            None,
+            Some("\"v2\""),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(listed, ListedReleases::NotModified));
+        assert_eq!(bytes_received, 0);
+    }
+
+    #[tokio::test]
+    async fn test_releases_are_listed_through_the_proxy() {
+        // The stub answers absolute-form requests too, like a forward proxy
+        let proxy = ProxyConfig::new(Some(&releases_api()), |_| None);
+        let (listed, _) = list_releases_from(
+            "http://api.github.invalid",
+            &proxy,
+            "owner",
+            "repo",
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(listed, ListedReleases::Modified { releases, .. } if releases.len() == 1));
+    }
+}
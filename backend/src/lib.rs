@@ -0,0 +1,58 @@
+//! Everything but the server loop, shared by the backend and `wine-cellar-cli` so both speak the
+//! same protocol.
+
+pub mod app_id;
+pub mod appinfo;
+pub mod github_util;
+pub mod mounts;
+pub mod steam_util;
+#[cfg(test)]
+mod test_fixtures;
+pub mod vdf_edit;
+pub mod wine_cask;
+pub mod written_by;
+
+use crate::wine_cask::outbox::Outbox;
+use crate::wine_cask::permissions::PermissionSet;
+use crate::wine_cask::subscriptions::SubscriptionSet;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::{env, fmt};
+use tokio::sync::Mutex;
+
+pub type PeerMap = Arc<Mutex<HashMap<PeerAddr, Peer>>>;
+
+pub struct Peer {
+    /// Messages waiting to be written, bounded so a stuck peer can't grow it forever.
+    pub outbox: Arc<Outbox>,
+    pub permissions: PermissionSet,
+    /// Categories of messages broadcast to the peer.
+    pub subscriptions: SubscriptionSet,
+}
+
+/// A connected peer along with the transport it connected over.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    /// Unix socket peers have no address, they are numbered in the order they connected.
+    Unix(u64),
+}
+
+impl Display for PeerAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Tcp(addr) => write!(f, "tcp:{}", addr),
+            PeerAddr::Unix(number) => write!(f, "unix:#{}", number),
+        }
+    }
+}
+
+/// Directory Decky gives the plugin for files that only live as long as the backend runs.
+pub fn runtime_directory() -> PathBuf {
+    PathBuf::from(
+        env::var("DECKY_PLUGIN_RUNTIME_DIR").unwrap_or("/tmp/decky-wine-cellar".to_string()),
+    )
+}
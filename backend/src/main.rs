@@ -1,29 +1,57 @@
-mod github_util;
 mod multilogger;
-mod steam_util;
-mod wine_cask;
+mod unix_socket;
 
 use crate::multilogger::MultiLogger;
-use crate::steam_util::SteamUtil;
-use crate::wine_cask::app::{AppState, Request, RequestType, TaskType, UpdaterState, WineCask};
-use futures_channel::mpsc::{unbounded, UnboundedSender};
-use futures_util::{future, pin_mut, stream::TryStreamExt, StreamExt};
-use log::{error, info, Level};
-use std::collections::{HashMap, VecDeque};
+use crate::unix_socket::bind_unix_socket;
+use futures_util::{future, pin_mut, stream, stream::TryStreamExt, Sink, SinkExt, StreamExt};
+use log::{error, info, warn, Level};
+use std::collections::HashMap;
 use std::env;
 use std::fs::OpenOptions;
 use std::io::Error as IoError;
-use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::net::{TcpListener, TcpStream};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::handshake::server::{
+    ErrorResponse, Request as HandshakeRequest, Response as HandshakeResponse,
+};
 use tokio_tungstenite::tungstenite::Message;
+use wine_cask::steam_util::SteamUtil;
+use wine_cask::wine_cask::app::{Request, RequestType, Task, TaskType, WineCask};
+use wine_cask::wine_cask::app_error::{AppError, AppErrorCode};
+use wine_cask::wine_cask::environment::run_environment_sampler;
+use wine_cask::wine_cask::file_watcher::run_file_watcher;
+use wine_cask::wine_cask::flavors::CacheUse;
+use wine_cask::wine_cask::history::HistoryTrigger;
+use wine_cask::wine_cask::keepalive::{write_to_peer, Keepalive, PING_INTERVAL};
+use wine_cask::wine_cask::names;
+use wine_cask::wine_cask::outbox::{remove_peer, send_to_peer, Outbox};
+use wine_cask::wine_cask::permissions::{
+    required_permission, resolve_permissions, token_from_query, PermissionSet,
+};
+use wine_cask::wine_cask::prefix_scan;
+use wine_cask::wine_cask::protocol::{check_hello, hello, unknown_type_error, HELLO_TIMEOUT};
+use wine_cask::wine_cask::session_token::{
+    authenticate, Authentication, SessionToken, AUTHENTICATION_TIMEOUT, SESSION_TOKEN_FILE,
+};
+use wine_cask::wine_cask::settings::Settings;
+use wine_cask::wine_cask::startup::Startup;
+use wine_cask::wine_cask::subscriptions::SubscriptionSet;
+use wine_cask::wine_cask::tool_inspection;
+use wine_cask::wine_cask::update_check::run_update_checks;
+use wine_cask::wine_cask::validation::{unknown_request_type, validate_message};
+use wine_cask::{runtime_directory, Peer, PeerAddr, PeerMap};
 
-type Tx = UnboundedSender<Message>;
-type PeerMap = Arc<Mutex<HashMap<SocketAddr, Tx>>>;
-type AsyncAppState = Arc<Mutex<AppState>>;
-type ArcWineCask = Arc<WineCask>;
+/// What every connection is held to.
+#[derive(Clone)]
+struct ConnectionConfig {
+    ping_interval: Duration,
+    session_token: SessionToken,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), IoError> {
@@ -32,104 +60,302 @@ async fn main() -> Result<(), IoError> {
     let addr = get_server_address();
 
     let state = PeerMap::new(Mutex::new(HashMap::new()));
+    let runtime_directory = runtime_directory();
 
-    let steam_util = SteamUtil::new(get_steam_directory());
-
-    let app_state = AsyncAppState::new(Mutex::new(AppState {
-        available_flavors: Vec::new(),
-        installed_compatibility_tools: Vec::new(),
-        in_progress: None,
-        task_queue: VecDeque::new(),
-        updater_state: UpdaterState::Idle,
-        updater_last_check: None,
-        available_compat_tools: None,
-        flavors: Vec::new(),
-    }));
-
-    let wine_cask = WineCask {
-        steam_util,
-        app_state: app_state.clone(),
+    // Anything on the system can connect, peers prove they were handed the token
+    let session_token = SessionToken::generate().expect("Failed to generate the session token");
+    let session_token_path = runtime_directory.join(SESSION_TOKEN_FILE);
+    if let Err(err) = session_token.write(&session_token_path) {
+        error!(
+            "Failed to write the session token to {}: {}",
+            session_token_path.display(),
+            err
+        );
+    }
+    let config = ConnectionConfig {
+        ping_interval: PING_INTERVAL,
+        session_token,
     };
 
-    initialize_app_state(&wine_cask).await;
+    // Accept connections right away so the frontend can follow a slow startup
+    let listener = TcpListener::bind(&addr).await.expect("Failed to bind");
+    info!("Listening on: {}", addr);
+    let startup = Arc::new(Startup::new());
+    let server = tokio::spawn(start_server(
+        listener,
+        startup.clone(),
+        state.clone(),
+        config.clone(),
+    ));
+    let wine_cask_arc = startup
+        .initialize(&state, get_steam_directories, &runtime_directory)
+        .await;
 
-    let wine_cask_arc = ArcWineCask::new(wine_cask);
+    // Local tooling can use a unix socket instead, secured by its file permissions
+    let unix_socket = wine_cask_arc.app_state.lock().await.settings.unix_socket;
+    let _unix_socket_guard = if unix_socket {
+        let path = runtime_directory.join("wine-cask.sock");
+        match bind_unix_socket(&path) {
+            Ok((unix_listener, guard)) => {
+                info!("Listening on: {}", path.display());
+                tokio::spawn(start_unix_server(
+                    unix_listener,
+                    startup.clone(),
+                    state.clone(),
+                    config,
+                ));
+                Some(guard)
+            }
+            Err(err) => {
+                error!("Failed to listen on {}: {}", path.display(), err);
+                None
+            }
+        }
+    } else {
+        None
+    };
 
-    tokio::spawn(wine_cask::process_queue(
+    tool_inspection::start_tool_inspection(&wine_cask_arc, &state);
+    tokio::spawn(run_environment_sampler(
         wine_cask_arc.clone(),
         state.clone(),
     ));
+    tokio::spawn(run_update_checks(wine_cask_arc.clone(), state.clone()));
+    tokio::spawn(run_file_watcher(wine_cask_arc.clone(), state.clone()));
+    tokio::spawn(wine_cask::wine_cask::process_queue(
+        wine_cask_arc,
+        state.clone(),
+    ));
 
-    start_server(addr, wine_cask_arc, state).await;
+    // Return instead of getting killed so the unix socket is removed
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = server => result.unwrap(),
+        _ = terminate.recv() => info!("Received SIGTERM"),
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+    }
 
     info!("Exiting...");
     Ok(())
 }
 
-async fn start_server(addr: String, wine_cask: Arc<WineCask>, state: PeerMap) {
-    let try_socket = TcpListener::bind(&addr).await;
-    let listener = try_socket.expect("Failed to bind");
-    info!("Listening on: {}", addr);
-
+async fn start_server(
+    listener: TcpListener,
+    startup: Arc<Startup>,
+    state: PeerMap,
+    config: ConnectionConfig,
+) {
     while let Ok((stream, addr)) = listener.accept().await {
         tokio::spawn(handle_connection(
-            wine_cask.clone(),
+            startup.clone(),
+            state.clone(),
+            stream,
+            PeerAddr::Tcp(addr),
+            config.clone(),
+        ));
+    }
+}
+
+async fn start_unix_server(
+    listener: UnixListener,
+    startup: Arc<Startup>,
+    state: PeerMap,
+    config: ConnectionConfig,
+) {
+    let mut peers = 0;
+    while let Ok((stream, _)) = listener.accept().await {
+        peers += 1;
+        tokio::spawn(handle_connection(
+            startup.clone(),
             state.clone(),
             stream,
-            addr,
+            PeerAddr::Unix(peers),
+            config.clone(),
         ));
     }
 }
 
-async fn handle_connection(
-    wine_cask: Arc<WineCask>,
+// The handshake callback's error type is dictated by tungstenite
+#[allow(clippy::result_large_err)]
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    startup: Arc<Startup>,
     peer_map: PeerMap,
-    raw_stream: TcpStream,
-    addr: SocketAddr,
+    raw_stream: S,
+    addr: PeerAddr,
+    config: ConnectionConfig,
 ) {
-    info!("Incoming TCP connection from: {}", addr);
+    info!("Incoming connection from: {}", addr);
 
-    let ws_stream = tokio_tungstenite::accept_async(raw_stream)
-        .await
-        .expect("Error during the websocket handshake occurred");
+    let mut token = None;
+    let ws_stream = tokio_tungstenite::accept_hdr_async(
+        raw_stream,
+        |request: &HandshakeRequest,
+         response: HandshakeResponse|
+         -> Result<HandshakeResponse, ErrorResponse> {
+            token = token_from_query(request.uri().query());
+            Ok(response)
+        },
+    )
+    .await
+    .expect("Error during the websocket handshake occurred");
     info!("WebSocket connection established: {}", addr);
 
-    let (tx, rx) = unbounded();
-    peer_map.lock().await.insert(addr, tx);
-
-    let (outgoing, incoming) = ws_stream.split();
-
-    let broadcast_incoming = incoming.try_for_each_concurrent(Some(10), |msg| {
-        let wine_cask_clone = Arc::clone(&wine_cask);
-        let peer_map_clone = Arc::clone(&peer_map);
-        async move {
-            if msg.is_text() {
-                info!(
-                    "Received a message from {}: {}",
-                    addr,
-                    msg.to_text().unwrap()
-                );
-
-                if let Ok(msg) = &msg.to_text() {
-                    if !msg.is_empty() {
-                        handle_request(&wine_cask_clone, msg, &peer_map_clone).await;
-                    }
+    let (access_tokens, allow_unauthenticated) = match startup.wine_cask().await {
+        Some(wine_cask) => {
+            let settings = &wine_cask.app_state.lock().await.settings;
+            (
+                settings.access_tokens.clone(),
+                settings.allow_unauthenticated_peers,
+            )
+        }
+        None => {
+            let settings = Settings::load();
+            (settings.access_tokens, settings.allow_unauthenticated_peers)
+        }
+    };
+    let Some(permissions) = resolve_permissions(&access_tokens, token.as_deref()) else {
+        warn!("Closing connection from {}: unknown access token", addr);
+        return;
+    };
+
+    let (mut outgoing, mut incoming) = ws_stream.split();
+    // Peers built against another protocol are turned away before they can misread anything
+    let _ = outgoing
+        .send(Message::text(serde_json::to_string(&hello()).unwrap()))
+        .await;
+    let answer = match tokio::time::timeout(HELLO_TIMEOUT, incoming.next()).await {
+        Ok(Some(Ok(answer))) => answer,
+        _ => {
+            warn!("Closing connection from {}: it didn't answer Hello", addr);
+            return;
+        }
+    };
+    match check_hello(&answer) {
+        Ok(protocol_version) => info!("{} speaks protocol {}", addr, protocol_version),
+        Err(version_mismatch) => {
+            let rejection = version_mismatch.rejection();
+            warn!(
+                "Closing connection from {}: {}",
+                addr,
+                rejection.app_error.as_ref().unwrap()
+            );
+            reject(&mut outgoing, &rejection).await;
+            return;
+        }
+    }
+
+    // Access tokens and the unix socket's file permissions already tell who connected
+    let mut unauthenticated_message = None;
+    if token.is_none() && matches!(addr, PeerAddr::Tcp(_)) {
+        let first_message =
+            match tokio::time::timeout(AUTHENTICATION_TIMEOUT, incoming.next()).await {
+                Ok(Some(Ok(first_message))) => first_message,
+                _ => {
+                    warn!("Closing connection from {}: it didn't authenticate", addr);
+                    return;
                 }
-            } else {
-                info!("Unhandled message from {}: {:?}", addr, msg);
+            };
+        match authenticate(first_message, &config.session_token, allow_unauthenticated) {
+            Authentication::Authenticated => {}
+            Authentication::Unauthenticated(first_message) => {
+                warn!("{} didn't authenticate, accepted as configured", addr);
+                unauthenticated_message = Some(first_message);
+            }
+            Authentication::Rejected(app_error) => {
+                warn!("Closing connection from {}: {}", addr, app_error);
+                let rejection = Request {
+                    app_error: Some(app_error),
+                    ..Request::new(RequestType::Error)
+                };
+                reject(&mut outgoing, &rejection).await;
+                return;
             }
-
-            Ok(())
         }
-    });
+    }
+
+    let outbox = Arc::new(Outbox::default());
+    // Tell the peer what it may do before anything else is sent
+    let handshake = Request {
+        permissions: Some(permissions.clone()),
+        ..Request::new(RequestType::Permissions)
+    };
+    outbox.try_push(
+        RequestType::Permissions,
+        Message::text(serde_json::to_string(&handshake).unwrap()),
+    );
+    peer_map.lock().await.insert(
+        addr,
+        Peer {
+            outbox: outbox.clone(),
+            permissions: permissions.clone(),
+            subscriptions: SubscriptionSet::default(),
+        },
+    );
+
+    let keepalive = Keepalive::default();
+
+    let broadcast_incoming = stream::iter(unauthenticated_message.map(Ok))
+        .chain(incoming)
+        .try_for_each_concurrent(Some(10), |msg| {
+            let startup_clone = Arc::clone(&startup);
+            let peer_map_clone = Arc::clone(&peer_map);
+            let permissions = &permissions;
+            let keepalive = &keepalive;
+            async move {
+                if msg.is_pong() {
+                    keepalive.pong();
+                } else if msg.is_text() {
+                    info!(
+                        "Received a message from {}: {}",
+                        addr,
+                        msg.to_text().unwrap()
+                    );
+
+                    if let Ok(msg) = &msg.to_text() {
+                        if !msg.is_empty() {
+                            match startup_clone.wine_cask().await {
+                                Some(wine_cask) => {
+                                    handle_request(
+                                        &wine_cask,
+                                        msg,
+                                        addr,
+                                        permissions,
+                                        &peer_map_clone,
+                                    )
+                                    .await
+                                }
+                                None => startup_clone.reject_not_ready(&peer_map_clone, addr).await,
+                            }
+                        }
+                    }
+                } else {
+                    info!("Unhandled message from {}: {:?}", addr, msg);
+                }
 
-    let receive_from_others = rx.map(Ok).forward(outgoing);
+                Ok(())
+            }
+        });
+
+    // Ends once the peer was evicted or stopped answering pings
+    let receive_from_others =
+        write_to_peer(addr, &outbox, outgoing, &keepalive, config.ping_interval);
 
     pin_mut!(broadcast_incoming, receive_from_others);
     future::select(broadcast_incoming, receive_from_others).await;
 
     info!("{} disconnected", &addr);
-    peer_map.lock().await.remove(&addr);
+    remove_peer(&peer_map, addr).await;
+}
+
+/// Tells a peer why it's disconnected before closing the connection.
+async fn reject<S>(outgoing: &mut S, rejection: &Request)
+where
+    S: Sink<Message> + Unpin,
+{
+    let _ = outgoing
+        .send(Message::text(serde_json::to_string(rejection).unwrap()))
+        .await;
+    let _ = outgoing.close().await;
 }
 
 fn configure_logger() -> Result<(), IoError> {
@@ -169,70 +395,631 @@ fn get_server_address() -> String {
         .unwrap_or_else(|| "127.0.0.1:8887".to_string())
 }
 
-fn get_steam_directory() -> PathBuf {
-    match env::var("DECKY_USER_HOME") {
+/// Every Steam installation, the one selected in the settings first.
+fn get_steam_directories() -> Vec<PathBuf> {
+    let user_home = match env::var("DECKY_USER_HOME") {
         Ok(value) => {
             info!("Using DECKY_USER_HOME: {}", value);
-            SteamUtil::find_steam_directory(Some(value)).unwrap() // Todo: Handle if no steam folder is found, although this should never happen
+            Some(value)
         }
         Err(_) => {
             error!(
                 "Couldn't find environment variable DECKY_USER_HOME, using default steam directory"
             );
-            SteamUtil::find_steam_directory(None).unwrap()
+            None
+        }
+    };
+    let mut steam_directories = SteamUtil::find_all_steam_directories(user_home).unwrap();
+    if let Some(selected) = Settings::load().steam_directory {
+        match steam_directories
+            .iter()
+            .position(|steam_directory| steam_directory.to_string_lossy() == selected)
+        {
+            Some(index) => steam_directories[..=index].rotate_right(1),
+            None => warn!(
+                "Selected Steam installation {} wasn't found, using the first one",
+                selected
+            ),
         }
     }
+    steam_directories
+}
+
+async fn handle_request(
+    wine_cask: &Arc<WineCask>,
+    msg: &str,
+    addr: PeerAddr,
+    permissions: &PermissionSet,
+    peer_map: &PeerMap,
+) {
+    match validate_message(msg) {
+        Ok(request) => dispatch_request(wine_cask, request, addr, permissions, peer_map).await,
+        Err(validation_errors) => match unknown_request_type(msg) {
+            // Only the peer that sent it can make sense of it
+            Some(r#type) => {
+                warn!("{} sent an unknown request type: {}", addr, r#type);
+                send_to_peer(peer_map, addr, &unknown_type_error(r#type)).await;
+            }
+            None => {
+                wine_cask
+                    .reject_invalid_request(peer_map, addr, validation_errors)
+                    .await
+            }
+        },
+    }
+}
+
+/// Answers only the peer whose request was refused, the others didn't ask.
+async fn reject_request(peer_map: &PeerMap, addr: PeerAddr, app_error: AppError) {
+    error!("Rejected a request of {}: {}", addr, app_error);
+    let response = Request {
+        app_error: Some(app_error),
+        ..Request::new(RequestType::Error)
+    };
+    send_to_peer(peer_map, addr, &response).await;
 }
 
-async fn initialize_app_state(wine_cask: &WineCask) {
-    wine_cask
+fn missing_payload() -> AppError {
+    AppError::internal("The task is missing its payload")
+}
+
+async fn dispatch_task(
+    wine_cask: &Arc<WineCask>,
+    task: Task,
+    peer_map: &PeerMap,
+) -> Result<(), AppError> {
+    if task.r#type == TaskType::InstallCompatibilityTool {
+        wine_cask.add_to_task_queue(task, peer_map).await;
+    } else if task.r#type == TaskType::CancelCompatibilityToolInstall {
+        wine_cask
+            .remove_or_cancel_from_task_queue(task, peer_map)
+            .await;
+    } else if task.r#type == TaskType::UninstallCompatibilityTool {
+        let uninstall = task.uninstall.ok_or_else(missing_payload)?;
+        wine_cask
+            .uninstall_compatibility_tool(uninstall, HistoryTrigger::Manual, peer_map)
+            .await;
+    } else if task.r#type == TaskType::MigrateCompatibilityTools {
+        let migrate = task.migrate.ok_or_else(missing_payload)?;
+        wine_cask
+            .migrate_compatibility_tools(peer_map, migrate)
+            .await;
+    } else if task.r#type == TaskType::SetCompatibilityToolMapping {
+        let mapping = task.mapping.ok_or_else(missing_payload)?;
+        wine_cask
+            .set_compatibility_tool_mapping(peer_map, mapping)
+            .await;
+    } else if task.r#type == TaskType::SetCompatibilityToolMappings {
+        let mappings = task.mappings.ok_or_else(missing_payload)?;
+        wine_cask
+            .set_compatibility_tool_mappings(peer_map, mappings)
+            .await;
+    } else if task.r#type == TaskType::ImportCompatibilityToolMappings {
+        let import = task.import.ok_or_else(missing_payload)?;
+        wine_cask.import_mappings(peer_map, import).await;
+    } else if task.r#type == TaskType::InstallFromUrl {
+        let direct_install = task.direct_install.ok_or_else(missing_payload)?;
+        wine_cask.install_from_url(peer_map, direct_install).await;
+    } else if task.r#type == TaskType::InstallFromLocalFile {
+        let local_file = task.local_file.ok_or_else(missing_payload)?;
+        wine_cask
+            .install_from_local_file(peer_map, local_file)
+            .await;
+    } else if task.r#type == TaskType::DeleteCompatData {
+        let delete_compat_data = task.delete_compat_data.ok_or_else(missing_payload)?;
+        wine_cask
+            .delete_compat_data(peer_map, delete_compat_data)
+            .await;
+    } else if task.r#type == TaskType::RestorePrefixBackup {
+        let restore_prefix_backup = task.restore_prefix_backup.ok_or_else(missing_payload)?;
+        wine_cask
+            .restore_prefix_backup(peer_map, restore_prefix_backup)
+            .await;
+    } else if task.r#type == TaskType::UpdateAllCompatibilityTools {
+        let update_all = task.update_all.unwrap_or_default();
+        wine_cask
+            .update_all_compatibility_tools(peer_map, update_all, HistoryTrigger::UpdateAll)
+            .await;
+    } else if task.r#type == TaskType::CheckForFlavorUpdates {
+        wine_cask
+            .check_for_flavor_updates(peer_map, CacheUse::Revalidate)
+            .await;
+    }
+    Ok(())
+}
+
+async fn dispatch_request(
+    wine_cask: &Arc<WineCask>,
+    request: Request,
+    addr: PeerAddr,
+    permissions: &PermissionSet,
+    peer_map: &PeerMap,
+) {
+    if let Some(permission) =
+        required_permission(&request).filter(|permission| !permissions.allows(*permission))
+    {
+        let app_error = AppError::new(
+            AppErrorCode::Forbidden,
+            format!("{:?} needs the {} permission", request.r#type, permission),
+        );
+        reject_request(peer_map, addr, app_error).await;
+        return;
+    }
+    let feature_check = wine_cask
         .app_state
         .lock()
         .await
-        .installed_compatibility_tools = wine_cask.list_compatibility_tools().unwrap();
-}
+        .settings
+        .feature_flags
+        .check(&request);
+    if let Err(app_error) = feature_check {
+        reject_request(peer_map, addr, app_error).await;
+        return;
+    }
+    if let Err(invalid_name) = names::check_names(&request) {
+        let app_error = AppError::new(AppErrorCode::InvalidRequest, invalid_name.to_string());
+        reject_request(peer_map, addr, app_error).await;
+        return;
+    }
 
-async fn handle_request(wine_cask: &Arc<WineCask>, msg: &str, peer_map: &PeerMap) {
-    if let Ok(request) = serde_json::from_str::<Request>(msg) {
-        match request.r#type {
-            RequestType::RequestState => {
-                // Assumes available_compat_tools is Some
+    match request.r#type {
+        RequestType::RequestState => {
+            // Validation rejects requests without available_compat_tools
+            wine_cask
+                .process_frontend_compat_tools_update(
+                    peer_map,
+                    request.available_compat_tools.unwrap(),
+                )
+                .await;
+            wine_cask.update_used_by_games(peer_map).await;
+        }
+        RequestType::Task => {
+            let dispatched = match request.task {
+                Some(task) => dispatch_task(wine_cask, task, peer_map).await,
+                None => Err(AppError::internal(
+                    "Something went wrong with the task request",
+                )),
+            };
+            if let Err(app_error) = dispatched {
+                error!("{}", app_error);
+                wine_cask.broadcast_app_error(peer_map, app_error).await;
+            }
+        }
+        RequestType::RequestSnapshot => {
+            wine_cask.broadcast_snapshot(peer_map).await;
+        }
+        RequestType::Subscribe => {
+            if let Some(subscriptions) = request.subscriptions {
+                wine_cask.subscribe(peer_map, addr, subscriptions).await;
+            }
+        }
+        RequestType::GetToolProvenance => {
+            if let Some(internal_name) = request.internal_name {
+                wine_cask
+                    .get_tool_provenance(peer_map, &internal_name)
+                    .await;
+            }
+        }
+        RequestType::VerifyInstalledTool => {
+            if let Some(internal_name) = request.internal_name {
+                wine_cask
+                    .verify_installed_tool(peer_map, &internal_name)
+                    .await;
+            }
+        }
+        RequestType::GetActivity => {
+            if let Some(activity_query) = request.activity_query {
+                wine_cask.get_activity(peer_map, activity_query).await;
+            }
+        }
+        RequestType::GetStorageBreakdown => {
+            wine_cask
+                .get_storage_breakdown(peer_map, request.refresh.unwrap_or(false))
+                .await;
+        }
+        RequestType::GetPrefixSizes => {
+            wine_cask.get_prefix_sizes(peer_map).await;
+        }
+        RequestType::GetInstalledApps => {
+            wine_cask.get_installed_apps(peer_map).await;
+        }
+        RequestType::GetShortcutDetails => {
+            if let Some(shortcut_id) = request.shortcut_id {
+                wine_cask.get_shortcut_details(peer_map, shortcut_id).await;
+            }
+        }
+        RequestType::SetShortcutLaunchOptions => {
+            if let (Some(shortcut_id), Some(launch_options)) =
+                (request.shortcut_id, request.launch_options)
+            {
+                wine_cask
+                    .set_shortcut_launch_options(peer_map, shortcut_id, launch_options)
+                    .await;
+            }
+        }
+        RequestType::ClearShaderCache => {
+            if let Some(app_id) = request.app_id {
+                wine_cask.clear_shader_cache(peer_map, app_id).await;
+            }
+        }
+        RequestType::GetUndoStack => {
+            wine_cask.get_undo_stack(peer_map).await;
+        }
+        RequestType::GetMutationLog => {
+            wine_cask
+                .get_mutation_log(peer_map, request.limit.unwrap_or(100))
+                .await;
+        }
+        RequestType::GetHistory => {
+            if let Some(history_query) = request.history_query {
+                wine_cask.get_history(peer_map, history_query).await;
+            }
+        }
+        RequestType::UndoLast => {
+            wine_cask.undo_last(peer_map).await;
+        }
+        RequestType::Refresh => {
+            if let Some(refresh_scope) = request.refresh_scope {
                 wine_cask
-                    .process_frontend_compat_tools_update(
+                    .refresh(peer_map, refresh_scope, request.flavor, request.request_id)
+                    .await;
+            }
+        }
+        RequestType::SkipRelease | RequestType::UnskipRelease => {
+            if let (Some(flavor), Some(tag_name)) = (request.flavor, request.tag_name) {
+                let skipped = request.r#type == RequestType::SkipRelease;
+                wine_cask
+                    .set_release_skipped(peer_map, flavor, tag_name, skipped)
+                    .await;
+            }
+        }
+        RequestType::SetQuickSlots => {
+            if let (Some(app_id), Some(quick_slots)) = (request.app_id, request.quick_slots) {
+                wine_cask
+                    .set_quick_slots(peer_map, app_id, quick_slots)
+                    .await;
+            }
+        }
+        RequestType::SwitchQuickSlot => {
+            if let (Some(app_id), Some(slot)) = (request.app_id, request.slot) {
+                wine_cask.switch_quick_slot(peer_map, app_id, slot).await;
+            }
+        }
+        RequestType::CreatePlan => {
+            if let Some(plan_kind) = request.plan_kind {
+                wine_cask.create_plan(peer_map, plan_kind).await;
+            }
+        }
+        RequestType::ListOrphanedCompatData => {
+            wine_cask.list_orphaned_compat_data(peer_map).await;
+        }
+        RequestType::ExecutePlan => {
+            if let Some(plan_id) = request.plan_id {
+                wine_cask
+                    .execute_plan(
                         peer_map,
-                        request.available_compat_tools.unwrap(),
+                        plan_id,
+                        request.excluded_action_ids.unwrap_or_default(),
                     )
                     .await;
-                wine_cask.update_used_by_games(peer_map).await;
-            }
-            RequestType::Task => {
-                if let Some(task) = request.task {
-                    if task.r#type == TaskType::InstallCompatibilityTool {
-                        wine_cask.add_to_task_queue(task, peer_map).await;
-                    } else if task.r#type == TaskType::CancelCompatibilityToolInstall {
-                        wine_cask
-                            .remove_or_cancel_from_task_queue(task, peer_map)
-                            .await;
-                    } else if task.r#type == TaskType::UninstallCompatibilityTool {
-                        wine_cask
-                            .uninstall_compatibility_tool(
-                                task.uninstall.unwrap().steam_compatibility_tool,
-                                peer_map,
-                            )
-                            .await;
-                    } else if task.r#type == TaskType::CheckForFlavorUpdates {
-                        wine_cask.check_for_flavor_updates(peer_map, true).await;
-                    }
-                } else {
-                    wine_cask
-                        .broadcast_notification(
-                            peer_map,
-                            "Error: Something went wrong with the task request",
-                        )
-                        .await;
-                }
             }
-            _ => {}
         }
+        RequestType::PrioritizePrefixes => {
+            if let Some(app_ids) = request.app_ids {
+                prefix_scan::prioritize_prefixes(wine_cask, peer_map, app_ids).await;
+            }
+        }
+        RequestType::CancelPrefixScan => {
+            wine_cask.cancel_prefix_scan().await;
+        }
+        RequestType::SelectSteamInstallation => {
+            if let Some(steam_directory) = request.steam_directory {
+                wine_cask
+                    .select_steam_installation(peer_map, steam_directory)
+                    .await;
+            }
+        }
+        RequestType::ForceRefresh => {
+            wine_cask
+                .check_for_flavor_updates(peer_map, CacheUse::Bypass)
+                .await;
+        }
+        RequestType::PauseInspection => {
+            wine_cask.set_inspection_paused(peer_map, true).await;
+        }
+        RequestType::ResumeInspection => {
+            wine_cask.set_inspection_paused(peer_map, false).await;
+            tool_inspection::start_tool_inspection(wine_cask, peer_map);
+        }
+        RequestType::CancelTask => {
+            if let Some(task_id) = request.task_id {
+                wine_cask.cancel_task(peer_map, task_id).await;
+            }
+        }
+        RequestType::ResolveRestoreConflict => {
+            if let (Some(task_id), Some(restore_decision)) =
+                (request.task_id, request.restore_decision)
+            {
+                wine_cask
+                    .resolve_restore_conflict(peer_map, task_id, restore_decision)
+                    .await;
+            }
+        }
+        RequestType::RollbackUpdate => {
+            if let Some(history_id) = request.history_id {
+                wine_cask.rollback_update(peer_map, history_id).await;
+            }
+        }
+        RequestType::CreateVirtualTool => {
+            if let Some(virtual_tool) = request.virtual_tool {
+                wine_cask.create_virtual_tool(peer_map, virtual_tool).await;
+            }
+        }
+        RequestType::AdoptTool => {
+            if let Some(internal_name) = request.internal_name {
+                wine_cask.adopt_tool(peer_map, &internal_name).await;
+            }
+        }
+        RequestType::CheckLocalChanges => {
+            wine_cask
+                .check_local_changes(peer_map, request.internal_name)
+                .await;
+        }
+        RequestType::UpdateSettings => {
+            if let Some(settings) = request.settings {
+                wine_cask.update_settings(peer_map, settings).await;
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+    use tokio::net::{TcpStream, UnixStream};
+    use tokio_tungstenite::{client_async, connect_async, MaybeTlsStream, WebSocketStream};
+    use wine_cask::wine_cask::app::broadcast_to_peers;
+    use wine_cask::wine_cask::protocol::{ProtocolVersion, PROTOCOL_VERSION};
+
+    const SESSION_TOKEN: &str = "9f86d081884c7d659a2feaa0c55ad015";
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    fn config(ping_interval: Duration) -> ConnectionConfig {
+        ConnectionConfig {
+            ping_interval,
+            session_token: SessionToken::new(SESSION_TOKEN.to_string()),
+        }
+    }
+
+    /// Answers the backend's `Hello` with `protocol_version`.
+    async fn say_hello<S>(client: &mut WebSocketStream<S>, protocol_version: ProtocolVersion)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let message = client.next().await.unwrap().unwrap();
+        let hello: Request = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!(hello.r#type, RequestType::Hello);
+        assert_eq!(hello.protocol_version, Some(PROTOCOL_VERSION));
+        let answer = Request {
+            protocol_version: Some(protocol_version),
+            ..Request::new(RequestType::Hello)
+        };
+        client
+            .send(Message::text(serde_json::to_string(&answer).unwrap()))
+            .await
+            .unwrap();
+    }
+
+    async fn connect(address: SocketAddr, session_token: Option<&str>) -> Client {
+        let (mut client, _) = connect_async(format!("ws://{}/", address)).await.unwrap();
+        say_hello(&mut client, PROTOCOL_VERSION).await;
+        let authenticate = Request {
+            session_token: session_token.map(str::to_string),
+            ..Request::new(RequestType::Authenticate)
+        };
+        client
+            .send(Message::text(serde_json::to_string(&authenticate).unwrap()))
+            .await
+            .unwrap();
+        client
+    }
+
+    #[tokio::test]
+    async fn test_tcp_and_unix_peers_are_served_together() {
+        let runtime_directory = tempdir().unwrap();
+        let path = runtime_directory.path().join("wine-cask.sock");
+        let state = PeerMap::new(Mutex::new(HashMap::new()));
+        // Never initialized, every request is answered with NotReady
+        let startup = Arc::new(Startup::new());
+
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tcp_address = tcp_listener.local_addr().unwrap();
+        let (unix_listener, _guard) = bind_unix_socket(&path).unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        tokio::spawn(start_server(
+            tcp_listener,
+            startup.clone(),
+            state.clone(),
+            config(PING_INTERVAL),
+        ));
+        tokio::spawn(start_unix_server(
+            unix_listener,
+            startup,
+            state.clone(),
+            config(PING_INTERVAL),
+        ));
+
+        let mut tcp_client = connect(tcp_address, Some(SESSION_TOKEN)).await;
+        // The unix socket's file permissions stand in for the session token
+        let unix_stream = UnixStream::connect(&path).await.unwrap();
+        let (mut unix_client, _) = client_async("ws://localhost/", unix_stream).await.unwrap();
+        say_hello(&mut unix_client, PROTOCOL_VERSION).await;
+        while state.lock().await.len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        let peers: Vec<PeerAddr> = state.lock().await.keys().copied().collect();
+        assert!(peers.iter().any(|peer| matches!(peer, PeerAddr::Tcp(_))));
+        assert!(peers.contains(&PeerAddr::Unix(1)));
+
+        let tcp_handshake = tcp_client.next().await.unwrap().unwrap();
+        let unix_handshake = unix_client.next().await.unwrap().unwrap();
+        for message in [tcp_handshake, unix_handshake] {
+            let handshake: Request = serde_json::from_str(message.to_text().unwrap()).unwrap();
+            assert_eq!(handshake.r#type, RequestType::Permissions);
+            assert_eq!(handshake.permissions, Some(PermissionSet::all()));
+        }
+
+        unix_client
+            .send(Message::text("{\"type\":\"RequestState\"}"))
+            .await
+            .unwrap();
+        let unix_answer = unix_client.next().await.unwrap().unwrap();
+        let unix_answer: Request = serde_json::from_str(unix_answer.to_text().unwrap()).unwrap();
+        assert_eq!(unix_answer.app_error.unwrap().code, AppErrorCode::NotReady);
+        unix_client.next().await.unwrap().unwrap();
+        // Only the peer that asked is answered
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), tcp_client.next())
+                .await
+                .is_err()
+        );
+        tcp_client
+            .send(Message::text("{\"type\":\"RequestState\"}"))
+            .await
+            .unwrap();
+        let tcp_answer = tcp_client.next().await.unwrap().unwrap();
+        let tcp_answer: Request = serde_json::from_str(tcp_answer.to_text().unwrap()).unwrap();
+        assert_eq!(tcp_answer.app_error.unwrap().code, AppErrorCode::NotReady);
+        tcp_client.next().await.unwrap().unwrap();
+
+        // Broadcasts reach the peers of both transports
+        let notification = Request {
+            notification: Some("Installation Completed: GE-Proton9-20".to_string()),
+            ..Request::new(RequestType::Notification)
+        };
+        broadcast_to_peers(&state, &notification).await;
+        let tcp_broadcast = tcp_client.next().await.unwrap().unwrap();
+        let unix_broadcast = unix_client.next().await.unwrap().unwrap();
+        for message in [tcp_broadcast, unix_broadcast] {
+            let broadcast: Request = serde_json::from_str(message.to_text().unwrap()).unwrap();
+            assert_eq!(broadcast.r#type, RequestType::Notification);
+            assert_eq!(broadcast.notification, notification.notification);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_peers_without_the_session_token_are_disconnected() {
+        let state = PeerMap::new(Mutex::new(HashMap::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(start_server(
+            listener,
+            Arc::new(Startup::new()),
+            state.clone(),
+            config(PING_INTERVAL),
+        ));
+
+        // A token of an earlier run, none at all, and a request before authenticating
+        let wrong_token = connect(address, Some("0123456789abcdef0123456789abcdef")).await;
+        let missing_token = connect(address, None).await;
+        let (mut skipped, _) = connect_async(format!("ws://{}/", address)).await.unwrap();
+        say_hello(&mut skipped, PROTOCOL_VERSION).await;
+        skipped
+            .send(Message::text("{\"type\":\"RequestState\"}"))
+            .await
+            .unwrap();
+        for mut client in [wrong_token, missing_token, skipped] {
+            let message = client.next().await.unwrap().unwrap();
+            let rejection: Request = serde_json::from_str(message.to_text().unwrap()).unwrap();
+            assert_eq!(rejection.r#type, RequestType::Error);
+            assert_eq!(
+                rejection.app_error.unwrap().code,
+                AppErrorCode::Unauthenticated
+            );
+            // Nothing else is sent before the connection closes
+            assert!(matches!(
+                client.next().await,
+                None | Some(Ok(Message::Close(_))) | Some(Err(_))
+            ));
+        }
+        assert!(state.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_peers_of_another_major_version_are_disconnected() {
+        let state = PeerMap::new(Mutex::new(HashMap::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(start_server(
+            listener,
+            Arc::new(Startup::new()),
+            state.clone(),
+            config(PING_INTERVAL),
+        ));
+
+        let (mut client, _) = connect_async(format!("ws://{}/", address)).await.unwrap();
+        let incompatible = ProtocolVersion {
+            major: PROTOCOL_VERSION.major + 1,
+            minor: 0,
+        };
+        say_hello(&mut client, incompatible).await;
+        let message = client.next().await.unwrap().unwrap();
+        let rejection: Request = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!(
+            rejection.app_error.unwrap().code,
+            AppErrorCode::IncompatibleVersion
+        );
+        assert_eq!(rejection.version_mismatch.unwrap().peer, Some(incompatible));
+        assert!(matches!(
+            client.next().await,
+            None | Some(Ok(Message::Close(_))) | Some(Err(_))
+        ));
+        assert!(state.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_peers_that_stop_answering_pings_are_removed() {
+        let state = PeerMap::new(Mutex::new(HashMap::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(start_server(
+            listener,
+            Arc::new(Startup::new()),
+            state.clone(),
+            config(Duration::from_millis(50)),
+        ));
+
+        let mut answering = connect(address, Some(SESSION_TOKEN)).await;
+        let mut suspended = connect(address, Some(SESSION_TOKEN)).await;
+        while state.lock().await.len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        suspended.next().await.unwrap().unwrap();
+        let MaybeTlsStream::Plain(answering_stream) = answering.get_ref() else {
+            unreachable!();
+        };
+        let answering_addr = answering_stream.local_addr().unwrap();
+        // Reading is what answers the pings
+        tokio::spawn(async move { while answering.next().await.is_some() {} });
+
+        // The suspended peer never reads again, so its pongs never come
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while state.lock().await.len() > 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let peers: Vec<PeerAddr> = state.lock().await.keys().copied().collect();
+        assert_eq!(peers, [PeerAddr::Tcp(answering_addr)]);
+        drop(suspended);
     }
 }
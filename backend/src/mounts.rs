@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+use std::{fs, thread};
+
+/// Directories removable media are mounted under.
+const REMOVABLE_MEDIA_ROOTS: [&str; 3] = ["/run/media", "/media", "/mnt"];
+/// How long a library folder gets to list its steamapps directory, a dying SD card can block
+/// reads for minutes.
+const LIBRARY_FOLDER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Undoes the octal escapes /proc/mounts uses for spaces, tabs, newlines and backslashes.
+fn unescape_mount_field(field: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = field;
+    while let Some(index) = rest.find('\\') {
+        unescaped.push_str(&rest[..index]);
+        let code = rest
+            .get(index + 1..index + 4)
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match code {
+            Some(code) => {
+                unescaped.push(code as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// The mount point, type and options of the mount in `mounts` (formatted like /proc/mounts) that
+/// contains `path`.
+pub fn find_mount<'a>(mounts: &'a str, path: &Path) -> Option<(PathBuf, &'a str, &'a str)> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let mount_point = PathBuf::from(unescape_mount_field(fields.get(1)?));
+            Some((mount_point, *fields.get(2)?, *fields.get(3)?))
+        })
+        .filter(|(mount_point, _, _)| path.starts_with(mount_point))
+        // Later mounts shadow earlier ones on the same mount point
+        .enumerate()
+        .max_by_key(|(index, (mount_point, _, _))| (mount_point.components().count(), *index))
+        .map(|(_, mount)| mount)
+}
+
+/// Whether `path` is in a directory removable media are mounted under, without its media being
+/// mounted there.
+pub fn media_unmounted(mounts: &str, path: &Path) -> bool {
+    let Some(media_root) = REMOVABLE_MEDIA_ROOTS
+        .iter()
+        .map(Path::new)
+        .find(|media_root| path.starts_with(media_root))
+    else {
+        return false;
+    };
+    find_mount(mounts, path).is_none_or(|(mount_point, _, _)| media_root.starts_with(mount_point))
+}
+
+/// Whether the library folder at `path` can be used: its media is mounted and its steamapps
+/// directory can be listed in time. Waits on the media, so don't call it on the async runtime.
+pub fn library_folder_mounted(path: &Path) -> bool {
+    let mounts = fs::read_to_string("/proc/mounts").unwrap_or_default();
+    if media_unmounted(&mounts, path) {
+        return false;
+    }
+    let steamapps = path.join("steamapps");
+    let (sender, receiver) = mpsc::channel();
+    // Left behind if the read hangs, it exits whenever the read gives up
+    thread::spawn(move || {
+        let _ = sender.send(fs::read_dir(steamapps).is_ok());
+    });
+    receiver
+        .recv_timeout(LIBRARY_FOLDER_TIMEOUT)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+pub mod fixtures {
+    pub const MOUNTS: &str = "\
+/dev/nvme0n1p8 / ext4 rw,relatime 0 0
+/dev/nvme0n1p8 /home ext4 rw,relatime 0 0
+/dev/mmcblk0p1 /run/media/deck/SD\\040Card exfat rw,nosuid,nodev,relatime,uid=1000 0 0
+/dev/sda2 /run/media/deck/Windows ntfs3 rw,relatime,uid=1000,nocase 0 0
+/dev/sda3 /run/media/deck/Games fuseblk rw,nosuid,nodev,user_id=0,allow_other 0 0
+";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::*;
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_detects_unmounted_media() {
+        assert!(!media_unmounted(
+            MOUNTS,
+            Path::new("/run/media/deck/SD Card")
+        ));
+        assert!(!media_unmounted(
+            MOUNTS,
+            Path::new("/home/deck/.steam/steam")
+        ));
+        // The SD card with another label, or any while none is inserted
+        assert!(media_unmounted(
+            MOUNTS,
+            Path::new("/run/media/deck/SD Card2")
+        ));
+        assert!(media_unmounted("", Path::new("/run/media/deck/SD Card")));
+        assert!(media_unmounted(
+            "/dev/nvme0n1p8 / ext4 rw,relatime 0 0\ntmpfs /run tmpfs rw 0 0\n",
+            Path::new("/run/media/mmcblk0p1")
+        ));
+    }
+
+    #[test]
+    fn test_library_folder_needs_steamapps() {
+        let library = tempdir().expect("Failed to create temporary directory");
+        assert!(!library_folder_mounted(library.path()));
+        fs::create_dir(library.path().join("steamapps")).expect("Failed to create steamapps");
+        assert!(library_folder_mounted(library.path()));
+    }
+}
@@ -2,12 +2,24 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::fs;
-use std::path::PathBuf;
-use std::{env, fmt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{env, fmt, thread};
 
-use keyvalues_parser::Vdf;
+use keyvalues_parser::{Obj, Value, Vdf};
 use log::{error, info, warn};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::app_id::{AppId, CompatAppId, CompatAppIdKind, ShortcutId};
+use crate::appinfo::{
+    read_app_names, read_compat_tool_names, read_key_values, write_key_values, KeyValue,
+};
+use crate::mounts::library_folder_mounted;
+use crate::vdf_edit::{self, VdfEditError};
+use crate::written_by::WrittenBy;
 
 /// Represents errors that can occur while using `SteamUtil`.
 #[derive(Debug, Clone)]
@@ -28,11 +40,127 @@ pub enum SteamUtilError {
     VdfParsingError(String),
     /// Missing Vdf Entry
     VdfMissingEntry(String),
+    /// The Steam configuration vdf could not be written.
+    SteamConfigVdfWriteFailed(String),
+    /// Steam is running and would overwrite the configuration vdf when it exits.
+    SteamRunning,
+    /// A game is running, Steam isn't restarted under it.
+    GameRunning(AppId),
+    /// Steam couldn't be shut down or started again.
+    SteamRestartFailed(String),
+    /// Steam's registry.vdf could not be found.
+    RegistryVdfNotFound,
+    /// The shortcuts.vdf of a Steam account could not be found.
+    ShortcutsVdfNotFound,
+    /// A shortcuts.vdf could not be written.
+    ShortcutsVdfWriteFailed(String),
+    /// No account has a non-Steam shortcut with this id.
+    ShortcutNotFound(ShortcutId),
 }
 
+/// Possible Steam root directories relative to the home directory.
+const POSSIBLE_STEAM_ROOTS: [&str; 5] = [
+    ".local/share/Steam",
+    ".steam/root",
+    ".steam/steam",
+    ".steam/debian-installation",
+    ".var/app/com.valvesoftware.Steam/data/Steam", // flatpak
+];
+
+/// Times config.vdf is read again when something else wrote it while it was being changed.
+const CONFIG_WRITE_RETRIES: u32 = 3;
+
+/// How long Steam gets to exit after being asked to shut down.
+const STEAM_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Clock ticks per second used by `/proc/<pid>/stat`, fixed at 100 on the platforms Steam runs on.
+const USER_HZ: u64 = 100;
+
 /// Utility for working with Steam directories and settings.
+#[derive(Clone)]
 pub struct SteamUtil {
     steam_path: PathBuf,
+    /// Where Steam is looked for among the running processes.
+    proc_root: PathBuf,
+    /// Shared by every clone, appinfo.vdf is too large to parse for each lookup.
+    app_names: Arc<Mutex<AppInfoNames>>,
+}
+
+/// App names read from appinfo.vdf, along with the file they were read from so it's only re-read
+/// after Steam updated it.
+#[derive(Default)]
+struct AppInfoNames {
+    fingerprint: Option<(SystemTime, u64)>,
+    names: HashMap<u32, String>,
+    /// Internal names of the compatibility tools Steam ships itself, by app id.
+    compat_tool_names: HashMap<u32, String>,
+}
+
+/// How a write of config.vdf went.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub struct ConfigWrite {
+    /// Times the file changed between reading and replacing it, so the changes were re-applied.
+    pub retries: u32,
+    /// Whether the written file keeps changes made since it was first read, usually by Steam.
+    pub merged_foreign_change: bool,
+}
+
+/// What config.vdf looked like when it was read.
+#[derive(PartialEq)]
+struct ConfigFingerprint {
+    modified: Option<SystemTime>,
+    sha256: Vec<u8>,
+}
+
+fn read_config(path: &Path) -> Result<(String, ConfigFingerprint), SteamUtilError> {
+    let modified = fs::metadata(path)
+        .map_err(|_| SteamUtilError::SteamConfigVdfNotFound)?
+        .modified()
+        .ok();
+    let config = fs::read_to_string(path).map_err(|_| SteamUtilError::SteamConfigVdfNotFound)?;
+    let sha256 = Sha256::digest(config.as_bytes()).to_vec();
+    Ok((config, ConfigFingerprint { modified, sha256 }))
+}
+
+/// Applies every mapping change in order to the content of config.vdf.
+fn apply_mapping_changes(
+    config: &str,
+    changes: &[(CompatAppId, Option<&str>)],
+    steam_config_file: &Path,
+) -> Result<String, SteamUtilError> {
+    let parsing_error = |err: VdfEditError| {
+        SteamUtilError::VdfParsingError(format!("{}: {}", steam_config_file.display(), err))
+    };
+    // Only the mappings are spliced in, the rest of the file stays as Steam wrote it. Steam writes
+    // "valve" in lowercase on some installations, keys are matched ignoring case.
+    let mut config = config.to_string();
+    for (app_id, compatibility_tool) in changes {
+        let app_id = app_id.to_string();
+        let mapping = [
+            "InstallConfigStore",
+            "Software",
+            "Valve",
+            "Steam",
+            "CompatToolMapping",
+            &app_id,
+        ];
+        let field = |name| [&mapping[..], &[name][..]].concat();
+        match compatibility_tool {
+            Some(compatibility_tool) => {
+                let created = !vdf_edit::contains(&config, &mapping).map_err(parsing_error)?;
+                config = vdf_edit::set_string(&config, &field("name"), compatibility_tool)
+                    .map_err(parsing_error)?;
+                if created {
+                    config = vdf_edit::set_string(&config, &field("config"), "")
+                        .map_err(parsing_error)?;
+                    config = vdf_edit::set_string(&config, &field("priority"), "250")
+                        .map_err(parsing_error)?;
+                }
+            }
+            None => config = vdf_edit::remove(&config, &mapping).map_err(parsing_error)?,
+        }
+    }
+    Ok(config)
 }
 
 #[derive(Serialize, Clone)]
@@ -43,12 +171,165 @@ pub struct CompatibilityTool {
     pub display_name: String,
     pub from_os_list: String,
     pub to_os_list: String,
+    /// Whether 32-bit Windows games can run, always true for tools that aren't based on Wine.
+    pub supports_32bit: bool,
 }
 
-#[derive(Serialize)]
+/// One of Valve's own Proton releases, which Steam installs into a library folder like an app.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct OfficialProtonTool {
+    pub app_id: AppId,
+    pub path: PathBuf,
+    /// Name Steam maps apps to it by, e.g. `proton_9` or `proton_experimental`.
+    pub internal_name: String,
+    pub display_name: String,
+    /// Build from its `version` file, e.g. `9.0-2` or `experimental-9.0-20240701`.
+    pub version: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct SteamApp {
-    pub app_id: u64,
+    pub app_id: AppId,
+    pub name: String,
+    /// Whether the app has an appmanifest, mappings outlive uninstalled apps.
+    pub installed: bool,
+}
+
+/// Bits of an appmanifest's `StateFlags`.
+const STATE_UPDATE_REQUIRED: u32 = 2;
+const STATE_FULLY_INSTALLED: u32 = 4;
+const STATE_UPDATE_RUNNING: u32 = 256;
+const STATE_UPDATE_PAUSED: u32 = 512;
+const STATE_UPDATE_STARTED: u32 = 1024;
+const STATE_UNINSTALLING: u32 = 2048;
+const STATE_PREALLOCATING: u32 = 524288;
+
+/// Where an app is in its lifecycle, according to the `StateFlags` of its appmanifest.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum AppInstallState {
+    FullyInstalled,
+    /// Installed, but an update has to be downloaded before it can be launched.
+    UpdatePending,
+    /// Space is reserved for the files, which aren't downloaded yet.
+    Preallocating,
+    Uninstalling,
+    /// Any other combination of flags, e.g. files missing or being validated.
+    Other,
+}
+
+impl AppInstallState {
+    pub fn from_state_flags(state_flags: u32) -> Self {
+        let update_flags = STATE_UPDATE_REQUIRED
+            | STATE_UPDATE_RUNNING
+            | STATE_UPDATE_PAUSED
+            | STATE_UPDATE_STARTED;
+        if state_flags & STATE_UNINSTALLING != 0 {
+            AppInstallState::Uninstalling
+        } else if state_flags == STATE_FULLY_INSTALLED {
+            AppInstallState::FullyInstalled
+        } else if state_flags & STATE_PREALLOCATING != 0 {
+            AppInstallState::Preallocating
+        } else if state_flags & update_flags != 0 {
+            AppInstallState::UpdatePending
+        } else {
+            AppInstallState::Other
+        }
+    }
+}
+
+/// Apps Steam installs like games, but which are runtimes or redistributables.
+const KNOWN_TOOL_APP_IDS: [u32; 9] = [
+    // Steam Linux Runtime 1.0 (scout), 2.0 (soldier) and 3.0 (sniper)
+    1070560, 1391110, 1628350, //
+    // Steamworks Common Redistributables
+    228980, //
+    // Proton EasyAntiCheat and BattlEye Runtimes
+    1826330, 1161040, //
+    // Proton Experimental, Hotfix and 9.0
+    1493710, 2180100, 2805730,
+];
+
+/// Whether an installed app is something to play or something games run on.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum AppKind {
+    Game,
+    /// A compatibility tool, runtime or redistributable.
+    Tool,
+}
+
+/// Tells tools from games by the `toolmanifest.vdf` Steam ships with every compatibility tool and
+/// runtime, and by app id for the redistributables which don't have one.
+pub fn app_kind(library_path: &Path, app_id: AppId, installdir: &str) -> AppKind {
+    let tool_manifest = library_path
+        .join("steamapps/common")
+        .join(installdir)
+        .join("toolmanifest.vdf");
+    if KNOWN_TOOL_APP_IDS.contains(&app_id.value()) || tool_manifest.is_file() {
+        AppKind::Tool
+    } else {
+        AppKind::Game
+    }
+}
+
+/// An app as its appmanifest describes it, unlike `SteamApp` only apps with an appmanifest are
+/// listed.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct InstalledApp {
+    pub app_id: AppId,
     pub name: String,
+    /// Directory in `steamapps/common` the app is installed to.
+    pub installdir: String,
+    /// Library folder the appmanifest is in.
+    pub library_path: PathBuf,
+    pub size_on_disk: u64,
+    pub state_flags: u32,
+    pub install_state: AppInstallState,
+    pub kind: AppKind,
+}
+
+/// A non-Steam shortcut of one Steam account.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ShortcutDetails {
+    /// Directory of the account in `userdata`.
+    pub user_id: u32,
+    pub shortcut_id: ShortcutId,
+    pub app_name: String,
+    pub exe: String,
+    pub launch_options: String,
+}
+
+impl ShortcutDetails {
+    fn from_shortcut(user_id: u32, shortcut: &KeyValue) -> Option<Self> {
+        let shortcut_id = match shortcut.get("appid")? {
+            KeyValue::Int32(value) => ShortcutId::from_signed(*value).ok()?,
+            value => ShortcutId::new(u32::try_from(value.as_u64()?).ok()?).ok()?,
+        };
+        let string = |key: &str| {
+            shortcut
+                .get(key)
+                .and_then(KeyValue::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        Some(ShortcutDetails {
+            user_id,
+            shortcut_id,
+            app_name: string("AppName"),
+            exe: string("Exe"),
+            launch_options: string("LaunchOptions"),
+        })
+    }
+}
+
+/// A prefix left behind by an app that was uninstalled or a shortcut that was removed.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct OrphanedCompatData {
+    pub app_id: CompatAppId,
+    /// App name if it's known, the appmanifest it was read from is gone.
+    pub name: Option<String>,
+    pub path: PathBuf,
+    /// Bytes on disk, 0 until the prefix was measured.
+    pub size: u64,
 }
 
 impl SteamUtil {
@@ -56,58 +337,98 @@ impl SteamUtil {
     pub fn new(steam_home: PathBuf) -> Self {
         Self {
             steam_path: steam_home,
+            proc_root: PathBuf::from("/proc"),
+            app_names: Arc::default(),
         }
     }
 
-    /// Finds the Steam directory.
+    /// Finds the Steam directory, the first of several installations.
     pub fn find_steam_directory(
         user_home_directory: Option<String>,
     ) -> Result<PathBuf, SteamUtilError> {
-        // Possible Steam root directories
-        let possible_steam_roots = [
-            // todo: handle multiple installations perhaps a dropdown in frontend if we detect multiple installation
-            ".local/share/Steam",
-            ".steam/root",
-            ".steam/steam",
-            ".steam/debian-installation",
-            ".var/app/com.valvesoftware.Steam/data/Steam", // flatpak
-        ];
+        SteamUtil::find_all_steam_directories(user_home_directory)?
+            .into_iter()
+            .next()
+            .ok_or(SteamUtilError::SteamDirectoryNotFound)
+    }
 
+    /// Finds every Steam installation, e.g. a native and a flatpak one, in the order of
+    /// `POSSIBLE_STEAM_ROOTS`.
+    pub fn find_all_steam_directories(
+        user_home_directory: Option<String>,
+    ) -> Result<Vec<PathBuf>, SteamUtilError> {
         let user_profile = user_home_directory.map(PathBuf::from).or_else(|| {
             env::var_os("USERPROFILE")
                 .map(PathBuf::from)
                 .or_else(|| env::var_os("HOME").map(PathBuf::from))
         });
 
-        if let Some(user_profile) = user_profile {
-            info!("Looking for Steam directory in {}", user_profile.display());
-            for steam_dir in &possible_steam_roots {
-                let expanded_steam_dir = user_profile.join(steam_dir);
-                let ct_dir = expanded_steam_dir.join("config");
-                let config_vdf = ct_dir.join("config.vdf"); // this does exist on clean install
-                let libraryfolders_vdf = ct_dir.join("libraryfolders.vdf"); // On a clean install doesn't exist, it's generated after login
-
-                if config_vdf.exists() && libraryfolders_vdf.exists() {
-                    info!("Found Steam directory: {}", expanded_steam_dir.display());
-                    return Ok(expanded_steam_dir);
-                }
-            }
-        } else {
+        let Some(user_profile) = user_profile else {
             return Err(SteamUtilError::HomeDirectoryNotFound);
-        }
+        };
+        info!(
+            "Looking for Steam directories in {}",
+            user_profile.display()
+        );
+        let mut steam_directories: Vec<PathBuf> = Vec::new();
+        let mut canonical_directories: Vec<PathBuf> = Vec::new();
+        for steam_dir in &POSSIBLE_STEAM_ROOTS {
+            let expanded_steam_dir = user_profile.join(steam_dir);
+            let ct_dir = expanded_steam_dir.join("config");
+            let config_vdf = ct_dir.join("config.vdf"); // this does exist on clean install
+            let libraryfolders_vdf = ct_dir.join("libraryfolders.vdf"); // On a clean install doesn't exist, it's generated after login
+            if !config_vdf.exists() || !libraryfolders_vdf.exists() {
+                continue;
+            }
 
-        Err(SteamUtilError::SteamDirectoryNotFound)
+            // Several of the roots are usually symlinks to the same directory
+            let canonical = expanded_steam_dir
+                .canonicalize()
+                .unwrap_or_else(|_| expanded_steam_dir.clone());
+            if !canonical_directories.contains(&canonical) {
+                info!("Found Steam directory: {}", expanded_steam_dir.display());
+                canonical_directories.push(canonical);
+                steam_directories.push(expanded_steam_dir);
+            }
+        }
+        Ok(steam_directories)
     }
 
     pub fn find() -> Result<Self, SteamUtilError> {
         match SteamUtil::find_steam_directory(None) {
-            Ok(steam_home) => Ok(Self {
-                steam_path: steam_home,
-            }),
+            Ok(steam_home) => Ok(Self::new(steam_home)),
             Err(err) => Err(err),
         }
     }
 
+    /// The root of the Steam installation in use.
+    pub fn get_steam_path(&self) -> &Path {
+        &self.steam_path
+    }
+
+    /// Returns the other known Steam roots below `user_home` that have a compatibility tools
+    /// directory, e.g. the flatpak root after switching to native Steam.
+    pub fn find_other_steam_roots(&self, user_home: &Path) -> Vec<PathBuf> {
+        let active_root = self
+            .steam_path
+            .canonicalize()
+            .unwrap_or_else(|_| self.steam_path.clone());
+        let mut roots: Vec<PathBuf> = Vec::new();
+        for steam_dir in &POSSIBLE_STEAM_ROOTS {
+            // Several of the roots are usually symlinks to the same directory
+            let Ok(root) = user_home.join(steam_dir).canonicalize() else {
+                continue;
+            };
+            if root != active_root
+                && root.join("compatibilitytools.d").is_dir()
+                && !roots.contains(&root)
+            {
+                roots.push(root);
+            }
+        }
+        roots
+    }
+
     pub fn get_steam_compatibility_tools_directory(&self) -> PathBuf {
         let path = self.steam_path.join("compatibilitytools.d"); // Apparently this is not created by default
         if !path.exists() && self.steam_path.exists() {
@@ -116,88 +437,69 @@ impl SteamUtil {
                 .map_err(|_err| SteamUtilError::CompatibilityToolsDirectoryCreationFailed)
                 .unwrap();
         }
-        return path;
+        path
     }
 
-    pub fn read_compatibility_tool_from_vdf_path(
+    /// Reads every tool the VDF declares, the file is rejected if any of them is malformed.
+    pub fn read_compatibility_tools_from_vdf_path(
         &self,
         compat_tool_vdf: &PathBuf,
-    ) -> Result<CompatibilityTool, SteamUtilError> {
-        // Read the content of the VDF file
-        let vdf_text = fs::read_to_string(compat_tool_vdf)
-            .map_err(|err| SteamUtilError::VdfParsingError(err.to_string()))?;
-
-        // Parse the VDF text into a Vdf struct
-        let vdf = Vdf::parse(&vdf_text)
-            .map_err(|err| SteamUtilError::VdfParsingError(err.to_string()))?;
+    ) -> Result<Vec<CompatibilityTool>, SteamUtilError> {
+        let invalid = |reason: String| {
+            SteamUtilError::VdfParsingError(format!("{}: {}", compat_tool_vdf.display(), reason))
+        };
+        let vdf_text =
+            fs::read_to_string(compat_tool_vdf).map_err(|err| invalid(err.to_string()))?;
+        let vdf = Vdf::parse(&vdf_text).map_err(|err| invalid(err.to_string()))?;
 
-        // Extract the compatibility tool object from the parsed VDF
-        let compat_tool_obj = vdf
+        let compat_tools = vdf
             .value
             .get_obj()
             .and_then(|f| f.values().next())
-            .and_then(|f| f.get(0))
+            .and_then(|f| f.first())
             .and_then(|f| f.get_obj())
-            .ok_or_else(|| SteamUtilError::VdfParsingError("Invalid VDF structure".to_string()))?;
+            .ok_or_else(|| invalid("no compat_tools block".to_string()))?;
+        if compat_tools.is_empty() {
+            return Err(invalid("no tool declared".to_string()));
+        }
 
-        // Extract the path from the compatibility tool VDF file
         let path = compat_tool_vdf
             .parent()
             .ok_or_else(|| SteamUtilError::VdfMissingEntry("Parent directory not found".to_string()))?
             .to_path_buf();
-
-        // Extract directory name from the path
         let directory_name = path
             .file_name()
             .and_then(|o| o.to_str())
             .ok_or_else(|| SteamUtilError::VdfMissingEntry("Directory name not found".to_string()))?
             .to_string();
-
-        // Extract internal name, display name, from_os_list, and to_os_list from the compatibility tool object
-        let internal_name = compat_tool_obj
-            .keys()
-            .next()
-            .ok_or_else(|| SteamUtilError::VdfMissingEntry("Internal name not found".to_string()))?
-            .to_string();
-
-        let internal_value = compat_tool_obj
-            .values()
-            .next()
-            .and_then(|o| o.get(0))
-            .and_then(|o| o.get_obj())
-            .ok_or_else(|| SteamUtilError::VdfMissingEntry("Internal value not found".to_string()))?;
-
-        let display_name = internal_value
-            .get("display_name")
-            .and_then(|o| o.get(0))
-            .and_then(|o| o.get_str())
-            .and_then(|o| Option::from(o.to_string()))
-            .ok_or_else(|| SteamUtilError::VdfMissingEntry("Display name not found".to_string()))?;
-
-        let from_os_list = internal_value
-            .get("from_oslist")
-            .and_then(|o| o.get(0))
-            .and_then(|o| o.get_str())
-            .and_then(|o| Option::from(o.to_string()))
-            .ok_or_else(|| SteamUtilError::VdfMissingEntry("From OS list not found".to_string()))?;
-
-        let to_os_list = internal_value
-            .get("to_oslist")
-            .and_then(|o| o.get(0))
-            .and_then(|o| o.get_str())
-            .and_then(|o| Option::from(o.to_string()))
-            .ok_or_else(|| SteamUtilError::VdfMissingEntry("To OS list not found".to_string()))?;
-
-        // Create a CompatibilityTool struct and return it
-        let steam_compat_tool = CompatibilityTool {
-            path,
-            directory_name,
-            internal_name,
-            display_name,
-            from_os_list,
-            to_os_list,
-        };
-        Ok(steam_compat_tool)
+        let supports_32bit = detect_32bit_support(&path);
+
+        // Multi-runner packages declare several tools sharing the directory
+        compat_tools
+            .iter()
+            .map(|(internal_name, tool)| {
+                let tool = tool
+                    .first()
+                    .and_then(|o| o.get_obj())
+                    .ok_or_else(|| invalid(format!("{} isn't a block", internal_name)))?;
+                let entry = |key: &str| {
+                    tool.get(key)
+                        .and_then(|o| o.first())
+                        .and_then(|o| o.get_str())
+                        .map(str::to_string)
+                        .ok_or_else(|| invalid(format!("{} has no {}", internal_name, key)))
+                };
+                Ok(CompatibilityTool {
+                    path: path.clone(),
+                    directory_name: directory_name.clone(),
+                    internal_name: internal_name.to_string(),
+                    display_name: entry("display_name")?,
+                    from_os_list: entry("from_oslist")?,
+                    to_os_list: entry("to_oslist")?,
+                    supports_32bit,
+                })
+            })
+            .collect()
     }
 
     pub fn list_compatibility_tools(&self) -> Result<Vec<CompatibilityTool>, SteamUtilError> {
@@ -207,19 +509,68 @@ impl SteamUtil {
             .unwrap()
             .filter_map(Result::ok)
             .filter(|x| {
-                x.metadata().unwrap().is_dir() && x.path().join("compatibilitytool.vdf").exists()
+                x.metadata().is_ok_and(|metadata| metadata.is_dir())
+                    && x.path().join("compatibilitytool.vdf").exists()
             })
-            .map(|x| {
-                self.read_compatibility_tool_from_vdf_path(&x.path().join("compatibilitytool.vdf"))
-                    .unwrap()
+            .filter_map(|x| {
+                let vdf = x.path().join("compatibilitytool.vdf");
+                // One broken third-party tool shouldn't hide all the others
+                self.read_compatibility_tools_from_vdf_path(&vdf)
+                    .map_err(|err| warn!("Skipping {}: {}", x.path().display(), err))
+                    .ok()
             })
+            .flatten()
             .collect();
 
         Ok(compat_tools)
     }
 
-    pub fn get_compatibility_tools_mappings(&self) -> Result<HashMap<u64, String>, SteamUtilError> {
-        let steam_config_file = self.steam_path.join("config").join("config.vdf");
+    /// Lists Valve's Proton releases installed in the mounted library folders, Steam installs,
+    /// updates and uninstalls them itself.
+    pub fn list_official_proton_tools(&self) -> Result<Vec<OfficialProtonTool>, SteamUtilError> {
+        let tools = self
+            .list_installed_apps()?
+            .into_iter()
+            .filter(|app| app.kind == AppKind::Tool)
+            .filter_map(|app| {
+                let path = app
+                    .library_path
+                    .join("steamapps/common")
+                    .join(&app.installdir);
+                // Runtimes and redistributables are tools as well, only Proton has the script
+                if !path.join("proton").is_file() {
+                    return None;
+                }
+                let internal_name = self
+                    .lookup_compat_tool_name(app.app_id)
+                    .or_else(|| official_proton_internal_name(&app.installdir))?;
+                let build = fs::read_to_string(path.join("version"))
+                    .ok()
+                    .and_then(|contents| read_proton_build(&contents));
+                // Numbered releases are shown like Steam shows them, e.g. `Proton 9.0-2`
+                let (display_name, version) = match build {
+                    Some(build) => match build.strip_prefix("proton-") {
+                        Some(version) => (format!("Proton {}", version), Some(version.to_string())),
+                        None => (app.name.clone(), Some(build)),
+                    },
+                    None => (app.name.clone(), None),
+                };
+                Some(OfficialProtonTool {
+                    app_id: app.app_id,
+                    path,
+                    internal_name,
+                    display_name,
+                    version,
+                })
+            })
+            .collect();
+        Ok(tools)
+    }
+
+    pub fn get_compatibility_tools_mappings(
+        &self,
+    ) -> Result<HashMap<CompatAppId, String>, SteamUtilError> {
+        let steam_config_file = self.get_config_vdf_path();
 
         if !steam_config_file.exists() {
             return Err(SteamUtilError::SteamConfigVdfNotFound);
@@ -236,7 +587,7 @@ impl SteamUtil {
             .value
             .get_obj()
             .and_then(|config| config.get("Software"))
-            .and_then(|o| o.get(0))
+            .and_then(|o| o.first())
             .and_then(|f| f.get_obj())
             .ok_or_else(||SteamUtilError::VdfMissingEntry(
                 "Software object not found".to_string(),
@@ -245,7 +596,7 @@ impl SteamUtil {
         let valve_vdf_obj = software_vdf_obj
             .get("Valve")
             .or(software_vdf_obj.get("valve"))
-            .and_then(|valve_obj| valve_obj.get(0))
+            .and_then(|valve_obj| valve_obj.first())
             .and_then(|o| o.get_obj())
             .ok_or_else(||SteamUtilError::VdfMissingEntry(
                 "Valve object not found".to_string(),
@@ -253,7 +604,7 @@ impl SteamUtil {
 
         let steam_obj = valve_vdf_obj
             .get("Steam")
-            .and_then(|steam| steam.get(0))
+            .and_then(|steam| steam.first())
             .and_then(|o| o.get_obj())
             .ok_or_else(||SteamUtilError::VdfMissingEntry(
                 "Steam object not found".to_string(),
@@ -261,27 +612,26 @@ impl SteamUtil {
 
         let compat_tool_mapping = steam_obj
             .get("CompatToolMapping")
-            .and_then(|o| o.get(0))
+            .and_then(|o| o.first())
             .and_then(|f| f.get_obj())
             .ok_or_else(||SteamUtilError::VdfMissingEntry(
                 "CompatToolMapping object not found".to_string(),
             ))?;
 
-        let mut compatibility_tools_mappings: HashMap<u64, String> = HashMap::new();
+        let mut compatibility_tools_mappings: HashMap<CompatAppId, String> = HashMap::new();
         for (key, value) in compat_tool_mapping.iter() {
-            let key: u64 = key.parse().map_err(|_| {
-                SteamUtilError::VdfMissingEntry("Error parsing key to u64".to_string())
-            })?;
+            let key = CompatAppId::parse(key)
+                .map_err(|err| SteamUtilError::VdfMissingEntry(err.to_string()))?;
             let key_obj =
                 value
-                    .get(0)
+                    .first()
                     .and_then(|o| o.get_obj())
                     .ok_or_else(||SteamUtilError::VdfMissingEntry(
                         "Key object not found".to_string(),
                     ))?;
             let compat_tool_name = key_obj
                 .get("name")
-                .and_then(|n| n.get(0))
+                .and_then(|n| n.first())
                 .and_then(|o| o.get_str())
                 .ok_or_else(||SteamUtilError::VdfMissingEntry(
                     "Compat tool name not found or invalid".to_string(),
@@ -295,6 +645,403 @@ impl SteamUtil {
         Ok(compatibility_tools_mappings)
     }
 
+    /// Sets the compatibility tool Steam uses for an app.
+    ///
+    /// Steam only reads config.vdf on startup, the change applies after a restart.
+    pub fn set_compatibility_tool_mapping(
+        &self,
+        app_id: CompatAppId,
+        internal_name: &str,
+        force: bool,
+    ) -> Result<ConfigWrite, SteamUtilError> {
+        self.set_compatibility_tool_mappings(&[(app_id, Some(internal_name))], force)
+    }
+
+    /// Removes the mapping of an app, so it uses the default tool again.
+    pub fn remove_compatibility_tool_mapping(
+        &self,
+        app_id: CompatAppId,
+        force: bool,
+    ) -> Result<ConfigWrite, SteamUtilError> {
+        self.set_compatibility_tool_mappings(&[(app_id, None)], force)
+    }
+
+    /// Applies every mapping change in order with a single write of config.vdf, `None` removes
+    /// the mapping.
+    ///
+    /// Steam writes config.vdf when it exits, which would undo the changes. Unless `force` is
+    /// set nothing is written while it's running.
+    pub fn set_compatibility_tool_mappings(
+        &self,
+        changes: &[(CompatAppId, Option<&str>)],
+        force: bool,
+    ) -> Result<ConfigWrite, SteamUtilError> {
+        if !force && steam_started_at(&self.proc_root).is_some() {
+            return Err(SteamUtilError::SteamRunning);
+        }
+        let steam_config_file = self.get_config_vdf_path();
+        self.rewrite_config_vdf(|config| apply_mapping_changes(config, changes, &steam_config_file))
+    }
+
+    /// Replaces config.vdf with what `mutate` makes of it, the version being replaced is kept as
+    /// `config.vdf.wine-cellar-backup`.
+    ///
+    /// Steam rewrites config.vdf on its own, when it did so since the file was read it is read
+    /// again and `mutate` re-applied so neither change is lost.
+    fn rewrite_config_vdf(
+        &self,
+        mutate: impl Fn(&str) -> Result<String, SteamUtilError>,
+    ) -> Result<ConfigWrite, SteamUtilError> {
+        let steam_config_file = self.get_config_vdf_path();
+        let backup_file = steam_config_file.with_extension("vdf.wine-cellar-backup");
+        // Write next to config.vdf and rename so Steam never reads a partial file
+        let temporary_file = steam_config_file.with_extension("vdf.wine-cellar");
+        let mut first_read = None;
+        let mut retries = 0;
+        loop {
+            let (config, fingerprint) = read_config(&steam_config_file)?;
+            first_read.get_or_insert_with(|| fingerprint.sha256.clone());
+            let updated = mutate(&config)?;
+
+            // config.vdf itself can't carry a stamp, it goes into a file next to the backup
+            let written_by = WrittenBy::next("config_vdf_backup", &backup_file);
+            fs::write(&backup_file, &config)
+                .and_then(|_| {
+                    fs::write(
+                        backup_file.with_extension("wine-cellar-backup.json"),
+                        serde_json::to_string_pretty(&written_by)?,
+                    )
+                })
+                .and_then(|_| fs::write(&temporary_file, updated))
+                .map_err(|err| SteamUtilError::SteamConfigVdfWriteFailed(err.to_string()))?;
+
+            if read_config(&steam_config_file)?.1 != fingerprint {
+                if retries == CONFIG_WRITE_RETRIES {
+                    let _ = fs::remove_file(&temporary_file);
+                    return Err(SteamUtilError::SteamConfigVdfWriteFailed(format!(
+                        "it kept changing while being written, gave up after {} retries",
+                        retries
+                    )));
+                }
+                retries += 1;
+                warn!("config.vdf changed while being written, applying the changes again");
+                continue;
+            }
+            fs::rename(&temporary_file, &steam_config_file)
+                .map_err(|err| SteamUtilError::SteamConfigVdfWriteFailed(err.to_string()))?;
+            return Ok(ConfigWrite {
+                retries,
+                merged_foreign_change: first_read.as_ref() != Some(&fingerprint.sha256),
+            });
+        }
+    }
+
+    /// Whether the Steam client is running, it only reads compatibilitytools.d when it starts.
+    pub fn is_steam_running(&self) -> bool {
+        steam_started_at(&self.proc_root).is_some()
+    }
+
+    /// Shuts Steam down and starts it again, so it lists the tools installed since it started.
+    ///
+    /// Nothing happens while a game is running. In the gamescope session the session starts Steam
+    /// again once it exits, on the desktop it's launched here.
+    pub fn restart_steam(&self, game_mode: bool) -> Result<(), SteamUtilError> {
+        let running_app_id = self
+            .get_running_app_ids()
+            .ok()
+            .and_then(|app_ids| app_ids.first().copied())
+            .or_else(|| running_game(&self.proc_root));
+        if let Some(app_id) = running_app_id {
+            return Err(SteamUtilError::GameRunning(app_id));
+        }
+        if !self.is_steam_running() {
+            return Ok(());
+        }
+
+        info!("Shutting Steam down");
+        let status = Command::new("steam")
+            .arg("-shutdown")
+            .status()
+            .map_err(|err| SteamUtilError::SteamRestartFailed(err.to_string()))?;
+        if !status.success() {
+            return Err(SteamUtilError::SteamRestartFailed(format!(
+                "steam -shutdown exited with {}",
+                status
+            )));
+        }
+        let deadline = Instant::now() + STEAM_SHUTDOWN_TIMEOUT;
+        while self.is_steam_running() {
+            if Instant::now() > deadline {
+                return Err(SteamUtilError::SteamRestartFailed(format!(
+                    "Steam was still running {} seconds after being asked to shut down",
+                    STEAM_SHUTDOWN_TIMEOUT.as_secs()
+                )));
+            }
+            thread::sleep(Duration::from_millis(500));
+        }
+
+        if !game_mode {
+            info!("Starting Steam");
+            let mut steam = Command::new("steam")
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|err| SteamUtilError::SteamRestartFailed(err.to_string()))?;
+            // Reaped whenever Steam exits, which may be long after the backend stopped waiting
+            thread::spawn(move || steam.wait());
+        }
+        Ok(())
+    }
+
+    /// Steam's registry.vdf, in the `.steam` directory of the home directory the Steam root is in.
+    fn find_registry_vdf(&self) -> Option<PathBuf> {
+        self.steam_path
+            .ancestors()
+            .map(|ancestor| ancestor.join(".steam").join("registry.vdf"))
+            .find(|path| path.is_file())
+    }
+
+    /// Lists the apps Steam runs, from registry.vdf and the reaper processes Steam launches games
+    /// through. Only fails if neither can be read.
+    pub fn get_running_app_ids(&self) -> Result<Vec<AppId>, SteamUtilError> {
+        let registry = self
+            .find_registry_vdf()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .ok_or(SteamUtilError::RegistryVdfNotFound)
+            .and_then(|registry| parse_running_app_ids(&registry));
+        match (registry, reaper_app_ids(&self.proc_root)) {
+            (Err(err), None) => Err(err),
+            (registry, reapers) => {
+                if let Err(err) = &registry {
+                    warn!("Failed to read the running apps from registry.vdf: {}", err);
+                }
+                let mut app_ids: Vec<AppId> = registry
+                    .unwrap_or_default()
+                    .into_iter()
+                    .chain(reapers.unwrap_or_default())
+                    .collect();
+                app_ids.sort();
+                app_ids.dedup();
+                Ok(app_ids)
+            }
+        }
+    }
+
+    /// Steam's settings, the compatibility tool mappings among them.
+    pub fn get_config_vdf_path(&self) -> PathBuf {
+        self.steam_path.join("config").join("config.vdf")
+    }
+
+    /// Steam's cache of app metadata, binary and only updated by Steam itself.
+    pub fn get_appinfo_path(&self) -> PathBuf {
+        self.steam_path.join("appcache").join("appinfo.vdf")
+    }
+
+    /// Non-Steam shortcuts of the account whose directory in `userdata` is `user_id`.
+    pub fn get_shortcuts_path(&self, user_id: u32) -> PathBuf {
+        self.steam_path
+            .join("userdata")
+            .join(user_id.to_string())
+            .join("config")
+            .join("shortcuts.vdf")
+    }
+
+    pub fn read_shortcuts(&self, user_id: u32) -> Result<KeyValue, SteamUtilError> {
+        let bytes = fs::read(self.get_shortcuts_path(user_id))
+            .map_err(|_err| SteamUtilError::ShortcutsVdfNotFound)?;
+        read_key_values(&bytes).map_err(|err| SteamUtilError::VdfParsingError(err.to_string()))
+    }
+
+    /// Replaces shortcuts.vdf with `shortcuts`, the version being replaced is kept as
+    /// `shortcuts.vdf.wine-cellar-backup-<unix timestamp>` so later writes don't replace it.
+    ///
+    /// Like config.vdf, Steam writes the shortcuts it has loaded back when they change in the
+    /// client, so it's up to the caller to check whether Steam is running.
+    pub fn write_shortcuts(
+        &self,
+        user_id: u32,
+        shortcuts: &KeyValue,
+    ) -> Result<(), SteamUtilError> {
+        let shortcuts_file = self.get_shortcuts_path(user_id);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let backup_extension = format!("vdf.wine-cellar-backup-{}", timestamp);
+        let backup_file = shortcuts_file.with_extension(&backup_extension);
+        // Write next to shortcuts.vdf and rename so Steam never reads a partial file
+        let temporary_file = shortcuts_file.with_extension("vdf.wine-cellar");
+        let original =
+            fs::read(&shortcuts_file).map_err(|_err| SteamUtilError::ShortcutsVdfNotFound)?;
+
+        // A backup from the same second already holds the older version
+        let backed_up = if backup_file.exists() {
+            Ok(())
+        } else {
+            let written_by = WrittenBy::next("shortcuts_vdf_backup", &backup_file);
+            fs::write(&backup_file, original).and_then(|_| {
+                fs::write(
+                    shortcuts_file.with_extension(format!("{}.json", backup_extension)),
+                    serde_json::to_string_pretty(&written_by)?,
+                )
+            })
+        };
+        backed_up
+            .and_then(|_| fs::write(&temporary_file, write_key_values(shortcuts)))
+            .and_then(|_| fs::rename(&temporary_file, &shortcuts_file))
+            .map_err(|err| SteamUtilError::ShortcutsVdfWriteFailed(err.to_string()))
+    }
+
+    /// Accounts that have a directory in `userdata`, whether or not they have shortcuts.
+    fn list_user_ids(&self) -> Vec<u32> {
+        let mut user_ids: Vec<u32> = fs::read_dir(self.steam_path.join("userdata"))
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect();
+        user_ids.sort_unstable();
+        user_ids
+    }
+
+    /// Shortcuts of every account. Fails if a shortcuts.vdf can't be read, so no shortcut is taken
+    /// for removed while its account's file is broken.
+    pub fn list_shortcut_ids(&self) -> Result<Vec<ShortcutId>, SteamUtilError> {
+        let mut shortcut_ids = Vec::new();
+        for user_id in self.list_user_ids() {
+            let shortcuts = match self.read_shortcuts(user_id) {
+                Ok(shortcuts) => shortcuts,
+                // Accounts that never added a shortcut have no shortcuts.vdf
+                Err(SteamUtilError::ShortcutsVdfNotFound) => continue,
+                Err(err) => return Err(err),
+            };
+            let shortcuts = shortcuts.get("shortcuts").map(KeyValue::entries);
+            shortcut_ids.extend(
+                shortcuts
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|(_, shortcut)| ShortcutDetails::from_shortcut(user_id, shortcut))
+                    .map(|details| details.shortcut_id),
+            );
+        }
+        shortcut_ids.sort_unstable();
+        shortcut_ids.dedup();
+        Ok(shortcut_ids)
+    }
+
+    /// The shortcut with `shortcut_id` in every account that has it.
+    pub fn get_shortcut_details(
+        &self,
+        shortcut_id: ShortcutId,
+    ) -> Result<Vec<ShortcutDetails>, SteamUtilError> {
+        let mut details = Vec::new();
+        for user_id in self.list_user_ids() {
+            let Ok(shortcuts) = self.read_shortcuts(user_id) else {
+                continue;
+            };
+            let shortcuts = shortcuts.get("shortcuts").map(KeyValue::entries);
+            details.extend(
+                shortcuts
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|(_, shortcut)| ShortcutDetails::from_shortcut(user_id, shortcut))
+                    .filter(|details| details.shortcut_id == shortcut_id),
+            );
+        }
+        if details.is_empty() {
+            return Err(SteamUtilError::ShortcutNotFound(shortcut_id));
+        }
+        Ok(details)
+    }
+
+    /// Sets the launch options of the shortcut with `shortcut_id` in every account that has it.
+    ///
+    /// Steam writes back the shortcuts it has loaded, so nothing is written while it runs.
+    pub fn set_shortcut_launch_options(
+        &self,
+        shortcut_id: ShortcutId,
+        launch_options: &str,
+    ) -> Result<Vec<ShortcutDetails>, SteamUtilError> {
+        if steam_started_at(&self.proc_root).is_some() {
+            return Err(SteamUtilError::SteamRunning);
+        }
+        let mut details = Vec::new();
+        for user_id in self.list_user_ids() {
+            let Ok(mut shortcuts) = self.read_shortcuts(user_id) else {
+                continue;
+            };
+            let mut changed = false;
+            let entries = shortcuts.get_mut("shortcuts").map(KeyValue::entries_mut);
+            for (_, shortcut) in entries.unwrap_or_default() {
+                let Some(mut shortcut_details) = ShortcutDetails::from_shortcut(user_id, shortcut)
+                else {
+                    continue;
+                };
+                if shortcut_details.shortcut_id != shortcut_id {
+                    continue;
+                }
+                if shortcut_details.launch_options != launch_options {
+                    shortcut.set(
+                        "LaunchOptions",
+                        KeyValue::String(launch_options.to_string()),
+                    );
+                    shortcut_details.launch_options = launch_options.to_string();
+                    changed = true;
+                }
+                details.push(shortcut_details);
+            }
+            if changed {
+                self.write_shortcuts(user_id, &shortcuts)?;
+            }
+        }
+        if details.is_empty() {
+            return Err(SteamUtilError::ShortcutNotFound(shortcut_id));
+        }
+        Ok(details)
+    }
+
+    /// Looks up the name Steam has cached for an app, which it keeps after the app is uninstalled.
+    pub fn lookup_app_name(&self, app_id: AppId) -> Option<String> {
+        self.read_app_info_names()
+            .names
+            .get(&app_id.value())
+            .cloned()
+    }
+
+    /// Looks up the internal name Steam registered for one of its own tools, e.g. `proton_9`.
+    pub fn lookup_compat_tool_name(&self, app_id: AppId) -> Option<String> {
+        self.read_app_info_names()
+            .compat_tool_names
+            .get(&app_id.value())
+            .cloned()
+    }
+
+    /// Names from appinfo.vdf, read again if Steam updated it since.
+    fn read_app_info_names(&self) -> MutexGuard<'_, AppInfoNames> {
+        let appinfo = self.get_appinfo_path();
+        let fingerprint = fs::metadata(&appinfo)
+            .ok()
+            .and_then(|metadata| Some((metadata.modified().ok()?, metadata.len())));
+        let mut app_names = self.app_names.lock().unwrap();
+        if fingerprint != app_names.fingerprint {
+            app_names.fingerprint = fingerprint;
+            // Missing until Steam downloaded app info for the first time
+            let bytes = fs::read(&appinfo).unwrap_or_default();
+            app_names.names = read_app_names(&bytes).unwrap_or_else(|err| {
+                if !bytes.is_empty() {
+                    warn!("Failed to read app names from appinfo.vdf: {}", err);
+                }
+                HashMap::new()
+            });
+            app_names.compat_tool_names = read_compat_tool_names(&bytes).unwrap_or_else(|err| {
+                if !bytes.is_empty() {
+                    warn!("Failed to read tool names from appinfo.vdf: {}", err);
+                }
+                HashMap::new()
+            });
+        }
+        app_names
+    }
+
     /// Lists library folders.
     pub fn list_library_folders(&self) -> Result<Vec<PathBuf>, SteamUtilError> {
         let steam_apps_directory = self.steam_path.join("steamapps");
@@ -310,23 +1057,23 @@ impl SteamUtil {
         }
 
         let library_folders_vdf = fs::read_to_string(&library_folders_vdf_file)
-            .map_err(|err| SteamUtilError::VdfParsingError(err.to_string()))
-            .unwrap();
+            .map_err(|err| SteamUtilError::VdfParsingError(err.to_string()))?;
         let vdf = Vdf::parse(&library_folders_vdf)
-            .map_err(|err| SteamUtilError::VdfParsingError(err.to_string()))
-            .unwrap();
-        let app_state_obj = vdf.value.get_obj().unwrap();
+            .map_err(|err| SteamUtilError::VdfParsingError(err.to_string()))?;
+        let app_state_obj = vdf.value.get_obj().ok_or_else(|| {
+            SteamUtilError::VdfMissingEntry("libraryfolders object not found".to_string())
+        })?;
 
         let mut library_folders: Vec<PathBuf> = Vec::new();
 
         for value in app_state_obj.values() {
             let key_obj = value
-                .get(0)
+                .first()
                 .and_then(|o| o.get_obj())
                 .ok_or_else(||SteamUtilError::VdfMissingEntry("Fail to retrieve entry object".to_string()))?;
             let path = key_obj
                 .get("path")
-                .and_then(|o| o.get(0))
+                .and_then(|o| o.first())
                 .and_then(|o| o.get_str())
                 .ok_or_else(||SteamUtilError::VdfMissingEntry("Fail to retrieve path".to_string()))?
                 .to_string();
@@ -338,23 +1085,43 @@ impl SteamUtil {
         Ok(library_folders)
     }
 
-    /// Lists the installed games across all library folders.
+    /// Lists the library folders whose media is mounted and readable, leaving out those on an SD
+    /// card that was taken out. Waits on the media, so don't call it on the async runtime.
+    pub fn list_mounted_library_folders(&self) -> Result<Vec<PathBuf>, SteamUtilError> {
+        Ok(self
+            .list_library_folders()?
+            .into_iter()
+            .filter(|library_folder| library_folder_mounted(library_folder))
+            .collect())
+    }
+
+    /// Lists the installed games across all library folders, apps Steam is uninstalling aren't
+    /// offered a tool anymore and tools and runtimes aren't games.
     pub fn list_installed_games(&self) -> Result<Vec<SteamApp>, SteamUtilError> {
+        Ok(self
+            .list_installed_apps()?
+            .into_iter()
+            .filter(|app| {
+                app.install_state != AppInstallState::Uninstalling && app.kind == AppKind::Game
+            })
+            .map(|app| SteamApp {
+                app_id: app.app_id,
+                name: app.name,
+                installed: true,
+            })
+            .collect())
+    }
+
+    /// Lists the apps of every library folder as their appmanifests describe them.
+    pub fn list_installed_apps(&self) -> Result<Vec<InstalledApp>, SteamUtilError> {
         // todo: problem is this function can also return partial results because one library folder might be broken but the others might still work properly
-        let mut apps: Vec<SteamApp> = Vec::new();
-        match self.list_library_folders() {
+        let mut apps: Vec<InstalledApp> = Vec::new();
+        // Folders on an SD card that was taken out are expected to be missing
+        match self.list_mounted_library_folders() {
             Ok(library_folders) => {
                 for library_folder in library_folders {
-                    let library_folder = library_folder.join("steamapps");
-                    if !library_folder.exists() {
-                        error!(
-                            "Library folder {} does not exist",
-                            library_folder.to_str().unwrap()
-                        );
-                        continue;
-                    }
-                    match &mut self.find_installed_games(library_folder.clone()) {
-                        Ok(steam_apps) => apps.append(steam_apps),
+                    match &mut self.find_installed_apps(&library_folder) {
+                        Ok(installed_apps) => apps.append(installed_apps),
                         Err(err) => {
                             error!(
                                 "Failed to find installed games in library folder {}: {}",
@@ -374,41 +1141,126 @@ impl SteamUtil {
         Ok(apps)
     }
 
-    pub fn find_installed_games(
+    /// Lists the prefixes in `compatdata` of every library folder whose app isn't installed anymore
+    /// or whose shortcut was removed. The default prefix of app id 0 and directories that aren't
+    /// named after an app are never listed.
+    pub fn list_orphaned_compat_data(&self) -> Result<Vec<OrphanedCompatData>, SteamUtilError> {
+        // Without the installed games every prefix would look orphaned, apps being uninstalled
+        // still count
+        let mut installed: Vec<CompatAppId> = self
+            .list_installed_apps()?
+            .into_iter()
+            .map(|app| CompatAppId::from(app.app_id))
+            .collect();
+        // Shortcut prefixes are only listed if every shortcut is known
+        let shortcuts_known = match self.list_shortcut_ids() {
+            Ok(shortcut_ids) => {
+                installed.extend(shortcut_ids.into_iter().map(CompatAppId::from));
+                true
+            }
+            Err(err) => {
+                warn!("Failed to list shortcuts, skipping their prefixes: {}", err);
+                false
+            }
+        };
+        let mut orphaned: Vec<OrphanedCompatData> = Vec::new();
+        for library_folder in self.list_mounted_library_folders()? {
+            let Ok(entries) = fs::read_dir(library_folder.join("steamapps").join("compatdata"))
+            else {
+                continue;
+            };
+            for entry in entries.filter_map(Result::ok) {
+                let Ok(app_id) = CompatAppId::parse(&entry.file_name().to_string_lossy()) else {
+                    continue;
+                };
+                let listed = match app_id.kind() {
+                    CompatAppIdKind::App(_) => true,
+                    CompatAppIdKind::Shortcut(_) => shortcuts_known,
+                    CompatAppIdKind::Default | CompatAppIdKind::LegacyShortcut(_) => false,
+                };
+                if !listed {
+                    continue;
+                }
+                // Symlinked prefixes may point into a prefix that's still in use
+                let is_directory = entry
+                    .file_type()
+                    .is_ok_and(|file_type| file_type.is_dir());
+                if !is_directory || installed.contains(&app_id) {
+                    continue;
+                }
+                orphaned.push(OrphanedCompatData {
+                    app_id,
+                    name: None,
+                    size: 0,
+                    path: entry.path(),
+                });
+            }
+        }
+        orphaned.sort_by_key(|compat_data| compat_data.app_id);
+        Ok(orphaned)
+    }
+
+    /// Lists the apps mapped to a tool. Apps without an appmanifest are named by their app id.
+    pub fn get_applications_using_tool(&self, internal_name: &str) -> Vec<SteamApp> {
+        let mappings = match self.get_compatibility_tools_mappings() {
+            Ok(mappings) => mappings,
+            Err(err) => {
+                warn!("Failed to get compatibility tools mappings: {}", err);
+                return Vec::new();
+            }
+        };
+        let installed_games: HashMap<AppId, String> = self
+            .list_installed_games()
+            .unwrap_or_else(|err| {
+                warn!("Failed to get list of installed games: {}", err);
+                Vec::new()
+            })
+            .into_iter()
+            .map(|game| (game.app_id, game.name))
+            .collect();
+
+        // Shortcuts aren't listed, they have no app id
+        let mut apps: Vec<SteamApp> = mappings
+            .into_iter()
+            .filter(|(_, compatibility_tool)| compatibility_tool == internal_name)
+            .filter_map(|(compat_app_id, _)| compat_app_id.app_id())
+            .map(|app_id| match installed_games.get(&app_id) {
+                Some(name) => SteamApp {
+                    app_id,
+                    name: name.clone(),
+                    installed: true,
+                },
+                None => SteamApp {
+                    app_id,
+                    name: app_id.to_string(),
+                    installed: false,
+                },
+            })
+            .collect();
+        apps.sort_by_key(|app| app.app_id);
+        apps
+    }
+
+    /// Reads the appmanifests of a library folder, skipping those that can't be parsed.
+    pub fn find_installed_apps(
         &self,
-        steam_apps_directory: PathBuf,
-    ) -> Result<Vec<SteamApp>, SteamUtilError> {
-        let apps: Vec<SteamApp> = fs::read_dir(steam_apps_directory)
-            .map_err(|_err| SteamUtilError::SteamAppsDirectoryNotFound)
-            .unwrap()
+        library_folder: &Path,
+    ) -> Result<Vec<InstalledApp>, SteamUtilError> {
+        let apps: Vec<InstalledApp> = fs::read_dir(library_folder.join("steamapps"))
+            .map_err(|_err| SteamUtilError::SteamAppsDirectoryNotFound)?
             .filter_map(Result::ok)
             .filter(|x| x.path().extension().unwrap_or_default().eq("acf"))
-            .map(|file| {
-                let app_manifest = fs::read_to_string(file.path())
+            .filter_map(|file| {
+                let app = fs::read_to_string(file.path())
                     .map_err(|err| SteamUtilError::VdfParsingError(err.to_string()))
-                    .unwrap();
-                let vdf = Vdf::parse(&app_manifest)
-                    .map_err(|err| SteamUtilError::VdfParsingError(err.to_string()))
-                    .unwrap();
-                let app_state_obj = vdf.value.get_obj().unwrap();
-                let app_id: u64 = app_state_obj
-                    .get("appid")
-                    .unwrap()
-                    .get(0)
-                    .unwrap()
-                    .get_str()
-                    .unwrap()
-                    .parse()
-                    .unwrap();
-                let name: String = app_state_obj
-                    .get("name")
-                    .unwrap()
-                    .get(0)
-                    .unwrap()
-                    .get_str()
-                    .unwrap()
-                    .to_string();
-                SteamApp { app_id, name }
+                    .and_then(|app_manifest| parse_app_manifest(&app_manifest, library_folder));
+                match app {
+                    Ok(app) => Some(app),
+                    Err(err) => {
+                        warn!("Skipping {}: {}", file.path().display(), err);
+                        None
+                    }
+                }
             })
             .collect();
 
@@ -416,6 +1268,218 @@ impl SteamUtil {
     }
 }
 
+/// Reads an appmanifest of the library folder at `library_path`.
+fn parse_app_manifest(
+    app_manifest: &str,
+    library_path: &Path,
+) -> Result<InstalledApp, SteamUtilError> {
+    let vdf =
+        Vdf::parse(app_manifest).map_err(|err| SteamUtilError::VdfParsingError(err.to_string()))?;
+    let app_state_obj = vdf
+        .value
+        .get_obj()
+        .ok_or_else(|| SteamUtilError::VdfMissingEntry("AppState object not found".to_string()))?;
+    let field = |key: &str| vdf_entry(app_state_obj, key).and_then(Value::get_str);
+
+    let app_id = field("appid")
+        .and_then(|app_id| AppId::new(app_id.parse().ok()?).ok())
+        .ok_or_else(|| SteamUtilError::VdfMissingEntry("appid not found or invalid".to_string()))?;
+    let name = field("name")
+        .ok_or_else(|| SteamUtilError::VdfMissingEntry("name not found".to_string()))?
+        .to_string();
+    let state_flags = field("StateFlags")
+        .and_then(|state_flags| state_flags.parse().ok())
+        .unwrap_or(0);
+    let installdir = field("installdir").unwrap_or_default().to_string();
+    Ok(InstalledApp {
+        app_id,
+        name,
+        kind: app_kind(library_path, app_id, &installdir),
+        installdir,
+        library_path: library_path.to_path_buf(),
+        size_on_disk: field("SizeOnDisk")
+            .and_then(|size_on_disk| size_on_disk.parse().ok())
+            .unwrap_or(0),
+        state_flags,
+        install_state: AppInstallState::from_state_flags(state_flags),
+    })
+}
+
+/// Looks up an entry of a VDF object, Steam doesn't write its keys in a consistent case.
+fn vdf_entry<'a, 'text>(obj: &'a Obj<'text>, key: &str) -> Option<&'a Value<'text>> {
+    obj.iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(key))
+        .and_then(|(_, values)| values.first())
+}
+
+/// Returns the apps registry.vdf lists as running, through `RunningAppID` or their `Running` flag.
+fn parse_running_app_ids(registry: &str) -> Result<Vec<AppId>, SteamUtilError> {
+    let registry_vdf =
+        Vdf::parse(registry).map_err(|err| SteamUtilError::VdfParsingError(err.to_string()))?;
+    let steam_obj = ["HKCU", "Software", "Valve", "Steam"]
+        .iter()
+        .try_fold(&registry_vdf.value, |value, key| {
+            value.get_obj().and_then(|obj| vdf_entry(obj, key))
+        })
+        .and_then(Value::get_obj)
+        .ok_or_else(|| SteamUtilError::VdfMissingEntry("Steam object not found".to_string()))?;
+    let parse_app_id = |app_id: &str| AppId::new(app_id.parse().ok()?).ok();
+
+    // Zero while no game runs
+    let mut app_ids: Vec<AppId> = vdf_entry(steam_obj, "RunningAppID")
+        .and_then(Value::get_str)
+        .and_then(parse_app_id)
+        .into_iter()
+        .collect();
+    if let Some(apps) = vdf_entry(steam_obj, "apps").and_then(Value::get_obj) {
+        for (app_id, values) in apps.iter() {
+            let running = values
+                .first()
+                .and_then(Value::get_obj)
+                .and_then(|app| vdf_entry(app, "Running"))
+                .and_then(Value::get_str)
+                == Some("1");
+            if let Some(app_id) = parse_app_id(app_id.as_ref()).filter(|_| running) {
+                app_ids.push(app_id);
+            }
+        }
+    }
+    app_ids.sort();
+    app_ids.dedup();
+    Ok(app_ids)
+}
+
+/// Returns the apps of the reaper processes Steam launches games through, `None` if the processes
+/// can't be listed.
+fn reaper_app_ids(proc_root: &Path) -> Option<Vec<AppId>> {
+    let app_ids = fs::read_dir(proc_root)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            fs::read_to_string(entry.path().join("comm")).is_ok_and(|comm| comm.trim() == "reaper")
+        })
+        .filter_map(|entry| {
+            // reaper SteamLaunch AppId=1245620 -- ...
+            let cmdline = fs::read(entry.path().join("cmdline")).ok()?;
+            cmdline
+                .split(|byte| *byte == 0)
+                .find_map(|argument| argument.strip_prefix(b"AppId="))
+                .and_then(|app_id| std::str::from_utf8(app_id).ok()?.parse().ok())
+                .and_then(|app_id| AppId::new(app_id).ok())
+        })
+        .collect();
+    Some(app_ids)
+}
+
+/// Returns when the running Steam client started as a unix timestamp.
+pub fn steam_started_at(proc_root: &Path) -> Option<u64> {
+    let boot_time: u64 = fs::read_to_string(proc_root.join("stat"))
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()?;
+    fs::read_dir(proc_root)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().parse::<u32>().is_ok())
+        .filter(|entry| {
+            fs::read_to_string(entry.path().join("comm")).is_ok_and(|comm| comm.trim() == "steam")
+        })
+        .filter_map(|entry| {
+            // The command name may contain spaces, the fields after it don't
+            let stat = fs::read_to_string(entry.path().join("stat")).ok()?;
+            let start_ticks: u64 = stat
+                .rsplit_once(')')?
+                .1
+                .split_whitespace()
+                .nth(19)?
+                .parse()
+                .ok()?;
+            Some(boot_time + start_ticks / USER_HZ)
+        })
+        .min()
+}
+
+/// Returns a running game, found through the `SteamAppId` Steam sets for launched apps.
+pub fn running_game(proc_root: &Path) -> Option<AppId> {
+    fs::read_dir(proc_root)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().parse::<u32>().is_ok())
+        .find_map(|entry| {
+            let environ = fs::read(entry.path().join("environ")).ok()?;
+            environ
+                .split(|byte| *byte == 0)
+                .filter_map(|variable| variable.strip_prefix(b"SteamAppId="))
+                .filter_map(|app_id| std::str::from_utf8(app_id).ok()?.parse().ok())
+                .find_map(|app_id| AppId::new(app_id).ok())
+        })
+}
+
+/// Derives the internal name Steam uses for an official Proton release from its install directory,
+/// for when appinfo.vdf doesn't list it, e.g. `Proton 5.13` is `proton_513`.
+fn official_proton_internal_name(installdir: &str) -> Option<String> {
+    let lowercase = installdir.to_lowercase();
+    if lowercase.contains("experimental") {
+        return Some("proton_experimental".to_string());
+    }
+    if lowercase.contains("hotfix") {
+        return Some("proton_hotfix".to_string());
+    }
+    // `Proton 9.0 (Beta)`
+    let version = lowercase.strip_prefix("proton ")?.split(' ').next()?;
+    let (major, minor) = version.split_once('.').unwrap_or((version, "0"));
+    let is_number = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    if !is_number(major) || !is_number(minor) {
+        return None;
+    }
+    Some(match minor {
+        "0" => format!("proton_{}", major),
+        minor => format!("proton_{}{}", major, minor),
+    })
+}
+
+/// Reads the build from a Proton `version` file, which holds a build timestamp followed by the
+/// build, e.g. `1718226489 proton-9.0-2`.
+fn read_proton_build(contents: &str) -> Option<String> {
+    contents.split_whitespace().nth(1).map(str::to_string)
+}
+
+/// Detects whether a Wine based tool ships the libraries needed to run 32-bit games.
+fn detect_32bit_support(path: &Path) -> bool {
+    // Proton and its forks ship Wine in `files`, older releases in `dist`
+    let wine_roots: Vec<PathBuf> = ["files", "dist"]
+        .iter()
+        .map(|directory| path.join(directory))
+        .filter(|wine_root| wine_root.join("lib64").exists() || wine_root.join("lib").exists())
+        .collect();
+    if wine_roots.is_empty() {
+        return true;
+    }
+
+    wine_roots.iter().any(|wine_root| {
+        // Full and WoW64 builds both need the 32-bit PE libraries
+        let pe_libraries = ["lib", "lib64"].iter().any(|lib| {
+            wine_root
+                .join(lib)
+                .join("wine")
+                .join("i386-windows")
+                .is_dir()
+        });
+        // Before Wine 6 the 32-bit libraries were the only ones in `lib/wine`
+        let legacy_libraries = fs::read_dir(wine_root.join("lib").join("wine"))
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .any(|entry| entry.file_name().to_string_lossy().ends_with(".dll.so"))
+            })
+            .unwrap_or(false);
+        pe_libraries || legacy_libraries
+    })
+}
+
 impl Display for SteamUtilError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
@@ -436,6 +1500,31 @@ impl Display for SteamUtilError {
             SteamUtilError::SteamConfigVdfNotFound => write!(f, "Steam config file not found"),
             SteamUtilError::VdfParsingError(msg) => write!(f, "Failed to parse VDF file: {}", msg),
             SteamUtilError::VdfMissingEntry(msg) => write!(f, "Missing VDF entry: {}", msg),
+            SteamUtilError::SteamConfigVdfWriteFailed(msg) => {
+                write!(f, "Failed to write Steam config file: {}", msg)
+            }
+            SteamUtilError::SteamRunning => write!(
+                f,
+                "Steam is running and would overwrite its config file when it exits"
+            ),
+            SteamUtilError::GameRunning(app_id) => {
+                write!(
+                    f,
+                    "App {} is running, Steam isn't restarted under it",
+                    app_id
+                )
+            }
+            SteamUtilError::SteamRestartFailed(msg) => {
+                write!(f, "Failed to restart Steam: {}", msg)
+            }
+            SteamUtilError::RegistryVdfNotFound => write!(f, "Steam registry file not found"),
+            SteamUtilError::ShortcutsVdfNotFound => write!(f, "Steam shortcuts file not found"),
+            SteamUtilError::ShortcutsVdfWriteFailed(msg) => {
+                write!(f, "Failed to write Steam shortcuts file: {}", msg)
+            }
+            SteamUtilError::ShortcutNotFound(shortcut_id) => {
+                write!(f, "No Steam account has a shortcut with id {}", shortcut_id)
+            }
         }
     }
 }
@@ -449,7 +1538,10 @@ impl Error for SteamUtilError {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
+    use crate::appinfo::fixtures::{build_appinfo, object, string, SHORTCUTS_VDF};
+    use std::cell::Cell;
+    use std::sync::mpsc;
+    use std::{fs, thread};
     use tempfile::{tempdir, TempDir};
 
     // Helper function to create a test Steam directory with required files
@@ -598,6 +1690,45 @@ mod tests {
         steam_dir
     }
 
+    #[test]
+    fn test_find_all_steam_directories() {
+        let home = tempdir().expect("Failed to create temporary directory");
+        for root in [
+            ".local/share/Steam",
+            ".var/app/com.valvesoftware.Steam/data/Steam",
+        ] {
+            let config_dir = home.path().join(root).join("config");
+            fs::create_dir_all(&config_dir).expect("Failed to create config directory");
+            fs::write(config_dir.join("config.vdf"), "").expect("Failed to write config.vdf");
+            fs::write(config_dir.join("libraryfolders.vdf"), "")
+                .expect("Failed to write libraryfolders.vdf");
+        }
+        // Links to the native installation, which is only listed once
+        fs::create_dir_all(home.path().join(".steam")).expect("Failed to create .steam");
+        std::os::unix::fs::symlink(
+            home.path().join(".local/share/Steam"),
+            home.path().join(".steam/root"),
+        )
+        .expect("Failed to create symlink");
+        // Not logged in yet
+        fs::create_dir_all(home.path().join(".steam/debian-installation/config"))
+            .expect("Failed to create config directory");
+
+        let user_home = Some(home.path().to_string_lossy().to_string());
+        assert_eq!(
+            SteamUtil::find_all_steam_directories(user_home.clone()).unwrap(),
+            vec![
+                home.path().join(".local/share/Steam"),
+                home.path()
+                    .join(".var/app/com.valvesoftware.Steam/data/Steam"),
+            ]
+        );
+        assert_eq!(
+            SteamUtil::find_steam_directory(user_home).unwrap(),
+            home.path().join(".local/share/Steam")
+        );
+    }
+
     #[test]
     fn test_list_compatibility_tools() {
         // Create emulated Steam directory for the test
@@ -612,6 +1743,148 @@ mod tests {
         assert_eq!(compat_tools[1].display_name, "Sample Compatibility Tool 1");
     }
 
+    #[test]
+    fn test_malformed_and_multi_tool_vdfs() {
+        let steam_dir = create_test_steam_directory();
+        let steam_util = SteamUtil::new(steam_dir.path().join("root").to_path_buf());
+        let tools_dir = steam_dir.path().join("root/compatibilitytools.d");
+        let write_tool = |name: &str, vdf: &str| {
+            let vdf_path = tools_dir.join(name).join("compatibilitytool.vdf");
+            fs::create_dir_all(vdf_path.parent().unwrap()).unwrap();
+            fs::write(&vdf_path, vdf).unwrap();
+            vdf_path
+        };
+
+        let truncated = write_tool(
+            "truncated",
+            r#""compatibilitytools" { "compat_tools" { "Truncated" { "display_na"#,
+        );
+        assert!(matches!(
+            steam_util.read_compatibility_tools_from_vdf_path(&truncated),
+            Err(SteamUtilError::VdfParsingError(_))
+        ));
+
+        let nested = write_tool(
+            "nested",
+            r#""compatibilitytools"
+            {
+              "compat_tools"
+              {
+                "Wrapper"
+                {
+                  "Nested-Tool"
+                  {
+                    "display_name" "Nested Tool"
+                    "from_oslist"  "windows"
+                    "to_oslist"    "linux"
+                  }
+                }
+              }
+            }"#,
+        );
+        let error = steam_util
+            .read_compatibility_tools_from_vdf_path(&nested)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.ends_with("Wrapper has no display_name"), "{}", error);
+
+        let two_tools = write_tool(
+            "two_tools",
+            r#""compatibilitytools"
+            {
+              "compat_tools"
+              {
+                "Tool-A"
+                {
+                  "display_name" "Tool A"
+                  "from_oslist"  "windows"
+                  "to_oslist"    "linux"
+                }
+                "Tool-B"
+                {
+                  "display_name" "Tool B"
+                  "from_oslist"  "windows"
+                  "to_oslist"    "linux"
+                }
+              }
+            }"#,
+        );
+        let tools = steam_util
+            .read_compatibility_tools_from_vdf_path(&two_tools)
+            .unwrap();
+        let names: Vec<(&str, &str)> = tools
+            .iter()
+            .map(|tool| (tool.internal_name.as_str(), tool.display_name.as_str()))
+            .collect();
+        assert_eq!(names, [("Tool-A", "Tool A"), ("Tool-B", "Tool B")]);
+        assert!(tools
+            .iter()
+            .all(|tool| tool.directory_name == "two_tools"
+                && tool.path == two_tools.parent().unwrap()));
+
+        // The broken tools are skipped instead of failing the whole list
+        let compat_tools = steam_util.list_compatibility_tools().unwrap();
+        assert_eq!(compat_tools.len(), 4);
+        assert!(compat_tools
+            .iter()
+            .any(|tool| tool.internal_name == "Tool-B"));
+    }
+
+    fn create_wine_tool(path: &Path, libraries: &[&str]) {
+        for library in libraries {
+            fs::create_dir_all(path.join(library)).expect("Failed to create library directory");
+        }
+    }
+
+    #[test]
+    fn test_detect_32bit_support() {
+        let tools_dir = tempdir().expect("Failed to create temporary directory");
+
+        let full = tools_dir.path().join("GE-Proton8-25");
+        create_wine_tool(
+            &full,
+            &[
+                "files/lib/wine/i386-unix",
+                "files/lib/wine/i386-windows",
+                "files/lib64/wine/x86_64-unix",
+                "files/lib64/wine/x86_64-windows",
+            ],
+        );
+        assert!(detect_32bit_support(&full));
+
+        let wow64_only = tools_dir.path().join("GE-Proton9-1-wow64");
+        create_wine_tool(
+            &wow64_only,
+            &[
+                "files/lib/wine/i386-windows",
+                "files/lib/wine/x86_64-unix",
+                "files/lib/wine/x86_64-windows",
+            ],
+        );
+        assert!(detect_32bit_support(&wow64_only));
+
+        let only_64bit = tools_dir.path().join("Proton-slim");
+        create_wine_tool(
+            &only_64bit,
+            &[
+                "files/lib64/wine/x86_64-unix",
+                "files/lib64/wine/x86_64-windows",
+            ],
+        );
+        assert!(!detect_32bit_support(&only_64bit));
+
+        let legacy = tools_dir.path().join("Proton-5.9-GE-8-ST");
+        create_wine_tool(&legacy, &["dist/lib/wine", "dist/lib64/wine"]);
+        fs::write(legacy.join("dist/lib/wine/kernel32.dll.so"), "")
+            .expect("Failed to write library");
+        assert!(detect_32bit_support(&legacy));
+
+        let not_wine = tools_dir.path().join("Luxtorpeda");
+        fs::create_dir_all(&not_wine).expect("Failed to create directory");
+        assert!(detect_32bit_support(&not_wine));
+    }
+
     #[test]
     fn test_get_compatibility_tools_mappings() {
         // Create emulated Steam directory for the test
@@ -624,6 +1897,340 @@ mod tests {
         assert_eq!(compat_tools_mappings.len(), 2);
     }
 
+    #[test]
+    fn test_set_compatibility_tool_mapping() {
+        let steam_dir = create_test_steam_directory();
+        let steam_util = SteamUtil::new(steam_dir.path().join("root").to_path_buf());
+        let mapped = CompatAppId::from(AppId::new(730).unwrap());
+        let unmapped = CompatAppId::from(AppId::new(1245620).unwrap());
+
+        steam_util
+            .set_compatibility_tool_mapping(unmapped, "GE-Proton9-21", false)
+            .unwrap();
+        steam_util
+            .remove_compatibility_tool_mapping(mapped, false)
+            .unwrap();
+        steam_util
+            .set_compatibility_tool_mapping(CompatAppId::DEFAULT, "proton_experimental", false)
+            .unwrap();
+
+        let updated = steam_util.get_compatibility_tools_mappings().unwrap();
+        assert_eq!(updated.len(), 3);
+        assert_eq!(updated[&unmapped], "GE-Proton9-21");
+        assert_eq!(updated[&CompatAppId::DEFAULT], "proton_experimental");
+        assert!(!updated.contains_key(&mapped));
+    }
+
+    /// Contents of the backups kept next to `shortcuts_file`, oldest first.
+    fn shortcuts_backups(shortcuts_file: &Path) -> Vec<Vec<u8>> {
+        let mut backups: Vec<PathBuf> = fs::read_dir(shortcuts_file.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.starts_with("shortcuts.vdf.wine-cellar-backup-") && !name.ends_with(".json")
+            })
+            .collect();
+        backups.sort();
+        backups
+            .iter()
+            .map(|backup| fs::read(backup).unwrap())
+            .collect()
+    }
+
+    /// A `SteamUtil` that sees a Steam client running in a fake `/proc`.
+    fn steam_util_with_steam_running(steam_dir: &TempDir) -> SteamUtil {
+        let proc_root = steam_dir.path().join("proc");
+        let process = proc_root.join("42");
+        fs::create_dir_all(&process).expect("Failed to create process directory");
+        fs::write(proc_root.join("stat"), "btime 1700000000\n").expect("Failed to write stat");
+        fs::write(process.join("comm"), "steam\n").expect("Failed to write comm");
+        fs::write(
+            process.join("stat"),
+            "42 (steam) S 1 42 0 0 0 0 0 0 0 0 0 0 0 0 20 0 1 0 1500 0 0",
+        )
+        .expect("Failed to write stat");
+        SteamUtil {
+            steam_path: steam_dir.path().join("root"),
+            proc_root,
+            app_names: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn test_mappings_are_not_written_while_steam_is_running() {
+        let steam_dir = create_test_steam_directory();
+        let steam_util = steam_util_with_steam_running(&steam_dir);
+        let app_id = CompatAppId::from(AppId::new(1245620).unwrap());
+        let config_file = steam_dir.path().join("root/config/config.vdf");
+        let original = fs::read_to_string(&config_file).unwrap();
+
+        assert!(matches!(
+            steam_util.set_compatibility_tool_mapping(app_id, "GE-Proton9-21", false),
+            Err(SteamUtilError::SteamRunning)
+        ));
+        assert_eq!(fs::read_to_string(&config_file).unwrap(), original);
+
+        steam_util
+            .set_compatibility_tool_mapping(app_id, "GE-Proton9-21", true)
+            .unwrap();
+        let mappings = steam_util.get_compatibility_tools_mappings().unwrap();
+        assert_eq!(mappings[&app_id], "GE-Proton9-21");
+        // Unrelated entries are kept
+        assert_eq!(mappings.len(), 3);
+    }
+
+    #[test]
+    fn test_steam_is_not_restarted_under_a_game() {
+        let steam_dir = create_test_steam_directory();
+        let proc_root = steam_dir.path().join("proc");
+        fs::create_dir_all(&proc_root).expect("Failed to create proc directory");
+        fs::write(proc_root.join("stat"), "btime 1700000000\n").expect("Failed to write stat");
+        let steam_util = SteamUtil {
+            steam_path: steam_dir.path().join("root"),
+            proc_root: proc_root.clone(),
+            app_names: Arc::default(),
+        };
+        assert!(!steam_util.is_steam_running());
+        // Steam reads the new tools whenever it starts next
+        steam_util.restart_steam(false).unwrap();
+
+        let steam = proc_root.join("42");
+        fs::create_dir_all(&steam).expect("Failed to create process directory");
+        fs::write(steam.join("comm"), "steam\n").expect("Failed to write comm");
+        fs::write(
+            steam.join("stat"),
+            "42 (steam) S 1 42 0 0 0 0 0 0 0 0 0 0 0 0 20 0 1 0 1500 0 0",
+        )
+        .expect("Failed to write stat");
+        let game = proc_root.join("4242");
+        fs::create_dir_all(&game).expect("Failed to create process directory");
+        fs::write(game.join("comm"), "eldenring.exe\n").expect("Failed to write comm");
+        fs::write(
+            game.join("environ"),
+            b"HOME=/home/deck\0SteamAppId=1245620\0",
+        )
+        .expect("Failed to write environ");
+        assert!(steam_util.is_steam_running());
+
+        assert!(matches!(
+            steam_util.restart_steam(false),
+            Err(SteamUtilError::GameRunning(app_id)) if app_id == AppId::new(1245620).unwrap()
+        ));
+    }
+
+    fn registry_vdf(running_app_id: u32, running: [&str; 2]) -> String {
+        format!(
+            r#""Registry"
+{{
+	"HKCU"
+	{{
+		"Software"
+		{{
+			"valve"
+			{{
+				"Steam"
+				{{
+					"language"		"english"
+					"RunningAppID"		"{}"
+					"apps"
+					{{
+						"1245620"
+						{{
+							"installed"		"1"
+							"Running"		"{}"
+							"name"		"ELDEN RING"
+						}}
+						"292030"
+						{{
+							"installed"		"1"
+							"running"		"{}"
+						}}
+					}}
+				}}
+			}}
+		}}
+	}}
+}}"#,
+            running_app_id, running[0], running[1]
+        )
+    }
+
+    #[test]
+    fn test_parse_running_app_ids() {
+        let app_ids = |app_ids: &[u32]| -> Vec<AppId> {
+            app_ids
+                .iter()
+                .map(|app_id| AppId::new(*app_id).unwrap())
+                .collect()
+        };
+        assert_eq!(
+            parse_running_app_ids(&registry_vdf(0, ["0", "0"])).unwrap(),
+            app_ids(&[])
+        );
+        assert_eq!(
+            parse_running_app_ids(&registry_vdf(1245620, ["1", "0"])).unwrap(),
+            app_ids(&[1245620])
+        );
+        // Only flagged as running, e.g. while it's launching
+        assert_eq!(
+            parse_running_app_ids(&registry_vdf(0, ["0", "1"])).unwrap(),
+            app_ids(&[292030])
+        );
+        assert!(parse_running_app_ids("\"Registry\" { \"HKCU\" { } }").is_err());
+    }
+
+    #[test]
+    fn test_get_running_app_ids() {
+        let steam_dir = create_test_steam_directory();
+        let proc_root = steam_dir.path().join("proc");
+        let steam_util = SteamUtil {
+            steam_path: steam_dir.path().join("root"),
+            proc_root: proc_root.clone(),
+            app_names: Arc::default(),
+        };
+        // Neither registry.vdf nor the processes can be read
+        assert!(matches!(
+            steam_util.get_running_app_ids(),
+            Err(SteamUtilError::RegistryVdfNotFound)
+        ));
+
+        let reaper = proc_root.join("4242");
+        fs::create_dir_all(&reaper).expect("Failed to create process directory");
+        fs::write(reaper.join("comm"), "reaper\n").expect("Failed to write comm");
+        fs::write(
+            reaper.join("cmdline"),
+            b"/home/deck/.steam/root/ubuntu12_32/reaper\0SteamLaunch\0AppId=730\0--\0",
+        )
+        .expect("Failed to write cmdline");
+        assert_eq!(
+            steam_util.get_running_app_ids().unwrap(),
+            [AppId::new(730).unwrap()]
+        );
+
+        fs::create_dir_all(steam_dir.path().join(".steam")).expect("Failed to create .steam");
+        fs::write(
+            steam_dir.path().join(".steam/registry.vdf"),
+            registry_vdf(1245620, ["1", "0"]),
+        )
+        .expect("Failed to write registry.vdf");
+        assert_eq!(
+            steam_util.get_running_app_ids().unwrap(),
+            [AppId::new(730).unwrap(), AppId::new(1245620).unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_batched_mappings_match_single_writes() {
+        let changes = [
+            (
+                CompatAppId::from(AppId::new(1245620).unwrap()),
+                Some("GE-Proton9-21"),
+            ),
+            (CompatAppId::from(AppId::new(730).unwrap()), None),
+            (
+                CompatAppId::from(AppId::new(1245620).unwrap()),
+                Some("GE-Proton9-22"),
+            ),
+            (CompatAppId::DEFAULT, Some("proton_experimental")),
+        ];
+        let config_file = |steam_dir: &TempDir| steam_dir.path().join("root/config/config.vdf");
+
+        let single_dir = create_test_steam_directory();
+        let single = SteamUtil::new(single_dir.path().join("root").to_path_buf());
+        for (app_id, compatibility_tool) in changes {
+            single
+                .set_compatibility_tool_mappings(&[(app_id, compatibility_tool)], false)
+                .unwrap();
+        }
+
+        let batched_dir = create_test_steam_directory();
+        let original = fs::read_to_string(config_file(&batched_dir)).unwrap();
+        let batched = SteamUtil::new(batched_dir.path().join("root").to_path_buf());
+        batched
+            .set_compatibility_tool_mappings(&changes, false)
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(config_file(&batched_dir)).unwrap(),
+            fs::read_to_string(config_file(&single_dir)).unwrap()
+        );
+        // One write, so the backup is the untouched original
+        let backup_file = config_file(&batched_dir).with_extension("vdf.wine-cellar-backup");
+        assert_eq!(fs::read_to_string(&backup_file).unwrap(), original);
+        let sidecar = backup_file.with_extension("wine-cellar-backup.json");
+        let written_by: WrittenBy =
+            serde_json::from_str(&fs::read_to_string(sidecar).unwrap()).unwrap();
+        assert_eq!(written_by.artifact, "config_vdf_backup");
+    }
+
+    #[test]
+    fn test_foreign_config_change_is_merged() {
+        let steam_dir = create_test_steam_directory();
+        let root = steam_dir.path().join("root");
+        let steam_config_file = root.join("config/config.vdf");
+        let steam_util = SteamUtil::new(root.clone());
+        let ours = CompatAppId::from(AppId::new(1245620).unwrap());
+        let theirs = CompatAppId::from(AppId::new(570).unwrap());
+        let changes = [(ours, Some("GE-Proton9-21"))];
+
+        // Stands in for Steam, writing config.vdf between our read and our write
+        let (read_sender, read_receiver) = mpsc::channel();
+        let (written_sender, written_receiver) = mpsc::channel();
+        let steam = thread::spawn(move || {
+            read_receiver.recv().unwrap();
+            SteamUtil::new(root)
+                .set_compatibility_tool_mapping(theirs, "proton_9", false)
+                .unwrap();
+            written_sender.send(()).unwrap();
+        });
+        let reads = Cell::new(0);
+        let write = steam_util
+            .rewrite_config_vdf(|config| {
+                reads.set(reads.get() + 1);
+                if reads.get() == 1 {
+                    read_sender.send(()).unwrap();
+                    written_receiver.recv().unwrap();
+                }
+                apply_mapping_changes(config, &changes, &steam_config_file)
+            })
+            .unwrap();
+        steam.join().unwrap();
+
+        assert_eq!(
+            write,
+            ConfigWrite {
+                retries: 1,
+                merged_foreign_change: true
+            }
+        );
+        let mappings = steam_util.get_compatibility_tools_mappings().unwrap();
+        assert_eq!(mappings[&ours], "GE-Proton9-21");
+        assert_eq!(mappings[&theirs], "proton_9");
+
+        // Changing on every read exhausts the retries without touching the file
+        let before = fs::read_to_string(&steam_config_file).unwrap();
+        let error = steam_util
+            .rewrite_config_vdf(|config| {
+                let updated = apply_mapping_changes(config, &changes, &steam_config_file)?;
+                fs::write(&steam_config_file, format!("{}\n", config)).unwrap();
+                Ok(updated)
+            })
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            SteamUtilError::SteamConfigVdfWriteFailed(_)
+        ));
+        let after = fs::read_to_string(&steam_config_file).unwrap();
+        assert_eq!(after.trim_end(), before.trim_end());
+        assert_eq!(
+            steam_util
+                .set_compatibility_tool_mappings(&changes, false)
+                .unwrap(),
+            ConfigWrite::default()
+        );
+    }
+
     #[test]
     fn test_list_installed_games() {
         // Create emulated Steam directory for the test
@@ -633,8 +2240,534 @@ mod tests {
         let result = steam_util.list_installed_games();
         assert!(result.is_ok());
         let installed_games = result.unwrap();
+        // Appmanifests are listed in directory order
+        let mut names: Vec<&str> = installed_games.iter().map(|game| game.name.as_str()).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["Counter-Strike: Global Offensive", "Hades"]);
+    }
+
+    #[test]
+    fn test_parse_app_manifest() {
+        let library_path = Path::new("/run/media/deck/SD Card");
+        let app = parse_app_manifest(
+            r#""AppState"
+            {
+                "appid"		"1245620"
+                "universe"		"1"
+                "name"		"ELDEN RING"
+                "StateFlags"		"4"
+                "installdir"		"ELDEN RING"
+                "SizeOnDisk"		"50536693174"
+            }
+            "#,
+            library_path,
+        )
+        .unwrap();
+        assert_eq!(
+            app,
+            InstalledApp {
+                app_id: AppId::new(1245620).unwrap(),
+                name: "ELDEN RING".to_string(),
+                installdir: "ELDEN RING".to_string(),
+                library_path: library_path.to_path_buf(),
+                size_on_disk: 50536693174,
+                state_flags: 4,
+                install_state: AppInstallState::FullyInstalled,
+                kind: AppKind::Game,
+            }
+        );
+
+        let app = parse_app_manifest(
+            "\"AppState\" { \"appid\" \"1145360\" \"name\" \"Hades\" }",
+            library_path,
+        )
+        .unwrap();
+        assert_eq!(app.size_on_disk, 0);
+        assert_eq!(app.install_state, AppInstallState::Other);
+        assert!(parse_app_manifest("\"AppState\" { \"name\" \"Hades\" }", library_path).is_err());
+    }
+
+    #[test]
+    fn test_app_install_states() {
+        for (state_flags, install_state) in [
+            (4, AppInstallState::FullyInstalled),
+            (6, AppInstallState::UpdatePending),
+            (1026, AppInstallState::UpdatePending),
+            (524290, AppInstallState::Preallocating),
+            (2052, AppInstallState::Uninstalling),
+            // Files missing
+            (36, AppInstallState::Other),
+        ] {
+            assert_eq!(
+                AppInstallState::from_state_flags(state_flags),
+                install_state,
+                "{}",
+                state_flags
+            );
+        }
+    }
+
+    #[test]
+    fn test_apps_being_uninstalled_arent_games() {
+        let steam_dir = create_test_steam_directory();
+        fs::write(
+            steam_dir
+                .path()
+                .join("root/steamapps/appmanifest_292030.acf"),
+            r#""AppState"
+            {
+                "appid"		"292030"
+                "name"		"The Witcher 3: Wild Hunt"
+                "StateFlags"		"2052"
+            }
+            "#,
+        )
+        .expect("Failed to write app manifest file");
+        let steam_util = SteamUtil::new(steam_dir.path().join("root"));
+
+        let installed_apps = steam_util.list_installed_apps().unwrap();
+        assert_eq!(installed_apps.len(), 3);
+        assert!(installed_apps
+            .iter()
+            .all(|app| app.library_path == steam_dir.path().join("root")));
+        let installed_games = steam_util.list_installed_games().unwrap();
         assert_eq!(installed_games.len(), 2);
-        assert_eq!(installed_games[0].name, "Hades");
-        assert_eq!(installed_games[1].name, "Counter-Strike: Global Offensive");
+        assert!(installed_games
+            .iter()
+            .all(|game| game.app_id != AppId::new(292030).unwrap()));
+    }
+
+    #[test]
+    fn test_tools_arent_games() {
+        let library = tempdir().expect("Failed to create temporary directory");
+        let steamapps_dir = library.path().join("steamapps");
+        for (app_id, name, installdir, tool_manifest) in [
+            ("1245620", "ELDEN RING", "ELDEN RING", false),
+            ("2348590", "Proton 8.0", "Proton 8.0", true),
+            ("1628350", "Steam Linux Runtime", "SteamLinuxRuntime", false),
+            ("228980", "Steamworks Redist", "Steamworks Shared", false),
+        ] {
+            let install_dir = steamapps_dir.join("common").join(installdir);
+            fs::create_dir_all(&install_dir).expect("Failed to create install directory");
+            if tool_manifest {
+                fs::write(
+                    install_dir.join("toolmanifest.vdf"),
+                    "\"manifest\" { \"commandline\" \"/proton %verb%\" }",
+                )
+                .expect("Failed to write tool manifest");
+            }
+            fs::write(
+                steamapps_dir.join(format!("appmanifest_{}.acf", app_id)),
+                format!(
+                    "\"AppState\" {{ \"appid\" \"{}\" \"name\" \"{}\" \"installdir\" \"{}\" }}",
+                    app_id, name, installdir
+                ),
+            )
+            .expect("Failed to write app manifest file");
+        }
+        let steam_util = SteamUtil::new(library.path().to_path_buf());
+
+        let mut kinds: Vec<(u32, AppKind)> = steam_util
+            .find_installed_apps(library.path())
+            .unwrap()
+            .into_iter()
+            .map(|app| (app.app_id.value(), app.kind))
+            .collect();
+        kinds.sort_by_key(|(app_id, _)| *app_id);
+        assert_eq!(
+            kinds,
+            [
+                (228980, AppKind::Tool),
+                (1245620, AppKind::Game),
+                (1628350, AppKind::Tool),
+                (2348590, AppKind::Tool),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_shortcuts() {
+        let steam_dir = create_test_steam_directory();
+        let steam_util = SteamUtil::new(steam_dir.path().join("root"));
+        let shortcuts_file = steam_util.get_shortcuts_path(12345678);
+        assert!(matches!(
+            steam_util.read_shortcuts(12345678),
+            Err(SteamUtilError::ShortcutsVdfNotFound)
+        ));
+
+        let shortcut = |launch_options: &str| {
+            object(&[(
+                "shortcuts",
+                object(&[(
+                    "0",
+                    object(&[
+                        ("appid", KeyValue::Int32(-2024944363)),
+                        ("AppName", string("Heroic Games Launcher")),
+                        ("LaunchOptions", string(launch_options)),
+                    ]),
+                )]),
+            )])
+        };
+        let original = write_key_values(&shortcut(""));
+        fs::create_dir_all(shortcuts_file.parent().unwrap())
+            .expect("Failed to create config directory");
+        fs::write(&shortcuts_file, &original).expect("Failed to write shortcuts.vdf");
+
+        let mut shortcuts = steam_util.read_shortcuts(12345678).unwrap();
+        assert_eq!(shortcuts, shortcut(""));
+        shortcuts = shortcut("PROTON_LOG=1 %command%");
+        steam_util.write_shortcuts(12345678, &shortcuts).unwrap();
+
+        assert_eq!(steam_util.read_shortcuts(12345678).unwrap(), shortcuts);
+        assert_eq!(shortcuts_backups(&shortcuts_file), vec![original.clone()]);
+        assert!(!shortcuts_file.with_extension("vdf.wine-cellar").exists());
+
+        // Writing again doesn't replace the backup of the original
+        let updated = write_key_values(&shortcuts);
+        steam_util
+            .write_shortcuts(12345678, &shortcut("%command% -novid"))
+            .unwrap();
+        let backups = shortcuts_backups(&shortcuts_file);
+        assert_eq!(backups[0], original);
+        assert!(backups[1..].iter().all(|backup| *backup == updated));
+    }
+
+    #[test]
+    fn test_list_official_proton_tools() {
+        let steam_dir = create_test_steam_directory();
+        let root_dir = steam_dir.path().join("root");
+        let steamapps_dir = root_dir.join("steamapps");
+        for (app_id, name, installdir, version) in [
+            (
+                "2805730",
+                "Proton 9.0",
+                "Proton 9.0 (Beta)",
+                Some("proton-9.0-2"),
+            ),
+            (
+                "1493710",
+                "Proton Experimental",
+                "Proton - Experimental",
+                Some("experimental-9.0-20240701"),
+            ),
+            ("1420170", "Proton 5.13", "Proton 5.13", None),
+            (
+                "1628350",
+                "Steam Linux Runtime 3.0 (sniper)",
+                "SteamLinuxRuntime_sniper",
+                None,
+            ),
+        ] {
+            let install_dir = steamapps_dir.join("common").join(installdir);
+            fs::create_dir_all(&install_dir).expect("Failed to create install directory");
+            fs::write(install_dir.join("toolmanifest.vdf"), "\"manifest\" {}")
+                .expect("Failed to write tool manifest");
+            if installdir.starts_with("Proton") {
+                fs::write(install_dir.join("proton"), "#!/usr/bin/env python3\n")
+                    .expect("Failed to write proton script");
+            }
+            if let Some(version) = version {
+                fs::write(
+                    install_dir.join("version"),
+                    format!("1718226489 {}\n", version),
+                )
+                .expect("Failed to write version file");
+            }
+            fs::write(
+                steamapps_dir.join(format!("appmanifest_{}.acf", app_id)),
+                format!(
+                    "\"AppState\" {{ \"appid\" \"{}\" \"name\" \"{}\" \"installdir\" \"{}\" }}",
+                    app_id, name, installdir
+                ),
+            )
+            .expect("Failed to write app manifest file");
+        }
+        // Steam registered a name for 9.0 only, the others' names are derived
+        let manifests = object(&[(
+            "extended",
+            object(&[(
+                "compat_tools",
+                object(&[("proton_9", object(&[("appid", string("2805730"))]))]),
+            )]),
+        )]);
+        let appcache_dir = root_dir.join("appcache");
+        fs::create_dir_all(&appcache_dir).expect("Failed to create appcache directory");
+        fs::write(
+            appcache_dir.join("appinfo.vdf"),
+            build_appinfo(0x0756_4428, &[(891390, object(&[("appinfo", manifests)]))]),
+        )
+        .expect("Failed to write appinfo.vdf");
+        let steam_util = SteamUtil::new(root_dir);
+
+        let mut tools = steam_util.list_official_proton_tools().unwrap();
+        tools.sort_by_key(|tool| tool.app_id);
+        let tool = |app_id: u32, installdir: &str, internal_name: &str, display_name: &str| {
+            OfficialProtonTool {
+                app_id: AppId::new(app_id).unwrap(),
+                path: steamapps_dir.join("common").join(installdir),
+                internal_name: internal_name.to_string(),
+                display_name: display_name.to_string(),
+                version: None,
+            }
+        };
+        assert_eq!(
+            tools,
+            [
+                tool(1420170, "Proton 5.13", "proton_513", "Proton 5.13"),
+                OfficialProtonTool {
+                    version: Some("experimental-9.0-20240701".to_string()),
+                    ..tool(
+                        1493710,
+                        "Proton - Experimental",
+                        "proton_experimental",
+                        "Proton Experimental"
+                    )
+                },
+                OfficialProtonTool {
+                    version: Some("9.0-2".to_string()),
+                    ..tool(2805730, "Proton 9.0 (Beta)", "proton_9", "Proton 9.0-2")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shortcut_launch_options() {
+        let steam_dir = create_test_steam_directory();
+        let steam_util = SteamUtil::new(steam_dir.path().join("root"));
+        for user_id in [12345678, 87654321] {
+            let shortcuts_file = steam_util.get_shortcuts_path(user_id);
+            fs::create_dir_all(shortcuts_file.parent().unwrap())
+                .expect("Failed to create config directory");
+            fs::write(&shortcuts_file, SHORTCUTS_VDF).expect("Failed to write shortcuts.vdf");
+        }
+        // An account that never added a shortcut
+        fs::create_dir_all(steam_dir.path().join("root/userdata/11111111"))
+            .expect("Failed to create userdata directory");
+        let heroic = ShortcutId::new(0x875b_cd15).unwrap();
+
+        let details = steam_util.get_shortcut_details(heroic).unwrap();
+        assert_eq!(
+            details,
+            [12345678, 87654321].map(|user_id| ShortcutDetails {
+                user_id,
+                shortcut_id: heroic,
+                app_name: "Heroic Games Launcher".to_string(),
+                exe: "\"/usr/bin/heroic\"".to_string(),
+                launch_options: String::new(),
+            })
+        );
+
+        let launch_options = "PROTON_ENABLE_NVAPI=1 %command%";
+        let updated = steam_util
+            .set_shortcut_launch_options(heroic, launch_options)
+            .unwrap();
+        assert!(updated
+            .iter()
+            .all(|details| details.launch_options == launch_options));
+        assert_eq!(steam_util.get_shortcut_details(heroic).unwrap(), updated);
+        // Everything but the launch options is written back as it was
+        let mut expected = read_key_values(SHORTCUTS_VDF).unwrap();
+        expected
+            .get_mut("shortcuts")
+            .and_then(|shortcuts| shortcuts.get_mut("0"))
+            .unwrap()
+            .set("LaunchOptions", string(launch_options));
+        let shortcuts_file = steam_util.get_shortcuts_path(87654321);
+        assert_eq!(
+            fs::read(&shortcuts_file).unwrap(),
+            write_key_values(&expected)
+        );
+        assert_eq!(shortcuts_backups(&shortcuts_file), [SHORTCUTS_VDF.to_vec()]);
+
+        let unknown = ShortcutId::new(0x8000_0001).unwrap();
+        assert!(matches!(
+            steam_util.set_shortcut_launch_options(unknown, ""),
+            Err(SteamUtilError::ShortcutNotFound(shortcut_id)) if shortcut_id == unknown
+        ));
+
+        let written = fs::read(&shortcuts_file).unwrap();
+        assert!(matches!(
+            steam_util_with_steam_running(&steam_dir).set_shortcut_launch_options(heroic, ""),
+            Err(SteamUtilError::SteamRunning)
+        ));
+        assert_eq!(fs::read(&shortcuts_file).unwrap(), written);
+    }
+
+    #[test]
+    fn test_lookup_app_name() {
+        let steam_dir = create_test_steam_directory();
+        let appcache_dir = steam_dir.path().join("root/appcache");
+        fs::create_dir_all(&appcache_dir).expect("Failed to create appcache directory");
+        let steam_util = SteamUtil::new(steam_dir.path().join("root"));
+        let elden_ring = AppId::new(1245620).unwrap();
+        assert_eq!(steam_util.lookup_app_name(elden_ring), None);
+
+        let app = |name: &str| {
+            object(&[(
+                "appinfo",
+                object(&[("common", object(&[("name", string(name))]))]),
+            )])
+        };
+        fs::write(
+            appcache_dir.join("appinfo.vdf"),
+            build_appinfo(0x0756_4428, &[(1245620, app("ELDEN RING"))]),
+        )
+        .expect("Failed to write appinfo.vdf");
+        assert_eq!(
+            steam_util.clone().lookup_app_name(elden_ring),
+            Some("ELDEN RING".to_string())
+        );
+
+        // Clones share the names, which are read again once Steam updated the file
+        fs::write(
+            appcache_dir.join("appinfo.vdf"),
+            build_appinfo(0x0756_4427, &[(1245620, app("ELDEN RING NIGHTREIGN"))]),
+        )
+        .expect("Failed to write appinfo.vdf");
+        assert_eq!(
+            steam_util.lookup_app_name(elden_ring),
+            Some("ELDEN RING NIGHTREIGN".to_string())
+        );
+        assert_eq!(steam_util.lookup_app_name(AppId::new(730).unwrap()), None);
+    }
+
+    #[test]
+    fn test_broken_library_folders_vdf_is_an_error() {
+        let steam_dir = create_test_steam_directory();
+        let root_dir = steam_dir.path().join("root");
+        // Steam was killed while writing it
+        fs::write(
+            root_dir.join("steamapps/libraryfolders.vdf"),
+            "\"libraryfolders\"\n{\n\t\"0\"\n\t{",
+        )
+        .expect("Failed to write libraryfolders.vdf");
+        let steam_util = SteamUtil::new(root_dir);
+
+        assert!(matches!(
+            steam_util.list_library_folders(),
+            Err(SteamUtilError::VdfParsingError(_))
+        ));
+    }
+
+    #[test]
+    fn test_list_orphaned_compat_data() {
+        let steam_dir = create_test_steam_directory();
+        let root_dir = steam_dir.path().join("root");
+        let compatdata_dir = root_dir.join("steamapps").join("compatdata");
+        // Counter-Strike is installed, Elden Ring was uninstalled. Heroic is still a shortcut,
+        // 3228583970 was removed from the library.
+        for prefix in ["730", "1245620", "0", "pfx", "2270940437", "3228583970"] {
+            fs::create_dir_all(compatdata_dir.join(prefix).join("pfx"))
+                .expect("Failed to create prefix directory");
+        }
+        let steam_util = SteamUtil::new(root_dir);
+        let shortcuts_file = steam_util.get_shortcuts_path(12345678);
+        fs::create_dir_all(shortcuts_file.parent().unwrap())
+            .expect("Failed to create config directory");
+        fs::write(&shortcuts_file, SHORTCUTS_VDF).expect("Failed to write shortcuts.vdf");
+
+        let elden_ring = OrphanedCompatData {
+            app_id: CompatAppId::from(AppId::new(1245620).unwrap()),
+            name: None,
+            path: compatdata_dir.join("1245620"),
+            size: 0,
+        };
+        let removed_shortcut = OrphanedCompatData {
+            app_id: CompatAppId::from(ShortcutId::new(3228583970).unwrap()),
+            name: None,
+            path: compatdata_dir.join("3228583970"),
+            size: 0,
+        };
+        assert_eq!(
+            steam_util.list_orphaned_compat_data().unwrap(),
+            [elden_ring.clone(), removed_shortcut]
+        );
+
+        // Without knowing every shortcut, none of their prefixes is taken for orphaned
+        fs::write(&shortcuts_file, [0x00, 0x73]).expect("Failed to write shortcuts.vdf");
+        assert_eq!(
+            steam_util.list_orphaned_compat_data().unwrap(),
+            [elden_ring]
+        );
+    }
+
+    #[test]
+    fn test_get_applications_using_tool() {
+        let steam_dir = create_test_steam_directory();
+        let steam_util = SteamUtil::new(steam_dir.path().join("root").to_path_buf());
+        // Elden Ring was uninstalled, its mapping stayed
+        let uninstalled = AppId::new(1245620).unwrap();
+        steam_util
+            .set_compatibility_tool_mapping(
+                CompatAppId::from(uninstalled),
+                "Sample-Compatibility-Tool-1",
+                false,
+            )
+            .unwrap();
+
+        let apps = steam_util.get_applications_using_tool("Sample-Compatibility-Tool-1");
+        assert_eq!(
+            apps,
+            [
+                SteamApp {
+                    app_id: AppId::new(730).unwrap(),
+                    name: "Counter-Strike: Global Offensive".to_string(),
+                    installed: true,
+                },
+                SteamApp {
+                    app_id: uninstalled,
+                    name: "1245620".to_string(),
+                    installed: false,
+                },
+            ]
+        );
+        assert!(steam_util
+            .get_applications_using_tool("GE-Proton9-21")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_steam_started_at() {
+        let proc_root = tempdir().unwrap();
+        fs::write(
+            proc_root.path().join("stat"),
+            "cpu  1 2 3\nbtime 1700000000\n",
+        )
+        .unwrap();
+        for (pid, comm, start_ticks) in [("42", "steam", 1500), ("43", "steamwebhelper", 100)] {
+            let process = proc_root.path().join(pid);
+            fs::create_dir_all(&process).unwrap();
+            fs::write(process.join("comm"), format!("{}\n", comm)).unwrap();
+            fs::write(
+                process.join("stat"),
+                format!(
+                    "{} ({}) S 1 {} 0 0 0 0 0 0 0 0 0 0 0 0 20 0 1 0 {} 0 0",
+                    pid, comm, pid, start_ticks
+                ),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(steam_started_at(proc_root.path()), Some(1700000015));
+        assert_eq!(steam_started_at(&proc_root.path().join("missing")), None);
+    }
+
+    #[test]
+    fn test_running_game() {
+        let proc_root = tempdir().unwrap();
+        for (pid, environ) in [
+            ("1", "HOME=/home/deck\0"),
+            ("2", "SteamAppId=0\0"),
+            ("3", "HOME=/home/deck\0SteamAppId=1245620\0"),
+        ] {
+            fs::create_dir_all(proc_root.path().join(pid)).unwrap();
+            fs::write(proc_root.path().join(pid).join("environ"), environ).unwrap();
+        }
+        assert_eq!(
+            running_game(proc_root.path()),
+            Some(AppId::new(1245620).unwrap())
+        );
+        assert_eq!(running_game(&proc_root.path().join("missing")), None);
     }
 }
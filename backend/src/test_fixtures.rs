@@ -0,0 +1,95 @@
+//! Releases, tools and Steam installations the unit tests build their cases from.
+
+use crate::github_util::{Asset, Release};
+use crate::wine_cask::flavors::{CompatibilityToolFlavor, SteamCompatibilityTool};
+use std::fs;
+use std::path::Path;
+
+/// A release without assets, named after its tag.
+pub fn release(tag_name: &str) -> Release {
+    Release {
+        url: String::new(),
+        id: 0,
+        draft: false,
+        prerelease: false,
+        name: tag_name.to_string(),
+        tag_name: tag_name.to_string(),
+        target_commitish: String::new(),
+        assets: Vec::new(),
+        created_at: String::new(),
+        published_at: String::new(),
+        tarball_url: String::new(),
+        body: String::new(),
+    }
+}
+
+/// A release published at `published_at`, which naming schemes are picked by.
+pub fn published_release(tag_name: &str, published_at: &str) -> Release {
+    Release {
+        created_at: published_at.to_string(),
+        published_at: published_at.to_string(),
+        ..release(tag_name)
+    }
+}
+
+/// A release with an empty asset for each of `asset_names`.
+pub fn release_with_assets(tag_name: &str, asset_names: &[&str]) -> Release {
+    Release {
+        assets: asset_names.iter().map(|name| asset(name)).collect(),
+        ..release(tag_name)
+    }
+}
+
+/// An empty asset that can't be downloaded.
+pub fn asset(name: &str) -> Asset {
+    Asset {
+        url: String::new(),
+        id: 0,
+        name: name.to_string(),
+        content_type: String::new(),
+        state: String::new(),
+        size: 0,
+        download_count: 0,
+        created_at: String::new(),
+        updated_at: String::new(),
+        browser_download_url: String::new(),
+    }
+}
+
+/// A tool installed from the release it's named after, unless its flavor is unknown.
+pub fn tool(flavor: CompatibilityToolFlavor, name: &str) -> SteamCompatibilityTool {
+    let managed = flavor != CompatibilityToolFlavor::Unknown;
+    SteamCompatibilityTool {
+        path: format!("/compatibilitytools.d/{}", name),
+        display_name: name.to_string(),
+        internal_name: name.to_string(),
+        used_by_games: Vec::new(),
+        used_by_apps: Vec::new(),
+        requires_restart: false,
+        supports_32bit: true,
+        r#virtual: None,
+        github_release: managed.then(|| release(name)),
+        flavor,
+        modified_since_install: None,
+        inspection: None,
+        managed,
+        version: managed.then(|| name.to_string()),
+        official: false,
+    }
+}
+
+/// A Steam installation at `root` with GE-Proton9-20 mapped to app 1245620.
+pub fn steam_root(root: &Path) {
+    fs::create_dir_all(root.join("config")).unwrap();
+    fs::create_dir_all(root.join("steamapps")).unwrap();
+    fs::create_dir_all(root.join("compatibilitytools.d")).unwrap();
+    fs::write(root.join("config/config.vdf"), "\"InstallConfigStore\"\n{\n\"Software\"\n{\n\"Valve\"\n{\n\"Steam\"\n{\n\"CompatToolMapping\"\n{\n\"1245620\"\n{\n\"name\" \"GE-Proton9-20\"\n\"config\" \"\"\n\"priority\" \"250\"\n}\n}\n}\n}\n}\n}\n").unwrap();
+    fs::write(
+        root.join("steamapps/libraryfolders.vdf"),
+        format!(
+            "\"libraryfolders\"\n{{\n\"0\"\n{{\n\"path\" \"{}\"\n}}\n}}\n",
+            root.display()
+        ),
+    )
+    .unwrap();
+}
@@ -0,0 +1,85 @@
+use log::{info, warn};
+use std::fs;
+use std::fs::Permissions;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use tokio::net::UnixListener;
+
+/// Removes the socket file once the listener is no longer served.
+pub struct UnixSocketGuard {
+    path: PathBuf,
+}
+
+impl Drop for UnixSocketGuard {
+    fn drop(&mut self) {
+        match fs::remove_file(&self.path) {
+            Ok(_) => info!("Removed unix socket {}", self.path.display()),
+            Err(err) => warn!(
+                "Failed to remove unix socket {}: {}",
+                self.path.display(),
+                err
+            ),
+        }
+    }
+}
+
+/// Binds a unix domain socket only the plugin's user can connect to.
+///
+/// A socket file left behind by a backend that didn't shut down cleanly is replaced, one another
+/// process still listens on is not.
+pub fn bind_unix_socket(path: &Path) -> io::Result<(UnixListener, UnixSocketGuard)> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by another process", path.display()),
+            ));
+        }
+        info!("Removing stale unix socket {}", path.display());
+        fs::remove_file(path)?;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    let guard = UnixSocketGuard {
+        path: path.to_path_buf(),
+    };
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    Ok((listener, guard))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_stale_sockets_are_replaced() {
+        let runtime_directory = tempdir().unwrap();
+        let path = runtime_directory.path().join("wine-cask.sock");
+
+        let (listener, guard) = bind_unix_socket(&path).unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        // Still served, must not be taken over
+        assert_eq!(
+            bind_unix_socket(&path).err().unwrap().kind(),
+            io::ErrorKind::AddrInUse
+        );
+
+        // Left behind by a backend that was killed
+        drop(listener);
+        std::mem::forget(guard);
+        assert!(path.exists());
+        let (_listener, guard) = bind_unix_socket(&path).unwrap();
+
+        drop(guard);
+        assert!(!path.exists());
+    }
+}
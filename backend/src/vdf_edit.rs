@@ -0,0 +1,627 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+/// Indentation and separator used when the file doesn't show which ones it uses, as Steam writes
+/// them.
+const DEFAULT_INDENT: &str = "\t";
+const DEFAULT_SEPARATOR: &str = "\t\t";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VdfEditError {
+    /// The text isn't valid VDF, `offset` is the byte the problem was found at.
+    Syntax { offset: usize, message: String },
+    /// An entry along the path is a string where an object was expected.
+    NotAnObject(String),
+    /// The entry to set is an object where a string was expected.
+    NotAString(String),
+}
+
+impl Display for VdfEditError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VdfEditError::Syntax { offset, message } => {
+                write!(f, "Invalid VDF at byte {}: {}", offset, message)
+            }
+            VdfEditError::NotAnObject(key) => write!(f, "VDF entry {} isn't an object", key),
+            VdfEditError::NotAString(key) => write!(f, "VDF entry {} isn't a string", key),
+        }
+    }
+}
+
+impl Error for VdfEditError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+/// An entry of the text along with where it is, so it can be changed without touching the rest.
+#[derive(Debug)]
+struct Entry {
+    key: String,
+    /// From the opening quote of the key to the end of the value.
+    range: Range<usize>,
+    /// Byte range of the key token.
+    key_range: Range<usize>,
+    value: Node,
+}
+
+#[derive(Debug)]
+enum Node {
+    /// Byte range of the value token, quotes included.
+    String(Range<usize>),
+    Object {
+        entries: Vec<Entry>,
+        /// Offset of the closing brace, the end of the text for the root of the file.
+        close: usize,
+    },
+}
+
+enum Token {
+    Open,
+    Close,
+    String(String),
+}
+
+struct Tokenizer<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl Tokenizer<'_> {
+    fn error(&self, message: &str) -> VdfEditError {
+        VdfEditError::Syntax {
+            offset: self.position,
+            message: message.to_string(),
+        }
+    }
+
+    /// Skips whitespace, comments and conditionals like `[$WIN32]`.
+    fn skip_ignored(&mut self) {
+        let bytes = self.text.as_bytes();
+        while self.position < bytes.len() {
+            let rest = &self.text[self.position..];
+            if bytes[self.position].is_ascii_whitespace() {
+                self.position += 1;
+            } else if rest.starts_with("//") {
+                self.position += rest.find('\n').unwrap_or(rest.len());
+            } else if rest.starts_with('[') {
+                self.position += rest.find(']').map_or(rest.len(), |end| end + 1);
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Returns the next token and where it is, `None` at the end of the text.
+    fn next_token(&mut self) -> Result<Option<(Token, Range<usize>)>, VdfEditError> {
+        self.skip_ignored();
+        let start = self.position;
+        let Some(first) = self.text[start..].chars().next() else {
+            return Ok(None);
+        };
+        let token = match first {
+            '{' => {
+                self.position += 1;
+                Token::Open
+            }
+            '}' => {
+                self.position += 1;
+                Token::Close
+            }
+            '"' => {
+                let mut string = String::new();
+                let mut chars = self.text[start + 1..].char_indices();
+                loop {
+                    match chars.next() {
+                        Some((offset, '"')) => {
+                            self.position = start + 1 + offset + 1;
+                            break;
+                        }
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, 'n')) => string.push('\n'),
+                            Some((_, 't')) => string.push('\t'),
+                            Some((_, escaped)) => string.push(escaped),
+                            None => return Err(self.error("unterminated string")),
+                        },
+                        Some((_, char)) => string.push(char),
+                        None => return Err(self.error("unterminated string")),
+                    }
+                }
+                Token::String(string)
+            }
+            _ => {
+                let rest = &self.text[start..];
+                let length = rest
+                    .find(|char: char| char.is_whitespace() || "{}\"".contains(char))
+                    .unwrap_or(rest.len());
+                self.position += length;
+                Token::String(rest[..length].to_string())
+            }
+        };
+        Ok(Some((token, start..self.position)))
+    }
+
+    /// Reads entries until the closing brace, or the end of the text for the root of the file.
+    fn parse_entries(&mut self, root: bool) -> Result<(Vec<Entry>, usize), VdfEditError> {
+        let mut entries = Vec::new();
+        loop {
+            let (key, key_range) = match self.next_token()? {
+                None if root => return Ok((entries, self.text.len())),
+                None => return Err(self.error("missing closing brace")),
+                Some((Token::Close, range)) if !root => return Ok((entries, range.start)),
+                Some((Token::String(key), range)) => (key, range),
+                Some(_) => return Err(self.error("expected a key")),
+            };
+            let value = match self.next_token()? {
+                Some((Token::String(_), range)) => Node::String(range),
+                Some((Token::Open, _)) => {
+                    let (entries, close) = self.parse_entries(false)?;
+                    Node::Object { entries, close }
+                }
+                _ => return Err(self.error("expected a value")),
+            };
+            entries.push(Entry {
+                key,
+                range: key_range.start..self.position,
+                key_range,
+                value,
+            });
+        }
+    }
+}
+
+fn parse(text: &str) -> Result<Node, VdfEditError> {
+    let (entries, close) = Tokenizer { text, position: 0 }.parse_entries(true)?;
+    Ok(Node::Object { entries, close })
+}
+
+fn quote(string: &str) -> String {
+    format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Offset of the start of the line `offset` is on.
+fn line_start(text: &str, offset: usize) -> usize {
+    text[..offset].rfind('\n').map_or(0, |newline| newline + 1)
+}
+
+/// Whitespace in front of `offset`, if nothing else is in front of it on its line.
+fn indentation_before(text: &str, offset: usize) -> Option<&str> {
+    let indentation = &text[line_start(text, offset)..offset];
+    indentation
+        .chars()
+        .all(|char| char == ' ' || char == '\t')
+        .then_some(indentation)
+}
+
+/// Finds the entry at `path`, keys are compared ignoring case like Steam does.
+fn find<'a>(node: &'a Node, path: &[&str]) -> Result<Option<&'a Entry>, VdfEditError> {
+    let Some((key, rest)) = path.split_first() else {
+        return Ok(None);
+    };
+    let Node::Object { entries, .. } = node else {
+        return Ok(None);
+    };
+    let Some(entry) = entries
+        .iter()
+        .find(|entry| entry.key.eq_ignore_ascii_case(key))
+    else {
+        return Ok(None);
+    };
+    if rest.is_empty() {
+        return Ok(Some(entry));
+    }
+    match entry.value {
+        Node::Object { .. } => find(&entry.value, rest),
+        Node::String(_) => Err(VdfEditError::NotAnObject(entry.key.clone())),
+    }
+}
+
+/// Returns the string at `path`, `None` if there is no entry or it is an object.
+pub fn get_string(text: &str, path: &[&str]) -> Result<Option<String>, VdfEditError> {
+    let root = parse(text)?;
+    Ok(find(&root, path)?.and_then(|entry| match &entry.value {
+        Node::String(range) => Tokenizer {
+            text,
+            position: range.start,
+        }
+        .next_token()
+        .ok()
+        .flatten()
+        .and_then(|(token, _)| match token {
+            Token::String(string) => Some(string),
+            _ => None,
+        }),
+        Node::Object { .. } => None,
+    }))
+}
+
+/// Whether there is an entry at `path`, string or object.
+pub fn contains(text: &str, path: &[&str]) -> Result<bool, VdfEditError> {
+    Ok(find(&parse(text)?, path)?.is_some())
+}
+
+/// Sets the string at `path`, creating the objects leading to it. Only the value is replaced when
+/// the entry exists, new entries are added at the end of their object with the indentation of
+/// their siblings.
+pub fn set_string(text: &str, path: &[&str], value: &str) -> Result<String, VdfEditError> {
+    let root = parse(text)?;
+    let mut node = &root;
+    let mut depth = 0;
+    for (index, key) in path.iter().enumerate() {
+        let Node::Object { entries, .. } = node else {
+            unreachable!("only objects are descended into");
+        };
+        let Some(entry) = entries
+            .iter()
+            .find(|entry| entry.key.eq_ignore_ascii_case(key))
+        else {
+            break;
+        };
+        match &entry.value {
+            Node::String(range) if index == path.len() - 1 => {
+                let mut text = text.to_string();
+                text.replace_range(range.clone(), &quote(value));
+                return Ok(text);
+            }
+            Node::String(_) => return Err(VdfEditError::NotAnObject(entry.key.clone())),
+            Node::Object { .. } if index == path.len() - 1 => {
+                return Err(VdfEditError::NotAString(entry.key.clone()))
+            }
+            Node::Object { .. } => {
+                node = &entry.value;
+                depth += 1;
+            }
+        }
+    }
+    Ok(insert(text, node, depth, &path[depth..], value))
+}
+
+/// Adds the entry for `path` to the object `node`, which is `depth` levels deep.
+fn insert(text: &str, node: &Node, depth: usize, path: &[&str], value: &str) -> String {
+    let Node::Object { entries, close } = node else {
+        unreachable!("entries are only inserted into objects");
+    };
+    let close = *close;
+    let root = depth == 0;
+
+    // Follow the style of the siblings, or of the closing brace if there are none
+    let unit = indentation_unit(text);
+    let close_indentation = if root {
+        Some("")
+    } else {
+        indentation_before(text, close)
+    };
+    let indentation = if root {
+        String::new()
+    } else {
+        entries
+            .first()
+            .and_then(|entry| indentation_before(text, entry.key_range.start))
+            .map(str::to_string)
+            .or_else(|| close_indentation.map(|close| format!("{}{}", close, unit)))
+            .unwrap_or_else(|| unit.repeat(depth))
+    };
+    let separator = entries
+        .iter()
+        .find_map(|entry| match &entry.value {
+            Node::String(range) => Some(&text[entry.key_range.end..range.start]),
+            Node::Object { .. } => None,
+        })
+        .filter(|separator| !separator.contains('\n'))
+        .unwrap_or(DEFAULT_SEPARATOR);
+
+    let rendered = render(path, value, &indentation, unit, separator);
+    let mut text = text.to_string();
+    if root {
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push_str(&rendered);
+    } else if close_indentation.is_some() {
+        text.insert_str(line_start(&text, close), &rendered);
+    } else {
+        // The object is on one line, e.g. `"apps" { "730" "1" }`
+        text.insert_str(close, &format!("{} ", rendered.trim()));
+    }
+    text
+}
+
+/// Indentation of the first indented line, which is one level deep.
+fn indentation_unit(text: &str) -> &str {
+    text.lines()
+        .map(|line| &line[..line.len() - line.trim_start_matches([' ', '\t']).len()])
+        .find(|indentation| !indentation.is_empty())
+        .unwrap_or(DEFAULT_INDENT)
+}
+
+/// Writes `path` as nested objects holding `value`, one entry per line.
+fn render(path: &[&str], value: &str, indentation: &str, unit: &str, separator: &str) -> String {
+    match path {
+        [] => String::new(),
+        [key] => format!(
+            "{}{}{}{}\n",
+            indentation,
+            quote(key),
+            separator,
+            quote(value)
+        ),
+        [key, rest @ ..] => {
+            let inner = format!("{}{}", indentation, unit);
+            format!(
+                "{indentation}{}\n{indentation}{{\n{}{indentation}}}\n",
+                quote(key),
+                render(rest, value, &inner, unit, separator),
+            )
+        }
+    }
+}
+
+/// Removes the entry at `path` along with its lines, leaves the text as is if there is none.
+pub fn remove(text: &str, path: &[&str]) -> Result<String, VdfEditError> {
+    let root = parse(text)?;
+    let Some(entry) = find(&root, path)? else {
+        return Ok(text.to_string());
+    };
+    let mut range = entry.range.clone();
+    let rest = &text[range.end..];
+    let trailing = rest.find('\n').map(|newline| &rest[..newline]);
+    if let (Some(_), Some(trailing)) = (
+        indentation_before(text, range.start),
+        trailing.filter(|trailing| trailing.trim().is_empty()),
+    ) {
+        range = line_start(text, range.start)..range.end + trailing.len() + 1;
+    }
+    let mut text = text.to_string();
+    text.replace_range(range, "");
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// config.vdf as Steam writes it, shortened but with every kind of section it has.
+    const CONFIG_VDF: &str = r#""InstallConfigStore"
+{
+	"Software"
+	{
+		"valve"
+		{
+			"Steam"
+			{
+				"AutoUpdateWindowEnabled"		"0"
+				"ShaderCacheManager"
+				{
+					"HasCurrentBucket"		"1"
+					"CurrentBucketGPU"		"c2c90f2eb5c9f3b8ba0e9aa52fe1c7e0"
+					"CurrentBucketDriver"		"W2:b5a79c8a4a7e02c7"
+				}
+				"RecentWebSocketPort"		"27060"
+				"SurveyDate"		"2024-07-12"
+				"SurveyDateVersion"		"-6405196236547209036"
+				"ipv6check_http_state"		"bad"
+				"ipv6check_udp_state"		"bad"
+				"cip"		"02000000ec0a1f62c4f9d0b3f2f50000f8f3c5"
+				"Accounts"
+				{
+					"deck_user"
+					{
+						"SteamID"		"76561198000000000"
+					}
+				}
+				"CompatToolMapping"
+				{
+					"0"
+					{
+						"name"		"proton_9"
+						"config"		""
+						"priority"		"75"
+					}
+					"730"
+					{
+						"name"		"GE-Proton9-20"
+						"config"		""
+						"priority"		"250"
+					}
+					"1245620"
+					{
+						"name"		"proton_experimental"
+						"config"		""
+						"priority"		"250"
+					}
+				}
+				"depots"
+				{
+					"1245621"
+					{
+						"CDN"		"cache1-fra1.steamcontent.com"
+					}
+				}
+				"LaunchOptions"		"PROTON_LOG=1 \"%command%\" -windowed"
+				"BaseInstallFolder_1"		"/run/media/deck/SD Card"
+				"ShortcutsPath"		"C:\\Program Files\\Heroic"
+			}
+		}
+	}
+	"Music"
+	{
+		"CrawlSteamInstallFolders"		"1"
+	}
+	"Streaming"
+	{
+		"ClientID"		"-8251298473219873498"
+	}
+	"WebStorage"
+	{
+		"ToastNotifications"		"1"
+	}
+}
+"#;
+
+    const MAPPINGS: [&str; 5] = [
+        "InstallConfigStore",
+        "Software",
+        "Valve",
+        "Steam",
+        "CompatToolMapping",
+    ];
+
+    fn steam(key: &str) -> [&str; 5] {
+        ["InstallConfigStore", "Software", "Valve", "Steam", key]
+    }
+
+    fn mapping<'a>(keys: &[&'a str]) -> Vec<&'a str> {
+        MAPPINGS.iter().chain(keys).copied().collect()
+    }
+
+    /// The lines that were removed and added, going by the lines both texts start and end with.
+    fn changed_lines<'a>(before: &'a str, after: &'a str) -> (Vec<&'a str>, Vec<&'a str>) {
+        let before: Vec<&str> = before.lines().collect();
+        let after: Vec<&str> = after.lines().collect();
+        let prefix = before
+            .iter()
+            .zip(&after)
+            .take_while(|(before, after)| before == after)
+            .count();
+        let suffix = before[prefix..]
+            .iter()
+            .rev()
+            .zip(after[prefix..].iter().rev())
+            .take_while(|(before, after)| before == after)
+            .count();
+        (
+            before[prefix..before.len() - suffix].to_vec(),
+            after[prefix..after.len() - suffix].to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_update_only_replaces_the_value() {
+        let updated = set_string(CONFIG_VDF, &mapping(&["730", "name"]), "GE-Proton9-21").unwrap();
+        assert_eq!(
+            changed_lines(CONFIG_VDF, &updated),
+            (
+                vec!["\t\t\t\t\t\t\"name\"\t\t\"GE-Proton9-20\""],
+                vec!["\t\t\t\t\t\t\"name\"\t\t\"GE-Proton9-21\""]
+            )
+        );
+        assert_eq!(
+            get_string(&updated, &mapping(&["730", "name"])).unwrap(),
+            Some("GE-Proton9-21".to_string())
+        );
+    }
+
+    #[test]
+    fn test_insert_follows_the_siblings() {
+        let updated = set_string(CONFIG_VDF, &mapping(&["1145360", "name"]), "proton_9").unwrap();
+        assert_eq!(
+            changed_lines(CONFIG_VDF, &updated),
+            (
+                vec![],
+                vec![
+                    "\t\t\t\t\t\"1145360\"",
+                    "\t\t\t\t\t{",
+                    "\t\t\t\t\t\t\"name\"\t\t\"proton_9\"",
+                    "\t\t\t\t\t}",
+                ]
+            )
+        );
+        // Added as the last mapping
+        assert!(updated.contains("\t\t\t\t\t}\n\t\t\t\t\t\"1145360\"\n\t\t\t\t\t{\n"));
+        assert!(contains(&updated, &mapping(&["1145360", "name"])).unwrap());
+
+        let updated = set_string(&updated, &mapping(&["1145360", "priority"]), "250").unwrap();
+        assert_eq!(
+            changed_lines(CONFIG_VDF, &updated).1[3],
+            "\t\t\t\t\t\t\"priority\"\t\t\"250\""
+        );
+    }
+
+    #[test]
+    fn test_missing_objects_are_created() {
+        let config = "\"InstallConfigStore\"\n{\n    \"Software\"\n    {\n    }\n}\n";
+        let updated = set_string(config, &mapping(&["730", "name"]), "proton_9").unwrap();
+        assert_eq!(
+            updated,
+            "\"InstallConfigStore\"\n{\n    \"Software\"\n    {\n        \"Valve\"\n        {\n            \"Steam\"\n            {\n                \"CompatToolMapping\"\n                {\n                    \"730\"\n                    {\n                        \"name\"\t\t\"proton_9\"\n                    }\n                }\n            }\n        }\n    }\n}\n"
+        );
+        assert_eq!(
+            get_string(&updated, &mapping(&["730", "name"])).unwrap(),
+            Some("proton_9".to_string())
+        );
+
+        let updated = set_string("", &["InstallConfigStore", "Software"], "1").unwrap();
+        assert_eq!(
+            updated,
+            "\"InstallConfigStore\"\n{\n\t\"Software\"\t\t\"1\"\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_remove_takes_the_whole_entry() {
+        let updated = remove(CONFIG_VDF, &mapping(&["730"])).unwrap();
+        assert_eq!(
+            changed_lines(CONFIG_VDF, &updated),
+            (
+                vec![
+                    "\t\t\t\t\t\"730\"",
+                    "\t\t\t\t\t{",
+                    "\t\t\t\t\t\t\"name\"\t\t\"GE-Proton9-20\"",
+                    "\t\t\t\t\t\t\"config\"\t\t\"\"",
+                    "\t\t\t\t\t\t\"priority\"\t\t\"250\"",
+                    "\t\t\t\t\t}",
+                ],
+                vec![]
+            )
+        );
+        assert!(!contains(&updated, &mapping(&["730", "name"])).unwrap());
+        // Removing what isn't there changes nothing
+        assert_eq!(
+            remove(&updated, &mapping(&["730", "name"])).unwrap(),
+            updated
+        );
+    }
+
+    #[test]
+    fn test_escapes_are_kept() {
+        let launch_options = steam("LaunchOptions");
+        assert_eq!(
+            get_string(CONFIG_VDF, &launch_options).unwrap(),
+            Some("PROTON_LOG=1 \"%command%\" -windowed".to_string())
+        );
+        let shortcuts_path = steam("ShortcutsPath");
+        assert_eq!(
+            get_string(CONFIG_VDF, &shortcuts_path).unwrap(),
+            Some("C:\\Program Files\\Heroic".to_string())
+        );
+
+        let updated = set_string(CONFIG_VDF, &launch_options, "\"%command%\"\t-dx11").unwrap();
+        assert_eq!(
+            changed_lines(CONFIG_VDF, &updated).1,
+            ["\t\t\t\t\"LaunchOptions\"\t\t\"\\\"%command%\\\"\t-dx11\""]
+        );
+        assert_eq!(
+            get_string(&updated, &launch_options).unwrap(),
+            Some("\"%command%\"\t-dx11".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_text_is_an_error() {
+        assert!(matches!(
+            set_string("\"InstallConfigStore\"\n{\n\t\"Software\"", &MAPPINGS, "1"),
+            Err(VdfEditError::Syntax { .. })
+        ));
+        let crawl = ["InstallConfigStore", "Music", "CrawlSteamInstallFolders"];
+        assert_eq!(
+            set_string(CONFIG_VDF, &[&crawl[..], &["x"]].concat(), "1"),
+            Err(VdfEditError::NotAnObject(crawl[2].to_string()))
+        );
+        assert_eq!(
+            set_string(CONFIG_VDF, &MAPPINGS, "1"),
+            Err(VdfEditError::NotAString("CompatToolMapping".to_string()))
+        );
+    }
+}
@@ -0,0 +1,364 @@
+use crate::app_id::CompatAppId;
+use crate::wine_cask::app::{CompatibilityToolMapping, WineCask};
+use crate::wine_cask::provenance::current_timestamp;
+use crate::wine_cask::snapshot_diff::{diff_snapshots, SnapshotChange};
+use crate::written_by::stamped;
+use crate::PeerMap;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::{fs, io};
+
+/// Number of events kept on disk, older events are dropped first.
+const MAX_EVENTS: usize = 500;
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum ActivitySource {
+    /// Caused by one of our own tasks.
+    Task,
+    /// Detected when re-reading the compatibility tools or Steam's mappings.
+    External,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum ActivityChange {
+    ToolInstalled {
+        internal_name: String,
+        display_name: String,
+    },
+    ToolRemoved {
+        internal_name: String,
+        display_name: String,
+    },
+    ToolRenamed {
+        internal_name: String,
+        old_display_name: String,
+        display_name: String,
+    },
+    MappingChanged {
+        app_id: CompatAppId,
+        app_name: String,
+        /// `None` when the app had no mapping before.
+        from: Option<String>,
+        /// `None` when the mapping was removed.
+        to: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ActivityEvent {
+    pub timestamp: u64,
+    pub source: ActivitySource,
+    pub change: ActivityChange,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ActivityQuery {
+    pub limit: usize,
+    /// Only return events older than this timestamp, used for paging.
+    pub before: Option<u64>,
+}
+
+/// Persisted activity feed along with the last seen state used to detect external changes.
+#[derive(Serialize, Deserialize, Default)]
+pub struct ActivityLog {
+    events: Vec<ActivityEvent>,
+    // Internal name to display name, `None` until the first snapshot was taken
+    tools: Option<BTreeMap<String, String>>,
+    mappings: Option<BTreeMap<CompatAppId, String>>,
+    #[serde(skip)]
+    file: PathBuf,
+}
+
+impl ActivityLog {
+    pub fn load(file: PathBuf) -> ActivityLog {
+        let activity_log = fs::read_to_string(&file)
+            .ok()
+            .and_then(|string| serde_json::from_str(&string).ok())
+            .unwrap_or_default();
+        ActivityLog {
+            file,
+            ..activity_log
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            &self.file,
+            serde_json::to_string(&stamped(self, "activity_log", &self.file))?,
+        )
+    }
+
+    pub fn record(&mut self, source: ActivitySource, change: ActivityChange, timestamp: u64) {
+        self.events.push(ActivityEvent {
+            timestamp,
+            source,
+            change,
+        });
+        if self.events.len() > MAX_EVENTS {
+            let excess = self.events.len() - MAX_EVENTS;
+            self.events.drain(..excess);
+        }
+    }
+
+    /// Returns up to `limit` events older than `before`, newest first.
+    pub fn get_activity(&self, limit: usize, before: Option<u64>) -> Vec<ActivityEvent> {
+        let before = before.unwrap_or(u64::MAX);
+        self.events
+            .iter()
+            .rev()
+            .filter(|event| event.timestamp < before)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Records the differences to the previous tool snapshot, the first snapshot records nothing.
+    pub fn update_tools(
+        &mut self,
+        tools: BTreeMap<String, String>,
+        source: ActivitySource,
+        timestamp: u64,
+    ) -> bool {
+        let changes = match &self.tools {
+            Some(previous) => diff_snapshots(previous, &tools),
+            None => Vec::new(),
+        };
+        for change in changes {
+            let change = match change {
+                SnapshotChange::Added { key, value } => ActivityChange::ToolInstalled {
+                    internal_name: key,
+                    display_name: value,
+                },
+                SnapshotChange::Removed { key, value } => ActivityChange::ToolRemoved {
+                    internal_name: key,
+                    display_name: value,
+                },
+                SnapshotChange::Changed { key, old, new } => ActivityChange::ToolRenamed {
+                    internal_name: key,
+                    old_display_name: old,
+                    display_name: new,
+                },
+            };
+            self.record(source, change, timestamp);
+        }
+        let updated = self.tools.as_ref() != Some(&tools);
+        self.tools = Some(tools);
+        updated
+    }
+
+    /// Records mapping changes since the previous snapshot.
+    pub fn update_mappings(
+        &mut self,
+        mappings: &[CompatibilityToolMapping],
+        source: ActivitySource,
+        timestamp: u64,
+    ) -> bool {
+        let snapshot: BTreeMap<CompatAppId, String> = mappings
+            .iter()
+            .map(|mapping| (mapping.app_id, mapping.compatibility_tool.clone()))
+            .collect();
+        let changes = match &self.mappings {
+            Some(previous) => diff_snapshots(previous, &snapshot),
+            None => Vec::new(),
+        };
+        for change in changes {
+            let (app_id, from, to) = match change {
+                SnapshotChange::Added { key, value } => (key, None, Some(value)),
+                SnapshotChange::Removed { key, value } => (key, Some(value), None),
+                SnapshotChange::Changed { key, old, new } => (key, Some(old), Some(new)),
+            };
+            let app_name = mappings
+                .iter()
+                .find(|mapping| mapping.app_id == app_id)
+                .map_or_else(|| app_id.to_string(), |mapping| mapping.name.clone());
+            self.record(
+                source,
+                ActivityChange::MappingChanged {
+                    app_id,
+                    app_name,
+                    from,
+                    to,
+                },
+                timestamp,
+            );
+        }
+        let updated = self.mappings.as_ref() != Some(&snapshot);
+        self.mappings = Some(snapshot);
+        updated
+    }
+}
+
+impl WineCask {
+    /// Compares the installed tools against the last snapshot and records the differences.
+    pub async fn record_tool_activity(&self, source: ActivitySource) {
+        let tools: BTreeMap<String, String> = self
+            .app_state
+            .lock()
+            .await
+            .installed_compatibility_tools
+            .iter()
+            .map(|tool| (tool.internal_name.clone(), tool.display_name.clone()))
+            .collect();
+        let mut activity_log = self.activity_log.lock().await;
+        if activity_log.update_tools(tools, source, current_timestamp()) {
+            if let Err(err) = activity_log.save() {
+                warn!("Failed to save activity log: {}", err);
+            }
+        }
+    }
+
+    pub async fn record_mapping_activity(&self, source: ActivitySource) {
+        let mappings = self
+            .app_state
+            .lock()
+            .await
+            .compatibility_tool_mappings
+            .clone();
+        let mut activity_log = self.activity_log.lock().await;
+        if activity_log.update_mappings(&mappings, source, current_timestamp()) {
+            if let Err(err) = activity_log.save() {
+                warn!("Failed to save activity log: {}", err);
+            }
+        }
+    }
+
+    pub async fn get_activity(&self, peer_map: &PeerMap, query: ActivityQuery) {
+        let activity = self
+            .activity_log
+            .lock()
+            .await
+            .get_activity(query.limit, query.before);
+        self.broadcast_activity(peer_map, activity).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn tools(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(internal_name, display_name)| {
+                (internal_name.to_string(), display_name.to_string())
+            })
+            .collect()
+    }
+
+    fn mapping(app_id: u64, name: &str, compatibility_tool: &str) -> CompatibilityToolMapping {
+        CompatibilityToolMapping {
+            app_id: CompatAppId::new(app_id).unwrap(),
+            name: name.to_string(),
+            compatibility_tool: compatibility_tool.to_string(),
+            unresolved: false,
+            steam_override: None,
+            quick_slots: None,
+        }
+    }
+
+    #[test]
+    fn test_tool_changes_are_attributed() {
+        let temp_dir = tempdir().unwrap();
+        let mut activity_log = ActivityLog::load(temp_dir.path().join("activity.json"));
+
+        // The first snapshot is only a baseline
+        activity_log.update_tools(
+            tools(&[("GE-Proton9-20", "GE-Proton9-20")]),
+            ActivitySource::External,
+            100,
+        );
+        assert!(activity_log.get_activity(10, None).is_empty());
+
+        activity_log.update_tools(
+            tools(&[
+                ("GE-Proton9-20", "GE-Proton9-20"),
+                ("GE-Proton9-21", "GE-Proton9-21"),
+            ]),
+            ActivitySource::Task,
+            200,
+        );
+        activity_log.update_tools(
+            tools(&[("GE-Proton9-21", "GE-Proton9-21")]),
+            ActivitySource::External,
+            300,
+        );
+
+        let activity = activity_log.get_activity(10, None);
+        assert_eq!(activity.len(), 2);
+        assert_eq!(activity[0].source, ActivitySource::External);
+        assert!(matches!(
+            &activity[0].change,
+            ActivityChange::ToolRemoved { internal_name, .. } if internal_name == "GE-Proton9-20"
+        ));
+        assert_eq!(activity[1].source, ActivitySource::Task);
+        assert_eq!(activity[1].timestamp, 200);
+    }
+
+    #[test]
+    fn test_mapping_changes_are_detected() {
+        let temp_dir = tempdir().unwrap();
+        let mut activity_log = ActivityLog::load(temp_dir.path().join("activity.json"));
+
+        activity_log.update_mappings(
+            &[mapping(1245620, "ELDEN RING", "GE-Proton9-20")],
+            ActivitySource::External,
+            100,
+        );
+        activity_log.update_mappings(
+            &[mapping(1245620, "ELDEN RING", "GE-Proton9-21")],
+            ActivitySource::External,
+            200,
+        );
+        activity_log.update_mappings(&[], ActivitySource::External, 300);
+
+        let activity = activity_log.get_activity(10, None);
+        assert_eq!(
+            activity[1].change,
+            ActivityChange::MappingChanged {
+                app_id: CompatAppId::new(1245620).unwrap(),
+                app_name: "ELDEN RING".to_string(),
+                from: Some("GE-Proton9-20".to_string()),
+                to: Some("GE-Proton9-21".to_string()),
+            }
+        );
+        assert!(matches!(
+            &activity[0].change,
+            ActivityChange::MappingChanged { app_name, to: None, .. } if app_name == "1245620"
+        ));
+    }
+
+    #[test]
+    fn test_activity_is_capped_paged_and_persisted() {
+        let temp_dir = tempdir().unwrap();
+        let file = temp_dir.path().join("activity.json");
+        let mut activity_log = ActivityLog::load(file.clone());
+        for timestamp in 0..(MAX_EVENTS as u64 + 10) {
+            activity_log.record(
+                ActivitySource::Task,
+                ActivityChange::ToolInstalled {
+                    internal_name: timestamp.to_string(),
+                    display_name: timestamp.to_string(),
+                },
+                timestamp,
+            );
+        }
+        activity_log.save().unwrap();
+
+        let activity_log = ActivityLog::load(file);
+        assert_eq!(
+            activity_log.get_activity(usize::MAX, None).len(),
+            MAX_EVENTS
+        );
+        let page = activity_log.get_activity(2, Some(100));
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].timestamp, 99);
+        assert_eq!(page[1].timestamp, 98);
+        assert!(activity_log.get_activity(10, Some(10)).is_empty());
+    }
+}
@@ -1,34 +1,177 @@
-use crate::steam_util::SteamUtil;
+use crate::app_id::{AppId, CompatAppId, ShortcutId};
+use crate::steam_util::{ConfigWrite, InstalledApp, ShortcutDetails, SteamUtil, SteamUtilError};
+use crate::wine_cask::activity::{ActivityEvent, ActivityLog, ActivityQuery, ActivitySource};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::app_names::AppNameResolver;
+use crate::wine_cask::clock::RefreshSchedule;
+use crate::wine_cask::compat_data::{CompatDataListing, CompatDataListings, DeleteCompatData};
+use crate::wine_cask::direct_install::DirectInstall;
+use crate::wine_cask::disk_space::DiskSpaceShortage;
+use crate::wine_cask::environment::{Environment, EnvironmentSnapshot};
+use crate::wine_cask::error_aggregation::{ErrorAggregator, ErrorGroup};
+use crate::wine_cask::feature_flags::Feature;
+use crate::wine_cask::file_watcher::OwnWrites;
+use crate::wine_cask::filesystems::{detect_filesystem, PathFilesystem};
 use crate::wine_cask::flavors::{
-    CompatibilityToolFlavor, Flavor, SteamClientCompatToolInfo, SteamCompatibilityTool,
+    CacheUse, CompatibilityToolFlavor, Flavor, SteamClientCompatToolInfo, SteamCompatibilityTool,
 };
+use crate::wine_cask::history::{HistoryPage, HistoryQuery};
 use crate::wine_cask::install::{Install, QueueCompatibilityTool, QueueCompatibilityToolState};
+use crate::wine_cask::install_manifest::InstallManifest;
+use crate::wine_cask::local_changes::LocalChangesCache;
+use crate::wine_cask::local_install::LocalFileInstall;
+use crate::wine_cask::mapping_import::{ImportRowResult, MappingImport};
+use crate::wine_cask::mappings::{MappingChange, MappingChangeResult, MappingChanges};
+use crate::wine_cask::migration::{Migrate, StrandedCompatibilityTool};
+use crate::wine_cask::network_usage::NetworkUsage;
+use crate::wine_cask::outbox::{
+    evict_peer, remove_peer, send_to_peer, BroadcastCounters, Delivery, Outbox, BROADCAST_STATS,
+};
+use crate::wine_cask::permissions::{filter_for_peer, validate_access_tokens, PermissionSet};
+use crate::wine_cask::plans::{ActionResult, Plan, PlanKind, PlanStore};
+use crate::wine_cask::prefix_backup::{
+    PrefixRestore, RestoreDecision, RestoreDecisions, RestorePrefixBackup,
+};
+use crate::wine_cask::prefix_scan::{PrefixInfo, PrefixScan};
+use crate::wine_cask::protocol::{ProtocolVersion, VersionMismatch};
+use crate::wine_cask::provenance::{Provenance, Verification};
+use crate::wine_cask::proxy::{set_proxy_url, validate_proxy_url};
+use crate::wine_cask::quick_slots::QuickSlotState;
+use crate::wine_cask::r#virtual::{virtual_tool_status, VirtualToolSettings};
+use crate::wine_cask::refresh::{RefreshKey, RefreshScope, RefreshTracker};
+use crate::wine_cask::settings::Settings;
+use crate::wine_cask::startup::{CompletedStartupStage, StartupProgress};
+use crate::wine_cask::state_delta::{StateBroadcasts, StateDeltas};
+use crate::wine_cask::steam_overrides::SteamOverrides;
+use crate::wine_cask::storage::{PrefixSize, ShaderCacheStatus, StorageBreakdown};
+use crate::wine_cask::subscriptions::{filter_for_subscriptions, SubscriptionSet};
+use crate::wine_cask::tool_inspection::{
+    apply_inspections, InspectionProgress, ToolInspection, ToolInspector,
+};
+use crate::wine_cask::undo::{UndoEntry, UndoStack};
 use crate::wine_cask::uninstall::Uninstall;
-use crate::PeerMap;
+use crate::wine_cask::update_all::{UpdateAll, UpdateSummary};
+use crate::wine_cask::update_check::AvailableUpdate;
+use crate::wine_cask::validation::ValidationError;
+use crate::wine_cask::{task_target, TaskTarget};
+use crate::written_by::WrittenBy;
+use crate::{PeerAddr, PeerMap};
+use futures_util::future;
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Mutex;
 use tokio_tungstenite::tungstenite::Message;
 
+/// Ids of queued tasks, unique for as long as the backend runs.
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(1);
+
+pub(crate) fn next_task_id() -> u64 {
+    NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed)
+}
+
 pub struct WineCask {
     pub steam_util: SteamUtil,
     pub app_state: Arc<Mutex<AppState>>,
+    pub app_name_resolver: Arc<std::sync::Mutex<AppNameResolver>>,
+    pub activity_log: Arc<Mutex<ActivityLog>>,
+    pub undo_stack: Arc<Mutex<UndoStack>>,
+    pub environment: Arc<std::sync::Mutex<Environment>>,
+    pub state_broadcasts: Arc<Mutex<StateBroadcasts>>,
+    /// Steam files written by Wine Cellar itself, the file watcher doesn't re-read them.
+    pub own_writes: OwnWrites,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct AppState {
     pub available_flavors: Vec<Flavor>,
     pub installed_compatibility_tools: Vec<SteamCompatibilityTool>,
-    pub in_progress: Option<QueueCompatibilityTool>,
+    /// Wine versions installed for Lutris and Heroic, Steam doesn't use them.
+    pub installed_runners: Vec<SteamCompatibilityTool>,
+    /// DXVK and vkd3d-proton versions kept in the plugin's directory to drop into prefixes.
+    pub installed_components: Vec<SteamCompatibilityTool>,
+    pub compatibility_tool_mappings: Vec<CompatibilityToolMapping>,
+    /// Installs currently running, several run at once up to the configured number.
+    pub in_progress: Vec<QueueCompatibilityTool>,
     pub task_queue: VecDeque<Task>,
     pub updater_state: UpdaterState,
     pub updater_last_check: Option<u64>,
+    /// When the GitHub rate limit the last release check ran into resets, `None` unless limited.
+    pub github_rate_limit_reset: Option<u64>,
+    pub settings: Settings,
+    pub network_usage: NetworkUsage,
+    /// Last sample of the environment, refreshed while tasks run and by the sampler.
+    pub environment: EnvironmentSnapshot,
+    /// Tools under other Steam roots that the active Steam installation doesn't see.
+    pub stranded_compatibility_tools: Vec<StrandedCompatibilityTool>,
+    /// Startup stages and their timings, sent along once the backend is ready.
+    pub startup_stages: Vec<CompletedStartupStage>,
+    /// Mappings were written since the backend started, Steam only reads them on startup.
+    pub restart_required: bool,
+    /// Filesystems of the library folders and of the tools directory.
+    pub filesystem_profiles: Vec<PathFilesystem>,
+    /// Messages peers missed because they didn't keep up, since startup.
+    pub broadcast_counters: BroadcastCounters,
+    /// Every Steam installation found, the one in use first.
+    pub steam_installations: Vec<String>,
+    /// Background inspection of the installed tools, `None` until it started.
+    pub inspection_progress: Option<InspectionProgress>,
+    /// Updates the last scheduled update check found.
+    pub available_updates: Vec<AvailableUpdate>,
+    /// Prefix backups being restored, including the ones waiting for a decision.
+    pub prefix_restores: Vec<PrefixRestore>,
     #[serde(skip)]
     pub available_compat_tools: Option<Vec<SteamClientCompatToolInfo>>,
     #[serde(skip)]
     pub flavors: Vec<Flavor>,
+    #[serde(skip)]
+    pub storage_breakdown: Option<StorageBreakdown>,
+    /// Tools already warned about not showing up in Steam, so each problem is reported once.
+    #[serde(skip)]
+    pub reported_pickup_problems: HashSet<String>,
+    #[serde(skip)]
+    pub steam_overrides: SteamOverrides,
+    #[serde(skip)]
+    pub clock_checked: Option<Instant>,
+    #[serde(skip)]
+    pub release_refresh: RefreshSchedule,
+    #[serde(skip)]
+    pub error_aggregator: ErrorAggregator,
+    #[serde(skip)]
+    pub refreshes: RefreshTracker,
+    #[serde(skip)]
+    pub prefix_scan: Option<PrefixScan>,
+    #[serde(skip)]
+    pub local_changes: LocalChangesCache,
+    #[serde(skip)]
+    pub install_manifest: InstallManifest,
+    /// Imported mappings applied once the install of their tool finished.
+    #[serde(skip)]
+    pub pending_mappings: Vec<MappingChange>,
+    #[serde(skip)]
+    pub plans: PlanStore,
+    #[serde(skip)]
+    pub compat_data_listings: CompatDataListings,
+    #[serde(skip)]
+    pub tool_inspector: ToolInspector,
+    #[serde(skip)]
+    pub restore_decisions: RestoreDecisions,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CompatibilityToolMapping {
+    pub app_id: CompatAppId,
+    /// App name, or the app id if no name could be found.
+    pub name: String,
+    pub compatibility_tool: String,
+    pub unresolved: bool,
+    /// Tool Steam forces on the app regardless of this mapping, e.g. `proton_hotfix`.
+    pub steam_override: Option<String>,
+    /// Tools the app can be switched between, if any were set.
+    pub quick_slots: Option<QuickSlotState>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -43,21 +186,129 @@ pub enum RequestType {
     UpdateState,
     Notification,
     Task,
+    UpdateSettings,
+    GetToolProvenance,
+    ToolProvenance,
+    VerifyInstalledTool,
+    Verification,
+    GetActivity,
+    Activity,
+    GetStorageBreakdown,
+    StorageBreakdown,
+    ClearShaderCache,
+    StartupProgress,
+    ValidationError,
+    GetUndoStack,
+    UndoStack,
+    UndoLast,
+    Refresh,
+    RefreshCompleted,
+    GetMutationLog,
+    MutationLog,
+    SkipRelease,
+    UnskipRelease,
+    PrioritizePrefixes,
+    CancelPrefixScan,
+    PrefixScanned,
+    PrefixScanCompleted,
+    CheckLocalChanges,
+    Permissions,
+    SetQuickSlots,
+    SwitchQuickSlot,
+    CreatePlan,
+    Plan,
+    ExecutePlan,
+    PlanExecuted,
+    PauseInspection,
+    ResumeInspection,
+    ToolInspected,
+    InspectionCompleted,
+    SelectSteamInstallation,
+    CancelTask,
+    TaskCancelled,
+    UpdateSummary,
+    UninstallBlocked,
+    ForceRefresh,
+    InsufficientDiskSpace,
+    UpdatesAvailable,
+    AdoptTool,
+    ListOrphanedCompatData,
+    OrphanedCompatData,
+    GetPrefixSizes,
+    PrefixSizes,
+    RequestSnapshot,
+    StateDelta,
+    Error,
+    Authenticate,
+    Subscribe,
+    Subscribed,
+    Hello,
+    GetInstalledApps,
+    InstalledApps,
+    GetShortcutDetails,
+    ShortcutDetails,
+    SetShortcutLaunchOptions,
+    GetHistory,
+    History,
+    ResolveRestoreConflict,
+    CreateVirtualTool,
+    RollbackUpdate,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Task {
+    /// Assigned by the backend once the task is queued, `CancelTask` refers to it.
+    #[serde(default)]
+    pub id: u64,
     pub r#type: TaskType,
     pub install: Option<Install>,
     pub uninstall: Option<Uninstall>,
+    pub migrate: Option<Migrate>,
+    pub mapping: Option<MappingChange>,
+    pub mappings: Option<MappingChanges>,
+    pub import: Option<MappingImport>,
+    pub direct_install: Option<DirectInstall>,
+    pub update_all: Option<UpdateAll>,
+    pub local_file: Option<LocalFileInstall>,
+    pub delete_compat_data: Option<DeleteCompatData>,
+    pub restore_prefix_backup: Option<RestorePrefixBackup>,
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq)]
+impl Task {
+    pub fn new(r#type: TaskType) -> Self {
+        Self {
+            id: 0,
+            r#type,
+            install: None,
+            uninstall: None,
+            migrate: None,
+            mapping: None,
+            mappings: None,
+            import: None,
+            direct_install: None,
+            update_all: None,
+            local_file: None,
+            delete_compat_data: None,
+            restore_prefix_backup: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub enum TaskType {
     CheckForFlavorUpdates,
     InstallCompatibilityTool,
     CancelCompatibilityToolInstall,
     UninstallCompatibilityTool,
+    MigrateCompatibilityTools,
+    SetCompatibilityToolMapping,
+    SetCompatibilityToolMappings,
+    ImportCompatibilityToolMappings,
+    InstallFromUrl,
+    UpdateAllCompatibilityTools,
+    InstallFromLocalFile,
+    DeleteCompatData,
+    RestorePrefixBackup,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -65,32 +316,280 @@ pub struct Request {
     pub r#type: RequestType,
     pub task: Option<Task>,
     pub notification: Option<String>,
+    /// Sent with the notification of a finished install, whether Steam has to be restarted
+    /// before it lists the tool.
+    pub steam_restart_required: Option<bool>,
+    /// Set on the warning sent when an operation went ahead without knowing whether games run.
+    pub running_games_unknown: Option<bool>,
     pub available_compat_tools: Option<Vec<SteamClientCompatToolInfo>>,
     pub app_state: Option<AppState>,
+    pub settings: Option<Settings>,
+    pub internal_name: Option<String>,
+    pub provenance: Option<Provenance>,
+    pub verification: Option<Verification>,
+    pub activity_query: Option<ActivityQuery>,
+    pub activity: Option<Vec<ActivityEvent>>,
+    pub storage_breakdown: Option<StorageBreakdown>,
+    pub app_id: Option<AppId>,
+    /// Ignore cached results.
+    pub refresh: Option<bool>,
+    pub startup_progress: Option<StartupProgress>,
+    pub validation_errors: Option<Vec<ValidationError>>,
+    pub undo_stack: Option<Vec<UndoEntry>>,
+    pub error_group: Option<ErrorGroup>,
+    pub app_error: Option<AppError>,
+    /// Sent with `Authenticate` as the first message of a connection.
+    pub session_token: Option<String>,
+    /// Categories of messages the peer wants, or got with `Subscribed`.
+    pub subscriptions: Option<SubscriptionSet>,
+    /// Exchanged with `Hello` when a peer connects.
+    pub protocol_version: Option<ProtocolVersion>,
+    /// Sent with an `IncompatibleVersion` error.
+    pub version_mismatch: Option<VersionMismatch>,
+    /// Type of the request an `UnknownRequestType` error answers.
+    pub unknown_request_type: Option<String>,
+    /// Echoed back in the `RefreshCompleted` answering the request.
+    pub request_id: Option<String>,
+    pub refresh_scope: Option<RefreshScope>,
+    pub flavor: Option<CompatibilityToolFlavor>,
+    /// Sent along with mapping changes that invalidate the app's pre-cached shaders.
+    pub shader_cache: Option<ShaderCacheStatus>,
+    /// Outcome of every change of a mapping task.
+    pub mapping_results: Option<Vec<MappingChangeResult>>,
+    /// How the write of config.vdf behind `mapping_results` went.
+    pub config_write: Option<ConfigWrite>,
+    /// Disposition of every row of a mapping import.
+    pub mapping_import: Option<Vec<ImportRowResult>>,
+    /// Maximum number of entries to send back.
+    pub limit: Option<usize>,
+    pub mutation_log: Option<Vec<WrittenBy>>,
+    pub tag_name: Option<String>,
+    /// Apps visible in the frontend, scanned before every other prefix.
+    pub app_ids: Option<Vec<CompatAppId>>,
+    pub prefix: Option<PrefixInfo>,
+    /// What the receiving peer may do, sent once it connected.
+    pub permissions: Option<PermissionSet>,
+    /// Tools of the quick slots of `app_id`, in slot order.
+    pub quick_slots: Option<Vec<String>>,
+    pub slot: Option<usize>,
+    pub plan_kind: Option<PlanKind>,
+    pub plan: Option<Plan>,
+    pub plan_id: Option<u64>,
+    /// Actions of the plan left out when executing it.
+    pub excluded_action_ids: Option<Vec<u32>>,
+    /// Outcome of every action of an executed plan.
+    pub plan_results: Option<Vec<ActionResult>>,
+    pub tool_inspection: Option<ToolInspection>,
+    pub inspection_progress: Option<InspectionProgress>,
+    pub steam_directory: Option<String>,
+    pub task_id: Option<u64>,
+    /// Sent with `ResolveRestoreConflict` for the restore `task_id`.
+    pub restore_decision: Option<RestoreDecision>,
+    pub virtual_tool: Option<VirtualToolSettings>,
+    pub update_summary: Option<UpdateSummary>,
+    pub disk_space: Option<DiskSpaceShortage>,
+    pub available_updates: Option<Vec<AvailableUpdate>>,
+    pub compat_data_listing: Option<CompatDataListing>,
+    /// Size of every prefix, biggest first.
+    pub prefix_sizes: Option<Vec<PrefixSize>>,
+    /// Apps with an appmanifest, with their size, library folder and install state.
+    pub installed_apps: Option<Vec<InstalledApp>>,
+    pub shortcut_id: Option<ShortcutId>,
+    /// Sent with `SetShortcutLaunchOptions`, e.g. `PROTON_ENABLE_NVAPI=1 %command%`.
+    pub launch_options: Option<String>,
+    /// The shortcut in every account that has it.
+    pub shortcut_details: Option<Vec<ShortcutDetails>>,
+    pub history_query: Option<HistoryQuery>,
+    /// Sent with `RollbackUpdate` for the update's history record.
+    pub history_id: Option<u64>,
+    /// Finished installs and uninstalls, newest first.
+    pub history: Option<HistoryPage>,
+    /// Revision of the state an `UpdateState` snapshot carries.
+    pub revision: Option<u64>,
+    /// Filled in for each peer from `app_state` and `base_app_state`.
+    pub state_delta: Option<StateDeltas>,
+    /// State the peers had before `app_state`, never sent itself.
+    #[serde(skip)]
+    pub base_app_state: Option<Box<AppState>>,
 }
 
-// Internal only
-#[derive(Serialize, Deserialize, Clone)]
-pub struct VirtualCompatibilityToolMetadata {
-    r#virtual: bool,
-    virtual_original: String,
+impl Request {
+    pub fn new(r#type: RequestType) -> Self {
+        Self {
+            r#type,
+            task: None,
+            notification: None,
+            steam_restart_required: None,
+            running_games_unknown: None,
+            available_compat_tools: None,
+            app_state: None,
+            settings: None,
+            internal_name: None,
+            provenance: None,
+            verification: None,
+            activity_query: None,
+            activity: None,
+            storage_breakdown: None,
+            app_id: None,
+            refresh: None,
+            startup_progress: None,
+            validation_errors: None,
+            undo_stack: None,
+            error_group: None,
+            app_error: None,
+            session_token: None,
+            subscriptions: None,
+            protocol_version: None,
+            version_mismatch: None,
+            unknown_request_type: None,
+            request_id: None,
+            refresh_scope: None,
+            flavor: None,
+            shader_cache: None,
+            mapping_results: None,
+            config_write: None,
+            mapping_import: None,
+            limit: None,
+            mutation_log: None,
+            tag_name: None,
+            app_ids: None,
+            prefix: None,
+            permissions: None,
+            quick_slots: None,
+            slot: None,
+            plan_kind: None,
+            plan: None,
+            plan_id: None,
+            excluded_action_ids: None,
+            plan_results: None,
+            tool_inspection: None,
+            inspection_progress: None,
+            steam_directory: None,
+            task_id: None,
+            restore_decision: None,
+            virtual_tool: None,
+            update_summary: None,
+            disk_space: None,
+            available_updates: None,
+            compat_data_listing: None,
+            prefix_sizes: None,
+            installed_apps: None,
+            shortcut_id: None,
+            launch_options: None,
+            shortcut_details: None,
+            history_query: None,
+            history_id: None,
+            history: None,
+            revision: None,
+            state_delta: None,
+            base_app_state: None,
+        }
+    }
 }
 
 impl WineCask {
-    pub(crate) async fn task_queue_pop_front(&self) -> Option<Task> {
-        self.app_state.lock().await.task_queue.pop_front()
+    /// Takes the first queued task that doesn't target the same release as a running one.
+    pub(crate) async fn next_runnable_task(&self, running: &HashSet<TaskTarget>) -> Option<Task> {
+        let mut app_state = self.app_state.lock().await;
+        let position = app_state
+            .task_queue
+            .iter()
+            .position(|task| task_target(task).is_none_or(|target| !running.contains(&target)))?;
+        app_state.task_queue.remove(position)
     }
 
-    pub async fn add_to_task_queue(&self, task: Task, peer_map: &PeerMap) {
+    /// Returns whether the task was queued.
+    pub async fn add_to_task_queue(&self, mut task: Task, peer_map: &PeerMap) -> bool {
+        if let Some(install) = &task.install {
+            // Reinstalling a release overwrites the installed copy
+            let reinstalled: Vec<SteamCompatibilityTool> = self
+                .app_state
+                .lock()
+                .await
+                .installed_compatibility_tools
+                .iter()
+                .filter(|tool| {
+                    tool.flavor == install.flavor
+                        && tool
+                            .github_release
+                            .as_ref()
+                            .map(|release| &release.tag_name)
+                            == Some(&install.release.tag_name)
+                })
+                .cloned()
+                .collect();
+            for tool in &reinstalled {
+                if let Some(refusal) = self
+                    .refuse_local_changes_loss(tool, install.accept_local_changes_loss)
+                    .await
+                {
+                    error!("{}", refusal);
+                    self.broadcast_app_state(peer_map).await;
+                    self.broadcast_app_error(peer_map, refusal).await;
+                    return false;
+                }
+            }
+            let tools_directory = self.steam_util.get_steam_compatibility_tools_directory();
+            if let Some(refusal) = detect_filesystem(&tools_directory)
+                .refuse_symlinks(&tools_directory, install.copy_install)
+            {
+                error!("{}", refusal);
+                self.broadcast_app_error(peer_map, refusal).await;
+                return false;
+            }
+        }
+        task.id = next_task_id();
         self.app_state.lock().await.task_queue.push_back(task);
         self.broadcast_app_state(peer_map).await;
+        true
     }
 
     pub async fn remove_or_cancel_from_task_queue(&self, task: Task, peer_map: &PeerMap) {
+        let app_state = self.app_state.lock().await;
+        let task_id = task.install.as_ref().and_then(|install| {
+            app_state
+                .task_queue
+                .iter()
+                .find(|queued| {
+                    queued
+                        .install
+                        .as_ref()
+                        .is_some_and(|queued| queued.release.url == install.release.url)
+                })
+                .map(|queued| queued.id)
+                .or_else(|| {
+                    app_state
+                        .in_progress
+                        .iter()
+                        .find(|in_progress| {
+                            in_progress.flavor == install.flavor
+                                && in_progress.name == install.release.tag_name
+                        })
+                        .map(|in_progress| in_progress.id)
+                })
+        });
+        drop(app_state);
+        match task_id {
+            Some(task_id) => self.cancel_task(peer_map, task_id).await,
+            None => {
+                self.broadcast_notification(
+                    peer_map,
+                    "Not Found: Compatibility tool not found in queue",
+                )
+                .await
+            }
+        }
+    }
+
+    /// Removes a queued task or asks a running install to stop, peers are told with a
+    /// `TaskCancelled` once it did.
+    pub async fn cancel_task(&self, peer_map: &PeerMap, task_id: u64) {
         let mut app_state = self.app_state.lock().await;
-        if let Some(position) = app_state.task_queue.iter().position(|x| {
-            x.install.clone().unwrap().release.url == task.install.clone().unwrap().release.url
-        }) {
+        if let Some(position) = app_state
+            .task_queue
+            .iter()
+            .position(|task| task.id == task_id)
+        {
             app_state.task_queue.remove(position);
             drop(app_state);
             self.broadcast_app_state(peer_map).await;
@@ -99,63 +598,298 @@ impl WineCask {
                 "Cancelled: Compatibility tool installation removed from queue",
             )
             .await;
-        } else if let Some(in_progress) = &mut app_state.in_progress {
+            self.broadcast_task_cancelled(peer_map, task_id).await;
+        } else if let Some(in_progress) = app_state
+            .in_progress
+            .iter_mut()
+            .find(|in_progress| in_progress.id == task_id)
+        {
+            // Only this install stops, the others keep running
             in_progress.state = QueueCompatibilityToolState::Cancelling;
+            in_progress.cancellation.cancel();
+            drop(app_state);
+            self.broadcast_app_state(peer_map).await;
             self.broadcast_notification(
                 peer_map,
                 "Cancelling: Compatibility tool installation in progress",
             )
             .await;
+        } else if app_state
+            .restore_decisions
+            .decide(task_id, RestoreDecision::Abort)
+        {
+            // The restore broadcasts its own outcome
+            drop(app_state);
         } else {
+            drop(app_state);
             self.broadcast_notification(
                 peer_map,
-                "Not Found: Compatibility tool not found in queue",
+                &format!("Not Found: Task {} not found in queue", task_id),
             )
             .await;
         }
     }
 
+    pub(crate) async fn broadcast_task_cancelled(&self, peer_map: &PeerMap, task_id: u64) {
+        let response_new = Request {
+            task_id: Some(task_id),
+            ..Request::new(RequestType::TaskCancelled)
+        };
+        self.broadcast_message(peer_map, &response_new).await;
+    }
+
+    /// Publishes the state of a running install. A cancellation requested meanwhile is kept.
+    pub(crate) async fn set_in_progress(&self, queue_compatibility_tool: &QueueCompatibilityTool) {
+        let mut app_state = self.app_state.lock().await;
+        match app_state
+            .in_progress
+            .iter_mut()
+            .find(|in_progress| in_progress.id == queue_compatibility_tool.id)
+        {
+            Some(in_progress) if in_progress.state == QueueCompatibilityToolState::Cancelling => {}
+            Some(in_progress) => *in_progress = queue_compatibility_tool.clone(),
+            None => app_state.in_progress.push(queue_compatibility_tool.clone()),
+        }
+    }
+
+    pub(crate) async fn clear_in_progress(&self, id: u64) {
+        self.app_state
+            .lock()
+            .await
+            .in_progress
+            .retain(|in_progress| in_progress.id != id);
+    }
+
+    /// Sends what changed in the state since the last broadcast, nothing if nothing did.
     pub async fn broadcast_app_state(&self, peer_map: &PeerMap) {
-        let app_state = self.app_state.lock().await;
+        let app_state = {
+            let mut app_state = self.app_state.lock().await;
+            app_state.broadcast_counters = BROADCAST_STATS.counters();
+            app_state.clone()
+        };
+        // Held while sending so the peers get the revisions in order
+        let mut state_broadcasts = self.state_broadcasts.lock().await;
+        let Some(base_app_state) = state_broadcasts.last.take() else {
+            drop(state_broadcasts);
+            self.broadcast_snapshot(peer_map).await;
+            return;
+        };
+        if serde_json::to_value(&base_app_state).ok() == serde_json::to_value(&app_state).ok() {
+            state_broadcasts.last = Some(base_app_state);
+            return;
+        }
+        state_broadcasts.revision += 1;
+        state_broadcasts.last = Some(app_state.clone());
         let response_new: Request = Request {
-            r#type: RequestType::UpdateState,
-            task: None,
-            notification: None,
-            available_compat_tools: None,
-            app_state: Some(app_state.clone()),
+            app_state: Some(app_state),
+            state_delta: Some(StateDeltas {
+                base_revision: state_broadcasts.revision - 1,
+                revision: state_broadcasts.revision,
+                deltas: Vec::new(),
+            }),
+            base_app_state: Some(Box::new(base_app_state)),
+            ..Request::new(RequestType::StateDelta)
+        };
+        self.broadcast_message(peer_map, &response_new).await;
+    }
+
+    /// Sends the whole state, for peers that just connected or missed a revision.
+    pub async fn broadcast_snapshot(&self, peer_map: &PeerMap) {
+        let app_state = {
+            let mut app_state = self.app_state.lock().await;
+            app_state.broadcast_counters = BROADCAST_STATS.counters();
+            app_state.clone()
+        };
+        let mut state_broadcasts = self.state_broadcasts.lock().await;
+        let unchanged = state_broadcasts.last.as_ref().is_some_and(|last| {
+            serde_json::to_value(last).ok() == serde_json::to_value(&app_state).ok()
+        });
+        if !unchanged {
+            state_broadcasts.revision += 1;
+            state_broadcasts.last = Some(app_state.clone());
+        }
+        let response_new: Request = Request {
+            app_state: Some(app_state),
+            revision: Some(state_broadcasts.revision),
+            ..Request::new(RequestType::UpdateState)
         };
-        drop(app_state);
         self.broadcast_message(peer_map, &response_new).await;
     }
 
     pub async fn broadcast_notification(&self, peer_map: &PeerMap, message: &str) {
         let response_new: Request = Request {
-            r#type: RequestType::Notification,
-            task: None,
             notification: Some(message.to_string()),
-            available_compat_tools: None,
-            app_state: None,
+            ..Request::new(RequestType::Notification)
         };
         self.broadcast_message(peer_map, &response_new).await;
     }
 
-    async fn broadcast_message(&self, peer_map: &PeerMap, response: &Request) {
-        let update = serde_json::to_string(response).unwrap();
-        let message = Message::text(&update);
-        for recp in peer_map.lock().await.values() {
-            match recp.unbounded_send(message.clone()) {
-                Ok(_) => {
-                    info!("Type: {:?}", response.r#type);
-                    debug!("Websocket message sent: {}", &update);
-                }
-                Err(e) => {
-                    error!("Failed to send websocket message: {}", e);
-                }
-            }
+    /// Notification naming `app_ids`, peers that may not read apps don't get it.
+    pub async fn broadcast_app_notification(
+        &self,
+        peer_map: &PeerMap,
+        message: &str,
+        app_ids: Vec<CompatAppId>,
+    ) {
+        let response_new: Request = Request {
+            notification: Some(message.to_string()),
+            app_ids: Some(app_ids),
+            ..Request::new(RequestType::Notification)
+        };
+        self.broadcast_message(peer_map, &response_new).await;
+    }
+
+    pub async fn broadcast_provenance(&self, peer_map: &PeerMap, provenance: Provenance) {
+        let response_new: Request = Request {
+            provenance: Some(provenance),
+            ..Request::new(RequestType::ToolProvenance)
+        };
+        self.broadcast_message(peer_map, &response_new).await;
+    }
+
+    pub async fn broadcast_verification(&self, peer_map: &PeerMap, verification: Verification) {
+        let response_new: Request = Request {
+            verification: Some(verification),
+            ..Request::new(RequestType::Verification)
+        };
+        self.broadcast_message(peer_map, &response_new).await;
+    }
+
+    pub async fn broadcast_activity(&self, peer_map: &PeerMap, activity: Vec<ActivityEvent>) {
+        let response_new: Request = Request {
+            activity: Some(activity),
+            ..Request::new(RequestType::Activity)
+        };
+        self.broadcast_message(peer_map, &response_new).await;
+    }
+
+    pub async fn broadcast_storage_breakdown(
+        &self,
+        peer_map: &PeerMap,
+        storage_breakdown: StorageBreakdown,
+    ) {
+        let response_new: Request = Request {
+            storage_breakdown: Some(storage_breakdown),
+            ..Request::new(RequestType::StorageBreakdown)
+        };
+        self.broadcast_message(peer_map, &response_new).await;
+    }
+
+    pub async fn broadcast_refresh_completed(
+        &self,
+        peer_map: &PeerMap,
+        key: &RefreshKey,
+        request_id: Option<String>,
+    ) {
+        let response_new: Request = Request {
+            request_id,
+            refresh_scope: Some(key.scope),
+            flavor: key.flavor.clone(),
+            ..Request::new(RequestType::RefreshCompleted)
+        };
+        self.broadcast_message(peer_map, &response_new).await;
+    }
+
+    pub async fn broadcast_undo_stack(&self, peer_map: &PeerMap, undo_stack: Vec<UndoEntry>) {
+        let response_new: Request = Request {
+            undo_stack: Some(undo_stack),
+            ..Request::new(RequestType::UndoStack)
+        };
+        self.broadcast_message(peer_map, &response_new).await;
+    }
+
+    /// Reports a malformed request to the peer that sent it, the error carries the same errors for
+    /// display.
+    pub async fn reject_invalid_request(
+        &self,
+        peer_map: &PeerMap,
+        addr: PeerAddr,
+        validation_errors: Vec<ValidationError>,
+    ) {
+        let app_error = AppError::new(
+            AppErrorCode::InvalidRequest,
+            validation_errors
+                .iter()
+                .map(|error| format!("{} expected {}", error.pointer, error.expected))
+                .collect::<Vec<String>>()
+                .join(", "),
+        );
+        error!("{}: {}", addr, app_error);
+        let response_new: Request = Request {
+            validation_errors: Some(validation_errors),
+            ..Request::new(RequestType::ValidationError)
+        };
+        send_to_peer(peer_map, addr, &response_new).await;
+        let response_new: Request = Request {
+            app_error: Some(app_error),
+            ..Request::new(RequestType::Error)
+        };
+        send_to_peer(peer_map, addr, &response_new).await;
+    }
+
+    pub async fn update_settings(&self, peer_map: &PeerMap, settings: Settings) {
+        let validated = validate_access_tokens(&settings.access_tokens)
+            .and_then(|()| validate_proxy_url(settings.proxy_url.as_deref()));
+        if let Err(err) = validated {
+            let app_error = AppError::new(AppErrorCode::InvalidRequest, err.to_string());
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error).await;
+            return;
+        }
+        if let Err(err) = settings.save() {
+            let app_error = AppError::from(err).context("Failed to save settings");
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error).await;
+            return;
         }
+        set_proxy_url(settings.proxy_url.clone());
+        let write_steam_config = settings.feature_flags.is_enabled(Feature::WriteSteamConfig);
+        self.app_state.lock().await.settings = settings;
+        // Skipped releases are part of the flavor summaries
+        self.update_compatibility_tools_and_available_flavors()
+            .await;
+        self.update_quick_slot_states().await;
+        self.broadcast_app_state(peer_map).await;
+        // Mappings kept while writing Steam's config was turned off
+        if write_steam_config {
+            self.apply_pending_mappings(peer_map).await;
+        }
+    }
+
+    /// Persists which Steam installation to operate on, the backend switches to it on its next
+    /// start.
+    pub async fn select_steam_installation(&self, peer_map: &PeerMap, steam_directory: String) {
+        let app_state = self.app_state.lock().await;
+        if !app_state.steam_installations.contains(&steam_directory) {
+            drop(app_state);
+            let app_error = AppError::new(
+                AppErrorCode::NotFound,
+                format!("{} isn't a Steam installation", steam_directory),
+            );
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error).await;
+            return;
+        }
+        let mut settings = app_state.settings.clone();
+        drop(app_state);
+        info!("Selected the Steam installation at {}", steam_directory);
+        settings.steam_directory = Some(steam_directory.clone());
+        self.update_settings(peer_map, settings).await;
+        // Saving the settings may have failed
+        if self.app_state.lock().await.settings.steam_directory == Some(steam_directory) {
+            self.broadcast_notification(
+                peer_map,
+                "Switching Steam installations applies after the plugin restarts",
+            )
+            .await;
+        }
+    }
+
+    async fn broadcast_message(&self, peer_map: &PeerMap, response: &Request) {
+        broadcast_to_peers(peer_map, response).await;
     }
 
-    fn get_used_by_games(&self, display_name: &str, internal_name: &str) -> Vec<String> {
+    pub fn get_used_by_games(&self, display_name: &str, internal_name: &str) -> Vec<String> {
         let compat_tools_mapping = self
             .steam_util
             .get_compatibility_tools_mappings()
@@ -173,15 +907,11 @@ impl WineCask {
         let used_by_games: Vec<String> = installed_games
             .iter()
             .filter(|game| {
-                compat_tools_mapping.contains_key(&game.app_id)
-                    && (compat_tools_mapping
-                        .get(&game.app_id)
-                        .unwrap()
-                        .eq(display_name)
-                        || compat_tools_mapping
-                            .get(&game.app_id)
-                            .unwrap()
-                            .eq(internal_name))
+                compat_tools_mapping
+                    .get(&CompatAppId::from(game.app_id))
+                    .is_some_and(|compat_tool| {
+                        compat_tool.eq(display_name) || compat_tool.eq(internal_name)
+                    })
             })
             .map(|game| game.name.clone())
             .collect();
@@ -192,10 +922,187 @@ impl WineCask {
         for compat_tool in &mut self.app_state.lock().await.installed_compatibility_tools {
             compat_tool.used_by_games =
                 self.get_used_by_games(&compat_tool.display_name, &compat_tool.internal_name);
+            compat_tool.used_by_apps = self
+                .steam_util
+                .get_applications_using_tool(&compat_tool.internal_name);
         }
+        self.update_compatibility_tool_mappings(ActivitySource::External)
+            .await;
         self.broadcast_app_state(peer_map).await;
     }
 
+    /// Sends back every app with an appmanifest, including the ones being installed or uninstalled.
+    pub async fn get_installed_apps(&self, peer_map: &PeerMap) {
+        let steam_util = self.steam_util.clone();
+        let installed_apps = tokio::task::spawn_blocking(move || steam_util.list_installed_apps())
+            .await
+            .unwrap();
+        match installed_apps {
+            Ok(installed_apps) => {
+                self.broadcast_message(
+                    peer_map,
+                    &Request {
+                        installed_apps: Some(installed_apps),
+                        ..Request::new(RequestType::InstalledApps)
+                    },
+                )
+                .await;
+            }
+            Err(err) => {
+                let app_error = AppError::from(err).context("Failed to list installed apps");
+                error!("{}", app_error);
+                self.broadcast_app_error(peer_map, app_error).await;
+            }
+        }
+    }
+
+    pub async fn get_shortcut_details(&self, peer_map: &PeerMap, shortcut_id: ShortcutId) {
+        let steam_util = self.steam_util.clone();
+        let details =
+            tokio::task::spawn_blocking(move || steam_util.get_shortcut_details(shortcut_id))
+                .await
+                .unwrap();
+        self.send_shortcut_details(peer_map, details, "Failed to read shortcut")
+            .await;
+    }
+
+    pub async fn set_shortcut_launch_options(
+        &self,
+        peer_map: &PeerMap,
+        shortcut_id: ShortcutId,
+        launch_options: String,
+    ) {
+        let steam_util = self.steam_util.clone();
+        let details = tokio::task::spawn_blocking(move || {
+            steam_util.set_shortcut_launch_options(shortcut_id, &launch_options)
+        })
+        .await
+        .unwrap();
+        self.send_shortcut_details(peer_map, details, "Failed to set launch options")
+            .await;
+    }
+
+    async fn send_shortcut_details(
+        &self,
+        peer_map: &PeerMap,
+        details: Result<Vec<ShortcutDetails>, SteamUtilError>,
+        context: &str,
+    ) {
+        match details {
+            Ok(details) => {
+                self.broadcast_message(
+                    peer_map,
+                    &Request {
+                        shortcut_details: Some(details),
+                        ..Request::new(RequestType::ShortcutDetails)
+                    },
+                )
+                .await;
+            }
+            Err(err) => {
+                let app_error = AppError::from(err).context(context);
+                error!("{}", app_error);
+                self.broadcast_app_error(peer_map, app_error).await;
+            }
+        }
+    }
+
+    pub async fn update_compatibility_tool_mappings(&self, source: ActivitySource) {
+        let compat_tools_mapping = self.steam_util.get_compatibility_tools_mappings();
+        // An unreadable config.vdf doesn't mean every mapping was removed
+        let mappings_readable = compat_tools_mapping.is_ok();
+        let compat_tools_mapping = compat_tools_mapping.unwrap_or_else(|err| {
+            warn!("Failed to get compatibility tools mappings: {}", err);
+            HashMap::new()
+        });
+        let installed_games: HashMap<AppId, String> = self
+            .steam_util
+            .list_installed_games()
+            .unwrap_or_else(|err| {
+                warn!("Failed to get list of installed games: {}", err);
+                Vec::new()
+            })
+            .into_iter()
+            .map(|game| (game.app_id, game.name))
+            .collect();
+
+        // Steam keeps the names of apps without a manifest in appinfo.vdf as long as it has seen
+        // them, the others can only be named by asking the Steam store. Neither knows shortcuts.
+        let unknown_app_ids: Vec<AppId> = compat_tools_mapping
+            .keys()
+            .filter_map(|compat_app_id| compat_app_id.app_id())
+            .filter(|app_id| !installed_games.contains_key(app_id))
+            .collect();
+        let steam_util = self.steam_util.clone();
+        let (appinfo_names, unknown_app_ids) = tokio::task::spawn_blocking(move || {
+            let appinfo_names: HashMap<AppId, String> = unknown_app_ids
+                .iter()
+                .filter_map(|app_id| Some((*app_id, steam_util.lookup_app_name(*app_id)?)))
+                .collect();
+            let unknown_app_ids: Vec<AppId> = unknown_app_ids
+                .into_iter()
+                .filter(|app_id| !appinfo_names.contains_key(app_id))
+                .collect();
+            (appinfo_names, unknown_app_ids)
+        })
+        .await
+        .unwrap();
+        let resolve_app_names = self.app_state.lock().await.settings.resolve_app_names;
+        let resolved_names = if resolve_app_names && !unknown_app_ids.is_empty() {
+            let app_name_resolver = self.app_name_resolver.clone();
+            tokio::task::spawn_blocking(move || {
+                app_name_resolver.lock().unwrap().resolve(&unknown_app_ids)
+            })
+            .await
+            .unwrap_or_default()
+        } else {
+            HashMap::new()
+        };
+
+        // appinfo.vdf is large, it's only parsed again after Steam updated it
+        let mut steam_overrides = self.app_state.lock().await.steam_overrides.clone();
+        let appinfo = self.steam_util.get_appinfo_path();
+        let steam_overrides = tokio::task::spawn_blocking(move || {
+            steam_overrides.refresh(&appinfo);
+            steam_overrides
+        })
+        .await
+        .unwrap();
+
+        let mut compatibility_tool_mappings: Vec<CompatibilityToolMapping> = compat_tools_mapping
+            .into_iter()
+            .map(|(app_id, compatibility_tool)| {
+                let name = app_id.app_id().and_then(|app_id| {
+                    installed_games
+                        .get(&app_id)
+                        .or(appinfo_names.get(&app_id))
+                        .or(resolved_names.get(&app_id))
+                        .cloned()
+                });
+                CompatibilityToolMapping {
+                    app_id,
+                    unresolved: name.is_none(),
+                    name: name.unwrap_or_else(|| app_id.to_string()),
+                    compatibility_tool,
+                    steam_override: app_id
+                        .app_id()
+                        .and_then(|app_id| steam_overrides.get(app_id).cloned()),
+                    quick_slots: None,
+                }
+            })
+            .collect();
+        compatibility_tool_mappings.sort_by_key(|mapping| mapping.app_id);
+        let mut app_state = self.app_state.lock().await;
+        app_state.compatibility_tool_mappings = compatibility_tool_mappings;
+        app_state.steam_overrides = steam_overrides;
+        drop(app_state);
+        self.update_quick_slot_states().await;
+        self.record_mapping_activity(source).await;
+        if mappings_readable {
+            self.invalidate_undo_entries().await;
+        }
+    }
+
     pub fn list_compatibility_tools(&self) -> Option<Vec<SteamCompatibilityTool>> {
         let compat_tools = self
             .steam_util
@@ -207,21 +1114,59 @@ impl WineCask {
         for compat_tool in &compat_tools {
             let used_by_games: Vec<String> =
                 self.get_used_by_games(&compat_tool.display_name, &compat_tool.internal_name);
-            //let metadata = self.lookup_virtual_compatibility_tool_metadata(compat_tool);
             compatibility_tools.push(SteamCompatibilityTool {
                 path: compat_tool.path.to_string_lossy().to_string(),
                 //directory_name: compat_tool.directory_name.to_string(),
                 display_name: compat_tool.display_name.to_string(),
                 internal_name: compat_tool.internal_name.to_string(),
                 used_by_games,
+                used_by_apps: self
+                    .steam_util
+                    .get_applications_using_tool(&compat_tool.internal_name),
                 flavor: CompatibilityToolFlavor::Unknown,
                 github_release: None,
+                modified_since_install: None,
+                inspection: None,
+                managed: false,
+                version: None,
+                official: false,
                 requires_restart: false,
-                //r#virtual: metadata.r#virtual,
-                //virtual_original: metadata.virtual_original,
+                supports_32bit: compat_tool.supports_32bit,
+                r#virtual: virtual_tool_status(&compat_tool.path),
             })
         }
 
+        // Listed so apps can be mapped to them, Steam keeps them up to date itself
+        let official_tools = self
+            .steam_util
+            .list_official_proton_tools()
+            .unwrap_or_else(|err| {
+                warn!("Failed to list official Proton releases: {}", err);
+                Vec::new()
+            });
+        for official_tool in official_tools {
+            compatibility_tools.push(SteamCompatibilityTool {
+                path: official_tool.path.to_string_lossy().to_string(),
+                used_by_games: self
+                    .get_used_by_games(&official_tool.display_name, &official_tool.internal_name),
+                used_by_apps: self
+                    .steam_util
+                    .get_applications_using_tool(&official_tool.internal_name),
+                display_name: official_tool.display_name,
+                internal_name: official_tool.internal_name,
+                flavor: CompatibilityToolFlavor::Unknown,
+                github_release: None,
+                modified_since_install: None,
+                inspection: None,
+                managed: false,
+                version: official_tool.version,
+                official: true,
+                requires_restart: false,
+                supports_32bit: true,
+                r#virtual: None,
+            });
+        }
+
         Some(compatibility_tools)
     }
 
@@ -234,12 +1179,17 @@ impl WineCask {
         app_state.available_compat_tools = Some(available_compat_tools);
         drop(app_state);
         self.sync_backend_with_installed_compat_tools().await;
+        // Our own installs and uninstalls are recorded as they happen, anything else was external
+        self.record_tool_activity(ActivitySource::External).await;
+        self.check_steam_pickup(peer_map).await;
         self.broadcast_app_state(peer_map).await;
     }
 
     pub async fn sync_backend_with_installed_compat_tools(&self) {
         let mut app_state = self.app_state.lock().await;
         app_state.installed_compatibility_tools = self.list_compatibility_tools().unwrap();
+        app_state.installed_runners = self.list_runners();
+        app_state.installed_components = self.list_components();
 
         let available_compat_tools = app_state.available_compat_tools.clone().unwrap();
 
@@ -249,19 +1199,99 @@ impl WineCask {
                 .map(|tool| (tool.str_tool_name.clone(), tool))
                 .collect();
 
-        for tool in &mut app_state.installed_compatibility_tools {
+        let state = &mut *app_state;
+        for tool in &mut state.installed_compatibility_tools {
             tool.requires_restart = !available_tools_map.contains_key(&tool.internal_name);
+            // Checking is too slow for every listing, reuse the last result instead
+            tool.modified_since_install = state
+                .local_changes
+                .last(&tool.internal_name)
+                .filter(|local_changes| !local_changes.is_empty())
+                .cloned();
         }
+        apply_inspections(&mut state.installed_compatibility_tools, &state.tool_inspector);
         drop(app_state);
         self.update_compatibility_tools_and_available_flavors()
             .await;
+        self.update_stranded_compatibility_tools().await;
+        self.update_filesystem_profiles().await;
+        // Removed tools leave their slots dangling
+        self.update_quick_slot_states().await;
     }
 
-    pub async fn check_for_flavor_updates(&self, peer_map: &PeerMap, renew_cache: bool) {
+    pub async fn check_for_flavor_updates(&self, peer_map: &PeerMap, cache_use: CacheUse) {
         self.app_state.lock().await.updater_state = UpdaterState::Checking;
         self.broadcast_app_state(peer_map).await;
-        self.app_state.lock().await.flavors = self.get_flavors(renew_cache).await;
+        self.check_clock(peer_map).await;
+        self.app_state.lock().await.flavors = self.get_flavors(peer_map, cache_use).await;
+        self.reassign_naming_schemes(peer_map).await;
+        self.clear_superseded_skips(peer_map).await;
         self.app_state.lock().await.updater_state = UpdaterState::Idle;
         self.broadcast_app_state(peer_map).await;
     }
 }
+
+/// Sends a message to every connected peer, also used before the `WineCask` exists.
+///
+/// Each peer only gets what its permissions allow, filtered once per distinct permission set.
+/// Critical messages that don't fit into a peer's outbox wait for room after the peer map is
+/// released, peers that don't make room in time are evicted.
+pub async fn broadcast_to_peers(peer_map: &PeerMap, response: &Request) {
+    let peers = peer_map.lock().await;
+    let mut blocked: Vec<(PeerAddr, Arc<Outbox>, Message)> = Vec::new();
+    let mut closed: Vec<PeerAddr> = Vec::new();
+    type Filtered<'a> = (&'a PermissionSet, &'a SubscriptionSet, Option<(String, Message)>);
+    let mut filtered: Vec<Filtered> = Vec::new();
+    for (addr, peer) in peers.iter() {
+        let index = match filtered.iter().position(|(permissions, subscriptions, _)| {
+            **permissions == peer.permissions && **subscriptions == peer.subscriptions
+        }) {
+            Some(index) => index,
+            None => {
+                // Subscriptions first, the permissions filter diffs what is left into deltas
+                let message = filter_for_subscriptions(response, &peer.subscriptions)
+                    .and_then(|response| filter_for_peer(&response, &peer.permissions))
+                    .map(|response| {
+                        let update = serde_json::to_string(&response).unwrap();
+                        let message = Message::text(&update);
+                        (update, message)
+                    });
+                filtered.push((&peer.permissions, &peer.subscriptions, message));
+                filtered.len() - 1
+            }
+        };
+        let Some((update, message)) = &filtered[index].2 else {
+            continue;
+        };
+        match peer
+            .outbox
+            .try_push(response.r#type.clone(), message.clone())
+        {
+            Delivery::Full => blocked.push((*addr, peer.outbox.clone(), message.clone())),
+            Delivery::Closed => {
+                debug!("{} disconnected, not sending {:?}", addr, response.r#type);
+                closed.push(*addr);
+            }
+            _ => {
+                info!("Type: {:?}", response.r#type);
+                debug!("Websocket message queued: {}", &update);
+            }
+        }
+    }
+    drop(peers);
+    for addr in closed {
+        remove_peer(peer_map, addr).await;
+    }
+
+    let deliveries = future::join_all(blocked.into_iter().map(
+        |(addr, outbox, message)| async move {
+            (addr, outbox.push(response.r#type.clone(), message).await)
+        },
+    ))
+    .await;
+    for (addr, delivery) in deliveries {
+        if delivery == Delivery::Full {
+            evict_peer(peer_map, addr).await;
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use crate::github_util::GitHubUtilError;
+use crate::steam_util::SteamUtilError;
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, WineCask};
+use crate::wine_cask::signature::SignatureError;
+use crate::PeerMap;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::{fmt, io};
+
+/// No space left on the device.
+const ENOSPC: i32 = 28;
+/// The user's disk quota is used up.
+const EDQUOT: i32 = 122;
+
+/// What kind of failure an `AppError` is, peers pick what to suggest by it.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum AppErrorCode {
+    NetworkError,
+    DiskFull,
+    ChecksumMismatch,
+    /// The archive isn't signed with the key its flavor signs releases with.
+    SignatureInvalid,
+    SteamNotFound,
+    VdfParse,
+    Internal,
+    /// The peer didn't authenticate with the session token.
+    Unauthenticated,
+    /// The peer speaks another major version of the protocol.
+    IncompatibleVersion,
+    UnknownRequestType,
+    /// A game using what the operation would change is running.
+    OperationBlockedGameRunning,
+    /// Steam is running and would overwrite what the operation writes.
+    OperationBlockedSteamRunning,
+    /// The backend is still starting and can't handle the request yet.
+    NotReady,
+    /// The request is malformed or refers to something that can't be used.
+    InvalidRequest,
+    /// The peer's permissions don't allow the request.
+    Forbidden,
+    /// The request needs a feature that is turned off.
+    FeatureDisabled,
+    /// A tool, app or other thing the request refers to doesn't exist.
+    NotFound,
+    /// Apps are still mapped to the tool the operation would remove.
+    ToolInUse,
+    /// The tool was modified since it was installed and the operation would discard the changes.
+    LocalChanges,
+    /// The target filesystem can't store what is being installed.
+    UnsupportedFilesystem,
+    /// The tool is managed by Steam and has to be changed from the Steam library.
+    ManagedBySteam,
+    /// The monthly network cap doesn't leave room for the download.
+    NetworkCapReached,
+}
+
+/// A failure sent to the peers as an `Error` message.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct AppError {
+    pub code: AppErrorCode,
+    pub message: String,
+    /// The task that failed, if the failure ended one.
+    pub task_id: Option<u64>,
+}
+
+impl AppError {
+    pub fn new(code: AppErrorCode, message: impl Into<String>) -> Self {
+        AppError {
+            code,
+            message: message.into(),
+            task_id: None,
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        AppError::new(AppErrorCode::Internal, message)
+    }
+
+    pub fn for_task(self, task_id: u64) -> Self {
+        AppError {
+            task_id: Some(task_id),
+            ..self
+        }
+    }
+
+    /// Prefixes the message with what was being done, e.g. `Failed to write download`.
+    pub fn context(self, context: impl Display) -> Self {
+        AppError {
+            message: format!("{}: {}", context, self.message),
+            ..self
+        }
+    }
+}
+
+impl Display for AppError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl Error for AppError {}
+
+impl From<SteamUtilError> for AppError {
+    fn from(err: SteamUtilError) -> AppError {
+        let code = match err {
+            SteamUtilError::HomeDirectoryNotFound
+            | SteamUtilError::SteamDirectoryNotFound
+            | SteamUtilError::SteamAppsDirectoryNotFound
+            | SteamUtilError::LibraryFoldersVdfNotFound
+            | SteamUtilError::SteamConfigVdfNotFound
+            | SteamUtilError::RegistryVdfNotFound
+            | SteamUtilError::ShortcutsVdfNotFound
+            | SteamUtilError::ShortcutNotFound(_) => AppErrorCode::SteamNotFound,
+            SteamUtilError::VdfParsingError(_) | SteamUtilError::VdfMissingEntry(_) => {
+                AppErrorCode::VdfParse
+            }
+            SteamUtilError::CompatibilityToolsDirectoryCreationFailed
+            | SteamUtilError::SteamConfigVdfWriteFailed(_)
+            | SteamUtilError::ShortcutsVdfWriteFailed(_)
+            | SteamUtilError::SteamRestartFailed(_) => AppErrorCode::Internal,
+            SteamUtilError::GameRunning(_) => AppErrorCode::OperationBlockedGameRunning,
+            SteamUtilError::SteamRunning => AppErrorCode::OperationBlockedSteamRunning,
+        };
+        AppError::new(code, err.to_string())
+    }
+}
+
+impl From<io::Error> for AppError {
+    fn from(err: io::Error) -> AppError {
+        let code = match (err.raw_os_error(), err.kind()) {
+            (Some(ENOSPC | EDQUOT), _) => AppErrorCode::DiskFull,
+            (
+                _,
+                io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::NotConnected
+                | io::ErrorKind::TimedOut,
+            ) => AppErrorCode::NetworkError,
+            _ => AppErrorCode::Internal,
+        };
+        AppError::new(code, err.to_string())
+    }
+}
+
+impl From<GitHubUtilError> for AppError {
+    fn from(err: GitHubUtilError) -> AppError {
+        let code = match err {
+            GitHubUtilError::RequestError(_)
+            | GitHubUtilError::ResponseError(_)
+            | GitHubUtilError::RateLimited { .. } => AppErrorCode::NetworkError,
+            GitHubUtilError::JsonParsingError(_) => AppErrorCode::Internal,
+        };
+        AppError::new(code, err.to_string())
+    }
+}
+
+impl From<SignatureError> for AppError {
+    fn from(err: SignatureError) -> AppError {
+        match err {
+            SignatureError::Invalid(message) => {
+                AppError::new(AppErrorCode::SignatureInvalid, message)
+            }
+            SignatureError::Unverifiable(message) => {
+                AppError::new(AppErrorCode::NetworkError, message)
+            }
+        }
+    }
+}
+
+impl WineCask {
+    pub async fn broadcast_app_error(&self, peer_map: &PeerMap, app_error: AppError) {
+        let response_new: Request = Request {
+            app_error: Some(app_error),
+            ..Request::new(RequestType::Error)
+        };
+        broadcast_to_peers(peer_map, &response_new).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_are_mapped_to_codes() {
+        let disk_full = AppError::from(io::Error::from_raw_os_error(ENOSPC))
+            .context("Failed to write download")
+            .for_task(3);
+        assert_eq!(disk_full.code, AppErrorCode::DiskFull);
+        assert!(disk_full.message.starts_with("Failed to write download: "));
+        assert_eq!(disk_full.task_id, Some(3));
+        assert_eq!(
+            AppError::from(io::Error::from(io::ErrorKind::ConnectionReset)).code,
+            AppErrorCode::NetworkError
+        );
+        assert_eq!(
+            AppError::from(io::Error::from(io::ErrorKind::PermissionDenied)).code,
+            AppErrorCode::Internal
+        );
+
+        assert_eq!(
+            AppError::from(SteamUtilError::SteamDirectoryNotFound).code,
+            AppErrorCode::SteamNotFound
+        );
+        assert_eq!(
+            AppError::from(SteamUtilError::SteamRunning).code,
+            AppErrorCode::OperationBlockedSteamRunning
+        );
+        assert_eq!(
+            AppError::from(SteamUtilError::VdfParsingError("Unexpected }".to_string())),
+            AppError::new(
+                AppErrorCode::VdfParse,
+                "Failed to parse VDF file: Unexpected }"
+            )
+        );
+    }
+
+    #[test]
+    fn test_errors_are_sent_with_their_task() {
+        let app_error =
+            AppError::new(AppErrorCode::ChecksumMismatch, "Checksum mismatch").for_task(7);
+        assert_eq!(
+            serde_json::to_value(&app_error).unwrap(),
+            serde_json::json!({
+                "code": "ChecksumMismatch",
+                "message": "Checksum mismatch",
+                "task_id": 7,
+            })
+        );
+    }
+}
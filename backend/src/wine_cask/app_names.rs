@@ -0,0 +1,261 @@
+use crate::app_id::AppId;
+use crate::runtime_directory;
+use crate::wine_cask::proxy::ProxyConfig;
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// Number of unknown apps looked up per resolution pass, the store API allows roughly 200 requests every 5 minutes.
+const BATCH_SIZE: usize = 10;
+const MAX_RETRIES: u32 = 3;
+
+#[derive(Debug, PartialEq)]
+pub enum AppDetailsError {
+    RateLimited,
+    RequestError(String),
+}
+
+/// Looks up app names on a remote service, injectable so tests can use canned responses.
+pub trait AppDetailsResponder: Send {
+    /// Returns the name of the app, or `None` if the service doesn't know the app id.
+    fn fetch_name(&self, app_id: AppId) -> Result<Option<String>, AppDetailsError>;
+}
+
+/// Queries the public Steam store appdetails endpoint.
+///
+/// Only the app id is sent and no Steam account information is attached to the request.
+pub struct SteamStoreResponder;
+
+#[derive(Deserialize)]
+struct AppDetails {
+    success: bool,
+    data: Option<AppDetailsData>,
+}
+
+#[derive(Deserialize)]
+struct AppDetailsData {
+    name: String,
+}
+
+impl AppDetailsResponder for SteamStoreResponder {
+    fn fetch_name(&self, app_id: AppId) -> Result<Option<String>, AppDetailsError> {
+        let url = format!(
+            "https://store.steampowered.com/api/appdetails?appids={}&filters=basic",
+            app_id
+        );
+        // The blocking client must be created and dropped outside of the async runtime
+        let proxy = ProxyConfig::current();
+        let client = proxy
+            .blocking_client_builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|err| AppDetailsError::RequestError(err.to_string()))?;
+        let response = client
+            .get(url)
+            .send()
+            .map_err(|err| AppDetailsError::RequestError(proxy.describe_error(&err)))?;
+
+        if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Err(AppDetailsError::RateLimited);
+        }
+        if !response.status().is_success() {
+            return Err(AppDetailsError::RequestError(format!(
+                "Failed to fetch app details: {}",
+                response.status()
+            )));
+        }
+
+        let response_text = response
+            .text()
+            .map_err(|err| AppDetailsError::RequestError(err.to_string()))?;
+        let mut details: HashMap<String, AppDetails> = serde_json::from_str(&response_text)
+            .map_err(|err| AppDetailsError::RequestError(err.to_string()))?;
+        Ok(details
+            .remove(&app_id.to_string())
+            .filter(|details| details.success)
+            .and_then(|details| details.data)
+            .map(|data| data.name))
+    }
+}
+
+/// Resolves names for apps without a local manifest, caching answers on disk indefinitely.
+pub struct AppNameResolver {
+    responder: Box<dyn AppDetailsResponder>,
+    cache_file: PathBuf,
+    // `None` records that the service doesn't know the app, so we don't ask again
+    cache: HashMap<AppId, Option<String>>,
+    backoff: Duration,
+}
+
+impl AppNameResolver {
+    pub fn new(responder: Box<dyn AppDetailsResponder>, cache_file: PathBuf) -> Self {
+        let cache = fs::read_to_string(&cache_file)
+            .ok()
+            .and_then(|string| serde_json::from_str(&string).ok())
+            .unwrap_or_default();
+        Self {
+            responder,
+            cache_file,
+            cache,
+            backoff: Duration::from_secs(2),
+        }
+    }
+
+    pub fn with_steam_store() -> Self {
+        Self::new(
+            Box::new(SteamStoreResponder),
+            runtime_directory().join("app_names_cache.json"),
+        )
+    }
+
+    /// Returns the cached name without touching the network.
+    pub fn cached_name(&self, app_id: AppId) -> Option<String> {
+        self.cache.get(&app_id).cloned().flatten()
+    }
+
+    /// Every name resolved so far.
+    pub fn cached_names(&self) -> HashMap<AppId, String> {
+        self.cache
+            .iter()
+            .filter_map(|(app_id, name)| Some((*app_id, name.clone()?)))
+            .collect()
+    }
+
+    /// Resolves the given app ids, looking up at most one batch of uncached apps per call.
+    pub fn resolve(&mut self, app_ids: &[AppId]) -> HashMap<AppId, String> {
+        let uncached: Vec<AppId> = app_ids
+            .iter()
+            .filter(|app_id| !self.cache.contains_key(app_id))
+            .copied()
+            .take(BATCH_SIZE)
+            .collect();
+
+        let mut updated = false;
+        'batch: for app_id in uncached {
+            let mut retries = 0;
+            loop {
+                match self.responder.fetch_name(app_id) {
+                    Ok(name) => {
+                        self.cache.insert(app_id, name);
+                        updated = true;
+                        break;
+                    }
+                    Err(AppDetailsError::RateLimited) if retries < MAX_RETRIES => {
+                        let delay = self.backoff * 2u32.pow(retries);
+                        warn!(
+                            "Rate limited while resolving app names, retrying in {:?}",
+                            delay
+                        );
+                        thread::sleep(delay);
+                        retries += 1;
+                    }
+                    Err(err) => {
+                        warn!("Unable to resolve app name for {}: {:?}", app_id, err);
+                        break 'batch;
+                    }
+                }
+            }
+        }
+
+        if updated {
+            info!("Resolved app names, {} entries cached", self.cache.len());
+            if let Ok(json) = serde_json::to_string(&self.cache) {
+                if let Err(err) = fs::write(&self.cache_file, json) {
+                    warn!("Failed to write app names cache: {}", err);
+                }
+            }
+        }
+
+        app_ids
+            .iter()
+            .filter_map(|app_id| self.cached_name(*app_id).map(|name| (*app_id, name)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    struct CannedResponder {
+        names: HashMap<AppId, String>,
+        rate_limited_calls: usize,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl AppDetailsResponder for CannedResponder {
+        fn fetch_name(&self, app_id: AppId) -> Result<Option<String>, AppDetailsError> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            if call < self.rate_limited_calls {
+                return Err(AppDetailsError::RateLimited);
+            }
+            Ok(self.names.get(&app_id).cloned())
+        }
+    }
+
+    fn elden_ring() -> AppId {
+        AppId::new(1245620).unwrap()
+    }
+
+    fn delisted() -> AppId {
+        AppId::new(2000000).unwrap()
+    }
+
+    fn canned_resolver(
+        cache_file: PathBuf,
+        rate_limited_calls: usize,
+    ) -> (AppNameResolver, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let responder = CannedResponder {
+            names: HashMap::from([(elden_ring(), "ELDEN RING".to_string())]),
+            rate_limited_calls,
+            calls: calls.clone(),
+        };
+        let mut resolver = AppNameResolver::new(Box::new(responder), cache_file);
+        resolver.backoff = Duration::ZERO;
+        (resolver, calls)
+    }
+
+    #[test]
+    fn test_resolve_caches_on_disk() {
+        let temp_dir = tempdir().unwrap();
+        let cache_file = temp_dir.path().join("app_names_cache.json");
+
+        let (mut resolver, calls) = canned_resolver(cache_file.clone(), 0);
+        let names = resolver.resolve(&[elden_ring(), delisted()]);
+        assert_eq!(names.get(&elden_ring()).unwrap(), "ELDEN RING");
+        assert!(!names.contains_key(&delisted()));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Both the known and the unknown answer are cached
+        let (mut resolver, calls) = canned_resolver(cache_file, 0);
+        let names = resolver.resolve(&[elden_ring(), delisted()]);
+        assert_eq!(names.len(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_resolve_backs_off_when_rate_limited() {
+        let temp_dir = tempdir().unwrap();
+        let (mut resolver, calls) =
+            canned_resolver(temp_dir.path().join("app_names_cache.json"), 2);
+        let names = resolver.resolve(&[elden_ring()]);
+        assert_eq!(names.get(&elden_ring()).unwrap(), "ELDEN RING");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_resolve_gives_up_after_max_retries() {
+        let temp_dir = tempdir().unwrap();
+        let (mut resolver, _) = canned_resolver(temp_dir.path().join("app_names_cache.json"), 10);
+        assert!(resolver.resolve(&[elden_ring()]).is_empty());
+        assert_eq!(resolver.cached_name(elden_ring()), None);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::install_target::{components_directory, runner_directories, user_home};
+use crate::wine_cask::recursive_delete_dir_entry;
+use log::{info, warn};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the directories installs are put together in, next to the installed tools. Steam
+/// only lists the directories right below `compatibilitytools.d`, so it never sees a tool before
+/// it's complete.
+pub const TEMP_DIRECTORY_PREFIX: &str = ".wine-cellar-tmp-";
+/// Directory in a temporary directory the tool being reinstalled is moved to.
+const REPLACED_DIRECTORY: &str = ".replaced";
+
+/// Directory an install is put together in before it's renamed into `install_directory`.
+pub fn temp_directory(install_directory: &Path, id: u64) -> PathBuf {
+    install_directory.join(format!("{}{}", TEMP_DIRECTORY_PREFIX, id))
+}
+
+/// Renames the complete tool `staged` to `destination`, replacing the tool installed there.
+///
+/// The replaced tool is moved into `temp_directory` first and only deleted once the new one is
+/// in place, so a crash at any point leaves one of them complete for `sweep_temp_directories`.
+pub fn replace_directory(
+    staged: &Path,
+    destination: &Path,
+    temp_directory: &Path,
+) -> io::Result<()> {
+    let replaced = fs::symlink_metadata(destination).is_ok();
+    let aside = temp_directory
+        .join(REPLACED_DIRECTORY)
+        .join(destination.file_name().unwrap_or_default());
+    if replaced {
+        fs::create_dir_all(aside.parent().unwrap())?;
+        fs::rename(destination, &aside)?;
+    }
+    if let Err(err) = fs::rename(staged, destination) {
+        if replaced {
+            fs::rename(&aside, destination)?;
+        }
+        return Err(err);
+    }
+    if replaced {
+        recursive_delete_dir_entry(&aside)?;
+    }
+    Ok(())
+}
+
+/// Removes the temporary directories of installs that were interrupted, restoring a replaced
+/// tool if the crash came before its replacement was in place.
+pub fn sweep_temp_directories(install_directory: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(install_directory)? {
+        let entry = entry?;
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(TEMP_DIRECTORY_PREFIX)
+        {
+            continue;
+        }
+        let replaced = entry.path().join(REPLACED_DIRECTORY);
+        for tool in fs::read_dir(&replaced).into_iter().flatten() {
+            let tool = tool?;
+            let destination = install_directory.join(tool.file_name());
+            if fs::symlink_metadata(&destination).is_err() {
+                info!(
+                    "Restoring {} replaced by an interrupted install",
+                    destination.display()
+                );
+                fs::rename(tool.path(), destination)?;
+            }
+        }
+        info!(
+            "Removing {} left by an interrupted install",
+            entry.path().display()
+        );
+        recursive_delete_dir_entry(&entry.path())?;
+    }
+    Ok(())
+}
+
+impl WineCask {
+    /// Every directory tools are installed to, whether it exists or not.
+    pub fn install_directories(&self) -> Vec<PathBuf> {
+        let mut directories = vec![self.steam_util.get_steam_compatibility_tools_directory()];
+        if let Some(home) = user_home() {
+            directories.extend(runner_directories(&home));
+        }
+        if let Ok(entries) = fs::read_dir(components_directory()) {
+            directories.extend(entries.filter_map(Result::ok).map(|entry| entry.path()));
+        }
+        directories
+    }
+
+    pub fn sweep_install_temp_directories(&self) {
+        for directory in self.install_directories() {
+            if !directory.is_dir() {
+                continue;
+            }
+            if let Err(err) = sweep_temp_directories(&directory) {
+                warn!(
+                    "Failed to sweep temporary directories in {}: {}",
+                    directory.display(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_reinstall_replaces_the_installed_tool() {
+        let install_directory = tempdir().unwrap();
+        let destination = install_directory.path().join("GE-Proton9-21");
+        fs::create_dir_all(&destination).unwrap();
+        fs::write(destination.join("version"), "old").unwrap();
+        let temp_dir = temp_directory(install_directory.path(), 1);
+        let staged = temp_dir.join("GE-Proton9-21");
+        fs::create_dir_all(&staged).unwrap();
+        fs::write(staged.join("version"), "new").unwrap();
+
+        replace_directory(&staged, &destination, &temp_dir).unwrap();
+        assert_eq!(
+            fs::read_to_string(destination.join("version")).unwrap(),
+            "new"
+        );
+        assert!(!temp_dir
+            .join(REPLACED_DIRECTORY)
+            .join("GE-Proton9-21")
+            .exists());
+    }
+
+    #[test]
+    fn test_sweep_keeps_a_complete_tool() {
+        let install_directory = tempdir().unwrap();
+        // Interrupted while extracting
+        let extracting = temp_directory(install_directory.path(), 1);
+        fs::create_dir_all(extracting.join("GE-Proton9-22/files")).unwrap();
+        // Interrupted after moving the installed tool aside
+        let replacing = temp_directory(install_directory.path(), 2);
+        fs::create_dir_all(replacing.join("GE-Proton9-21")).unwrap();
+        let aside = replacing.join(REPLACED_DIRECTORY).join("GE-Proton9-20");
+        fs::create_dir_all(&aside).unwrap();
+        fs::write(aside.join("version"), "old").unwrap();
+        // Interrupted after moving the new tool in
+        let replaced = temp_directory(install_directory.path(), 3);
+        let aside = replaced.join(REPLACED_DIRECTORY).join("GE-Proton9-19");
+        fs::create_dir_all(&aside).unwrap();
+        fs::write(aside.join("version"), "old").unwrap();
+        let installed = install_directory.path().join("GE-Proton9-19");
+        fs::create_dir_all(&installed).unwrap();
+        fs::write(installed.join("version"), "new").unwrap();
+
+        sweep_temp_directories(install_directory.path()).unwrap();
+        let mut remaining: Vec<String> = fs::read_dir(install_directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        remaining.sort();
+        assert_eq!(remaining, vec!["GE-Proton9-19", "GE-Proton9-20"]);
+        assert_eq!(
+            fs::read_to_string(installed.join("version")).unwrap(),
+            "new"
+        );
+        assert_eq!(
+            fs::read_to_string(install_directory.path().join("GE-Proton9-20/version")).unwrap(),
+            "old"
+        );
+    }
+}
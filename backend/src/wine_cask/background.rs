@@ -0,0 +1,452 @@
+use crate::app_id::AppId;
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::environment::EnvironmentSnapshot;
+use crate::wine_cask::settings::Settings;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, fs};
+
+/// Niceness of worker threads in background mode, high enough to stay out of a game's way.
+const BACKGROUND_NICENESS: i32 = 10;
+/// Shader cache writes more recent than this mean the game is compiling shaders.
+const SHADER_CACHE_CHURN_WINDOW: Duration = Duration::from_secs(15);
+/// Time download speeds are averaged over.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+/// Shortest time between two progress messages of an install, each one goes to every peer.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Limits applied to a task so it doesn't compete with a running game.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct TaskConstraints {
+    pub niceness: i32,
+    /// Only use the disk when nothing else does.
+    pub idle_io: bool,
+    /// Bytes per second, unlimited if `None`.
+    pub download_limit: Option<u64>,
+    /// Game to wait for before extracting while it writes its shader cache.
+    pub running_game: Option<AppId>,
+}
+
+impl Display for TaskConstraints {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "nice {}", self.niceness)?;
+        if self.idle_io {
+            write!(f, ", idle I/O")?;
+        }
+        if let Some(download_limit) = self.download_limit {
+            write!(f, ", {} KiB/s", download_limit / 1024)?;
+        }
+        if let Some(running_game) = self.running_game {
+            write!(f, ", yielding to app {}", running_game)?;
+        }
+        Ok(())
+    }
+}
+
+/// Selects the constraints for a task, `None` when it may run at full speed.
+pub fn select_constraints(
+    background_task: bool,
+    running_game: Option<AppId>,
+    settings: &Settings,
+) -> Option<TaskConstraints> {
+    let while_gaming = settings.background_while_gaming && running_game.is_some();
+    (background_task || while_gaming).then_some(TaskConstraints {
+        niceness: BACKGROUND_NICENESS,
+        idle_io: true,
+        download_limit: settings.background_download_limit,
+        running_game,
+    })
+}
+
+/// Whether a queued task may start now.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Admission {
+    /// Start under the constraints, at full speed if `None`.
+    Run(Option<TaskConstraints>),
+    /// Wait for the environment to change, for the given reason.
+    Defer(String),
+}
+
+/// Decides whether a task may start. Background tasks wait while on battery or on a metered
+/// network, nobody is waiting on them.
+pub fn admit_task(
+    background_task: bool,
+    environment: &EnvironmentSnapshot,
+    settings: &Settings,
+) -> Admission {
+    if background_task {
+        if environment.on_battery == Some(true) {
+            return Admission::Defer("running on battery".to_string());
+        }
+        if environment.metered_network == Some(true) {
+            return Admission::Defer("the network is metered".to_string());
+        }
+    }
+    Admission::Run(select_constraints(
+        background_task,
+        environment.running_game,
+        settings,
+    ))
+}
+
+fn newest_modification(directory: &Path) -> Option<SystemTime> {
+    fs::read_dir(directory)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            if metadata.is_dir() {
+                newest_modification(&entry.path()).or(metadata.modified().ok())
+            } else {
+                metadata.modified().ok()
+            }
+        })
+        .max()
+}
+
+/// Whether the game wrote to its shader cache recently in any library folder.
+pub fn shader_cache_active(library_folders: &[PathBuf], app_id: AppId, now: SystemTime) -> bool {
+    library_folders.iter().any(|library_folder| {
+        newest_modification(
+            &library_folder
+                .join("steamapps/shadercache")
+                .join(app_id.to_string()),
+        )
+        .and_then(|modified| now.duration_since(modified).ok())
+        .is_some_and(|age| age < SHADER_CACHE_CHURN_WINDOW)
+    })
+}
+
+/// Download speed limit in bytes per second, the lower of `download_rate_limit` in KiB/s and the
+/// limit of the task's constraints. Unlimited if `None`.
+pub fn download_limit(
+    constraints: Option<&TaskConstraints>,
+    download_rate_limit: u64,
+) -> Option<u64> {
+    let limits = [
+        (download_rate_limit > 0).then_some(download_rate_limit * 1024),
+        constraints.and_then(|constraints| constraints.download_limit),
+    ];
+    limits.into_iter().flatten().min()
+}
+
+/// Measures the download speed, including the time spent waiting on the rate limit.
+pub struct ThroughputMeter {
+    window_started: Instant,
+    window_bytes: u64,
+}
+
+impl ThroughputMeter {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            window_started: now,
+            window_bytes: 0,
+        }
+    }
+
+    /// Counts `bytes` received, returns the speed in bytes per second once a window completed.
+    pub fn record(&mut self, bytes: u64, now: Instant) -> Option<u64> {
+        self.window_bytes += bytes;
+        let elapsed = now.saturating_duration_since(self.window_started);
+        if elapsed < THROUGHPUT_WINDOW {
+            return None;
+        }
+        let bytes_per_second = (self.window_bytes as f64 / elapsed.as_secs_f64()) as u64;
+        *self = ThroughputMeter::new(now);
+        Some(bytes_per_second)
+    }
+}
+
+/// Holds back progress updates that come in faster than `PROGRESS_INTERVAL`.
+#[derive(Default)]
+pub struct ProgressThrottle {
+    last_sent: Option<(Instant, u8)>,
+}
+
+impl ProgressThrottle {
+    /// Whether `progress` is sent now. Reaching 100 is always sent, so the bar doesn't stop short
+    /// of complete when the last update came in right after the one before.
+    pub fn should_send(&mut self, progress: u8, now: Instant) -> bool {
+        match self.last_sent {
+            Some((_, sent)) if sent == progress => false,
+            Some((sent_at, _))
+                if progress < 100 && now.saturating_duration_since(sent_at) < PROGRESS_INTERVAL =>
+            {
+                false
+            }
+            _ => {
+                self.last_sent = Some((now, progress));
+                true
+            }
+        }
+    }
+}
+
+/// Averages the download rate over the time since the limit last changed.
+pub struct RateLimiter {
+    limit: Option<u64>,
+    window_started: Instant,
+    window_bytes: u64,
+}
+
+impl RateLimiter {
+    pub fn new(limit: Option<u64>, now: Instant) -> Self {
+        Self {
+            limit,
+            window_started: now,
+            window_bytes: 0,
+        }
+    }
+
+    pub fn set_limit(&mut self, limit: Option<u64>, now: Instant) {
+        if self.limit != limit {
+            // Bytes received at the old rate must not be paid for at the new one
+            *self = RateLimiter::new(limit, now);
+        }
+    }
+
+    /// Returns how long to wait after receiving `bytes` to stay under the limit.
+    pub fn throttle(&mut self, bytes: u64, now: Instant) -> Duration {
+        let Some(limit) = self.limit.filter(|limit| *limit > 0) else {
+            return Duration::ZERO;
+        };
+        self.window_bytes += bytes;
+        let expected = Duration::from_secs_f64(self.window_bytes as f64 / limit as f64);
+        expected.saturating_sub(now.saturating_duration_since(self.window_started))
+    }
+}
+
+fn apply_to_current_thread(constraints: &TaskConstraints) {
+    // Both commands take thread ids, which only affects this thread
+    let Some(thread_id) = fs::read_link("/proc/thread-self").ok().and_then(|path| {
+        path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+    }) else {
+        warn!("Failed to find the current thread id, running without constraints");
+        return;
+    };
+    let mut commands = vec![Command::new("renice")
+        .args(["-n", &constraints.niceness.to_string(), "-p", &thread_id])
+        .output()];
+    if constraints.idle_io {
+        commands.push(
+            Command::new("ionice")
+                .args(["-c", "3", "-p", &thread_id])
+                .output(),
+        );
+    }
+    for output in commands {
+        match output {
+            Ok(output) if output.status.success() => {}
+            Ok(output) => warn!(
+                "Failed to lower thread priority: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(err) => warn!("Failed to lower thread priority: {}", err),
+        }
+    }
+}
+
+/// Runs `work` on a dedicated thread with lowered CPU and I/O priority.
+///
+/// Priorities can't be raised again without privileges, so the pooled blocking threads must never
+/// be lowered themselves.
+pub fn run_constrained<T: Send>(
+    constraints: Option<&TaskConstraints>,
+    work: impl FnOnce() -> T + Send,
+) -> T {
+    match constraints {
+        Some(constraints) => thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    apply_to_current_thread(constraints);
+                    work()
+                })
+                .join()
+                .unwrap()
+        }),
+        None => work(),
+    }
+}
+
+impl WineCask {
+    pub async fn select_task_constraints(&self, background_task: bool) -> Option<TaskConstraints> {
+        let (environment, _) = self.sample_environment().await;
+        select_constraints(
+            background_task,
+            environment.running_game,
+            &self.app_state.lock().await.settings,
+        )
+    }
+
+    pub async fn admit_task(&self, background_task: bool) -> Admission {
+        let (environment, _) = self.sample_environment().await;
+        admit_task(
+            background_task,
+            &environment,
+            &self.app_state.lock().await.settings,
+        )
+    }
+
+    /// Waits while the running game compiles shaders, extraction would compete for the disk.
+    pub async fn wait_for_shader_cache(&self, constraints: &TaskConstraints) {
+        let Some(app_id) = constraints.running_game else {
+            return;
+        };
+        let library_folders = self.mounted_library_folders().await;
+        let started = Instant::now();
+        loop {
+            let library_folders = library_folders.clone();
+            let active = tokio::task::spawn_blocking(move || {
+                shader_cache_active(&library_folders, app_id, SystemTime::now())
+            })
+            .await
+            .unwrap();
+            // Don't hold the install back forever for a game that keeps compiling
+            if !active || started.elapsed() > Duration::from_secs(600) {
+                break;
+            }
+            info!(
+                "App {} is writing its shader cache, waiting to extract",
+                app_id
+            );
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn settings(background_while_gaming: bool) -> Settings {
+        Settings {
+            background_while_gaming,
+            background_download_limit: Some(1024 * 1024),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn test_select_constraints() {
+        let game = AppId::new(1245620).ok();
+        assert_eq!(select_constraints(false, game, &settings(false)), None);
+        assert_eq!(select_constraints(false, None, &settings(true)), None);
+
+        let constraints = select_constraints(false, game, &settings(true)).unwrap();
+        assert_eq!(constraints.download_limit, Some(1024 * 1024));
+        assert!(constraints.idle_io);
+        assert_eq!(constraints.running_game, game);
+
+        // Scheduled tasks are always constrained, but only wait for shader caches of a game
+        let constraints = select_constraints(true, None, &settings(false)).unwrap();
+        assert_eq!(constraints.niceness, BACKGROUND_NICENESS);
+        assert_eq!(constraints.running_game, None);
+    }
+
+    #[test]
+    fn test_limiter_follows_game_state_mid_download() {
+        let start = Instant::now();
+        let settings = settings(true);
+        let game = AppId::new(1245620).ok();
+        let download_limit =
+            |running_game| select_constraints(false, running_game, &settings)?.download_limit;
+        let mut limiter = RateLimiter::new(download_limit(None), start);
+
+        // No game, full speed
+        assert_eq!(limiter.throttle(4 * 1024 * 1024, start), Duration::ZERO);
+
+        // The game starts, 2 MiB at 1 MiB/s take two seconds
+        let flipped = start + Duration::from_secs(1);
+        limiter.set_limit(download_limit(game), flipped);
+        let delay = limiter.throttle(2 * 1024 * 1024, flipped);
+        assert_eq!(delay, Duration::from_secs(2));
+        assert_eq!(
+            limiter.throttle(0, flipped + Duration::from_secs(1)),
+            Duration::from_secs(1)
+        );
+
+        // The game exits, the limit is lifted right away
+        limiter.set_limit(download_limit(None), flipped + Duration::from_secs(2));
+        assert_eq!(
+            limiter.throttle(8 * 1024 * 1024, flipped + Duration::from_secs(2)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_lower_download_limit_applies() {
+        let constraints = select_constraints(true, None, &settings(false));
+        assert_eq!(download_limit(None, 0), None);
+        assert_eq!(download_limit(None, 512), Some(512 * 1024));
+        assert_eq!(download_limit(constraints.as_ref(), 0), Some(1024 * 1024));
+        assert_eq!(download_limit(constraints.as_ref(), 512), Some(512 * 1024));
+        assert_eq!(
+            download_limit(constraints.as_ref(), 4096),
+            Some(1024 * 1024)
+        );
+    }
+
+    #[test]
+    fn test_throughput_is_measured_per_window() {
+        let start = Instant::now();
+        let mut meter = ThroughputMeter::new(start);
+        assert_eq!(meter.record(1024, start), None);
+        assert_eq!(meter.record(1024, start + Duration::from_millis(500)), None);
+        assert_eq!(
+            meter.record(2048, start + Duration::from_secs(2)),
+            Some(2048)
+        );
+        // A new window starts after each measurement
+        assert_eq!(meter.record(512, start + Duration::from_millis(2500)), None);
+    }
+
+    #[test]
+    fn test_progress_is_throttled_but_completion_is_sent() {
+        let start = Instant::now();
+        let mut throttle = ProgressThrottle::default();
+        assert!(throttle.should_send(0, start));
+        assert!(!throttle.should_send(1, start + Duration::from_millis(100)));
+        assert!(throttle.should_send(2, start + Duration::from_millis(250)));
+        assert!(!throttle.should_send(2, start + Duration::from_secs(1)));
+        assert!(throttle.should_send(40, start + Duration::from_secs(1)));
+        assert!(!throttle.should_send(99, start + Duration::from_millis(1100)));
+        assert!(throttle.should_send(100, start + Duration::from_millis(1101)));
+        assert!(!throttle.should_send(100, start + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_shader_cache_churn() {
+        let app_id = AppId::new(1245620).unwrap();
+        let library_folder = tempdir().unwrap();
+        let shader_cache = library_folder
+            .path()
+            .join("steamapps/shadercache/1245620/fozpipelinesv6");
+        fs::create_dir_all(&shader_cache).unwrap();
+        fs::write(
+            shader_cache.join("steamapp_pipeline_cache.foz"),
+            "pipelines",
+        )
+        .unwrap();
+
+        let library_folders = [library_folder.path().to_path_buf()];
+        let now = SystemTime::now();
+        assert!(shader_cache_active(&library_folders, app_id, now));
+        assert!(!shader_cache_active(
+            &library_folders,
+            app_id,
+            now + Duration::from_secs(60)
+        ));
+        assert!(!shader_cache_active(
+            &library_folders,
+            AppId::new(730).unwrap(),
+            now
+        ));
+    }
+}
@@ -0,0 +1,211 @@
+use crate::github_util::Asset;
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::install::{archive_stem, Install, QueueCompatibilityTool};
+use crate::wine_cask::network_usage::NetworkTraffic;
+use crate::wine_cask::proxy::ProxyConfig;
+use crate::PeerMap;
+use log::warn;
+use sha2::{Digest, Sha512};
+
+/// Times an archive is downloaded before a checksum mismatch fails the install.
+pub const CHECKSUM_ATTEMPTS: u32 = 2;
+
+/// Hashes an archive as its chunks arrive, so it doesn't have to be read a second time.
+#[derive(Default)]
+pub struct ArchiveVerifier {
+    hasher: Sha512,
+}
+
+impl ArchiveVerifier {
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+    }
+
+    /// Returns the digest of the archive, or why it doesn't match the `expected` one.
+    pub fn finish(self, expected: Option<&str>) -> Result<String, String> {
+        let checksum = format!("{:x}", self.hasher.finalize());
+        match expected {
+            Some(expected) if !expected.eq_ignore_ascii_case(&checksum) => Err(format!(
+                "SHA-512 {} doesn't match the published {}",
+                checksum, expected
+            )),
+            _ => Ok(checksum),
+        }
+    }
+}
+
+/// The `.sha512sum` asset released alongside the archive, e.g. `GE-Proton9-21.sha512sum`.
+pub fn checksum_asset<'a>(assets: &'a [Asset], archive: &Asset) -> Option<&'a Asset> {
+    let stem = archive_stem(&archive.name).unwrap_or(&archive.name);
+    let name = format!("{}.sha512sum", stem);
+    assets.iter().find(|asset| asset.name == name)
+}
+
+/// Finds the digest of `archive_name` in `sha512sum` output, which may also be a bare digest.
+pub fn parse_checksum_file(content: &str, archive_name: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let digest = fields.next()?;
+        // Binary mode marks names with a leading `*`, some list the path they were hashed at
+        let names_archive = fields.next().is_none_or(|name| {
+            name.trim_start_matches('*').rsplit('/').next() == Some(archive_name)
+        });
+        let is_digest = digest.len() == 128 && digest.chars().all(|c| c.is_ascii_hexdigit());
+        (names_archive && is_digest).then(|| digest.to_ascii_lowercase())
+    })
+}
+
+impl WineCask {
+    /// Downloads the published checksum of the archive, `None` if there's nothing to verify
+    /// against.
+    pub async fn expected_checksum(
+        &self,
+        peer_map: &PeerMap,
+        install: &Install,
+        queue_compatibility_tool: &QueueCompatibilityTool,
+    ) -> Result<Option<String>, String> {
+        let skipped = self
+            .app_state
+            .lock()
+            .await
+            .settings
+            .skip_checksum_flavors
+            .contains(&install.flavor);
+        if skipped {
+            return Ok(None);
+        }
+        let assets = &install.release.assets;
+        let checksum_asset = assets
+            .iter()
+            .find(|asset| asset.browser_download_url == queue_compatibility_tool.url)
+            .and_then(|archive| Some((archive, checksum_asset(assets, archive)?)));
+        let Some((archive, checksum_asset)) = checksum_asset else {
+            let warning_message = format!(
+                "{} has no published checksum, the download can't be verified",
+                queue_compatibility_tool.name
+            );
+            warn!("{}", warning_message);
+            self.broadcast_notification(peer_map, &format!("Warning: {}", warning_message))
+                .await;
+            return Ok(None);
+        };
+
+        let proxy = ProxyConfig::current();
+        let content = async {
+            proxy
+                .client_builder()
+                .build()?
+                .get(&checksum_asset.browser_download_url)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        }
+        .await
+        .map_err(|err| {
+            format!(
+                "Failed to download {}: {}",
+                checksum_asset.name,
+                proxy.describe_error(&err)
+            )
+        })?;
+        self.record_network_usage(NetworkTraffic::Asset, content.len() as u64)
+            .await;
+        parse_checksum_file(&content, &archive.name)
+            .map(Some)
+            .ok_or_else(|| format!("{} doesn't list {}", checksum_asset.name, archive.name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asset(name: &str) -> Asset {
+        Asset {
+            url: String::new(),
+            id: 0,
+            name: name.to_string(),
+            content_type: String::new(),
+            state: "uploaded".to_string(),
+            size: 0,
+            download_count: 0,
+            created_at: String::new(),
+            updated_at: String::new(),
+            browser_download_url: format!("https://github.com/{}", name),
+        }
+    }
+
+    fn verify(archive: &[u8], expected: &str) -> Result<String, String> {
+        let mut verifier = ArchiveVerifier::default();
+        // Fed in download sized chunks
+        for chunk in archive.chunks(7) {
+            verifier.update(chunk);
+        }
+        verifier.finish(Some(expected))
+    }
+
+    #[test]
+    fn test_corrupted_archive_is_rejected() {
+        let archive: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let expected = format!("{:x}", Sha512::digest(&archive));
+        let content = format!("{}  GE-Proton9-21.tar.gz\n", expected.to_uppercase());
+        let published = parse_checksum_file(&content, "GE-Proton9-21.tar.gz").unwrap();
+
+        assert_eq!(verify(&archive, &published), Ok(expected.clone()));
+
+        let mut corrupted = archive.clone();
+        corrupted[500] ^= 0x01;
+        let error = verify(&corrupted, &published).unwrap_err();
+        assert!(error.ends_with(&format!("doesn't match the published {}", expected)));
+        // A truncated download doesn't match either
+        assert!(verify(&archive[..999], &published).is_err());
+    }
+
+    #[test]
+    fn test_checksum_files_are_parsed() {
+        let digest = "ab".repeat(64);
+        assert_eq!(
+            parse_checksum_file(
+                &format!("{} *GE-Proton9-21.tar.gz", digest),
+                "GE-Proton9-21.tar.gz"
+            ),
+            Some(digest.clone())
+        );
+        assert_eq!(
+            parse_checksum_file(
+                &format!("{}  build/GE-Proton9-21.tar.gz", digest),
+                "GE-Proton9-21.tar.gz"
+            ),
+            Some(digest.clone())
+        );
+        assert_eq!(
+            parse_checksum_file(&digest, "GE-Proton9-21.tar.gz"),
+            Some(digest.clone())
+        );
+        assert_eq!(
+            parse_checksum_file(
+                &format!("{}  GE-Proton9-20.tar.gz", digest),
+                "GE-Proton9-21.tar.gz"
+            ),
+            None
+        );
+        assert_eq!(
+            parse_checksum_file("not a digest", "GE-Proton9-21.tar.gz"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_checksum_asset_matches_archive() {
+        let assets = [
+            asset("GE-Proton9-21.sha512sum"),
+            asset("GE-Proton9-21.tar.gz"),
+            asset("GE-Proton9-20.sha512sum"),
+        ];
+        let checksum = checksum_asset(&assets, &assets[1]).unwrap();
+        assert_eq!(checksum.name, "GE-Proton9-21.sha512sum");
+        assert!(checksum_asset(&assets[1..2], &assets[1]).is_none());
+    }
+}
@@ -0,0 +1,343 @@
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::proxy::ProxyConfig;
+use crate::PeerMap;
+use chrono::DateTime;
+use log::{info, warn};
+use reqwest::header::DATE;
+use reqwest::redirect::Policy;
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Plain HTTP so the measurement itself can't fail because of the clock, redirects aren't followed.
+const CLOCK_CHECK_URL: &str = "http://api.github.com/";
+/// Skew beyond which certificates and cache ages become unreliable.
+const SKEW_THRESHOLD: i64 = 5 * 60;
+/// Minimum time between two clock checks.
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How long cached releases are used before checking for new ones.
+pub const RELEASE_REFRESH_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Returns how many seconds the local clock is ahead of the server's `Date` header, negative when
+/// behind. `sent` and `received` bracket the request, the server time is compared to their middle.
+pub fn measure_skew(date_header: &str, sent: SystemTime, received: SystemTime) -> Option<i64> {
+    let server_time = DateTime::parse_from_rfc2822(date_header).ok()?.timestamp();
+    let sent = sent.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+    let received = received.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+    Some(sent + (received - sent) / 2 - server_time)
+}
+
+/// Returns the skew if it's large enough to cause problems.
+pub fn significant_skew(skew: i64) -> Option<i64> {
+    (skew.abs() > SKEW_THRESHOLD).then_some(skew)
+}
+
+pub fn describe_skew(skew: i64) -> String {
+    let seconds = skew.unsigned_abs();
+    let amount = match seconds {
+        0..=3599 => format!("{} minutes", seconds / 60),
+        3600..=86399 => format!("{} hours", seconds / 3600),
+        _ => format!("{} days", seconds / 86400),
+    };
+    let direction = if skew > 0 { "ahead" } else { "behind" };
+    format!("{} {} ({:+} s)", amount, direction, skew)
+}
+
+/// Formats `err` along with every error that caused it.
+pub fn error_chain(err: &dyn Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        message = format!("{}: {}", message, err);
+        source = err.source();
+    }
+    message
+}
+
+/// Adds a hint to certificate errors when the clock is known to be off.
+pub fn annotate_tls_error(message: &str, skew: Option<i64>) -> String {
+    let lowercase = message.to_lowercase();
+    let is_tls_error = ["certificate", "tls", "handshake"]
+        .iter()
+        .any(|keyword| lowercase.contains(keyword));
+    match skew {
+        Some(skew) if is_tls_error => format!(
+            "{} (the system clock is {}, which makes certificates appear invalid)",
+            message,
+            describe_skew(skew)
+        ),
+        _ => message.to_string(),
+    }
+}
+
+pub fn clock_check_due(last_check: Option<Instant>, now: Instant) -> bool {
+    match last_check {
+        Some(last_check) => now.saturating_duration_since(last_check) >= CLOCK_CHECK_INTERVAL,
+        None => true,
+    }
+}
+
+/// Tracks refreshes on the monotonic clock, the wall clock is only used once per key to account
+/// for the age of a cache left by a previous run.
+#[derive(Clone)]
+pub struct RefreshSchedule {
+    interval: Duration,
+    last_refresh: HashMap<String, Instant>,
+}
+
+impl Default for RefreshSchedule {
+    fn default() -> Self {
+        RefreshSchedule::new(RELEASE_REFRESH_INTERVAL)
+    }
+}
+
+impl RefreshSchedule {
+    pub fn new(interval: Duration) -> Self {
+        RefreshSchedule {
+            interval,
+            last_refresh: HashMap::new(),
+        }
+    }
+
+    /// Returns whether `key` should be refreshed. `cache_modified` is when the cache was written,
+    /// it is considered brand new when the wall clock isn't trusted.
+    pub fn is_due(
+        &mut self,
+        key: &str,
+        cache_modified: Option<SystemTime>,
+        wall_now: SystemTime,
+        wall_clock_trusted: bool,
+        now: Instant,
+    ) -> bool {
+        if !self.last_refresh.contains_key(key) {
+            let Some(cache_modified) = cache_modified else {
+                return true;
+            };
+            let age = if wall_clock_trusted {
+                // A cache from the future was written before the clock went back
+                wall_now
+                    .duration_since(cache_modified)
+                    .unwrap_or(Duration::ZERO)
+            } else {
+                Duration::ZERO
+            };
+            match now.checked_sub(age) {
+                Some(refreshed) if age < self.interval => {
+                    self.last_refresh.insert(key.to_string(), refreshed);
+                }
+                _ => return true,
+            }
+        }
+        now.saturating_duration_since(self.last_refresh[key]) >= self.interval
+    }
+
+    /// Records a refresh attempt, failed attempts count too so they aren't retried in a loop.
+    pub fn record(&mut self, key: &str, now: Instant) {
+        self.last_refresh.insert(key.to_string(), now);
+    }
+}
+
+async fn fetch_clock_skew() -> Result<i64, String> {
+    let proxy = ProxyConfig::current();
+    let client = proxy
+        .client_builder()
+        .redirect(Policy::none())
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|err| err.to_string())?;
+    let sent = SystemTime::now();
+    let response = client
+        .head(CLOCK_CHECK_URL)
+        .send()
+        .await
+        .map_err(|err| proxy.describe_error(&err))?;
+    let received = SystemTime::now();
+    let date_header = response
+        .headers()
+        .get(DATE)
+        .and_then(|date| date.to_str().ok())
+        .ok_or("Response has no Date header")?;
+    measure_skew(date_header, sent, received)
+        .ok_or_else(|| format!("Invalid Date header: {}", date_header))
+}
+
+impl WineCask {
+    /// Compares the system clock against a server's, at most once per `CLOCK_CHECK_INTERVAL`.
+    pub async fn check_clock(&self, peer_map: &PeerMap) {
+        let now = Instant::now();
+        let mut app_state = self.app_state.lock().await;
+        if !clock_check_due(app_state.clock_checked, now) {
+            return;
+        }
+        app_state.clock_checked = Some(now);
+        drop(app_state);
+
+        let skew = match fetch_clock_skew().await {
+            Ok(skew) => significant_skew(skew),
+            Err(err) => {
+                warn!("Failed to check the system clock: {}", err);
+                return;
+            }
+        };
+        let previous = self.record_clock_skew(skew).await;
+        match (previous, skew) {
+            (None, Some(skew)) => {
+                let warning_message = format!(
+                    "Warning: clock_skew_detected: the system clock is {}, downloads may fail until it is corrected",
+                    describe_skew(skew)
+                );
+                warn!("{}", warning_message);
+                self.broadcast_notification(peer_map, &warning_message)
+                    .await;
+            }
+            (Some(_), None) => info!("The system clock is correct again"),
+            _ => {}
+        }
+    }
+
+    pub async fn annotate_tls_error(&self, message: &str) -> String {
+        annotate_tls_error(message, self.app_state.lock().await.environment.clock_skew)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamp(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn test_skew_is_measured_against_the_date_header() {
+        // Wed, 16 Oct 2024 12:00:00 GMT
+        let server_time = 1_729_080_000;
+        let date_header = "Wed, 16 Oct 2024 12:00:00 GMT";
+        assert_eq!(
+            measure_skew(date_header, timestamp(server_time), timestamp(server_time)),
+            Some(0)
+        );
+        // The request took 4 seconds, the server answered halfway through
+        assert_eq!(
+            measure_skew(
+                date_header,
+                timestamp(server_time - 2),
+                timestamp(server_time + 2)
+            ),
+            Some(0)
+        );
+
+        // Battery drained, the clock reset to a day in 2023
+        let skew = measure_skew(
+            date_header,
+            timestamp(1_700_000_000),
+            timestamp(1_700_000_001),
+        )
+        .unwrap();
+        assert_eq!(skew, -29_080_000);
+        assert_eq!(significant_skew(skew), Some(skew));
+        assert_eq!(describe_skew(skew), "336 days behind (-29080000 s)");
+
+        assert_eq!(significant_skew(90), None);
+        assert_eq!(describe_skew(7200), "2 hours ahead (+7200 s)");
+        assert_eq!(measure_skew("yesterday", timestamp(0), timestamp(0)), None);
+    }
+
+    #[test]
+    fn test_tls_errors_get_a_clock_hint() {
+        let message = "error sending request: invalid peer certificate: NotValidYet";
+        assert!(annotate_tls_error(message, Some(-86400)).contains("1 days behind"));
+        assert_eq!(annotate_tls_error(message, None), message);
+        assert_eq!(
+            annotate_tls_error("connection refused", Some(-86400)),
+            "connection refused"
+        );
+    }
+
+    #[test]
+    fn test_clock_checks_are_rate_limited() {
+        let now = Instant::now();
+        assert!(clock_check_due(None, now));
+        assert!(!clock_check_due(Some(now), now + Duration::from_secs(60)));
+        assert!(clock_check_due(Some(now), now + CLOCK_CHECK_INTERVAL));
+    }
+
+    #[test]
+    fn test_refreshes_follow_the_monotonic_clock() {
+        let interval = Duration::from_secs(100);
+        let now = Instant::now() + Duration::from_secs(1000);
+        let cache_modified = timestamp(1_729_080_000);
+
+        // No cache yet
+        let mut schedule = RefreshSchedule::new(interval);
+        assert!(schedule.is_due("proton", None, timestamp(0), true, now));
+
+        // A cache from a previous run ages with the wall clock
+        let mut schedule = RefreshSchedule::new(interval);
+        let wall_now = cache_modified + Duration::from_secs(60);
+        assert!(!schedule.is_due("proton", Some(cache_modified), wall_now, true, now));
+        assert!(schedule.is_due(
+            "proton",
+            Some(cache_modified),
+            wall_now,
+            true,
+            now + Duration::from_secs(40)
+        ));
+        let mut schedule = RefreshSchedule::new(interval);
+        let wall_now = cache_modified + interval;
+        assert!(schedule.is_due("proton", Some(cache_modified), wall_now, true, now));
+
+        // Once seeded, wall clock jumps are ignored
+        let mut schedule = RefreshSchedule::new(interval);
+        schedule.record("proton", now);
+        let far_future = cache_modified + Duration::from_secs(10 * 365 * 86400);
+        assert!(!schedule.is_due("proton", Some(cache_modified), far_future, true, now));
+        assert!(!schedule.is_due(
+            "proton",
+            Some(cache_modified),
+            timestamp(0),
+            true,
+            now + Duration::from_secs(99)
+        ));
+        assert!(schedule.is_due(
+            "proton",
+            Some(cache_modified),
+            timestamp(0),
+            true,
+            now + interval
+        ));
+    }
+
+    #[test]
+    fn test_skewed_clocks_dont_cause_refresh_storms() {
+        let interval = Duration::from_secs(100);
+        let now = Instant::now() + Duration::from_secs(1000);
+        let cache_modified = timestamp(1_729_080_000);
+        let far_future = cache_modified + Duration::from_secs(10 * 365 * 86400);
+
+        // The cache looks ancient, but the clock is known to be wrong
+        let mut schedule = RefreshSchedule::new(interval);
+        for second in 0..interval.as_secs() {
+            let now = now + Duration::from_secs(second);
+            assert!(!schedule.is_due("proton", Some(cache_modified), far_future, false, now));
+        }
+        assert!(schedule.is_due(
+            "proton",
+            Some(cache_modified),
+            far_future,
+            false,
+            now + interval
+        ));
+
+        // A cache from the future doesn't block refreshes forever either
+        let mut schedule = RefreshSchedule::new(interval);
+        assert!(!schedule.is_due("proton", Some(far_future), cache_modified, true, now));
+        assert!(schedule.is_due(
+            "proton",
+            Some(far_future),
+            cache_modified,
+            true,
+            now + interval
+        ));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::app_id::CompatAppId;
+use crate::steam_util::{OrphanedCompatData, SteamUtilError};
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, WineCask};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::network_usage::format_bytes;
+use crate::wine_cask::plans::PLAN_TTL;
+use crate::wine_cask::provenance::current_timestamp;
+use crate::wine_cask::recursive_delete_dir_entry;
+use crate::wine_cask::storage::directory_size;
+use crate::PeerMap;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Orphaned prefixes as listed to the peers, deleting any of them needs the listing's token.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct CompatDataListing {
+    pub confirmation_token: u64,
+    pub expires_at: u64,
+    pub prefixes: Vec<OrphanedCompatData>,
+}
+
+/// Prefixes to delete, picked from a listing.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeleteCompatData {
+    /// Token of the listing the prefixes were picked from.
+    pub confirmation_token: u64,
+    /// Apps or shortcuts whose prefixes are deleted.
+    pub app_ids: Vec<CompatAppId>,
+}
+
+/// The last listing sent, older listings can't be confirmed anymore.
+#[derive(Clone, Default, Debug)]
+pub struct CompatDataListings {
+    listing: Option<CompatDataListing>,
+    next_token: u64,
+}
+
+impl CompatDataListings {
+    /// Stores a new listing, replacing the older one.
+    pub fn insert(&mut self, prefixes: Vec<OrphanedCompatData>, now: u64) -> CompatDataListing {
+        self.next_token += 1;
+        let listing = CompatDataListing {
+            confirmation_token: self.next_token,
+            expires_at: now + PLAN_TTL,
+            prefixes,
+        };
+        self.listing = Some(listing.clone());
+        listing
+    }
+
+    /// Removes the listing a deletion confirms, so it can't be confirmed twice.
+    pub fn take(
+        &mut self,
+        confirmation_token: u64,
+        now: u64,
+    ) -> Result<CompatDataListing, AppError> {
+        if self
+            .listing
+            .as_ref()
+            .map(|listing| listing.confirmation_token)
+            != Some(confirmation_token)
+        {
+            return Err(AppError::new(
+                AppErrorCode::NotFound,
+                format!(
+                    "Listing {} was replaced, used or never made",
+                    confirmation_token
+                ),
+            ));
+        }
+        let listing = self.listing.take().unwrap();
+        if listing.expires_at <= now {
+            return Err(AppError::new(
+                AppErrorCode::InvalidRequest,
+                format!(
+                    "Listing {} expired, list the prefixes again",
+                    confirmation_token
+                ),
+            ));
+        }
+        Ok(listing)
+    }
+}
+
+/// Prefixes of `listing` picked by `app_ids` that are still orphaned, apps may have been
+/// reinstalled since the listing was made.
+pub fn prefixes_to_delete(
+    listing: &CompatDataListing,
+    app_ids: &[CompatAppId],
+    still_orphaned: &[OrphanedCompatData],
+) -> Vec<OrphanedCompatData> {
+    listing
+        .prefixes
+        .iter()
+        .filter(|prefix| app_ids.contains(&prefix.app_id))
+        .filter(|prefix| {
+            still_orphaned
+                .iter()
+                .any(|orphaned| orphaned.path == prefix.path)
+        })
+        .cloned()
+        .collect()
+}
+
+impl WineCask {
+    /// Walks and measures every library folder's compatdata, so it runs off the async runtime.
+    async fn orphaned_compat_data(&self) -> Result<Vec<OrphanedCompatData>, AppError> {
+        let steam_util = self.steam_util.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut orphaned = steam_util.list_orphaned_compat_data()?;
+            for compat_data in &mut orphaned {
+                compat_data.size = directory_size(&compat_data.path);
+            }
+            Ok::<_, SteamUtilError>(orphaned)
+        })
+        .await
+        .map_err(|err| AppError::internal(err.to_string()))?
+        .map_err(|err| AppError::from(err).context("Failed to list orphaned prefixes"))
+    }
+
+    /// Sends the orphaned prefixes back along with the token deleting them needs.
+    pub async fn list_orphaned_compat_data(&self, peer_map: &PeerMap) {
+        let mut prefixes = match self.orphaned_compat_data().await {
+            Ok(prefixes) => prefixes,
+            Err(app_error) => {
+                error!("{}", app_error);
+                self.broadcast_app_error(peer_map, app_error).await;
+                return;
+            }
+        };
+        let names = self.app_name_resolver.lock().unwrap().cached_names();
+        for prefix in &mut prefixes {
+            prefix.name = prefix
+                .app_id
+                .app_id()
+                .and_then(|app_id| names.get(&app_id))
+                .cloned();
+        }
+
+        let listing = self
+            .app_state
+            .lock()
+            .await
+            .compat_data_listings
+            .insert(prefixes, current_timestamp());
+        info!(
+            "Listed {} orphaned prefixes as listing {}",
+            listing.prefixes.len(),
+            listing.confirmation_token
+        );
+        broadcast_to_peers(
+            peer_map,
+            &Request {
+                compat_data_listing: Some(listing),
+                ..Request::new(RequestType::OrphanedCompatData)
+            },
+        )
+        .await;
+    }
+
+    /// Deletes the prefixes picked from the listing the token confirms.
+    pub async fn delete_compat_data(
+        &self,
+        peer_map: &PeerMap,
+        delete_compat_data: DeleteCompatData,
+    ) {
+        let taken = self
+            .app_state
+            .lock()
+            .await
+            .compat_data_listings
+            .take(delete_compat_data.confirmation_token, current_timestamp());
+        let listed = match taken {
+            Ok(listing) => self
+                .orphaned_compat_data()
+                .await
+                .map(|still_orphaned| (listing, still_orphaned)),
+            Err(app_error) => Err(app_error),
+        };
+        let (listing, still_orphaned) = match listed {
+            Ok(listed) => listed,
+            Err(app_error) => {
+                error!("{}", app_error);
+                self.broadcast_app_error(peer_map, app_error).await;
+                return;
+            }
+        };
+
+        let prefixes = prefixes_to_delete(&listing, &delete_compat_data.app_ids, &still_orphaned);
+        // Prefixes of the same app in several library folders are picked together
+        let skipped = delete_compat_data
+            .app_ids
+            .iter()
+            .filter(|app_id| !prefixes.iter().any(|prefix| prefix.app_id == **app_id))
+            .count();
+        if skipped > 0 {
+            warn!(
+                "Skipping {} prefixes that weren't listed or aren't orphaned anymore",
+                skipped
+            );
+        }
+        if let Err(app_error) = self
+            .refuse_while_running(peer_map, "delete its prefix", |app_id| {
+                prefixes
+                    .iter()
+                    .any(|prefix| prefix.app_id == CompatAppId::from(app_id))
+            })
+            .await
+        {
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error).await;
+            return;
+        }
+        let (deleted, failed): (Vec<_>, Vec<_>) = tokio::task::spawn_blocking(move || {
+            prefixes
+                .into_iter()
+                .map(|prefix| {
+                    let result = recursive_delete_dir_entry(&prefix.path);
+                    (prefix, result)
+                })
+                .partition(|(_, result)| result.is_ok())
+        })
+        .await
+        .unwrap();
+        for (prefix, result) in &failed {
+            if let Err(err) = result {
+                error!("Failed to delete {}: {}", prefix.path.display(), err);
+            }
+        }
+
+        let reclaimed_bytes: u64 = deleted.iter().map(|(prefix, _)| prefix.size).sum();
+        let deleted_paths: Vec<PathBuf> =
+            deleted.into_iter().map(|(prefix, _)| prefix.path).collect();
+        info!("Deleted orphaned prefixes {:?}", deleted_paths);
+        let mut message = format!(
+            "Deleted {} prefixes, freed {}",
+            deleted_paths.len(),
+            format_bytes(reclaimed_bytes)
+        );
+        if !failed.is_empty() {
+            message = format!("{}, {} couldn't be deleted", message, failed.len());
+        }
+        if skipped > 0 {
+            message = format!("{}, {} were skipped", message, skipped);
+        }
+        self.broadcast_notification(peer_map, &message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix(app_id: u32) -> OrphanedCompatData {
+        OrphanedCompatData {
+            app_id: CompatAppId::new(app_id as u64).unwrap(),
+            name: None,
+            path: PathBuf::from(format!("/steamapps/compatdata/{}", app_id)),
+            size: 100,
+        }
+    }
+
+    #[test]
+    fn test_listings_are_confirmed_once_before_expiring() {
+        let now = 1_750_000_000;
+        let mut listings = CompatDataListings::default();
+        let first = listings.insert(vec![prefix(1245620)], now);
+        let second = listings.insert(vec![prefix(1245620)], now);
+        // Listing again replaces the older listing
+        assert!(listings.take(first.confirmation_token, now).is_err());
+        assert_eq!(
+            listings.take(second.confirmation_token, now),
+            Ok(second.clone())
+        );
+        assert!(listings.take(second.confirmation_token, now).is_err());
+
+        let third = listings.insert(Vec::new(), now);
+        assert_eq!(
+            listings
+                .take(third.confirmation_token, now + PLAN_TTL)
+                .unwrap_err()
+                .code,
+            AppErrorCode::InvalidRequest
+        );
+    }
+
+    #[test]
+    fn test_only_listed_prefixes_still_orphaned_are_deleted() {
+        let mut listings = CompatDataListings::default();
+        let listing = listings.insert(vec![prefix(1245620), prefix(292030)], 0);
+        let app_ids = [1245620, 292030, 730].map(|app_id| CompatAppId::new(app_id).unwrap());
+        // The Witcher 3 was reinstalled since, Counter-Strike was never listed
+        assert_eq!(
+            prefixes_to_delete(&listing, &app_ids, &[prefix(1245620)]),
+            [prefix(1245620)]
+        );
+    }
+}
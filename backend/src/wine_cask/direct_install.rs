@@ -0,0 +1,397 @@
+use crate::github_util::{Asset, Release};
+use crate::wine_cask::app::{Task, TaskType, WineCask};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::flavors::{flavor_repository, CompatibilityToolFlavor};
+use crate::wine_cask::generate_compatibility_tool_vdf;
+use crate::wine_cask::history::HistoryTrigger;
+use crate::wine_cask::install::{archive_stem, Install};
+use crate::wine_cask::local_install::unsupported_archive;
+use crate::wine_cask::names::{validate_name, NameKind};
+use crate::wine_cask::naming::{naming_schemes, tool_version};
+use crate::wine_cask::settings::Settings;
+use crate::PeerMap;
+use log::{error, info};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// Hosts GitHub serves release assets from, direct installs of known flavors don't download from
+/// anywhere else.
+const ALLOWED_HOSTS: [&str; 3] = [
+    "github.com",
+    "objects.githubusercontent.com",
+    "release-assets.githubusercontent.com",
+];
+
+/// Largest archive a direct install downloads unless configured otherwise, 2 GiB.
+pub const DEFAULT_MAX_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+
+/// Flavors a direct install can be told apart by, along with the archive name prefix of the
+/// flavors not using a naming scheme.
+const ARCHIVE_PREFIXES: [(CompatibilityToolFlavor, &str); 3] = [
+    (CompatibilityToolFlavor::Luxtorpeda, "luxtorpeda"),
+    (CompatibilityToolFlavor::Boxtron, "boxtron"),
+    (CompatibilityToolFlavor::Roberta, "roberta"),
+];
+
+/// An asset URL pasted by the user, for when the release listing can't be fetched or the tool
+/// isn't one of the flavors.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DirectInstall {
+    pub url: String,
+    /// Overrides the flavor inferred from the URL, `Unknown` installs a fork from any host.
+    pub flavor: Option<CompatibilityToolFlavor>,
+    /// Overrides the tag inferred from the URL.
+    pub tag_name: Option<String>,
+}
+
+/// Name of the downloaded file, CDN links carry it in the content disposition they ask for.
+fn archive_name(url: &Url) -> Option<String> {
+    url.query_pairs()
+        .find(|(key, _)| key == "response-content-disposition")
+        .and_then(|(_, disposition)| {
+            let name = disposition.split("filename=").nth(1)?;
+            Some(name.trim_matches('"').to_string())
+        })
+        .or_else(|| url.path_segments()?.next_back().map(str::to_string))
+        .filter(|name| !name.is_empty())
+}
+
+/// Flavor and tag of `github.com/<owner>/<repository>/releases/download/<tag>/<file>` links.
+fn release_download(url: &Url) -> Option<(Option<CompatibilityToolFlavor>, String)> {
+    if url.host_str() != Some("github.com") {
+        return None;
+    }
+    let segments: Vec<&str> = url.path_segments()?.collect();
+    let [owner, repository, "releases", "download", tag_name, _] = segments[..] else {
+        return None;
+    };
+    let flavor = [
+        CompatibilityToolFlavor::ProtonGE,
+        CompatibilityToolFlavor::SteamTinkerLaunch,
+        CompatibilityToolFlavor::Luxtorpeda,
+        CompatibilityToolFlavor::Boxtron,
+        CompatibilityToolFlavor::Roberta,
+        CompatibilityToolFlavor::ProtonTkg,
+        CompatibilityToolFlavor::WineGE,
+        CompatibilityToolFlavor::Dxvk,
+        CompatibilityToolFlavor::Vkd3dProton,
+    ]
+    .into_iter()
+    .find(|flavor| flavor_repository(flavor) == Some((owner, repository)));
+    Some((flavor, tag_name.to_string()))
+}
+
+/// Flavor and tag told by the archive name alone.
+pub(crate) fn infer_from_archive(stem: &str) -> Option<(CompatibilityToolFlavor, Option<String>)> {
+    let proton_ge = CompatibilityToolFlavor::ProtonGE;
+    if tool_version(naming_schemes(&proton_ge), stem, stem).is_some() {
+        return Some((proton_ge, Some(stem.to_string())));
+    }
+    ARCHIVE_PREFIXES.into_iter().find_map(|(flavor, prefix)| {
+        let start = stem.get(..prefix.len())?;
+        if !start.eq_ignore_ascii_case(prefix) {
+            return None;
+        }
+        let tag_name = stem[prefix.len()..]
+            .strip_prefix('-')
+            .filter(|tag_name| !tag_name.is_empty());
+        Some((flavor, tag_name.map(str::to_string)))
+    })
+}
+
+/// Validates the URL and turns it into an install of a release with only that asset.
+pub fn direct_install(
+    direct_install: &DirectInstall,
+    settings: &Settings,
+) -> Result<Install, String> {
+    let url = Url::parse(&direct_install.url)
+        .map_err(|err| format!("{} isn't a valid URL: {}", direct_install.url, err))?;
+    let allowed_scheme =
+        url.scheme() == "https" || (url.scheme() == "http" && settings.direct_install_allow_http);
+    if !allowed_scheme {
+        return Err(format!("{} isn't an https URL", direct_install.url));
+    }
+    let host = url.host_str().unwrap_or_default();
+    // Forks are published anywhere, a known flavor only on GitHub
+    let custom = direct_install.flavor == Some(CompatibilityToolFlavor::Unknown);
+    if !custom && !ALLOWED_HOSTS.contains(&host) {
+        return Err(format!(
+            "{} isn't a GitHub download host, only {} are allowed",
+            host,
+            ALLOWED_HOSTS.join(", ")
+        ));
+    }
+    let name =
+        archive_name(&url).ok_or_else(|| format!("{} doesn't name a file", direct_install.url))?;
+    let stem = archive_stem(&name).ok_or_else(|| unsupported_archive(&name))?;
+
+    let (download_flavor, download_tag) = release_download(&url).unwrap_or_default();
+    let (archive_flavor, archive_tag) = infer_from_archive(stem).unzip();
+    let flavor = direct_install
+        .flavor
+        .clone()
+        .or(download_flavor)
+        .or(archive_flavor)
+        .ok_or_else(|| format!("Couldn't tell the flavor of {}, pick one", name))?;
+    let tag_name = direct_install
+        .tag_name
+        .clone()
+        .or(Some(download_tag).filter(|tag_name| !tag_name.is_empty()))
+        .or(archive_tag.flatten())
+        .or_else(|| custom.then(|| stem.to_string()))
+        .ok_or_else(|| format!("Couldn't tell the release of {}, enter its tag", name))?;
+    // Tags end up in directory, internal and display names
+    validate_name(NameKind::Internal, &tag_name).map_err(|err| err.to_string())?;
+
+    Ok(Install {
+        flavor,
+        release: Release {
+            url: direct_install.url.clone(),
+            id: 0,
+            draft: false,
+            prerelease: false,
+            name: tag_name.clone(),
+            tag_name,
+            target_commitish: String::new(),
+            assets: vec![Asset {
+                url: direct_install.url.clone(),
+                id: 0,
+                name,
+                content_type: String::new(),
+                state: "uploaded".to_string(),
+                // Unknown until the download starts
+                size: 0,
+                download_count: 0,
+                created_at: String::new(),
+                updated_at: String::new(),
+                browser_download_url: direct_install.url.clone(),
+            }],
+            created_at: String::new(),
+            published_at: String::new(),
+            tarball_url: String::new(),
+            body: String::new(),
+        },
+        ignore_network_cap: false,
+        ignore_disk_space: false,
+        background: false,
+        accept_local_changes_loss: false,
+        copy_install: false,
+        replaces: Vec::new(),
+        migrate_mappings: false,
+        max_size: Some(settings.direct_install_max_size.unwrap_or(DEFAULT_MAX_SIZE)),
+        local_path: None,
+        restart_steam: false,
+        trigger: HistoryTrigger::Manual,
+    })
+}
+
+/// Declares the single directory extracted into `directory` as a compatibility tool named after
+/// it, unless it declares itself. Returns the name of the tool if it was declared.
+pub fn declare_custom_tool(directory: &Path) -> io::Result<Option<String>> {
+    let children: Vec<PathBuf> = fs::read_dir(directory)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    let [tool] = &children[..] else {
+        return Ok(None);
+    };
+    if tool.join("compatibilitytool.vdf").exists() {
+        return Ok(None);
+    }
+    let name = tool.file_name().unwrap().to_string_lossy().to_string();
+    validate_name(NameKind::Internal, &name)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    generate_compatibility_tool_vdf(tool.join("compatibilitytool.vdf"), &name, &name);
+    Ok(Some(name))
+}
+
+impl WineCask {
+    /// Queues the install of an asset URL, which works while the GitHub API is blocked.
+    pub async fn install_from_url(&self, peer_map: &PeerMap, direct: DirectInstall) {
+        let settings = self.app_state.lock().await.settings.clone();
+        let install = match direct_install(&direct, &settings) {
+            Ok(install) => install,
+            Err(error_message) => {
+                let app_error = AppError::new(AppErrorCode::InvalidRequest, error_message);
+                error!("{}", app_error);
+                self.broadcast_app_error(peer_map, app_error).await;
+                return;
+            }
+        };
+        info!(
+            "Installing {} {} from {}",
+            install.flavor, install.release.tag_name, direct.url
+        );
+        let task = Task {
+            id: 0,
+            r#type: TaskType::InstallCompatibilityTool,
+            install: Some(install),
+            uninstall: None,
+            migrate: None,
+            mapping: None,
+            mappings: None,
+            import: None,
+            direct_install: None,
+            update_all: None,
+            local_file: None,
+            delete_compat_data: None,
+            restore_prefix_backup: None,
+        };
+        self.add_to_task_queue(task, peer_map).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn install(url: &str) -> Result<Install, String> {
+        direct_install(
+            &DirectInstall {
+                url: url.to_string(),
+                flavor: None,
+                tag_name: None,
+            },
+            &Settings::default(),
+        )
+    }
+
+    fn inferred(url: &str) -> (CompatibilityToolFlavor, String) {
+        let install = install(url).unwrap();
+        (install.flavor, install.release.tag_name)
+    }
+
+    #[test]
+    fn test_flavor_and_tag_are_inferred() {
+        assert_eq!(
+            inferred("https://github.com/GloriousEggroll/proton-ge-custom/releases/download/GE-Proton9-21/GE-Proton9-21.tar.gz"),
+            (CompatibilityToolFlavor::ProtonGE, "GE-Proton9-21".to_string())
+        );
+        // The release path tells the tag the archive name doesn't
+        assert_eq!(
+            inferred("https://github.com/dreamer/boxtron/releases/download/v0.5.4/boxtron.tar.xz"),
+            (CompatibilityToolFlavor::Boxtron, "v0.5.4".to_string())
+        );
+        assert_eq!(
+            inferred("https://github.com/dreamer/roberta/releases/download/v0.1.0/roberta.tar.xz"),
+            (CompatibilityToolFlavor::Roberta, "v0.1.0".to_string())
+        );
+        assert_eq!(
+            inferred("https://objects.githubusercontent.com/github-production-release-asset-2e65be/123/456?X-Amz-Algorithm=AWS4-HMAC-SHA256&response-content-disposition=attachment%3B%20filename%3DGE-Proton8-25.tar.gz&response-content-type=application%2Foctet-stream"),
+            (CompatibilityToolFlavor::ProtonGE, "GE-Proton8-25".to_string())
+        );
+        assert_eq!(
+            inferred("https://objects.githubusercontent.com/assets/luxtorpeda-v68.0.0.tar.xz"),
+            (CompatibilityToolFlavor::Luxtorpeda, "v68.0.0".to_string())
+        );
+
+        let install = install(
+            "https://github.com/GloriousEggroll/proton-ge-custom/releases/download/GE-Proton9-21/GE-Proton9-21.tar.gz",
+        )
+        .unwrap();
+        assert_eq!(
+            install.release.assets[0].browser_download_url,
+            install.release.url
+        );
+        assert_eq!(install.release.assets[0].name, "GE-Proton9-21.tar.gz");
+    }
+
+    #[test]
+    fn test_urls_are_checked() {
+        let error = install("https://example.com/GE-Proton9-21.tar.gz")
+            .err()
+            .unwrap();
+        assert!(error.starts_with("example.com isn't a GitHub download host"));
+        assert!(
+            install("http://github.com/a/b/releases/download/c/GE-Proton9-21.tar.gz")
+                .err()
+                .unwrap()
+                .ends_with("isn't an https URL")
+        );
+        assert_eq!(
+            install("https://objects.githubusercontent.com/GE-Proton9-21.7z")
+                .err()
+                .unwrap(),
+            "GE-Proton9-21.7z isn't a gzip, xz, zstd, tar or zip archive"
+        );
+        assert_eq!(
+            install("https://objects.githubusercontent.com/wine-custom.tar.gz")
+                .err()
+                .unwrap(),
+            "Couldn't tell the flavor of wine-custom.tar.gz, pick one"
+        );
+        assert_eq!(
+            install("https://objects.githubusercontent.com/boxtron.tar.xz")
+                .err()
+                .unwrap(),
+            "Couldn't tell the release of boxtron.tar.xz, enter its tag"
+        );
+
+        // Given along with the URL
+        let install = direct_install(
+            &DirectInstall {
+                url: "https://objects.githubusercontent.com/boxtron.tar.xz".to_string(),
+                flavor: None,
+                tag_name: Some("v0.5.4".to_string()),
+            },
+            &Settings::default(),
+        )
+        .unwrap();
+        assert_eq!(install.flavor, CompatibilityToolFlavor::Boxtron);
+        assert_eq!(install.release.tag_name, "v0.5.4");
+    }
+
+    #[test]
+    fn test_forks_install_from_any_host() {
+        let fork = |url: &str, settings: &Settings| {
+            direct_install(
+                &DirectInstall {
+                    url: url.to_string(),
+                    flavor: Some(CompatibilityToolFlavor::Unknown),
+                    tag_name: None,
+                },
+                settings,
+            )
+        };
+        let install = fork(
+            "https://mirror.cachyos.org/proton-cachyos-9.0-20241002-slr.tar.xz",
+            &Settings::default(),
+        )
+        .unwrap();
+        assert_eq!(install.flavor, CompatibilityToolFlavor::Unknown);
+        assert_eq!(install.release.tag_name, "proton-cachyos-9.0-20241002-slr");
+        assert_eq!(install.max_size, Some(DEFAULT_MAX_SIZE));
+
+        let http = "http://example.com/NorthstarProton-8-24.tar.gz";
+        assert!(fork(http, &Settings::default())
+            .err()
+            .unwrap()
+            .ends_with("isn't an https URL"));
+        let settings = Settings {
+            direct_install_allow_http: true,
+            direct_install_max_size: Some(1024),
+            ..Settings::default()
+        };
+        assert_eq!(fork(http, &settings).unwrap().max_size, Some(1024));
+    }
+
+    #[test]
+    fn test_custom_tools_are_declared_after_their_directory() {
+        let directory = tempdir().unwrap();
+        let tool = directory.path().join("NorthstarProton-8-24");
+        fs::create_dir_all(tool.join("files")).unwrap();
+        assert_eq!(
+            declare_custom_tool(directory.path()).unwrap().as_deref(),
+            Some("NorthstarProton-8-24")
+        );
+        let compatibility_tool = fs::read_to_string(tool.join("compatibilitytool.vdf")).unwrap();
+        assert!(compatibility_tool.contains("\"NorthstarProton-8-24\""));
+
+        // Declared by now, like tools shipping their own declaration
+        assert_eq!(declare_custom_tool(directory.path()).unwrap(), None);
+    }
+}
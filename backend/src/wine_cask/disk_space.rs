@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Extracted size of a release relative to its archive, compressed Proton builds unpack to about
+/// two and a half times their size.
+const EXTRACTION_FACTOR: f64 = 2.5;
+
+/// A filesystem without room for an install, sent to peers so they can tell how much to free.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct DiskSpaceShortage {
+    /// Directory on the filesystem that's short on space.
+    pub path: String,
+    pub required_bytes: u64,
+    pub available_bytes: u64,
+}
+
+/// Bytes needed on each filesystem to download an archive of `archive_size` bytes into
+/// `download_directory` and extract it into `install_directory`. The archive is kept until the
+/// extraction finished, a single filesystem needs room for both.
+pub fn required_space(
+    archive_size: u64,
+    download_directory: (&Path, Option<u64>),
+    install_directory: (&Path, Option<u64>),
+) -> Vec<(PathBuf, u64)> {
+    let extracted_size = (archive_size as f64 * EXTRACTION_FACTOR) as u64;
+    let (download_path, download_device) = download_directory;
+    let (install_path, install_device) = install_directory;
+    if download_device.is_some() && download_device == install_device {
+        vec![(install_path.to_path_buf(), archive_size + extracted_size)]
+    } else {
+        vec![
+            (download_path.to_path_buf(), archive_size),
+            (install_path.to_path_buf(), extracted_size),
+        ]
+    }
+}
+
+/// The path itself or the closest of its parents that exists, directories are created by the
+/// install itself.
+fn existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|ancestor| ancestor.exists())
+}
+
+/// Device the filesystem containing `path` is on.
+pub fn device(path: &Path) -> Option<u64> {
+    Some(existing_ancestor(path)?.metadata().ok()?.dev())
+}
+
+/// Available bytes from the output of `df --output=avail -B1`.
+pub fn parse_df_available(output: &str) -> Option<u64> {
+    output.lines().nth(1)?.trim().parse().ok()
+}
+
+/// Bytes available to unprivileged users on the filesystem containing `path`, `None` if that
+/// can't be told.
+pub fn available_space(path: &Path) -> Option<u64> {
+    let output = Command::new("df")
+        .args(["--output=avail", "-B1"])
+        .arg(existing_ancestor(path)?)
+        .output()
+        .ok()?;
+    parse_df_available(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The first filesystem without the space an install of an archive of `archive_size` bytes needs.
+/// Filesystems whose free space can't be told are assumed to have enough.
+pub fn check_disk_space(
+    archive_size: u64,
+    download_directory: &Path,
+    install_directory: &Path,
+) -> Option<DiskSpaceShortage> {
+    required_space(
+        archive_size,
+        (download_directory, device(download_directory)),
+        (install_directory, device(install_directory)),
+    )
+    .into_iter()
+    .find_map(|(path, required_bytes)| {
+        let available_bytes = available_space(&path)?;
+        (available_bytes < required_bytes).then(|| DiskSpaceShortage {
+            path: path.to_string_lossy().to_string(),
+            required_bytes,
+            available_bytes,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_filesystem_needs_room_for_both() {
+        let downloads = Path::new("/tmp/decky-wine-cellar/downloads");
+        let tools = Path::new("/home/deck/.steam/root/compatibilitytools.d");
+        assert_eq!(
+            required_space(400, (downloads, Some(1)), (tools, Some(1))),
+            vec![(tools.to_path_buf(), 1400)]
+        );
+        assert_eq!(
+            required_space(400, (downloads, Some(1)), (tools, Some(2))),
+            vec![(downloads.to_path_buf(), 400), (tools.to_path_buf(), 1000)]
+        );
+        assert_eq!(
+            required_space(400, (downloads, None), (tools, None)).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_parse_df_available() {
+        assert_eq!(
+            parse_df_available("       Avail\n314572800\n"),
+            Some(314572800)
+        );
+        assert_eq!(parse_df_available("       Avail\n"), None);
+        assert_eq!(parse_df_available(""), None);
+    }
+}
@@ -0,0 +1,500 @@
+use crate::app_id::AppId;
+use crate::steam_util::{running_game, steam_started_at};
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::reachability::GitHubReachability;
+use crate::wine_cask::requirements::SystemVersions;
+use crate::PeerMap;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::TryRecvError;
+
+/// How often the sampler looks for expired items, the shortest TTL.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What the backend knows about the system it runs on, `None` when it couldn't be determined.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+pub struct EnvironmentSnapshot {
+    pub on_battery: Option<bool>,
+    pub metered_network: Option<bool>,
+    /// When the running Steam client started as a unix timestamp, `None` if it isn't running.
+    pub steam_started_at: Option<u64>,
+    pub running_game: Option<AppId>,
+    /// Running in the gamescope session rather than on the desktop.
+    pub game_mode: Option<bool>,
+    pub system_versions: SystemVersions,
+    /// Seconds the system clock is ahead of the time servers report, only set when it's far enough
+    /// off to break certificate validation.
+    pub clock_skew: Option<i64>,
+    /// Set once a request to GitHub failed or went through, `None` until then.
+    pub github_reachability: Option<GitHubReachability>,
+}
+
+/// Changes other components react to.
+#[derive(PartialEq, Clone, Debug)]
+pub enum EnvironmentEvent {
+    ChargingChanged { on_battery: bool },
+    MeteredNetworkChanged { metered: bool },
+    GameStarted(AppId),
+    GameStopped(AppId),
+}
+
+/// Reads the environment, replaced by scripted values in tests.
+pub trait EnvironmentProbe: Send {
+    fn on_battery(&self) -> Option<bool>;
+    fn metered_network(&self) -> Option<bool>;
+    fn steam_started_at(&self) -> Option<u64>;
+    fn running_game(&self) -> Option<AppId>;
+    fn game_mode(&self) -> Option<bool>;
+    fn system_versions(&self) -> SystemVersions;
+}
+
+/// Probes the system files below `root` and NetworkManager.
+pub struct SystemProbe {
+    root: PathBuf,
+}
+
+impl SystemProbe {
+    pub fn new(root: PathBuf) -> SystemProbe {
+        SystemProbe { root }
+    }
+}
+
+/// Whether the system runs on battery, false for systems without one.
+pub fn on_battery(power_supply: &Path) -> Option<bool> {
+    let mut has_battery = false;
+    for entry in fs::read_dir(power_supply).ok()?.filter_map(Result::ok) {
+        let read = |name: &str| {
+            fs::read_to_string(entry.path().join(name))
+                .map(|value| value.trim().to_string())
+                .unwrap_or_default()
+        };
+        match read("type").as_str() {
+            "Mains" | "USB" if read("online") == "1" => return Some(false),
+            "Battery" => has_battery = true,
+            _ => {}
+        }
+    }
+    Some(has_battery)
+}
+
+/// Parses NetworkManager's `Metered` property as printed by `busctl get-property`.
+pub fn parse_metered(output: &str) -> Option<bool> {
+    match output.trim().strip_prefix("u ")? {
+        // Yes and guessed yes
+        "1" | "3" => Some(true),
+        "2" | "4" => Some(false),
+        _ => None,
+    }
+}
+
+/// Whether a process whose command name starts with `name` is running.
+fn process_running(proc_root: &Path, name: &str) -> Option<bool> {
+    Some(
+        fs::read_dir(proc_root)
+            .ok()?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().parse::<u32>().is_ok())
+            .any(|entry| {
+                fs::read_to_string(entry.path().join("comm"))
+                    .is_ok_and(|comm| comm.trim().starts_with(name))
+            }),
+    )
+}
+
+impl EnvironmentProbe for SystemProbe {
+    fn on_battery(&self) -> Option<bool> {
+        on_battery(&self.root.join("sys/class/power_supply"))
+    }
+
+    fn metered_network(&self) -> Option<bool> {
+        let output = Command::new("busctl")
+            .args([
+                "get-property",
+                "org.freedesktop.NetworkManager",
+                "/org/freedesktop/NetworkManager",
+                "org.freedesktop.NetworkManager",
+                "Metered",
+            ])
+            .output()
+            .ok()?;
+        parse_metered(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    fn steam_started_at(&self) -> Option<u64> {
+        steam_started_at(&self.root.join("proc"))
+    }
+
+    fn running_game(&self) -> Option<AppId> {
+        running_game(&self.root.join("proc"))
+    }
+
+    fn game_mode(&self) -> Option<bool> {
+        process_running(&self.root.join("proc"), "gamescope")
+    }
+
+    fn system_versions(&self) -> SystemVersions {
+        SystemVersions::detect_in(&self.root)
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+enum EnvironmentItem {
+    Charging,
+    MeteredNetwork,
+    Steam,
+    RunningGame,
+    GameMode,
+    SystemVersions,
+}
+
+impl EnvironmentItem {
+    const ALL: [EnvironmentItem; 6] = [
+        EnvironmentItem::Charging,
+        EnvironmentItem::MeteredNetwork,
+        EnvironmentItem::Steam,
+        EnvironmentItem::RunningGame,
+        EnvironmentItem::GameMode,
+        EnvironmentItem::SystemVersions,
+    ];
+
+    /// How long a sample stays current, `None` for what doesn't change while the backend runs.
+    fn ttl(self) -> Option<Duration> {
+        match self {
+            EnvironmentItem::Charging => Some(Duration::from_secs(30)),
+            EnvironmentItem::MeteredNetwork => Some(Duration::from_secs(60)),
+            EnvironmentItem::Steam | EnvironmentItem::GameMode => Some(Duration::from_secs(10)),
+            EnvironmentItem::RunningGame => Some(SAMPLE_INTERVAL),
+            EnvironmentItem::SystemVersions => None,
+        }
+    }
+}
+
+fn changes(previous: &EnvironmentSnapshot, current: &EnvironmentSnapshot) -> Vec<EnvironmentEvent> {
+    let mut events = Vec::new();
+    if let (Some(was), Some(on_battery)) = (previous.on_battery, current.on_battery) {
+        if was != on_battery {
+            events.push(EnvironmentEvent::ChargingChanged { on_battery });
+        }
+    }
+    if let (Some(was), Some(metered)) = (previous.metered_network, current.metered_network) {
+        if was != metered {
+            events.push(EnvironmentEvent::MeteredNetworkChanged { metered });
+        }
+    }
+    if previous.running_game != current.running_game {
+        events.extend(previous.running_game.map(EnvironmentEvent::GameStopped));
+        events.extend(current.running_game.map(EnvironmentEvent::GameStarted));
+    }
+    events
+}
+
+/// Samples the environment through a probe, caching each item for its TTL.
+pub struct Environment {
+    probe: Box<dyn EnvironmentProbe>,
+    snapshot: EnvironmentSnapshot,
+    sampled: HashMap<EnvironmentItem, Instant>,
+    events: broadcast::Sender<EnvironmentEvent>,
+}
+
+impl Environment {
+    pub fn new(probe: Box<dyn EnvironmentProbe>) -> Environment {
+        Environment {
+            probe,
+            snapshot: EnvironmentSnapshot::default(),
+            sampled: HashMap::new(),
+            events: broadcast::channel(16).0,
+        }
+    }
+
+    pub fn snapshot(&self) -> &EnvironmentSnapshot {
+        &self.snapshot
+    }
+
+    /// Receives the events of every later sample.
+    pub fn subscribe(&self) -> broadcast::Receiver<EnvironmentEvent> {
+        self.events.subscribe()
+    }
+
+    /// Probes the items whose sample expired and returns what changed.
+    pub fn sample(&mut self, now: Instant) -> Vec<EnvironmentEvent> {
+        let previous = self.snapshot.clone();
+        for item in EnvironmentItem::ALL {
+            let expired = match (self.sampled.get(&item), item.ttl()) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(sampled), Some(ttl)) => now.duration_since(*sampled) >= ttl,
+            };
+            if !expired {
+                continue;
+            }
+            self.sampled.insert(item, now);
+            let snapshot = &mut self.snapshot;
+            match item {
+                EnvironmentItem::Charging => snapshot.on_battery = self.probe.on_battery(),
+                EnvironmentItem::MeteredNetwork => {
+                    snapshot.metered_network = self.probe.metered_network()
+                }
+                EnvironmentItem::Steam => snapshot.steam_started_at = self.probe.steam_started_at(),
+                EnvironmentItem::RunningGame => snapshot.running_game = self.probe.running_game(),
+                EnvironmentItem::GameMode => snapshot.game_mode = self.probe.game_mode(),
+                EnvironmentItem::SystemVersions => {
+                    snapshot.system_versions = self.probe.system_versions()
+                }
+            }
+        }
+
+        let events = changes(&previous, &self.snapshot);
+        for event in &events {
+            info!("Environment changed: {:?}", event);
+            // Nobody may be listening
+            let _ = self.events.send(event.clone());
+        }
+        events
+    }
+
+    /// Records the result of a clock check, which the clock check schedules itself. Returns the
+    /// previous skew.
+    pub fn record_clock_skew(&mut self, clock_skew: Option<i64>) -> Option<i64> {
+        std::mem::replace(&mut self.snapshot.clock_skew, clock_skew)
+    }
+
+    /// Records whether GitHub could be reached, known from requests rather than sampled. Returns
+    /// the previous reachability.
+    pub fn record_github_reachability(
+        &mut self,
+        github_reachability: Option<GitHubReachability>,
+    ) -> Option<GitHubReachability> {
+        std::mem::replace(&mut self.snapshot.github_reachability, github_reachability)
+    }
+}
+
+/// Drains the received events, returning whether a game started or stopped. A receiver that fell
+/// behind may have missed one.
+pub fn game_changed(receiver: &mut broadcast::Receiver<EnvironmentEvent>) -> bool {
+    let mut changed = false;
+    loop {
+        match receiver.try_recv() {
+            Ok(EnvironmentEvent::GameStarted(_) | EnvironmentEvent::GameStopped(_))
+            | Err(TryRecvError::Lagged(_)) => changed = true,
+            Ok(_) => {}
+            Err(_) => return changed,
+        }
+    }
+}
+
+impl WineCask {
+    /// Samples the expired items and copies the snapshot into the app state.
+    pub async fn sample_environment(&self) -> (EnvironmentSnapshot, Vec<EnvironmentEvent>) {
+        let environment = Arc::clone(&self.environment);
+        let (snapshot, events) = tokio::task::spawn_blocking(move || {
+            let mut environment = environment.lock().unwrap();
+            let events = environment.sample(Instant::now());
+            (environment.snapshot().clone(), events)
+        })
+        .await
+        .unwrap();
+        self.app_state.lock().await.environment = snapshot.clone();
+        (snapshot, events)
+    }
+
+    pub async fn record_clock_skew(&self, clock_skew: Option<i64>) -> Option<i64> {
+        let (previous, snapshot) = {
+            let mut environment = self.environment.lock().unwrap();
+            let previous = environment.record_clock_skew(clock_skew);
+            (previous, environment.snapshot().clone())
+        };
+        self.app_state.lock().await.environment = snapshot;
+        previous
+    }
+}
+
+/// Keeps the environment sampled so subscribers hear about changes, sends the app state along
+/// whenever something changed.
+pub async fn run_environment_sampler(wine_cask: Arc<WineCask>, peer_map: PeerMap) {
+    loop {
+        tokio::time::sleep(SAMPLE_INTERVAL).await;
+        let (_, events) = wine_cask.sample_environment().await;
+        if !events.is_empty() {
+            wine_cask.broadcast_app_state(&peer_map).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wine_cask::background::{admit_task, Admission};
+    use crate::wine_cask::settings::Settings;
+    use std::sync::Mutex;
+    use tempfile::tempdir;
+
+    /// Returns whatever the test last put into the shared snapshot.
+    struct ScriptedProbe {
+        script: Arc<Mutex<EnvironmentSnapshot>>,
+        probes: Arc<Mutex<usize>>,
+    }
+
+    impl ScriptedProbe {
+        fn read<T>(&self, read: impl FnOnce(&EnvironmentSnapshot) -> T) -> T {
+            *self.probes.lock().unwrap() += 1;
+            read(&self.script.lock().unwrap())
+        }
+    }
+
+    impl EnvironmentProbe for ScriptedProbe {
+        fn on_battery(&self) -> Option<bool> {
+            self.read(|script| script.on_battery)
+        }
+
+        fn metered_network(&self) -> Option<bool> {
+            self.read(|script| script.metered_network)
+        }
+
+        fn steam_started_at(&self) -> Option<u64> {
+            self.read(|script| script.steam_started_at)
+        }
+
+        fn running_game(&self) -> Option<AppId> {
+            self.read(|script| script.running_game)
+        }
+
+        fn game_mode(&self) -> Option<bool> {
+            self.read(|script| script.game_mode)
+        }
+
+        fn system_versions(&self) -> SystemVersions {
+            self.read(|script| script.system_versions.clone())
+        }
+    }
+
+    fn scripted_environment() -> (
+        Environment,
+        Arc<Mutex<EnvironmentSnapshot>>,
+        Arc<Mutex<usize>>,
+    ) {
+        let script = Arc::new(Mutex::new(EnvironmentSnapshot {
+            on_battery: Some(false),
+            metered_network: Some(false),
+            ..EnvironmentSnapshot::default()
+        }));
+        let probes = Arc::new(Mutex::new(0));
+        let environment = Environment::new(Box::new(ScriptedProbe {
+            script: Arc::clone(&script),
+            probes: Arc::clone(&probes),
+        }));
+        (environment, script, probes)
+    }
+
+    #[test]
+    fn test_items_are_cached_for_their_ttl() {
+        let (mut environment, script, probes) = scripted_environment();
+        let start = Instant::now();
+        environment.sample(start);
+        assert_eq!(*probes.lock().unwrap(), 6);
+
+        script.lock().unwrap().on_battery = Some(true);
+        // Only the running game expired
+        assert!(environment.sample(start + SAMPLE_INTERVAL).is_empty());
+        assert_eq!(*probes.lock().unwrap(), 7);
+        assert_eq!(environment.snapshot().on_battery, Some(false));
+
+        let mut subscription = environment.subscribe();
+        assert_eq!(
+            environment.sample(start + Duration::from_secs(30)),
+            vec![EnvironmentEvent::ChargingChanged { on_battery: true }]
+        );
+        assert_eq!(
+            subscription.try_recv().unwrap(),
+            EnvironmentEvent::ChargingChanged { on_battery: true }
+        );
+        // The metered network wasn't due yet
+        assert_eq!(*probes.lock().unwrap(), 7 + 4);
+        // System versions are sampled only once
+        environment.sample(start + Duration::from_secs(3600));
+        assert_eq!(*probes.lock().unwrap(), 7 + 4 + 5);
+    }
+
+    #[test]
+    fn test_scheduler_follows_environment_transitions() {
+        let (mut environment, script, _) = scripted_environment();
+        let settings = Settings {
+            background_while_gaming: true,
+            ..Settings::default()
+        };
+        let game = AppId::new(1245620).unwrap();
+        let mut now = Instant::now();
+        environment.sample(now);
+        let mut subscription = environment.subscribe();
+        let mut step = |update: &dyn Fn(&mut EnvironmentSnapshot)| {
+            update(&mut script.lock().unwrap());
+            now += Duration::from_secs(60);
+            let events = environment.sample(now);
+            let admissions = (
+                admit_task(true, environment.snapshot(), &settings),
+                admit_task(false, environment.snapshot(), &settings),
+            );
+            (events, admissions)
+        };
+
+        // On battery, background installs wait while interactive ones run at full speed
+        let (events, (background, interactive)) = step(&|script| script.on_battery = Some(true));
+        assert_eq!(
+            events,
+            vec![EnvironmentEvent::ChargingChanged { on_battery: true }]
+        );
+        assert!(matches!(background, Admission::Defer(_)));
+        assert_eq!(interactive, Admission::Run(None));
+
+        assert!(!game_changed(&mut subscription));
+
+        // Charging, background installs run constrained
+        let (events, (background, interactive)) = step(&|script| script.on_battery = Some(false));
+        assert_eq!(
+            events,
+            vec![EnvironmentEvent::ChargingChanged { on_battery: false }]
+        );
+        let Admission::Run(Some(constraints)) = background else {
+            panic!("Background install wasn't admitted: {:?}", background);
+        };
+        assert_eq!(constraints.running_game, None);
+        assert_eq!(interactive, Admission::Run(None));
+
+        // A game starts, both yield to it
+        let (events, (background, interactive)) = step(&|script| script.running_game = Some(game));
+        assert_eq!(events, vec![EnvironmentEvent::GameStarted(game)]);
+        assert!(game_changed(&mut subscription));
+        for admission in [background, interactive] {
+            let Admission::Run(Some(constraints)) = admission else {
+                panic!("Install wasn't constrained: {:?}", admission);
+            };
+            assert_eq!(constraints.running_game, Some(game));
+        }
+    }
+
+    #[test]
+    fn test_system_probes() {
+        let power_supply = tempdir().unwrap();
+        for (name, kind, online) in [("ACAD", "Mains", "0"), ("BAT1", "Battery", "")] {
+            let supply = power_supply.path().join(name);
+            fs::create_dir_all(&supply).unwrap();
+            fs::write(supply.join("type"), format!("{}\n", kind)).unwrap();
+            fs::write(supply.join("online"), online).unwrap();
+        }
+        assert_eq!(on_battery(power_supply.path()), Some(true));
+        fs::write(power_supply.path().join("ACAD/online"), "1\n").unwrap();
+        assert_eq!(on_battery(power_supply.path()), Some(false));
+        assert_eq!(on_battery(&power_supply.path().join("missing")), None);
+
+        assert_eq!(parse_metered("u 3\n"), Some(true));
+        assert_eq!(parse_metered("u 4\n"), Some(false));
+        assert_eq!(parse_metered("u 0\n"), None);
+        assert_eq!(parse_metered(""), None);
+    }
+}
@@ -0,0 +1,329 @@
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, WineCask};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::PeerMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Errors of the same kind within this window are folded into one event.
+const AGGREGATION_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum ErrorSource {
+    Network,
+    Filesystem,
+    Steam,
+}
+
+#[derive(Clone, Debug)]
+pub struct ErrorReport {
+    /// Identifies the kind of failure, e.g. `release_fetch_failed`.
+    pub code: &'static str,
+    pub source: ErrorSource,
+    /// What failed, e.g. a repository or tool name.
+    pub target: String,
+    pub error: AppError,
+    /// Failures that end a task are always sent on their own.
+    pub terminal: bool,
+}
+
+/// One or more errors of the same code and source.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ErrorGroup {
+    pub code: String,
+    pub source: ErrorSource,
+    /// Code of the first error in the group.
+    pub error_code: AppErrorCode,
+    /// Message of the first error in the group.
+    pub message: String,
+    pub count: u32,
+    /// Distinct targets in the order they failed.
+    pub targets: Vec<String>,
+    /// Sent when the window closed, repeats the errors folded since the first event.
+    pub summary: bool,
+}
+
+impl ErrorGroup {
+    fn new(report: ErrorReport) -> Self {
+        ErrorGroup {
+            code: report.code.to_string(),
+            source: report.source,
+            error_code: report.error.code,
+            message: report.error.message,
+            count: 1,
+            targets: vec![report.target],
+            summary: false,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        if self.summary {
+            format!(
+                "{} ({} times, affecting {})",
+                self.message,
+                self.count,
+                self.targets.join(", ")
+            )
+        } else {
+            format!("{}: {}", self.targets.join(", "), self.message)
+        }
+    }
+}
+
+#[derive(Clone)]
+struct OpenGroup {
+    opened: Instant,
+    group: ErrorGroup,
+}
+
+#[derive(Clone)]
+pub struct ErrorAggregator {
+    window: Duration,
+    open: HashMap<(String, ErrorSource), OpenGroup>,
+}
+
+impl Default for ErrorAggregator {
+    fn default() -> Self {
+        ErrorAggregator::new(AGGREGATION_WINDOW)
+    }
+}
+
+impl ErrorAggregator {
+    pub fn new(window: Duration) -> Self {
+        ErrorAggregator {
+            window,
+            open: HashMap::new(),
+        }
+    }
+
+    /// Returns the events to send right away. The first error of a kind opens a window and is
+    /// sent immediately, later ones are folded into it until `flush` closes the window.
+    pub fn submit(&mut self, report: ErrorReport, now: Instant) -> Vec<ErrorGroup> {
+        if report.terminal {
+            return vec![ErrorGroup::new(report)];
+        }
+
+        let key = (report.code.to_string(), report.source);
+        if let Some(open) = self.open.get_mut(&key) {
+            if now.saturating_duration_since(open.opened) < self.window {
+                open.group.count += 1;
+                if !open.group.targets.contains(&report.target) {
+                    open.group.targets.push(report.target);
+                }
+                return Vec::new();
+            }
+        }
+
+        // Close an expired window that wasn't flushed yet
+        let mut events: Vec<ErrorGroup> = self
+            .open
+            .remove(&key)
+            .and_then(|open| summarize(open.group))
+            .into_iter()
+            .collect();
+        let group = ErrorGroup::new(report);
+        events.push(group.clone());
+        self.open.insert(key, OpenGroup { opened: now, group });
+        events
+    }
+
+    /// Closes every window older than the aggregation window, returns summaries of the ones that
+    /// folded more than their first error.
+    pub fn flush(&mut self, now: Instant) -> Vec<ErrorGroup> {
+        let window = self.window;
+        let expired: Vec<(String, ErrorSource)> = self
+            .open
+            .iter()
+            .filter(|(_, open)| now.saturating_duration_since(open.opened) >= window)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut summaries: Vec<ErrorGroup> = expired
+            .iter()
+            .filter_map(|key| self.open.remove(key))
+            .filter_map(|open| summarize(open.group))
+            .collect();
+        summaries.sort_by(|a, b| a.code.cmp(&b.code));
+        summaries
+    }
+}
+
+fn summarize(group: ErrorGroup) -> Option<ErrorGroup> {
+    (group.count > 1).then_some(ErrorGroup {
+        summary: true,
+        ..group
+    })
+}
+
+fn error_request(group: ErrorGroup) -> Request {
+    Request {
+        app_error: Some(AppError::new(group.error_code, group.describe())),
+        error_group: Some(group),
+        ..Request::new(RequestType::Error)
+    }
+}
+
+impl WineCask {
+    /// Broadcasts an error, folding bursts of the same error into one event and a summary.
+    pub async fn broadcast_error(&self, peer_map: &PeerMap, report: ErrorReport) {
+        let terminal = report.terminal;
+        let events = self
+            .app_state
+            .lock()
+            .await
+            .error_aggregator
+            .submit(report, Instant::now());
+        if !terminal && !events.is_empty() {
+            // A window opened, send its summary once it closes
+            let app_state = self.app_state.clone();
+            let peer_map = peer_map.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(AGGREGATION_WINDOW).await;
+                let summaries = app_state
+                    .lock()
+                    .await
+                    .error_aggregator
+                    .flush(Instant::now());
+                for summary in summaries {
+                    broadcast_to_peers(&peer_map, &error_request(summary)).await;
+                }
+            });
+        }
+        for event in events {
+            broadcast_to_peers(peer_map, &error_request(event)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(code: &'static str, source: ErrorSource, target: &str) -> ErrorReport {
+        ErrorReport {
+            code,
+            source,
+            target: target.to_string(),
+            error: AppError::new(AppErrorCode::NetworkError, "error sending request"),
+            terminal: false,
+        }
+    }
+
+    #[test]
+    fn test_bursts_are_folded_into_a_summary() {
+        let window = Duration::from_secs(2);
+        let start = Instant::now();
+        let mut aggregator = ErrorAggregator::new(window);
+        let mut events = Vec::new();
+
+        // The network drops, every refresh fails within a second
+        for (offset, target) in [
+            (0, "GloriousEggroll/proton-ge-custom"),
+            (200, "luxtorpeda-dev/luxtorpeda"),
+            (400, "dreamer/boxtron"),
+            (600, "dreamer/boxtron"),
+        ] {
+            let now = start + Duration::from_millis(offset);
+            events.extend(aggregator.submit(
+                report("release_fetch_failed", ErrorSource::Network, target),
+                now,
+            ));
+        }
+        // A different error passes through right away
+        events.extend(aggregator.submit(
+            report("mapping_write_failed", ErrorSource::Steam, "config.vdf"),
+            start + Duration::from_millis(700),
+        ));
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].count, 1);
+        assert_eq!(events[0].targets, vec!["GloriousEggroll/proton-ge-custom"]);
+        assert_eq!(events[1].code, "mapping_write_failed");
+
+        // Windows only close once they are old enough
+        assert!(aggregator.flush(start + Duration::from_secs(1)).is_empty());
+        let summaries = aggregator.flush(start + window + Duration::from_millis(700));
+        assert_eq!(summaries.len(), 1);
+        assert!(summaries[0].summary);
+        assert_eq!(summaries[0].count, 4);
+        assert_eq!(
+            summaries[0].targets,
+            vec![
+                "GloriousEggroll/proton-ge-custom",
+                "luxtorpeda-dev/luxtorpeda",
+                "dreamer/boxtron"
+            ]
+        );
+        assert_eq!(
+            summaries[0].describe(),
+            "error sending request (4 times, affecting GloriousEggroll/proton-ge-custom, luxtorpeda-dev/luxtorpeda, dreamer/boxtron)"
+        );
+
+        // The next burst starts a new window
+        let events = aggregator.submit(
+            report(
+                "release_fetch_failed",
+                ErrorSource::Network,
+                "dreamer/boxtron",
+            ),
+            start + Duration::from_secs(10),
+        );
+        assert_eq!(events.len(), 1);
+        assert!(!events[0].summary);
+    }
+
+    #[test]
+    fn test_grouping_key_is_code_and_source() {
+        let start = Instant::now();
+        let mut aggregator = ErrorAggregator::new(Duration::from_secs(2));
+        let events: Vec<ErrorGroup> = [
+            report("download_failed", ErrorSource::Network, "GE-Proton9-20"),
+            report("download_failed", ErrorSource::Filesystem, "GE-Proton9-20"),
+            report(
+                "release_fetch_failed",
+                ErrorSource::Network,
+                "dreamer/boxtron",
+            ),
+            report("download_failed", ErrorSource::Network, "GE-Proton9-21"),
+        ]
+        .into_iter()
+        .flat_map(|report| aggregator.submit(report, start))
+        .collect();
+        assert_eq!(events.len(), 3);
+    }
+
+    #[test]
+    fn test_terminal_failures_are_never_aggregated() {
+        let start = Instant::now();
+        let mut aggregator = ErrorAggregator::new(Duration::from_secs(2));
+        for _ in 0..3 {
+            let terminal = ErrorReport {
+                terminal: true,
+                ..report("download_failed", ErrorSource::Network, "GE-Proton9-20")
+            };
+            let events = aggregator.submit(terminal, start);
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].count, 1);
+        }
+        assert!(aggregator.flush(start + Duration::from_secs(10)).is_empty());
+    }
+
+    #[test]
+    fn test_unflushed_windows_are_summarized_by_the_next_error() {
+        let start = Instant::now();
+        let mut aggregator = ErrorAggregator::new(Duration::from_secs(2));
+        let failure = || {
+            report(
+                "release_fetch_failed",
+                ErrorSource::Network,
+                "dreamer/boxtron",
+            )
+        };
+        aggregator.submit(failure(), start);
+        aggregator.submit(failure(), start);
+
+        let events = aggregator.submit(failure(), start + Duration::from_secs(5));
+        assert_eq!(events.len(), 2);
+        assert!(events[0].summary);
+        assert_eq!(events[0].count, 2);
+        assert!(!events[1].summary);
+    }
+}
@@ -0,0 +1,943 @@
+use crate::runtime_directory;
+use crate::wine_cask::cancellation::CancellationToken;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+use std::{fs, io};
+
+const KIB: usize = 1024;
+const MIB: usize = 1024 * KIB;
+/// Bytes written by the throughput probe.
+const PROBE_SIZE: usize = 4 * MIB;
+/// Probed throughput above which a device of unknown type is treated like internal storage.
+const FAST_THROUGHPUT: f64 = 200.0 * MIB as f64;
+/// Largest file handed to the writer threads, larger ones are streamed to disk by the reading
+/// thread so the queue holds a few megabytes at most.
+const PARALLEL_FILE_LIMIT: u64 = 2 * MIB as u64;
+/// File type bits of a unix mode, and the type of a symlink.
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum StorageClass {
+    Nvme,
+    Ssd,
+    Sd,
+    Rotational,
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum ExtractionStrategy {
+    /// Write one file at a time, small buffers keep slow cards responsive.
+    Serial { buffer_size: usize },
+    /// Hand files to a bounded pool of writer threads.
+    Parallel { buffer_size: usize, writers: usize },
+}
+
+/// What was chosen for a device and how fast the last extraction to it was.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct StorageMeasurement {
+    pub storage_class: StorageClass,
+    /// Measured by a short write burst, only probed for devices of unknown type.
+    pub probed_bytes_per_second: Option<f64>,
+    pub strategy: ExtractionStrategy,
+    pub achieved_bytes_per_second: Option<f64>,
+}
+
+pub fn classify_device(device_name: &str, rotational: Option<bool>) -> StorageClass {
+    if rotational == Some(true) {
+        StorageClass::Rotational
+    } else if device_name.starts_with("nvme") {
+        StorageClass::Nvme
+    } else if device_name.starts_with("mmcblk") {
+        StorageClass::Sd
+    } else if rotational == Some(false) {
+        StorageClass::Ssd
+    } else {
+        StorageClass::Unknown
+    }
+}
+
+/// Looks up the block device in sysfs, partitions take the queue attributes of their disk.
+pub fn detect_storage_class(sysfs_root: &Path, major: u64, minor: u64) -> StorageClass {
+    let Ok(device) = sysfs_root
+        .join("dev/block")
+        .join(format!("{}:{}", major, minor))
+        .canonicalize()
+    else {
+        return StorageClass::Unknown;
+    };
+    let device_name = device
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let rotational = [device.join("queue/rotational")]
+        .into_iter()
+        .chain(device.parent().map(|disk| disk.join("queue/rotational")))
+        .find_map(|path| fs::read_to_string(path).ok())
+        .map(|rotational| rotational.trim() == "1");
+    classify_device(&device_name, rotational)
+}
+
+pub fn select_strategy(
+    storage_class: StorageClass,
+    probed_bytes_per_second: Option<f64>,
+    force_serial: bool,
+) -> ExtractionStrategy {
+    let fast = match storage_class {
+        StorageClass::Nvme | StorageClass::Ssd => true,
+        StorageClass::Sd | StorageClass::Rotational => false,
+        StorageClass::Unknown => {
+            probed_bytes_per_second.is_some_and(|throughput| throughput >= FAST_THROUGHPUT)
+        }
+    };
+    match (fast, force_serial) {
+        (true, false) => ExtractionStrategy::Parallel {
+            buffer_size: MIB,
+            writers: 4,
+        },
+        (true, true) => ExtractionStrategy::Serial { buffer_size: MIB },
+        (false, _) => ExtractionStrategy::Serial {
+            buffer_size: 64 * KIB,
+        },
+    }
+}
+
+/// Writes a short burst to `directory` and returns the achieved bytes per second.
+pub fn measure_write_throughput(directory: &Path) -> io::Result<f64> {
+    let probe_file = directory.join(".wine-cellar-probe");
+    let started = Instant::now();
+    let result = (|| {
+        let mut file = File::create(&probe_file)?;
+        let chunk = vec![0u8; 64 * KIB];
+        for _ in 0..PROBE_SIZE / chunk.len() {
+            file.write_all(&chunk)?;
+        }
+        file.sync_all()
+    })();
+    let elapsed = started.elapsed().as_secs_f64();
+    let _ = fs::remove_file(&probe_file);
+    result?;
+    Ok(PROBE_SIZE as f64 / elapsed.max(f64::EPSILON))
+}
+
+fn device_key(directory: &Path) -> io::Result<(String, u64, u64)> {
+    let dev = fs::metadata(directory)?.dev();
+    // Same encoding as glibc's major() and minor()
+    let major = ((dev >> 32) & 0xffff_f000) | ((dev >> 8) & 0x0000_0fff);
+    let minor = ((dev >> 12) & 0xffff_ff00) | (dev & 0x0000_00ff);
+    Ok((format!("{}:{}", major, minor), major, minor))
+}
+
+fn measurements_file() -> PathBuf {
+    runtime_directory().join("storage_measurements.json")
+}
+
+fn load_measurements(file: &Path) -> HashMap<String, StorageMeasurement> {
+    fs::read_to_string(file)
+        .ok()
+        .and_then(|string| serde_json::from_str(&string).ok())
+        .unwrap_or_default()
+}
+
+/// Counts the bytes read through it, so progress can be told from how much of a compressed
+/// archive was read.
+pub struct CountingReader<R> {
+    inner: R,
+    read: Arc<AtomicU64>,
+}
+
+impl<R> CountingReader<R> {
+    /// Returns the reader and the count of bytes read through it so far.
+    pub fn new(inner: R) -> (CountingReader<R>, Arc<AtomicU64>) {
+        let read = Arc::new(AtomicU64::new(0));
+        let reader = CountingReader {
+            inner,
+            read: read.clone(),
+        };
+        (reader, read)
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buffer)?;
+        self.read.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// Zip archives are read out of order, seeking doesn't count as reading.
+impl<R: Seek> Seek for CountingReader<R> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(position)
+    }
+}
+
+/// Extracts `archive` into `destination` with the strategy for its device, reusing earlier
+/// measurements of the same device and recording the achieved throughput.
+pub fn extract_adaptive(
+    archive: impl Read,
+    destination: &Path,
+    force_serial: bool,
+    cancellation: &CancellationToken,
+    on_entry: impl FnMut(),
+) -> io::Result<()> {
+    fs::create_dir_all(destination)?;
+    let file = measurements_file();
+    let mut measurements = load_measurements(&file);
+    let (key, major, minor) = device_key(destination)?;
+
+    let mut measurement = match measurements.get(&key) {
+        Some(measurement) => measurement.clone(),
+        None => {
+            let storage_class = detect_storage_class(Path::new("/sys"), major, minor);
+            let probed_bytes_per_second = if storage_class == StorageClass::Unknown {
+                measure_write_throughput(destination)
+                    .map_err(|err| warn!("Failed to measure write throughput: {}", err))
+                    .ok()
+            } else {
+                None
+            };
+            StorageMeasurement {
+                storage_class,
+                probed_bytes_per_second,
+                strategy: select_strategy(storage_class, probed_bytes_per_second, false),
+                achieved_bytes_per_second: None,
+            }
+        }
+    };
+    // The override only applies to this extraction, the measurement stays reusable
+    let strategy = if force_serial {
+        select_strategy(
+            measurement.storage_class,
+            measurement.probed_bytes_per_second,
+            true,
+        )
+    } else {
+        measurement.strategy
+    };
+
+    let started = Instant::now();
+    let written = extract(archive, destination, strategy, cancellation, on_entry)?;
+    let achieved = written as f64 / started.elapsed().as_secs_f64().max(f64::EPSILON);
+    info!(
+        "Extracted {} bytes to {:?} storage using {:?} at {:.1} MiB/s",
+        written,
+        measurement.storage_class,
+        strategy,
+        achieved / MIB as f64
+    );
+
+    if strategy == measurement.strategy {
+        measurement.achieved_bytes_per_second = Some(achieved);
+    }
+    measurements.insert(key, measurement);
+    let saved = serde_json::to_string(&measurements)
+        .map_err(io::Error::from)
+        .and_then(|json| {
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&file, json)
+        });
+    if let Err(err) = saved {
+        warn!("Failed to save storage measurements: {}", err);
+    }
+    Ok(())
+}
+
+/// Returns the archive path without leading `./`, `None` if it would escape the destination.
+pub fn sanitize(path: &Path) -> Option<PathBuf> {
+    let mut sanitized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => sanitized.push(name),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!sanitized.as_os_str().is_empty()).then_some(sanitized)
+}
+
+fn write_file(
+    reader: &mut impl Read,
+    target: &Path,
+    mode: u32,
+    buffer_size: usize,
+) -> io::Result<()> {
+    let mut writer = BufWriter::with_capacity(buffer_size, File::create(target)?);
+    io::copy(reader, &mut writer)?;
+    writer.flush()?;
+    fs::set_permissions(target, fs::Permissions::from_mode(mode & 0o7777))
+}
+
+/// Extracts a tar archive as it's read, returning the number of file bytes written. Calls
+/// `on_entry` as each entry is reached and stops between entries once `cancellation` is
+/// cancelled, leaving what was extracted so far.
+pub fn extract(
+    archive: impl Read,
+    destination: &Path,
+    strategy: ExtractionStrategy,
+    cancellation: &CancellationToken,
+    mut on_entry: impl FnMut(),
+) -> io::Result<u64> {
+    fs::create_dir_all(destination)?;
+    let canonical_destination = destination.canonicalize()?;
+    let (buffer_size, writers) = match strategy {
+        ExtractionStrategy::Serial { buffer_size } => (buffer_size, 0),
+        ExtractionStrategy::Parallel {
+            buffer_size,
+            writers,
+        } => (buffer_size, writers),
+    };
+
+    let mut hard_links: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let written = thread::scope(|scope| -> io::Result<u64> {
+        let (sender, receiver) = sync_channel::<(PathBuf, Vec<u8>, u32)>(writers * 2);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handles: Vec<_> = (0..writers)
+            .map(|_| {
+                let receiver = receiver.clone();
+                scope.spawn(move || -> io::Result<()> {
+                    loop {
+                        let job = receiver.lock().unwrap().recv();
+                        let Ok((target, contents, mode)) = job else {
+                            return Ok(());
+                        };
+                        write_file(&mut contents.as_slice(), &target, mode, buffer_size)?;
+                    }
+                })
+            })
+            .collect();
+        // Once every writer failed the channel closes and sending stops the extraction
+        drop(receiver);
+
+        let mut written = 0;
+        for entry in tar::Archive::new(archive).entries()? {
+            cancellation.check()?;
+            on_entry();
+            let mut entry = entry?;
+            let Some(relative) = sanitize(&entry.path()?) else {
+                continue;
+            };
+            let target = destination.join(&relative);
+            let entry_type = entry.header().entry_type();
+
+            if entry_type.is_file() {
+                let parent = target.parent().unwrap_or(destination);
+                fs::create_dir_all(parent)?;
+                // A symlink extracted earlier must not redirect writes outside the destination
+                if !parent.canonicalize()?.starts_with(&canonical_destination) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} escapes the destination", relative.display()),
+                    ));
+                }
+                if fs::symlink_metadata(&target).is_ok_and(|metadata| metadata.is_symlink()) {
+                    fs::remove_file(&target)?;
+                }
+                let mode = entry.header().mode()?;
+                written += entry.size();
+                if writers == 0 || entry.size() > PARALLEL_FILE_LIMIT {
+                    write_file(&mut entry, &target, mode, buffer_size)?;
+                } else {
+                    let mut contents = Vec::with_capacity(entry.size() as usize);
+                    entry.read_to_end(&mut contents)?;
+                    if sender.send((target, contents, mode)).is_err() {
+                        break;
+                    }
+                }
+            } else if entry_type.is_hard_link() {
+                // Created once every file is written, the target may still be queued
+                let source = entry
+                    .link_name()?
+                    .and_then(|link_name| sanitize(&link_name))
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "Invalid hard link")
+                    })?;
+                hard_links.push((destination.join(source), target));
+            } else {
+                entry.unpack_in(destination)?;
+            }
+        }
+
+        drop(sender);
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+        Ok(written)
+    })?;
+
+    for (source, target) in hard_links {
+        if target.exists() {
+            fs::remove_file(&target)?;
+        }
+        fs::hard_link(source, target)?;
+    }
+    Ok(written)
+}
+
+/// Extracts a zip archive like `extract` does a tar archive, one entry at a time.
+///
+/// Permissions and symlinks come from the unix mode in each entry's external attributes, files
+/// of zips made on Windows have none and are extracted readable and writable.
+pub fn extract_zip(
+    archive: impl Read + Seek,
+    destination: &Path,
+    cancellation: &CancellationToken,
+    mut on_entry: impl FnMut(),
+) -> io::Result<u64> {
+    fs::create_dir_all(destination)?;
+    let canonical_destination = destination.canonicalize()?;
+    let mut archive = zip::ZipArchive::new(archive)?;
+    let mut written = 0;
+    for index in 0..archive.len() {
+        cancellation.check()?;
+        on_entry();
+        let mut entry = archive.by_index(index)?;
+        let Some(relative) = sanitize(Path::new(entry.name())) else {
+            continue;
+        };
+        let target = destination.join(&relative);
+        if entry.is_dir() {
+            fs::create_dir_all(&target)?;
+            continue;
+        }
+
+        let parent = target.parent().unwrap_or(destination);
+        fs::create_dir_all(parent)?;
+        // A symlink extracted earlier must not redirect writes outside the destination
+        if !parent.canonicalize()?.starts_with(&canonical_destination) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} escapes the destination", relative.display()),
+            ));
+        }
+        if fs::symlink_metadata(&target).is_ok_and(|metadata| !metadata.is_dir()) {
+            fs::remove_file(&target)?;
+        }
+        match entry.unix_mode() {
+            // The link's target is stored as its contents
+            Some(mode) if mode & S_IFMT == S_IFLNK => {
+                let mut link_name = String::new();
+                entry.read_to_string(&mut link_name)?;
+                std::os::unix::fs::symlink(link_name, &target)?;
+            }
+            mode => {
+                written += entry.size();
+                write_file(&mut entry, &target, mode.unwrap_or(0o644), MIB)?;
+            }
+        }
+    }
+    Ok(written)
+}
+
+/// Moves what was extracted into `directory` into a directory called `name` if the archive had
+/// its files at the top level instead of in a directory of their own, as Luxtorpeda's does.
+///
+/// Returns whether the extraction was flat.
+pub fn wrap_flat_extraction(directory: &Path, name: &str) -> io::Result<bool> {
+    if !directory.join("compatibilitytool.vdf").is_file() {
+        return Ok(false);
+    }
+    let wrapper = directory.join(name);
+    fs::create_dir(&wrapper)?;
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        if entry.file_name() != name {
+            fs::rename(entry.path(), wrapper.join(entry.file_name()))?;
+        }
+    }
+    Ok(true)
+}
+
+/// Moves a tool nested one directory deeper than usual up into `directory`, if no tool is where
+/// it's expected. Returns whether a tool was moved.
+pub fn hoist_nested_tool(directory: &Path) -> io::Result<bool> {
+    let children: Vec<PathBuf> = fs::read_dir(directory)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    if children
+        .iter()
+        .any(|child| child.join("compatibilitytool.vdf").is_file())
+    {
+        return Ok(false);
+    }
+    let mut nested = Vec::new();
+    for child in &children {
+        for entry in fs::read_dir(child)? {
+            let path = entry?.path();
+            if path.join("compatibilitytool.vdf").is_file() {
+                nested.push((child, path));
+            }
+        }
+    }
+    let [(parent, tool)] = &nested[..] else {
+        return Ok(false);
+    };
+    // The parent may be named like the tool, move the tool aside first
+    let hoisted = directory.join(tool.file_name().unwrap());
+    let aside = directory.join(".hoisted");
+    fs::rename(tool, &aside)?;
+    fs::remove_dir_all(parent)?;
+    fs::rename(aside, hoisted)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wine_cask::install::CompressionType;
+    use crate::wine_cask::local_install::sniff_archive_file;
+    use crate::wine_cask::partial_update::decompressor;
+    use crate::wine_cask::provenance::generate_file_manifest;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    fn create_sysfs_device(sysfs: &Path, dev: &str, disk: &str, partition: &str, rotational: &str) {
+        let disk_path = sysfs.join("devices/pci0000:00").join(disk);
+        fs::create_dir_all(disk_path.join("queue")).unwrap();
+        fs::create_dir_all(disk_path.join(partition)).unwrap();
+        fs::write(disk_path.join("queue/rotational"), rotational).unwrap();
+        fs::create_dir_all(sysfs.join("dev/block")).unwrap();
+        symlink(disk_path.join(partition), sysfs.join("dev/block").join(dev)).unwrap();
+    }
+
+    fn build_archive() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut append = |path: &str, contents: &[u8], entry_type: tar::EntryType, link: &str| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_size(contents.len() as u64);
+            header.set_mode(if entry_type.is_dir() { 0o755 } else { 0o644 });
+            if !link.is_empty() {
+                header.set_link_name(link).unwrap();
+            }
+            header.set_cksum();
+            builder.append_data(&mut header, path, contents).unwrap();
+        };
+        append("GE-Proton9-21/", b"", tar::EntryType::Directory, "");
+        append(
+            "GE-Proton9-21/proton",
+            b"#!/usr/bin/env python3",
+            tar::EntryType::Regular,
+            "",
+        );
+        for index in 0..32 {
+            append(
+                &format!("GE-Proton9-21/files/lib/wine/{}.dll", index),
+                &vec![index as u8; 1000 * index],
+                tar::EntryType::Regular,
+                "",
+            );
+        }
+        append(
+            "GE-Proton9-21/files/lib/wine/latest.dll",
+            b"",
+            tar::EntryType::Symlink,
+            "31.dll",
+        );
+        append(
+            "GE-Proton9-21/files/lib/wine/copy.dll",
+            b"",
+            tar::EntryType::Link,
+            "GE-Proton9-21/files/lib/wine/30.dll",
+        );
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_detect_storage_class() {
+        let sysfs = tempdir().unwrap();
+        create_sysfs_device(sysfs.path(), "259:8", "nvme0n1", "nvme0n1p8", "0\n");
+        create_sysfs_device(sysfs.path(), "179:1", "mmcblk0", "mmcblk0p1", "0\n");
+        create_sysfs_device(sysfs.path(), "8:1", "sda", "sda1", "1\n");
+        create_sysfs_device(sysfs.path(), "8:17", "sdb", "sdb1", "0\n");
+
+        assert_eq!(
+            detect_storage_class(sysfs.path(), 259, 8),
+            StorageClass::Nvme
+        );
+        assert_eq!(detect_storage_class(sysfs.path(), 179, 1), StorageClass::Sd);
+        assert_eq!(
+            detect_storage_class(sysfs.path(), 8, 1),
+            StorageClass::Rotational
+        );
+        assert_eq!(detect_storage_class(sysfs.path(), 8, 17), StorageClass::Ssd);
+        // tmpfs and overlay mounts have no block device
+        assert_eq!(
+            detect_storage_class(sysfs.path(), 0, 42),
+            StorageClass::Unknown
+        );
+    }
+
+    #[test]
+    fn test_select_strategy() {
+        assert!(matches!(
+            select_strategy(StorageClass::Nvme, None, false),
+            ExtractionStrategy::Parallel { .. }
+        ));
+        assert_eq!(
+            select_strategy(StorageClass::Sd, Some(FAST_THROUGHPUT * 2.0), false),
+            ExtractionStrategy::Serial {
+                buffer_size: 64 * KIB
+            }
+        );
+        assert!(matches!(
+            select_strategy(StorageClass::Unknown, Some(FAST_THROUGHPUT), false),
+            ExtractionStrategy::Parallel { .. }
+        ));
+        assert!(matches!(
+            select_strategy(StorageClass::Unknown, None, false),
+            ExtractionStrategy::Serial { .. }
+        ));
+        assert!(matches!(
+            select_strategy(StorageClass::Nvme, None, true),
+            ExtractionStrategy::Serial { .. }
+        ));
+    }
+
+    #[test]
+    fn test_serial_and_parallel_extract_identical_trees() {
+        let archive = build_archive();
+        let serial = tempdir().unwrap();
+        let parallel = tempdir().unwrap();
+
+        let serial_written = extract(
+            archive.as_slice(),
+            serial.path(),
+            ExtractionStrategy::Serial {
+                buffer_size: 64 * KIB,
+            },
+            &CancellationToken::default(),
+            || {},
+        )
+        .unwrap();
+        let parallel_written = extract(
+            archive.as_slice(),
+            parallel.path(),
+            ExtractionStrategy::Parallel {
+                buffer_size: MIB,
+                writers: 4,
+            },
+            &CancellationToken::default(),
+            || {},
+        )
+        .unwrap();
+
+        assert_eq!(serial_written, parallel_written);
+        let serial_files = generate_file_manifest(serial.path()).unwrap();
+        assert_eq!(serial_files.len(), 34);
+        assert_eq!(
+            serial_files,
+            generate_file_manifest(parallel.path()).unwrap()
+        );
+        let wine = parallel.path().join("GE-Proton9-21/files/lib/wine");
+        assert_eq!(
+            fs::read_link(wine.join("latest.dll")).unwrap(),
+            PathBuf::from("31.dll")
+        );
+        assert_eq!(
+            fs::metadata(wine.join("copy.dll")).unwrap().ino(),
+            fs::metadata(wine.join("30.dll")).unwrap().ino()
+        );
+    }
+
+    #[test]
+    fn test_streamed_extraction_preserves_contents_and_permissions() {
+        // Every hundredth file is too large for the writer threads' queue
+        let size = |index: usize| {
+            if index.is_multiple_of(100) {
+                PARALLEL_FILE_LIMIT as usize + 1
+            } else {
+                index * 10
+            }
+        };
+        let mode = |index: usize| {
+            if index.is_multiple_of(3) {
+                0o755
+            } else {
+                0o644
+            }
+        };
+        let directory = tempdir().unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(directory.path().join("GE-Proton9-21.tar.gz")).unwrap(),
+            flate2::Compression::fast(),
+        );
+        {
+            let mut builder = tar::Builder::new(&mut encoder);
+            for index in 0..400 {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(size(index) as u64);
+                header.set_mode(mode(index));
+                header.set_cksum();
+                let path = format!("GE-Proton9-21/files/{}/{}.dll", index % 8, index);
+                builder
+                    .append_data(&mut header, path, vec![index as u8; size(index)].as_slice())
+                    .unwrap();
+            }
+            builder.finish().unwrap();
+        }
+        encoder.finish().unwrap();
+
+        let archive = File::open(directory.path().join("GE-Proton9-21.tar.gz")).unwrap();
+        let (reader, read) = CountingReader::new(archive);
+        let destination = directory.path().join("extracted");
+        let mut entries = 0;
+        extract(
+            decompressor(io::BufReader::new(reader), &CompressionType::Gzip),
+            &destination,
+            ExtractionStrategy::Parallel {
+                buffer_size: MIB,
+                writers: 4,
+            },
+            &CancellationToken::default(),
+            || entries += 1,
+        )
+        .unwrap();
+
+        assert_eq!(entries, 400);
+        assert!(read.load(Ordering::Relaxed) > 0);
+        for index in 0..400 {
+            let file = destination.join(format!("GE-Proton9-21/files/{}/{}.dll", index % 8, index));
+            assert_eq!(fs::read(&file).unwrap(), vec![index as u8; size(index)]);
+            let permissions = fs::metadata(&file).unwrap().permissions();
+            assert_eq!(permissions.mode() & 0o777, mode(index));
+        }
+    }
+
+    /// Files of the fixture archives with their modes, and a symlink to the last one.
+    const FIXTURE_FILES: [(&str, u32); 3] = [
+        ("GE-Proton9-21/proton", 0o755),
+        ("GE-Proton9-21/files/bin/wine64", 0o755),
+        ("GE-Proton9-21/files/lib/wine/31.dll", 0o644),
+    ];
+    const FIXTURE_LINK: (&str, &str) = ("GE-Proton9-21/files/lib/wine/latest.dll", "31.dll");
+
+    fn fixture_contents(path: &str) -> Vec<u8> {
+        path.repeat(1000).into_bytes()
+    }
+
+    fn build_fixture_tar() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, mode) in FIXTURE_FILES {
+            let contents = fixture_contents(path);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(mode);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, contents.as_slice())
+                .unwrap();
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        builder
+            .append_link(&mut header, FIXTURE_LINK.0, FIXTURE_LINK.1)
+            .unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    fn build_fixture_zip() -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        for (path, mode) in FIXTURE_FILES {
+            let options = zip::write::FileOptions::default().unix_permissions(mode);
+            writer.start_file(path, options).unwrap();
+            writer.write_all(&fixture_contents(path)).unwrap();
+        }
+        writer
+            .add_symlink(FIXTURE_LINK.0, FIXTURE_LINK.1, Default::default())
+            .unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_every_archive_type_extracts_the_same_tree() {
+        let tar = build_fixture_tar();
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gzip.write_all(&tar).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 1);
+        xz.write_all(&tar).unwrap();
+        let fixtures = [
+            ("GE-Proton9-21.tar", tar.clone(), CompressionType::Tar),
+            (
+                "GE-Proton9-21.tar.gz",
+                gzip.finish().unwrap(),
+                CompressionType::Gzip,
+            ),
+            (
+                "GE-Proton9-21.tar.xz",
+                xz.finish().unwrap(),
+                CompressionType::Xz,
+            ),
+            (
+                "GE-Proton9-21.tar.zst",
+                zstd::stream::encode_all(tar.as_slice(), 1).unwrap(),
+                CompressionType::Zstd,
+            ),
+            (
+                "GE-Proton9-21.zip",
+                build_fixture_zip(),
+                CompressionType::Zip,
+            ),
+        ];
+
+        let directory = tempdir().unwrap();
+        for (name, contents, expected_type) in fixtures {
+            let archive = directory.path().join(name);
+            fs::write(&archive, contents).unwrap();
+            let compress_type = sniff_archive_file(&archive).unwrap().unwrap();
+            assert!(compress_type == expected_type, "{} wasn't told apart", name);
+
+            let destination = directory.path().join(format!("{}.extracted", name));
+            let file = File::open(&archive).unwrap();
+            if compress_type == CompressionType::Zip {
+                extract_zip(file, &destination, &CancellationToken::default(), || {}).unwrap();
+            } else {
+                extract(
+                    decompressor(io::BufReader::new(file), &compress_type),
+                    &destination,
+                    ExtractionStrategy::Serial { buffer_size: MIB },
+                    &CancellationToken::default(),
+                    || {},
+                )
+                .unwrap();
+            }
+
+            let files = generate_file_manifest(&destination).unwrap();
+            assert_eq!(files.len(), FIXTURE_FILES.len(), "{}", name);
+            for (path, mode) in FIXTURE_FILES {
+                let file = destination.join(path);
+                assert_eq!(fs::read(&file).unwrap(), fixture_contents(path), "{}", name);
+                let permissions = fs::metadata(&file).unwrap().permissions();
+                assert_eq!(permissions.mode() & 0o777, mode, "{}: {}", name, path);
+            }
+            assert_eq!(
+                fs::read_link(destination.join(FIXTURE_LINK.0)).unwrap(),
+                PathBuf::from(FIXTURE_LINK.1),
+                "{}",
+                name
+            );
+        }
+    }
+
+    /// Cancels `cancellation` once `remaining` bytes of the archive were read.
+    struct CancelAfter<'a> {
+        archive: &'a [u8],
+        remaining: usize,
+        cancellation: CancellationToken,
+    }
+
+    impl Read for CancelAfter<'_> {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let read = self.archive.read(buffer)?;
+            self.remaining = self.remaining.saturating_sub(read);
+            if self.remaining == 0 {
+                self.cancellation.cancel();
+            }
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn test_cancelled_extraction_stops_between_entries() {
+        let archive = build_archive();
+        let destination = tempdir().unwrap();
+        let cancellation = CancellationToken::default();
+        let reader = CancelAfter {
+            archive: archive.as_slice(),
+            remaining: archive.len() / 4,
+            cancellation: cancellation.clone(),
+        };
+
+        let error = extract(
+            reader,
+            destination.path(),
+            ExtractionStrategy::Serial {
+                buffer_size: 64 * KIB,
+            },
+            &cancellation,
+            || {},
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Interrupted);
+        let extracted = generate_file_manifest(destination.path()).unwrap();
+        assert!(!extracted.is_empty() && extracted.len() < 34);
+    }
+
+    #[test]
+    fn test_flat_extraction_is_wrapped_in_a_directory() {
+        let nested = tempdir().unwrap();
+        fs::create_dir_all(nested.path().join("GE-Proton9-21")).unwrap();
+        fs::write(
+            nested.path().join("GE-Proton9-21/compatibilitytool.vdf"),
+            "",
+        )
+        .unwrap();
+        assert!(!wrap_flat_extraction(nested.path(), "ProtonGEGE-Proton9-21").unwrap());
+        assert!(!nested.path().join("ProtonGEGE-Proton9-21").exists());
+
+        let flat = tempdir().unwrap();
+        fs::write(flat.path().join("compatibilitytool.vdf"), "").unwrap();
+        fs::write(flat.path().join("toolmanifest.vdf"), "").unwrap();
+        fs::create_dir_all(flat.path().join("engines")).unwrap();
+        fs::write(flat.path().join("engines/luxtorpeda"), "").unwrap();
+        assert!(wrap_flat_extraction(flat.path(), "Luxtorpedav68.0.0").unwrap());
+
+        let entries: Vec<PathBuf> = fs::read_dir(flat.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(entries, [flat.path().join("Luxtorpedav68.0.0")]);
+        let wrapper = flat.path().join("Luxtorpedav68.0.0");
+        assert!(wrapper.join("compatibilitytool.vdf").is_file());
+        assert!(wrapper.join("toolmanifest.vdf").is_file());
+        assert!(wrapper.join("engines/luxtorpeda").is_file());
+    }
+
+    #[test]
+    fn test_nested_tool_is_moved_up() {
+        let directory = tempdir().unwrap();
+        let tool = directory.path().join("usr/proton_tkg_9.5.r0.g3c4f8d1d");
+        fs::create_dir_all(tool.join("files/bin")).unwrap();
+        fs::write(tool.join("compatibilitytool.vdf"), "").unwrap();
+        fs::write(tool.join("files/bin/wine"), "").unwrap();
+
+        assert!(hoist_nested_tool(directory.path()).unwrap());
+        let hoisted = directory.path().join("proton_tkg_9.5.r0.g3c4f8d1d");
+        assert!(hoisted.join("compatibilitytool.vdf").is_file());
+        assert!(hoisted.join("files/bin/wine").is_file());
+        assert!(!directory.path().join("usr").exists());
+
+        // Tools where they're expected stay
+        assert!(!hoist_nested_tool(directory.path()).unwrap());
+        assert!(hoisted.is_dir());
+    }
+
+    #[test]
+    fn test_sanitize_rejects_escaping_paths() {
+        assert_eq!(
+            sanitize(Path::new("./GE-Proton9-21/proton")),
+            Some(PathBuf::from("GE-Proton9-21/proton"))
+        );
+        assert_eq!(sanitize(Path::new("GE-Proton9-21/../../etc/passwd")), None);
+        assert_eq!(sanitize(Path::new("/etc/passwd")), None);
+        assert_eq!(sanitize(Path::new("./")), None);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::wine_cask::app::{Request, RequestType, TaskType};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use serde::{Deserialize, Serialize};
+
+/// Capability that can be turned off entirely, regardless of what the frontend asks for.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum Feature {
+    WriteSteamConfig,
+    AutoUpdate,
+    Dedup,
+    ExternalCommands,
+    NetworkRecommendations,
+}
+
+impl std::fmt::Display for Feature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Feature::WriteSteamConfig => write!(f, "write_steam_config"),
+            Feature::AutoUpdate => write!(f, "auto_update"),
+            Feature::Dedup => write!(f, "dedup"),
+            Feature::ExternalCommands => write!(f, "external_commands"),
+            Feature::NetworkRecommendations => write!(f, "network_recommendations"),
+        }
+    }
+}
+
+/// Opt-in toggles for risky functionality, the frontend hides the UI of disabled features.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(default)]
+pub struct FeatureFlags {
+    pub write_steam_config: bool,
+    pub auto_update: bool,
+    pub dedup: bool,
+    pub external_commands: bool,
+    pub network_recommendations: bool,
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        // Writing Steam's config and running external commands can break things outside the plugin
+        Self {
+            write_steam_config: false,
+            auto_update: true,
+            dedup: true,
+            external_commands: false,
+            network_recommendations: true,
+        }
+    }
+}
+
+impl FeatureFlags {
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::WriteSteamConfig => self.write_steam_config,
+            Feature::AutoUpdate => self.auto_update,
+            Feature::Dedup => self.dedup,
+            Feature::ExternalCommands => self.external_commands,
+            Feature::NetworkRecommendations => self.network_recommendations,
+        }
+    }
+
+    /// Returns the `FeatureDisabled` error when the feature is turned off.
+    pub fn require(&self, feature: Feature) -> Result<(), AppError> {
+        if self.is_enabled(feature) {
+            Ok(())
+        } else {
+            Err(AppError::new(
+                AppErrorCode::FeatureDisabled,
+                format!("{} is turned off", feature),
+            ))
+        }
+    }
+
+    /// Returns the `FeatureDisabled` error when the request needs a disabled feature.
+    pub fn check(&self, request: &Request) -> Result<(), AppError> {
+        match required_feature(request) {
+            Some(feature) => self.require(feature),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Maps a request to the feature it needs, read-only requests never need one.
+pub fn required_feature(request: &Request) -> Option<Feature> {
+    match request.r#type {
+        RequestType::RequestState
+        | RequestType::UpdateState
+        | RequestType::Notification
+        | RequestType::UpdateSettings
+        | RequestType::GetToolProvenance
+        | RequestType::ToolProvenance
+        | RequestType::VerifyInstalledTool
+        | RequestType::Verification
+        | RequestType::GetActivity
+        | RequestType::Activity
+        | RequestType::GetStorageBreakdown
+        | RequestType::StorageBreakdown
+        | RequestType::ClearShaderCache
+        | RequestType::StartupProgress
+        | RequestType::ValidationError
+        | RequestType::GetUndoStack
+        | RequestType::UndoStack
+        | RequestType::Refresh
+        | RequestType::RefreshCompleted
+        | RequestType::GetMutationLog
+        | RequestType::MutationLog
+        | RequestType::SkipRelease
+        | RequestType::UnskipRelease
+        | RequestType::SetQuickSlots
+        | RequestType::PrioritizePrefixes
+        | RequestType::CancelPrefixScan
+        | RequestType::PrefixScanned
+        | RequestType::PrefixScanCompleted
+        | RequestType::CheckLocalChanges
+        | RequestType::Permissions
+        | RequestType::CreatePlan
+        | RequestType::Plan
+        | RequestType::ExecutePlan
+        | RequestType::PlanExecuted
+        | RequestType::PauseInspection
+        | RequestType::ResumeInspection
+        | RequestType::ToolInspected
+        | RequestType::InspectionCompleted
+        | RequestType::SelectSteamInstallation
+        | RequestType::CancelTask
+        | RequestType::TaskCancelled
+        | RequestType::UpdateSummary
+        | RequestType::UninstallBlocked
+        | RequestType::ForceRefresh
+        | RequestType::InsufficientDiskSpace
+        | RequestType::UpdatesAvailable
+        | RequestType::AdoptTool
+        | RequestType::ListOrphanedCompatData
+        | RequestType::OrphanedCompatData
+        | RequestType::GetPrefixSizes
+        | RequestType::PrefixSizes
+        | RequestType::RequestSnapshot
+        | RequestType::StateDelta
+        | RequestType::Error
+        | RequestType::Authenticate
+        | RequestType::Subscribe
+        | RequestType::Subscribed
+        | RequestType::Hello
+        | RequestType::GetInstalledApps
+        | RequestType::InstalledApps
+        | RequestType::GetShortcutDetails
+        | RequestType::ShortcutDetails
+        | RequestType::GetHistory
+        | RequestType::History
+        | RequestType::ResolveRestoreConflict
+        | RequestType::CreateVirtualTool => None,
+        RequestType::UndoLast
+        | RequestType::RollbackUpdate
+        | RequestType::SwitchQuickSlot
+        | RequestType::SetShortcutLaunchOptions => Some(Feature::WriteSteamConfig),
+        RequestType::Task => match request.task.as_ref().map(|task| &task.r#type) {
+            Some(
+                TaskType::SetCompatibilityToolMapping
+                | TaskType::SetCompatibilityToolMappings
+                | TaskType::ImportCompatibilityToolMappings,
+            ) => Some(Feature::WriteSteamConfig),
+            Some(TaskType::UpdateAllCompatibilityTools) => Some(Feature::AutoUpdate),
+            // Moving the tool's apps to a replacement rewrites their mappings
+            Some(TaskType::UninstallCompatibilityTool)
+                if request
+                    .task
+                    .as_ref()
+                    .and_then(|task| task.uninstall.as_ref())
+                    .is_some_and(|uninstall| uninstall.replacement.is_some()) =>
+            {
+                Some(Feature::WriteSteamConfig)
+            }
+            _ => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wine_cask::app::Task;
+    use crate::wine_cask::flavors::CompatibilityToolFlavor;
+    use crate::wine_cask::uninstall::Uninstall;
+
+    fn request(r#type: RequestType, task_type: Option<TaskType>) -> Request {
+        Request {
+            task: task_type.map(|task_type| Task {
+                id: 0,
+                r#type: task_type,
+                install: None,
+                uninstall: None,
+                migrate: None,
+                mapping: None,
+                mappings: None,
+                import: None,
+                direct_install: None,
+                update_all: None,
+                local_file: None,
+                delete_compat_data: None,
+                restore_prefix_backup: None,
+            }),
+            ..Request::new(r#type)
+        }
+    }
+
+    fn all_disabled() -> FeatureFlags {
+        FeatureFlags {
+            write_steam_config: false,
+            auto_update: false,
+            dedup: false,
+            external_commands: false,
+            network_recommendations: false,
+        }
+    }
+
+    #[test]
+    fn test_defaults_are_conservative() {
+        let feature_flags = FeatureFlags::default();
+        assert!(!feature_flags.is_enabled(Feature::WriteSteamConfig));
+        assert!(!feature_flags.is_enabled(Feature::ExternalCommands));
+        assert!(feature_flags.is_enabled(Feature::AutoUpdate));
+
+        // Flags missing from older settings files fall back to the defaults
+        let feature_flags: FeatureFlags = serde_json::from_str(r#"{"dedup": false}"#).unwrap();
+        assert!(!feature_flags.is_enabled(Feature::Dedup));
+        assert!(!feature_flags.is_enabled(Feature::WriteSteamConfig));
+    }
+
+    #[test]
+    fn test_read_only_requests_are_never_gated() {
+        let feature_flags = all_disabled();
+        for read_only in [
+            request(RequestType::RequestState, None),
+            request(RequestType::Task, Some(TaskType::CheckForFlavorUpdates)),
+        ] {
+            assert_eq!(required_feature(&read_only), None);
+            assert!(feature_flags.check(&read_only).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_mapping_writes_need_write_steam_config() {
+        let feature_flags = all_disabled();
+        for write in [
+            request(RequestType::UndoLast, None),
+            request(
+                RequestType::Task,
+                Some(TaskType::SetCompatibilityToolMapping),
+            ),
+            request(
+                RequestType::Task,
+                Some(TaskType::SetCompatibilityToolMappings),
+            ),
+        ] {
+            assert_eq!(required_feature(&write), Some(Feature::WriteSteamConfig));
+            assert_eq!(
+                feature_flags.check(&write),
+                Err(AppError::new(
+                    AppErrorCode::FeatureDisabled,
+                    "write_steam_config is turned off"
+                ))
+            );
+        }
+        assert_eq!(
+            required_feature(&request(RequestType::GetUndoStack, None)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_uninstall_replacement_needs_write_steam_config() {
+        let feature_flags = all_disabled();
+        let uninstall = |replacement: Option<&str>| {
+            let mut uninstall_request = request(
+                RequestType::Task,
+                Some(TaskType::UninstallCompatibilityTool),
+            );
+            let uninstall: Uninstall = serde_json::from_value(serde_json::json!({
+                "flavor": CompatibilityToolFlavor::ProtonGE,
+                "steam_compatibility_tool": {
+                    "path": "/home/deck/.steam/root/compatibilitytools.d/GE-Proton9-20",
+                    "display_name": "GE-Proton9-20",
+                    "internal_name": "GE-Proton9-20",
+                    "used_by_games": [],
+                    "requires_restart": false,
+                    "supports_32bit": true,
+                    "flavor": CompatibilityToolFlavor::ProtonGE,
+                    "github_release": null,
+                },
+                "force": true,
+                "replacement": replacement,
+            }))
+            .unwrap();
+            uninstall_request.task.as_mut().unwrap().uninstall = Some(uninstall);
+            uninstall_request
+        };
+
+        assert_eq!(required_feature(&uninstall(None)), None);
+        assert!(feature_flags.check(&uninstall(None)).is_ok());
+        let with_replacement = uninstall(Some("GE-Proton9-21"));
+        assert_eq!(
+            required_feature(&with_replacement),
+            Some(Feature::WriteSteamConfig)
+        );
+        assert_eq!(
+            feature_flags.check(&with_replacement),
+            Err(AppError::new(
+                AppErrorCode::FeatureDisabled,
+                "write_steam_config is turned off"
+            ))
+        );
+    }
+}
@@ -0,0 +1,437 @@
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::refresh::RefreshScope;
+use crate::PeerMap;
+use libc::{
+    c_int, IN_CLOEXEC, IN_CLOSE_WRITE, IN_CREATE, IN_DELETE, IN_DELETE_SELF, IN_IGNORED,
+    IN_MOVED_FROM, IN_MOVED_TO, IN_MOVE_SELF, IN_ONLYDIR,
+};
+use log::{error, info, warn};
+use std::collections::HashSet;
+use std::ffi::{CString, OsStr};
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use std::{fs, io, mem, thread};
+use tokio::sync::mpsc;
+
+/// How long changes have to settle before the affected state is read again, Steam and
+/// ProtonUp-Qt write in bursts.
+const DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// Directories are watched rather than the files in them, a file replaced by a rename keeps
+/// being noticed that way.
+const WATCH_MASK: u32 = IN_CLOSE_WRITE
+    | IN_MOVED_FROM
+    | IN_MOVED_TO
+    | IN_CREATE
+    | IN_DELETE
+    | IN_DELETE_SELF
+    | IN_MOVE_SELF
+    | IN_ONLYDIR;
+
+/// Large enough for several events with names up to NAME_MAX.
+const EVENT_BUFFER_SIZE: usize = 4096;
+const EVENT_HEADER_SIZE: usize = mem::size_of::<libc::inotify_event>();
+
+/// Modification time and size of a file.
+type Fingerprint = (SystemTime, u64);
+
+fn fingerprint(path: &Path) -> Option<Fingerprint> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Fingerprints of the files Wine Cellar wrote itself, changes matching them are not re-read.
+///
+/// Clones share the same fingerprints.
+#[derive(Clone, Default)]
+pub struct OwnWrites(Arc<Mutex<Vec<(PathBuf, Fingerprint)>>>);
+
+impl OwnWrites {
+    /// Remembers `path` as it is now, called right after writing it.
+    pub fn record(&self, path: &Path) {
+        let Some(fingerprint) = fingerprint(path) else {
+            return;
+        };
+        let mut own_writes = self.0.lock().unwrap();
+        own_writes.retain(|(written, _)| written != path);
+        own_writes.push((path.to_path_buf(), fingerprint));
+    }
+
+    /// Whether `path` is still exactly what Wine Cellar last wrote to it.
+    pub fn contains(&self, path: &Path) -> bool {
+        let current = fingerprint(path);
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(written, fingerprint)| written == path && current.as_ref() == Some(fingerprint))
+    }
+}
+
+/// A directory whose entries, or the one entry named, make part of the state stale.
+#[derive(Clone, Debug)]
+struct Target {
+    directory: PathBuf,
+    name: Option<&'static str>,
+    scope: RefreshScope,
+}
+
+impl Target {
+    /// The file whose changes are tracked, if it's a single file.
+    fn file(&self) -> Option<PathBuf> {
+        self.name.map(|name| self.directory.join(name))
+    }
+}
+
+/// Returns what is watched below `steam_root` and what goes stale when it changes.
+fn targets(steam_root: &Path) -> Vec<Target> {
+    vec![
+        Target {
+            directory: steam_root.join("compatibilitytools.d"),
+            name: None,
+            scope: RefreshScope::Tools,
+        },
+        // Notices compatibilitytools.d being deleted or created again
+        Target {
+            directory: steam_root.to_path_buf(),
+            name: Some("compatibilitytools.d"),
+            scope: RefreshScope::Tools,
+        },
+        Target {
+            directory: steam_root.join("config"),
+            name: Some("config.vdf"),
+            scope: RefreshScope::Mappings,
+        },
+        Target {
+            directory: steam_root.join("steamapps"),
+            name: Some("libraryfolders.vdf"),
+            scope: RefreshScope::Apps,
+        },
+    ]
+}
+
+#[derive(PartialEq, Debug)]
+struct InotifyEvent {
+    watch: c_int,
+    mask: u32,
+    name: Option<PathBuf>,
+}
+
+/// Splits what a read from an inotify descriptor returned into events.
+fn parse_events(buffer: &[u8]) -> Vec<InotifyEvent> {
+    let mut events = Vec::new();
+    let mut offset = 0;
+    while offset + EVENT_HEADER_SIZE <= buffer.len() {
+        let field = |index: usize| {
+            let start = offset + index * 4;
+            u32::from_ne_bytes(buffer[start..start + 4].try_into().unwrap())
+        };
+        let length = field(3) as usize;
+        let name_start = offset + EVENT_HEADER_SIZE;
+        let name_bytes = buffer
+            .get(name_start..name_start + length)
+            .unwrap_or_default();
+        // Names are padded with NULs to the next alignment boundary
+        let name = name_bytes
+            .split(|byte| *byte == 0)
+            .next()
+            .filter(|name| !name.is_empty())
+            .map(|name| PathBuf::from(OsStr::from_bytes(name)));
+        events.push(InotifyEvent {
+            watch: field(0) as c_int,
+            mask: field(1),
+            name,
+        });
+        offset = name_start + length;
+    }
+    events
+}
+
+/// Watches the Steam files other programs change behind Wine Cellar's back.
+pub struct FileWatcher {
+    inotify: File,
+    own_writes: OwnWrites,
+    targets: Vec<Target>,
+    /// Watch descriptors of the targets whose directory currently exists.
+    watches: Vec<(c_int, Target)>,
+}
+
+impl FileWatcher {
+    pub fn new(steam_root: &Path, own_writes: OwnWrites) -> io::Result<Self> {
+        // SAFETY: inotify_init1 only takes flags and has no preconditions
+        let fd = unsafe { libc::inotify_init1(IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just returned by inotify_init1, it's open and nothing else owns it
+        let inotify = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut watcher = Self {
+            inotify: File::from(inotify),
+            own_writes,
+            targets: targets(steam_root),
+            watches: Vec::new(),
+        };
+        watcher.arm();
+        Ok(watcher)
+    }
+
+    /// Watches the directories of the targets that aren't watched yet and exist by now.
+    fn arm(&mut self) {
+        for target in &self.targets {
+            let watched = self
+                .watches
+                .iter()
+                .any(|(_, watched)| watched.directory == target.directory);
+            if watched || !target.directory.is_dir() {
+                continue;
+            }
+            let Ok(path) = CString::new(target.directory.as_os_str().as_bytes()) else {
+                continue;
+            };
+            // SAFETY: the descriptor is owned by `self.inotify` and stays open for the call, `path`
+            // is a NUL terminated string that outlives it
+            let watch = unsafe {
+                libc::inotify_add_watch(self.inotify.as_raw_fd(), path.as_ptr(), WATCH_MASK)
+            };
+            if watch < 0 {
+                warn!(
+                    "Couldn't watch {}: {}",
+                    target.directory.display(),
+                    io::Error::last_os_error()
+                );
+                continue;
+            }
+            // Targets sharing a directory share its watch descriptor
+            for target in self
+                .targets
+                .iter()
+                .filter(|other| other.directory == target.directory)
+            {
+                self.watches.push((watch, target.clone()));
+            }
+        }
+    }
+
+    /// Blocks until something changes, returns the targets the changes touched.
+    fn read_targets(&mut self) -> io::Result<Vec<Target>> {
+        let mut buffer = [0; EVENT_BUFFER_SIZE];
+        let read = self.inotify.read(&mut buffer)?;
+        let mut changed: Vec<Target> = Vec::new();
+        let mut rearm = false;
+        for event in parse_events(&buffer[..read]) {
+            if event.mask & IN_IGNORED != 0 {
+                // The directory is gone or was moved away, it's watched again once it's back
+                self.watches.retain(|(watch, _)| *watch != event.watch);
+                continue;
+            }
+            rearm |= event.mask & (IN_CREATE | IN_MOVED_TO) != 0;
+            for (_, target) in self
+                .watches
+                .iter()
+                .filter(|(watch, _)| *watch == event.watch)
+            {
+                let matches = match (target.name, &event.name) {
+                    (None, _) => true,
+                    (Some(name), Some(event_name)) => event_name.as_os_str() == name,
+                    (Some(_), None) => false,
+                };
+                if matches {
+                    changed.push(target.clone());
+                }
+            }
+        }
+        if rearm {
+            self.arm();
+        }
+        Ok(changed)
+    }
+
+    /// Blocks until something changes, returns the parts of the state that are stale now.
+    ///
+    /// Files that are still exactly what Wine Cellar wrote itself are left out.
+    pub fn read_scopes(&mut self) -> io::Result<HashSet<RefreshScope>> {
+        Ok(self
+            .read_targets()?
+            .into_iter()
+            .filter(|target| {
+                !target
+                    .file()
+                    .is_some_and(|file| self.own_writes.contains(&file))
+            })
+            .map(|target| target.scope)
+            .collect())
+    }
+}
+
+/// Re-reads whatever other programs change in the Steam directory, e.g. ProtonUp-Qt installing a
+/// tool or a mapping changed in Steam's settings, and broadcasts the new state.
+pub async fn run_file_watcher(wine_cask: Arc<WineCask>, peer_map: PeerMap) {
+    let steam_root = wine_cask.steam_util.get_steam_path().to_path_buf();
+    let mut watcher = match FileWatcher::new(&steam_root, wine_cask.own_writes.clone()) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            error!("Couldn't watch the Steam directory for changes: {}", err);
+            return;
+        }
+    };
+    info!("Watching {} for changes", steam_root.display());
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    thread::spawn(move || loop {
+        match watcher.read_scopes() {
+            Ok(scopes) if scopes.is_empty() => {}
+            Ok(scopes) => {
+                if sender.send(scopes).is_err() {
+                    return;
+                }
+            }
+            Err(err) => {
+                error!("Stopped watching the Steam directory: {}", err);
+                return;
+            }
+        }
+    });
+
+    while let Some(mut scopes) = receiver.recv().await {
+        while let Ok(Some(more)) = tokio::time::timeout(DEBOUNCE, receiver.recv()).await {
+            scopes.extend(more);
+        }
+        // Installs and uninstalls refresh the tools once they're done
+        if scopes.contains(&RefreshScope::Tools)
+            && !wine_cask.app_state.lock().await.in_progress.is_empty()
+        {
+            scopes.remove(&RefreshScope::Tools);
+        }
+        for scope in scopes {
+            info!(
+                "{:?} changed outside of Wine Cellar, reading them again",
+                scope
+            );
+            wine_cask.refresh(&peer_map, scope, None, None).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn event_bytes(watch: c_int, mask: u32, name: &str) -> Vec<u8> {
+        let padded_length = if name.is_empty() {
+            0
+        } else {
+            (name.len() + 1).div_ceil(16) * 16
+        };
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&watch.to_ne_bytes());
+        bytes.extend_from_slice(&mask.to_ne_bytes());
+        bytes.extend_from_slice(&0u32.to_ne_bytes());
+        bytes.extend_from_slice(&(padded_length as u32).to_ne_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.resize(bytes.len() + padded_length - name.len(), 0);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let mut buffer = event_bytes(1, IN_MOVED_TO, "config.vdf");
+        buffer.extend(event_bytes(2, IN_DELETE_SELF, ""));
+        buffer.extend(event_bytes(3, IN_CREATE, "GE-Proton9-20"));
+
+        assert_eq!(
+            parse_events(&buffer),
+            vec![
+                InotifyEvent {
+                    watch: 1,
+                    mask: IN_MOVED_TO,
+                    name: Some(PathBuf::from("config.vdf")),
+                },
+                InotifyEvent {
+                    watch: 2,
+                    mask: IN_DELETE_SELF,
+                    name: None,
+                },
+                InotifyEvent {
+                    watch: 3,
+                    mask: IN_CREATE,
+                    name: Some(PathBuf::from("GE-Proton9-20")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_watches_steam_files() {
+        let steam_root = tempdir().unwrap();
+        let config_dir = steam_root.path().join("config");
+        let tools_dir = steam_root.path().join("compatibilitytools.d");
+        fs::create_dir(&config_dir).unwrap();
+        fs::create_dir(steam_root.path().join("steamapps")).unwrap();
+        fs::create_dir(&tools_dir).unwrap();
+        let mut watcher = FileWatcher::new(steam_root.path(), OwnWrites::default()).unwrap();
+
+        // Replaced by a rename, the way Steam and Wine Cellar write it
+        let config_file = config_dir.join("config.vdf");
+        fs::write(
+            config_dir.join("config.vdf.tmp"),
+            "\"InstallConfigStore\" {}",
+        )
+        .unwrap();
+        fs::rename(config_dir.join("config.vdf.tmp"), &config_file).unwrap();
+        assert_eq!(
+            watcher.read_scopes().unwrap(),
+            HashSet::from([RefreshScope::Mappings])
+        );
+
+        fs::write(&config_file, "\"InstallConfigStore\" { }").unwrap();
+        fs::write(steam_root.path().join("steamapps/libraryfolders.vdf"), "").unwrap();
+        assert_eq!(
+            watcher.read_scopes().unwrap(),
+            HashSet::from([RefreshScope::Mappings, RefreshScope::Apps])
+        );
+
+        // compatibilitytools.d is watched again after being created anew
+        fs::remove_dir(&tools_dir).unwrap();
+        fs::create_dir(&tools_dir).unwrap();
+        assert_eq!(
+            watcher.read_scopes().unwrap(),
+            HashSet::from([RefreshScope::Tools])
+        );
+        fs::create_dir(tools_dir.join("GE-Proton9-20")).unwrap();
+        assert_eq!(
+            watcher.read_scopes().unwrap(),
+            HashSet::from([RefreshScope::Tools])
+        );
+    }
+
+    #[test]
+    fn test_own_writes_are_ignored() {
+        let steam_root = tempdir().unwrap();
+        let config_dir = steam_root.path().join("config");
+        fs::create_dir(&config_dir).unwrap();
+        let own_writes = OwnWrites::default();
+        let mut watcher = FileWatcher::new(steam_root.path(), own_writes.clone()).unwrap();
+
+        let config_file = config_dir.join("config.vdf");
+        fs::write(&config_file, "\"InstallConfigStore\" {}").unwrap();
+        own_writes.record(&config_file);
+        assert!(own_writes.contains(&config_file));
+        // Steam's write lands before the watcher got to the first one
+        fs::write(&config_file, "\"InstallConfigStore\" { \"Software\" {} }").unwrap();
+        assert!(!own_writes.contains(&config_file));
+        assert_eq!(
+            watcher.read_scopes().unwrap(),
+            HashSet::from([RefreshScope::Mappings])
+        );
+
+        fs::write(&config_file, "\"InstallConfigStore\" {}").unwrap();
+        own_writes.record(&config_file);
+        assert_eq!(watcher.read_scopes().unwrap(), HashSet::new());
+    }
+}
@@ -0,0 +1,202 @@
+use crate::mounts::{find_mount, library_folder_mounted};
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What a filesystem can store, decides how tools are installed onto it.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct FilesystemProfile {
+    /// Type as listed in /proc/mounts, `None` when the mount wasn't found.
+    pub fs_type: Option<String>,
+    pub symlinks: bool,
+    pub unix_permissions: bool,
+    pub case_sensitive: bool,
+}
+
+impl Default for FilesystemProfile {
+    fn default() -> Self {
+        FilesystemProfile {
+            fs_type: None,
+            symlinks: true,
+            unix_permissions: true,
+            case_sensitive: true,
+        }
+    }
+}
+
+impl FilesystemProfile {
+    /// Profile of a mount, `options` are its comma separated mount options.
+    pub fn for_mount(fs_type: &str, options: &str) -> FilesystemProfile {
+        let has_option = |option: &str| options.split(',').any(|candidate| candidate == option);
+        let (symlinks, unix_permissions, case_sensitive) = match fs_type {
+            "vfat" | "msdos" | "exfat" => (false, false, false),
+            // The Linux NTFS drivers use the POSIX namespace unless told otherwise, fuseblk is
+            // usually ntfs-3g or exfat-fuse
+            "ntfs" | "ntfs3" | "fuseblk" => (false, false, !has_option("nocase")),
+            _ => (true, true, true),
+        };
+        FilesystemProfile {
+            fs_type: Some(fs_type.to_string()),
+            symlinks,
+            unix_permissions,
+            case_sensitive,
+        }
+    }
+
+    /// Whether two names in the same directory refer to the same entry.
+    pub fn names_collide(&self, a: &str, b: &str) -> bool {
+        if self.case_sensitive {
+            a == b
+        } else {
+            a.to_lowercase() == b.to_lowercase()
+        }
+    }
+
+    /// Error refusing an install into `destination` that would create symlinks.
+    pub fn refuse_symlinks(&self, destination: &Path, copy_install: bool) -> Option<AppError> {
+        if copy_install || self.symlinks {
+            return None;
+        }
+        Some(AppError::new(
+            AppErrorCode::UnsupportedFilesystem,
+            format!(
+                "{} is on {}, which can't store the symlinks of a tool, set copy_install to install copies of the linked files instead",
+                destination.display(),
+                self.fs_type.as_deref().unwrap_or("an unknown filesystem")
+            ),
+        ))
+    }
+}
+
+/// The filesystem a Steam library or the tools directory lives on.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct PathFilesystem {
+    pub path: String,
+    pub profile: FilesystemProfile,
+    /// Whether the library folder can be read, `false` e.g. while its SD card is taken out.
+    pub mounted: bool,
+}
+
+/// Profile of the mount in `mounts` (formatted like /proc/mounts) that contains `path`.
+pub fn profile_from_mounts(mounts: &str, path: &Path) -> FilesystemProfile {
+    find_mount(mounts, path)
+        .map(|(_, fs_type, options)| FilesystemProfile::for_mount(fs_type, options))
+        .unwrap_or_default()
+}
+
+/// Detects the filesystem `path` is on, for a missing path the one it would be created on.
+pub fn detect_filesystem(path: &Path) -> FilesystemProfile {
+    let Ok(mounts) = fs::read_to_string("/proc/mounts") else {
+        return FilesystemProfile::default();
+    };
+    let existing = path
+        .ancestors()
+        .find_map(|ancestor| ancestor.canonicalize().ok())
+        .unwrap_or_else(|| path.to_path_buf());
+    profile_from_mounts(&mounts, &existing)
+}
+
+impl WineCask {
+    /// Records the filesystems of every library folder and of the tools directory, along with
+    /// whether the library folders are mounted.
+    pub async fn update_filesystem_profiles(&self) {
+        let steam_util = self.steam_util.clone();
+        let tools_directory = self.steam_util.get_steam_compatibility_tools_directory();
+        let profiles = tokio::task::spawn_blocking(move || {
+            let library_folders = steam_util.list_library_folders().unwrap_or_else(|err| {
+                warn!("Failed to list library folders: {}", err);
+                Vec::new()
+            });
+            library_folders
+                .into_iter()
+                .map(|path| (library_folder_mounted(&path), path))
+                .chain([(true, tools_directory)])
+                .map(|(mounted, path)| PathFilesystem {
+                    profile: detect_filesystem(&path),
+                    path: path.to_string_lossy().to_string(),
+                    mounted,
+                })
+                .collect()
+        })
+        .await;
+        match profiles {
+            Ok(profiles) => self.app_state.lock().await.filesystem_profiles = profiles,
+            Err(err) => warn!("Failed to detect filesystems: {}", err),
+        }
+    }
+
+    /// Library folders whose media is mounted, listed off the async runtime.
+    pub async fn mounted_library_folders(&self) -> Vec<PathBuf> {
+        let steam_util = self.steam_util.clone();
+        let listed = tokio::task::spawn_blocking(move || steam_util.list_mounted_library_folders())
+            .await
+            .map_err(|err| err.to_string())
+            .and_then(|listed| listed.map_err(|err| err.to_string()));
+        listed.unwrap_or_else(|err| {
+            warn!("Failed to list library folders: {}", err);
+            Vec::new()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mounts::fixtures::MOUNTS;
+
+    #[test]
+    fn test_detects_filesystem_of_mount() {
+        let home = profile_from_mounts(MOUNTS, Path::new("/home/deck/.steam/steam"));
+        assert_eq!(home.fs_type.as_deref(), Some("ext4"));
+        assert!(home.symlinks && home.unix_permissions && home.case_sensitive);
+
+        let sd_card = profile_from_mounts(
+            MOUNTS,
+            Path::new("/run/media/deck/SD Card/steamapps/compatdata"),
+        );
+        assert_eq!(sd_card.fs_type.as_deref(), Some("exfat"));
+        assert!(!sd_card.symlinks && !sd_card.unix_permissions && !sd_card.case_sensitive);
+
+        assert!(!profile_from_mounts(MOUNTS, Path::new("/run/media/deck/Windows")).case_sensitive);
+        let games = profile_from_mounts(MOUNTS, Path::new("/run/media/deck/Games/SteamLibrary"));
+        assert!(!games.symlinks && games.case_sensitive);
+
+        // Not under the SD card, only a mount point sharing its prefix
+        assert_eq!(
+            profile_from_mounts(MOUNTS, Path::new("/run/media/deck/SD Card2"))
+                .fs_type
+                .as_deref(),
+            Some("ext4")
+        );
+        assert_eq!(
+            profile_from_mounts("", Path::new("/home")),
+            FilesystemProfile::default()
+        );
+    }
+
+    #[test]
+    fn test_name_collisions_follow_case_sensitivity() {
+        let ext4 = FilesystemProfile::for_mount("ext4", "rw");
+        let exfat = FilesystemProfile::for_mount("exfat", "rw");
+        assert!(!ext4.names_collide("GE-Proton9-20", "ge-proton9-20"));
+        assert!(exfat.names_collide("GE-Proton9-20", "ge-proton9-20"));
+        assert!(!exfat.names_collide("GE-Proton9-20", "GE-Proton9-21"));
+    }
+
+    #[test]
+    fn test_refuses_symlinks_unless_copying() {
+        let destination = Path::new("/run/media/deck/SD Card/compatibilitytools.d");
+        let exfat = FilesystemProfile::for_mount("exfat", "rw");
+        let refusal = exfat.refuse_symlinks(destination, false).unwrap();
+        assert_eq!(refusal.code, AppErrorCode::UnsupportedFilesystem);
+        assert!(refusal.message.contains("exfat") && refusal.message.contains("copy_install"));
+        assert_eq!(exfat.refuse_symlinks(destination, true), None);
+        assert_eq!(
+            FilesystemProfile::default().refuse_symlinks(destination, false),
+            None
+        );
+    }
+}
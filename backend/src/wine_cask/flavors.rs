@@ -1,19 +1,42 @@
 use crate::github_util;
-use crate::github_util::Release;
+use crate::github_util::{GitHubUtilError, ListedReleases, Release};
+use crate::runtime_directory;
+use crate::steam_util::SteamApp;
 use crate::wine_cask::app::WineCask;
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::error_aggregation::{ErrorReport, ErrorSource};
+use crate::wine_cask::install_manifest::mark_managed;
+use crate::wine_cask::install_target::{directory_matches_release, install_target};
+use crate::wine_cask::local_changes::LocalChanges;
+use crate::wine_cask::naming::{naming_schemes, tool_matches_release};
+use crate::wine_cask::network_usage::NetworkTraffic;
+use crate::wine_cask::proton_tkg;
+use crate::wine_cask::r#virtual::VirtualToolStatus;
+use crate::wine_cask::reachability::GitHubReachability;
+use crate::wine_cask::requirements::{load_flavor_requirements, Requirements};
+use crate::wine_cask::settings::Settings;
+use crate::wine_cask::skipped_releases::{latest_release, skipped_tags};
+use crate::wine_cask::steam_tinker_launch;
+use crate::wine_cask::tool_inspection::ToolInspection;
+use crate::PeerMap;
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::{env, fs};
+use std::fs;
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum CompatibilityToolFlavor {
     Unknown,
     ProtonGE,
     SteamTinkerLaunch,
     Luxtorpeda,
     Boxtron,
+    Roberta,
+    ProtonTkg,
+    WineGE,
+    Dxvk,
+    Vkd3dProton,
 }
 
 impl std::fmt::Display for CompatibilityToolFlavor {
@@ -24,6 +47,11 @@ impl std::fmt::Display for CompatibilityToolFlavor {
             CompatibilityToolFlavor::SteamTinkerLaunch => write!(f, "SteamTinkerLaunch"),
             CompatibilityToolFlavor::Luxtorpeda => write!(f, "Luxtorpeda"),
             CompatibilityToolFlavor::Boxtron => write!(f, "Boxtron"),
+            CompatibilityToolFlavor::Roberta => write!(f, "Roberta"),
+            CompatibilityToolFlavor::ProtonTkg => write!(f, "ProtonTkg"),
+            CompatibilityToolFlavor::WineGE => write!(f, "WineGE"),
+            CompatibilityToolFlavor::Dxvk => write!(f, "Dxvk"),
+            CompatibilityToolFlavor::Vkd3dProton => write!(f, "Vkd3dProton"),
         }
     }
 }
@@ -32,6 +60,14 @@ impl std::fmt::Display for CompatibilityToolFlavor {
 pub struct Flavor {
     pub flavor: CompatibilityToolFlavor,
     pub releases: Vec<Release>,
+    pub requirements: Requirements,
+    /// Requirements this system doesn't meet or that couldn't be verified.
+    pub requirement_warnings: Vec<String>,
+    /// Newest release that wasn't skipped, installed or not.
+    pub latest_release: Option<String>,
+    pub skipped_releases: Vec<String>,
+    /// When the cached releases were fetched, set when they're shown because fetching failed.
+    pub stale_since: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -41,11 +77,33 @@ pub struct SteamCompatibilityTool {
     pub display_name: String,
     pub internal_name: String,
     pub used_by_games: Vec<String>,
+    /// Apps mapped to the tool, including those no longer installed.
+    #[serde(default)]
+    pub used_by_apps: Vec<SteamApp>,
     pub requires_restart: bool,
+    pub supports_32bit: bool,
     pub flavor: CompatibilityToolFlavor,
     pub github_release: Option<Release>,
-    //pub r#virtual: bool,
-    //pub virtual_original: String, // Display name or Internal name or name?
+    /// Files changed since the tool was installed, `None` if unchanged or not checked yet.
+    #[serde(default)]
+    pub modified_since_install: Option<LocalChanges>,
+    /// Size, components and the like, `None` until the tool was inspected in the background.
+    #[serde(default)]
+    pub inspection: Option<ToolInspection>,
+    /// Installed by the plugin or matching a release of its flavor, only managed tools are
+    /// updated.
+    #[serde(default)]
+    pub managed: bool,
+    /// Release a managed tool was installed from, or the build of an official Proton release.
+    #[serde(default)]
+    pub version: Option<String>,
+    /// One of Valve's Proton releases in a library folder, Steam installs, updates and
+    /// uninstalls it.
+    #[serde(default)]
+    pub official: bool,
+    /// Set for virtual tools, which run another tool with their own settings.
+    #[serde(default)]
+    pub r#virtual: Option<VirtualToolStatus>,
 }
 
 // SteamClient.Apps.GetAvailableCompatTools()
@@ -57,68 +115,160 @@ pub struct SteamClientCompatToolInfo {
     pub str_display_name: String,
 }
 
+/// Flavors releases are fetched for.
+const FETCHED_FLAVORS: [CompatibilityToolFlavor; 9] = [
+    CompatibilityToolFlavor::ProtonGE,
+    CompatibilityToolFlavor::SteamTinkerLaunch,
+    CompatibilityToolFlavor::Luxtorpeda,
+    CompatibilityToolFlavor::Boxtron,
+    CompatibilityToolFlavor::Roberta,
+    CompatibilityToolFlavor::ProtonTkg,
+    CompatibilityToolFlavor::WineGE,
+    CompatibilityToolFlavor::Dxvk,
+    CompatibilityToolFlavor::Vkd3dProton,
+];
+
+/// Key a flavor signs its release archives with, published as detached signatures next to them.
+pub struct SigningKey {
+    /// Appended to an archive's name to get the name of its signature asset, tried in order.
+    pub signature_suffixes: &'static [&'static str],
+    /// Ed25519 public key, embedded so a compromised release can't bring its own.
+    pub public_key: [u8; 32],
+}
+
+/// Flavors whose releases are signed, along with the key they're signed with. None of the
+/// supported flavors sign their archives yet, a flavor is added here once it publishes a key.
+const SIGNING_KEYS: &[(CompatibilityToolFlavor, SigningKey)] = &[];
+
+pub fn signing_key(flavor: &CompatibilityToolFlavor) -> Option<&'static SigningKey> {
+    SIGNING_KEYS
+        .iter()
+        .find(|(signed, _)| signed == flavor)
+        .map(|(_, key)| key)
+}
+
+/// How much a release fetch may rely on the cached releases.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum CacheUse {
+    /// Use the cache until a refresh is due.
+    Cached,
+    /// Ask GitHub whether the cache is still current, which doesn't count against the rate limit.
+    Revalidate,
+    /// Download every release again.
+    Bypass,
+}
+
+/// Releases of a repository as last fetched, along with the ETag to revalidate them with.
+#[derive(Serialize, Deserialize)]
+struct ReleaseCache {
+    etag: Option<String>,
+    releases: Vec<Release>,
+}
+
+/// GitHub repository a flavor is released on.
+pub fn flavor_repository(flavor: &CompatibilityToolFlavor) -> Option<(&'static str, &'static str)> {
+    match flavor {
+        CompatibilityToolFlavor::ProtonGE => Some(("GloriousEggroll", "proton-ge-custom")),
+        CompatibilityToolFlavor::SteamTinkerLaunch => Some(("sonic2kk", "steamtinkerlaunch")),
+        CompatibilityToolFlavor::Luxtorpeda => Some(("luxtorpeda-dev", "luxtorpeda")),
+        CompatibilityToolFlavor::Boxtron => Some(("dreamer", "boxtron")),
+        CompatibilityToolFlavor::Roberta => Some(("dreamer", "roberta")),
+        CompatibilityToolFlavor::ProtonTkg => Some(("Frogging-Family", "wine-tkg-git")),
+        CompatibilityToolFlavor::WineGE => Some(("GloriousEggroll", "wine-ge-custom")),
+        CompatibilityToolFlavor::Dxvk => Some(("doitsujin", "dxvk")),
+        CompatibilityToolFlavor::Vkd3dProton => Some(("HansKristian-Work", "vkd3d-proton")),
+        CompatibilityToolFlavor::Unknown => None,
+    }
+}
+
+/// Whether releases of `flavor` are fetched, nightly builds only if the user opted in.
+fn is_fetched(flavor: &CompatibilityToolFlavor, settings: &Settings) -> bool {
+    FETCHED_FLAVORS.contains(flavor)
+        && (*flavor != CompatibilityToolFlavor::ProtonTkg || settings.proton_tkg_builds)
+}
+
 impl WineCask {
-    pub async fn get_flavors(&self, renew_cache: bool) -> Vec<Flavor> {
+    pub async fn get_flavors(&self, peer_map: &PeerMap, cache_use: CacheUse) -> Vec<Flavor> {
+        let settings = self.app_state.lock().await.settings.clone();
         let mut flavors = Vec::new();
+        for flavor in FETCHED_FLAVORS {
+            if !is_fetched(&flavor, &settings) {
+                continue;
+            }
+            let (owner, repository) = flavor_repository(&flavor).unwrap();
+            flavors.push(
+                self.get_flavor(peer_map, flavor, owner, repository, cache_use)
+                    .await,
+            );
+        }
+        flavors
+    }
 
-        let proton_ge_flavor = self
-            .get_flavor(
-                CompatibilityToolFlavor::ProtonGE,
-                "GloriousEggroll",
-                "proton-ge-custom",
-                renew_cache,
-            )
-            .await;
-        /*let steam_tinker_launch_flavor = self
-        .get_flavor(
-            &installed_compatibility_tools,
-            CompatibilityToolFlavor::SteamTinkerLaunch,
-            "sonic2kk",
-            "steamtinkerlaunch",
-            renew_cache,
-        )
-        .await;*/
-        let luxtorpeda_flavor = self
-            .get_flavor(
-                CompatibilityToolFlavor::Luxtorpeda,
-                "luxtorpeda-dev",
-                "luxtorpeda",
-                renew_cache,
-            )
-            .await;
-        let boxtron_flavor = self
+    /// Fetches the releases of a single flavor, leaving the others cached.
+    pub async fn refresh_flavor(&self, peer_map: &PeerMap, flavor: CompatibilityToolFlavor) {
+        let settings = self.app_state.lock().await.settings.clone();
+        let Some((owner, repository)) =
+            flavor_repository(&flavor).filter(|_| is_fetched(&flavor, &settings))
+        else {
+            warn!("Releases of {} aren't fetched", flavor);
+            return;
+        };
+        let fetched = self
             .get_flavor(
-                CompatibilityToolFlavor::Boxtron,
-                "dreamer",
-                "boxtron",
-                renew_cache,
+                peer_map,
+                flavor.clone(),
+                owner,
+                repository,
+                CacheUse::Revalidate,
             )
             .await;
-
-        flavors.push(proton_ge_flavor);
-        //flavors.push(steam_tinker_launch_flavor); fixme: we need to have a special installation process for this.
-        flavors.push(luxtorpeda_flavor);
-        flavors.push(boxtron_flavor);
-
-        flavors
+        let mut app_state = self.app_state.lock().await;
+        match app_state
+            .flavors
+            .iter_mut()
+            .find(|cached| cached.flavor == flavor)
+        {
+            Some(cached) => *cached = fetched,
+            None => app_state.flavors.push(fetched),
+        }
     }
 
     async fn get_flavor(
         &self,
+        peer_map: &PeerMap,
         compatibility_tool_flavor: CompatibilityToolFlavor,
         owner: &str,
         repository: &str,
-        renew_cache: bool,
+        cache_use: CacheUse,
     ) -> Flavor {
-        if let Some(github_releases) = self.get_releases(owner, repository, renew_cache).await {
+        let requirements = load_flavor_requirements(&compatibility_tool_flavor);
+        let requirement_warnings =
+            requirements.unmet(&self.app_state.lock().await.environment.system_versions);
+        if let Some((mut github_releases, stale_since)) = self
+            .get_releases(peer_map, owner, repository, cache_use)
+            .await
+        {
+            if compatibility_tool_flavor == CompatibilityToolFlavor::ProtonTkg {
+                github_releases = proton_tkg::listed_builds(github_releases);
+            }
             Flavor {
                 flavor: compatibility_tool_flavor,
                 releases: github_releases,
+                requirements,
+                requirement_warnings,
+                latest_release: None,
+                skipped_releases: Vec::new(),
+                stale_since,
             }
         } else {
             Flavor {
                 flavor: compatibility_tool_flavor,
                 releases: Vec::new(),
+                requirements,
+                requirement_warnings,
+                latest_release: None,
+                skipped_releases: Vec::new(),
+                stale_since: None,
             }
         }
     }
@@ -127,98 +277,171 @@ impl WineCask {
         let mut app_state = self.app_state.lock().await;
         app_state.available_flavors.clear();
         for flavor in app_state.flavors.clone() {
-            let mut installed_compatibility_tools = app_state.installed_compatibility_tools.clone();
             let compatibility_tool_flavor = flavor.flavor.clone();
+            // Runners and components are installed elsewhere, never as Steam compatibility tools
+            let target = install_target(&compatibility_tool_flavor);
+            let mut installed_compatibility_tools = app_state.installed_to(target).clone();
             let github_releases = flavor.releases.clone();
 
+            let schemes = naming_schemes(&compatibility_tool_flavor);
+            // SteamTinkerLaunch keeps its name across versions, its script tells which it is
+            let script_version = installed_compatibility_tools
+                .iter()
+                .filter(|_| compatibility_tool_flavor == CompatibilityToolFlavor::SteamTinkerLaunch)
+                .find(|tool| tool.internal_name == steam_tinker_launch::INTERNAL_NAME)
+                .and_then(|tool| steam_tinker_launch::installed_version(Path::new(&tool.path)));
+            let matches = |tool: &SteamCompatibilityTool, release: &Release| {
+                tool_matches_release(
+                    schemes,
+                    &compatibility_tool_flavor,
+                    &tool.internal_name,
+                    &tool.display_name,
+                    release,
+                ) || (tool.internal_name == steam_tinker_launch::INTERNAL_NAME
+                    && script_version.as_ref() == Some(&release.tag_name))
+                    || directory_matches_release(target, tool, &release.tag_name)
+            };
+
             for steam_compat_tool in &mut installed_compatibility_tools {
-                if let Some(release) = github_releases.iter().find(|gh| {
-                    if compatibility_tool_flavor == CompatibilityToolFlavor::ProtonGE {
-                        steam_compat_tool.internal_name == gh.tag_name
-                            || steam_compat_tool.display_name == gh.tag_name
-                    } else {
-                        steam_compat_tool.display_name
-                            == compatibility_tool_flavor.to_string() + " " + &gh.tag_name
-                            || steam_compat_tool.internal_name
-                                == compatibility_tool_flavor.to_string() + &gh.tag_name
-                    }
-                }) {
+                if steam_compat_tool.official {
+                    continue;
+                }
+                if let Some(release) = github_releases
+                    .iter()
+                    .find(|gh| matches(steam_compat_tool, gh))
+                {
                     steam_compat_tool.flavor = compatibility_tool_flavor.clone();
                     steam_compat_tool.github_release = Some(release.clone());
                 }
             }
 
-            app_state.installed_compatibility_tools = installed_compatibility_tools.clone();
+            *app_state.installed_to(target) = installed_compatibility_tools.clone();
 
             let not_installed: Vec<Release> = github_releases
                 .iter()
                 .filter(|gh| {
-                    !installed_compatibility_tools.iter().any(|tool| {
-                        if compatibility_tool_flavor == CompatibilityToolFlavor::ProtonGE {
-                            tool.internal_name == gh.tag_name || tool.display_name == gh.tag_name
-                        } else {
-                            tool.display_name
-                                == compatibility_tool_flavor.to_string() + " " + &gh.tag_name
-                                || tool.internal_name
-                                    == compatibility_tool_flavor.to_string() + &gh.tag_name
-                        }
-                    })
+                    !installed_compatibility_tools
+                        .iter()
+                        .any(|tool| matches(tool, gh))
                 })
                 .cloned()
                 .collect();
+            let skipped_tags = skipped_tags(
+                &app_state.settings.skipped_releases,
+                &compatibility_tool_flavor,
+            );
+            let latest_release = latest_release(&github_releases, &skipped_tags)
+                .map(|release| release.tag_name.clone());
+            let skipped_releases = skipped_tags.into_iter().map(str::to_string).collect();
             app_state.available_flavors.push(Flavor {
                 flavor: compatibility_tool_flavor,
                 releases: not_installed,
+                requirements: flavor.requirements,
+                requirement_warnings: flavor.requirement_warnings,
+                latest_release,
+                skipped_releases,
+                stale_since: flavor.stale_since,
             });
         }
+
+        let app_state = &mut *app_state;
+        for tools in [
+            &mut app_state.installed_compatibility_tools,
+            &mut app_state.installed_runners,
+            &mut app_state.installed_components,
+        ] {
+            mark_managed(tools, &app_state.install_manifest);
+        }
     }
 
+    /// Returns the releases along with when they were cached if fetching them failed, in which case
+    /// the cache is used however old it is.
     async fn get_releases(
         &self,
+        peer_map: &PeerMap,
         owner: &str,
         repository: &str,
-        renew_cache: bool,
-    ) -> Option<Vec<Release>> {
-        const SECONDS_IN_A_DAY: u64 = 84_600;
-
-        let path = env::var("DECKY_PLUGIN_RUNTIME_DIR").unwrap_or("/tmp/".parse().unwrap());
-
+        cache_use: CacheUse,
+    ) -> Option<(Vec<Release>, Option<u64>)> {
         let file_name = format!("github_releases_{}_{}_cache.json", owner, repository);
-        let cache_file = PathBuf::from(path).join(&file_name);
-
-        if !renew_cache && cache_file.exists() && cache_file.is_file() {
-            let metadata = fs::metadata(&cache_file).ok()?;
-            let modified = metadata.modified().ok()?;
-
-            // Calculate the duration between the current time and the file modification time
-            let now = SystemTime::now();
-            let duration = now.duration_since(modified).ok()?;
+        let cache_file = runtime_directory().join(&file_name);
 
-            if duration.as_secs() < SECONDS_IN_A_DAY {
-                // Update last checked time with file last modified time
-                let unix_timestamp = modified
-                    .duration_since(UNIX_EPOCH)
-                    .expect("Failed to calculate duration")
-                    .as_secs();
-                self.app_state.lock().await.updater_last_check = Some(unix_timestamp);
+        let cache_modified = fs::metadata(&cache_file)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        // A wrong wall clock would make the cache look ancient, or new forever
+        let mut app_state = self.app_state.lock().await;
+        let wall_clock_trusted = app_state.environment.clock_skew.is_none();
+        let refresh_due = app_state.release_refresh.is_due(
+            &file_name,
+            cache_modified,
+            SystemTime::now(),
+            wall_clock_trusted,
+            Instant::now(),
+        );
+        drop(app_state);
 
-                let string = fs::read_to_string(&cache_file).ok()?;
-                let github_releases: Vec<Release> = serde_json::from_str(&string).ok()?;
+        let cache = read_release_cache(&cache_file);
+        if cache_use == CacheUse::Cached {
+            match (&cache, refresh_due, cache_modified) {
+                (Some(cache), false, Some(modified)) => {
+                    // Update last checked time with file last modified time
+                    let unix_timestamp = modified
+                        .duration_since(UNIX_EPOCH)
+                        .expect("Failed to calculate duration")
+                        .as_secs();
+                    self.app_state.lock().await.updater_last_check = Some(unix_timestamp);
 
-                // Check if parsing failed but data exists (cache is corrupted)
-                if github_releases.is_empty() {
-                    info!("Cached data is possibly corrupted or possibly missing information from outdated version. Renewing cache...");
-                } else {
-                    return Some(github_releases);
+                    // Check if parsing failed but data exists (cache is corrupted)
+                    if cache.releases.is_empty() {
+                        info!("Cached data is possibly corrupted or possibly missing information from outdated version. Renewing cache...");
+                    } else {
+                        return Some((cache.releases.clone(), None));
+                    }
+                }
+                (Some(_), true, _) => {
+                    info!("Cached releases are due for a refresh. Fetching new releases.")
                 }
-            } else {
-                info!("Cache file is older than 1 day. Fetching new releases.");
+                _ => {}
             }
         }
+        let mut app_state = self.app_state.lock().await;
+        app_state.release_refresh.record(&file_name, Instant::now());
+        let token = app_state.settings.github_token.clone();
+        drop(app_state);
 
-        let github_releases = match github_util::list_all_releases(owner, repository).await {
-            Ok(releases) => {
-                if releases.is_empty() {
+        // An empty cache is no use to revalidate
+        let etag = cache
+            .as_ref()
+            .filter(|cache| cache_use != CacheUse::Bypass && !cache.releases.is_empty())
+            .and_then(|cache| cache.etag.clone());
+        let result =
+            github_util::list_all_releases(owner, repository, token.as_deref(), etag.as_deref())
+                .await;
+        self.app_state.lock().await.github_rate_limit_reset = match &result {
+            Err(GitHubUtilError::RateLimited { reset_at }) => *reset_at,
+            _ => None,
+        };
+        let github_releases = match result {
+            Ok((listed, bytes_received)) => {
+                self.record_network_usage(NetworkTraffic::Metadata, bytes_received)
+                    .await;
+                self.record_github_reachability(GitHubReachability::Reachable)
+                    .await;
+                let cache = match (listed, cache) {
+                    (ListedReleases::NotModified, Some(cache)) => {
+                        info!("{}/{}: Cached releases are current", owner, repository);
+                        cache
+                    }
+                    (ListedReleases::NotModified, None) => {
+                        error!("Releases weren't sent again but the cache is gone.");
+                        return None;
+                    }
+                    (ListedReleases::Modified { releases, etag }, _) => {
+                        ReleaseCache { etag, releases }
+                    }
+                };
+                if cache.releases.is_empty() {
                     error!("No releases found.");
                     return None;
                 }
@@ -231,25 +454,50 @@ impl WineCask {
                     .as_secs();
                 self.app_state.lock().await.updater_last_check = Some(unix_timestamp);
 
-                let json = serde_json::to_string(&releases).ok()?;
+                // Rewritten when revalidated too, its modification time is when it was last checked
+                let json = serde_json::to_string(&cache).ok()?;
                 fs::write(&cache_file, json).ok()?;
-                releases
+                (cache.releases, None)
             }
-            Err(_) => {
-                if cache_file.exists() && cache_file.is_file() {
+            Err(err) => {
+                let mut message = format!(
+                    "Failed to fetch releases: {}",
+                    self.annotate_tls_error(&err.to_string()).await
+                );
+                let rate_limited = matches!(err, GitHubUtilError::RateLimited { .. });
+                // Tells a blocked API, where cached releases still install, from being offline
+                if !rate_limited {
+                    let reachability = self.check_github_reachability().await;
+                    if reachability != GitHubReachability::Reachable {
+                        message = format!("{} ({})", message, reachability.describe());
+                    }
+                }
+                warn!("{}/{}: {}", owner, repository, message);
+                self.broadcast_error(
+                    peer_map,
+                    ErrorReport {
+                        code: if rate_limited {
+                            "github_rate_limited"
+                        } else {
+                            "release_fetch_failed"
+                        },
+                        source: ErrorSource::Network,
+                        target: format!("{}/{}", owner, repository),
+                        error: AppError::new(AppErrorCode::NetworkError, message),
+                        terminal: false,
+                    },
+                )
+                .await;
+                if let (Some(cache), Some(modified)) = (cache, cache_modified) {
                     // Update last checked time with file last modified time
-                    let metadata = fs::metadata(&cache_file).ok()?;
-                    let modified = metadata.modified().ok()?;
                     let unix_timestamp = modified
                         .duration_since(UNIX_EPOCH)
                         .expect("Failed to calculate duration")
                         .as_secs();
                     self.app_state.lock().await.updater_last_check = Some(unix_timestamp);
 
-                    let string = fs::read_to_string(&cache_file).ok()?;
-                    let github_releases: Vec<Release> = serde_json::from_str(&string).ok()?;
                     warn!("Unable to fetch new releases. Using cached releases.");
-                    github_releases
+                    (cache.releases, Some(unix_timestamp))
                 } else {
                     error!("Unable to fetch new releases. No cached releases found.");
                     return None;
@@ -260,3 +508,67 @@ impl WineCask {
         Some(github_releases)
     }
 }
+
+/// Reads cached releases, `None` if there are none or they can't be read.
+fn read_release_cache(cache_file: &Path) -> Option<ReleaseCache> {
+    let string = fs::read_to_string(cache_file).ok()?;
+    serde_json::from_str(&string).ok().or_else(|| {
+        // Older versions cached the bare list
+        let releases = serde_json::from_str(&string).ok()?;
+        Some(ReleaseCache {
+            etag: None,
+            releases,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_flavors_serialize_by_name() {
+        // Settings and cached payloads refer to flavors by these names
+        let flavors = [
+            CompatibilityToolFlavor::Unknown,
+            CompatibilityToolFlavor::ProtonGE,
+            CompatibilityToolFlavor::SteamTinkerLaunch,
+            CompatibilityToolFlavor::Luxtorpeda,
+            CompatibilityToolFlavor::Boxtron,
+            CompatibilityToolFlavor::Roberta,
+            CompatibilityToolFlavor::ProtonTkg,
+            CompatibilityToolFlavor::WineGE,
+            CompatibilityToolFlavor::Dxvk,
+            CompatibilityToolFlavor::Vkd3dProton,
+        ];
+        assert_eq!(
+            serde_json::to_string(&flavors).unwrap(),
+            r#"["Unknown","ProtonGE","SteamTinkerLaunch","Luxtorpeda","Boxtron","Roberta","ProtonTkg","WineGE","Dxvk","Vkd3dProton"]"#
+        );
+        for flavor in flavors {
+            assert_eq!(
+                serde_json::to_string(&flavor).unwrap(),
+                format!("\"{}\"", flavor)
+            );
+        }
+    }
+
+    #[test]
+    fn test_release_cache_of_older_versions_is_read() {
+        let dir = tempdir().unwrap();
+        let cache_file = dir.path().join("github_releases_cache.json");
+        assert!(read_release_cache(&cache_file).is_none());
+
+        fs::write(&cache_file, "[]").unwrap();
+        let cache = read_release_cache(&cache_file).unwrap();
+        assert_eq!(cache.etag, None);
+        assert!(cache.releases.is_empty());
+
+        fs::write(&cache_file, r#"{"etag": "\"v1\"", "releases": []}"#).unwrap();
+        assert_eq!(
+            read_release_cache(&cache_file).unwrap().etag.as_deref(),
+            Some("\"v1\"")
+        );
+    }
+}
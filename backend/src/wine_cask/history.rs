@@ -0,0 +1,361 @@
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, WineCask};
+use crate::wine_cask::app_error::AppErrorCode;
+use crate::wine_cask::flavors::CompatibilityToolFlavor;
+use crate::wine_cask::provenance::current_timestamp;
+use crate::wine_cask::rollback::RollbackPlan;
+use crate::PeerMap;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{fs, io};
+
+/// Records kept in the history, older ones are dropped on startup.
+const MAX_RECORDS: usize = 500;
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum HistoryTaskType {
+    Install,
+    Uninstall,
+    /// Going back to the tools an update moved apps away from.
+    Rollback,
+}
+
+/// What started a task, superseded tools uninstalled after an update inherit its trigger.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug, Default)]
+pub enum HistoryTrigger {
+    #[default]
+    Manual,
+    /// Updating all tools from the frontend.
+    UpdateAll,
+    /// The periodic update check queued the update on its own.
+    AutoUpdate,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum HistoryOutcome {
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// One finished install, uninstall or rollback.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct HistoryRecord {
+    /// Identifies the record, `0` for records written by older versions.
+    #[serde(default)]
+    pub id: u64,
+    /// When the task ended.
+    pub timestamp: u64,
+    pub task_type: HistoryTaskType,
+    pub flavor: CompatibilityToolFlavor,
+    /// Tag of the release installed, or name of the tool uninstalled.
+    pub version: String,
+    pub duration_ms: u64,
+    pub outcome: HistoryOutcome,
+    /// Set if the task failed with an error, refusals only told to the peers have none.
+    pub error_code: Option<AppErrorCode>,
+    pub trigger: HistoryTrigger,
+    /// How to go back to the tools an update moved apps away from.
+    #[serde(default)]
+    pub rollback: Option<RollbackPlan>,
+    /// Id of the update a rollback went back from.
+    #[serde(default)]
+    pub rolls_back: Option<u64>,
+}
+
+impl HistoryRecord {
+    pub fn new(
+        task_type: HistoryTaskType,
+        flavor: CompatibilityToolFlavor,
+        version: String,
+        duration: Duration,
+        result: Result<HistoryOutcome, Option<AppErrorCode>>,
+        trigger: HistoryTrigger,
+    ) -> HistoryRecord {
+        let (outcome, error_code) = match result {
+            Ok(outcome) => (outcome, None),
+            Err(error_code) => (HistoryOutcome::Failed, error_code),
+        };
+        HistoryRecord {
+            id: next_record_id(),
+            timestamp: current_timestamp(),
+            task_type,
+            flavor,
+            version,
+            duration_ms: duration.as_millis() as u64,
+            outcome,
+            error_code,
+            trigger,
+            rollback: None,
+            rolls_back: None,
+        }
+    }
+}
+
+/// Records of the history, newest first, and how many there are in all.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct HistoryPage {
+    pub records: Vec<HistoryRecord>,
+    pub total: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HistoryQuery {
+    pub limit: usize,
+    /// Newest records skipped, used for paging.
+    #[serde(default)]
+    pub offset: usize,
+}
+
+struct History {
+    /// `None` until startup opened the history, nothing is recorded before.
+    log_file: Option<PathBuf>,
+    /// How running installs ended, by task id, until the install returns.
+    outcomes: BTreeMap<u64, Result<HistoryOutcome, Option<AppErrorCode>>>,
+    /// Rollback plans of running installs, by task id, until the install returns.
+    rollbacks: BTreeMap<u64, RollbackPlan>,
+    next_id: u64,
+}
+
+static HISTORY: Mutex<History> = Mutex::new(History {
+    log_file: None,
+    outcomes: BTreeMap::new(),
+    rollbacks: BTreeMap::new(),
+    next_id: 1,
+});
+
+/// Held by tests that open a history, there's one for the whole process.
+#[cfg(test)]
+pub static TEST_HISTORY: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Keeps the newest records of the history in `log_file`, every later record is appended to it.
+pub fn open_history(log_file: PathBuf) {
+    let records = read_records(&log_file);
+    if records.len() > MAX_RECORDS {
+        let kept = &records[records.len() - MAX_RECORDS..];
+        if let Err(err) = write_history(&log_file, kept) {
+            warn!("Failed to truncate history: {}", err);
+        }
+    }
+    let mut history = HISTORY.lock().unwrap();
+    let last_id = records.iter().map(|record| record.id).max().unwrap_or(0);
+    history.next_id = history.next_id.max(last_id + 1);
+    history.log_file = Some(log_file);
+}
+
+fn next_record_id() -> u64 {
+    let mut history = HISTORY.lock().unwrap();
+    history.next_id += 1;
+    history.next_id - 1
+}
+
+/// Appends `record` to the history.
+pub fn record(record: &HistoryRecord) {
+    let log_file = HISTORY.lock().unwrap().log_file.clone();
+    if let Some(log_file) = log_file {
+        if let Err(err) = append_record(&log_file, record) {
+            warn!("Failed to append to history: {}", err);
+        }
+    }
+}
+
+/// Notes how the install of task `task_id` ended, recorded once the install returns.
+pub fn note_outcome(task_id: u64, result: Result<HistoryOutcome, Option<AppErrorCode>>) {
+    HISTORY.lock().unwrap().outcomes.insert(task_id, result);
+}
+
+/// How the install of task `task_id` ended, a failure without error if nothing was noted since
+/// refusals are only told to the peers.
+pub fn take_outcome(task_id: u64) -> Result<HistoryOutcome, Option<AppErrorCode>> {
+    HISTORY
+        .lock()
+        .unwrap()
+        .outcomes
+        .remove(&task_id)
+        .unwrap_or(Err(None))
+}
+
+/// Notes the rollback plan of the update task `task_id` made, recorded with the install.
+pub fn note_rollback(task_id: u64, rollback: RollbackPlan) {
+    HISTORY.lock().unwrap().rollbacks.insert(task_id, rollback);
+}
+
+pub fn take_rollback(task_id: u64) -> Option<RollbackPlan> {
+    HISTORY.lock().unwrap().rollbacks.remove(&task_id)
+}
+
+/// Every record of the history in the order they were appended, none before startup opened it.
+pub fn all_records() -> Vec<HistoryRecord> {
+    match HISTORY.lock().unwrap().log_file.clone() {
+        Some(log_file) => read_records(&log_file),
+        None => Vec::new(),
+    }
+}
+
+fn append_record(log_file: &Path, record: &HistoryRecord) -> io::Result<()> {
+    if let Some(parent) = log_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)
+}
+
+fn write_history(log_file: &Path, records: &[HistoryRecord]) -> io::Result<()> {
+    let mut lines = String::new();
+    for record in records {
+        lines.push_str(&serde_json::to_string(record)?);
+        lines.push('\n');
+    }
+    let temporary_file = log_file.with_extension("tmp");
+    fs::write(&temporary_file, lines)?;
+    fs::rename(temporary_file, log_file)
+}
+
+// Records in the order they were appended, skipping unreadable lines.
+fn read_records(log_file: &Path) -> Vec<HistoryRecord> {
+    let Ok(history) = fs::read_to_string(log_file) else {
+        return Vec::new();
+    };
+    history
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Returns `query.limit` records, newest first, after skipping the newest `query.offset`.
+pub fn read_history(log_file: &Path, query: &HistoryQuery) -> HistoryPage {
+    let records = read_records(log_file);
+    HistoryPage {
+        total: records.len(),
+        records: records
+            .into_iter()
+            .rev()
+            .skip(query.offset)
+            .take(query.limit)
+            .collect(),
+    }
+}
+
+impl WineCask {
+    pub async fn get_history(&self, peer_map: &PeerMap, query: HistoryQuery) {
+        let log_file = HISTORY.lock().unwrap().log_file.clone();
+        let history = match log_file {
+            Some(log_file) => read_history(&log_file, &query),
+            None => HistoryPage {
+                records: Vec::new(),
+                total: 0,
+            },
+        };
+        broadcast_to_peers(
+            peer_map,
+            &Request {
+                history: Some(history),
+                ..Request::new(RequestType::History)
+            },
+        )
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn history_record(
+        version: &str,
+        result: Result<HistoryOutcome, Option<AppErrorCode>>,
+    ) -> HistoryRecord {
+        HistoryRecord::new(
+            HistoryTaskType::Install,
+            CompatibilityToolFlavor::ProtonGE,
+            version.to_string(),
+            Duration::from_millis(1500),
+            result,
+            HistoryTrigger::AutoUpdate,
+        )
+    }
+
+    #[test]
+    fn test_history_record() {
+        let failed = history_record("GE-Proton9-20", Err(Some(AppErrorCode::NetworkError)));
+        assert_eq!(failed.outcome, HistoryOutcome::Failed);
+        assert_eq!(failed.error_code, Some(AppErrorCode::NetworkError));
+        assert_eq!(failed.duration_ms, 1500);
+        let cancelled = history_record("GE-Proton9-20", Ok(HistoryOutcome::Cancelled));
+        assert_eq!(cancelled.outcome, HistoryOutcome::Cancelled);
+        assert_eq!(cancelled.error_code, None);
+    }
+
+    #[test]
+    fn test_read_history_pages() {
+        let directory = tempdir().unwrap();
+        let log_file = directory.path().join("history.jsonl");
+        for version in ["GE-Proton9-1", "GE-Proton9-2", "GE-Proton9-3"] {
+            append_record(
+                &log_file,
+                &history_record(version, Ok(HistoryOutcome::Succeeded)),
+            )
+            .unwrap();
+        }
+        let mut history = fs::read_to_string(&log_file).unwrap();
+        history.push_str("not json\n");
+        fs::write(&log_file, history).unwrap();
+
+        let first_page = read_history(
+            &log_file,
+            &HistoryQuery {
+                limit: 2,
+                offset: 0,
+            },
+        );
+        assert_eq!(first_page.total, 3);
+        let versions: Vec<&str> = first_page
+            .records
+            .iter()
+            .map(|record| record.version.as_str())
+            .collect();
+        assert_eq!(versions, vec!["GE-Proton9-3", "GE-Proton9-2"]);
+        let second_page = read_history(
+            &log_file,
+            &HistoryQuery {
+                limit: 2,
+                offset: 2,
+            },
+        );
+        assert_eq!(second_page.records.len(), 1);
+        assert_eq!(second_page.records[0].version, "GE-Proton9-1");
+    }
+
+    #[test]
+    fn test_history_is_truncated() {
+        let _history = TEST_HISTORY.blocking_lock();
+        let directory = tempdir().unwrap();
+        let log_file = directory.path().join("history.jsonl");
+        let records: Vec<HistoryRecord> = (0..MAX_RECORDS + 10)
+            .map(|index| history_record(&index.to_string(), Ok(HistoryOutcome::Succeeded)))
+            .collect();
+        write_history(&log_file, &records).unwrap();
+
+        open_history(log_file.clone());
+        let records = read_records(&log_file);
+        assert_eq!(records.len(), MAX_RECORDS);
+        assert_eq!(records[0].version, "10");
+    }
+
+    #[test]
+    fn test_take_outcome() {
+        note_outcome(7, Err(Some(AppErrorCode::ChecksumMismatch)));
+        assert_eq!(take_outcome(7), Err(Some(AppErrorCode::ChecksumMismatch)));
+        // Refused installs note nothing
+        assert_eq!(take_outcome(7), Err(None));
+    }
+}
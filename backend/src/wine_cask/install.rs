@@ -1,119 +1,809 @@
 use crate::github_util::{Asset, Release};
-use crate::wine_cask::app::WineCask;
-use crate::wine_cask::flavors::CompatibilityToolFlavor;
-use crate::wine_cask::{copy_dir, generate_compatibility_tool_vdf, recursive_delete_dir_entry};
+use crate::runtime_directory;
+use crate::wine_cask::activity::ActivitySource;
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, WineCask};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::atomic_install::{replace_directory, temp_directory};
+use crate::wine_cask::background::{
+    download_limit, run_constrained, ProgressThrottle, RateLimiter, TaskConstraints,
+    ThroughputMeter,
+};
+use crate::wine_cask::cancellation::CancellationToken;
+use crate::wine_cask::checksum::{ArchiveVerifier, CHECKSUM_ATTEMPTS};
+use crate::wine_cask::direct_install::declare_custom_tool;
+use crate::wine_cask::disk_space::check_disk_space;
+use crate::wine_cask::environment::game_changed;
+use crate::wine_cask::extraction::{
+    extract_adaptive, extract_zip, hoist_nested_tool, wrap_flat_extraction, CountingReader,
+};
+use crate::wine_cask::filesystems::detect_filesystem;
+use crate::wine_cask::flavors::{CompatibilityToolFlavor, SteamCompatibilityTool};
+use crate::wine_cask::history::{
+    note_outcome, record, take_outcome, take_rollback, HistoryOutcome, HistoryRecord,
+    HistoryTaskType, HistoryTrigger,
+};
+use crate::wine_cask::install_target::{install_target, InstallTarget};
+use crate::wine_cask::local_install::{sniff_archive_file, unsupported_archive};
+use crate::wine_cask::mutation_guard::{copy_dir_guarded, journal_directory};
+use crate::wine_cask::naming::{naming_schemes, release_version};
+use crate::wine_cask::network_usage::{NetworkPreflight, NetworkTraffic};
+use crate::wine_cask::partial_update::{decompressor, try_partial_update};
+use crate::wine_cask::provenance::{
+    current_timestamp, generate_file_manifest, FileManifestEntry, Provenance, ProvenanceSource,
+};
+use crate::wine_cask::proxy::ProxyConfig;
+use crate::wine_cask::resumable_download::{
+    classify_error, downloads_directory, jitter, retry_delay, DownloadFailure, PartialDownload,
+    DOWNLOAD_ATTEMPTS,
+};
+use crate::wine_cask::steam_tinker_launch;
+use crate::wine_cask::{generate_compatibility_tool_vdf, recursive_delete_dir_entry};
 use crate::PeerMap;
-use flate2::bufread::GzDecoder;
 use futures_util::StreamExt;
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::env;
-use std::fs::create_dir_all;
-use std::io::{Cursor, Read};
+use std::fs::{create_dir_all, File};
+use std::io::BufReader;
 use std::path::{Path, PathBuf};
-use xz2::bufread::XzDecoder;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+use tokio::sync::watch;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Install {
-    pub(crate) flavor: CompatibilityToolFlavor,
-    pub(crate) release: Release,
+    pub flavor: CompatibilityToolFlavor,
+    pub release: Release,
+    /// Install even if the monthly network cap has already been reached.
+    #[serde(default)]
+    pub ignore_network_cap: bool,
+    /// Install even if the release doesn't seem to fit on disk.
+    #[serde(default)]
+    pub ignore_disk_space: bool,
+    /// Always run with background constraints, for installs nobody is waiting on.
+    #[serde(default)]
+    pub background: bool,
+    /// Reinstall even if the installed copy was modified since it was installed.
+    #[serde(default)]
+    pub accept_local_changes_loss: bool,
+    /// Copy the files symlinks point at instead of staging a partial update with links, needed
+    /// when the tools directory is on a filesystem without symlinks.
+    #[serde(default)]
+    pub copy_install: bool,
+    /// Internal names of the tools uninstalled once this release is installed, unless games are
+    /// still mapped to them.
+    #[serde(default)]
+    pub replaces: Vec<String>,
+    /// Move the apps mapped to the replaced tools to this release once it's installed, keeping the
+    /// replaced tools in the trash to roll back to.
+    #[serde(default)]
+    pub migrate_mappings: bool,
+    /// Largest archive downloaded in bytes, for installs from URLs whose size isn't published.
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    /// Archive on disk installed instead of downloading the release.
+    #[serde(default)]
+    pub local_path: Option<String>,
+    /// Restart Steam once installed so it lists the tool, unless a game is running.
+    #[serde(default)]
+    pub restart_steam: bool,
+    /// What queued the install, kept in the history.
+    #[serde(default)]
+    pub trigger: HistoryTrigger,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct QueueCompatibilityTool {
+    /// Id of the task installing it, tells apart the installs running at once.
+    pub id: u64,
     pub flavor: CompatibilityToolFlavor,
     pub name: String,
     pub url: String,
     pub state: QueueCompatibilityToolState,
     pub compress_type: CompressionType,
+    pub size: u64,
     pub progress: u8,
+    /// Limits the install currently runs under, `None` at full speed.
+    pub constraints: Option<TaskConstraints>,
+    /// Download speed over the last second, `None` unless downloading.
+    #[serde(default)]
+    pub bytes_per_second: Option<u64>,
+    /// Set once a download attempt failed and it's tried again.
+    #[serde(default)]
+    pub retry: Option<DownloadRetry>,
+    /// Shared with the entry in the app state, so cancelling that one stops the install.
+    #[serde(skip)]
+    pub cancellation: CancellationToken,
 }
 
-#[derive(Deserialize, Serialize, PartialEq, Clone)]
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
 pub enum QueueCompatibilityToolState {
     Extracting,
     Downloading,
     Waiting,
     Cancelling,
+    /// The download didn't match the published checksum and is downloaded again.
+    ChecksumFailed,
+    /// Waiting to try the download again after it failed.
+    Retrying,
+    /// Checking the archive against its published checksum.
+    Verifying,
+    /// Extracted, the tool is being checked and moved into place.
+    Finalizing,
+}
+
+/// Download attempt under way and how many are made at most.
+#[derive(Deserialize, Serialize, PartialEq, Clone)]
+pub struct DownloadRetry {
+    pub attempt: u32,
+    pub attempts: u32,
 }
 
 #[derive(Deserialize, Serialize, PartialEq, Clone)]
 pub enum CompressionType {
     Gzip,
     Xz,
+    Zstd,
+    /// An uncompressed tar archive.
+    Tar,
+    /// Published by a few forks' CI builds instead of a tar archive.
+    Zip,
     Unknown,
 }
 
+/// Extensions of the archives that can be installed, each with the type it's assumed to be until
+/// its contents tell otherwise.
+pub const ARCHIVE_EXTENSIONS: [(&str, CompressionType); 6] = [
+    (".tar.gz", CompressionType::Gzip),
+    (".tgz", CompressionType::Gzip),
+    (".tar.xz", CompressionType::Xz),
+    (".tar.zst", CompressionType::Zstd),
+    (".tar", CompressionType::Tar),
+    (".zip", CompressionType::Zip),
+];
+
+/// The name of an archive without its extension, `None` if it isn't an archive.
+pub fn archive_stem(name: &str) -> Option<&str> {
+    ARCHIVE_EXTENSIONS
+        .iter()
+        .find_map(|(extension, _)| name.strip_suffix(extension))
+}
+
+/// Type of the archive `asset` is, from its content type or else its name.
+fn asset_compression_type(asset: &Asset) -> Option<CompressionType> {
+    let by_content_type = match asset.content_type.as_str() {
+        "application/gzip" | "application/x-gzip" => Some(CompressionType::Gzip),
+        "application/x-xz" => Some(CompressionType::Xz),
+        "application/zstd" => Some(CompressionType::Zstd),
+        "application/x-tar" => Some(CompressionType::Tar),
+        "application/zip" => Some(CompressionType::Zip),
+        _ => None,
+    };
+    by_content_type.or_else(|| {
+        ARCHIVE_EXTENSIONS
+            .iter()
+            .find(|(extension, _)| asset.name.ends_with(extension))
+            .map(|(_, compress_type)| compress_type.clone())
+    })
+}
+
 impl WineCask {
-    // Why is this task queue here? Well because steam deck will die if someone tries to queue up 50 installs at once.
-    pub async fn install_compatibility_tool(&self, install: Install, peer_map: &PeerMap) {
-        if let Some(mut queue_compatibility_tool) = look_for_compressed_archive(&install) {
-            // Mark as downloading...
-            queue_compatibility_tool.state = QueueCompatibilityToolState::Downloading;
-            queue_compatibility_tool.progress = 0;
-            self.app_state.lock().await.in_progress = Some(queue_compatibility_tool.clone());
+    /// Installs the release of `install` and records how it went in the history.
+    pub async fn install_compatibility_tool(
+        &self,
+        task_id: u64,
+        install: Install,
+        peer_map: &PeerMap,
+    ) {
+        let started = Instant::now();
+        let (flavor, version, trigger) = (
+            install.flavor.clone(),
+            install.release.tag_name.clone(),
+            install.trigger,
+        );
+        self.install_release(task_id, install, peer_map).await;
+        let mut history_record = HistoryRecord::new(
+            HistoryTaskType::Install,
+            flavor,
+            version,
+            started.elapsed(),
+            take_outcome(task_id),
+            trigger,
+        );
+        history_record.rollback = take_rollback(task_id);
+        record(&history_record);
+    }
+
+    async fn install_release(&self, task_id: u64, install: Install, peer_map: &PeerMap) {
+        if install.local_path.is_some() {
+            self.install_local_archive(task_id, install, peer_map).await;
+            return;
+        }
+        let Some(mut queue_compatibility_tool) = look_for_compressed_archive(&install) else {
+            let app_error = AppError::new(
+                AppErrorCode::NotFound,
+                format!(
+                    "{}: none of the release's assets is an archive that can be installed",
+                    install.release.tag_name
+                ),
+            );
+            error!("{}", app_error);
+            note_outcome(task_id, Err(Some(app_error.code)));
+            self.broadcast_app_error(peer_map, app_error.for_task(task_id))
+                .await;
+            return;
+        };
+        queue_compatibility_tool.id = task_id;
+        if !self
+            .network_preflight(peer_map, &install, &queue_compatibility_tool)
+            .await
+        {
+            return;
+        }
+        if !self
+            .disk_space_preflight(peer_map, &install, &queue_compatibility_tool)
+            .await
+        {
+            return;
+        }
+        self.warn_unmet_requirements(peer_map, &install).await;
+
+        // Mark as downloading...
+        queue_compatibility_tool.state = QueueCompatibilityToolState::Downloading;
+        queue_compatibility_tool.progress = 0;
+        queue_compatibility_tool.constraints =
+            self.select_task_constraints(install.background).await;
+        if queue_compatibility_tool.constraints.is_some() {
+            self.announce_constraints(peer_map, &queue_compatibility_tool)
+                .await;
+        }
+        self.set_in_progress(&queue_compatibility_tool).await;
+        self.broadcast_app_state(peer_map).await;
+
+        let expected_checksum = match self
+            .expected_checksum(peer_map, &install, &queue_compatibility_tool)
+            .await
+        {
+            Ok(expected_checksum) => expected_checksum,
+            Err(message) => {
+                self.fail_install(
+                    peer_map,
+                    &queue_compatibility_tool,
+                    AppError::new(AppErrorCode::NetworkError, message),
+                )
+                .await;
+                return;
+            }
+        };
+        let mut attempt = 1;
+        let (archive, checksum) = loop {
+            let Some((archive, verifier)) = self
+                .download_archive(peer_map, &install, &mut queue_compatibility_tool)
+                .await
+            else {
+                return;
+            };
+            queue_compatibility_tool.state = QueueCompatibilityToolState::Verifying;
+            queue_compatibility_tool.progress = 100;
+            queue_compatibility_tool.bytes_per_second = None;
+            self.set_in_progress(&queue_compatibility_tool).await;
             self.broadcast_app_state(peer_map).await;
+            match verifier.finish(expected_checksum.as_deref()) {
+                Ok(checksum) => break (archive, checksum),
+                Err(message) if attempt < CHECKSUM_ATTEMPTS => {
+                    let _ = std::fs::remove_file(&archive);
+                    warn!(
+                        "{}: {}, downloading again",
+                        queue_compatibility_tool.name, message
+                    );
+                    queue_compatibility_tool.state = QueueCompatibilityToolState::ChecksumFailed;
+                    self.set_in_progress(&queue_compatibility_tool).await;
+                    self.broadcast_app_state(peer_map).await;
+                    attempt += 1;
+                }
+                Err(message) => {
+                    let _ = std::fs::remove_file(&archive);
+                    self.fail_install(
+                        peer_map,
+                        &queue_compatibility_tool,
+                        AppError::new(AppErrorCode::ChecksumMismatch, message),
+                    )
+                    .await;
+                    return;
+                }
+            }
+        };
+        if let Err(err) = self
+            .verify_release_signature(peer_map, &install, &queue_compatibility_tool, &checksum)
+            .await
+        {
+            let _ = std::fs::remove_file(&archive);
+            self.fail_install(peer_map, &queue_compatibility_tool, AppError::from(err))
+                .await;
+            return;
+        }
 
-            // Starting download compatibility tool
-            let client = reqwest::Client::new();
-            let response_wrapped = client.get(&queue_compatibility_tool.url).send().await;
-            let response = response_wrapped.unwrap();
-            let total_size = response.content_length().unwrap_or(0);
+        self.extract_generate_and_move(
+            peer_map,
+            &install,
+            &mut queue_compatibility_tool,
+            &archive,
+            checksum,
+        )
+        .await;
+        if let Err(err) = std::fs::remove_file(&archive) {
+            warn!("Failed to delete downloaded archive: {}", err);
+        }
+    }
 
-            let mut downloaded_bytes = Vec::new();
-            let mut downloaded_size = 0;
-            let mut body = response.bytes_stream();
+    /// Downloads the archive, hashing it along the way. `None` if it failed or was cancelled,
+    /// which has already been reported.
+    ///
+    /// Lost connections are resumed from the bytes kept on disk, which also lets a retried
+    /// install continue an earlier attempt.
+    async fn download_archive(
+        &self,
+        peer_map: &PeerMap,
+        install: &Install,
+        queue_compatibility_tool: &mut QueueCompatibilityTool,
+    ) -> Option<(PathBuf, ArchiveVerifier)> {
+        // Starting download compatibility tool
+        queue_compatibility_tool.state = QueueCompatibilityToolState::Downloading;
+        queue_compatibility_tool.progress = 0;
+        queue_compatibility_tool.retry = None;
+        let mut partial =
+            match PartialDownload::open(&downloads_directory(), &queue_compatibility_tool.url) {
+                Ok(partial) => partial,
+                Err(err) => {
+                    let app_error = AppError::from(err).context("Failed to prepare download");
+                    self.fail_install(peer_map, queue_compatibility_tool, app_error)
+                        .await;
+                    return None;
+                }
+            };
+        let proxy = ProxyConfig::current();
+        let client = match proxy.client_builder().build() {
+            Ok(client) => client,
+            Err(err) => {
+                let app_error =
+                    AppError::internal(format!("Failed to create HTTP client: {}", err));
+                self.fail_install(peer_map, queue_compatibility_tool, app_error)
+                    .await;
+                return None;
+            }
+        };
+        let mut downloaded_size = 0;
+        let mut download_rate_limit = self.app_state.lock().await.settings.download_rate_limit;
+        let mut rate_limiter = RateLimiter::new(
+            download_limit(
+                queue_compatibility_tool.constraints.as_ref(),
+                download_rate_limit,
+            ),
+            Instant::now(),
+        );
+        let mut throughput = ThroughputMeter::new(Instant::now());
+        let mut throttle = ProgressThrottle::default();
+        let subscribed = self
+            .environment
+            .lock()
+            .map(|environment| environment.subscribe())
+            .map_err(|err| AppError::internal(err.to_string()));
+        let mut environment_events = match subscribed {
+            Ok(environment_events) => environment_events,
+            Err(app_error) => {
+                self.fail_install(peer_map, queue_compatibility_tool, app_error)
+                    .await;
+                return None;
+            }
+        };
+        let attempts = self
+            .app_state
+            .lock()
+            .await
+            .settings
+            .download_attempts
+            .unwrap_or(DOWNLOAD_ATTEMPTS);
+        let mut attempt = 1;
 
+        let verifier = 'download: loop {
+            let response = match partial.request(&client).await {
+                Ok(response) => response,
+                Err(err)
+                    if attempt < attempts && classify_error(&err) == DownloadFailure::Retryable =>
+                {
+                    warn!(
+                        "{}: download failed, retrying: {}",
+                        queue_compatibility_tool.name,
+                        proxy.describe_error(&err)
+                    );
+                    attempt += 1;
+                    self.wait_to_retry(peer_map, queue_compatibility_tool, attempt, attempts)
+                        .await;
+                    continue;
+                }
+                Err(err) => {
+                    let message = format!(
+                        "Failed to download: {}",
+                        self.annotate_tls_error(&proxy.describe_error(&err)).await
+                    );
+                    self.record_network_usage(NetworkTraffic::Asset, downloaded_size)
+                        .await;
+                    self.fail_install(
+                        peer_map,
+                        queue_compatibility_tool,
+                        AppError::new(AppErrorCode::NetworkError, message),
+                    )
+                    .await;
+                    return None;
+                }
+            };
+            // Resumed downloads are verified including the bytes from earlier attempts
+            let mut verifier = match partial.verifier() {
+                Ok(verifier) => verifier,
+                Err(err) => {
+                    let app_error = AppError::from(err).context("Failed to read partial download");
+                    self.fail_install(peer_map, queue_compatibility_tool, app_error)
+                        .await;
+                    return None;
+                }
+            };
+
+            let mut body = response.bytes_stream();
             while let Some(chunk_result) = body.next().await {
                 // Check if we need to cancel the download
-                if self
-                    .app_state
-                    .lock()
-                    .await
-                    .in_progress
-                    .clone()
-                    .unwrap()
-                    .state
-                    == QueueCompatibilityToolState::Cancelling
+                if queue_compatibility_tool.cancellation.is_cancelled() {
+                    partial.discard();
+                    self.record_network_usage(NetworkTraffic::Asset, downloaded_size)
+                        .await;
+                    self.end_if_cancelled(peer_map, queue_compatibility_tool, &[])
+                        .await;
+                    return None; // We stop the function here
+                }
+                let chunk = match chunk_result {
+                    Ok(chunk) => chunk,
+                    Err(err) if attempt < attempts => {
+                        warn!(
+                            "{}: connection lost at {} bytes, resuming: {}",
+                            queue_compatibility_tool.name,
+                            partial.downloaded,
+                            proxy.describe_error(&err)
+                        );
+                        attempt += 1;
+                        self.wait_to_retry(peer_map, queue_compatibility_tool, attempt, attempts)
+                            .await;
+                        continue 'download;
+                    }
+                    Err(err) => {
+                        // The partial download is kept for when the install is retried
+                        self.record_network_usage(NetworkTraffic::Asset, downloaded_size)
+                            .await;
+                        self.fail_install(
+                            peer_map,
+                            queue_compatibility_tool,
+                            AppError::new(
+                                AppErrorCode::NetworkError,
+                                format!(
+                                    "Download in progress failed: {}",
+                                    proxy.describe_error(&err)
+                                ),
+                            ),
+                        )
+                        .await;
+                        return None;
+                    }
+                };
+                if let Err(err) = partial.append(&chunk) {
+                    let app_error = AppError::from(err).context("Failed to write download");
+                    self.record_network_usage(NetworkTraffic::Asset, downloaded_size)
+                        .await;
+                    self.fail_install(peer_map, queue_compatibility_tool, app_error)
+                        .await;
+                    return None;
+                }
+                verifier.update(&chunk);
+                downloaded_size += chunk.len() as u64;
+                if let Some(max_size) = install
+                    .max_size
+                    .filter(|max_size| partial.downloaded.max(partial.total_size()) > *max_size)
                 {
-                    self.app_state.lock().await.in_progress = None;
-                    self.broadcast_app_state(peer_map).await;
-                    return; // We stop the function here
+                    partial.discard();
+                    self.record_network_usage(NetworkTraffic::Asset, downloaded_size)
+                        .await;
+                    let message = format!(
+                        "The archive is larger than the {} MiB allowed",
+                        max_size / (1024 * 1024)
+                    );
+                    self.fail_install(
+                        peer_map,
+                        queue_compatibility_tool,
+                        AppError::internal(message),
+                    )
+                    .await;
+                    return None;
                 }
-                if let Ok(chunk) = chunk_result {
-                    downloaded_bytes.extend_from_slice(&chunk);
-                    downloaded_size += chunk.len() as u64;
-
-                    let progress = ((downloaded_size as f64 / total_size as f64) * 100.0) as u8;
-                    if queue_compatibility_tool.progress != progress {
-                        // Update progress...
-                        queue_compatibility_tool.progress = progress;
-                        self.app_state.lock().await.in_progress =
-                            Some(queue_compatibility_tool.clone());
-                        self.broadcast_app_state(peer_map).await;
+
+                // A game may start or exit while downloading
+                if game_changed(&mut environment_events) {
+                    let constraints = self.select_task_constraints(install.background).await;
+                    if constraints != queue_compatibility_tool.constraints {
+                        queue_compatibility_tool.constraints = constraints;
+                        self.announce_constraints(peer_map, queue_compatibility_tool)
+                            .await;
                     }
-                } else {
-                    let error_message =
-                        "Connection Error: Download in progress failed!".to_string();
-                    error!("{}", error_message);
-                    self.app_state.lock().await.in_progress = None;
+                    rate_limiter.set_limit(
+                        download_limit(
+                            queue_compatibility_tool.constraints.as_ref(),
+                            download_rate_limit,
+                        ),
+                        Instant::now(),
+                    );
+                }
+                let delay = rate_limiter.throttle(chunk.len() as u64, Instant::now());
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                // The limit may be changed in the settings while downloading
+                let measured = throughput.record(chunk.len() as u64, Instant::now());
+                if let Some(bytes_per_second) = measured {
+                    queue_compatibility_tool.bytes_per_second = Some(bytes_per_second);
+                    download_rate_limit = self.app_state.lock().await.settings.download_rate_limit;
+                    rate_limiter.set_limit(
+                        download_limit(
+                            queue_compatibility_tool.constraints.as_ref(),
+                            download_rate_limit,
+                        ),
+                        Instant::now(),
+                    );
+                }
+
+                // Counts the bytes of earlier attempts, so the bar doesn't jump back on resume
+                let progress =
+                    ((partial.downloaded as f64 / partial.total_size() as f64) * 100.0) as u8;
+                queue_compatibility_tool.progress = progress;
+                if throttle.should_send(progress, Instant::now()) || measured.is_some() {
+                    self.set_in_progress(queue_compatibility_tool).await;
                     self.broadcast_app_state(peer_map).await;
-                    self.broadcast_notification(peer_map, error_message.as_str())
+                }
+            }
+            if !partial.is_complete() {
+                let message = format!(
+                    "Download ended at {} of {} bytes",
+                    partial.downloaded,
+                    partial.total_size()
+                );
+                if attempt < attempts {
+                    warn!("{}: {}, resuming", queue_compatibility_tool.name, message);
+                    attempt += 1;
+                    self.wait_to_retry(peer_map, queue_compatibility_tool, attempt, attempts)
                         .await;
-                    return;
+                    continue;
                 }
+                self.record_network_usage(NetworkTraffic::Asset, downloaded_size)
+                    .await;
+                self.fail_install(
+                    peer_map,
+                    queue_compatibility_tool,
+                    AppError::new(AppErrorCode::NetworkError, message),
+                )
+                .await;
+                return None;
             }
+            break verifier;
+        };
 
-            let reader = Cursor::new(downloaded_bytes);
+        self.record_network_usage(NetworkTraffic::Asset, downloaded_size)
+            .await;
+        match partial.finish() {
+            Ok(archive) => Some((archive, verifier)),
+            Err(err) => {
+                let app_error = AppError::from(err).context("Failed to write download");
+                self.fail_install(peer_map, queue_compatibility_tool, app_error)
+                    .await;
+                None
+            }
+        }
+    }
 
-            self.extract_generate_and_move(
+    /// Waits with backoff before download attempt `attempt` of `attempts`, shown as retrying
+    /// until it starts.
+    async fn wait_to_retry(
+        &self,
+        peer_map: &PeerMap,
+        queue_compatibility_tool: &mut QueueCompatibilityTool,
+        attempt: u32,
+        attempts: u32,
+    ) {
+        queue_compatibility_tool.state = QueueCompatibilityToolState::Retrying;
+        queue_compatibility_tool.retry = Some(DownloadRetry { attempt, attempts });
+        self.set_in_progress(queue_compatibility_tool).await;
+        self.broadcast_app_state(peer_map).await;
+        tokio::time::sleep(retry_delay(attempt - 1, jitter())).await;
+        queue_compatibility_tool.state = QueueCompatibilityToolState::Downloading;
+        self.set_in_progress(queue_compatibility_tool).await;
+        self.broadcast_app_state(peer_map).await;
+    }
+
+    /// Ends the install if it was cancelled, removing `directories` it left behind. Returns
+    /// whether it was.
+    async fn end_if_cancelled(
+        &self,
+        peer_map: &PeerMap,
+        queue_compatibility_tool: &QueueCompatibilityTool,
+        directories: &[&Path],
+    ) -> bool {
+        if !queue_compatibility_tool.cancellation.is_cancelled() {
+            return false;
+        }
+        for directory in directories.iter().filter(|directory| directory.exists()) {
+            cleanup_temp_directory(directory);
+        }
+        info!("Cancelled installing {}", queue_compatibility_tool.name);
+        note_outcome(queue_compatibility_tool.id, Ok(HistoryOutcome::Cancelled));
+        self.clear_in_progress(queue_compatibility_tool.id).await;
+        self.broadcast_app_state(peer_map).await;
+        self.broadcast_task_cancelled(peer_map, queue_compatibility_tool.id)
+            .await;
+        true
+    }
+
+    /// Ends the install, telling the peers why.
+    pub(crate) async fn fail_install(
+        &self,
+        peer_map: &PeerMap,
+        queue_compatibility_tool: &QueueCompatibilityTool,
+        app_error: AppError,
+    ) {
+        error!("{}: {}", queue_compatibility_tool.name, app_error);
+        note_outcome(queue_compatibility_tool.id, Err(Some(app_error.code)));
+        self.clear_in_progress(queue_compatibility_tool.id).await;
+        self.broadcast_app_state(peer_map).await;
+        let app_error = AppError {
+            message: format!("{}: {}", queue_compatibility_tool.name, app_error.message),
+            ..app_error
+        };
+        self.broadcast_app_error(peer_map, app_error.for_task(queue_compatibility_tool.id))
+            .await;
+    }
+
+    async fn announce_constraints(
+        &self,
+        peer_map: &PeerMap,
+        queue_compatibility_tool: &QueueCompatibilityTool,
+    ) {
+        let message = match &queue_compatibility_tool.constraints {
+            Some(constraints) => format!(
+                "Installing {} in background mode ({})",
+                queue_compatibility_tool.name, constraints
+            ),
+            None => format!("Installing {} at full speed", queue_compatibility_tool.name),
+        };
+        info!("{}", message);
+        self.broadcast_notification(peer_map, &message).await;
+        self.set_in_progress(queue_compatibility_tool).await;
+        self.broadcast_app_state(peer_map).await;
+    }
+
+    // Returns whether the download may proceed under the monthly network cap.
+    async fn network_preflight(
+        &self,
+        peer_map: &PeerMap,
+        install: &Install,
+        queue_compatibility_tool: &QueueCompatibilityTool,
+    ) -> bool {
+        let mut app_state = self.app_state.lock().await;
+        let monthly_cap = app_state.settings.monthly_network_cap;
+        let preflight = app_state.network_usage.preflight(
+            queue_compatibility_tool.size,
+            monthly_cap,
+            &chrono::Local::now(),
+        );
+        drop(app_state);
+
+        match preflight {
+            NetworkPreflight::Allowed => true,
+            NetworkPreflight::Warning(message) => {
+                warn!("{}", message);
+                self.broadcast_notification(peer_map, &format!("Warning: {}", message))
+                    .await;
+                true
+            }
+            NetworkPreflight::Blocked(message) if install.ignore_network_cap => {
+                warn!("{}, continuing as requested", message);
+                self.broadcast_notification(peer_map, &format!("Warning: {}", message))
+                    .await;
+                true
+            }
+            NetworkPreflight::Blocked(message) => {
+                let app_error = AppError::new(AppErrorCode::NetworkCapReached, message);
+                error!("{}", app_error);
+                note_outcome(queue_compatibility_tool.id, Err(Some(app_error.code)));
+                self.broadcast_app_error(peer_map, app_error.for_task(queue_compatibility_tool.id))
+                    .await;
+                false
+            }
+        }
+    }
+
+    // Returns whether the release fits on disk, or the install goes ahead regardless as requested.
+    async fn disk_space_preflight(
+        &self,
+        peer_map: &PeerMap,
+        install: &Install,
+        queue_compatibility_tool: &QueueCompatibilityTool,
+    ) -> bool {
+        let size = queue_compatibility_tool.size;
+        let Some(install_directory) = self.install_directory(&install.flavor) else {
+            return true;
+        };
+        // Nothing to go by for archives of unknown size
+        if size == 0 {
+            return true;
+        }
+        let shortage = match tokio::task::spawn_blocking(move || {
+            check_disk_space(size, &downloads_directory(), &install_directory)
+        })
+        .await
+        {
+            Ok(Some(shortage)) => shortage,
+            Ok(None) => return true,
+            Err(err) => {
+                let app_error = AppError::internal(err.to_string())
+                    .context("Failed to check the free disk space");
+                self.fail_install(peer_map, queue_compatibility_tool, app_error)
+                    .await;
+                return false;
+            }
+        };
+
+        let message = format!(
+            "{} needs {} MiB free in {}, {} MiB are available",
+            install.release.name,
+            shortage.required_bytes / (1024 * 1024),
+            shortage.path,
+            shortage.available_bytes / (1024 * 1024)
+        );
+        if install.ignore_disk_space {
+            warn!("{}, continuing as requested", message);
+            self.broadcast_notification(peer_map, &format!("Warning: {}", message))
+                .await;
+            return true;
+        }
+        error!("{}", message);
+        let response = Request {
+            task_id: Some(queue_compatibility_tool.id),
+            disk_space: Some(shortage),
+            ..Request::new(RequestType::InsufficientDiskSpace)
+        };
+        broadcast_to_peers(peer_map, &response).await;
+        note_outcome(
+            queue_compatibility_tool.id,
+            Err(Some(AppErrorCode::DiskFull)),
+        );
+        self.broadcast_app_error(
+            peer_map,
+            AppError::new(AppErrorCode::DiskFull, message).for_task(queue_compatibility_tool.id),
+        )
+        .await;
+        false
+    }
+
+    // Requirements are advisory, the install continues regardless.
+    pub(crate) async fn warn_unmet_requirements(&self, peer_map: &PeerMap, install: &Install) {
+        let requirement_warnings: Vec<String> = self
+            .app_state
+            .lock()
+            .await
+            .flavors
+            .iter()
+            .find(|flavor| flavor.flavor == install.flavor)
+            .map(|flavor| flavor.requirement_warnings.clone())
+            .unwrap_or_default();
+        for requirement_warning in requirement_warnings {
+            warn!("{}: {}", install.release.name, requirement_warning);
+            self.broadcast_notification(
                 peer_map,
-                &install,
-                &mut queue_compatibility_tool,
-                reader,
+                &format!("Warning: {}: {}", install.release.name, requirement_warning),
             )
             .await;
-        } else {
         }
     }
 
@@ -122,111 +812,589 @@ impl WineCask {
         peer_map: &PeerMap,
         install: &Install,
         queue_compatibility_tool: &mut QueueCompatibilityTool,
-        reader: Cursor<Vec<u8>>,
+        archive: &Path,
+        checksum: String,
     ) {
-        if let Some(temp_dir) = prepare_temp_directory() {
+        let Some(install_directory) = self.install_directory(&queue_compatibility_tool.flavor)
+        else {
+            let message = format!(
+                "{} installs into Lutris or Heroic, neither is installed",
+                queue_compatibility_tool.flavor
+            );
+            self.fail_install(
+                peer_map,
+                queue_compatibility_tool,
+                AppError::internal(message),
+            )
+            .await;
+            return;
+        };
+        // Put together next to the installed tools and renamed into place once complete, so a
+        // failed install never leaves a broken tool behind. Filesystems that can't store the
+        // tool's symlinks get copies instead, extracted elsewhere first.
+        let staging_directory = temp_directory(&install_directory, queue_compatibility_tool.id);
+        let profile = detect_filesystem(&install_directory);
+        let in_place = profile.symlinks && profile.unix_permissions;
+        let temp_dir = if in_place {
+            // One level down, Steam would list a tool whose archive had its files at the top level
+            prepare_temp_directory(staging_directory.join("extracted"))
+        } else {
+            prepare_temp_directory(runtime_temp_directory(queue_compatibility_tool.id))
+        };
+        if let Some(temp_dir) = temp_dir {
+            let constraints = self.select_task_constraints(install.background).await;
+            if constraints != queue_compatibility_tool.constraints {
+                queue_compatibility_tool.constraints = constraints;
+                self.announce_constraints(peer_map, queue_compatibility_tool)
+                    .await;
+            }
+            if let Some(constraints) = &queue_compatibility_tool.constraints {
+                self.wait_for_shader_cache(constraints).await;
+            }
+            if self
+                .end_if_cancelled(
+                    peer_map,
+                    queue_compatibility_tool,
+                    &[&temp_dir, &staging_directory],
+                )
+                .await
+            {
+                return;
+            }
+
+            // Assets aren't always named or labelled after what they hold
+            match sniff_archive_file(archive) {
+                Ok(Some(compress_type)) => queue_compatibility_tool.compress_type = compress_type,
+                Ok(None) => {
+                    cleanup_temp_directories(&[&temp_dir, &staging_directory]);
+                    self.fail_install(
+                        peer_map,
+                        queue_compatibility_tool,
+                        AppError::internal(unsupported_archive("The download")),
+                    )
+                    .await;
+                    return;
+                }
+                Err(err) => warn!("Failed to read the downloaded archive's type: {}", err),
+            }
+
             // Mark as extracting...
             queue_compatibility_tool.state = QueueCompatibilityToolState::Extracting;
             queue_compatibility_tool.progress = 0;
-            self.app_state.lock().await.in_progress = Some(queue_compatibility_tool.clone());
+            queue_compatibility_tool.bytes_per_second = None;
+            self.set_in_progress(queue_compatibility_tool).await;
             self.broadcast_app_state(peer_map).await;
 
-            let steam_compatibility_tools_directory =
-                self.steam_util.get_steam_compatibility_tools_directory();
             // Spawn a new thread for the extraction process
             // Why do we need this turns out unpack process is blocking, because of this async function doesn't yield control back to Rust runtime until the extraction is finished.
             let queue_compatibility_tool_clone = queue_compatibility_tool.clone(); // Clone the queue_compatibility_tool
             let temp_dir_clone = temp_dir.clone();
-            tokio::task::spawn_blocking(move || {
-                let decompressed: Box<dyn Read> =
-                    if queue_compatibility_tool_clone.compress_type == CompressionType::Gzip {
-                        Box::new(GzDecoder::new(reader))
-                    } else if queue_compatibility_tool_clone.compress_type == CompressionType::Xz {
-                        Box::new(XzDecoder::new(reader))
-                    } else {
-                        Box::new(reader) // fixme: explosion
-                    };
-                let mut tar = tar::Archive::new(decompressed);
-                tar.unpack(temp_dir_clone).unwrap();
-            })
-            .await
-            .unwrap();
-
-            // Scan for the extracted directory
-            let valid_directories: Vec<PathBuf> = std::fs::read_dir(&temp_dir)
-                .map_err(|_err| {
-                    error!("Failed to read directory");
+            let partial_update_base = self.partial_update_base(install).await;
+            // Unchanged files are hard-linked from the installed tool next to it
+            let staging_directory_clone = staging_directory.clone();
+            let force_serial_extraction =
+                self.app_state.lock().await.settings.force_serial_extraction;
+            let constraints = queue_compatibility_tool.constraints.clone();
+            let archive = archive.to_path_buf();
+            // Progress is told by how much of the compressed archive was read, sent on as each
+            // entry is reached
+            let (progress_sender, mut progress) = watch::channel(0);
+            let mut throttle = ProgressThrottle::default();
+            let mut extraction = tokio::task::spawn_blocking(move || {
+                run_constrained(constraints.as_ref(), || {
+                    let cancellation = &queue_compatibility_tool_clone.cancellation;
+                    let staged = partial_update_base.and_then(|(base, installed_files)| {
+                        try_partial_update(
+                            &archive,
+                            &queue_compatibility_tool_clone.compress_type,
+                            &base,
+                            &installed_files,
+                            &staging_directory_clone,
+                            cancellation,
+                        )
+                    });
+                    if staged.is_none() {
+                        let file = File::open(&archive)?;
+                        let archive_size = file.metadata()?.len().max(1);
+                        let (reader, read) = CountingReader::new(file);
+                        let on_entry = || {
+                            let percent =
+                                (read.load(Ordering::Relaxed) * 100 / archive_size).min(100) as u8;
+                            progress_sender.send_if_modified(|progress| {
+                                let changed = *progress != percent;
+                                *progress = percent;
+                                changed
+                            });
+                        };
+                        if queue_compatibility_tool_clone.compress_type == CompressionType::Zip {
+                            extract_zip(reader, &temp_dir_clone, cancellation, on_entry)?;
+                        } else {
+                            let decompressed = decompressor(
+                                BufReader::new(reader),
+                                &queue_compatibility_tool_clone.compress_type,
+                            );
+                            extract_adaptive(
+                                decompressed,
+                                &temp_dir_clone,
+                                force_serial_extraction,
+                                cancellation,
+                                on_entry,
+                            )?;
+                        }
+                    }
+                    Ok::<_, std::io::Error>(staged)
                 })
-                .unwrap()
+            });
+            let staged = loop {
+                tokio::select! {
+                    staged = &mut extraction => {
+                        break staged.unwrap_or_else(|err| Err(std::io::Error::other(err)));
+                    }
+                    // Ends once the extraction dropped its sender
+                    Ok(()) = progress.changed() => {
+                        queue_compatibility_tool.progress = *progress.borrow_and_update();
+                        if throttle.should_send(queue_compatibility_tool.progress, Instant::now()) {
+                            self.set_in_progress(queue_compatibility_tool).await;
+                            self.broadcast_app_state(peer_map).await;
+                        }
+                    }
+                }
+            };
+            // Whatever was extracted before the cancellation is removed
+            if self
+                .end_if_cancelled(
+                    peer_map,
+                    queue_compatibility_tool,
+                    &[&temp_dir, &staging_directory],
+                )
+                .await
+            {
+                return;
+            }
+            let staged = match staged {
+                Ok(staged) => staged,
+                Err(err) => {
+                    cleanup_temp_directories(&[&temp_dir, &staging_directory]);
+                    self.fail_install(
+                        peer_map,
+                        queue_compatibility_tool,
+                        AppError::from(err).context("Failed to extract"),
+                    )
+                    .await;
+                    return;
+                }
+            };
+            // The last update may have been held back, and partial updates don't report any
+            if throttle.should_send(100, Instant::now()) {
+                queue_compatibility_tool.progress = 100;
+                self.set_in_progress(queue_compatibility_tool).await;
+                self.broadcast_app_state(peer_map).await;
+            }
+            queue_compatibility_tool.state = QueueCompatibilityToolState::Finalizing;
+            self.set_in_progress(queue_compatibility_tool).await;
+            self.broadcast_app_state(peer_map).await;
+
+            if let Some((staged, files)) = staged {
+                let Some(name) = staged.file_name() else {
+                    cleanup_temp_directories(&[&temp_dir, &staging_directory]);
+                    self.fail_install(
+                        peer_map,
+                        queue_compatibility_tool,
+                        AppError::internal("The updated tool has no directory name"),
+                    )
+                    .await;
+                    return;
+                };
+                let destination = install_directory.join(name);
+                match replace_directory(&staged, &destination, &staging_directory) {
+                    Ok(_) => {
+                        let skip_file_manifest =
+                            self.app_state.lock().await.settings.skip_file_manifest;
+                        self.record_provenance(
+                            install,
+                            queue_compatibility_tool,
+                            &destination,
+                            checksum,
+                            (!skip_file_manifest).then_some(files),
+                        );
+                        self.record_managed_install(install, &destination).await;
+                    }
+                    Err(err) => {
+                        cleanup_temp_directories(&[&temp_dir, &staging_directory]);
+                        self.fail_install(
+                            peer_map,
+                            queue_compatibility_tool,
+                            AppError::from(err).context("Failed to move the tool into place"),
+                        )
+                        .await;
+                        return;
+                    }
+                }
+
+                self.sync_backend_with_installed_compat_tools().await;
+                self.record_tool_activity(ActivitySource::Task).await;
+                self.finish_installation(
+                    peer_map,
+                    install,
+                    queue_compatibility_tool,
+                    &[&temp_dir, &staging_directory],
+                )
+                .await;
+                return;
+            }
+
+            if queue_compatibility_tool.flavor == CompatibilityToolFlavor::SteamTinkerLaunch {
+                if let Err(err) = steam_tinker_launch::lay_out(&temp_dir) {
+                    error!("Failed to lay out SteamTinkerLaunch: {}", err);
+                }
+            }
+            // Some archives extract their files without a directory of their own, forks are named
+            // after their archive alone
+            let flavor_directory_name =
+                if queue_compatibility_tool.flavor == CompatibilityToolFlavor::Unknown {
+                    install.release.tag_name.clone()
+                } else {
+                    format!(
+                        "{}{}",
+                        &queue_compatibility_tool.flavor, &install.release.tag_name
+                    )
+                };
+            match wrap_flat_extraction(&temp_dir, &flavor_directory_name) {
+                Ok(true) => info!(
+                    "{} extracted flat, moved into {}",
+                    queue_compatibility_tool.name, flavor_directory_name
+                ),
+                Ok(false) => {}
+                Err(err) => error!("Failed to wrap flat extraction: {}", err),
+            }
+            // Others nest the tool's directory in one more, like Proton-Tkg's builds
+            if let Err(err) = hoist_nested_tool(&temp_dir) {
+                error!("Failed to move nested tool up: {}", err);
+            }
+
+            // Forks installed from a URL may not declare themselves
+            if queue_compatibility_tool.flavor == CompatibilityToolFlavor::Unknown {
+                match declare_custom_tool(&temp_dir) {
+                    Ok(Some(name)) => info!("Declared {} as a compatibility tool", name),
+                    Ok(None) => {}
+                    Err(err) => error!("Failed to declare custom tool: {}", err),
+                }
+            }
+
+            // Scan for the extracted directory, only Steam's tools declare themselves
+            let declared = install_target(&queue_compatibility_tool.flavor)
+                == InstallTarget::SteamCompatibilityTools;
+            let entries = match std::fs::read_dir(&temp_dir) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    cleanup_temp_directories(&[&temp_dir, &staging_directory]);
+                    self.fail_install(
+                        peer_map,
+                        queue_compatibility_tool,
+                        AppError::from(err).context("Failed to read the extracted files"),
+                    )
+                    .await;
+                    return;
+                }
+            };
+            let valid_directories: Vec<PathBuf> = entries
                 .filter_map(Result::ok)
-                .filter(|x| {
-                    x.metadata().unwrap().is_dir()
-                        && x.path().join("compatibilitytool.vdf").exists()
-                })
                 .map(|x| x.path())
+                .filter(|path| {
+                    path.is_dir() && (!declared || path.join("compatibilitytool.vdf").exists())
+                })
                 .collect();
 
-            if valid_directories.len() == 1 {
-                let first = valid_directories.get(0).unwrap();
+            if let [first] = valid_directories.as_slice() {
                 let new_compat_tool_vdf = first.join("compatibilitytool.vdf");
                 let new_path = match queue_compatibility_tool.flavor {
-                    CompatibilityToolFlavor::ProtonGE => first.clone(),
-                    CompatibilityToolFlavor::SteamTinkerLaunch
-                    | CompatibilityToolFlavor::Luxtorpeda
-                    | CompatibilityToolFlavor::Boxtron => {
-                        let new_folder_name = format!(
-                            "{}{}",
-                            &queue_compatibility_tool.flavor, &install.release.tag_name
-                        );
+                    CompatibilityToolFlavor::ProtonGE
+                    | CompatibilityToolFlavor::SteamTinkerLaunch
+                    | CompatibilityToolFlavor::WineGE
+                    | CompatibilityToolFlavor::Dxvk
+                    | CompatibilityToolFlavor::Vkd3dProton
+                    | CompatibilityToolFlavor::Unknown => first.clone(),
+                    CompatibilityToolFlavor::Luxtorpeda
+                    | CompatibilityToolFlavor::Boxtron
+                    | CompatibilityToolFlavor::Roberta
+                    | CompatibilityToolFlavor::ProtonTkg => {
                         generate_compatibility_tool_vdf(
                             new_compat_tool_vdf,
-                            &new_folder_name,
+                            &flavor_directory_name,
                             &format!(
                                 "{} {}",
                                 &queue_compatibility_tool.flavor, &install.release.tag_name
                             ),
                         );
-                        temp_dir.join(&new_folder_name)
-                    }
-                    _ => {
-                        error!("Unsupported compatibility tool flavor");
-                        first.clone()
+                        temp_dir.join(&flavor_directory_name)
                     }
                 };
-                std::fs::rename(first, &new_path).unwrap();
+                if let Err(err) = std::fs::rename(first, &new_path) {
+                    cleanup_temp_directories(&[&temp_dir, &staging_directory]);
+                    self.fail_install(
+                        peer_map,
+                        queue_compatibility_tool,
+                        AppError::from(err).context("Failed to rename the extracted tool"),
+                    )
+                    .await;
+                    return;
+                }
 
-                match copy_dir(&temp_dir, &steam_compatibility_tools_directory) {
-                    Ok(_) => debug!("Directory copied successfully."),
-                    Err(e) => error!("Failed to copy directory: {}", e),
+                let skip_file_manifest = self.app_state.lock().await.settings.skip_file_manifest;
+                let files = if skip_file_manifest {
+                    None
+                } else {
+                    let new_path_clone = new_path.clone();
+                    let constraints = queue_compatibility_tool.constraints.clone();
+                    tokio::task::spawn_blocking(move || {
+                        run_constrained(constraints.as_ref(), || {
+                            generate_file_manifest(&new_path_clone)
+                        })
+                    })
+                    .await
+                    .unwrap_or_else(|err| Err(std::io::Error::other(err)))
+                    .map_err(|err| error!("Failed to generate file manifest: {}", err))
+                    .ok()
+                };
+                // Last chance to stop, the installed tools are replaced from here on
+                if self
+                    .end_if_cancelled(
+                        peer_map,
+                        queue_compatibility_tool,
+                        &[&temp_dir, &staging_directory],
+                    )
+                    .await
+                {
+                    return;
+                }
+
+                let Some(name) = new_path.file_name() else {
+                    cleanup_temp_directories(&[&temp_dir, &staging_directory]);
+                    self.fail_install(
+                        peer_map,
+                        queue_compatibility_tool,
+                        AppError::internal("The extracted tool has no directory name"),
+                    )
+                    .await;
+                    return;
+                };
+                let destination = install_directory.join(name);
+                let staged = if in_place {
+                    Ok(new_path.clone())
+                } else {
+                    // Journaled, so a copy cut short is rolled back on the next startup
+                    let staged = staging_directory.join(name);
+                    copy_dir_guarded(
+                        &journal_directory(),
+                        &new_path,
+                        &staged,
+                        profile.unix_permissions,
+                    )
+                    .map(|_| staged)
+                };
+                match staged
+                    .and_then(|staged| replace_directory(&staged, &destination, &staging_directory))
+                {
+                    Ok(_) => {
+                        debug!("Moved {} into place", destination.display());
+                        self.record_provenance(
+                            install,
+                            queue_compatibility_tool,
+                            &destination,
+                            checksum,
+                            files,
+                        );
+                        self.record_managed_install(install, &destination).await;
+                    }
+                    Err(err) => {
+                        cleanup_temp_directories(&[&temp_dir, &staging_directory]);
+                        self.fail_install(
+                            peer_map,
+                            queue_compatibility_tool,
+                            AppError::from(err).context("Failed to move the tool into place"),
+                        )
+                        .await;
+                        return;
+                    }
                 }
 
                 self.sync_backend_with_installed_compat_tools().await;
+                self.record_tool_activity(ActivitySource::Task).await;
                 self.broadcast_app_state(peer_map).await;
             } else {
-                error!("Failed to find extracted directory");
+                cleanup_temp_directories(&[&temp_dir, &staging_directory]);
+                self.fail_install(
+                    peer_map,
+                    queue_compatibility_tool,
+                    AppError::internal("The archive doesn't hold one compatibility tool"),
+                )
+                .await;
+                return;
             }
 
-            cleanup_temp_directory(&temp_dir);
+            self.finish_installation(
+                peer_map,
+                install,
+                queue_compatibility_tool,
+                &[&temp_dir, &staging_directory],
+            )
+            .await;
+        } else {
+            self.fail_install(
+                peer_map,
+                queue_compatibility_tool,
+                AppError::internal("Failed to prepare a temporary directory"),
+            )
+            .await;
+        }
+    }
+
+    async fn finish_installation(
+        &self,
+        peer_map: &PeerMap,
+        install: &Install,
+        queue_compatibility_tool: &QueueCompatibilityTool,
+        temp_directories: &[&Path],
+    ) {
+        cleanup_temp_directories(temp_directories);
+        let steam_restart_required = self.restart_steam_after_install(install).await;
 
-            // Mark as completed
-            let message = format!("Installation Completed: {}", install.release.name);
-            info!("{}", message);
-            self.broadcast_notification(peer_map, message.as_str())
+        // Mark as completed
+        let message = format!("Installation Completed: {}", install.release.name);
+        info!("{}", message);
+        note_outcome(queue_compatibility_tool.id, Ok(HistoryOutcome::Succeeded));
+        let completed = Request {
+            notification: Some(message),
+            steam_restart_required: Some(steam_restart_required),
+            ..Request::new(RequestType::Notification)
+        };
+        broadcast_to_peers(peer_map, &completed).await;
+        self.clear_in_progress(queue_compatibility_tool.id).await;
+        self.broadcast_app_state(peer_map).await;
+        self.apply_pending_mappings(peer_map).await;
+        if !install.replaces.is_empty() {
+            self.uninstall_superseded(peer_map, queue_compatibility_tool.id, install)
                 .await;
-            self.app_state.lock().await.in_progress = None;
-            self.broadcast_app_state(peer_map).await;
-        } else {
-            error!("Failed to prepare temp directory");
+        }
+    }
+
+    /// Restarts Steam if the install asked for it, returns whether Steam still has to be restarted
+    /// before it lists the tool.
+    async fn restart_steam_after_install(&self, install: &Install) -> bool {
+        if !self.steam_util.is_steam_running() {
+            return false;
+        }
+        if !install.restart_steam {
+            return true;
+        }
+        let game_mode = self.app_state.lock().await.environment.game_mode == Some(true);
+        let steam_util = self.steam_util.clone();
+        match tokio::task::spawn_blocking(move || steam_util.restart_steam(game_mode)).await {
+            Ok(Ok(())) => false,
+            Ok(Err(err)) => {
+                warn!("Not restarting Steam after installing: {}", err);
+                true
+            }
+            Err(err) => {
+                error!("Restarting Steam panicked: {}", err);
+                true
+            }
+        }
+    }
+
+    // The most recently installed unmodified tool of the same flavor with a file manifest, if any.
+    async fn partial_update_base(
+        &self,
+        install: &Install,
+    ) -> Option<(PathBuf, Vec<FileManifestEntry>)> {
+        // Other flavors are small and get renamed after extraction, staging links files in place
+        if install.flavor != CompatibilityToolFlavor::ProtonGE || install.copy_install {
+            return None;
+        }
+        let installed_tools: Vec<SteamCompatibilityTool> = self
+            .app_state
+            .lock()
+            .await
+            .installed_compatibility_tools
+            .iter()
+            .filter(|tool| tool.flavor == install.flavor)
+            .cloned()
+            .collect();
+        let (_, tool, files) = installed_tools
+            .into_iter()
+            .filter_map(|tool| {
+                let provenance = Provenance::load(&tool.internal_name)?;
+                Some((provenance.installed_at, tool, provenance.files?))
+            })
+            .max_by_key(|(installed_at, _, _)| *installed_at)?;
+        // Hard-linking patched files would carry the patches into the new version
+        if self
+            .local_changes(&tool, false)
+            .await
+            .is_some_and(|local_changes| !local_changes.is_empty())
+        {
+            info!(
+                "{} was modified since it was installed, installing fully",
+                tool.display_name
+            );
+            return None;
+        }
+        Some((PathBuf::from(tool.path), files))
+    }
+
+    fn record_provenance(
+        &self,
+        install: &Install,
+        queue_compatibility_tool: &QueueCompatibilityTool,
+        installed_path: &Path,
+        checksum: String,
+        files: Option<Vec<FileManifestEntry>>,
+    ) {
+        let Some(asset) = install
+            .release
+            .assets
+            .iter()
+            .find(|asset| asset.browser_download_url == queue_compatibility_tool.url)
+        else {
+            warn!("Downloaded asset not found in release, not recording provenance");
+            return;
+        };
+        let compat_tools = match self
+            .steam_util
+            .read_compatibility_tools_from_vdf_path(&installed_path.join("compatibilitytool.vdf"))
+        {
+            Ok(compat_tools) => compat_tools,
+            Err(err) => {
+                error!("Failed to read installed compatibility tool: {}", err);
+                return;
+            }
+        };
+
+        // Every tool the archive declares came from the same release
+        for compat_tool in compat_tools {
+            let provenance = Provenance {
+                internal_name: compat_tool.internal_name,
+                flavor: install.flavor.clone(),
+                tag_name: install.release.tag_name.clone(),
+                naming_scheme: release_version(naming_schemes(&install.flavor), &install.release)
+                    .map(|(scheme, _)| scheme.name.to_string()),
+                installed_at: current_timestamp(),
+                source: ProvenanceSource::new(&install.release, asset, Some(checksum.clone())),
+                files: files.clone(),
+            };
+            if let Err(err) = provenance.save() {
+                error!("Failed to save provenance: {}", err);
+            }
         }
     }
 }
 
-fn prepare_temp_directory() -> Option<PathBuf> {
-    let temp_dir = PathBuf::from(
-        env::var("DECKY_PLUGIN_RUNTIME_DIR").unwrap_or("/tmp/decky-wine-cellar".to_string()),
-    )
-    .join("temp");
+/// Each install extracts into its own directory, several may run at once.
+fn runtime_temp_directory(id: u64) -> PathBuf {
+    runtime_directory().join("temp").join(id.to_string())
+}
 
+fn prepare_temp_directory(temp_dir: PathBuf) -> Option<PathBuf> {
     if temp_dir.exists() {
         warn!("Found existing temp directory, cleaning up...");
         cleanup_temp_directory(&temp_dir);
@@ -246,51 +1414,154 @@ fn cleanup_temp_directory(temp_dir: &Path) {
     }
 }
 
+/// Cleans up the ones of `temp_directories` that exist, an install may use one or two.
+fn cleanup_temp_directories(temp_directories: &[&Path]) {
+    for temp_dir in temp_directories.iter().filter(|temp_dir| temp_dir.exists()) {
+        cleanup_temp_directory(temp_dir);
+    }
+}
+
 pub fn look_for_compressed_archive(install_request: &Install) -> Option<QueueCompatibilityTool> {
-    /*if install_request.flavor == CompatibilityToolFlavor::SteamTinkerLaunch {// fixme: doesn't actually work we need to handle this STL separately
+    if install_request.flavor == CompatibilityToolFlavor::SteamTinkerLaunch {
         return Some(QueueCompatibilityTool {
+            id: 0,
             flavor: install_request.flavor.to_owned(),
             name: install_request.release.tag_name.to_owned(),
-            url: format!("https://codeload.github.com/sonic2kk/steamtinkerlaunch/legacy.tar.gz/refs/tags/{}", install_request.release.tag_name), //install_request.release.tarball_url.to_owned(),
+            url: steam_tinker_launch::tarball_url(&install_request.release.tag_name),
             state: QueueCompatibilityToolState::Waiting,
             compress_type: CompressionType::Gzip,
+            // Source tarballs aren't listed along with their size
+            size: 0,
             progress: 0,
+            constraints: None,
+            bytes_per_second: None,
+            retry: None,
+            cancellation: CancellationToken::default(),
         });
-    }*/
-
-    let is_compressed = |asset: &Asset| {
-        asset.content_type == "application/gzip"
-            || asset.content_type == "application/x-xz"
-            || asset.name.ends_with(".tar.gz")
-            || asset.name.ends_with(".tar.xz")
-    };
-
-    let compress_type = |asset: &Asset| {
-        if asset.content_type == "application/gzip" || asset.name.ends_with(".tar.gz") {
-            CompressionType::Gzip
-        } else if asset.content_type == "application/x-xz" || asset.name.ends_with(".tar.xz") {
-            CompressionType::Xz
-        } else {
-            CompressionType::Unknown
-        }
-    };
+    }
 
-    if let Some(asset) = install_request
+    // Tar archives keep permissions whatever made them, a zip is only picked if there's no other
+    if let Some((asset, compress_type)) = install_request
         .release
         .assets
-        .clone()
-        .into_iter()
-        .find(is_compressed)
+        .iter()
+        .filter_map(|asset| Some((asset, asset_compression_type(asset)?)))
+        .min_by_key(|(_, compress_type)| *compress_type == CompressionType::Zip)
     {
         return Some(QueueCompatibilityTool {
+            id: 0,
             flavor: install_request.flavor.to_owned(),
             name: install_request.release.tag_name.to_owned(),
-            url: asset.clone().browser_download_url,
+            url: asset.browser_download_url.clone(),
             state: QueueCompatibilityToolState::Waiting,
-            compress_type: compress_type(&asset),
+            compress_type,
+            size: asset.size,
             progress: 0,
+            constraints: None,
+            bytes_per_second: None,
+            retry: None,
+            cancellation: CancellationToken::default(),
         });
     }
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{asset, release, steam_root};
+    use crate::wine_cask::history::{all_records, TEST_HISTORY};
+    use crate::wine_cask::outbox::Outbox;
+    use crate::wine_cask::permissions::PermissionSet;
+    use crate::wine_cask::startup::Startup;
+    use crate::wine_cask::subscriptions::SubscriptionSet;
+    use crate::{Peer, PeerAddr};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+    use tokio::sync::Mutex;
+
+    fn install(tag_name: &str, size: u64) -> Install {
+        let name = format!("{}.tar.gz", tag_name);
+        Install {
+            flavor: CompatibilityToolFlavor::ProtonGE,
+            release: Release {
+                assets: vec![Asset {
+                    browser_download_url: format!("http://127.0.0.1:9/{}", name),
+                    content_type: "application/gzip".to_string(),
+                    size,
+                    ..asset(&name)
+                }],
+                ..release(tag_name)
+            },
+            ignore_network_cap: false,
+            ignore_disk_space: false,
+            background: false,
+            accept_local_changes_loss: false,
+            copy_install: false,
+            replaces: Vec::new(),
+            migrate_mappings: false,
+            max_size: None,
+            local_path: None,
+            restart_steam: false,
+            trigger: HistoryTrigger::Manual,
+        }
+    }
+
+    fn last_install() -> HistoryRecord {
+        all_records()
+            .into_iter()
+            .rev()
+            .find(|record| record.task_type == HistoryTaskType::Install)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_refused_preflights_are_recorded() {
+        let _history = TEST_HISTORY.lock().await;
+        let steam = tempdir().unwrap();
+        let runtime = tempdir().unwrap();
+        steam_root(steam.path());
+        let peer_map: PeerMap = Arc::new(Mutex::new(HashMap::new()));
+        peer_map.lock().await.insert(
+            PeerAddr::Tcp("127.0.0.1:8887".parse().unwrap()),
+            Peer {
+                outbox: Arc::new(Outbox::default()),
+                permissions: PermissionSet::all(),
+                subscriptions: SubscriptionSet::default(),
+            },
+        );
+        let steam_directory = steam.path().to_path_buf();
+        let wine_cask = Startup::new()
+            .initialize(&peer_map, || vec![steam_directory], runtime.path())
+            .await;
+
+        // The cap is reached before anything was downloaded
+        wine_cask
+            .app_state
+            .lock()
+            .await
+            .settings
+            .monthly_network_cap = Some(0);
+        wine_cask
+            .install_compatibility_tool(900101, install("GE-Proton9-20", 1024), &peer_map)
+            .await;
+        let refused = last_install();
+        assert_eq!(refused.outcome, HistoryOutcome::Failed);
+        assert_eq!(refused.error_code, Some(AppErrorCode::NetworkCapReached));
+
+        wine_cask
+            .app_state
+            .lock()
+            .await
+            .settings
+            .monthly_network_cap = None;
+        wine_cask
+            .install_compatibility_tool(900102, install("GE-Proton9-21", 1 << 50), &peer_map)
+            .await;
+        let refused = last_install();
+        assert_eq!(refused.outcome, HistoryOutcome::Failed);
+        assert_eq!(refused.error_code, Some(AppErrorCode::DiskFull));
+    }
+}
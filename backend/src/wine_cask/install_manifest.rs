@@ -0,0 +1,271 @@
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::flavors::{CompatibilityToolFlavor, SteamCompatibilityTool};
+use crate::wine_cask::install::Install;
+use crate::wine_cask::install_target::all_installed;
+use crate::wine_cask::naming::adoptable_release;
+use crate::wine_cask::provenance::current_timestamp;
+use crate::written_by::stamped;
+use crate::PeerMap;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// A tool the plugin installed itself.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ManagedInstall {
+    /// Directory the tool was installed to.
+    pub path: String,
+    pub flavor: CompatibilityToolFlavor,
+    pub tag_name: String,
+    pub installed_at: u64,
+}
+
+/// Every tool the plugin installed that is still there, tools found anywhere else were put there
+/// by the user.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(default)]
+pub struct InstallManifest {
+    installs: Vec<ManagedInstall>,
+    #[serde(skip)]
+    file: PathBuf,
+}
+
+impl InstallManifest {
+    pub fn load(file: PathBuf) -> InstallManifest {
+        let install_manifest = fs::read_to_string(&file)
+            .ok()
+            .and_then(|string| serde_json::from_str(&string).ok())
+            .unwrap_or_default();
+        InstallManifest {
+            file,
+            ..install_manifest
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            &self.file,
+            serde_json::to_string(&stamped(self, "install_manifest", &self.file))?,
+        )
+    }
+
+    fn save_or_log(&self) {
+        if let Err(err) = self.save() {
+            error!("Failed to save the install manifest: {}", err);
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Option<&ManagedInstall> {
+        self.installs.iter().find(|install| install.path == path)
+    }
+
+    /// Records an install, replacing whatever was installed to the same directory before.
+    pub fn record(&mut self, managed_install: ManagedInstall) {
+        self.installs
+            .retain(|install| install.path != managed_install.path);
+        self.installs.push(managed_install);
+        self.save_or_log();
+    }
+
+    pub fn forget(&mut self, path: &str) {
+        let count = self.installs.len();
+        self.installs.retain(|install| install.path != path);
+        if self.installs.len() != count {
+            self.save_or_log();
+        }
+    }
+
+    /// Drops the installs whose directory isn't among `installed_paths` anymore, returning how
+    /// many were dropped.
+    pub fn reconcile<'a>(&mut self, installed_paths: impl IntoIterator<Item = &'a str>) -> usize {
+        let installed_paths: Vec<&str> = installed_paths.into_iter().collect();
+        let count = self.installs.len();
+        self.installs
+            .retain(|install| installed_paths.contains(&install.path.as_str()));
+        let removed = count - self.installs.len();
+        if removed > 0 {
+            self.save_or_log();
+        }
+        removed
+    }
+}
+
+/// Marks the tools the plugin installed or that match a release of their flavor as managed,
+/// along with the version they were installed from. Only managed tools are updated.
+pub fn mark_managed(tools: &mut [SteamCompatibilityTool], install_manifest: &InstallManifest) {
+    for tool in tools {
+        let released_version = tool
+            .github_release
+            .as_ref()
+            .map(|release| release.tag_name.clone());
+        match install_manifest.get(&tool.path) {
+            Some(managed_install) => {
+                // Renamed releases don't match their flavor's naming scheme anymore
+                if tool.flavor == CompatibilityToolFlavor::Unknown {
+                    tool.flavor = managed_install.flavor.clone();
+                }
+                tool.managed = true;
+                tool.version = Some(managed_install.tag_name.clone());
+            }
+            None => {
+                tool.managed = released_version.is_some();
+                tool.version = released_version;
+            }
+        }
+    }
+}
+
+impl WineCask {
+    /// Records the tool `install` put at `installed_path` as installed by the plugin.
+    pub async fn record_managed_install(&self, install: &Install, installed_path: &Path) {
+        self.app_state
+            .lock()
+            .await
+            .install_manifest
+            .record(ManagedInstall {
+                path: installed_path.to_string_lossy().to_string(),
+                flavor: install.flavor.clone(),
+                tag_name: install.release.tag_name.clone(),
+                installed_at: current_timestamp(),
+            });
+    }
+
+    /// Records a GE build another manager installed as if the plugin had installed it, so it's
+    /// updated from then on. Its directory and internal name both have to say which release it is.
+    pub async fn adopt_tool(&self, peer_map: &PeerMap, internal_name: &str) {
+        let installed = all_installed(&*self.app_state.lock().await);
+        let Some(tool) = installed
+            .iter()
+            .find(|tool| tool.internal_name == internal_name)
+        else {
+            let app_error = AppError::new(
+                AppErrorCode::NotFound,
+                format!("{} isn't installed", internal_name),
+            );
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error).await;
+            return;
+        };
+        let directory_name = Path::new(&tool.path)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let release = adoptable_release(&directory_name)
+            .filter(|release| adoptable_release(&tool.internal_name).as_ref() == Some(release));
+        let Some((flavor, tag_name)) = release else {
+            let app_error = AppError::new(
+                AppErrorCode::InvalidRequest,
+                format!(
+                    "{} isn't named like a GE build, its version can't be told",
+                    tool.display_name
+                ),
+            );
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error).await;
+            return;
+        };
+
+        info!("Adopting {} as {} {}", tool.display_name, flavor, tag_name);
+        self.app_state
+            .lock()
+            .await
+            .install_manifest
+            .record(ManagedInstall {
+                path: tool.path.clone(),
+                flavor,
+                tag_name,
+                installed_at: current_timestamp(),
+            });
+        self.update_compatibility_tools_and_available_flavors()
+            .await;
+        self.broadcast_app_state(peer_map).await;
+    }
+
+    /// Forgets installs whose directory was removed while the backend wasn't running.
+    pub async fn reconcile_install_manifest(&self) {
+        let mut app_state = self.app_state.lock().await;
+        let app_state = &mut *app_state;
+        let removed = app_state.install_manifest.reconcile(
+            app_state
+                .installed_compatibility_tools
+                .iter()
+                .chain(&app_state.installed_runners)
+                .chain(&app_state.installed_components)
+                .map(|tool| tool.path.as_str()),
+        );
+        if removed > 0 {
+            info!("Forgot {} installs that were removed", removed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::tool;
+    use tempfile::tempdir;
+
+    fn managed_install(name: &str, tag_name: &str) -> ManagedInstall {
+        ManagedInstall {
+            path: format!("/compatibilitytools.d/{}", name),
+            flavor: CompatibilityToolFlavor::ProtonGE,
+            tag_name: tag_name.to_string(),
+            installed_at: 1_750_000_000,
+        }
+    }
+
+    #[test]
+    fn test_manifest_survives_a_reload_and_reconciles() {
+        let directory = tempdir().unwrap();
+        let file = directory.path().join("install_manifest.json");
+        let mut install_manifest = InstallManifest::load(file.clone());
+        install_manifest.record(managed_install("GE-Proton9-20", "GE-Proton9-20"));
+        install_manifest.record(managed_install("GE-Proton9-21", "GE-Proton9-21"));
+        // Reinstalling replaces the entry
+        install_manifest.record(managed_install("GE-Proton9-21", "GE-Proton9-21-rebuild"));
+
+        let mut install_manifest = InstallManifest::load(file.clone());
+        assert_eq!(install_manifest.installs.len(), 2);
+        assert_eq!(
+            install_manifest
+                .get("/compatibilitytools.d/GE-Proton9-21")
+                .unwrap()
+                .tag_name,
+            "GE-Proton9-21-rebuild"
+        );
+
+        assert_eq!(
+            install_manifest.reconcile(["/compatibilitytools.d/GE-Proton9-21"]),
+            1
+        );
+        install_manifest.forget("/compatibilitytools.d/GE-Proton9-21");
+        assert!(InstallManifest::load(file).installs.is_empty());
+    }
+
+    #[test]
+    fn test_tools_are_managed_when_installed_or_released() {
+        let mut install_manifest = InstallManifest::default();
+        install_manifest
+            .installs
+            .push(managed_install("proton-renamed", "GE-Proton9-20"));
+        let mut tools = [
+            tool(CompatibilityToolFlavor::ProtonGE, "GE-Proton9-21"),
+            tool(CompatibilityToolFlavor::Unknown, "proton-renamed"),
+            tool(CompatibilityToolFlavor::Unknown, "proton-custom"),
+        ];
+        mark_managed(&mut tools, &install_manifest);
+
+        assert!(tools[0].managed);
+        assert_eq!(tools[0].version.as_deref(), Some("GE-Proton9-21"));
+        assert!(tools[1].managed);
+        assert_eq!(tools[1].flavor, CompatibilityToolFlavor::ProtonGE);
+        assert_eq!(tools[1].version.as_deref(), Some("GE-Proton9-20"));
+        assert!(!tools[2].managed);
+        assert_eq!(tools[2].version, None);
+    }
+}
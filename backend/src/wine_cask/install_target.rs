@@ -0,0 +1,270 @@
+use crate::runtime_directory;
+use crate::wine_cask::app::{AppState, WineCask};
+use crate::wine_cask::flavors::{CompatibilityToolFlavor, SteamCompatibilityTool};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where a flavor's tools are installed to.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum InstallTarget {
+    /// Steam's `compatibilitytools.d`, every tool declares itself in a `compatibilitytool.vdf`.
+    SteamCompatibilityTools,
+    /// Wine runner directories of Lutris and Heroic, every version is a directory of its own.
+    WineRunners,
+    /// A directory of the plugin's own per flavor, for components dropped into prefixes by hand.
+    Components,
+}
+
+/// Wine runner directories below the user's home, in the order installs prefer them. Only those
+/// of launchers that are installed are used.
+const RUNNER_DIRECTORIES: [(&str, &str); 3] = [
+    (".local/share/lutris", "runners/wine"),
+    (".var/app/net.lutris.Lutris/data/lutris", "runners/wine"),
+    (".config/heroic", "tools/wine"),
+];
+
+pub fn install_target(flavor: &CompatibilityToolFlavor) -> InstallTarget {
+    match flavor {
+        CompatibilityToolFlavor::WineGE => InstallTarget::WineRunners,
+        CompatibilityToolFlavor::Dxvk | CompatibilityToolFlavor::Vkd3dProton => {
+            InstallTarget::Components
+        }
+        _ => InstallTarget::SteamCompatibilityTools,
+    }
+}
+
+/// Home of the user Steam runs as, the plugin itself may run as root.
+pub fn user_home() -> Option<PathBuf> {
+    env::var_os("DECKY_USER_HOME")
+        .or_else(|| env::var_os("HOME"))
+        .map(PathBuf::from)
+}
+
+/// Runner directories of the launchers installed below `home`, whether they exist yet or not.
+pub fn runner_directories(home: &Path) -> Vec<PathBuf> {
+    RUNNER_DIRECTORIES
+        .iter()
+        .filter(|(launcher, _)| home.join(launcher).is_dir())
+        .map(|(launcher, runners)| home.join(launcher).join(runners))
+        .collect()
+}
+
+/// Versions installed in `directories`, each a subdirectory with a `bin/wine` in it.
+pub fn list_runners(directories: &[PathBuf]) -> Vec<PathBuf> {
+    let mut runners: Vec<PathBuf> = directories
+        .iter()
+        .filter_map(|directory| fs::read_dir(directory).ok())
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .map(|entry| entry.path())
+        .filter(|path| path.join("bin").join("wine").is_file())
+        .collect();
+    runners.sort();
+    runners
+}
+
+pub fn components_directory() -> PathBuf {
+    runtime_directory().join("components")
+}
+
+/// Versions of a component installed in `directory`, each a subdirectory with the 64-bit DLLs.
+pub fn list_components(directory: &Path) -> Vec<PathBuf> {
+    let mut components: Vec<PathBuf> = fs::read_dir(directory)
+        .into_iter()
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .map(|entry| entry.path())
+        .filter(|path| path.join("x64").is_dir())
+        .collect();
+    components.sort();
+    components
+}
+
+/// Release tag of a component directory, e.g. `v2.3` for `dxvk-2.3`.
+pub fn component_tag(directory_name: &str) -> Option<String> {
+    let (_, version) = directory_name.rsplit_once('-')?;
+    version
+        .starts_with(|c: char| c.is_ascii_digit())
+        .then(|| format!("v{}", version))
+}
+
+/// Release tag of a Wine-GE runner directory, e.g. `GE-Proton8-26` for
+/// `lutris-GE-Proton8-26-x86_64` as released or `Wine-GE-Proton8-26` as Heroic names it.
+pub fn runner_tag(directory_name: &str) -> &str {
+    let name = ["lutris-", "Wine-"]
+        .iter()
+        .find_map(|prefix| directory_name.strip_prefix(prefix))
+        .unwrap_or(directory_name);
+    name.strip_suffix("-x86_64").unwrap_or(name)
+}
+
+/// Whether `tool`, installed to `target`, was installed from `release_tag` by its directory name.
+pub fn directory_matches_release(
+    target: InstallTarget,
+    tool: &SteamCompatibilityTool,
+    release_tag: &str,
+) -> bool {
+    match target {
+        InstallTarget::SteamCompatibilityTools => false,
+        InstallTarget::WineRunners => runner_tag(&tool.internal_name) == release_tag,
+        InstallTarget::Components => {
+            component_tag(&tool.internal_name).as_deref() == Some(release_tag)
+        }
+    }
+}
+
+/// Installed compatibility tools, runners and components alike, for what works the same on all.
+pub fn all_installed(app_state: &AppState) -> Vec<SteamCompatibilityTool> {
+    app_state
+        .installed_compatibility_tools
+        .iter()
+        .chain(&app_state.installed_runners)
+        .chain(&app_state.installed_components)
+        .cloned()
+        .collect()
+}
+
+impl AppState {
+    /// What's installed to `target`.
+    pub fn installed_to(&mut self, target: InstallTarget) -> &mut Vec<SteamCompatibilityTool> {
+        match target {
+            InstallTarget::SteamCompatibilityTools => &mut self.installed_compatibility_tools,
+            InstallTarget::WineRunners => &mut self.installed_runners,
+            InstallTarget::Components => &mut self.installed_components,
+        }
+    }
+}
+
+impl WineCask {
+    /// Directory `flavor` is installed to, `None` if no launcher it installs into is installed.
+    pub fn install_directory(&self, flavor: &CompatibilityToolFlavor) -> Option<PathBuf> {
+        match install_target(flavor) {
+            InstallTarget::SteamCompatibilityTools => {
+                Some(self.steam_util.get_steam_compatibility_tools_directory())
+            }
+            InstallTarget::WineRunners => runner_directories(&user_home()?).into_iter().next(),
+            InstallTarget::Components => Some(components_directory().join(flavor.to_string())),
+        }
+    }
+
+    /// Wine versions installed for Lutris and Heroic, listed like compatibility tools.
+    pub fn list_runners(&self) -> Vec<SteamCompatibilityTool> {
+        let Some(home) = user_home() else {
+            return Vec::new();
+        };
+        list_runners(&runner_directories(&home))
+            .into_iter()
+            .map(|path| listed_directory(path, CompatibilityToolFlavor::Unknown))
+            .collect()
+    }
+
+    /// Components in the plugin's directory, their flavor is told by the directory they're in.
+    pub fn list_components(&self) -> Vec<SteamCompatibilityTool> {
+        [
+            CompatibilityToolFlavor::Dxvk,
+            CompatibilityToolFlavor::Vkd3dProton,
+        ]
+        .into_iter()
+        .flat_map(|flavor| {
+            list_components(&components_directory().join(flavor.to_string()))
+                .into_iter()
+                .map(move |path| listed_directory(path, flavor.clone()))
+        })
+        .collect()
+    }
+}
+
+/// A directory listed like a compatibility tool, both names are the directory's.
+fn listed_directory(path: PathBuf, flavor: CompatibilityToolFlavor) -> SteamCompatibilityTool {
+    let name = path.file_name().unwrap().to_string_lossy().to_string();
+    SteamCompatibilityTool {
+        path: path.to_string_lossy().to_string(),
+        display_name: name.clone(),
+        internal_name: name,
+        used_by_games: Vec::new(),
+        used_by_apps: Vec::new(),
+        requires_restart: false,
+        supports_32bit: true,
+        flavor,
+        github_release: None,
+        modified_since_install: None,
+        inspection: None,
+        managed: false,
+        version: None,
+        official: false,
+        r#virtual: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_only_installed_launchers_are_used() {
+        let home = tempdir().unwrap();
+        assert!(runner_directories(home.path()).is_empty());
+
+        fs::create_dir_all(home.path().join(".config/heroic")).unwrap();
+        assert_eq!(
+            runner_directories(home.path()),
+            vec![home.path().join(".config/heroic/tools/wine")]
+        );
+        fs::create_dir_all(home.path().join(".local/share/lutris")).unwrap();
+        assert_eq!(
+            runner_directories(home.path())[0],
+            home.path().join(".local/share/lutris/runners/wine")
+        );
+    }
+
+    #[test]
+    fn test_runners_are_versioned_subdirectories() {
+        let lutris = tempdir().unwrap();
+        let heroic = tempdir().unwrap();
+        fs::create_dir_all(lutris.path().join("lutris-GE-Proton8-26-x86_64/bin")).unwrap();
+        fs::write(
+            lutris.path().join("lutris-GE-Proton8-26-x86_64/bin/wine"),
+            "",
+        )
+        .unwrap();
+        fs::create_dir_all(lutris.path().join("incomplete/bin")).unwrap();
+        fs::write(lutris.path().join("runners.json"), "{}").unwrap();
+        fs::create_dir_all(heroic.path().join("Wine-GE-Proton7-43/bin")).unwrap();
+        fs::write(heroic.path().join("Wine-GE-Proton7-43/bin/wine"), "").unwrap();
+
+        let directories = vec![
+            lutris.path().to_path_buf(),
+            heroic.path().to_path_buf(),
+            lutris.path().join("missing"),
+        ];
+        let runners = list_runners(&directories);
+        assert_eq!(runners.len(), 2);
+        assert!(runners.contains(&lutris.path().join("lutris-GE-Proton8-26-x86_64")));
+        assert!(runners.contains(&heroic.path().join("Wine-GE-Proton7-43")));
+    }
+
+    #[test]
+    fn test_components_are_versioned_subdirectories() {
+        let directory = tempdir().unwrap();
+        fs::create_dir_all(directory.path().join("dxvk-2.3/x64")).unwrap();
+        fs::create_dir_all(directory.path().join("dxvk-2.3/x32")).unwrap();
+        fs::create_dir_all(directory.path().join("dxvk-2.2/x32")).unwrap();
+        assert_eq!(
+            list_components(directory.path()),
+            vec![directory.path().join("dxvk-2.3")]
+        );
+        assert!(list_components(&directory.path().join("missing")).is_empty());
+
+        assert_eq!(component_tag("dxvk-2.3").as_deref(), Some("v2.3"));
+        assert_eq!(component_tag("vkd3d-proton-2.11").as_deref(), Some("v2.11"));
+        assert_eq!(component_tag("vkd3d-proton"), None);
+    }
+
+    #[test]
+    fn test_runner_tag() {
+        assert_eq!(runner_tag("lutris-GE-Proton8-26-x86_64"), "GE-Proton8-26");
+        assert_eq!(runner_tag("Wine-GE-Proton7-43"), "GE-Proton7-43");
+        assert_eq!(runner_tag("lutris-7.2-GE-1-LoL-x86_64"), "7.2-GE-1-LoL");
+        assert_eq!(runner_tag("wine-staging"), "wine-staging");
+    }
+}
@@ -0,0 +1,79 @@
+use crate::wine_cask::outbox::Outbox;
+use crate::PeerAddr;
+use futures_util::{Sink, SinkExt};
+use log::warn;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Time between the pings sent to each peer.
+pub const PING_INTERVAL: Duration = Duration::from_secs(20);
+/// Pings a peer may leave unanswered, it's dropped when the next one is due.
+pub const MISSED_PONGS_ALLOWED: u32 = 2;
+
+/// Tracks whether a peer still answers pings, a suspended Deck leaves connections open that
+/// nobody reads anymore.
+#[derive(Default)]
+pub struct Keepalive {
+    unanswered: AtomicU32,
+}
+
+impl Keepalive {
+    pub fn pong(&self) {
+        self.unanswered.store(0, Ordering::Relaxed);
+    }
+
+    /// Whether to ping the peer again, `false` once it missed too many pongs.
+    pub fn ping(&self) -> bool {
+        self.unanswered.fetch_add(1, Ordering::Relaxed) < MISSED_PONGS_ALLOWED
+    }
+}
+
+/// Writes the peer's messages and pings it in between. Returns once the peer was evicted,
+/// stopped answering pings or a write failed.
+pub async fn write_to_peer<S>(
+    addr: PeerAddr,
+    outbox: &Outbox,
+    mut outgoing: S,
+    keepalive: &Keepalive,
+    ping_interval: Duration,
+) where
+    S: Sink<Message> + Unpin,
+{
+    let mut pings =
+        tokio::time::interval_at(tokio::time::Instant::now() + ping_interval, ping_interval);
+    loop {
+        let message = tokio::select! {
+            message = outbox.next() => match message {
+                Some(message) => message,
+                None => return,
+            },
+            _ = pings.tick() => {
+                if !keepalive.ping() {
+                    warn!("{} missed {} pongs, dropping it", addr, MISSED_PONGS_ALLOWED);
+                    return;
+                }
+                Message::Ping(Vec::new())
+            }
+        };
+        if outgoing.send(message).await.is_err() {
+            warn!("Failed to write to {}, dropping it", addr);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peers_are_dropped_after_missing_pongs() {
+        let keepalive = Keepalive::default();
+        assert!(keepalive.ping());
+        keepalive.pong();
+        assert!(keepalive.ping());
+        assert!(keepalive.ping());
+        assert!(!keepalive.ping());
+    }
+}
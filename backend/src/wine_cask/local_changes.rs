@@ -0,0 +1,330 @@
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::flavors::SteamCompatibilityTool;
+use crate::wine_cask::provenance::{FileManifestEntry, Provenance};
+use crate::PeerMap;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+/// Files of a managed tool that differ from its file manifest, paths are relative to the tool.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+pub struct LocalChanges {
+    pub changed: Vec<String>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl LocalChanges {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.added.is_empty() && self.removed.is_empty()
+    }
+
+    /// Error refusing to touch `display_name` unless the request accepted losing the changes.
+    pub fn refusal(&self, display_name: &str, accepted: bool) -> Option<AppError> {
+        if accepted || self.is_empty() {
+            return None;
+        }
+        Some(AppError::new(
+            AppErrorCode::LocalChanges,
+            format!(
+                "{} was modified since it was installed ({} changed, {} added, {} removed), set accept_local_changes_loss to discard the changes",
+                display_name,
+                self.changed.len(),
+                self.added.len(),
+                self.removed.len()
+            ),
+        ))
+    }
+}
+
+fn visit_tool_files(
+    root: &Path,
+    directory: &Path,
+    visit: &mut impl FnMut(String, &fs::Metadata, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry_path = entry?.path();
+        let metadata = fs::symlink_metadata(&entry_path)?;
+        if metadata.is_dir() {
+            visit_tool_files(root, &entry_path, visit)?;
+        } else if metadata.is_file() {
+            let path = entry_path
+                .strip_prefix(root)
+                .unwrap_or(&entry_path)
+                .to_string_lossy()
+                .to_string();
+            visit(path, &metadata, &entry_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compares `directory` against its manifest. Files with the recorded size that weren't modified
+/// since `installed_at` are trusted, only the others get hashed.
+pub fn detect_local_changes(
+    directory: &Path,
+    files: &[FileManifestEntry],
+    installed_at: u64,
+) -> io::Result<LocalChanges> {
+    let installed_at = UNIX_EPOCH + Duration::from_secs(installed_at);
+    let mut expected: HashMap<&str, &FileManifestEntry> = files
+        .iter()
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+    let mut local_changes = LocalChanges::default();
+
+    visit_tool_files(directory, directory, &mut |path, metadata, file_path| {
+        let Some(entry) = expected.remove(path.as_str()) else {
+            local_changes.added.push(path);
+            return Ok(());
+        };
+        if metadata.len() != entry.size {
+            local_changes.changed.push(path);
+            return Ok(());
+        }
+        // Extracted files keep the times of the archive, anything newer was touched since
+        if metadata.modified()? < installed_at {
+            return Ok(());
+        }
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(file_path)?, &mut hasher)?;
+        if format!("{:x}", hasher.finalize()) != entry.sha256 {
+            local_changes.changed.push(path);
+        }
+        Ok(())
+    })?;
+
+    local_changes.removed = expected.into_keys().map(str::to_string).collect();
+    local_changes.changed.sort();
+    local_changes.added.sort();
+    local_changes.removed.sort();
+    Ok(local_changes)
+}
+
+/// Newest modification time of any directory of the tool, which changes whenever a file is added,
+/// removed or replaced.
+pub fn directory_mtime(directory: &Path) -> Option<SystemTime> {
+    let mut newest = fs::metadata(directory).ok()?.modified().ok()?;
+    for entry in fs::read_dir(directory).ok()?.filter_map(Result::ok) {
+        if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            newest = newest.max(directory_mtime(&entry.path())?);
+        }
+    }
+    Some(newest)
+}
+
+/// Results of earlier checks, reused while the directories of the tool are unchanged.
+#[derive(Clone, Default)]
+pub struct LocalChangesCache {
+    checked: HashMap<String, (SystemTime, LocalChanges)>,
+}
+
+impl LocalChangesCache {
+    /// Result of the last check of `internal_name`, without checking whether it's still current.
+    pub fn last(&self, internal_name: &str) -> Option<&LocalChanges> {
+        self.checked
+            .get(internal_name)
+            .map(|(_, local_changes)| local_changes)
+    }
+}
+
+impl WineCask {
+    /// Checks an installed tool for local changes, `None` for tools without a file manifest.
+    ///
+    /// Without `use_cache` the files are always compared, edits that don't touch a directory are
+    /// invisible to the cache.
+    pub async fn local_changes(
+        &self,
+        tool: &SteamCompatibilityTool,
+        use_cache: bool,
+    ) -> Option<LocalChanges> {
+        let provenance = Provenance::load(&tool.internal_name)?;
+        let files = provenance.files?;
+        let path = PathBuf::from(&tool.path);
+        let mtime = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || directory_mtime(&path))
+                .await
+                .unwrap()?
+        };
+
+        if use_cache {
+            let app_state = self.app_state.lock().await;
+            if let Some((checked_mtime, local_changes)) =
+                app_state.local_changes.checked.get(&tool.internal_name)
+            {
+                if *checked_mtime == mtime {
+                    return Some(local_changes.clone());
+                }
+            }
+        }
+
+        let installed_at = provenance.installed_at;
+        let local_changes =
+            tokio::task::spawn_blocking(move || detect_local_changes(&path, &files, installed_at))
+                .await
+                .unwrap()
+                .map_err(|err| {
+                    warn!(
+                        "Failed to check {} for local changes: {}",
+                        tool.internal_name, err
+                    )
+                })
+                .ok()?;
+        if !local_changes.is_empty() {
+            info!(
+                "{} was modified since it was installed: {:?}",
+                tool.internal_name, local_changes
+            );
+        }
+
+        let mut app_state = self.app_state.lock().await;
+        app_state
+            .local_changes
+            .checked
+            .insert(tool.internal_name.clone(), (mtime, local_changes.clone()));
+        for installed in &mut app_state.installed_compatibility_tools {
+            if installed.internal_name == tool.internal_name {
+                installed.modified_since_install =
+                    Some(local_changes.clone()).filter(|changes| !changes.is_empty());
+            }
+        }
+        Some(local_changes)
+    }
+
+    /// Checks `internal_name`, or every installed tool if `None`, and sends the updated listing.
+    pub async fn check_local_changes(&self, peer_map: &PeerMap, internal_name: Option<String>) {
+        let tools: Vec<SteamCompatibilityTool> = self
+            .app_state
+            .lock()
+            .await
+            .installed_compatibility_tools
+            .iter()
+            .filter(|tool| {
+                internal_name
+                    .as_ref()
+                    .is_none_or(|internal_name| &tool.internal_name == internal_name)
+            })
+            .cloned()
+            .collect();
+        for tool in &tools {
+            self.local_changes(tool, true).await;
+        }
+        self.broadcast_app_state(peer_map).await;
+    }
+
+    /// Returns the refusal to send if `tool` has local changes the request didn't accept losing.
+    pub async fn refuse_local_changes_loss(
+        &self,
+        tool: &SteamCompatibilityTool,
+        accepted: bool,
+    ) -> Option<AppError> {
+        if accepted {
+            return None;
+        }
+        self.local_changes(tool, false)
+            .await?
+            .refusal(&tool.display_name, accepted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wine_cask::provenance::{current_timestamp, generate_file_manifest};
+    use tempfile::tempdir;
+
+    fn install_mock_tool(tool_dir: &Path) -> Vec<FileManifestEntry> {
+        fs::create_dir_all(tool_dir.join("files/lib/wine")).unwrap();
+        fs::write(tool_dir.join("proton"), "#!/usr/bin/env python3").unwrap();
+        fs::write(tool_dir.join("files/lib/wine/d3d11.dll"), "d3d11").unwrap();
+        fs::write(tool_dir.join("version"), "1 GE-Proton9-20").unwrap();
+        generate_file_manifest(tool_dir).unwrap()
+    }
+
+    #[test]
+    fn test_detects_local_changes() {
+        let tool_dir = tempdir().unwrap();
+        let files = install_mock_tool(tool_dir.path());
+        let installed_at = current_timestamp();
+        assert!(detect_local_changes(tool_dir.path(), &files, installed_at)
+            .unwrap()
+            .is_empty());
+
+        // Same size, only the hash tells the patched script apart
+        fs::write(tool_dir.path().join("proton"), "#!/usr/bin/env python2").unwrap();
+        fs::write(tool_dir.path().join("files/lib/wine/dxgi.dll"), "patched").unwrap();
+        fs::remove_file(tool_dir.path().join("version")).unwrap();
+
+        let local_changes = detect_local_changes(tool_dir.path(), &files, installed_at).unwrap();
+        assert_eq!(
+            local_changes,
+            LocalChanges {
+                changed: vec!["proton".to_string()],
+                added: vec!["files/lib/wine/dxgi.dll".to_string()],
+                removed: vec!["version".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_unmodified_files_are_not_hashed() {
+        let tool_dir = tempdir().unwrap();
+        let mut files = install_mock_tool(tool_dir.path());
+        // A wrong hash goes unnoticed as long as the file is older than the install
+        files[0].sha256 = "0".repeat(64);
+        let installed_at = current_timestamp() + 60;
+        assert!(detect_local_changes(tool_dir.path(), &files, installed_at)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            detect_local_changes(tool_dir.path(), &files, 0)
+                .unwrap()
+                .changed,
+            vec![files[0].path.clone()]
+        );
+    }
+
+    #[test]
+    fn test_refuses_to_discard_local_changes() {
+        let local_changes = LocalChanges {
+            changed: vec!["proton".to_string()],
+            ..LocalChanges::default()
+        };
+        let refusal = local_changes.refusal("GE-Proton9-20", false).unwrap();
+        assert_eq!(refusal.code, AppErrorCode::LocalChanges);
+        assert!(refusal.message.starts_with("GE-Proton9-20"));
+        assert!(refusal.message.contains("accept_local_changes_loss"));
+        assert_eq!(local_changes.refusal("GE-Proton9-20", true), None);
+        assert_eq!(
+            LocalChanges::default().refusal("GE-Proton9-20", false),
+            None
+        );
+    }
+
+    #[test]
+    fn test_directory_mtime_follows_added_files() {
+        let tool_dir = tempdir().unwrap();
+        install_mock_tool(tool_dir.path());
+        let lib = tool_dir.path().join("files/lib/wine");
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        for directory in [
+            tool_dir.path(),
+            &tool_dir.path().join("files"),
+            &tool_dir.path().join("files/lib"),
+            &lib,
+        ] {
+            File::open(directory).unwrap().set_modified(old).unwrap();
+        }
+        assert_eq!(directory_mtime(tool_dir.path()), Some(old));
+
+        fs::write(lib.join("dxgi.dll"), "patched").unwrap();
+        assert!(directory_mtime(tool_dir.path()).unwrap() > old);
+    }
+}
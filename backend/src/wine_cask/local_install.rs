@@ -0,0 +1,345 @@
+use crate::github_util::Release;
+use crate::wine_cask::app::{Task, TaskType, WineCask};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::checksum::{parse_checksum_file, ArchiveVerifier};
+use crate::wine_cask::direct_install::infer_from_archive;
+use crate::wine_cask::flavors::CompatibilityToolFlavor;
+use crate::wine_cask::history::HistoryTrigger;
+use crate::wine_cask::install::{
+    archive_stem, CompressionType, Install, QueueCompatibilityTool, QueueCompatibilityToolState,
+};
+use crate::wine_cask::names::{validate_name, NameKind};
+use crate::PeerMap;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::{fs, io};
+
+/// Bytes read to tell the archive type, tar's magic ends at 262.
+const MAGIC_LENGTH: usize = 262;
+
+/// An archive already on disk, for offline installs or releases downloaded elsewhere.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LocalFileInstall {
+    /// Absolute path, anywhere including removable media.
+    pub path: String,
+    /// Overrides the flavor inferred from the file name.
+    pub flavor: Option<CompatibilityToolFlavor>,
+    /// Overrides the tag inferred from the file name.
+    pub tag_name: Option<String>,
+}
+
+pub fn unsupported_archive(name: &str) -> String {
+    format!("{} isn't a gzip, xz, zstd, tar or zip archive", name)
+}
+
+/// Type of the archive starting with `header`, `None` if it isn't one we extract.
+pub fn sniff_archive(header: &[u8]) -> Option<CompressionType> {
+    if header.starts_with(&[0x1f, 0x8b]) {
+        Some(CompressionType::Gzip)
+    } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Some(CompressionType::Xz)
+    } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Some(CompressionType::Zstd)
+    } else if header.get(257..MAGIC_LENGTH) == Some(&b"ustar"[..]) {
+        Some(CompressionType::Tar)
+    } else if header.starts_with(b"PK\x03\x04") {
+        Some(CompressionType::Zip)
+    } else {
+        None
+    }
+}
+
+/// Type of the archive at `path` by its contents, whatever its name says.
+pub fn sniff_archive_file(path: &Path) -> io::Result<Option<CompressionType>> {
+    let mut header = Vec::with_capacity(MAGIC_LENGTH);
+    File::open(path)?
+        .take(MAGIC_LENGTH as u64)
+        .read_to_end(&mut header)?;
+    Ok(sniff_archive(&header))
+}
+
+/// Checks that `path` is a regular file holding a supported archive, returning its type and size.
+pub fn check_local_archive(path: &Path) -> Result<(CompressionType, u64), String> {
+    if !path.is_absolute() {
+        return Err(format!("{} isn't an absolute path", path.display()));
+    }
+    // Follows symlinks, a link to an archive on an SD card is fine
+    let metadata = fs::metadata(path).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => format!("{} doesn't exist", path.display()),
+        _ => format!("Failed to read {}: {}", path.display(), err),
+    })?;
+    if !metadata.is_file() {
+        return Err(format!("{} isn't a regular file", path.display()));
+    }
+    let compress_type = sniff_archive_file(path)
+        .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?
+        .ok_or_else(|| unsupported_archive(&path.display().to_string()))?;
+    Ok((compress_type, metadata.len()))
+}
+
+/// Digest listed in a `.sha512sum` file next to the archive, as releases publish them.
+fn sibling_checksum(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy().to_string();
+    let stem = archive_stem(&name).unwrap_or(&name);
+    let content = fs::read_to_string(path.with_file_name(format!("{}.sha512sum", stem))).ok()?;
+    parse_checksum_file(&content, &name)
+}
+
+/// Validates the file and turns it into an install of a release without assets.
+pub fn local_file_install(local: &LocalFileInstall) -> Result<Install, String> {
+    let path = Path::new(&local.path);
+    check_local_archive(path)?;
+    let name = path.file_name().unwrap().to_string_lossy().to_string();
+    // The archive type is told by its contents, the extension only names it
+    let stem = archive_stem(&name).unwrap_or(&name);
+    let (archive_flavor, archive_tag) = infer_from_archive(stem).unzip();
+    let flavor = local
+        .flavor
+        .clone()
+        .or(archive_flavor)
+        .ok_or_else(|| format!("Couldn't tell the flavor of {}, pick one", name))?;
+    let tag_name = local
+        .tag_name
+        .clone()
+        .or(archive_tag.flatten())
+        .or_else(|| (flavor == CompatibilityToolFlavor::Unknown).then(|| stem.to_string()))
+        .ok_or_else(|| format!("Couldn't tell the release of {}, enter its tag", name))?;
+    // Tags end up in directory, internal and display names
+    validate_name(NameKind::Internal, &tag_name).map_err(|err| err.to_string())?;
+
+    Ok(Install {
+        flavor,
+        release: Release {
+            url: local.path.clone(),
+            id: 0,
+            draft: false,
+            prerelease: false,
+            name: tag_name.clone(),
+            tag_name,
+            target_commitish: String::new(),
+            assets: Vec::new(),
+            created_at: String::new(),
+            published_at: String::new(),
+            tarball_url: String::new(),
+            body: String::new(),
+        },
+        ignore_network_cap: false,
+        ignore_disk_space: false,
+        background: false,
+        accept_local_changes_loss: false,
+        copy_install: false,
+        replaces: Vec::new(),
+        migrate_mappings: false,
+        max_size: None,
+        local_path: Some(local.path.clone()),
+        restart_steam: false,
+        trigger: HistoryTrigger::Manual,
+    })
+}
+
+impl WineCask {
+    /// Queues the install of an archive on disk, which needs no network at all.
+    pub async fn install_from_local_file(&self, peer_map: &PeerMap, local: LocalFileInstall) {
+        let install = match local_file_install(&local) {
+            Ok(install) => install,
+            Err(error_message) => {
+                let app_error = AppError::new(AppErrorCode::InvalidRequest, error_message);
+                error!("{}", app_error);
+                self.broadcast_app_error(peer_map, app_error).await;
+                return;
+            }
+        };
+        info!(
+            "Installing {} {} from {}",
+            install.flavor, install.release.tag_name, local.path
+        );
+        let task = Task {
+            id: 0,
+            r#type: TaskType::InstallCompatibilityTool,
+            install: Some(install),
+            uninstall: None,
+            migrate: None,
+            mapping: None,
+            mappings: None,
+            import: None,
+            direct_install: None,
+            update_all: None,
+            local_file: None,
+            delete_compat_data: None,
+            restore_prefix_backup: None,
+        };
+        self.add_to_task_queue(task, peer_map).await;
+    }
+
+    /// Installs the archive at `install.local_path`, skipping the download. The file is checked
+    /// again since it may have changed while the install was queued.
+    pub async fn install_local_archive(&self, task_id: u64, install: Install, peer_map: &PeerMap) {
+        let path = install.local_path.clone().unwrap_or_default();
+        let mut queue_compatibility_tool = QueueCompatibilityTool {
+            id: task_id,
+            flavor: install.flavor.clone(),
+            name: install.release.tag_name.clone(),
+            url: path.clone(),
+            // The archive is hashed before it's extracted
+            state: QueueCompatibilityToolState::Verifying,
+            compress_type: CompressionType::Unknown,
+            size: 0,
+            progress: 0,
+            constraints: None,
+            bytes_per_second: None,
+            retry: None,
+            cancellation: Default::default(),
+        };
+        let (compress_type, size) = match check_local_archive(Path::new(&path)) {
+            Ok(checked) => checked,
+            Err(message) => {
+                self.fail_install(
+                    peer_map,
+                    &queue_compatibility_tool,
+                    AppError::internal(message),
+                )
+                .await;
+                return;
+            }
+        };
+        queue_compatibility_tool.compress_type = compress_type;
+        queue_compatibility_tool.size = size;
+        self.warn_unmet_requirements(peer_map, &install).await;
+        self.set_in_progress(&queue_compatibility_tool).await;
+        self.broadcast_app_state(peer_map).await;
+
+        // Hashed in chunks, the archive is streamed into the extraction afterwards
+        let read_path = path.clone();
+        let read = tokio::task::spawn_blocking(move || {
+            let mut file = File::open(&read_path)?;
+            let mut verifier = ArchiveVerifier::default();
+            let mut buffer = vec![0; 1024 * 1024];
+            loop {
+                let length = file.read(&mut buffer)?;
+                if length == 0 {
+                    return Ok::<_, io::Error>(verifier);
+                }
+                verifier.update(&buffer[..length]);
+            }
+        })
+        .await
+        .unwrap();
+        let verifier = match read {
+            Ok(read) => read,
+            Err(err) => {
+                self.fail_install(
+                    peer_map,
+                    &queue_compatibility_tool,
+                    AppError::from(err).context(format!("Failed to read {}", path)),
+                )
+                .await;
+                return;
+            }
+        };
+        let expected_checksum = sibling_checksum(Path::new(&path));
+        let checksum = match verifier.finish(expected_checksum.as_deref()) {
+            Ok(checksum) => checksum,
+            Err(message) => {
+                self.fail_install(
+                    peer_map,
+                    &queue_compatibility_tool,
+                    AppError::new(AppErrorCode::ChecksumMismatch, message),
+                )
+                .await;
+                return;
+            }
+        };
+
+        self.extract_generate_and_move(
+            peer_map,
+            &install,
+            &mut queue_compatibility_tool,
+            Path::new(&path),
+            checksum,
+        )
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha512};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_archives_are_told_by_their_contents() {
+        assert!(sniff_archive(&[0x1f, 0x8b, 0x08, 0x00]) == Some(CompressionType::Gzip));
+        assert!(
+            sniff_archive(&[0xfd, b'7', b'z', b'X', b'Z', 0x00, 0x00]) == Some(CompressionType::Xz)
+        );
+        assert!(sniff_archive(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]) == Some(CompressionType::Zstd));
+        let mut tar = vec![0; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert!(sniff_archive(&tar) == Some(CompressionType::Tar));
+        assert!(sniff_archive(b"PK\x03\x04\x14\x00") == Some(CompressionType::Zip));
+        assert!(sniff_archive(b"7z\xbc\xaf\x27\x1c").is_none());
+        assert!(sniff_archive(&[]).is_none());
+    }
+
+    #[test]
+    fn test_local_files_are_checked() {
+        let directory = tempdir().unwrap();
+        // Named like a tarball, but a 7z archive
+        let seven_zip = directory.path().join("GE-Proton9-20.tar.gz");
+        fs::write(&seven_zip, b"7z\xbc\xaf\x27\x1c").unwrap();
+        let install = |path: &Path| {
+            local_file_install(&LocalFileInstall {
+                path: path.to_string_lossy().to_string(),
+                flavor: None,
+                tag_name: None,
+            })
+        };
+        assert!(install(&seven_zip)
+            .err()
+            .unwrap()
+            .ends_with("isn't a gzip, xz, zstd, tar or zip archive"));
+        assert!(install(directory.path())
+            .err()
+            .unwrap()
+            .ends_with("isn't a regular file"));
+        assert!(install(&directory.path().join("missing.tar.gz"))
+            .err()
+            .unwrap()
+            .ends_with("doesn't exist"));
+        assert!(install(Path::new("GE-Proton9-20.tar.gz"))
+            .err()
+            .unwrap()
+            .ends_with("isn't an absolute path"));
+
+        // The extension doesn't matter either way
+        let archive = directory.path().join("GE-Proton9-20.tgz");
+        fs::write(&archive, [0x1f, 0x8b, 0x08, 0x00]).unwrap();
+        let install = install(&archive).unwrap();
+        assert_eq!(install.flavor, CompatibilityToolFlavor::ProtonGE);
+        assert_eq!(install.release.tag_name, "GE-Proton9-20");
+        assert_eq!(
+            install.local_path.as_deref(),
+            Some(archive.to_str().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_checksum_next_to_the_archive_is_used() {
+        let directory = tempdir().unwrap();
+        let archive = directory.path().join("GE-Proton9-20.tar.gz");
+        fs::write(&archive, [0x1f, 0x8b, 0x08, 0x00]).unwrap();
+        assert_eq!(sibling_checksum(&archive), None);
+
+        let digest = format!("{:x}", Sha512::digest([0x1f, 0x8b, 0x08, 0x00]));
+        fs::write(
+            directory.path().join("GE-Proton9-20.sha512sum"),
+            format!("{}  GE-Proton9-20.tar.gz\n", digest),
+        )
+        .unwrap();
+        assert_eq!(sibling_checksum(&archive), Some(digest));
+    }
+}
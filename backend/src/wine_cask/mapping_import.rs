@@ -0,0 +1,624 @@
+use crate::app_id::CompatAppId;
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, Task, TaskType, WineCask};
+use crate::wine_cask::feature_flags::Feature;
+use crate::wine_cask::flavors::{CompatibilityToolFlavor, Flavor};
+use crate::wine_cask::history::HistoryTrigger;
+use crate::wine_cask::install::Install;
+use crate::wine_cask::mappings::{MappingChange, MappingChanges, MappingContext};
+use crate::wine_cask::names::{validate_name, NameKind};
+use crate::wine_cask::naming::{naming_schemes, tool_matches_release, tool_version};
+use crate::PeerMap;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum MappingImportFormat {
+    /// One `<app id> [name]: <tool>` line per game, `=` or `->` work as separators too.
+    ProtonUpQt,
+    /// `<app id>,<tool>` rows, extra columns in between (e.g. the game's name) are ignored.
+    Csv,
+}
+
+/// Mappings exported by another tool.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MappingImport {
+    pub format: MappingImportFormat,
+    pub content: String,
+    /// Queue installs of missing tools that match a release, their mappings are applied once
+    /// they're installed.
+    #[serde(default)]
+    pub install_missing: bool,
+}
+
+/// A row of an import, or why it couldn't be read.
+#[derive(PartialEq, Debug)]
+pub struct ImportedRow {
+    /// 1-based line in the imported content.
+    pub line: usize,
+    pub mapping: Result<(CompatAppId, String), String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum ImportDisposition {
+    Applied,
+    AlreadySet,
+    /// The tool isn't installed, `release` is the release that installs it if one matched.
+    ToolMissing {
+        flavor: Option<CompatibilityToolFlavor>,
+        release: Option<String>,
+        queued: bool,
+    },
+    AppUnknown,
+    Invalid(String),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ImportRowResult {
+    pub line: usize,
+    pub app_id: Option<CompatAppId>,
+    pub compatibility_tool: Option<String>,
+    pub disposition: ImportDisposition,
+}
+
+/// Splits a CSV line on its delimiter outside of double quotes.
+fn split_csv(line: &str) -> Vec<String> {
+    let delimiter = [',', ';', '\t']
+        .into_iter()
+        .find(|delimiter| line.contains(*delimiter))
+        .unwrap_or(',');
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+        .into_iter()
+        .map(|field| field.trim().to_string())
+        .collect()
+}
+
+/// Splits a ProtonUp-Qt line at its last separator, game names may contain separators too.
+fn split_listing(line: &str) -> Option<(String, String)> {
+    let (index, separator) = [":", "=", "->"]
+        .into_iter()
+        .filter_map(|separator| Some((line.rfind(separator)?, separator)))
+        .max_by_key(|(index, _)| *index)?;
+    let game = line[..index].trim();
+    let app_id = game.split_whitespace().next().unwrap_or_default();
+    Some((
+        app_id.to_string(),
+        line[index + separator.len()..].trim().to_string(),
+    ))
+}
+
+/// The app id and tool fields of a line, unparsed.
+fn split_row(format: MappingImportFormat, line: &str) -> Result<(String, String), String> {
+    match format {
+        MappingImportFormat::Csv => match split_csv(line).as_slice() {
+            [app_id, .., compatibility_tool] => Ok((app_id.clone(), compatibility_tool.clone())),
+            _ => Err("Expected an app id and a tool".to_string()),
+        },
+        MappingImportFormat::ProtonUpQt => split_listing(line)
+            .ok_or_else(|| "Expected an app id and a tool separated by ':'".to_string()),
+    }
+}
+
+fn parse_row(format: MappingImportFormat, line: &str) -> Result<(CompatAppId, String), String> {
+    let (app_id, compatibility_tool) = split_row(format, line)?;
+    let app_id = CompatAppId::parse(&app_id).map_err(|err| err.to_string())?;
+    validate_name(NameKind::Internal, &compatibility_tool).map_err(|err| err.to_string())?;
+    Ok((app_id, compatibility_tool))
+}
+
+/// Reads every row of an import, skipping blank lines, `#` comments and a header row.
+pub fn parse_mapping_import(format: MappingImportFormat, content: &str) -> Vec<ImportedRow> {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .peekable();
+    // Headers are the only rows expected to lack a numeric app id
+    if lines.peek().is_some_and(|(_, line)| {
+        split_row(format, line).is_ok_and(|(app_id, _)| app_id.parse::<u64>().is_err())
+    }) {
+        lines.next();
+    }
+    lines
+        .map(|(line_number, line)| ImportedRow {
+            line: line_number,
+            mapping: parse_row(format, line),
+        })
+        .collect()
+}
+
+/// The flavor a tool name belongs to, along with the release that installs it if one is known.
+pub fn resolve_release(
+    flavors: &[Flavor],
+    compatibility_tool: &str,
+) -> Option<(CompatibilityToolFlavor, Option<String>)> {
+    flavors.iter().find_map(|flavor| {
+        let schemes = naming_schemes(&flavor.flavor);
+        let release = flavor.releases.iter().find(|release| {
+            tool_matches_release(
+                schemes,
+                &flavor.flavor,
+                compatibility_tool,
+                compatibility_tool,
+                release,
+            )
+        });
+        let follows_scheme = tool_version(schemes, compatibility_tool, compatibility_tool)
+            .is_some()
+            || (schemes.is_empty() && compatibility_tool.starts_with(&flavor.flavor.to_string()));
+        (release.is_some() || follows_scheme).then(|| {
+            (
+                flavor.flavor.clone(),
+                release.map(|release| release.tag_name.clone()),
+            )
+        })
+    })
+}
+
+/// What importing a list of rows amounts to.
+#[derive(Default)]
+pub struct ImportPlan {
+    pub results: Vec<ImportRowResult>,
+    /// Changes applying every row whose tool is installed.
+    pub changes: Vec<MappingChange>,
+    /// Changes waiting on a release of a flavor being installed.
+    pub installs: Vec<(CompatibilityToolFlavor, String, Vec<MappingChange>)>,
+}
+
+/// Decides what happens to every row, in order.
+pub fn plan_import(
+    context: &mut MappingContext,
+    flavors: &[Flavor],
+    rows: Vec<ImportedRow>,
+) -> ImportPlan {
+    let mut plan = ImportPlan::default();
+    for row in rows {
+        let (app_id, compatibility_tool) = match row.mapping {
+            Ok(mapping) => mapping,
+            Err(reason) => {
+                plan.results.push(ImportRowResult {
+                    line: row.line,
+                    app_id: None,
+                    compatibility_tool: None,
+                    disposition: ImportDisposition::Invalid(reason),
+                });
+                continue;
+            }
+        };
+        let change = MappingChange {
+            app_id,
+            compatibility_tool: Some(compatibility_tool.clone()),
+            clear_shader_cache: false,
+        };
+
+        let known_app = match (app_id.app_id(), &context.installed_apps) {
+            (Some(app), Some(installed_apps)) => {
+                installed_apps.contains(&app) || context.mappings.contains_key(&app_id)
+            }
+            _ => true,
+        };
+        let known_tool = context.installed_tools.contains_key(&compatibility_tool)
+            || context
+                .steam_tools
+                .as_ref()
+                .is_some_and(|steam_tools| steam_tools.contains(&compatibility_tool));
+        let disposition = if !known_app {
+            ImportDisposition::AppUnknown
+        } else if !known_tool {
+            let resolved = resolve_release(flavors, &compatibility_tool);
+            let (flavor, release) = match resolved {
+                Some((flavor, release)) => (Some(flavor), release),
+                None => (None, None),
+            };
+            if let (Some(flavor), Some(release)) = (&flavor, &release) {
+                match plan
+                    .installs
+                    .iter_mut()
+                    .find(|(other, tag_name, _)| other == flavor && tag_name == release)
+                {
+                    Some((_, _, changes)) => changes.push(change),
+                    None => plan
+                        .installs
+                        .push((flavor.clone(), release.clone(), vec![change])),
+                }
+            }
+            ImportDisposition::ToolMissing {
+                flavor,
+                release,
+                queued: false,
+            }
+        } else {
+            match context.plan(&change) {
+                Ok(Some(_)) => {
+                    plan.changes.push(change);
+                    ImportDisposition::Applied
+                }
+                Ok(None) => ImportDisposition::AlreadySet,
+                Err(reason) => ImportDisposition::Invalid(reason),
+            }
+        };
+        plan.results.push(ImportRowResult {
+            line: row.line,
+            app_id: Some(app_id),
+            compatibility_tool: Some(compatibility_tool),
+            disposition,
+        });
+    }
+    plan
+}
+
+impl WineCask {
+    /// Applies every importable row in one config write, and queues the installs missing tools
+    /// need if asked to.
+    pub async fn import_mappings(&self, peer_map: &PeerMap, import: MappingImport) {
+        let rows = parse_mapping_import(import.format, &import.content);
+        let mut context = self.mapping_context().await;
+        let flavors = self.app_state.lock().await.available_flavors.clone();
+        let mut plan = plan_import(&mut context, &flavors, rows);
+
+        if !plan.changes.is_empty() {
+            let results = self
+                .set_compatibility_tool_mappings(
+                    peer_map,
+                    MappingChanges {
+                        changes: plan.changes,
+                        atomic: false,
+                    },
+                )
+                .await;
+            // The write itself may have failed, or Steam's config changed since planning
+            for result in results.into_iter().filter(|result| !result.applied) {
+                let disposition = match result.reason.as_deref() {
+                    Some("Unchanged") => ImportDisposition::AlreadySet,
+                    reason => ImportDisposition::Invalid(reason.unwrap_or_default().to_string()),
+                };
+                for row in plan.results.iter_mut().filter(|row| {
+                    row.app_id == Some(result.app_id)
+                        && row.compatibility_tool == result.compatibility_tool
+                        && row.disposition == ImportDisposition::Applied
+                }) {
+                    row.disposition = disposition.clone();
+                }
+            }
+        }
+
+        let mut queued: HashSet<(CompatibilityToolFlavor, String)> = HashSet::new();
+        if import.install_missing {
+            for (flavor, tag_name, changes) in plan.installs {
+                let Some(release) = flavors
+                    .iter()
+                    .filter(|candidate| candidate.flavor == flavor)
+                    .flat_map(|candidate| &candidate.releases)
+                    .find(|release| release.tag_name == tag_name)
+                else {
+                    continue;
+                };
+                let install = Install {
+                    flavor: flavor.clone(),
+                    release: release.clone(),
+                    ignore_network_cap: false,
+                    ignore_disk_space: false,
+                    background: false,
+                    accept_local_changes_loss: false,
+                    copy_install: false,
+                    replaces: Vec::new(),
+                    migrate_mappings: false,
+                    max_size: None,
+                    local_path: None,
+                    restart_steam: false,
+                    trigger: HistoryTrigger::Manual,
+                };
+                let task = Task {
+                    id: 0,
+                    r#type: TaskType::InstallCompatibilityTool,
+                    install: Some(install),
+                    uninstall: None,
+                    migrate: None,
+                    mapping: None,
+                    mappings: None,
+                    import: None,
+                    direct_install: None,
+                    update_all: None,
+                    local_file: None,
+                    delete_compat_data: None,
+                    restore_prefix_backup: None,
+                };
+                if self.add_to_task_queue(task, peer_map).await {
+                    self.app_state.lock().await.pending_mappings.extend(changes);
+                    queued.insert((flavor, tag_name));
+                }
+            }
+        }
+        for row in &mut plan.results {
+            if let ImportDisposition::ToolMissing {
+                flavor: Some(flavor),
+                release: Some(release),
+                queued: row_queued,
+            } = &mut row.disposition
+            {
+                *row_queued = queued.contains(&(flavor.clone(), release.clone()));
+            }
+        }
+
+        let count = |matches: fn(&ImportDisposition) -> bool| {
+            plan.results
+                .iter()
+                .filter(|row| matches(&row.disposition))
+                .count()
+        };
+        let applied = count(|disposition| *disposition == ImportDisposition::Applied);
+        let waiting = count(|disposition| {
+            matches!(
+                disposition,
+                ImportDisposition::ToolMissing { queued: true, .. }
+            )
+        });
+        let mut message = format!("Imported {} of {} mappings", applied, plan.results.len());
+        if waiting > 0 {
+            message += &format!(
+                ", {} more once {} installed",
+                waiting,
+                match queued.len() {
+                    1 => "its tool is".to_string(),
+                    tools => format!("{} tools are", tools),
+                }
+            );
+        }
+        info!("{}", message);
+        broadcast_to_peers(
+            peer_map,
+            &Request {
+                notification: Some(message),
+                mapping_import: Some(plan.results),
+                ..Request::new(RequestType::Notification)
+            },
+        )
+        .await;
+    }
+
+    /// Applies the imported mappings that waited on a tool that's installed now.
+    /// They keep waiting while writing Steam's config is turned off.
+    pub async fn apply_pending_mappings(&self, peer_map: &PeerMap) {
+        let mut app_state = self.app_state.lock().await;
+        let installed: HashSet<String> = app_state
+            .installed_compatibility_tools
+            .iter()
+            .map(|tool| tool.internal_name.clone())
+            .collect();
+        let is_ready = |change: &MappingChange| {
+            change
+                .compatibility_tool
+                .as_ref()
+                .is_some_and(|compatibility_tool| installed.contains(compatibility_tool))
+        };
+        if !app_state
+            .settings
+            .feature_flags
+            .is_enabled(Feature::WriteSteamConfig)
+        {
+            let waiting = app_state
+                .pending_mappings
+                .iter()
+                .filter(|change| is_ready(change))
+                .count();
+            drop(app_state);
+            if waiting > 0 {
+                let message = format!(
+                    "{} mappings wait until {} is turned on",
+                    waiting,
+                    Feature::WriteSteamConfig
+                );
+                info!("{}", message);
+                self.broadcast_notification(peer_map, &message).await;
+            }
+            return;
+        }
+        let (ready, pending): (Vec<MappingChange>, Vec<MappingChange>) =
+            app_state.pending_mappings.drain(..).partition(is_ready);
+        app_state.pending_mappings = pending;
+        drop(app_state);
+        if !ready.is_empty() {
+            self.set_compatibility_tool_mappings(
+                peer_map,
+                MappingChanges {
+                    changes: ready,
+                    atomic: false,
+                },
+            )
+            .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_id::AppId;
+    use crate::test_fixtures::release;
+    use crate::wine_cask::requirements::Requirements;
+    use std::collections::HashMap;
+
+    fn app(app_id: u32) -> CompatAppId {
+        CompatAppId::from(AppId::new(app_id).unwrap())
+    }
+
+    fn mappings(format: MappingImportFormat, content: &str) -> Vec<(usize, Option<(u64, String)>)> {
+        parse_mapping_import(format, content)
+            .into_iter()
+            .map(|row| {
+                (
+                    row.line,
+                    row.mapping
+                        .ok()
+                        .map(|(app_id, tool)| (app_id.value(), tool)),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parses_messy_imports() {
+        use MappingImportFormat::{Csv, ProtonUpQt};
+        // Line number and, for lines that are mappings, the app id and tool
+        type Rows<'a> = Vec<(usize, Option<(u64, &'a str)>)>;
+        let cases: &[(MappingImportFormat, &str, Rows)] = &[
+            (
+                Csv,
+                "appid,tool\n1245620,GE-Proton9-20\n",
+                vec![(2, Some((1245620, "GE-Proton9-20")))],
+            ),
+            (
+                Csv,
+                "\u{feff}1245620;GE-Proton9-20\r\n\r\n# comment\r\n292030\tproton_8\r\n",
+                vec![
+                    (1, Some((1245620, "GE-Proton9-20"))),
+                    (4, Some((292030, "proton_8"))),
+                ],
+            ),
+            (
+                Csv,
+                "1091500,\"Cyberpunk 2077, Ultimate\",GE-Proton8-25\n 730 , proton_8 \n",
+                vec![
+                    (1, Some((1091500, "GE-Proton8-25"))),
+                    (2, Some((730, "proton_8"))),
+                ],
+            ),
+            (
+                Csv,
+                "730\nabc,proton_8\n730,GE Proton\n",
+                vec![(1, None), (2, None), (3, None)],
+            ),
+            (
+                ProtonUpQt,
+                "1245620 ELDEN RING: GE-Proton9-20\n292030 The Witcher 3: Wild Hunt = proton_8\n",
+                vec![
+                    (1, Some((1245620, "GE-Proton9-20"))),
+                    (2, Some((292030, "proton_8"))),
+                ],
+            ),
+            (
+                ProtonUpQt,
+                "Game: Tool\n730 -> GE-Proton8-25\nno separator\n",
+                vec![(2, Some((730, "GE-Proton8-25"))), (3, None)],
+            ),
+        ];
+        for (format, content, expected) in cases {
+            let expected: Vec<(usize, Option<(u64, String)>)> = expected
+                .iter()
+                .map(|(line, mapping)| {
+                    (
+                        *line,
+                        mapping.map(|(app_id, tool)| (app_id, tool.to_string())),
+                    )
+                })
+                .collect();
+            assert_eq!(mappings(*format, content), expected, "{:?}", content);
+        }
+    }
+
+    fn flavors() -> Vec<Flavor> {
+        vec![Flavor {
+            flavor: CompatibilityToolFlavor::ProtonGE,
+            releases: vec![release("GE-Proton8-25"), release("GE-Proton9-20")],
+            requirements: Requirements::default(),
+            requirement_warnings: Vec::new(),
+            latest_release: None,
+            skipped_releases: Vec::new(),
+            stale_since: None,
+        }]
+    }
+
+    fn context() -> MappingContext {
+        MappingContext {
+            mappings: HashMap::from([(app(730), "proton_8".to_string())]),
+            installed_tools: HashMap::from([("GE-Proton9-20".to_string(), Vec::new())]),
+            steam_tools: Some(HashSet::from(["proton_8".to_string()])),
+            installed_apps: Some(HashSet::from([
+                AppId::new(1245620).unwrap(),
+                AppId::new(1091500).unwrap(),
+                AppId::new(292030).unwrap(),
+            ])),
+            running_games: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_dispositions_of_imported_rows() {
+        let content = "\
+appid,tool
+1245620,GE-Proton9-20
+730,proton_8
+440,GE-Proton9-20
+1091500,GE-Proton8-25
+292030,GE-Proton8-25
+1245620,GE-Proton7-1
+292030,SomethingElse
+not a row
+";
+        let rows = parse_mapping_import(MappingImportFormat::Csv, content);
+        let plan = plan_import(&mut context(), &flavors(), rows);
+
+        let missing = |release: Option<&str>| ImportDisposition::ToolMissing {
+            flavor: Some(CompatibilityToolFlavor::ProtonGE),
+            release: release.map(str::to_string),
+            queued: false,
+        };
+        let dispositions: Vec<(usize, ImportDisposition)> = plan
+            .results
+            .iter()
+            .map(|row| (row.line, row.disposition.clone()))
+            .collect();
+        assert_eq!(
+            dispositions,
+            vec![
+                (2, ImportDisposition::Applied),
+                (3, ImportDisposition::AlreadySet),
+                (4, ImportDisposition::AppUnknown),
+                (5, missing(Some("GE-Proton8-25"))),
+                (6, missing(Some("GE-Proton8-25"))),
+                // Follows the naming scheme, but the release isn't known
+                (7, missing(None)),
+                (
+                    8,
+                    ImportDisposition::ToolMissing {
+                        flavor: None,
+                        release: None,
+                        queued: false,
+                    }
+                ),
+                (
+                    9,
+                    ImportDisposition::Invalid("Expected an app id and a tool".to_string())
+                ),
+            ]
+        );
+
+        let changed: Vec<CompatAppId> = plan.changes.iter().map(|change| change.app_id).collect();
+        assert_eq!(changed, vec![app(1245620)]);
+        // Both rows wait on the same install
+        assert_eq!(plan.installs.len(), 1);
+        let (flavor, tag_name, changes) = &plan.installs[0];
+        assert_eq!(*flavor, CompatibilityToolFlavor::ProtonGE);
+        assert_eq!(tag_name, "GE-Proton8-25");
+        let waiting: Vec<CompatAppId> = changes.iter().map(|change| change.app_id).collect();
+        assert_eq!(waiting, vec![app(1091500), app(292030)]);
+    }
+}
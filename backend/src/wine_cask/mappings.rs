@@ -0,0 +1,603 @@
+use crate::app_id::{AppId, CompatAppId};
+use crate::steam_util::ConfigWrite;
+use crate::wine_cask::activity::ActivitySource;
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, WineCask};
+use crate::wine_cask::app_error::AppError;
+use crate::wine_cask::feature_flags::Feature;
+use crate::wine_cask::network_usage::format_bytes;
+use crate::wine_cask::requirements::load_flavor_requirements;
+use crate::wine_cask::running_games::{mapping_affects, RunningGame};
+use crate::wine_cask::storage::shader_cache_status;
+use crate::wine_cask::undo::UndoOperation;
+use crate::PeerMap;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MappingChange {
+    /// `0` changes the default tool.
+    pub app_id: CompatAppId,
+    /// `None` removes the mapping.
+    pub compatibility_tool: Option<String>,
+    /// Clear the app's shader cache right away, the new tool can't use it.
+    #[serde(default)]
+    pub clear_shader_cache: bool,
+}
+
+/// Mapping changes written to config.vdf at once.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MappingChanges {
+    pub changes: Vec<MappingChange>,
+    /// Apply none of the changes unless every one of them is valid.
+    #[serde(default)]
+    pub atomic: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct MappingChangeResult {
+    pub app_id: CompatAppId,
+    pub compatibility_tool: Option<String>,
+    pub applied: bool,
+    /// Why the change wasn't applied.
+    pub reason: Option<String>,
+}
+
+/// What mapping changes are validated against.
+#[derive(Default)]
+pub struct MappingContext {
+    /// Mappings as they'll be once the changes planned so far are applied.
+    pub mappings: HashMap<CompatAppId, String>,
+    /// Tools in compatibilitytools.d along with the requirements this system violates.
+    pub installed_tools: HashMap<String, Vec<String>>,
+    /// Tools Steam reported, `None` until the frontend sent them.
+    pub steam_tools: Option<HashSet<String>>,
+    /// `None` if the installed apps couldn't be listed.
+    pub installed_apps: Option<HashSet<AppId>>,
+    /// Games whose tool can't be changed until they stopped.
+    pub running_games: Vec<RunningGame>,
+}
+
+impl MappingContext {
+    /// The error refusing the change if it would change the tool of a running game.
+    pub fn blocked_change(&self, change: &MappingChange) -> Option<AppError> {
+        if self.mappings.get(&change.app_id) == change.compatibility_tool.as_ref() {
+            return None;
+        }
+        let game = self
+            .running_games
+            .iter()
+            .find(|game| mapping_affects(&self.mappings, change.app_id, game.app_id))?;
+        if change.app_id == CompatAppId::DEFAULT {
+            Some(game.blocks("change the default tool"))
+        } else {
+            Some(game.blocks(&format!("change the mapping of {}", change.app_id)))
+        }
+    }
+
+    /// Returns the operation the change amounts to, `None` if it doesn't change anything, or why
+    /// it can't be applied.
+    pub fn plan(&mut self, change: &MappingChange) -> Result<Option<UndoOperation>, String> {
+        if let Some(app_error) = self.blocked_change(change) {
+            return Err(app_error.message);
+        }
+        if let Some(compatibility_tool) = &change.compatibility_tool {
+            match self.installed_tools.get(compatibility_tool) {
+                Some(violated) if !violated.is_empty() => {
+                    return Err(format!(
+                        "{} can't run on this system: {}",
+                        compatibility_tool,
+                        violated.join(", ")
+                    ));
+                }
+                Some(_) => {}
+                None if self
+                    .steam_tools
+                    .as_ref()
+                    .is_some_and(|steam_tools| !steam_tools.contains(compatibility_tool)) =>
+                {
+                    return Err(format!("Unknown tool {}", compatibility_tool));
+                }
+                None => {}
+            }
+            // Shortcuts can't be listed, mappings of uninstalled apps can still be removed
+            if let (Some(app_id), Some(installed_apps)) =
+                (change.app_id.app_id(), &self.installed_apps)
+            {
+                if !installed_apps.contains(&app_id) && !self.mappings.contains_key(&change.app_id)
+                {
+                    return Err(format!("Unknown app {}", change.app_id));
+                }
+            }
+        }
+
+        let previous = self.mappings.get(&change.app_id).cloned();
+        let operation =
+            UndoOperation::new(change.app_id, previous, change.compatibility_tool.clone());
+        match &change.compatibility_tool {
+            Some(compatibility_tool) => {
+                self.mappings
+                    .insert(change.app_id, compatibility_tool.clone());
+            }
+            None => {
+                self.mappings.remove(&change.app_id);
+            }
+        }
+        Ok(operation)
+    }
+}
+
+/// Plans every change in order, returning the operations to apply along with the result of each
+/// change. With `atomic`, a single invalid change cancels all of them.
+pub fn plan_mapping_changes<'a>(
+    context: &mut MappingContext,
+    changes: &'a [MappingChange],
+    atomic: bool,
+) -> (
+    Vec<(UndoOperation, &'a MappingChange)>,
+    Vec<MappingChangeResult>,
+) {
+    let planned: Vec<Result<Option<UndoOperation>, String>> =
+        changes.iter().map(|change| context.plan(change)).collect();
+    let cancelled = atomic && planned.iter().any(Result::is_err);
+
+    let mut operations = Vec::new();
+    let mut results = Vec::new();
+    for (change, plan) in changes.iter().zip(planned) {
+        let reason = match plan {
+            Ok(Some(_)) if cancelled => Some("Another change is invalid".to_string()),
+            Ok(Some(operation)) => {
+                operations.push((operation, change));
+                None
+            }
+            Ok(None) => Some("Unchanged".to_string()),
+            Err(reason) => Some(reason),
+        };
+        results.push(MappingChangeResult {
+            app_id: change.app_id,
+            compatibility_tool: change.compatibility_tool.clone(),
+            applied: reason.is_none(),
+            reason,
+        });
+    }
+    (operations, results)
+}
+
+impl WineCask {
+    async fn steam_override(&self, app_id: CompatAppId) -> Option<String> {
+        self.app_state
+            .lock()
+            .await
+            .steam_overrides
+            .get(app_id.app_id()?)
+            .cloned()
+    }
+
+    /// Tells the frontend the app's pre-cached shaders were built for the previous tool, and
+    /// clears them if asked to.
+    async fn advise_shader_rebuild(&self, peer_map: &PeerMap, app_id: AppId, clear: bool) {
+        let library_folders = self.mounted_library_folders().await;
+        let status =
+            tokio::task::spawn_blocking(move || shader_cache_status(&library_folders, app_id))
+                .await
+                .unwrap();
+        let Some(mut status) = status else {
+            return;
+        };
+        if status.fossilize_databases == 0 {
+            return;
+        }
+        if clear {
+            status.cleared = self.clear_shader_cache(peer_map, app_id).await;
+        }
+
+        let notification = if status.cleared {
+            format!(
+                "Warning: shaders_will_rebuild: {} will rebuild its shaders on the next launch, expect stutter at first",
+                app_id
+            )
+        } else {
+            format!(
+                "Warning: shaders_will_rebuild: {} will rebuild its shaders on the next launch, expect stutter at first. Its old shader cache ({}) can be cleared",
+                app_id,
+                format_bytes(status.bytes)
+            )
+        };
+        info!("{}", notification);
+        broadcast_to_peers(
+            peer_map,
+            &Request {
+                notification: Some(notification),
+                shader_cache: Some(status),
+                ..Request::new(RequestType::Notification)
+            },
+        )
+        .await;
+    }
+
+    pub(crate) async fn mapping_context(&self) -> MappingContext {
+        let installed_apps = match self.steam_util.list_installed_games() {
+            Ok(installed_games) => Some(
+                installed_games
+                    .into_iter()
+                    .map(|game| game.app_id)
+                    .collect(),
+            ),
+            Err(err) => {
+                warn!("Failed to get list of installed games: {}", err);
+                None
+            }
+        };
+        let app_state = self.app_state.lock().await;
+        MappingContext {
+            mappings: app_state
+                .compatibility_tool_mappings
+                .iter()
+                .map(|mapping| (mapping.app_id, mapping.compatibility_tool.clone()))
+                .collect(),
+            installed_tools: app_state
+                .installed_compatibility_tools
+                .iter()
+                .map(|tool| {
+                    let violated = load_flavor_requirements(&tool.flavor)
+                        .violated(&app_state.environment.system_versions);
+                    (tool.internal_name.clone(), violated)
+                })
+                .collect(),
+            steam_tools: app_state.available_compat_tools.as_ref().map(|tools| {
+                tools
+                    .iter()
+                    .map(|tool| tool.str_tool_name.clone())
+                    .collect()
+            }),
+            installed_apps,
+            // Only looked for right before writing
+            running_games: Vec::new(),
+        }
+    }
+
+    pub async fn set_compatibility_tool_mapping(&self, peer_map: &PeerMap, change: MappingChange) {
+        let changes = MappingChanges {
+            changes: vec![change],
+            atomic: true,
+        };
+        self.set_compatibility_tool_mappings(peer_map, changes)
+            .await;
+    }
+
+    /// Validates every change and applies the valid ones with a single write of config.vdf,
+    /// returns the result of every change.
+    pub async fn set_compatibility_tool_mappings(
+        &self,
+        peer_map: &PeerMap,
+        changes: MappingChanges,
+    ) -> Vec<MappingChangeResult> {
+        // Every write of config.vdf goes through here, whichever request or task asked for it
+        let enabled = self
+            .app_state
+            .lock()
+            .await
+            .settings
+            .feature_flags
+            .require(Feature::WriteSteamConfig);
+        if let Err(app_error) = enabled {
+            let results = changes
+                .changes
+                .iter()
+                .map(|change| MappingChangeResult {
+                    app_id: change.app_id,
+                    compatibility_tool: change.compatibility_tool.clone(),
+                    applied: false,
+                    reason: Some(app_error.message.clone()),
+                })
+                .collect();
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error).await;
+            return results;
+        }
+
+        // Re-read first so the undo entries keep what Steam has now
+        self.update_compatibility_tool_mappings(ActivitySource::External)
+            .await;
+        let mut context = self.mapping_context().await;
+        context.running_games = self.running_games(peer_map).await;
+        let blocked = changes
+            .changes
+            .iter()
+            .find_map(|change| context.blocked_change(change));
+        let (mut operations, mut results) =
+            plan_mapping_changes(&mut context, &changes.changes, changes.atomic);
+
+        let written = if operations.is_empty() {
+            Ok(ConfigWrite::default())
+        } else {
+            let mapping_changes: Vec<(CompatAppId, Option<&str>)> = operations
+                .iter()
+                .map(|(operation, _)| (operation.target(), operation.applied()))
+                .collect();
+            let force = self
+                .app_state
+                .lock()
+                .await
+                .settings
+                .write_while_steam_running;
+            let written = self
+                .steam_util
+                .set_compatibility_tool_mappings(&mapping_changes, force);
+            if written.is_ok() {
+                self.own_writes
+                    .record(&self.steam_util.get_config_vdf_path());
+            }
+            written
+        };
+        let mut write_error = None;
+        let config_write = match written {
+            Ok(config_write) => config_write,
+            Err(err) => {
+                for result in results.iter_mut().filter(|result| result.applied) {
+                    result.applied = false;
+                    result.reason = Some(err.to_string());
+                }
+                operations.clear();
+                write_error = Some(AppError::from(err).context("Failed to write config.vdf"));
+                ConfigWrite::default()
+            }
+        };
+        if config_write.merged_foreign_change {
+            info!(
+                "Kept changes made to config.vdf while writing it, after {} retries",
+                config_write.retries
+            );
+        }
+
+        let failures: Vec<String> = results
+            .iter()
+            .filter(|result| !result.applied && result.reason.as_deref() != Some("Unchanged"))
+            .map(|result| {
+                format!(
+                    "{}: {}",
+                    result.app_id,
+                    result.reason.as_deref().unwrap_or("")
+                )
+            })
+            .collect();
+        if let Some(app_error) = write_error {
+            // Every change failed the same way
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error).await;
+        } else if let Some(app_error) = blocked {
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error).await;
+        } else if !failures.is_empty() {
+            let app_error = AppError::internal(format!(
+                "Failed to change mapping of {}",
+                failures.join(", ")
+            ));
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error).await;
+        }
+
+        if !operations.is_empty() {
+            let message = match operations.as_slice() {
+                [(operation, _)] => format!("Changed {}, restart Steam to apply", operation),
+                _ => format!(
+                    "Changed {} mappings, restart Steam to apply",
+                    operations.len()
+                ),
+            };
+            info!("{}", message);
+            for (operation, _) in &operations {
+                self.record_undo_operation(operation.clone()).await;
+            }
+            self.app_state.lock().await.restart_required = true;
+            self.update_compatibility_tool_mappings(ActivitySource::Task)
+                .await;
+            broadcast_to_peers(
+                peer_map,
+                &Request {
+                    notification: Some(message),
+                    mapping_results: Some(results.clone()),
+                    config_write: Some(config_write),
+                    ..Request::new(RequestType::Notification)
+                },
+            )
+            .await;
+
+            for (_, change) in &operations {
+                self.advise_on_mapping_change(peer_map, change).await;
+            }
+            self.get_undo_stack(peer_map).await;
+        }
+        self.broadcast_app_state(peer_map).await;
+        results
+    }
+
+    /// Warnings about an applied change that are specific to its app.
+    async fn advise_on_mapping_change(&self, peer_map: &PeerMap, change: &MappingChange) {
+        if let Some(steam_override) = self.steam_override(change.app_id).await {
+            let warning_message = format!(
+                "Warning: Steam forces {} on {}, the mapping may be ignored",
+                steam_override, change.app_id
+            );
+            warn!("{}", warning_message);
+            self.broadcast_app_notification(peer_map, &warning_message, vec![change.app_id])
+                .await;
+        }
+        // The default tool applies to every app without a mapping, too many caches to advise on
+        if let Some(app_id) = change.app_id.app_id() {
+            self.advise_shader_rebuild(peer_map, app_id, change.clear_shader_cache)
+                .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wine_cask::app_error::AppErrorCode;
+
+    fn app(app_id: u32) -> CompatAppId {
+        CompatAppId::from(AppId::new(app_id).unwrap())
+    }
+
+    fn change(app_id: CompatAppId, compatibility_tool: Option<&str>) -> MappingChange {
+        MappingChange {
+            app_id,
+            compatibility_tool: compatibility_tool.map(str::to_string),
+            clear_shader_cache: false,
+        }
+    }
+
+    fn context() -> MappingContext {
+        MappingContext {
+            mappings: HashMap::from([(app(730), "proton_8".to_string())]),
+            installed_tools: HashMap::from([
+                ("GE-Proton9-20".to_string(), Vec::new()),
+                (
+                    "GE-Proton10-1".to_string(),
+                    vec!["Requires Mesa 25.0 or newer, found 24.1".to_string()],
+                ),
+            ]),
+            steam_tools: Some(HashSet::from([
+                "proton_8".to_string(),
+                "proton_experimental".to_string(),
+            ])),
+            // 730 was uninstalled, its mapping is still there
+            installed_apps: Some(HashSet::from([AppId::new(1245620).unwrap()])),
+            running_games: Vec::new(),
+        }
+    }
+
+    fn reasons(results: &[MappingChangeResult]) -> Vec<Option<&str>> {
+        results
+            .iter()
+            .map(|result| result.reason.as_deref())
+            .collect()
+    }
+
+    #[test]
+    fn test_invalid_changes_are_skipped() {
+        let changes = [
+            change(app(1245620), Some("GE-Proton9-20")),
+            change(app(730), Some("GE-Proton10-1")),
+            change(app(292030), Some("GE-Proton9-20")),
+            change(app(1245620), Some("GE-Proton9-99")),
+            change(app(730), Some("proton_8")),
+            // Mapped but uninstalled apps can be cleared
+            change(app(730), None),
+            change(CompatAppId::DEFAULT, Some("proton_experimental")),
+        ];
+        let (operations, results) = plan_mapping_changes(&mut context(), &changes, false);
+
+        assert_eq!(
+            reasons(&results),
+            vec![
+                None,
+                Some("GE-Proton10-1 can't run on this system: Requires Mesa 25.0 or newer, found 24.1"),
+                Some("Unknown app 292030"),
+                Some("Unknown tool GE-Proton9-99"),
+                Some("Unchanged"),
+                None,
+                None,
+            ]
+        );
+        let targets: Vec<CompatAppId> = operations
+            .iter()
+            .map(|(operation, _)| operation.target())
+            .collect();
+        assert_eq!(targets, vec![app(1245620), app(730), CompatAppId::DEFAULT]);
+    }
+
+    #[test]
+    fn test_atomic_changes_are_all_or_nothing() {
+        let changes = [
+            change(app(1245620), Some("GE-Proton9-20")),
+            change(app(1245620), Some("GE-Proton9-99")),
+        ];
+        let (operations, results) = plan_mapping_changes(&mut context(), &changes, true);
+        assert!(operations.is_empty());
+        assert_eq!(
+            reasons(&results),
+            vec![
+                Some("Another change is invalid"),
+                Some("Unknown tool GE-Proton9-99")
+            ]
+        );
+
+        // Unchanged mappings don't cancel the others
+        let changes = [
+            change(app(730), Some("proton_8")),
+            change(app(1245620), Some("GE-Proton9-20")),
+        ];
+        let (operations, _) = plan_mapping_changes(&mut context(), &changes, true);
+        assert_eq!(operations.len(), 1);
+    }
+
+    #[test]
+    fn test_single_and_bulk_changes_plan_the_same_operations() {
+        let changes = [
+            change(app(1245620), Some("GE-Proton9-20")),
+            change(app(730), None),
+            change(app(1245620), Some("proton_experimental")),
+            change(CompatAppId::DEFAULT, Some("GE-Proton9-20")),
+        ];
+        let mut single_context = context();
+        let single: Vec<UndoOperation> = changes
+            .iter()
+            .flat_map(|change| {
+                plan_mapping_changes(&mut single_context, std::slice::from_ref(change), true).0
+            })
+            .map(|(operation, _)| operation)
+            .collect();
+        let mut bulk_context = context();
+        let bulk: Vec<UndoOperation> = plan_mapping_changes(&mut bulk_context, &changes, false)
+            .0
+            .into_iter()
+            .map(|(operation, _)| operation)
+            .collect();
+
+        assert_eq!(single, bulk);
+        assert_eq!(single_context.mappings, bulk_context.mappings);
+        // Later changes to the same app see the earlier ones
+        assert_eq!(bulk[2].previous(), Some("GE-Proton9-20"));
+    }
+
+    #[test]
+    fn test_unknown_lists_dont_reject_changes() {
+        let mut context = MappingContext::default();
+        let changes = [change(app(1245620), Some("proton_hotfix"))];
+        let (operations, _) = plan_mapping_changes(&mut context, &changes, true);
+        assert_eq!(operations.len(), 1);
+    }
+
+    #[test]
+    fn test_changes_to_running_games_are_blocked() {
+        let mut context = MappingContext {
+            running_games: vec![RunningGame {
+                app_id: AppId::new(1245620).unwrap(),
+                name: "ELDEN RING".to_string(),
+            }],
+            ..context()
+        };
+        let changes = [
+            change(app(1245620), Some("GE-Proton9-20")),
+            // ELDEN RING has no mapping, it runs with the default tool
+            change(CompatAppId::DEFAULT, Some("GE-Proton9-20")),
+            change(app(730), Some("GE-Proton9-20")),
+        ];
+        assert_eq!(
+            context.blocked_change(&changes[0]).unwrap().code,
+            AppErrorCode::OperationBlockedGameRunning
+        );
+        let (operations, results) = plan_mapping_changes(&mut context, &changes, false);
+        assert_eq!(
+            reasons(&results),
+            [
+                Some("Can't change the mapping of 1245620 while ELDEN RING is running"),
+                Some("Can't change the default tool while ELDEN RING is running"),
+                None,
+            ]
+        );
+        assert_eq!(operations.len(), 1);
+    }
+}
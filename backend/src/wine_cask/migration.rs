@@ -0,0 +1,396 @@
+use crate::steam_util::{CompatibilityTool, SteamUtil};
+use crate::wine_cask::activity::ActivitySource;
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::error_aggregation::{ErrorReport, ErrorSource};
+use crate::wine_cask::filesystems::{detect_filesystem, FilesystemProfile};
+use crate::wine_cask::generate_compatibility_tool_vdf;
+use crate::wine_cask::mutation_guard::{copy_dir_guarded, delete_dir_guarded, journal_directory};
+use crate::PeerMap;
+use keyvalues_parser::Vdf;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+/// Compatibility tool installed under a Steam root that isn't the active one, Steam never loads it.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct StrandedCompatibilityTool {
+    /// Steam root the tool was found under.
+    pub root: PathBuf,
+    pub directory_name: String,
+    pub internal_name: String,
+    pub display_name: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Migrate {
+    pub from_root: PathBuf,
+    /// Directory names of the tools to move into the active root.
+    pub tool_names: Vec<String>,
+}
+
+/// Lists the tools of every other root that aren't installed in `active_directory`, whose
+/// filesystem is described by `profile`.
+pub fn find_stranded_compatibility_tools(
+    other_roots: &[PathBuf],
+    active_directory: &Path,
+    profile: &FilesystemProfile,
+) -> Vec<StrandedCompatibilityTool> {
+    let installed: Vec<String> = fs::read_dir(active_directory)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut stranded = Vec::new();
+    for root in other_roots {
+        let Ok(entries) = fs::read_dir(root.join("compatibilitytools.d")) else {
+            continue;
+        };
+        // Don't use SteamUtil::list_compatibility_tools, it would create missing directories
+        let steam_util = SteamUtil::new(root.clone());
+        let mut tools: Vec<CompatibilityTool> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path().join("compatibilitytool.vdf"))
+            .filter(|vdf| vdf.exists())
+            .filter_map(|vdf| {
+                steam_util
+                    .read_compatibility_tools_from_vdf_path(&vdf)
+                    .map_err(|err| warn!("Skipping {}: {}", vdf.display(), err))
+                    .ok()
+            })
+            .flatten()
+            .filter(|tool| {
+                !installed
+                    .iter()
+                    .any(|name| profile.names_collide(name, &tool.directory_name))
+            })
+            .collect();
+        tools.sort_by(|a, b| a.directory_name.cmp(&b.directory_name));
+        stranded.extend(tools.into_iter().map(|tool| StrandedCompatibilityTool {
+            root: root.clone(),
+            directory_name: tool.directory_name,
+            internal_name: tool.internal_name,
+            display_name: tool.display_name,
+        }));
+    }
+    stranded
+}
+
+pub fn read_install_path(vdf_path: &Path) -> Option<String> {
+    let vdf_text = fs::read_to_string(vdf_path).ok()?;
+    let vdf = Vdf::parse(&vdf_text).ok()?;
+    vdf.value
+        .get_obj()?
+        .values()
+        .next()?
+        .first()?
+        .get_obj()?
+        .values()
+        .next()?
+        .first()?
+        .get_obj()?
+        .get("install_path")?
+        .first()?
+        .get_str()
+        .map(|install_path| install_path.to_string())
+}
+
+/// Moves a tool directory into `destination`, renaming when both are on the same filesystem and
+/// copying otherwise.
+pub fn migrate_tool(
+    journal_directory: &Path,
+    tool: &StrandedCompatibilityTool,
+    destination: &Path,
+    profile: &FilesystemProfile,
+) -> io::Result<()> {
+    let source = tool
+        .root
+        .join("compatibilitytools.d")
+        .join(&tool.directory_name);
+    if destination.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", destination.display()),
+        ));
+    }
+
+    if let Err(err) = fs::rename(&source, destination) {
+        info!(
+            "Renaming {} failed, copying it instead: {}",
+            source.display(),
+            err
+        );
+        copy_dir_guarded(
+            journal_directory,
+            &source,
+            destination,
+            profile.unix_permissions,
+        )?;
+        delete_dir_guarded(journal_directory, &source)?;
+    }
+
+    // Steam resolves a relative install path against the tool directory, an absolute one still
+    // points into the old root
+    let vdf_path = destination.join("compatibilitytool.vdf");
+    if read_install_path(&vdf_path)
+        .is_some_and(|install_path| Path::new(&install_path).is_absolute())
+    {
+        generate_compatibility_tool_vdf(vdf_path, &tool.internal_name, &tool.display_name);
+    }
+    Ok(())
+}
+
+impl WineCask {
+    /// Looks for tools left behind under the other Steam roots, e.g. after switching from the
+    /// flatpak to the native Steam package or back.
+    pub async fn update_stranded_compatibility_tools(&self) {
+        let Some(user_home) = env::var_os("HOME").map(PathBuf::from) else {
+            return;
+        };
+        let other_roots = self.steam_util.find_other_steam_roots(&user_home);
+        let active_directory = self.steam_util.get_steam_compatibility_tools_directory();
+        let stranded = tokio::task::spawn_blocking(move || {
+            let profile = detect_filesystem(&active_directory);
+            find_stranded_compatibility_tools(&other_roots, &active_directory, &profile)
+        })
+        .await
+        .unwrap();
+        self.app_state.lock().await.stranded_compatibility_tools = stranded;
+    }
+
+    pub async fn migrate_compatibility_tools(&self, peer_map: &PeerMap, migrate: Migrate) {
+        // Only move tools we reported as stranded, the request must not pick arbitrary paths
+        let tools: Vec<StrandedCompatibilityTool> = self
+            .app_state
+            .lock()
+            .await
+            .stranded_compatibility_tools
+            .iter()
+            .filter(|tool| {
+                tool.root == migrate.from_root && migrate.tool_names.contains(&tool.directory_name)
+            })
+            .cloned()
+            .collect();
+        if tools.len() != migrate.tool_names.len() {
+            let app_error = AppError::new(
+                AppErrorCode::NotFound,
+                format!(
+                    "Not every tool to migrate was found under {}",
+                    migrate.from_root.display()
+                ),
+            );
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error).await;
+            return;
+        }
+
+        let active_directory = self.steam_util.get_steam_compatibility_tools_directory();
+        let profile = detect_filesystem(&active_directory);
+        for (index, tool) in tools.iter().enumerate() {
+            self.broadcast_notification(
+                peer_map,
+                &format!(
+                    "Migrating {} ({}/{})",
+                    tool.display_name,
+                    index + 1,
+                    tools.len()
+                ),
+            )
+            .await;
+            let tool_clone = tool.clone();
+            let destination = active_directory.join(&tool.directory_name);
+            let profile_clone = profile.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                migrate_tool(
+                    &journal_directory(),
+                    &tool_clone,
+                    &destination,
+                    &profile_clone,
+                )
+            })
+            .await
+            .unwrap();
+            if let Err(err) = result {
+                error!("{}: Failed to migrate: {}", tool.display_name, err);
+                self.broadcast_error(
+                    peer_map,
+                    ErrorReport {
+                        code: "migration_failed",
+                        source: ErrorSource::Filesystem,
+                        target: tool.display_name.clone(),
+                        error: AppError::from(err).context("Failed to migrate"),
+                        terminal: true,
+                    },
+                )
+                .await;
+                break;
+            }
+        }
+
+        self.sync_backend_with_installed_compat_tools().await;
+        self.record_tool_activity(ActivitySource::Task).await;
+        self.broadcast_app_state(peer_map).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    const NATIVE_ROOT: &str = ".local/share/Steam";
+    const FLATPAK_ROOT: &str = ".var/app/com.valvesoftware.Steam/data/Steam";
+
+    fn create_steam_root(home: &Path, root: &str, tools: &[&str]) -> PathBuf {
+        let root = home.join(root);
+        fs::create_dir_all(root.join("config")).unwrap();
+        fs::write(root.join("config/config.vdf"), "").unwrap();
+        fs::write(root.join("config/libraryfolders.vdf"), "").unwrap();
+        let compatibility_tools_directory = root.join("compatibilitytools.d");
+        fs::create_dir_all(&compatibility_tools_directory).unwrap();
+        for tool in tools {
+            let tool_directory = compatibility_tools_directory.join(tool);
+            fs::create_dir_all(tool_directory.join("files/bin")).unwrap();
+            fs::write(tool_directory.join("files/bin/wine"), "wine").unwrap();
+            generate_compatibility_tool_vdf(
+                tool_directory.join("compatibilitytool.vdf"),
+                tool,
+                tool,
+            );
+        }
+        root.canonicalize().unwrap()
+    }
+
+    fn migrate_all(home: &Path, active_root: &Path) -> Vec<String> {
+        let steam_util = SteamUtil::new(active_root.to_path_buf());
+        let journal = home.join("journal");
+        let stranded = find_stranded_compatibility_tools(
+            &steam_util.find_other_steam_roots(home),
+            &steam_util.get_steam_compatibility_tools_directory(),
+            &FilesystemProfile::default(),
+        );
+        for tool in &stranded {
+            migrate_tool(
+                &journal,
+                tool,
+                &steam_util
+                    .get_steam_compatibility_tools_directory()
+                    .join(&tool.directory_name),
+                &FilesystemProfile::default(),
+            )
+            .unwrap();
+        }
+        let mut tools: Vec<String> = steam_util
+            .list_compatibility_tools()
+            .unwrap()
+            .into_iter()
+            .map(|tool| tool.internal_name)
+            .collect();
+        tools.sort();
+        tools
+    }
+
+    #[test]
+    fn test_migrates_flatpak_tools_to_native() {
+        let home = tempdir().unwrap();
+        let native = create_steam_root(home.path(), NATIVE_ROOT, &["GE-Proton9-21"]);
+        let flatpak = create_steam_root(
+            home.path(),
+            FLATPAK_ROOT,
+            &["GE-Proton9-20", "GE-Proton9-21"],
+        );
+        // .steam/root usually points at the native root and must not be reported
+        fs::create_dir_all(home.path().join(".steam")).unwrap();
+        symlink(&native, home.path().join(".steam/root")).unwrap();
+
+        let steam_util = SteamUtil::new(native.clone());
+        assert_eq!(
+            steam_util.find_other_steam_roots(home.path()),
+            vec![flatpak.clone()]
+        );
+        assert_eq!(
+            find_stranded_compatibility_tools(
+                std::slice::from_ref(&flatpak),
+                &native.join("compatibilitytools.d"),
+                &FilesystemProfile::default()
+            ),
+            vec![StrandedCompatibilityTool {
+                root: flatpak.clone(),
+                directory_name: "GE-Proton9-20".to_string(),
+                internal_name: "GE-Proton9-20".to_string(),
+                display_name: "GE-Proton9-20".to_string(),
+            }]
+        );
+
+        assert_eq!(
+            migrate_all(home.path(), &native),
+            vec!["GE-Proton9-20", "GE-Proton9-21"]
+        );
+        assert!(!flatpak.join("compatibilitytools.d/GE-Proton9-20").exists());
+        assert!(flatpak.join("compatibilitytools.d/GE-Proton9-21").exists());
+    }
+
+    #[test]
+    fn test_migrates_native_tools_to_flatpak() {
+        let home = tempdir().unwrap();
+        create_steam_root(home.path(), NATIVE_ROOT, &["GE-Proton9-20", "Luxtorpeda"]);
+        let flatpak = create_steam_root(home.path(), FLATPAK_ROOT, &[]);
+
+        assert_eq!(
+            migrate_all(home.path(), &flatpak),
+            vec!["GE-Proton9-20", "Luxtorpeda"]
+        );
+        assert_eq!(
+            fs::read_to_string(flatpak.join("compatibilitytools.d/Luxtorpeda/files/bin/wine"))
+                .unwrap(),
+            "wine"
+        );
+    }
+
+    #[test]
+    fn test_stranded_detection_follows_case_sensitivity() {
+        let home = tempdir().unwrap();
+        let native = create_steam_root(home.path(), NATIVE_ROOT, &["ge-proton9-20"]);
+        let flatpak = create_steam_root(home.path(), FLATPAK_ROOT, &["GE-Proton9-20"]);
+        let stranded = |profile: &FilesystemProfile| {
+            find_stranded_compatibility_tools(
+                std::slice::from_ref(&flatpak),
+                &native.join("compatibilitytools.d"),
+                profile,
+            )
+            .len()
+        };
+        assert_eq!(stranded(&FilesystemProfile::for_mount("ext4", "rw")), 1);
+        // Moving it onto exFAT would clash with the installed tool
+        assert_eq!(stranded(&FilesystemProfile::for_mount("exfat", "rw")), 0);
+    }
+
+    #[test]
+    fn test_absolute_install_path_is_rewritten() {
+        let home = tempdir().unwrap();
+        let native = create_steam_root(home.path(), NATIVE_ROOT, &[]);
+        let flatpak = create_steam_root(home.path(), FLATPAK_ROOT, &["GE-Proton9-20"]);
+        let vdf_path = flatpak.join("compatibilitytools.d/GE-Proton9-20/compatibilitytool.vdf");
+        let vdf = fs::read_to_string(&vdf_path).unwrap().replace(
+            r#""install_path" ".""#,
+            &format!(
+                r#""install_path" "{}""#,
+                flatpak.join("compatibilitytools.d/GE-Proton9-20").display()
+            ),
+        );
+        fs::write(&vdf_path, vdf).unwrap();
+
+        migrate_all(home.path(), &native);
+        assert_eq!(
+            read_install_path(
+                &native.join("compatibilitytools.d/GE-Proton9-20/compatibilitytool.vdf")
+            ),
+            Some(".".to_string())
+        );
+    }
+}
@@ -1,17 +1,88 @@
-use crate::wine_cask::app::{TaskType, WineCask};
+use crate::wine_cask::app::{Task, TaskType, WineCask};
+use crate::wine_cask::background::Admission;
+use crate::wine_cask::flavors::{CacheUse, CompatibilityToolFlavor};
+use crate::wine_cask::names::escape_vdf;
 use crate::PeerMap;
+use log::info;
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{fs, io};
 
+pub mod activity;
 pub mod app;
+pub mod app_error;
+pub mod app_names;
+pub mod atomic_install;
+pub mod background;
+pub mod cancellation;
+pub mod checksum;
+pub mod clock;
+pub mod compat_data;
+pub mod direct_install;
+pub mod disk_space;
+pub mod environment;
+pub mod error_aggregation;
+pub mod extraction;
+pub mod feature_flags;
+pub mod file_watcher;
+pub mod filesystems;
 pub mod flavors;
+pub mod history;
 pub mod install;
+pub mod install_manifest;
+pub mod install_target;
+pub mod keepalive;
+pub mod local_changes;
+pub mod local_install;
+pub mod mapping_import;
+pub mod mappings;
+pub mod migration;
+pub mod mutation_guard;
+pub mod names;
+pub mod naming;
+pub mod network_usage;
+pub mod open_files;
+pub mod outbox;
+pub mod partial_update;
+pub mod permissions;
+pub mod plans;
+pub mod prefix_backup;
+pub mod prefix_scan;
+pub mod protocol;
+pub mod proton_tkg;
+pub mod provenance;
+pub mod proxy;
+pub mod quick_slots;
+pub mod reachability;
+pub mod refresh;
+pub mod requirements;
+pub mod resumable_download;
+pub mod rollback;
+pub mod running_games;
+pub mod session_token;
+pub mod settings;
+pub mod signature;
+pub mod skipped_releases;
+pub mod snapshot_diff;
+pub mod startup;
+pub mod state_delta;
+pub mod steam_overrides;
+pub mod steam_pickup;
+pub mod steam_tinker_launch;
+pub mod storage;
+pub mod subscriptions;
+pub mod tool_inspection;
+pub mod undo;
 pub mod uninstall;
+pub mod update_all;
+pub mod update_check;
+pub mod validation;
 pub mod r#virtual;
+pub mod written_by;
 
 pub fn generate_compatibility_tool_vdf(path: PathBuf, internal_name: &str, display_name: &str) {
     let mut file = File::create(path).expect("Failed to create file");
@@ -30,34 +101,71 @@ pub fn generate_compatibility_tool_vdf(path: PathBuf, internal_name: &str, displ
                 }}
               }}
             }}"#,
-        internal_name, display_name
+        escape_vdf(internal_name),
+        escape_vdf(display_name)
     )
     .expect("Failed to write to file");
 }
 
-fn copy_dir(source: &Path, destination: &Path) -> io::Result<()> {
+/// Copies a file and its modification time, keeping its permissions only if the destination
+/// filesystem can store them.
+fn copy_file(source: &Path, destination: &Path, unix_permissions: bool) -> io::Result<()> {
+    // Writing through a symlink left at the destination would change the file it points to
+    if fs::symlink_metadata(destination).is_ok_and(|metadata| metadata.is_symlink()) {
+        fs::remove_file(destination)?;
+    }
+    let metadata = fs::metadata(source)?;
+    if unix_permissions {
+        fs::copy(source, destination)?;
+        fs::set_permissions(destination, metadata.permissions())?;
+    } else {
+        io::copy(&mut File::open(source)?, &mut File::create(destination)?)?;
+    }
+    File::open(destination)?.set_modified(metadata.modified()?)
+}
+
+/// Copies a file, symlink or directory. Symlinks are recreated pointing where they did, relative
+/// or not, unless the destination filesystem can't store them and they're copied as what they
+/// point to instead.
+fn copy_entry(source: &Path, destination: &Path, unix_permissions: bool) -> io::Result<()> {
+    let file_type = fs::symlink_metadata(source)?.file_type();
+    if file_type.is_symlink() && unix_permissions {
+        if fs::symlink_metadata(destination).is_ok_and(|metadata| !metadata.is_dir()) {
+            fs::remove_file(destination)?;
+        }
+        std::os::unix::fs::symlink(fs::read_link(source)?, destination)
+    } else if source.is_dir() {
+        copy_dir(source, destination, unix_permissions)
+    } else {
+        copy_file(source, destination, unix_permissions)
+    }
+}
+
+fn copy_dir(source: &Path, destination: &Path, unix_permissions: bool) -> io::Result<()> {
     if !destination.exists() {
         fs::create_dir_all(destination)?;
     }
 
     for entry in fs::read_dir(source)? {
         let entry = entry?;
-        let entry_path = entry.path();
-        let file_name = entry_path.file_name().unwrap();
-        let destination_path = destination.join(file_name);
-
-        if entry_path.is_dir() {
-            copy_dir(&entry_path, &destination_path)?;
-        } else {
-            fs::copy(&entry_path, &destination_path)?;
-        }
+        copy_entry(
+            &entry.path(),
+            &destination.join(entry.file_name()),
+            unix_permissions,
+        )?;
     }
 
-    Ok(())
+    // Set once the entries are copied, copying them touches the directory
+    let metadata = fs::metadata(source)?;
+    if unix_permissions {
+        fs::set_permissions(destination, metadata.permissions())?;
+    }
+    File::open(destination)?.set_modified(metadata.modified()?)
 }
 
 fn recursive_delete_dir_entry(entry_path: &Path) -> io::Result<()> {
-    if entry_path.is_dir() {
+    // Symlinks are removed themselves, never what they point to
+    if fs::symlink_metadata(entry_path)?.is_dir() {
         for entry in fs::read_dir(entry_path)? {
             let entry = entry?;
             let path = entry.path();
@@ -71,15 +179,89 @@ fn recursive_delete_dir_entry(entry_path: &Path) -> io::Result<()> {
     Ok(())
 }
 
+/// Tasks run at once unless the settings say otherwise.
+const DEFAULT_CONCURRENT_TASKS: usize = 2;
+
+/// The release a task installs, tasks for the same release would write the same directories.
+pub type TaskTarget = (CompatibilityToolFlavor, String);
+
+pub fn task_target(task: &Task) -> Option<TaskTarget> {
+    let install = task.install.as_ref()?;
+    Some((install.flavor.clone(), install.release.tag_name.clone()))
+}
+
+/// Frees the target of a running task once it ends, even if it panicked.
+struct RunningTask {
+    running: Arc<Mutex<HashSet<TaskTarget>>>,
+    target: TaskTarget,
+}
+
+impl Drop for RunningTask {
+    fn drop(&mut self) {
+        self.running.lock().unwrap().remove(&self.target);
+    }
+}
+
 pub async fn process_queue(wine_cask: Arc<WineCask>, peer_map: PeerMap) {
-    wine_cask.check_for_flavor_updates(&peer_map, false).await;
+    wine_cask
+        .check_for_flavor_updates(&peer_map, CacheUse::Cached)
+        .await;
+    let running: Arc<Mutex<HashSet<TaskTarget>>> = Arc::default();
+    // Tasks deferred in a row, once every queued task was deferred wait for a change
+    let mut deferred = 0;
     loop {
-        match wine_cask.task_queue_pop_front().await {
+        // Why is this task queue here? Well because steam deck will die if someone tries to queue up 50 installs at once.
+        let concurrent_tasks = wine_cask
+            .app_state
+            .lock()
+            .await
+            .settings
+            .concurrent_tasks
+            .unwrap_or(DEFAULT_CONCURRENT_TASKS)
+            .max(1);
+        let busy = running.lock().unwrap().clone();
+        let task = if busy.len() < concurrent_tasks {
+            wine_cask.next_runnable_task(&busy).await
+        } else {
+            None
+        };
+        match task {
             Some(task) => {
                 if task.r#type == TaskType::InstallCompatibilityTool {
-                    wine_cask
-                        .install_compatibility_tool(task.install.unwrap(), &peer_map)
-                        .await;
+                    let (task_id, install) = (task.id, task.install.clone().unwrap());
+                    let mut environment_events = wine_cask.environment.lock().unwrap().subscribe();
+                    if let Admission::Defer(reason) = wine_cask.admit_task(install.background).await
+                    {
+                        let mut app_state = wine_cask.app_state.lock().await;
+                        app_state.task_queue.push_back(task);
+                        deferred += 1;
+                        if deferred >= app_state.task_queue.len() {
+                            drop(app_state);
+                            info!("Deferring {} background installs: {}", deferred, reason);
+                            deferred = 0;
+                            let _ = tokio::time::timeout(
+                                Duration::from_secs(60),
+                                environment_events.recv(),
+                            )
+                            .await;
+                        }
+                        continue;
+                    }
+                    deferred = 0;
+                    let target = task_target(&task).unwrap();
+                    running.lock().unwrap().insert(target.clone());
+                    let running_task = RunningTask {
+                        running: running.clone(),
+                        target,
+                    };
+                    let wine_cask = wine_cask.clone();
+                    let peer_map = peer_map.clone();
+                    tokio::spawn(async move {
+                        wine_cask
+                            .install_compatibility_tool(task_id, install, &peer_map)
+                            .await;
+                        drop(running_task);
+                    });
                 }
             }
             None => {
@@ -91,3 +273,68 @@ pub async fn process_queue(wine_cask: Arc<WineCask>, peer_map: PeerMap) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use std::time::SystemTime;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_copy_dir_preserves_symlinks_and_permissions() {
+        let temp_dir = tempdir().unwrap();
+        let source = temp_dir.path().join("GE-Proton9-21");
+        let bin = source.join("files/bin");
+        fs::create_dir_all(&bin).unwrap();
+        fs::write(bin.join("wine"), "#!/bin/sh\nexec wine64 \"$@\"\n").unwrap();
+        fs::set_permissions(bin.join("wine"), fs::Permissions::from_mode(0o755)).unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        File::open(bin.join("wine"))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        symlink("wine", bin.join("wine64")).unwrap();
+        symlink(bin.join("wine"), source.join("wine")).unwrap();
+
+        let destination = temp_dir.path().join("copy");
+        copy_dir(&source, &destination, true).unwrap();
+
+        let copied_bin = destination.join("files/bin");
+        let script = fs::symlink_metadata(copied_bin.join("wine")).unwrap();
+        assert!(script.is_file());
+        assert_eq!(script.permissions().mode() & 0o777, 0o755);
+        assert_eq!(script.modified().unwrap(), modified);
+        assert_eq!(
+            fs::read_link(copied_bin.join("wine64")).unwrap(),
+            PathBuf::from("wine")
+        );
+        // Absolute symlinks still point into the source, as they did
+        assert_eq!(
+            fs::read_link(destination.join("wine")).unwrap(),
+            bin.join("wine")
+        );
+
+        // Filesystems without symlinks get what they point to
+        let flat = temp_dir.path().join("flat");
+        copy_dir(&source, &flat, false).unwrap();
+        assert!(fs::symlink_metadata(flat.join("files/bin/wine64"))
+            .unwrap()
+            .is_file());
+    }
+
+    #[test]
+    fn test_deleting_a_symlink_keeps_what_it_points_to() {
+        let temp_dir = tempdir().unwrap();
+        let target = temp_dir.path().join("target");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("kept"), "").unwrap();
+        let tool = temp_dir.path().join("tool");
+        fs::create_dir_all(&tool).unwrap();
+        symlink(&target, tool.join("link")).unwrap();
+
+        recursive_delete_dir_entry(&tool).unwrap();
+        assert!(!tool.exists());
+        assert!(target.join("kept").exists());
+    }
+}
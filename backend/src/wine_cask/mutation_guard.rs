@@ -0,0 +1,365 @@
+use crate::runtime_directory;
+use crate::wine_cask::{copy_entry, recursive_delete_dir_entry};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+/// Intent record written to the journal before a file-mutating operation touches anything.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum MutationIntent {
+    /// Move `target` into `trash` and delete it from there, reconciliation finishes the deletion.
+    Delete { target: PathBuf, trash: PathBuf },
+    /// Copy new entries into a directory, reconciliation removes the `created` entries again.
+    Copy { created: Vec<PathBuf> },
+}
+
+/// Journal entry guarding a file-mutating operation.
+///
+/// The journal entry is only removed by `complete`, so an operation that gets cancelled or crashes
+/// leaves it behind for `reconcile` to finish or roll back on the next startup.
+pub struct MutationGuard {
+    journal_file: PathBuf,
+    intent: MutationIntent,
+}
+
+impl MutationGuard {
+    pub fn begin(journal_directory: &Path, intent: MutationIntent) -> io::Result<Self> {
+        fs::create_dir_all(journal_directory)?;
+        let journal_file = journal_directory.join(format!("{}.json", unique_suffix()));
+        let guard = MutationGuard {
+            journal_file,
+            intent,
+        };
+        guard.write()?;
+        Ok(guard)
+    }
+
+    /// Records a path that is about to be created by a `Copy` operation.
+    pub fn record_created(&mut self, path: PathBuf) -> io::Result<()> {
+        if let MutationIntent::Copy { created } = &mut self.intent {
+            created.push(path);
+        }
+        self.write()
+    }
+
+    pub fn complete(self) -> io::Result<()> {
+        fs::remove_file(&self.journal_file)
+    }
+
+    fn write(&self) -> io::Result<()> {
+        // Write to a temporary file first so a crash never leaves a half-written record
+        let temp_file = self.journal_file.with_extension("tmp");
+        fs::write(&temp_file, serde_json::to_string(&self.intent)?)?;
+        fs::rename(temp_file, &self.journal_file)
+    }
+}
+
+pub fn journal_directory() -> PathBuf {
+    runtime_directory().join("journal")
+}
+
+/// Deletes a directory by first moving it into a trash directory next to it.
+///
+/// If files in the trash are still held open the deletion is left to `reconcile` on the next
+/// startup, the directory is gone from its original location either way.
+pub fn delete_dir_guarded(journal_directory: &Path, target: &Path) -> io::Result<()> {
+    let guard = MutationGuard::begin(journal_directory, delete_intent(target)?)?;
+    if let MutationIntent::Delete { target, trash } = &guard.intent {
+        move_to_trash(target, trash)?;
+        if let Err(err) = recursive_delete_dir_entry(trash) {
+            warn!(
+                "Failed to delete {}, deleting it on the next startup: {}",
+                trash.display(),
+                err
+            );
+            return Ok(());
+        }
+    }
+    guard.complete()
+}
+
+/// Moves a directory into the trash right away and leaves deleting it to `reconcile` on the next
+/// startup, for directories whose files are still in use.
+pub fn trash_dir_guarded(journal_directory: &Path, target: &Path) -> io::Result<()> {
+    let guard = MutationGuard::begin(journal_directory, delete_intent(target)?)?;
+    if let MutationIntent::Delete { target, trash } = &guard.intent {
+        move_to_trash(target, trash)?;
+    }
+    // Intentionally not completed so the journal entry outlives this process
+    Ok(())
+}
+
+/// Moves a directory into the trash without deleting it, returning where it's kept. Nothing
+/// deletes it from there but a cleanup.
+pub fn keep_dir_in_trash(target: &Path) -> io::Result<PathBuf> {
+    let trash = trash_path(target)?;
+    move_to_trash(target, &trash)?;
+    Ok(trash)
+}
+
+/// Copies every entry of `source` into `destination`, rolling back newly created entries on failure.
+pub fn copy_dir_guarded(
+    journal_directory: &Path,
+    source: &Path,
+    destination: &Path,
+    unix_permissions: bool,
+) -> io::Result<()> {
+    let mut guard =
+        MutationGuard::begin(journal_directory, MutationIntent::Copy { created: vec![] })?;
+    fs::create_dir_all(destination)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let destination_path = destination.join(entry.file_name());
+        if fs::symlink_metadata(&destination_path).is_err() {
+            guard.record_created(destination_path.clone())?;
+        }
+        if let Err(err) = copy_entry(&entry.path(), &destination_path, unix_permissions) {
+            roll_back(&guard.intent);
+            guard.complete()?;
+            return Err(err);
+        }
+    }
+    guard.complete()
+}
+
+fn delete_intent(target: &Path) -> io::Result<MutationIntent> {
+    Ok(MutationIntent::Delete {
+        target: target.to_path_buf(),
+        trash: trash_path(target)?,
+    })
+}
+
+fn trash_path(target: &Path) -> io::Result<PathBuf> {
+    let parent = target.parent().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Cannot delete a root directory",
+        )
+    })?;
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // Keep the trash on the same filesystem so moving into it is a rename
+    Ok(parent
+        .join(".wine-cellar-trash")
+        .join(format!("{}-{}", name, unique_suffix())))
+}
+
+fn move_to_trash(target: &Path, trash: &Path) -> io::Result<()> {
+    if let Some(parent) = trash.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(target, trash)
+}
+
+fn roll_back(intent: &MutationIntent) {
+    if let MutationIntent::Copy { created } = intent {
+        for path in created.iter().rev() {
+            if path.exists() {
+                if let Err(err) = recursive_delete_dir_entry(path) {
+                    error!("Failed to roll back {}: {}", path.display(), err);
+                }
+            }
+        }
+    }
+}
+
+/// Finishes or rolls back every operation left in the journal by a cancellation or crash.
+pub fn reconcile(journal_directory: &Path) {
+    let Ok(entries) = fs::read_dir(journal_directory) else {
+        return;
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let journal_file = entry.path();
+        if journal_file.extension().unwrap_or_default() != "json" {
+            // Half-written record, the operation never started mutating
+            let _ = fs::remove_file(&journal_file);
+            continue;
+        }
+
+        let intent: Option<MutationIntent> = fs::read_to_string(&journal_file)
+            .ok()
+            .and_then(|string| serde_json::from_str(&string).ok());
+        match intent {
+            Some(MutationIntent::Delete { target, trash }) => {
+                if trash.exists() {
+                    info!("Finishing interrupted deletion of {}", target.display());
+                    if let Err(err) = recursive_delete_dir_entry(&trash) {
+                        // Files may still be held open, try again on the next startup
+                        error!("Failed to delete {}: {}", trash.display(), err);
+                        continue;
+                    }
+                } else {
+                    info!(
+                        "Interrupted deletion of {} never started, nothing to do",
+                        target.display()
+                    );
+                }
+            }
+            Some(intent @ MutationIntent::Copy { .. }) => {
+                info!("Rolling back interrupted copy");
+                roll_back(&intent);
+            }
+            None => warn!(
+                "Ignoring unreadable journal entry {}",
+                journal_file.display()
+            ),
+        }
+
+        if let Err(err) = fs::remove_file(&journal_file) {
+            error!("Failed to remove journal entry: {}", err);
+        }
+    }
+}
+
+fn unique_suffix() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Failed to calculate duration")
+        .as_nanos();
+    format!("{}-{}", nanos, std::process::id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn create_tool(path: &Path) {
+        fs::create_dir_all(path.join("files")).unwrap();
+        fs::write(path.join("compatibilitytool.vdf"), "").unwrap();
+        fs::write(path.join("files").join("proton"), "").unwrap();
+    }
+
+    // Simulates a crash by abandoning the operation without completing its guard
+    fn crash(_guard: MutationGuard) {}
+
+    fn journal_is_empty(journal_directory: &Path) -> bool {
+        fs::read_dir(journal_directory).unwrap().next().is_none()
+    }
+
+    #[test]
+    fn test_delete_completes() {
+        let temp_dir = tempdir().unwrap();
+        let journal = temp_dir.path().join("journal");
+        let target = temp_dir
+            .path()
+            .join("compatibilitytools.d")
+            .join("GE-Proton8-25");
+        create_tool(&target);
+
+        delete_dir_guarded(&journal, &target).unwrap();
+        assert!(!target.exists());
+        assert!(journal_is_empty(&journal));
+    }
+
+    #[test]
+    fn test_delete_crash_before_trash_keeps_target() {
+        let temp_dir = tempdir().unwrap();
+        let journal = temp_dir.path().join("journal");
+        let target = temp_dir
+            .path()
+            .join("compatibilitytools.d")
+            .join("GE-Proton8-25");
+        create_tool(&target);
+
+        // Crash right after the intent record was written
+        let guard = MutationGuard::begin(&journal, delete_intent(&target).unwrap()).unwrap();
+        crash(guard);
+
+        reconcile(&journal);
+        assert!(target.join("files").join("proton").exists());
+        assert!(journal_is_empty(&journal));
+    }
+
+    #[test]
+    fn test_delete_crash_after_trash_finishes_deletion() {
+        let temp_dir = tempdir().unwrap();
+        let journal = temp_dir.path().join("journal");
+        let target = temp_dir
+            .path()
+            .join("compatibilitytools.d")
+            .join("GE-Proton8-25");
+        create_tool(&target);
+
+        // Crash after the move into the trash, before the trash was emptied
+        let guard = MutationGuard::begin(&journal, delete_intent(&target).unwrap()).unwrap();
+        let MutationIntent::Delete { trash, .. } = guard.intent.clone() else {
+            unreachable!()
+        };
+        move_to_trash(&target, &trash).unwrap();
+        crash(guard);
+
+        reconcile(&journal);
+        assert!(!target.exists());
+        assert!(!trash.exists());
+        assert!(journal_is_empty(&journal));
+    }
+
+    #[test]
+    fn test_trash_defers_deletion_to_reconcile() {
+        let temp_dir = tempdir().unwrap();
+        let journal = temp_dir.path().join("journal");
+        let compatibility_tools = temp_dir.path().join("compatibilitytools.d");
+        let target = compatibility_tools.join("GE-Proton8-25");
+        create_tool(&target);
+
+        trash_dir_guarded(&journal, &target).unwrap();
+        assert!(!target.exists());
+        assert!(!journal_is_empty(&journal));
+
+        reconcile(&journal);
+        assert!(journal_is_empty(&journal));
+        assert!(fs::read_dir(compatibility_tools.join(".wine-cellar-trash"))
+            .unwrap()
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn test_copy_crash_rolls_back_created_entries() {
+        let temp_dir = tempdir().unwrap();
+        let journal = temp_dir.path().join("journal");
+        let destination = temp_dir.path().join("compatibilitytools.d");
+        let existing = destination.join("GE-Proton8-24");
+        let partial = destination.join("GE-Proton8-25");
+        create_tool(&existing);
+
+        // Crash halfway through copying the new tool
+        let mut guard =
+            MutationGuard::begin(&journal, MutationIntent::Copy { created: vec![] }).unwrap();
+        guard.record_created(partial.clone()).unwrap();
+        fs::create_dir_all(partial.join("files")).unwrap();
+        crash(guard);
+
+        reconcile(&journal);
+        assert!(!partial.exists());
+        assert!(existing.join("files").join("proton").exists());
+        assert!(journal_is_empty(&journal));
+    }
+
+    #[test]
+    fn test_copy_completes() {
+        let temp_dir = tempdir().unwrap();
+        let journal = temp_dir.path().join("journal");
+        let source = temp_dir.path().join("temp");
+        let destination = temp_dir.path().join("compatibilitytools.d");
+        create_tool(&source.join("GE-Proton8-25"));
+
+        copy_dir_guarded(&journal, &source, &destination, true).unwrap();
+        assert!(destination
+            .join("GE-Proton8-25")
+            .join("files")
+            .join("proton")
+            .exists());
+        assert!(journal_is_empty(&journal));
+
+        // Reconciling a clean journal must not touch completed copies
+        reconcile(&journal);
+        assert!(destination.join("GE-Proton8-25").exists());
+    }
+}
@@ -0,0 +1,314 @@
+use crate::wine_cask::app::{Request, RequestType, TaskType};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+const MAX_INTERNAL_NAME_LENGTH: usize = 64;
+const MAX_DISPLAY_NAME_LENGTH: usize = 128;
+const MAX_SHORTCUT_NAME_LENGTH: usize = 256;
+
+/// Which rules a name has to follow.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum NameKind {
+    /// Tool ids in VDF keys, directory names and Steam's mappings.
+    Internal,
+    /// Names shown in Steam, escaped when written to a VDF.
+    Display,
+    /// Non-Steam shortcut names, Steam only rejects control characters.
+    Shortcut,
+}
+
+impl Display for NameKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            NameKind::Internal => write!(f, "internal"),
+            NameKind::Display => write!(f, "display"),
+            NameKind::Shortcut => write!(f, "shortcut"),
+        }
+    }
+}
+
+impl NameKind {
+    fn max_length(self) -> usize {
+        match self {
+            NameKind::Internal => MAX_INTERNAL_NAME_LENGTH,
+            NameKind::Display => MAX_DISPLAY_NAME_LENGTH,
+            NameKind::Shortcut => MAX_SHORTCUT_NAME_LENGTH,
+        }
+    }
+
+    fn allows(self, c: char) -> bool {
+        match self {
+            NameKind::Internal => c.is_ascii_alphanumeric() || "._-+".contains(c),
+            NameKind::Display | NameKind::Shortcut => !c.is_control(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct InvalidName {
+    pub kind: NameKind,
+    pub name: String,
+    /// Distinct characters the rules don't allow, in the order they appear.
+    pub offending: Vec<char>,
+    pub too_long: bool,
+    pub suggestion: String,
+}
+
+impl Display for InvalidName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid_name: {} name {:?}", self.kind, self.name)?;
+        if !self.offending.is_empty() {
+            write!(f, " contains {:?}", self.offending)?;
+        }
+        if self.too_long {
+            write!(f, " is longer than {} characters", self.kind.max_length())?;
+        }
+        if self.name.trim().is_empty() {
+            write!(f, " is empty")?;
+        }
+        write!(f, ", try {:?}", self.suggestion)
+    }
+}
+
+/// Returns a name following the rules of `kind`, as close to `name` as possible.
+pub fn sanitize_name(kind: NameKind, name: &str) -> String {
+    let sanitized: String = match kind {
+        NameKind::Internal => name
+            .chars()
+            .filter_map(|c| match c {
+                c if kind.allows(c) => Some(c),
+                c if c.is_whitespace() => Some('-'),
+                _ => None,
+            })
+            .collect::<String>()
+            // Leading dots would make "." and ".." directory names possible
+            .trim_start_matches('.')
+            .to_string(),
+        NameKind::Display | NameKind::Shortcut => name
+            .chars()
+            .map(|c| if kind.allows(c) { c } else { ' ' })
+            .collect::<String>()
+            .trim()
+            .to_string(),
+    };
+    let sanitized: String = sanitized.chars().take(kind.max_length()).collect();
+    let sanitized = sanitized.trim_end().to_string();
+    if sanitized.is_empty() {
+        "unnamed".to_string()
+    } else {
+        sanitized
+    }
+}
+
+pub fn validate_name(kind: NameKind, name: &str) -> Result<(), InvalidName> {
+    let mut offending: Vec<char> = Vec::new();
+    for c in name.chars().filter(|c| !kind.allows(*c)) {
+        if !offending.contains(&c) {
+            offending.push(c);
+        }
+    }
+    let too_long = name.chars().count() > kind.max_length();
+    let blank = name.trim().is_empty();
+    let leading_dot = kind == NameKind::Internal && name.starts_with('.');
+    if offending.is_empty() && !too_long && !blank && !leading_dot && name.trim() == name {
+        return Ok(());
+    }
+    Err(InvalidName {
+        kind,
+        name: name.to_string(),
+        offending,
+        too_long,
+        suggestion: sanitize_name(kind, name),
+    })
+}
+
+/// Escapes a value for a quoted VDF string.
+pub fn escape_vdf(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Checks every name a request would create or change, before any handler runs.
+pub fn check_names(request: &Request) -> Result<(), InvalidName> {
+    if request.r#type == RequestType::SetQuickSlots {
+        return request
+            .quick_slots
+            .iter()
+            .flatten()
+            .try_for_each(|compatibility_tool| {
+                validate_name(NameKind::Internal, compatibility_tool)
+            });
+    }
+    if request.r#type == RequestType::CreateVirtualTool {
+        return match &request.virtual_tool {
+            Some(virtual_tool) => validate_name(NameKind::Display, &virtual_tool.name)
+                .and_then(|_| validate_name(NameKind::Internal, &virtual_tool.base_tool)),
+            None => Ok(()),
+        };
+    }
+    if request.r#type != RequestType::Task {
+        return Ok(());
+    }
+    let Some(task) = &request.task else {
+        return Ok(());
+    };
+    match task.r#type {
+        // Tags end up in directory, internal and display names
+        TaskType::InstallCompatibilityTool => match &task.install {
+            Some(install) => validate_name(NameKind::Internal, &install.release.tag_name),
+            None => Ok(()),
+        },
+        TaskType::InstallFromUrl => match task
+            .direct_install
+            .as_ref()
+            .and_then(|direct_install| direct_install.tag_name.as_ref())
+        {
+            Some(tag_name) => validate_name(NameKind::Internal, tag_name),
+            None => Ok(()),
+        },
+        TaskType::InstallFromLocalFile => match task
+            .local_file
+            .as_ref()
+            .and_then(|local_file| local_file.tag_name.as_ref())
+        {
+            Some(tag_name) => validate_name(NameKind::Internal, tag_name),
+            None => Ok(()),
+        },
+        TaskType::SetCompatibilityToolMapping => match task
+            .mapping
+            .as_ref()
+            .and_then(|mapping| mapping.compatibility_tool.as_ref())
+        {
+            Some(compatibility_tool) => validate_name(NameKind::Internal, compatibility_tool),
+            None => Ok(()),
+        },
+        TaskType::UninstallCompatibilityTool => match task
+            .uninstall
+            .as_ref()
+            .and_then(|uninstall| uninstall.replacement.as_ref())
+        {
+            Some(replacement) => validate_name(NameKind::Internal, replacement),
+            None => Ok(()),
+        },
+        TaskType::SetCompatibilityToolMappings => task
+            .mappings
+            .iter()
+            .flat_map(|mappings| &mappings.changes)
+            .filter_map(|change| change.compatibility_tool.as_ref())
+            .try_for_each(|compatibility_tool| {
+                validate_name(NameKind::Internal, compatibility_tool)
+            }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::steam_util::SteamUtil;
+    use crate::wine_cask::generate_compatibility_tool_vdf;
+    use std::fs;
+    use tempfile::tempdir;
+
+    /// Characters that break VDFs, paths or rendering, mixed with ordinary ones.
+    const ALPHABET: [char; 24] = [
+        'a', 'Z', '7', '-', '.', '_', '+', ' ', '\t', '\n', '\r', '\0', '{', '}', '"', '\\', '/',
+        'é', '日', '🍷', '\u{7f}', '\u{200b}', ':', '%',
+    ];
+
+    /// Deterministic xorshift, so failures can be reproduced.
+    fn random_names(count: usize) -> Vec<String> {
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        (0..count)
+            .map(|_| {
+                let length = (next() % 80) as usize;
+                (0..length)
+                    .map(|_| ALPHABET[(next() % ALPHABET.len() as u64) as usize])
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_offending_characters_are_listed() {
+        assert_eq!(validate_name(NameKind::Internal, "GE-Proton9-20"), Ok(()));
+        assert_eq!(
+            validate_name(NameKind::Internal, "proton_experimental"),
+            Ok(())
+        );
+        assert_eq!(
+            validate_name(NameKind::Display, "Proton 9.0 (Beta) \"é\""),
+            Ok(())
+        );
+
+        let invalid = validate_name(NameKind::Internal, "GE Proton}\t9").unwrap_err();
+        assert_eq!(invalid.offending, vec![' ', '}', '\t']);
+        assert_eq!(invalid.suggestion, "GE-Proton-9");
+        assert_eq!(
+            invalid.to_string(),
+            r#"invalid_name: internal name "GE Proton}\t9" contains [' ', '}', '\t'], try "GE-Proton-9""#
+        );
+
+        let invalid = validate_name(NameKind::Display, "My\tTool").unwrap_err();
+        assert_eq!(invalid.offending, vec!['\t']);
+        assert_eq!(invalid.suggestion, "My Tool");
+
+        assert!(validate_name(NameKind::Internal, "..").is_err());
+        assert!(validate_name(NameKind::Display, "   ").is_err());
+        assert!(
+            validate_name(NameKind::Shortcut, &"a".repeat(300))
+                .unwrap_err()
+                .too_long
+        );
+    }
+
+    #[test]
+    fn test_sanitized_names_are_valid() {
+        for name in random_names(2000) {
+            for kind in [NameKind::Internal, NameKind::Display, NameKind::Shortcut] {
+                let sanitized = sanitize_name(kind, &name);
+                assert_eq!(validate_name(kind, &sanitized), Ok(()), "{:?}", name);
+                // Valid names are left alone
+                assert_eq!(sanitize_name(kind, &sanitized), sanitized);
+            }
+        }
+    }
+
+    #[test]
+    fn test_names_round_trip_through_generated_vdfs() {
+        let steam_directory = tempdir().unwrap();
+        let steam_util = SteamUtil::new(steam_directory.path().to_path_buf());
+        for (index, name) in random_names(300).iter().enumerate() {
+            let internal_name = sanitize_name(NameKind::Internal, name);
+            let display_name = sanitize_name(NameKind::Display, name);
+            let tool = steam_directory.path().join(index.to_string());
+            fs::create_dir_all(&tool).unwrap();
+            let vdf = tool.join("compatibilitytool.vdf");
+            generate_compatibility_tool_vdf(vdf.clone(), &internal_name, &display_name);
+
+            let compatibility_tool = steam_util
+                .read_compatibility_tools_from_vdf_path(&vdf)
+                .unwrap()
+                .remove(0);
+            assert_eq!(compatibility_tool.internal_name, internal_name);
+            assert_eq!(compatibility_tool.display_name, display_name, "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use crate::github_util::Release;
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::flavors::CompatibilityToolFlavor;
+use crate::wine_cask::provenance::Provenance;
+use crate::PeerMap;
+use log::{error, info};
+
+/// A way a flavor names its releases and the tools they install.
+pub struct NamingScheme {
+    /// Recorded in provenance manifests.
+    pub name: &'static str,
+    /// Release tags, every `{}` stands for a number.
+    pub tag: &'static str,
+    /// Internal or display names of installed tools, same placeholders as `tag`.
+    pub tools: &'static [&'static str],
+    /// Publish date of the first release using this scheme, it stays in effect until the next
+    /// scheme's. Empty for the first scheme.
+    pub since: &'static str,
+}
+
+/// Oldest first, tags of both schemes are tried regardless of their publish date.
+const PROTON_GE_SCHEMES: [NamingScheme; 2] = [
+    NamingScheme {
+        name: "proton-ge-legacy",
+        tag: "{}.{}-GE-{}",
+        tools: &["Proton-{}.{}-GE-{}", "{}.{}-GE-{}"],
+        since: "",
+    },
+    NamingScheme {
+        name: "ge-proton",
+        tag: "GE-Proton{}-{}",
+        tools: &["GE-Proton{}-{}"],
+        since: "2022-02-01T00:00:00Z",
+    },
+];
+
+pub fn naming_schemes(flavor: &CompatibilityToolFlavor) -> &'static [NamingScheme] {
+    match flavor {
+        CompatibilityToolFlavor::ProtonGE => &PROTON_GE_SCHEMES,
+        // Only matched by their exact names so far
+        _ => &[],
+    }
+}
+
+/// Returns the numbers in `name` if it follows `template`.
+fn parse_template(template: &str, name: &str) -> Option<Vec<u32>> {
+    let mut literals = template.split("{}");
+    let mut rest = name.strip_prefix(literals.next()?)?;
+    let mut numbers = Vec::new();
+    for literal in literals {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return None;
+        }
+        numbers.push(rest[..digits].parse().ok()?);
+        rest = rest[digits..].strip_prefix(literal)?;
+    }
+    rest.is_empty().then_some(numbers)
+}
+
+/// Names other managers like ProtonUp-Qt install GE builds under, with the flavor and release tag
+/// each stands for. The tag gets the numbers of the name in the same order.
+const ADOPTABLE_NAMES: [(&str, CompatibilityToolFlavor, &str); 5] = [
+    (
+        "GE-Proton{}-{}",
+        CompatibilityToolFlavor::ProtonGE,
+        "GE-Proton{}-{}",
+    ),
+    (
+        "Proton-{}.{}-GE-{}",
+        CompatibilityToolFlavor::ProtonGE,
+        "{}.{}-GE-{}",
+    ),
+    (
+        "Proton-{}.{}rc{}-GE-{}",
+        CompatibilityToolFlavor::ProtonGE,
+        "{}.{}rc{}-GE-{}",
+    ),
+    (
+        "{}.{}-GE-{}",
+        CompatibilityToolFlavor::ProtonGE,
+        "{}.{}-GE-{}",
+    ),
+    (
+        "lutris-GE-Proton{}-{}-x86_64",
+        CompatibilityToolFlavor::WineGE,
+        "GE-Proton{}-{}",
+    ),
+];
+
+/// Puts `numbers` in place of the placeholders of `template`.
+fn fill_template(template: &str, numbers: &[u32]) -> String {
+    let mut filled = String::new();
+    for (index, literal) in template.split("{}").enumerate() {
+        if index > 0 {
+            filled.push_str(&numbers[index - 1].to_string());
+        }
+        filled.push_str(literal);
+    }
+    filled
+}
+
+/// The flavor and release tag of a GE build installed under `name` by another manager, `None`
+/// if the name isn't one GE builds are installed under.
+pub fn adoptable_release(name: &str) -> Option<(CompatibilityToolFlavor, String)> {
+    ADOPTABLE_NAMES.iter().find_map(|(template, flavor, tag)| {
+        let numbers = parse_template(template, name)?;
+        Some((flavor.clone(), fill_template(tag, &numbers)))
+    })
+}
+
+/// Returns the scheme a release uses along with its version, the scheme in effect when it was
+/// published is tried first.
+pub fn release_version(
+    schemes: &'static [NamingScheme],
+    release: &Release,
+) -> Option<(&'static NamingScheme, Vec<u32>)> {
+    let effective = schemes
+        .iter()
+        .rposition(|scheme| scheme.since <= release.published_at.as_str());
+    effective
+        .map(|index| &schemes[index])
+        .into_iter()
+        .chain(schemes.iter().rev())
+        .find_map(|scheme| Some((scheme, parse_template(scheme.tag, &release.tag_name)?)))
+}
+
+/// Whether `name` is the tag of `release` or its version, e.g. `9-20` for `GE-Proton9-20`.
+pub fn release_matches(flavor: &CompatibilityToolFlavor, name: &str, release: &Release) -> bool {
+    if release.tag_name.eq_ignore_ascii_case(name) {
+        return true;
+    }
+    let numbers: Result<Vec<u32>, _> = name
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|number| !number.is_empty())
+        .map(str::parse)
+        .collect();
+    numbers.is_ok_and(|numbers| {
+        !numbers.is_empty()
+            && release_version(naming_schemes(flavor), release)
+                .is_some_and(|(_, version)| version == numbers)
+    })
+}
+
+pub fn tool_version(
+    schemes: &[NamingScheme],
+    internal_name: &str,
+    display_name: &str,
+) -> Option<Vec<u32>> {
+    schemes.iter().rev().find_map(|scheme| {
+        scheme.tools.iter().find_map(|template| {
+            parse_template(template, internal_name)
+                .or_else(|| parse_template(template, display_name))
+        })
+    })
+}
+
+/// Whether an installed tool was built from `release`, regardless of the naming scheme either
+/// of them uses.
+pub fn tool_matches_release(
+    schemes: &'static [NamingScheme],
+    flavor: &CompatibilityToolFlavor,
+    internal_name: &str,
+    display_name: &str,
+    release: &Release,
+) -> bool {
+    let exact_match = if *flavor == CompatibilityToolFlavor::ProtonGE {
+        internal_name == release.tag_name || display_name == release.tag_name
+    } else {
+        display_name == flavor.to_string() + " " + &release.tag_name
+            || internal_name == flavor.to_string() + &release.tag_name
+    };
+    exact_match
+        || match (
+            tool_version(schemes, internal_name, display_name),
+            release_version(schemes, release),
+        ) {
+            (Some(tool_version), Some((_, release_version))) => tool_version == release_version,
+            _ => false,
+        }
+}
+
+/// Pairs an installed tool with its release in a freshly fetched list. Returns the updated
+/// provenance if the flavor, tag or naming scheme changed, and whether the tool was re-assigned
+/// rather than only gaining the scheme older versions didn't record.
+pub fn reassign_provenance(
+    provenance: &Provenance,
+    display_name: &str,
+    flavor: &CompatibilityToolFlavor,
+    schemes: &'static [NamingScheme],
+    releases: &[Release],
+) -> Option<(Provenance, bool)> {
+    let release = releases.iter().find(|release| {
+        tool_matches_release(
+            schemes,
+            flavor,
+            &provenance.internal_name,
+            display_name,
+            release,
+        )
+    })?;
+    let naming_scheme =
+        release_version(schemes, release).map(|(scheme, _)| scheme.name.to_string());
+    let reassigned = provenance.flavor != *flavor || provenance.tag_name != release.tag_name;
+    if !reassigned && (provenance.naming_scheme == naming_scheme || naming_scheme.is_none()) {
+        return None;
+    }
+    Some((
+        Provenance {
+            flavor: flavor.clone(),
+            tag_name: release.tag_name.clone(),
+            naming_scheme,
+            ..provenance.clone()
+        },
+        reassigned,
+    ))
+}
+
+impl WineCask {
+    /// Updates provenance manifests after a flavor re-tagged its releases under a new scheme.
+    pub async fn reassign_naming_schemes(&self, peer_map: &PeerMap) {
+        let app_state = self.app_state.lock().await;
+        let flavors = app_state.flavors.clone();
+        let installed_compatibility_tools = app_state.installed_compatibility_tools.clone();
+        drop(app_state);
+
+        let mut reassigned_tools = Vec::new();
+        for tool in &installed_compatibility_tools {
+            let Some(provenance) = Provenance::load(&tool.internal_name) else {
+                continue;
+            };
+            let Some((updated, reassigned)) = flavors.iter().find_map(|flavor| {
+                reassign_provenance(
+                    &provenance,
+                    &tool.display_name,
+                    &flavor.flavor,
+                    naming_schemes(&flavor.flavor),
+                    &flavor.releases,
+                )
+            }) else {
+                continue;
+            };
+            if let Err(err) = updated.save() {
+                error!(
+                    "Failed to update provenance of {}: {}",
+                    tool.internal_name, err
+                );
+                continue;
+            }
+            if reassigned {
+                info!(
+                    "Re-assigned {} from {} {} to {} {}",
+                    tool.internal_name,
+                    provenance.flavor,
+                    provenance.tag_name,
+                    updated.flavor,
+                    updated.tag_name
+                );
+                reassigned_tools.push(format!("{} ({})", tool.display_name, updated.tag_name));
+            }
+        }
+
+        if !reassigned_tools.is_empty() {
+            let message = format!(
+                "Releases were renamed upstream, re-assigned {}",
+                reassigned_tools.join(", ")
+            );
+            self.broadcast_notification(peer_map, &message).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{published_release, release};
+    use crate::wine_cask::provenance::ProvenanceSource;
+
+    /// GE-Proton switching to a hypothetical third scheme.
+    const RENAMED_SCHEMES: [NamingScheme; 2] = [
+        NamingScheme {
+            name: "ge-proton",
+            tag: "GE-Proton{}-{}",
+            tools: &["GE-Proton{}-{}"],
+            since: "",
+        },
+        NamingScheme {
+            name: "proton-ge",
+            tag: "Proton-GE-{}.{}",
+            tools: &["Proton-GE-{}.{}"],
+            since: "2025-01-01T00:00:00Z",
+        },
+    ];
+
+    fn provenance(internal_name: &str, naming_scheme: Option<&str>) -> Provenance {
+        Provenance {
+            internal_name: internal_name.to_string(),
+            flavor: CompatibilityToolFlavor::ProtonGE,
+            tag_name: internal_name.to_string(),
+            naming_scheme: naming_scheme.map(str::to_string),
+            installed_at: 0,
+            source: ProvenanceSource {
+                asset_url: String::new(),
+                asset_name: format!("{}.tar.gz", internal_name),
+                size: 0,
+                checksum: None,
+                uploaded_at: String::new(),
+                target_commitish: String::new(),
+            },
+            files: None,
+        }
+    }
+
+    #[test]
+    fn test_releases_are_matched_by_tag_or_version() {
+        let proton_ge = CompatibilityToolFlavor::ProtonGE;
+        let release = release("GE-Proton9-20");
+        assert!(release_matches(&proton_ge, "9-20", &release));
+        assert!(release_matches(&proton_ge, "ge-proton9-20", &release));
+        assert!(!release_matches(&proton_ge, "9-2", &release));
+        assert!(!release_matches(&proton_ge, "latest", &release));
+    }
+
+    #[test]
+    fn test_templates_are_parsed() {
+        assert_eq!(
+            parse_template("GE-Proton{}-{}", "GE-Proton9-20"),
+            Some(vec![9, 20])
+        );
+        assert_eq!(
+            parse_template("{}.{}-GE-{}", "6.21-GE-2"),
+            Some(vec![6, 21, 2])
+        );
+        assert_eq!(parse_template("GE-Proton{}-{}", "GE-Proton9-20-rtsp"), None);
+        assert_eq!(parse_template("GE-Proton{}-{}", "GE-Proton-20"), None);
+        assert_eq!(parse_template("GE-Proton{}-{}", "Proton-9.20"), None);
+    }
+
+    #[test]
+    fn test_historical_schemes_are_matched() {
+        let flavor = CompatibilityToolFlavor::ProtonGE;
+        let schemes = naming_schemes(&flavor);
+        let legacy = published_release("6.21-GE-2", "2021-11-20T00:00:00Z");
+        assert!(tool_matches_release(
+            schemes,
+            &flavor,
+            "Proton-6.21-GE-2",
+            "Proton-6.21-GE-2",
+            &legacy
+        ));
+        assert_eq!(
+            release_version(schemes, &legacy).unwrap().0.name,
+            "proton-ge-legacy"
+        );
+        let current = published_release("GE-Proton9-20", "2024-11-18T00:00:00Z");
+        assert!(tool_matches_release(
+            schemes,
+            &flavor,
+            "GE-Proton9-20",
+            "GE-Proton9-20",
+            &current
+        ));
+        assert!(!tool_matches_release(
+            schemes,
+            &flavor,
+            "Proton-6.21-GE-2",
+            "Proton-6.21-GE-2",
+            &current
+        ));
+    }
+
+    #[test]
+    fn test_renamed_releases_still_pair_with_installed_tools() {
+        let flavor = CompatibilityToolFlavor::ProtonGE;
+        // Upstream re-tagged everything, installed tools keep their old names
+        let releases = vec![
+            published_release("Proton-GE-9.21", "2025-02-01T00:00:00Z"),
+            published_release("Proton-GE-9.20", "2025-01-15T00:00:00Z"),
+        ];
+        let installed = provenance("GE-Proton9-20", Some("ge-proton"));
+
+        let paired: Vec<&Release> = releases
+            .iter()
+            .filter(|release| {
+                tool_matches_release(
+                    &RENAMED_SCHEMES,
+                    &flavor,
+                    "GE-Proton9-20",
+                    "GE-Proton9-20",
+                    release,
+                )
+            })
+            .collect();
+        assert_eq!(paired.len(), 1);
+        assert_eq!(paired[0].tag_name, "Proton-GE-9.20");
+
+        let (updated, reassigned) = reassign_provenance(
+            &installed,
+            "GE-Proton9-20",
+            &flavor,
+            &RENAMED_SCHEMES,
+            &releases,
+        )
+        .unwrap();
+        assert!(reassigned);
+        assert_eq!(updated.tag_name, "Proton-GE-9.20");
+        assert_eq!(updated.naming_scheme.as_deref(), Some("proton-ge"));
+        assert_eq!(updated.internal_name, "GE-Proton9-20");
+
+        // Nothing left to migrate the next time releases are fetched
+        assert!(reassign_provenance(
+            &updated,
+            "GE-Proton9-20",
+            &flavor,
+            &RENAMED_SCHEMES,
+            &releases
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_missing_schemes_are_filled_in_quietly() {
+        let flavor = CompatibilityToolFlavor::ProtonGE;
+        let releases = vec![published_release("GE-Proton9-20", "2024-11-18T00:00:00Z")];
+        let (updated, reassigned) = reassign_provenance(
+            &provenance("GE-Proton9-20", None),
+            "GE-Proton9-20",
+            &flavor,
+            naming_schemes(&flavor),
+            &releases,
+        )
+        .unwrap();
+        assert!(!reassigned);
+        assert_eq!(updated.naming_scheme.as_deref(), Some("ge-proton"));
+    }
+
+    #[test]
+    fn test_releases_are_inferred_from_names_of_other_managers() {
+        let proton_ge = |tag: &str| Some((CompatibilityToolFlavor::ProtonGE, tag.to_string()));
+        assert_eq!(
+            adoptable_release("GE-Proton9-20"),
+            proton_ge("GE-Proton9-20")
+        );
+        assert_eq!(
+            adoptable_release("Proton-7.0rc3-GE-1"),
+            proton_ge("7.0rc3-GE-1")
+        );
+        assert_eq!(
+            adoptable_release("Proton-6.21-GE-2"),
+            proton_ge("6.21-GE-2")
+        );
+        assert_eq!(
+            adoptable_release("lutris-GE-Proton8-26-x86_64"),
+            Some((CompatibilityToolFlavor::WineGE, "GE-Proton8-26".to_string()))
+        );
+        // Close isn't good enough, a wrong guess would update to another build
+        assert_eq!(adoptable_release("GE-Proton9-20-rtsp"), None);
+        assert_eq!(adoptable_release("Proton-7.0-GE"), None);
+        assert_eq!(adoptable_release("proton_experimental"), None);
+    }
+}
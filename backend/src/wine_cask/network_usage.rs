@@ -0,0 +1,199 @@
+use crate::runtime_directory;
+use crate::wine_cask::app::WineCask;
+use chrono::{Datelike, Local};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+/// Kind of traffic the plugin initiated, only asset downloads count towards the monthly cap.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum NetworkTraffic {
+    Metadata,
+    Asset,
+}
+
+/// Bytes downloaded by the plugin during the current calendar month.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+#[serde(default)]
+pub struct NetworkUsage {
+    /// Calendar month the counters belong to, formatted as `YYYY-MM`.
+    pub month: String,
+    pub metadata_bytes: u64,
+    pub asset_bytes: u64,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum NetworkPreflight {
+    Allowed,
+    /// The download is allowed but will go over the monthly cap.
+    Warning(String),
+    /// The monthly cap has already been reached.
+    Blocked(String),
+}
+
+impl NetworkUsage {
+    pub fn load(today: &impl Datelike) -> NetworkUsage {
+        let usage_file = network_usage_file();
+        let mut network_usage = if usage_file.exists() && usage_file.is_file() {
+            fs::read_to_string(&usage_file)
+                .ok()
+                .and_then(|string| serde_json::from_str(&string).ok())
+                .unwrap_or_else(|| {
+                    warn!("Network usage file is corrupted, resetting counters");
+                    NetworkUsage::default()
+                })
+        } else {
+            NetworkUsage::default()
+        };
+        network_usage.roll_over(today);
+        network_usage
+    }
+
+    pub fn save(&self) {
+        let json = serde_json::to_string(self).unwrap();
+        if let Err(err) = fs::write(network_usage_file(), json) {
+            error!("Failed to persist network usage: {}", err);
+        }
+    }
+
+    /// Resets the counters when `today` is in a different calendar month, returns whether it did.
+    pub fn roll_over(&mut self, today: &impl Datelike) -> bool {
+        let month = month_key(today);
+        if self.month != month {
+            *self = NetworkUsage {
+                month,
+                metadata_bytes: 0,
+                asset_bytes: 0,
+            };
+            return true;
+        }
+        false
+    }
+
+    pub fn record(&mut self, traffic: NetworkTraffic, bytes: u64, today: &impl Datelike) {
+        self.roll_over(today);
+        match traffic {
+            NetworkTraffic::Metadata => self.metadata_bytes += bytes,
+            NetworkTraffic::Asset => self.asset_bytes += bytes,
+        }
+    }
+
+    pub fn preflight(
+        &mut self,
+        download_size: u64,
+        monthly_cap: Option<u64>,
+        today: &impl Datelike,
+    ) -> NetworkPreflight {
+        self.roll_over(today);
+        let Some(monthly_cap) = monthly_cap else {
+            return NetworkPreflight::Allowed;
+        };
+
+        if self.asset_bytes >= monthly_cap {
+            NetworkPreflight::Blocked(format!(
+                "Monthly download cap reached: {} of {} used",
+                format_bytes(self.asset_bytes),
+                format_bytes(monthly_cap)
+            ))
+        } else if self.asset_bytes + download_size > monthly_cap {
+            NetworkPreflight::Warning(format!(
+                "Download of {} will exceed the monthly download cap: {} of {} used",
+                format_bytes(download_size),
+                format_bytes(self.asset_bytes),
+                format_bytes(monthly_cap)
+            ))
+        } else {
+            NetworkPreflight::Allowed
+        }
+    }
+}
+
+impl WineCask {
+    pub async fn record_network_usage(&self, traffic: NetworkTraffic, bytes: u64) {
+        let mut app_state = self.app_state.lock().await;
+        app_state
+            .network_usage
+            .record(traffic, bytes, &Local::now());
+        app_state.network_usage.save();
+    }
+}
+
+fn month_key(today: &impl Datelike) -> String {
+    format!("{:04}-{:02}", today.year(), today.month())
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+fn network_usage_file() -> PathBuf {
+    runtime_directory().join("network_usage.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_roll_over_at_month_boundary() {
+        let mut network_usage = NetworkUsage::default();
+        network_usage.record(NetworkTraffic::Asset, 10 * MIB, &date(2023, 12, 31));
+        network_usage.record(NetworkTraffic::Metadata, MIB, &date(2023, 12, 31));
+        assert_eq!(network_usage.month, "2023-12");
+        assert_eq!(network_usage.asset_bytes, 10 * MIB);
+
+        assert!(!network_usage.roll_over(&date(2023, 12, 1)));
+        assert_eq!(network_usage.asset_bytes, 10 * MIB);
+
+        network_usage.record(NetworkTraffic::Asset, 5 * MIB, &date(2024, 1, 1));
+        assert_eq!(network_usage.month, "2024-01");
+        assert_eq!(network_usage.asset_bytes, 5 * MIB);
+        assert_eq!(network_usage.metadata_bytes, 0);
+    }
+
+    #[test]
+    fn test_metadata_does_not_count_towards_cap() {
+        let mut network_usage = NetworkUsage::default();
+        network_usage.record(NetworkTraffic::Metadata, 100 * MIB, &date(2024, 3, 10));
+        assert_eq!(
+            network_usage.preflight(10 * MIB, Some(50 * MIB), &date(2024, 3, 10)),
+            NetworkPreflight::Allowed
+        );
+    }
+
+    #[test]
+    fn test_preflight_warns_then_blocks() {
+        let mut network_usage = NetworkUsage::default();
+        let today = date(2024, 3, 10);
+        assert_eq!(
+            network_usage.preflight(400 * MIB, None, &today),
+            NetworkPreflight::Allowed
+        );
+
+        network_usage.record(NetworkTraffic::Asset, 300 * MIB, &today);
+        assert!(matches!(
+            network_usage.preflight(400 * MIB, Some(500 * MIB), &today),
+            NetworkPreflight::Warning(_)
+        ));
+
+        network_usage.record(NetworkTraffic::Asset, 400 * MIB, &today);
+        assert!(matches!(
+            network_usage.preflight(400 * MIB, Some(500 * MIB), &today),
+            NetworkPreflight::Blocked(_)
+        ));
+
+        // A new month lifts the block
+        assert_eq!(
+            network_usage.preflight(400 * MIB, Some(500 * MIB), &date(2024, 4, 1)),
+            NetworkPreflight::Allowed
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::{fmt, fs};
+
+/// Processes scanned at most, the scan is best effort and must not stall on busy systems.
+const MAX_PROCESSES: usize = 4096;
+/// Open file descriptors checked at most per process.
+const MAX_FDS_PER_PROCESS: usize = 4096;
+
+/// Process holding a file open or mapped below a directory.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ProcessUsage {
+    pub pid: u32,
+    pub comm: String,
+}
+
+impl Display for ProcessUsage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.pid, self.comm)
+    }
+}
+
+/// Finds processes with an open file descriptor or memory mapping below `directory`.
+///
+/// Processes that can't be inspected are skipped, so an empty result doesn't guarantee the
+/// directory is unused.
+pub fn find_processes_using(proc_root: &Path, directory: &Path) -> Vec<ProcessUsage> {
+    let directory = directory
+        .canonicalize()
+        .unwrap_or_else(|_| directory.to_path_buf());
+    let Ok(entries) = fs::read_dir(proc_root) else {
+        return Vec::new();
+    };
+
+    let mut processes: Vec<ProcessUsage> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let pid: u32 = entry.file_name().to_string_lossy().parse().ok()?;
+            Some((pid, entry.path()))
+        })
+        .take(MAX_PROCESSES)
+        .filter(|(_, process)| {
+            maps_reference(process, &directory) || fds_reference(process, &directory)
+        })
+        .map(|(pid, process)| ProcessUsage {
+            pid,
+            comm: fs::read_to_string(process.join("comm"))
+                .map(|comm| comm.trim().to_string())
+                .unwrap_or_default(),
+        })
+        .collect();
+    processes.sort_by_key(|process| process.pid);
+    processes
+}
+
+fn maps_reference(process: &Path, directory: &Path) -> bool {
+    let Ok(maps) = fs::read_to_string(process.join("maps")) else {
+        return false;
+    };
+    // The path is the sixth column and may itself contain spaces
+    maps.lines()
+        .filter_map(|line| line.splitn(6, char::is_whitespace).nth(5))
+        .map(|path| path.trim().trim_end_matches(" (deleted)"))
+        .any(|path| path.starts_with('/') && Path::new(path).starts_with(directory))
+}
+
+fn fds_reference(process: &Path, directory: &Path) -> bool {
+    let Ok(fds) = fs::read_dir(process.join("fd")) else {
+        return false;
+    };
+    fds.filter_map(Result::ok)
+        .take(MAX_FDS_PER_PROCESS)
+        .filter_map(|fd| fs::read_link(fd.path()).ok())
+        .any(|target: PathBuf| target.starts_with(directory))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_detects_file_held_open_by_this_process() {
+        let tool_dir = tempdir().unwrap();
+        let proton = tool_dir.path().join("proton");
+        fs::write(&proton, "#!/usr/bin/env python3").unwrap();
+        let other_dir = tempdir().unwrap();
+
+        let held = File::open(&proton).unwrap();
+        let processes = find_processes_using(Path::new("/proc"), tool_dir.path());
+        assert!(processes
+            .iter()
+            .any(|process| process.pid == std::process::id()));
+        assert!(!find_processes_using(Path::new("/proc"), other_dir.path())
+            .iter()
+            .any(|process| process.pid == std::process::id()));
+
+        drop(held);
+        assert!(!find_processes_using(Path::new("/proc"), tool_dir.path())
+            .iter()
+            .any(|process| process.pid == std::process::id()));
+    }
+
+    #[test]
+    fn test_detects_mapped_files() {
+        let proc_root = tempdir().unwrap();
+        let process = proc_root.path().join("4242");
+        fs::create_dir_all(&process).unwrap();
+        fs::write(process.join("comm"), "wine64-preload\n").unwrap();
+        fs::write(
+            process.join("maps"),
+            "7f0000000000-7f0000001000 r-xp 00000000 103:05 1234   /home/deck/.steam/root/compatibilitytools.d/GE Proton/files/lib64/wine/x86_64-unix/ntdll.so (deleted)\n\
+             7f0000002000-7f0000003000 rw-p 00000000 00:00 0 \n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            find_processes_using(
+                proc_root.path(),
+                Path::new("/home/deck/.steam/root/compatibilitytools.d/GE Proton")
+            ),
+            vec![ProcessUsage {
+                pid: 4242,
+                comm: "wine64-preload".to_string()
+            }]
+        );
+        assert!(find_processes_using(
+            proc_root.path(),
+            Path::new("/home/deck/.steam/root/compatibilitytools.d/GE")
+        )
+        .is_empty());
+    }
+}
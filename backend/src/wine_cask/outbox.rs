@@ -0,0 +1,392 @@
+use crate::wine_cask::app::{Request, RequestType};
+use crate::{PeerAddr, PeerMap};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Messages queued for a peer before older ones are dropped.
+pub const OUTBOX_CAPACITY: usize = 256;
+/// How long a critical message waits for room before its peer is evicted.
+pub const CRITICAL_WAIT: Duration = Duration::from_secs(2);
+
+/// How a message is treated once the outbox of its peer is full.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum MessageKind {
+    /// Makes room by dropping the oldest coalescable message.
+    Coalescable,
+    /// Replaces a queued message of the same type, only the latest one matters.
+    Snapshot,
+    /// Waits for room, the peer is evicted if it doesn't make any.
+    Critical,
+}
+
+pub fn message_kind(r#type: &RequestType) -> MessageKind {
+    match r#type {
+        RequestType::UpdateState
+        | RequestType::Activity
+        | RequestType::StorageBreakdown
+        | RequestType::PrefixSizes
+        | RequestType::InstalledApps
+        | RequestType::UndoStack
+        | RequestType::MutationLog
+        | RequestType::History => MessageKind::Snapshot,
+        // Peers wait on these to finish what they started
+        RequestType::Permissions
+        | RequestType::ValidationError
+        | RequestType::RefreshCompleted
+        | RequestType::PrefixScanCompleted
+        | RequestType::PlanExecuted
+        | RequestType::InspectionCompleted
+        | RequestType::TaskCancelled
+        | RequestType::UpdateSummary
+        | RequestType::UninstallBlocked
+        | RequestType::InsufficientDiskSpace
+        | RequestType::UpdatesAvailable
+        | RequestType::ShortcutDetails
+        | RequestType::Error
+        | RequestType::Subscribed
+        | RequestType::Hello => MessageKind::Critical,
+        RequestType::RequestState
+        | RequestType::Notification
+        // Every stage is shown as it completes
+        | RequestType::StartupProgress
+        | RequestType::Task
+        | RequestType::UpdateSettings
+        | RequestType::GetToolProvenance
+        | RequestType::ToolProvenance
+        | RequestType::VerifyInstalledTool
+        | RequestType::Verification
+        | RequestType::GetActivity
+        | RequestType::GetStorageBreakdown
+        | RequestType::ClearShaderCache
+        | RequestType::GetUndoStack
+        | RequestType::UndoLast
+        | RequestType::RollbackUpdate
+        | RequestType::Refresh
+        | RequestType::GetMutationLog
+        | RequestType::SkipRelease
+        | RequestType::UnskipRelease
+        | RequestType::PrioritizePrefixes
+        | RequestType::CancelPrefixScan
+        | RequestType::PrefixScanned
+        | RequestType::CheckLocalChanges
+        | RequestType::SetQuickSlots
+        | RequestType::SwitchQuickSlot
+        | RequestType::CreatePlan
+        | RequestType::Plan
+        | RequestType::ExecutePlan
+        | RequestType::PauseInspection
+        | RequestType::ResumeInspection
+        | RequestType::ToolInspected
+        | RequestType::SelectSteamInstallation
+        | RequestType::CancelTask
+        | RequestType::ResolveRestoreConflict
+        | RequestType::CreateVirtualTool
+        | RequestType::ForceRefresh
+        | RequestType::AdoptTool
+        | RequestType::ListOrphanedCompatData
+        | RequestType::OrphanedCompatData
+        | RequestType::GetPrefixSizes
+        | RequestType::GetInstalledApps
+        | RequestType::GetShortcutDetails
+        | RequestType::SetShortcutLaunchOptions
+        | RequestType::GetHistory
+        | RequestType::RequestSnapshot
+        | RequestType::Authenticate
+        | RequestType::Subscribe
+        // Peers that miss one ask for a snapshot
+        | RequestType::StateDelta => MessageKind::Coalescable,
+    }
+}
+
+/// Counts of messages peers didn't get in full, across every peer since startup.
+pub struct BroadcastStats {
+    dropped: AtomicU64,
+    coalesced: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl Default for BroadcastStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BroadcastStats {
+    pub const fn new() -> Self {
+        BroadcastStats {
+            dropped: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    pub fn counters(&self) -> BroadcastCounters {
+        BroadcastCounters {
+            dropped: self.dropped.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub static BROADCAST_STATS: BroadcastStats = BroadcastStats::new();
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+pub struct BroadcastCounters {
+    /// Messages dropped to make room for newer ones.
+    pub dropped: u64,
+    /// Snapshots replaced by a newer one before they were sent.
+    pub coalesced: u64,
+    /// Peers disconnected for not making room for a critical message.
+    pub evictions: u64,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Delivery {
+    Queued,
+    /// Queued in place of an older snapshot of the same type.
+    Coalesced,
+    /// Queued after dropping an older message, or dropped itself.
+    Dropped,
+    /// A critical message found no room.
+    Full,
+    /// The peer disconnected or was evicted.
+    Closed,
+}
+
+struct Queue {
+    messages: VecDeque<(RequestType, Message)>,
+    closed: bool,
+}
+
+/// Bounded queue of messages waiting to be written to a peer.
+pub struct Outbox {
+    queue: Mutex<Queue>,
+    capacity: usize,
+    /// Notified once a message was queued or the outbox was closed.
+    ready: Notify,
+    /// Notified once a message was taken off the queue.
+    space: Notify,
+    /// How long a critical message waits for room.
+    critical_wait: Duration,
+    stats: &'static BroadcastStats,
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Outbox::new(OUTBOX_CAPACITY, CRITICAL_WAIT, &BROADCAST_STATS)
+    }
+}
+
+impl Outbox {
+    pub fn new(capacity: usize, critical_wait: Duration, stats: &'static BroadcastStats) -> Self {
+        Outbox {
+            queue: Mutex::new(Queue {
+                messages: VecDeque::new(),
+                closed: false,
+            }),
+            capacity,
+            ready: Notify::new(),
+            space: Notify::new(),
+            critical_wait,
+            stats,
+        }
+    }
+
+    /// Queues a message without waiting, critical messages that don't fit are refused.
+    pub fn try_push(&self, r#type: RequestType, message: Message) -> Delivery {
+        let kind = message_kind(&r#type);
+        let mut queue = self.queue.lock().unwrap();
+        if queue.closed {
+            return Delivery::Closed;
+        }
+        let mut delivery = Delivery::Queued;
+        if kind == MessageKind::Snapshot {
+            if let Some(index) = queue
+                .messages
+                .iter()
+                .position(|(queued, _)| *queued == r#type)
+            {
+                queue.messages.remove(index);
+                self.stats.coalesced.fetch_add(1, Ordering::Relaxed);
+                delivery = Delivery::Coalesced;
+            }
+        }
+        if queue.messages.len() >= self.capacity {
+            if kind == MessageKind::Critical {
+                return Delivery::Full;
+            }
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            let oldest = queue
+                .messages
+                .iter()
+                .position(|(queued, _)| message_kind(queued) == MessageKind::Coalescable);
+            match oldest {
+                Some(index) => {
+                    queue.messages.remove(index);
+                }
+                // Everything queued matters more
+                None => return Delivery::Dropped,
+            }
+            delivery = Delivery::Dropped;
+        }
+        queue.messages.push_back((r#type, message));
+        drop(queue);
+        self.ready.notify_one();
+        delivery
+    }
+
+    /// Queues a message, a critical one waits a while for room.
+    pub async fn push(&self, r#type: RequestType, message: Message) -> Delivery {
+        let deadline = tokio::time::Instant::now() + self.critical_wait;
+        loop {
+            let space = self.space.notified();
+            match self.try_push(r#type.clone(), message.clone()) {
+                Delivery::Full => {}
+                delivery => return delivery,
+            }
+            if tokio::time::timeout_at(deadline, space).await.is_err() {
+                return Delivery::Full;
+            }
+        }
+    }
+
+    /// The next message to write, `None` once the outbox was closed.
+    pub async fn next(&self) -> Option<Message> {
+        loop {
+            let ready = self.ready.notified();
+            {
+                let mut queue = self.queue.lock().unwrap();
+                if queue.closed {
+                    return None;
+                }
+                if let Some((_, message)) = queue.messages.pop_front() {
+                    drop(queue);
+                    self.space.notify_waiters();
+                    return Some(message);
+                }
+            }
+            ready.await;
+        }
+    }
+
+    /// Drops every queued message and ends `next`.
+    pub fn close(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.closed = true;
+        queue.messages.clear();
+        drop(queue);
+        self.ready.notify_one();
+        self.space.notify_waiters();
+    }
+}
+
+/// Forgets a peer that disconnected, broadcasts waiting for room in its outbox give up.
+pub async fn remove_peer(peer_map: &PeerMap, addr: PeerAddr) {
+    if let Some(peer) = peer_map.lock().await.remove(&addr) {
+        peer.outbox.close();
+    }
+}
+
+/// Sends a message to one peer alone, like the answer to a request only it made.
+pub async fn send_to_peer(peer_map: &PeerMap, addr: PeerAddr, response: &Request) {
+    let Some(outbox) = peer_map
+        .lock()
+        .await
+        .get(&addr)
+        .map(|peer| peer.outbox.clone())
+    else {
+        return;
+    };
+    let message = Message::text(serde_json::to_string(response).unwrap());
+    outbox.push(response.r#type.clone(), message).await;
+}
+
+/// Disconnects a peer that stopped reading its messages.
+pub async fn evict_peer(peer_map: &PeerMap, addr: PeerAddr) {
+    if let Some(peer) = peer_map.lock().await.remove(&addr) {
+        peer.outbox.close();
+        peer.outbox.stats.evictions.fetch_add(1, Ordering::Relaxed);
+        warn!("Evicted {}: it stopped reading its messages", addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wine_cask::app::{broadcast_to_peers, Request};
+    use crate::wine_cask::permissions::PermissionSet;
+    use crate::wine_cask::subscriptions::SubscriptionSet;
+    use crate::Peer;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn message(text: &str) -> Message {
+        Message::text(text)
+    }
+
+    fn queued(outbox: &Outbox) -> usize {
+        outbox.queue.lock().unwrap().messages.len()
+    }
+
+    #[tokio::test]
+    async fn test_snapshots_replace_older_ones() {
+        static STATS: BroadcastStats = BroadcastStats::new();
+        let outbox = Outbox::new(4, CRITICAL_WAIT, &STATS);
+        assert_eq!(
+            outbox.try_push(RequestType::UpdateState, message("1")),
+            Delivery::Queued
+        );
+        outbox.try_push(RequestType::Notification, message("a"));
+        assert_eq!(
+            outbox.try_push(RequestType::UpdateState, message("2")),
+            Delivery::Coalesced
+        );
+        assert_eq!(outbox.next().await, Some(message("a")));
+        assert_eq!(outbox.next().await, Some(message("2")));
+        assert_eq!(STATS.counters().coalesced, 1);
+    }
+
+    #[tokio::test]
+    async fn test_stuck_peer_stays_bounded_and_gets_evicted() {
+        static STATS: BroadcastStats = BroadcastStats::new();
+        let peer_map: PeerMap = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+        let addr = PeerAddr::Unix(1);
+        // Nothing ever reads from this outbox
+        let outbox = Arc::new(Outbox::new(8, Duration::from_millis(50), &STATS));
+        peer_map.lock().await.insert(
+            addr,
+            Peer {
+                outbox: outbox.clone(),
+                permissions: PermissionSet::all(),
+                subscriptions: SubscriptionSet::default(),
+            },
+        );
+
+        for number in 0..100 {
+            let notification = Request {
+                notification: Some(format!("Downloaded {}%", number)),
+                ..Request::new(RequestType::Notification)
+            };
+            broadcast_to_peers(&peer_map, &notification).await;
+            broadcast_to_peers(&peer_map, &Request::new(RequestType::UpdateState)).await;
+        }
+        assert_eq!(queued(&outbox), 8);
+        let counters = STATS.counters();
+        assert_eq!(counters.coalesced, 99);
+        assert_eq!(counters.dropped, 93);
+        assert_eq!(counters.evictions, 0);
+        assert!(peer_map.lock().await.contains_key(&addr));
+
+        broadcast_to_peers(&peer_map, &Request::new(RequestType::RefreshCompleted)).await;
+        assert!(!peer_map.lock().await.contains_key(&addr));
+        assert_eq!(STATS.counters().evictions, 1);
+        assert_eq!(outbox.next().await, None);
+    }
+}
@@ -0,0 +1,521 @@
+use crate::wine_cask::cancellation::CancellationToken;
+use crate::wine_cask::install::CompressionType;
+use crate::wine_cask::provenance::{verify_files, FileManifestEntry};
+use crate::wine_cask::recursive_delete_dir_entry;
+use flate2::bufread::GzDecoder;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use xz2::bufread::XzDecoder;
+
+/// Largest fraction of the release size that may have changed for a partial update to be used.
+pub const PARTIAL_UPDATE_THRESHOLD: f64 = 0.25;
+
+#[derive(PartialEq, Default, Debug)]
+pub struct ManifestDiff {
+    pub added: Vec<FileManifestEntry>,
+    pub changed: Vec<FileManifestEntry>,
+    pub removed: Vec<String>,
+    pub unchanged: Vec<FileManifestEntry>,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum UpdatePlan {
+    Partial(ManifestDiff),
+    /// Install the whole release, with the reason a partial update isn't possible.
+    Full(String),
+}
+
+/// Regular files of a release archive, relative to its single top-level directory.
+#[derive(PartialEq, Debug)]
+pub struct ArchiveManifest {
+    pub root: String,
+    pub files: Vec<FileManifestEntry>,
+}
+
+impl ManifestDiff {
+    /// Bytes that have to be extracted from the archive.
+    pub fn changed_bytes(&self) -> u64 {
+        self.added
+            .iter()
+            .chain(&self.changed)
+            .map(|file| file.size)
+            .sum()
+    }
+}
+
+pub fn diff_manifests(
+    installed: &[FileManifestEntry],
+    upstream: &[FileManifestEntry],
+) -> ManifestDiff {
+    let installed_files: HashMap<&str, &FileManifestEntry> = installed
+        .iter()
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+    let upstream_paths: HashSet<&str> = upstream.iter().map(|file| file.path.as_str()).collect();
+
+    let mut diff = ManifestDiff::default();
+    for file in upstream {
+        match installed_files.get(file.path.as_str()) {
+            Some(installed_file) if *installed_file == file => diff.unchanged.push(file.clone()),
+            Some(_) => diff.changed.push(file.clone()),
+            None => diff.added.push(file.clone()),
+        }
+    }
+    diff.removed = installed
+        .iter()
+        .filter(|file| !upstream_paths.contains(file.path.as_str()))
+        .map(|file| file.path.clone())
+        .collect();
+    diff
+}
+
+pub fn plan_update(
+    installed: Option<&[FileManifestEntry]>,
+    upstream: Option<&[FileManifestEntry]>,
+    threshold: f64,
+) -> UpdatePlan {
+    let Some(installed) = installed else {
+        return UpdatePlan::Full("No file manifest recorded for the installed tool".to_string());
+    };
+    let Some(upstream) = upstream else {
+        return UpdatePlan::Full("No file manifest available for the release".to_string());
+    };
+
+    let total_bytes: u64 = upstream.iter().map(|file| file.size).sum();
+    if total_bytes == 0 {
+        return UpdatePlan::Full("Release contains no files".to_string());
+    }
+    let diff = diff_manifests(installed, upstream);
+    let changed_fraction = diff.changed_bytes() as f64 / total_bytes as f64;
+    if changed_fraction > threshold {
+        return UpdatePlan::Full(format!(
+            "{:.0}% of the release changed",
+            changed_fraction * 100.0
+        ));
+    }
+    UpdatePlan::Partial(diff)
+}
+
+/// Decompresses `archive` as it's read, only the decoder's buffers are held in memory.
+pub fn decompressor<'a>(
+    archive: impl BufRead + 'a,
+    compress_type: &CompressionType,
+) -> Box<dyn Read + 'a> {
+    match compress_type {
+        CompressionType::Gzip => Box::new(GzDecoder::new(archive)),
+        CompressionType::Xz => Box::new(XzDecoder::new(archive)),
+        // Only fails if the decoder's context can't be allocated
+        CompressionType::Zstd => Box::new(
+            zstd::stream::read::Decoder::with_buffer(archive)
+                .expect("Failed to create zstd decoder"),
+        ),
+        // Zips can't be read as a stream, `extract_zip` reads them from the file instead
+        CompressionType::Tar | CompressionType::Zip | CompressionType::Unknown => Box::new(archive),
+    }
+}
+
+/// Splits an archive path into its top-level directory and the path below it.
+fn split_root(path: &Path) -> Option<(String, String)> {
+    let mut components = path.components().filter_map(|component| match component {
+        Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+        _ => None,
+    });
+    let root = components.next()?;
+    Some((root, components.collect::<Vec<String>>().join("/")))
+}
+
+/// Lists and hashes the regular files of a release archive.
+pub fn read_archive_manifest(archive: impl Read) -> io::Result<ArchiveManifest> {
+    let mut root: Option<String> = None;
+    let mut files = Vec::new();
+    for entry in tar::Archive::new(archive).entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let Some((entry_root, relative)) = split_root(&path) else {
+            continue;
+        };
+        if *root.get_or_insert_with(|| entry_root.clone()) != entry_root {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Archive has more than one top-level directory",
+            ));
+        }
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_hard_link() {
+            // Hard links can't be extracted on their own
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Archive contains hard links",
+            ));
+        }
+        if entry_type.is_file() && !relative.is_empty() {
+            let mut hasher = Sha256::new();
+            let size = io::copy(&mut entry, &mut hasher)?;
+            files.push(FileManifestEntry {
+                path: relative,
+                size,
+                sha256: format!("{:x}", hasher.finalize()),
+            });
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(ArchiveManifest {
+        root: root.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Archive is empty"))?,
+        files,
+    })
+}
+
+/// Builds the new tool in `staging_directory` from the unchanged files of `base` and the differing
+/// archive entries, then verifies the result against the archive manifest.
+pub fn stage_partial_update(
+    archive: impl Read,
+    manifest: &ArchiveManifest,
+    diff: &ManifestDiff,
+    base: &Path,
+    staging_directory: &Path,
+    cancellation: &CancellationToken,
+) -> io::Result<PathBuf> {
+    let staged = staging_directory.join(&manifest.root);
+    if staged.exists() {
+        recursive_delete_dir_entry(&staged)?;
+    }
+    fs::create_dir_all(&staged)?;
+
+    for file in &diff.unchanged {
+        cancellation.check()?;
+        let source = base.join(&file.path);
+        let destination = staged.join(&file.path);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Hard links only work within a filesystem, copy otherwise
+        if fs::hard_link(&source, &destination).is_err() {
+            fs::copy(&source, &destination)?;
+        }
+    }
+
+    let extract: HashSet<&str> = diff
+        .added
+        .iter()
+        .chain(&diff.changed)
+        .map(|file| file.path.as_str())
+        .collect();
+    for entry in tar::Archive::new(archive).entries()? {
+        cancellation.check()?;
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let Some((_, relative)) = split_root(&path) else {
+            continue;
+        };
+        // Directories and symlinks are cheap and not part of the manifest, always extract them
+        if entry.header().entry_type().is_file() && !extract.contains(relative.as_str()) {
+            continue;
+        }
+        entry.unpack_in(staging_directory)?;
+    }
+
+    let verification = verify_files(&staged, &manifest.root, &manifest.files);
+    if !verification.verified {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Staged tool doesn't match the release: {} mismatched, {} missing, {} added",
+                verification.mismatched.len(),
+                verification.missing.len(),
+                verification.added.len()
+            ),
+        ));
+    }
+    Ok(staged)
+}
+
+/// Attempts a partial update on top of `base`, returning the staged tool and its file manifest.
+///
+/// Returns `None` whenever a full install is needed instead, nothing is left behind in that case.
+/// The archive is read from disk twice, once for its manifest and once to stage the update.
+pub fn try_partial_update(
+    archive: &Path,
+    compress_type: &CompressionType,
+    base: &Path,
+    installed_files: &[FileManifestEntry],
+    staging_directory: &Path,
+    cancellation: &CancellationToken,
+) -> Option<(PathBuf, Vec<FileManifestEntry>)> {
+    if *compress_type == CompressionType::Zip {
+        info!("Installing fully: zip archives aren't updated partially");
+        return None;
+    }
+    let open = || File::open(archive).map(|file| decompressor(BufReader::new(file), compress_type));
+    let manifest = match open().and_then(read_archive_manifest) {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            warn!("Unable to read release manifest, installing fully: {}", err);
+            return None;
+        }
+    };
+    let diff = match plan_update(
+        Some(installed_files),
+        Some(&manifest.files),
+        PARTIAL_UPDATE_THRESHOLD,
+    ) {
+        UpdatePlan::Partial(diff) => diff,
+        UpdatePlan::Full(reason) => {
+            info!("Installing fully: {}", reason);
+            return None;
+        }
+    };
+
+    info!(
+        "Partially updating from {}: {} added, {} changed, {} removed, {} reused",
+        base.display(),
+        diff.added.len(),
+        diff.changed.len(),
+        diff.removed.len(),
+        diff.unchanged.len()
+    );
+    let staged = open().and_then(|archive| {
+        stage_partial_update(
+            archive,
+            &manifest,
+            &diff,
+            base,
+            staging_directory,
+            cancellation,
+        )
+    });
+    match staged {
+        Ok(staged) => Some((staged, manifest.files)),
+        Err(err) => {
+            warn!("Partial update failed, installing fully: {}", err);
+            if staging_directory.exists() {
+                if let Err(err) = recursive_delete_dir_entry(staging_directory) {
+                    warn!("Failed to clean up staging directory: {}", err);
+                }
+            }
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wine_cask::provenance::generate_file_manifest;
+    use tempfile::tempdir;
+
+    fn entry(path: &str, size: u64, sha256: &str) -> FileManifestEntry {
+        FileManifestEntry {
+            path: path.to_string(),
+            size,
+            sha256: sha256.to_string(),
+        }
+    }
+
+    fn build_archive(root: &str, files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o755);
+            header.set_cksum();
+            builder
+                .append_data(
+                    &mut header,
+                    format!("{}/{}", root, path),
+                    contents.as_bytes(),
+                )
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn create_base_tool(path: &Path, files: &[(&str, &str)]) {
+        for (file, contents) in files {
+            let file_path = path.join(file);
+            fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            fs::write(file_path, contents).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_diff_manifests() {
+        let installed = [
+            entry("files/bin/wine", 100, "a"),
+            entry("files/lib/wine/d3d11.dll", 50, "b"),
+            entry("version", 10, "c"),
+        ];
+        let upstream = [
+            entry("files/bin/wine", 100, "a"),
+            entry("files/lib/wine/d3d12.dll", 60, "d"),
+            entry("version", 10, "e"),
+        ];
+
+        let diff = diff_manifests(&installed, &upstream);
+        assert_eq!(diff.unchanged, vec![entry("files/bin/wine", 100, "a")]);
+        assert_eq!(diff.changed, vec![entry("version", 10, "e")]);
+        assert_eq!(diff.added, vec![entry("files/lib/wine/d3d12.dll", 60, "d")]);
+        assert_eq!(diff.removed, vec!["files/lib/wine/d3d11.dll"]);
+        assert_eq!(diff.changed_bytes(), 70);
+    }
+
+    #[test]
+    fn test_plan_update_falls_back_to_full() {
+        let installed = [
+            entry("files/bin/wine", 900, "a"),
+            entry("version", 100, "b"),
+        ];
+        let point_release = [
+            entry("files/bin/wine", 900, "a"),
+            entry("version", 100, "c"),
+        ];
+        let major_release = [
+            entry("files/bin/wine", 900, "d"),
+            entry("version", 100, "c"),
+        ];
+
+        assert!(matches!(
+            plan_update(None, Some(&point_release), PARTIAL_UPDATE_THRESHOLD),
+            UpdatePlan::Full(_)
+        ));
+        assert!(matches!(
+            plan_update(Some(&installed), None, PARTIAL_UPDATE_THRESHOLD),
+            UpdatePlan::Full(_)
+        ));
+        assert!(matches!(
+            plan_update(Some(&installed), Some(&[]), PARTIAL_UPDATE_THRESHOLD),
+            UpdatePlan::Full(_)
+        ));
+        assert!(matches!(
+            plan_update(
+                Some(&installed),
+                Some(&major_release),
+                PARTIAL_UPDATE_THRESHOLD
+            ),
+            UpdatePlan::Full(_)
+        ));
+        match plan_update(
+            Some(&installed),
+            Some(&point_release),
+            PARTIAL_UPDATE_THRESHOLD,
+        ) {
+            UpdatePlan::Partial(diff) => assert_eq!(diff.changed_bytes(), 100),
+            UpdatePlan::Full(reason) => panic!("Expected a partial update: {}", reason),
+        }
+    }
+
+    #[test]
+    fn test_read_archive_manifest() {
+        let archive = build_archive(
+            "GE-Proton9-21",
+            &[
+                ("proton", "#!/usr/bin/env python3"),
+                ("files/bin/wine", "wine"),
+            ],
+        );
+        let manifest = read_archive_manifest(archive.as_slice()).unwrap();
+        assert_eq!(manifest.root, "GE-Proton9-21");
+        assert_eq!(manifest.files.len(), 2);
+        assert_eq!(manifest.files[0].path, "files/bin/wine");
+        assert_eq!(manifest.files[0].size, 4);
+
+        let mut archive = build_archive("GE-Proton9-21", &[("proton", "")]);
+        archive.truncate(archive.len() - 1024);
+        archive.extend(build_archive("GE-Proton9-20", &[("proton", "")]));
+        assert!(read_archive_manifest(archive.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_partial_update_reuses_unchanged_files() {
+        let temp_dir = tempdir().unwrap();
+        let base = temp_dir.path().join("GE-Proton9-20");
+        let wine = "wine 9.0".repeat(100);
+        let base_files = [
+            ("proton", "#!/usr/bin/env python3"),
+            ("files/bin/wine", wine.as_str()),
+            ("files/lib/wine/d3d11.dll", "d3d11"),
+            ("version", "1 GE-Proton9-20"),
+        ];
+        create_base_tool(&base, &base_files);
+        let installed_files = generate_file_manifest(&base).unwrap();
+
+        let archive = build_archive(
+            "GE-Proton9-21",
+            &[
+                ("proton", "#!/usr/bin/env python3"),
+                ("files/bin/wine", wine.as_str()),
+                ("files/lib/wine/d3d11.dll", "d3d11"),
+                ("version", "1 GE-Proton9-21"),
+            ],
+        );
+        let archive_file = temp_dir.path().join("GE-Proton9-21.tar");
+        fs::write(&archive_file, archive).unwrap();
+        let staging_directory = temp_dir.path().join(".wine-cellar-staging");
+        let (staged, files) = try_partial_update(
+            &archive_file,
+            &CompressionType::Unknown,
+            &base,
+            &installed_files,
+            &staging_directory,
+            &CancellationToken::default(),
+        )
+        .unwrap();
+
+        assert_eq!(staged, staging_directory.join("GE-Proton9-21"));
+        assert_eq!(generate_file_manifest(&staged).unwrap(), files);
+        assert_eq!(
+            fs::read_to_string(staged.join("version")).unwrap(),
+            "1 GE-Proton9-21"
+        );
+        // The base tool is left untouched
+        assert_eq!(
+            fs::read_to_string(base.join("version")).unwrap(),
+            "1 GE-Proton9-20"
+        );
+    }
+
+    #[test]
+    fn test_partial_update_verification_failure_falls_back() {
+        let temp_dir = tempdir().unwrap();
+        let base = temp_dir.path().join("GE-Proton9-20");
+        let wine = "wine 9.0".repeat(100);
+        let base_files = [
+            ("proton", "#!/usr/bin/env python3"),
+            ("files/bin/wine", wine.as_str()),
+            ("files/lib/wine/d3d11.dll", "d3d11"),
+            ("version", "1 GE-Proton9-20"),
+        ];
+        create_base_tool(&base, &base_files);
+        let installed_files = generate_file_manifest(&base).unwrap();
+        // The base was modified after its manifest was recorded
+        fs::write(base.join("files/bin/wine"), "wine 8.0").unwrap();
+
+        let archive = build_archive(
+            "GE-Proton9-21",
+            &[
+                ("proton", "#!/usr/bin/env python3"),
+                ("files/bin/wine", wine.as_str()),
+                ("files/lib/wine/d3d11.dll", "d3d11"),
+                ("version", "1 GE-Proton9-21"),
+            ],
+        );
+        let archive_file = temp_dir.path().join("GE-Proton9-21.tar");
+        fs::write(&archive_file, archive).unwrap();
+        let staging_directory = temp_dir.path().join(".wine-cellar-staging");
+        assert!(try_partial_update(
+            &archive_file,
+            &CompressionType::Unknown,
+            &base,
+            &installed_files,
+            &staging_directory,
+            &CancellationToken::default(),
+        )
+        .is_none());
+        assert!(!staging_directory.exists());
+    }
+}
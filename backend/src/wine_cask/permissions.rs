@@ -0,0 +1,625 @@
+use crate::wine_cask::activity::ActivityChange;
+use crate::wine_cask::app::{AppState, Request, RequestType, TaskType};
+use crate::wine_cask::state_delta::diff_states;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+/// Tokens granting a write permission must be at least this long, shorter ones are guessable.
+pub const MIN_TOKEN_LENGTH: usize = 16;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// Installed tools, releases and everything recorded about them.
+    ReadTools,
+    ReadQueue,
+    /// App names and ids, including non-Steam shortcuts and which apps use which tool.
+    ReadApps,
+    ReadPrefixes,
+    /// Install, uninstall and other long-running operations.
+    ControlTasks,
+    /// Steam's config and the plugin settings.
+    WriteConfig,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 6] = [
+        Permission::ReadTools,
+        Permission::ReadQueue,
+        Permission::ReadApps,
+        Permission::ReadPrefixes,
+        Permission::ControlTasks,
+        Permission::WriteConfig,
+    ];
+
+    pub fn is_write(&self) -> bool {
+        matches!(self, Permission::ControlTasks | Permission::WriteConfig)
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Permission::ReadTools => "read_tools",
+            Permission::ReadQueue => "read_queue",
+            Permission::ReadApps => "read_apps",
+            Permission::ReadPrefixes => "read_prefixes",
+            Permission::ControlTasks => "control_tasks",
+            Permission::WriteConfig => "write_config",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+#[serde(transparent)]
+pub struct PermissionSet(BTreeSet<Permission>);
+
+impl PermissionSet {
+    pub fn new(permissions: &[Permission]) -> PermissionSet {
+        PermissionSet(permissions.iter().copied().collect())
+    }
+
+    pub fn all() -> PermissionSet {
+        PermissionSet::new(&Permission::ALL)
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        self.0.contains(&permission)
+    }
+}
+
+/// Token a peer can connect with to get `permissions`, e.g. for a dashboard on another device.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct AccessToken {
+    pub name: String,
+    pub token: String,
+    pub permissions: PermissionSet,
+}
+
+impl AccessToken {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.permissions.0.iter().find(|permission| permission.is_write()) {
+            Some(permission) if self.token.len() < MIN_TOKEN_LENGTH => Err(format!(
+                "invalid_token: {} grants {} without authentication, write permissions need a token of at least {} characters",
+                self.name, permission, MIN_TOKEN_LENGTH
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn validate_access_tokens(access_tokens: &[AccessToken]) -> Result<(), String> {
+    for (index, access_token) in access_tokens.iter().enumerate() {
+        access_token.validate()?;
+        if access_tokens[..index]
+            .iter()
+            .any(|other| other.token == access_token.token)
+        {
+            return Err(format!(
+                "invalid_token: {} uses the same token as another entry",
+                access_token.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Reads the `token` parameter of the query a peer connected with.
+pub fn token_from_query(query: Option<&str>) -> Option<String> {
+    query?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+        .map(str::to_string)
+}
+
+/// Permissions of a peer that connected with `token`, `None` if the token is unknown.
+///
+/// Peers without a token are the plugin's own frontend or local tooling and get every permission.
+pub fn resolve_permissions(
+    access_tokens: &[AccessToken],
+    token: Option<&str>,
+) -> Option<PermissionSet> {
+    let Some(token) = token else {
+        return Some(PermissionSet::all());
+    };
+    access_tokens
+        .iter()
+        .find(|access_token| access_token.token == token && access_token.validate().is_ok())
+        .map(|access_token| access_token.permissions.clone())
+}
+
+/// Maps a request from a peer to the permission it needs, messages only the backend sends need
+/// none since they are ignored.
+pub fn required_permission(request: &Request) -> Option<Permission> {
+    match request.r#type {
+        RequestType::RequestState
+        | RequestType::GetToolProvenance
+        | RequestType::VerifyInstalledTool
+        | RequestType::GetActivity
+        | RequestType::Refresh
+        | RequestType::ForceRefresh
+        | RequestType::GetMutationLog
+        | RequestType::GetHistory
+        | RequestType::CheckLocalChanges => Some(Permission::ReadTools),
+        RequestType::GetStorageBreakdown
+        | RequestType::PrioritizePrefixes
+        | RequestType::CancelPrefixScan
+        | RequestType::CreatePlan
+        | RequestType::ListOrphanedCompatData
+        | RequestType::GetPrefixSizes => Some(Permission::ReadPrefixes),
+        RequestType::GetUndoStack
+        | RequestType::GetInstalledApps
+        | RequestType::GetShortcutDetails => Some(Permission::ReadApps),
+        RequestType::ClearShaderCache
+        | RequestType::ExecutePlan
+        | RequestType::PauseInspection
+        | RequestType::ResumeInspection
+        | RequestType::CancelTask
+        | RequestType::ResolveRestoreConflict
+        | RequestType::CreateVirtualTool
+        | RequestType::RollbackUpdate => Some(Permission::ControlTasks),
+        RequestType::UpdateSettings
+        | RequestType::UndoLast
+        | RequestType::SkipRelease
+        | RequestType::UnskipRelease
+        | RequestType::SetQuickSlots
+        | RequestType::SwitchQuickSlot
+        | RequestType::SelectSteamInstallation
+        | RequestType::AdoptTool
+        | RequestType::SetShortcutLaunchOptions => Some(Permission::WriteConfig),
+        RequestType::Task => match request.task.as_ref().map(|task| &task.r#type) {
+            Some(
+                TaskType::SetCompatibilityToolMapping
+                | TaskType::SetCompatibilityToolMappings
+                | TaskType::ImportCompatibilityToolMappings,
+            ) => Some(Permission::WriteConfig),
+            _ => Some(Permission::ControlTasks),
+        },
+        RequestType::UpdateState
+        | RequestType::Notification
+        | RequestType::ToolProvenance
+        | RequestType::Verification
+        | RequestType::Activity
+        | RequestType::StorageBreakdown
+        | RequestType::StartupProgress
+        | RequestType::ValidationError
+        | RequestType::UndoStack
+        | RequestType::RefreshCompleted
+        | RequestType::MutationLog
+        | RequestType::PrefixScanned
+        | RequestType::PrefixScanCompleted
+        | RequestType::Permissions
+        | RequestType::Plan
+        | RequestType::PlanExecuted
+        | RequestType::ToolInspected
+        | RequestType::InspectionCompleted
+        | RequestType::TaskCancelled
+        | RequestType::UpdateSummary
+        | RequestType::UninstallBlocked
+        | RequestType::InsufficientDiskSpace
+        | RequestType::UpdatesAvailable
+        | RequestType::OrphanedCompatData
+        | RequestType::PrefixSizes
+        | RequestType::InstalledApps
+        | RequestType::ShortcutDetails
+        | RequestType::History
+        | RequestType::StateDelta
+        | RequestType::Error => None,
+        // Peers only get back the state they are sent anyway
+        RequestType::RequestSnapshot => None,
+        // Only checked as the first messages of a connection
+        RequestType::Authenticate | RequestType::Hello => None,
+        // Subscribing never shows a peer more than its permissions do
+        RequestType::Subscribe | RequestType::Subscribed => None,
+    }
+}
+
+fn filter_app_state(app_state: &mut AppState, permissions: &PermissionSet) {
+    if !permissions.allows(Permission::ReadApps) {
+        app_state.compatibility_tool_mappings.clear();
+        app_state.settings.quick_slots.clear();
+        for tool in &mut app_state.installed_compatibility_tools {
+            tool.used_by_games.clear();
+            tool.used_by_apps.clear();
+        }
+    }
+    if !permissions.allows(Permission::ReadTools) {
+        app_state.available_flavors.clear();
+        app_state.installed_compatibility_tools.clear();
+        app_state.installed_runners.clear();
+        app_state.installed_components.clear();
+        app_state.stranded_compatibility_tools.clear();
+        app_state.filesystem_profiles.clear();
+        app_state.inspection_progress = None;
+        app_state.available_updates.clear();
+    }
+    if !permissions.allows(Permission::ReadQueue) {
+        app_state.task_queue.clear();
+        app_state.in_progress.clear();
+        app_state.prefix_restores.clear();
+    }
+    if !permissions.allows(Permission::WriteConfig) {
+        app_state.settings.access_tokens.clear();
+        app_state.settings.github_token = None;
+        app_state.settings.proxy_url = None;
+    }
+}
+
+/// Whether a notification is about apps, in its text or in the results it carries.
+fn names_apps(request: &Request) -> bool {
+    request.app_ids.is_some()
+        || request.shader_cache.is_some()
+        || request.mapping_results.is_some()
+        || request.mapping_import.is_some()
+}
+
+/// Returns what a peer with `permissions` may see of a message, `None` if it may see nothing.
+pub fn filter_for_peer(request: &Request, permissions: &PermissionSet) -> Option<Request> {
+    let needed = match request.r#type {
+        RequestType::ToolProvenance
+        | RequestType::Verification
+        | RequestType::Activity
+        | RequestType::MutationLog
+        | RequestType::History
+        | RequestType::ToolInspected
+        | RequestType::InspectionCompleted
+        | RequestType::UpdateSummary
+        | RequestType::UpdatesAvailable => Some(Permission::ReadTools),
+        RequestType::StorageBreakdown
+        | RequestType::PrefixScanned
+        | RequestType::PrefixScanCompleted
+        | RequestType::Plan
+        | RequestType::PlanExecuted
+        | RequestType::OrphanedCompatData
+        | RequestType::PrefixSizes => Some(Permission::ReadPrefixes),
+        RequestType::UndoStack
+        | RequestType::UninstallBlocked
+        | RequestType::InstalledApps
+        | RequestType::ShortcutDetails => Some(Permission::ReadApps),
+        RequestType::TaskCancelled | RequestType::InsufficientDiskSpace => {
+            Some(Permission::ReadQueue)
+        }
+        RequestType::Notification if names_apps(request) => Some(Permission::ReadApps),
+        _ => None,
+    };
+    if needed.is_some_and(|permission| !permissions.allows(permission)) {
+        return None;
+    }
+
+    let mut request = request.clone();
+    if let Some(app_state) = &mut request.app_state {
+        filter_app_state(app_state, permissions);
+    }
+    // Diffed after filtering, so deltas never carry what the peer may not see
+    if let (Some(state_delta), Some(base_app_state), Some(app_state)) = (
+        &mut request.state_delta,
+        &mut request.base_app_state,
+        &request.app_state,
+    ) {
+        filter_app_state(base_app_state, permissions);
+        state_delta.deltas = diff_states(
+            &serde_json::to_value(&**base_app_state).unwrap(),
+            &serde_json::to_value(app_state).unwrap(),
+        );
+        request.base_app_state = None;
+        request.app_state = None;
+    }
+    if !permissions.allows(Permission::WriteConfig) {
+        if let Some(settings) = &mut request.settings {
+            settings.access_tokens.clear();
+            settings.github_token = None;
+            settings.proxy_url = None;
+        }
+    }
+    if !permissions.allows(Permission::ReadApps) {
+        request.shader_cache = None;
+        request.mapping_results = None;
+        request.mapping_import = None;
+        if let Some(activity) = &mut request.activity {
+            activity.retain(|event| !matches!(event.change, ActivityChange::MappingChanged { .. }));
+        }
+        if let Some(storage_breakdown) = &mut request.storage_breakdown {
+            for library_folder in &mut storage_breakdown.library_folders {
+                for usage in library_folder
+                    .top_prefixes
+                    .iter_mut()
+                    .chain(library_folder.top_shader_caches.iter_mut())
+                {
+                    usage.name = usage.app_id.to_string();
+                }
+            }
+        }
+        for prefix_size in request.prefix_sizes.iter_mut().flatten() {
+            prefix_size.name = prefix_size.app_id.to_string();
+        }
+    }
+    Some(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_id::CompatAppId;
+    use crate::wine_cask::activity::{ActivityEvent, ActivitySource};
+    use crate::wine_cask::state_delta::StateDeltas;
+    use crate::wine_cask::storage::{AppUsage, LibraryFolderUsage, StorageBreakdown};
+    use serde_json::json;
+
+    fn dashboard() -> PermissionSet {
+        PermissionSet::new(&[
+            Permission::ReadTools,
+            Permission::ReadQueue,
+            Permission::ReadPrefixes,
+        ])
+    }
+
+    fn app_state() -> AppState {
+        serde_json::from_value(json!({
+            "available_flavors": [],
+            "installed_compatibility_tools": [{
+                "path": "/home/deck/.steam/root/compatibilitytools.d/GE-Proton9-20",
+                "display_name": "GE-Proton9-20",
+                "internal_name": "GE-Proton9-20",
+                "used_by_games": ["ELDEN RING", "My Emulator Shortcut"],
+                "used_by_apps": [{"app_id": 1245620, "name": "ELDEN RING", "installed": false}],
+                "requires_restart": false,
+                "supports_32bit": true,
+                "flavor": "ProtonGE",
+                "github_release": null
+            }],
+            "installed_runners": [],
+            "installed_components": [],
+            "compatibility_tool_mappings": [{
+                "app_id": 1245620,
+                "name": "ELDEN RING",
+                "compatibility_tool": "GE-Proton9-20",
+                "unresolved": false,
+                "steam_override": null
+            }],
+            "in_progress": [],
+            "task_queue": [],
+            "updater_state": "Idle",
+            "updater_last_check": null,
+            "github_rate_limit_reset": null,
+            "settings": {
+                "access_tokens": [{
+                    "name": "dashboard",
+                    "token": "secret",
+                    "permissions": ["read_tools", "read_queue"]
+                }],
+                "github_token": "ghp_secret"
+            },
+            "network_usage": {},
+            "stranded_compatibility_tools": [],
+            "startup_stages": [],
+            "restart_required": false,
+            "filesystem_profiles": [],
+            "broadcast_counters": { "dropped": 0, "coalesced": 0, "evictions": 0 },
+            "steam_installations": ["/home/deck/.steam/root"],
+            "inspection_progress": null,
+            "available_updates": [],
+            "prefix_restores": [],
+            "environment": {
+                "on_battery": null,
+                "metered_network": null,
+                "steam_started_at": null,
+                "running_game": null,
+                "game_mode": null,
+                "system_versions": {"steamos": null, "mesa": null, "kernel": null},
+                "clock_skew": null,
+                "github_reachability": null
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_app_state_is_stripped_of_app_names() {
+        let request = Request {
+            app_state: Some(app_state()),
+            ..Request::new(RequestType::UpdateState)
+        };
+
+        let filtered = filtered_request(&request);
+        let app_state = filtered.app_state.unwrap();
+        assert!(app_state.compatibility_tool_mappings.is_empty());
+        assert_eq!(app_state.installed_compatibility_tools.len(), 1);
+        assert!(app_state.installed_compatibility_tools[0]
+            .used_by_games
+            .is_empty());
+        assert!(app_state.installed_compatibility_tools[0]
+            .used_by_apps
+            .is_empty());
+        assert!(app_state.settings.access_tokens.is_empty());
+        assert!(app_state.settings.github_token.is_none());
+        let serialized = serde_json::to_string(&filtered_request(&request)).unwrap();
+        assert!(!serialized.contains("ELDEN RING"));
+        assert!(!serialized.contains("My Emulator Shortcut"));
+
+        // The plugin's own frontend sees everything
+        let unfiltered = filter_for_peer(&request, &PermissionSet::all()).unwrap();
+        assert_eq!(
+            unfiltered.app_state.unwrap().compatibility_tool_mappings[0].name,
+            "ELDEN RING"
+        );
+    }
+
+    fn filtered_request(request: &Request) -> Request {
+        filter_for_peer(request, &dashboard()).unwrap()
+    }
+
+    #[test]
+    fn test_state_deltas_are_diffed_after_filtering() {
+        let mut app_state = app_state();
+        app_state.compatibility_tool_mappings[0].name = "ELDEN RING NIGHTREIGN".to_string();
+        let request = Request {
+            app_state: Some(app_state),
+            state_delta: Some(StateDeltas {
+                base_revision: 1,
+                revision: 2,
+                deltas: Vec::new(),
+            }),
+            base_app_state: Some(Box::new(self::app_state())),
+            ..Request::new(RequestType::StateDelta)
+        };
+
+        let filtered = filtered_request(&request);
+        assert!(filtered.app_state.is_none());
+        assert!(filtered.state_delta.unwrap().deltas.is_empty());
+        let unfiltered = filter_for_peer(&request, &PermissionSet::all()).unwrap();
+        assert_eq!(unfiltered.state_delta.as_ref().unwrap().deltas.len(), 1);
+        assert!(serde_json::to_string(&unfiltered)
+            .unwrap()
+            .contains("ELDEN RING NIGHTREIGN"));
+    }
+
+    #[test]
+    fn test_activity_is_stripped_of_mapping_changes() {
+        let event = |change| ActivityEvent {
+            timestamp: 0,
+            source: ActivitySource::Task,
+            change,
+        };
+        let request = Request {
+            activity: Some(vec![
+                event(ActivityChange::ToolInstalled {
+                    internal_name: "GE-Proton9-20".to_string(),
+                    display_name: "GE-Proton9-20".to_string(),
+                }),
+                event(ActivityChange::MappingChanged {
+                    app_id: CompatAppId::new(3228583970).unwrap(),
+                    app_name: "My Emulator Shortcut".to_string(),
+                    from: None,
+                    to: Some("GE-Proton9-20".to_string()),
+                }),
+            ]),
+            ..Request::new(RequestType::Activity)
+        };
+
+        let activity = filtered_request(&request).activity.unwrap();
+        assert_eq!(activity.len(), 1);
+        assert!(matches!(
+            activity[0].change,
+            ActivityChange::ToolInstalled { .. }
+        ));
+        assert!(filter_for_peer(&request, &PermissionSet::new(&[Permission::ReadQueue])).is_none());
+    }
+
+    #[test]
+    fn test_notifications_about_apps_need_read_apps() {
+        let notification = |message: &str, app_ids: Option<Vec<CompatAppId>>| Request {
+            notification: Some(message.to_string()),
+            app_ids,
+            ..Request::new(RequestType::Notification)
+        };
+
+        let about_apps = notification(
+            "Restored the prefix of 1245620",
+            Some(vec![CompatAppId::new(1245620).unwrap()]),
+        );
+        assert!(filter_for_peer(&about_apps, &dashboard()).is_none());
+        assert!(filter_for_peer(&about_apps, &PermissionSet::all()).is_some());
+        let mapping_results = Request {
+            mapping_results: Some(Vec::new()),
+            ..notification(
+                "Changed 1245620 to GE-Proton9-20, restart Steam to apply",
+                None,
+            )
+        };
+        assert!(filter_for_peer(&mapping_results, &dashboard()).is_none());
+        let installed = notification("Installation Completed: GE-Proton9-20", None);
+        assert!(filter_for_peer(&installed, &dashboard()).is_some());
+    }
+
+    #[test]
+    fn test_storage_breakdown_is_stripped_of_app_names() {
+        let app_id = CompatAppId::new(1245620).unwrap();
+        let request = Request {
+            storage_breakdown: Some(StorageBreakdown {
+                library_folders: vec![LibraryFolderUsage {
+                    path: "/run/media/mmcblk0p1".to_string(),
+                    common_bytes: 0,
+                    compatdata_bytes: 300,
+                    shadercache_bytes: 200,
+                    downloading_bytes: 0,
+                    top_prefixes: vec![AppUsage {
+                        app_id,
+                        name: "ELDEN RING".to_string(),
+                        bytes: 300,
+                    }],
+                    top_shader_caches: vec![AppUsage {
+                        app_id,
+                        name: "ELDEN RING".to_string(),
+                        bytes: 200,
+                    }],
+                }],
+                computed_at: 0,
+            }),
+            ..Request::new(RequestType::StorageBreakdown)
+        };
+
+        let storage_breakdown = filtered_request(&request).storage_breakdown.unwrap();
+        let library_folder = &storage_breakdown.library_folders[0];
+        assert_eq!(library_folder.top_prefixes[0].name, "1245620");
+        assert_eq!(library_folder.top_shader_caches[0].name, "1245620");
+        assert_eq!(library_folder.compatdata_bytes, 300);
+    }
+
+    #[test]
+    fn test_requests_need_their_permission() {
+        let request = Request::new;
+        assert_eq!(
+            required_permission(&request(RequestType::UpdateSettings)),
+            Some(Permission::WriteConfig)
+        );
+        assert_eq!(
+            required_permission(&request(RequestType::GetStorageBreakdown)),
+            Some(Permission::ReadPrefixes)
+        );
+        assert_eq!(
+            required_permission(&request(RequestType::Notification)),
+            None
+        );
+        assert_eq!(
+            required_permission(&request(RequestType::Task)),
+            Some(Permission::ControlTasks)
+        );
+    }
+
+    #[test]
+    fn test_write_permissions_need_authentication() {
+        let access_token = |token: &str, permissions: &[Permission]| AccessToken {
+            name: "dashboard".to_string(),
+            token: token.to_string(),
+            permissions: PermissionSet::new(permissions),
+        };
+        let read_only = access_token("", &[Permission::ReadQueue, Permission::ReadTools]);
+        assert!(read_only.validate().is_ok());
+        let error = access_token("short", &[Permission::ReadQueue, Permission::ControlTasks])
+            .validate()
+            .unwrap_err();
+        assert!(error.starts_with("invalid_token: dashboard grants control_tasks"));
+
+        let secret = "0123456789abcdef0123";
+        let controlling = access_token(secret, &[Permission::ControlTasks]);
+        assert!(validate_access_tokens(&[read_only.clone(), controlling.clone()]).is_ok());
+        assert!(validate_access_tokens(&[controlling.clone(), controlling.clone()]).is_err());
+
+        let access_tokens = [read_only, controlling];
+        assert_eq!(
+            resolve_permissions(
+                &access_tokens,
+                token_from_query(Some("v=1&token=0123456789abcdef0123")).as_deref()
+            ),
+            Some(PermissionSet::new(&[Permission::ControlTasks]))
+        );
+        assert_eq!(resolve_permissions(&access_tokens, Some("guess")), None);
+        assert_eq!(
+            resolve_permissions(&access_tokens, None),
+            Some(PermissionSet::all())
+        );
+    }
+}
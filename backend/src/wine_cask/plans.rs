@@ -0,0 +1,672 @@
+use crate::app_id::CompatAppId;
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, WineCask};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::history::all_records;
+use crate::wine_cask::mutation_guard::{delete_dir_guarded, journal_directory};
+use crate::wine_cask::network_usage::format_bytes;
+use crate::wine_cask::provenance::current_timestamp;
+use crate::wine_cask::recursive_delete_dir_entry;
+use crate::wine_cask::rollback::kept_in_trash;
+use crate::wine_cask::storage::directory_size;
+use crate::PeerMap;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Seconds a plan can be executed after it was made.
+pub const PLAN_TTL: u64 = 10 * 60;
+/// Prefixes modified more recently than this get a risk note.
+const RECENT_USE: u64 = 30 * 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum PlanKind {
+    /// Trash left behind by deletions that couldn't finish.
+    Cleanup,
+    /// Prefixes of apps that are neither installed nor mapped.
+    OrphanPrefixes,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum ActionKind {
+    DeleteTrash,
+    DeletePrefix,
+    DeleteShaderCache,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct PlannedAction {
+    pub id: u32,
+    pub kind: ActionKind,
+    pub target: PathBuf,
+    pub app_id: Option<CompatAppId>,
+    pub reclaimed_bytes: u64,
+    /// What could be lost, shown next to the action.
+    pub risks: Vec<String>,
+    /// Actions that have to succeed before this one runs.
+    pub depends_on: Vec<u32>,
+}
+
+/// Proposed actions, nothing is touched until the plan is executed.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Plan {
+    pub id: u64,
+    pub kind: PlanKind,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub actions: Vec<PlannedAction>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum ActionOutcome {
+    Done,
+    Excluded,
+    /// The action no longer holds, e.g. its app was mapped since planning.
+    Skipped(String),
+    Failed(String),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ActionResult {
+    pub action_id: u32,
+    pub outcome: ActionOutcome,
+    pub reclaimed_bytes: u64,
+}
+
+/// Plans waiting to be executed, at most one per kind.
+#[derive(Clone, Default, Debug)]
+pub struct PlanStore {
+    plans: HashMap<PlanKind, Plan>,
+    next_id: u64,
+}
+
+impl PlanStore {
+    /// Stores a new plan, replacing the older plan of the same kind.
+    pub fn insert(&mut self, kind: PlanKind, actions: Vec<PlannedAction>, now: u64) -> Plan {
+        self.next_id += 1;
+        let plan = Plan {
+            id: self.next_id,
+            kind,
+            created_at: now,
+            expires_at: now + PLAN_TTL,
+            actions,
+        };
+        self.plans.insert(kind, plan.clone());
+        plan
+    }
+
+    /// Removes a plan to execute it, so it can't run twice.
+    pub fn take(&mut self, plan_id: u64, now: u64) -> Result<Plan, AppError> {
+        let kind = self
+            .plans
+            .values()
+            .find(|plan| plan.id == plan_id)
+            .map(|plan| plan.kind)
+            .ok_or_else(|| {
+                AppError::new(
+                    AppErrorCode::NotFound,
+                    format!("Plan {} was replaced, executed or never made", plan_id),
+                )
+            })?;
+        let plan = self.plans.remove(&kind).unwrap();
+        if plan.expires_at <= now {
+            return Err(AppError::new(
+                AppErrorCode::InvalidRequest,
+                format!("Plan {} expired, make a new one", plan_id),
+            ));
+        }
+        Ok(plan)
+    }
+}
+
+/// What an action is checked against when it's about to run.
+#[derive(Default, Debug)]
+pub struct PlanContext {
+    pub installed: HashSet<CompatAppId>,
+    pub mapped: HashSet<CompatAppId>,
+}
+
+fn trash_directories(library_folders: &[PathBuf], tools_directory: &Path) -> Vec<PathBuf> {
+    library_folders
+        .iter()
+        .flat_map(|library_folder| {
+            ["compatdata", "shadercache"]
+                .map(|directory| library_folder.join("steamapps").join(directory))
+        })
+        .chain([tools_directory.to_path_buf()])
+        .map(|directory| directory.join(".wine-cellar-trash"))
+        .collect()
+}
+
+/// Deletes every entry left in the trash next to prefixes, shader caches and tools, except the
+/// tools `kept` to roll back updates to.
+pub fn plan_cleanup(
+    library_folders: &[PathBuf],
+    tools_directory: &Path,
+    kept: &[PathBuf],
+) -> Vec<PlannedAction> {
+    let mut actions = Vec::new();
+    for trash in trash_directories(library_folders, tools_directory) {
+        let Ok(entries) = fs::read_dir(&trash) else {
+            continue;
+        };
+        for entry in entries
+            .filter_map(Result::ok)
+            .filter(|entry| !kept.contains(&entry.path()))
+        {
+            actions.push(PlannedAction {
+                id: actions.len() as u32,
+                kind: ActionKind::DeleteTrash,
+                target: entry.path(),
+                app_id: None,
+                reclaimed_bytes: directory_size(&entry.path()),
+                risks: Vec::new(),
+                depends_on: Vec::new(),
+            });
+        }
+    }
+    actions
+}
+
+fn prefix_risks(prefix: &Path, now: u64) -> Vec<String> {
+    let mut risks = Vec::new();
+    if prefix.join("pfx/drive_c/users").is_dir() {
+        risks.push("Saves kept in the prefix are lost unless Steam Cloud has them".to_string());
+    }
+    let modified_at = fs::metadata(prefix.join("pfx"))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs());
+    if let Some(modified_at) = modified_at.filter(|modified_at| modified_at + RECENT_USE > now) {
+        risks.push(format!(
+            "Used {} days ago",
+            now.saturating_sub(modified_at) / (24 * 60 * 60)
+        ));
+    }
+    risks
+}
+
+/// Deletes the prefixes of Steam apps that are neither installed nor mapped, along with their
+/// shader caches. Prefixes of shortcuts are left alone since their ids change when recreated.
+pub fn plan_orphan_prefixes(
+    library_folders: &[PathBuf],
+    context: &PlanContext,
+    now: u64,
+) -> Vec<PlannedAction> {
+    let mut actions: Vec<PlannedAction> = Vec::new();
+    for library_folder in library_folders {
+        let steamapps = library_folder.join("steamapps");
+        let Ok(entries) = fs::read_dir(steamapps.join("compatdata")) else {
+            continue;
+        };
+        let mut prefixes: Vec<(CompatAppId, PathBuf)> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let app_id = CompatAppId::parse(&entry.file_name().to_string_lossy()).ok()?;
+                Some((app_id, entry.path()))
+            })
+            .filter(|(app_id, prefix)| {
+                app_id.app_id().is_some()
+                    && prefix.is_dir()
+                    && validate_app(*app_id, context).is_ok()
+            })
+            .collect();
+        prefixes.sort();
+        for (app_id, prefix) in prefixes {
+            let prefix_action = actions.len() as u32;
+            actions.push(PlannedAction {
+                id: prefix_action,
+                kind: ActionKind::DeletePrefix,
+                reclaimed_bytes: directory_size(&prefix),
+                risks: prefix_risks(&prefix, now),
+                target: prefix,
+                app_id: Some(app_id),
+                depends_on: Vec::new(),
+            });
+            let shader_cache = steamapps.join("shadercache").join(app_id.to_string());
+            if shader_cache.is_dir() {
+                actions.push(PlannedAction {
+                    id: actions.len() as u32,
+                    kind: ActionKind::DeleteShaderCache,
+                    reclaimed_bytes: directory_size(&shader_cache),
+                    target: shader_cache,
+                    app_id: Some(app_id),
+                    risks: Vec::new(),
+                    // Only goes along with the prefix
+                    depends_on: vec![prefix_action],
+                });
+            }
+        }
+    }
+    actions
+}
+
+fn validate_app(app_id: CompatAppId, context: &PlanContext) -> Result<(), String> {
+    if context.installed.contains(&app_id) {
+        return Err(format!("{} is installed", app_id));
+    }
+    if context.mapped.contains(&app_id) {
+        return Err(format!("{} is mapped to a compatibility tool", app_id));
+    }
+    Ok(())
+}
+
+/// Checks an action still holds, the plan may be older than the latest changes.
+fn revalidate(action: &PlannedAction, context: &PlanContext) -> Result<(), String> {
+    if !action.target.exists() {
+        return Err(format!("{} is gone", action.target.display()));
+    }
+    match (action.kind, action.app_id) {
+        (ActionKind::DeletePrefix | ActionKind::DeleteShaderCache, Some(app_id)) => {
+            validate_app(app_id, context)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Actions with their dependencies first, in plan order otherwise.
+fn execution_order(actions: &[PlannedAction]) -> Vec<&PlannedAction> {
+    let mut ordered: Vec<&PlannedAction> = Vec::new();
+    let mut placed: HashSet<u32> = HashSet::new();
+    while ordered.len() < actions.len() {
+        let before = ordered.len();
+        for action in actions {
+            if !placed.contains(&action.id)
+                && action.depends_on.iter().all(|id| placed.contains(id))
+            {
+                placed.insert(action.id);
+                ordered.push(action);
+            }
+        }
+        if ordered.len() == before {
+            // Cycles or unknown dependencies, they are skipped since their dependencies never run
+            ordered.extend(actions.iter().filter(|action| !placed.contains(&action.id)));
+            break;
+        }
+    }
+    ordered
+}
+
+fn run_action(action: &PlannedAction, journal_directory: &Path) -> Result<u64, String> {
+    let bytes = directory_size(&action.target);
+    let result = match action.kind {
+        // Already in the trash, there's nothing left to guard
+        ActionKind::DeleteTrash => recursive_delete_dir_entry(&action.target),
+        ActionKind::DeletePrefix | ActionKind::DeleteShaderCache => {
+            delete_dir_guarded(journal_directory, &action.target)
+        }
+    };
+    result
+        .map(|_| bytes)
+        .map_err(|err| format!("Failed to delete {}: {}", action.target.display(), err))
+}
+
+/// Runs every action that isn't excluded and still holds, returning the results in plan order.
+pub fn execute_plan(
+    plan: &Plan,
+    excluded_action_ids: &[u32],
+    context: &PlanContext,
+    journal_directory: &Path,
+) -> Vec<ActionResult> {
+    let mut outcomes: HashMap<u32, ActionResult> = HashMap::new();
+    for action in execution_order(&plan.actions) {
+        let unmet = action.depends_on.iter().find(|id| {
+            outcomes
+                .get(*id)
+                .is_none_or(|result| result.outcome != ActionOutcome::Done)
+        });
+        let outcome = if excluded_action_ids.contains(&action.id) {
+            Err(ActionOutcome::Excluded)
+        } else if let Some(id) = unmet {
+            Err(ActionOutcome::Skipped(format!(
+                "Depends on action {}, which didn't run",
+                id
+            )))
+        } else {
+            revalidate(action, context).map_err(ActionOutcome::Skipped)
+        };
+        let result = match outcome
+            .and_then(|_| run_action(action, journal_directory).map_err(ActionOutcome::Failed))
+        {
+            Ok(reclaimed_bytes) => ActionResult {
+                action_id: action.id,
+                outcome: ActionOutcome::Done,
+                reclaimed_bytes,
+            },
+            Err(outcome) => ActionResult {
+                action_id: action.id,
+                outcome,
+                reclaimed_bytes: 0,
+            },
+        };
+        outcomes.insert(action.id, result);
+    }
+    plan.actions
+        .iter()
+        .filter_map(|action| outcomes.remove(&action.id))
+        .collect()
+}
+
+impl WineCask {
+    async fn plan_context(&self) -> Result<PlanContext, AppError> {
+        // Without the installed apps every prefix would look orphaned, tools have prefixes too
+        let installed = self
+            .steam_util
+            .list_installed_apps()
+            .map_err(|err| AppError::from(err).context("Failed to get list of installed apps"))?
+            .into_iter()
+            .map(|app| CompatAppId::from(app.app_id))
+            .collect();
+        let mapped = self
+            .app_state
+            .lock()
+            .await
+            .compatibility_tool_mappings
+            .iter()
+            .map(|mapping| mapping.app_id)
+            .collect();
+        Ok(PlanContext { installed, mapped })
+    }
+
+    /// Plans the actions of `kind` and sends them back, invalidating the older plan of the kind.
+    pub async fn create_plan(&self, peer_map: &PeerMap, kind: PlanKind) {
+        let library_folders = self.mounted_library_folders().await;
+        let actions = match kind {
+            PlanKind::Cleanup => {
+                let tools_directory = self.steam_util.get_steam_compatibility_tools_directory();
+                let kept = kept_in_trash(&all_records(), current_timestamp());
+                tokio::task::spawn_blocking(move || {
+                    plan_cleanup(&library_folders, &tools_directory, &kept)
+                })
+                .await
+                .unwrap()
+            }
+            PlanKind::OrphanPrefixes => match self.plan_context().await {
+                Ok(context) => tokio::task::spawn_blocking(move || {
+                    plan_orphan_prefixes(&library_folders, &context, current_timestamp())
+                })
+                .await
+                .unwrap(),
+                Err(app_error) => {
+                    error!("{}", app_error);
+                    self.broadcast_app_error(peer_map, app_error).await;
+                    return;
+                }
+            },
+        };
+
+        let plan = self
+            .app_state
+            .lock()
+            .await
+            .plans
+            .insert(kind, actions, current_timestamp());
+        info!(
+            "Planned {} actions for {:?} as plan {}",
+            plan.actions.len(),
+            kind,
+            plan.id
+        );
+        broadcast_to_peers(
+            peer_map,
+            &Request {
+                plan: Some(plan),
+                ..Request::new(RequestType::Plan)
+            },
+        )
+        .await;
+    }
+
+    /// Runs a plan made earlier, minus the excluded actions.
+    pub async fn execute_plan(
+        &self,
+        peer_map: &PeerMap,
+        plan_id: u64,
+        excluded_action_ids: Vec<u32>,
+    ) {
+        let taken = self
+            .app_state
+            .lock()
+            .await
+            .plans
+            .take(plan_id, current_timestamp());
+        let planned = match taken {
+            Ok(plan) => self.plan_context().await.map(|context| (plan, context)),
+            Err(app_error) => Err(app_error),
+        };
+        let (plan, context) = match planned {
+            Ok(planned) => planned,
+            Err(app_error) => {
+                error!("{}", app_error);
+                self.broadcast_app_error(peer_map, app_error).await;
+                return;
+            }
+        };
+
+        let results = tokio::task::spawn_blocking(move || {
+            execute_plan(&plan, &excluded_action_ids, &context, &journal_directory())
+        })
+        .await
+        .unwrap();
+        let reclaimed_bytes: u64 = results.iter().map(|result| result.reclaimed_bytes).sum();
+        let not_done = results
+            .iter()
+            .filter(|result| result.outcome != ActionOutcome::Done)
+            .count();
+        let message = format!(
+            "Executed plan {}, freed {}, {} of {} actions didn't run",
+            plan_id,
+            format_bytes(reclaimed_bytes),
+            not_done,
+            results.len()
+        );
+        info!("{}", message);
+        self.invalidate_storage_breakdown().await;
+        broadcast_to_peers(
+            peer_map,
+            &Request {
+                plan_results: Some(results),
+                ..Request::new(RequestType::PlanExecuted)
+            },
+        )
+        .await;
+        self.broadcast_notification(peer_map, &message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_id::AppId;
+    use tempfile::tempdir;
+
+    fn app(app_id: u32) -> CompatAppId {
+        CompatAppId::from(AppId::new(app_id).unwrap())
+    }
+
+    fn library_folder(root: &Path) -> PathBuf {
+        let steamapps = root.join("steamapps");
+        for app_id in ["1245620", "292030", "730"] {
+            fs::create_dir_all(steamapps.join("compatdata").join(app_id).join("pfx")).unwrap();
+            fs::write(
+                steamapps
+                    .join("compatdata")
+                    .join(app_id)
+                    .join("pfx/system.reg"),
+                vec![0; 100],
+            )
+            .unwrap();
+        }
+        fs::create_dir_all(steamapps.join("shadercache/1245620")).unwrap();
+        fs::write(steamapps.join("shadercache/1245620/cache.foz"), vec![0; 50]).unwrap();
+        root.to_path_buf()
+    }
+
+    fn context(installed: &[u32], mapped: &[u32]) -> PlanContext {
+        PlanContext {
+            installed: installed.iter().map(|app_id| app(*app_id)).collect(),
+            mapped: mapped.iter().map(|app_id| app(*app_id)).collect(),
+        }
+    }
+
+    fn outcomes(results: &[ActionResult]) -> Vec<(u32, ActionOutcome)> {
+        results
+            .iter()
+            .map(|result| (result.action_id, result.outcome.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_orphan_prefixes_are_planned_with_their_shader_caches() {
+        let root = tempdir().unwrap();
+        let library_folder = library_folder(root.path());
+        let actions = plan_orphan_prefixes(&[library_folder], &context(&[730], &[]), 0);
+        let planned: Vec<(u32, ActionKind, Option<CompatAppId>, Vec<u32>)> = actions
+            .iter()
+            .map(|action| {
+                (
+                    action.id,
+                    action.kind,
+                    action.app_id,
+                    action.depends_on.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            planned,
+            vec![
+                (0, ActionKind::DeletePrefix, Some(app(292030)), vec![]),
+                (1, ActionKind::DeletePrefix, Some(app(1245620)), vec![]),
+                (
+                    2,
+                    ActionKind::DeleteShaderCache,
+                    Some(app(1245620)),
+                    vec![1]
+                ),
+            ]
+        );
+        assert_eq!(actions[1].reclaimed_bytes, 100);
+        assert_eq!(actions[2].reclaimed_bytes, 50);
+    }
+
+    #[test]
+    fn test_excluded_actions_and_their_dependents_are_kept() {
+        let root = tempdir().unwrap();
+        let journal = tempdir().unwrap();
+        let library_folder = library_folder(root.path());
+        let context = context(&[730], &[]);
+        let mut store = PlanStore::default();
+        let actions = plan_orphan_prefixes(std::slice::from_ref(&library_folder), &context, 0);
+        let plan = store.insert(PlanKind::OrphanPrefixes, actions, 0);
+
+        let results = execute_plan(&plan, &[1], &context, journal.path());
+        assert_eq!(
+            outcomes(&results),
+            vec![
+                (0, ActionOutcome::Done),
+                (1, ActionOutcome::Excluded),
+                (
+                    2,
+                    ActionOutcome::Skipped("Depends on action 1, which didn't run".to_string())
+                ),
+            ]
+        );
+        assert_eq!(results[0].reclaimed_bytes, 100);
+        let steamapps = library_folder.join("steamapps");
+        assert!(!steamapps.join("compatdata/292030").exists());
+        assert!(steamapps.join("compatdata/1245620").exists());
+        assert!(steamapps.join("shadercache/1245620").exists());
+    }
+
+    #[test]
+    fn test_stale_plans_are_revalidated() {
+        let root = tempdir().unwrap();
+        let journal = tempdir().unwrap();
+        let library_folder = library_folder(root.path());
+        let mut store = PlanStore::default();
+        let actions = plan_orphan_prefixes(
+            std::slice::from_ref(&library_folder),
+            &context(&[730], &[]),
+            0,
+        );
+        let older = store.insert(PlanKind::OrphanPrefixes, actions.clone(), 0);
+        let plan = store.insert(PlanKind::OrphanPrefixes, actions, 0);
+
+        // Re-planning invalidated the older plan
+        assert_eq!(
+            store.take(older.id, 0).unwrap_err().code,
+            AppErrorCode::NotFound
+        );
+        let plan = store.take(plan.id, 0).unwrap();
+        assert!(store.take(plan.id, 0).is_err());
+
+        // 1245620 got mapped since planning
+        let results = execute_plan(&plan, &[], &context(&[730], &[1245620]), journal.path());
+        assert_eq!(
+            outcomes(&results),
+            vec![
+                (0, ActionOutcome::Done),
+                (
+                    1,
+                    ActionOutcome::Skipped("1245620 is mapped to a compatibility tool".to_string())
+                ),
+                (
+                    2,
+                    ActionOutcome::Skipped("Depends on action 1, which didn't run".to_string())
+                ),
+            ]
+        );
+        assert!(library_folder
+            .join("steamapps/compatdata/1245620/pfx")
+            .is_dir());
+
+        let expired = store.insert(PlanKind::Cleanup, Vec::new(), 0);
+        assert_eq!(
+            store.take(expired.id, PLAN_TTL).unwrap_err().code,
+            AppErrorCode::InvalidRequest
+        );
+    }
+
+    #[test]
+    fn test_cleanup_purges_leftover_trash() {
+        let root = tempdir().unwrap();
+        let tools_directory = tempdir().unwrap();
+        let journal = tempdir().unwrap();
+        let library_folder = library_folder(root.path());
+        let trash = library_folder.join("steamapps/compatdata/.wine-cellar-trash/730-1");
+        fs::create_dir_all(&trash).unwrap();
+        fs::write(trash.join("user.reg"), vec![0; 20]).unwrap();
+        let tool_trash = tools_directory
+            .path()
+            .join(".wine-cellar-trash/GE-Proton8-25-1");
+        fs::create_dir_all(&tool_trash).unwrap();
+        let kept = tools_directory
+            .path()
+            .join(".wine-cellar-trash/GE-Proton8-24-1");
+        fs::create_dir_all(&kept).unwrap();
+
+        let actions = plan_cleanup(
+            std::slice::from_ref(&library_folder),
+            tools_directory.path(),
+            std::slice::from_ref(&kept),
+        );
+        let targets: Vec<&Path> = actions
+            .iter()
+            .map(|action| action.target.as_path())
+            .collect();
+        assert_eq!(targets, vec![trash.as_path(), tool_trash.as_path()]);
+
+        let plan = PlanStore::default().insert(PlanKind::Cleanup, actions, 0);
+        fs::remove_dir(&tool_trash).unwrap();
+        let results = execute_plan(&plan, &[], &PlanContext::default(), journal.path());
+        assert_eq!(results[0].outcome, ActionOutcome::Done);
+        assert_eq!(results[0].reclaimed_bytes, 20);
+        assert!(matches!(results[1].outcome, ActionOutcome::Skipped(_)));
+        assert!(!trash.exists());
+        assert!(kept.is_dir());
+        // The prefixes next to the trash are left alone
+        assert!(library_folder.join("steamapps/compatdata/730").is_dir());
+    }
+}
@@ -0,0 +1,654 @@
+use crate::app_id::CompatAppId;
+use crate::wine_cask::app::{next_task_id, WineCask};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::atomic_install::replace_directory;
+use crate::wine_cask::extraction::sanitize;
+use crate::wine_cask::install::CompressionType;
+use crate::wine_cask::local_install::{sniff_archive_file, unsupported_archive};
+use crate::wine_cask::partial_update::decompressor;
+use crate::wine_cask::{copy_entry, recursive_delete_dir_entry};
+use crate::PeerMap;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+use tokio::sync::oneshot;
+
+/// Paths below `pfx` where games keep their saves, unless the settings list others.
+pub const DEFAULT_SAVE_PATHS: [&str; 3] = [
+    "drive_c/users/steamuser/Documents",
+    "drive_c/users/steamuser/AppData",
+    "drive_c/users/steamuser/Saved Games",
+];
+
+/// Directory in `compatdata` a restore is put together in before it replaces the prefix.
+const RESTORE_DIRECTORY_PREFIX: &str = ".wine-cellar-restore-";
+
+/// A backup of an app's compatdata directory to restore over its prefix.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RestorePrefixBackup {
+    pub app_id: CompatAppId,
+    /// Absolute path of a tar archive of the compatdata directory, compressed or not, e.g. with
+    /// `pfx/drive_c` at its top.
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum PrefixRestoreState {
+    /// Comparing the saves in the backup with the ones in the prefix.
+    Comparing,
+    /// The prefix has newer saves, nothing is restored until the frontend decides.
+    WaitingForDecision,
+    Restoring,
+}
+
+/// A restore that is running, listed in the state.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PrefixRestore {
+    /// Task id a `ResolveRestoreConflict` refers to.
+    pub id: u64,
+    pub app_id: CompatAppId,
+    pub state: PrefixRestoreState,
+    /// Saves restoring the backup would lose, filled in once waiting for a decision.
+    pub conflicts: Vec<SaveConflict>,
+}
+
+/// A save in the prefix that is newer than the one in the backup, or missing from it.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct SaveConflict {
+    /// Relative to the compatdata directory, e.g. `pfx/drive_c/users/steamuser/Documents/save`.
+    pub path: String,
+    pub live_modified: u64,
+    pub live_size: u64,
+    /// `None` if the backup doesn't have the file.
+    pub backup_modified: Option<u64>,
+    pub backup_size: Option<u64>,
+}
+
+/// How the frontend resolves the conflicts of a restore.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum RestoreDecision {
+    /// Restore everything but the save paths, which keep what the prefix has.
+    KeepLiveSaves,
+    /// Restore everything, the saves included.
+    Overwrite,
+    /// Leave the prefix as it is.
+    Abort,
+}
+
+/// Restores waiting for a decision, by task id.
+#[derive(Clone, Default)]
+pub struct RestoreDecisions(Arc<Mutex<HashMap<u64, oneshot::Sender<RestoreDecision>>>>);
+
+impl RestoreDecisions {
+    /// Registers the restore `id` as waiting, the receiver gets the decision once it's made.
+    pub fn wait(&self, id: u64) -> oneshot::Receiver<RestoreDecision> {
+        let (sender, receiver) = oneshot::channel();
+        self.0.lock().unwrap().insert(id, sender);
+        receiver
+    }
+
+    /// Hands `decision` to the restore `id`, false if it isn't waiting for one.
+    pub fn decide(&self, id: u64, decision: RestoreDecision) -> bool {
+        match self.0.lock().unwrap().remove(&id) {
+            Some(sender) => sender.send(decision).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// Modification time in seconds and size of a file.
+type FileStamp = (u64, u64);
+
+fn open_backup(path: &Path) -> io::Result<tar::Archive<Box<dyn Read>>> {
+    match sniff_archive_file(path)? {
+        Some(
+            compress_type @ (CompressionType::Gzip
+            | CompressionType::Xz
+            | CompressionType::Zstd
+            | CompressionType::Tar),
+        ) => {
+            let archive = BufReader::new(File::open(path)?);
+            Ok(tar::Archive::new(decompressor(archive, &compress_type)))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}, backups are tar archives",
+                unsupported_archive(&path.display().to_string())
+            ),
+        )),
+    }
+}
+
+/// Whether `relative`, a path below the compatdata directory, is in one of the save paths.
+fn is_save_path(relative: &Path, save_paths: &[String]) -> bool {
+    save_paths
+        .iter()
+        .any(|save_path| relative.starts_with(Path::new("pfx").join(save_path)))
+}
+
+/// Files of the backup in the save paths, by their path below the compatdata directory.
+pub fn backup_saves(
+    backup: &Path,
+    save_paths: &[String],
+) -> io::Result<HashMap<PathBuf, FileStamp>> {
+    let mut saves = HashMap::new();
+    for entry in open_backup(backup)?.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let Some(relative) = sanitize(&entry.path()?) else {
+            continue;
+        };
+        if is_save_path(&relative, save_paths) {
+            saves.insert(relative, (entry.header().mtime()?, entry.size()));
+        }
+    }
+    Ok(saves)
+}
+
+/// Collects the regular files below `directory`, symlinks aren't followed.
+fn collect_files(directory: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Saves of the prefix in `compat_data` that restoring would lose: files that are newer than
+/// the backup's, differ in size at the same time, or that the backup doesn't have.
+pub fn save_conflicts(
+    compat_data: &Path,
+    backup_saves: &HashMap<PathBuf, FileStamp>,
+    save_paths: &[String],
+) -> io::Result<Vec<SaveConflict>> {
+    let mut files = Vec::new();
+    for save_path in save_paths {
+        let directory = compat_data.join("pfx").join(save_path);
+        if directory.is_dir() {
+            collect_files(&directory, &mut files)?;
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    for file in files {
+        let metadata = fs::metadata(&file)?;
+        let live_modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let live_size = metadata.len();
+        let relative = file.strip_prefix(compat_data).unwrap_or(&file);
+        let backup = backup_saves.get(relative).copied();
+        let conflicting = match backup {
+            Some((backup_modified, backup_size)) => {
+                live_modified > backup_modified
+                    || (live_modified == backup_modified && live_size != backup_size)
+            }
+            None => true,
+        };
+        if conflicting {
+            conflicts.push(SaveConflict {
+                path: relative.to_string_lossy().to_string(),
+                live_modified,
+                live_size,
+                backup_modified: backup.map(|(modified, _)| modified),
+                backup_size: backup.map(|(_, size)| size),
+            });
+        }
+    }
+    conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(conflicts)
+}
+
+/// Replaces the prefix in `compat_data` with the contents of `backup`, unless the decision is to
+/// abort. With `KeepLiveSaves` the backup's entries in the save paths are skipped and the
+/// prefix's saves are copied over instead. Returns whether anything was restored.
+///
+/// The backup is put together next to the prefix, which is only replaced once it's complete.
+pub fn restore_backup(
+    backup: &Path,
+    compat_data: &Path,
+    save_paths: &[String],
+    decision: RestoreDecision,
+) -> io::Result<bool> {
+    if decision == RestoreDecision::Abort {
+        return Ok(false);
+    }
+    let name = compat_data.file_name().unwrap_or_default();
+    let temp_directory = compat_data.with_file_name(format!(
+        "{}{}",
+        RESTORE_DIRECTORY_PREFIX,
+        name.to_string_lossy()
+    ));
+    if fs::symlink_metadata(&temp_directory).is_ok() {
+        recursive_delete_dir_entry(&temp_directory)?;
+    }
+    let staged = temp_directory.join(name);
+    fs::create_dir_all(&staged)?;
+
+    let restored = stage_backup(
+        backup,
+        compat_data,
+        &staged,
+        save_paths,
+        decision == RestoreDecision::KeepLiveSaves,
+    )
+    .and_then(|_| replace_directory(&staged, compat_data, &temp_directory));
+    // The next restore deletes a leftover directory, so its failure mustn't hide the outcome
+    if let Err(err) = recursive_delete_dir_entry(&temp_directory) {
+        warn!("Failed to delete {}: {}", temp_directory.display(), err);
+    }
+    restored.map(|_| true)
+}
+
+/// Extracts `backup` into `staged`, with the saves of the prefix instead of the backup's if
+/// `keep_live_saves`.
+fn stage_backup(
+    backup: &Path,
+    compat_data: &Path,
+    staged: &Path,
+    save_paths: &[String],
+    keep_live_saves: bool,
+) -> io::Result<()> {
+    for entry in open_backup(backup)?.entries()? {
+        let mut entry = entry?;
+        let Some(relative) = sanitize(&entry.path()?) else {
+            continue;
+        };
+        if keep_live_saves && is_save_path(&relative, save_paths) {
+            continue;
+        }
+        entry.unpack_in(staged)?;
+    }
+    if keep_live_saves {
+        for save_path in save_paths {
+            let live = compat_data.join("pfx").join(save_path);
+            if fs::symlink_metadata(&live).is_err() {
+                continue;
+            }
+            let destination = staged.join("pfx").join(save_path);
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            copy_entry(&live, &destination, true)?;
+        }
+    }
+    Ok(())
+}
+
+impl WineCask {
+    /// The prefix of `app_id` in whichever library folder has it.
+    fn find_compat_data(&self, app_id: CompatAppId) -> Result<PathBuf, AppError> {
+        self.steam_util
+            .list_library_folders()?
+            .into_iter()
+            .map(|library_folder| {
+                library_folder
+                    .join("steamapps/compatdata")
+                    .join(app_id.to_string())
+            })
+            .find(|compat_data| compat_data.is_dir())
+            .ok_or_else(|| {
+                AppError::new(
+                    AppErrorCode::NotFound,
+                    format!("{} has no prefix to restore the backup into", app_id),
+                )
+            })
+    }
+
+    async fn set_prefix_restore(&self, peer_map: &PeerMap, prefix_restore: PrefixRestore) {
+        let mut app_state = self.app_state.lock().await;
+        match app_state
+            .prefix_restores
+            .iter_mut()
+            .find(|restore| restore.id == prefix_restore.id)
+        {
+            Some(restore) => *restore = prefix_restore,
+            None => app_state.prefix_restores.push(prefix_restore),
+        }
+        drop(app_state);
+        self.broadcast_app_state(peer_map).await;
+    }
+
+    /// Restores a backup over the prefix of an app. If the prefix has newer saves the restore
+    /// waits in `WaitingForDecision` until the frontend resolves the conflict.
+    pub async fn restore_prefix_backup(&self, peer_map: &PeerMap, restore: RestorePrefixBackup) {
+        let id = next_task_id();
+        let mut prefix_restore = PrefixRestore {
+            id,
+            app_id: restore.app_id,
+            state: PrefixRestoreState::Comparing,
+            conflicts: Vec::new(),
+        };
+        if let Err(app_error) = self
+            .run_prefix_restore(peer_map, &restore, &mut prefix_restore)
+            .await
+        {
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error.for_task(id))
+                .await;
+        }
+        self.app_state
+            .lock()
+            .await
+            .prefix_restores
+            .retain(|restore| restore.id != id);
+        self.broadcast_app_state(peer_map).await;
+    }
+
+    async fn run_prefix_restore(
+        &self,
+        peer_map: &PeerMap,
+        restore: &RestorePrefixBackup,
+        prefix_restore: &mut PrefixRestore,
+    ) -> Result<(), AppError> {
+        let compat_data = self.find_compat_data(restore.app_id)?;
+        self.refuse_while_running(peer_map, "restore its prefix", |app_id| {
+            CompatAppId::from(app_id) == restore.app_id
+        })
+        .await?;
+        self.set_prefix_restore(peer_map, prefix_restore.clone())
+            .await;
+
+        let save_paths: Vec<String> = match &self.app_state.lock().await.settings.save_paths {
+            Some(save_paths) => save_paths.clone(),
+            None => DEFAULT_SAVE_PATHS.map(String::from).to_vec(),
+        };
+        let backup = PathBuf::from(&restore.path);
+        let conflicts = {
+            let (backup, compat_data, save_paths) =
+                (backup.clone(), compat_data.clone(), save_paths.clone());
+            tokio::task::spawn_blocking(move || {
+                let backup_saves = backup_saves(&backup, &save_paths)?;
+                save_conflicts(&compat_data, &backup_saves, &save_paths)
+            })
+            .await
+            .map_err(|err| AppError::internal(err.to_string()))?
+            .map_err(|err| AppError::from(err).context("Failed to compare the saves"))?
+        };
+
+        let decision = if conflicts.is_empty() {
+            RestoreDecision::Overwrite
+        } else {
+            info!(
+                "{} has {} saves newer than the backup, waiting for a decision",
+                restore.app_id,
+                conflicts.len()
+            );
+            let decision = self
+                .app_state
+                .lock()
+                .await
+                .restore_decisions
+                .wait(prefix_restore.id);
+            prefix_restore.state = PrefixRestoreState::WaitingForDecision;
+            prefix_restore.conflicts = conflicts;
+            self.set_prefix_restore(peer_map, prefix_restore.clone())
+                .await;
+            // The sender is only dropped along with the state, e.g. on shutdown
+            decision.await.unwrap_or(RestoreDecision::Abort)
+        };
+        if decision == RestoreDecision::Abort {
+            info!("Restore of the prefix of {} aborted", restore.app_id);
+            self.broadcast_app_notification(
+                peer_map,
+                &format!("Restore of the prefix of {} aborted", restore.app_id),
+                vec![restore.app_id],
+            )
+            .await;
+            return Ok(());
+        }
+        // Games may have been started while waiting for the decision
+        self.refuse_while_running(peer_map, "restore its prefix", |app_id| {
+            CompatAppId::from(app_id) == restore.app_id
+        })
+        .await?;
+
+        prefix_restore.state = PrefixRestoreState::Restoring;
+        self.set_prefix_restore(peer_map, prefix_restore.clone())
+            .await;
+        let restored = {
+            let compat_data = compat_data.clone();
+            tokio::task::spawn_blocking(move || {
+                restore_backup(&backup, &compat_data, &save_paths, decision)
+            })
+            .await
+            .map_err(|err| AppError::internal(err.to_string()))?
+            .map_err(|err| AppError::from(err).context("Failed to restore the backup"))?
+        };
+        if restored {
+            info!(
+                "Restored {} over {} ({:?})",
+                restore.path,
+                compat_data.display(),
+                decision
+            );
+            let message = match decision {
+                RestoreDecision::KeepLiveSaves => format!(
+                    "Restored the prefix of {}, its newer saves were kept",
+                    restore.app_id
+                ),
+                _ => format!("Restored the prefix of {}", restore.app_id),
+            };
+            self.broadcast_app_notification(peer_map, &message, vec![restore.app_id])
+                .await;
+        } else {
+            warn!("Nothing restored for {}", restore.app_id);
+        }
+        Ok(())
+    }
+
+    /// Resumes the restore `task_id` waiting for a decision.
+    pub async fn resolve_restore_conflict(
+        &self,
+        peer_map: &PeerMap,
+        task_id: u64,
+        decision: RestoreDecision,
+    ) {
+        let decided = self
+            .app_state
+            .lock()
+            .await
+            .restore_decisions
+            .decide(task_id, decision);
+        if !decided {
+            let app_error = AppError::new(
+                AppErrorCode::NotFound,
+                format!("Restore {} isn't waiting for a decision", task_id),
+            );
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use tempfile::tempdir;
+
+    const DOCUMENTS: &str = "pfx/drive_c/users/steamuser/Documents";
+
+    fn save_paths() -> Vec<String> {
+        DEFAULT_SAVE_PATHS.map(String::from).to_vec()
+    }
+
+    /// Writes a tar archive of `files`, each modified at the given time.
+    fn write_backup(path: &Path, files: &[(&str, &str, u64)]) {
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+        for (name, contents, modified) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(*modified);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, contents.as_bytes())
+                .unwrap();
+        }
+        builder.finish().unwrap();
+    }
+
+    fn write_live(compat_data: &Path, name: &str, contents: &str, modified: u64) {
+        let path = compat_data.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified))
+            .unwrap();
+    }
+
+    /// A prefix whose save was played on since the backup was made.
+    fn newer_save() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let dir = tempdir().unwrap();
+        let backup = dir.path().join("backup.tar");
+        write_backup(
+            &backup,
+            &[
+                ("pfx/system.reg", "backed up registry", 1_000),
+                (&format!("{}/Game/save.dat", DOCUMENTS), "old save", 1_000),
+            ],
+        );
+        let compat_data = dir.path().join("compatdata/1245620");
+        write_live(&compat_data, "pfx/system.reg", "broken registry", 3_000);
+        write_live(
+            &compat_data,
+            &format!("{}/Game/save.dat", DOCUMENTS),
+            "new save",
+            2_000,
+        );
+        (dir, backup, compat_data)
+    }
+
+    fn read(compat_data: &Path, name: &str) -> String {
+        fs::read_to_string(compat_data.join(name)).unwrap()
+    }
+
+    #[test]
+    fn test_newer_saves_conflict() {
+        let (_dir, backup, compat_data) = newer_save();
+        write_live(
+            &compat_data,
+            "pfx/drive_c/users/steamuser/AppData/Local/Game/settings.ini",
+            "only live",
+            500,
+        );
+        let backup_saves = backup_saves(&backup, &save_paths()).unwrap();
+        assert_eq!(backup_saves.len(), 1);
+
+        let conflicts = save_conflicts(&compat_data, &backup_saves, &save_paths()).unwrap();
+        assert_eq!(
+            conflicts,
+            vec![
+                SaveConflict {
+                    path: "pfx/drive_c/users/steamuser/AppData/Local/Game/settings.ini".to_string(),
+                    live_modified: 500,
+                    live_size: 9,
+                    backup_modified: None,
+                    backup_size: None,
+                },
+                SaveConflict {
+                    path: format!("{}/Game/save.dat", DOCUMENTS),
+                    live_modified: 2_000,
+                    live_size: 8,
+                    backup_modified: Some(1_000),
+                    backup_size: Some(8),
+                },
+            ]
+        );
+
+        // Files outside the save paths are restored without asking
+        write_live(
+            &compat_data,
+            &format!("{}/Game/save.dat", DOCUMENTS),
+            "old save",
+            1_000,
+        );
+        fs::remove_dir_all(compat_data.join("pfx/drive_c/users/steamuser/AppData")).unwrap();
+        assert_eq!(
+            save_conflicts(&compat_data, &backup_saves, &save_paths()).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_keep_live_saves_restores_the_rest() {
+        let (_dir, backup, compat_data) = newer_save();
+        assert!(restore_backup(
+            &backup,
+            &compat_data,
+            &save_paths(),
+            RestoreDecision::KeepLiveSaves
+        )
+        .unwrap());
+        assert_eq!(read(&compat_data, "pfx/system.reg"), "backed up registry");
+        assert_eq!(
+            read(&compat_data, &format!("{}/Game/save.dat", DOCUMENTS)),
+            "new save"
+        );
+        assert!(!compat_data
+            .with_file_name(".wine-cellar-restore-1245620")
+            .exists());
+    }
+
+    #[test]
+    fn test_overwrite_restores_everything() {
+        let (_dir, backup, compat_data) = newer_save();
+        write_live(&compat_data, "pfx/user.reg", "not in the backup", 3_000);
+        assert!(restore_backup(
+            &backup,
+            &compat_data,
+            &save_paths(),
+            RestoreDecision::Overwrite
+        )
+        .unwrap());
+        assert_eq!(read(&compat_data, "pfx/system.reg"), "backed up registry");
+        assert_eq!(
+            read(&compat_data, &format!("{}/Game/save.dat", DOCUMENTS)),
+            "old save"
+        );
+        assert!(!compat_data.join("pfx/user.reg").exists());
+    }
+
+    #[test]
+    fn test_abort_leaves_the_prefix() {
+        let (_dir, backup, compat_data) = newer_save();
+        assert!(
+            !restore_backup(&backup, &compat_data, &save_paths(), RestoreDecision::Abort).unwrap()
+        );
+        assert_eq!(read(&compat_data, "pfx/system.reg"), "broken registry");
+        assert_eq!(
+            read(&compat_data, &format!("{}/Game/save.dat", DOCUMENTS)),
+            "new save"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decision_reaches_the_waiting_restore() {
+        let restore_decisions = RestoreDecisions::default();
+        assert!(!restore_decisions.decide(7, RestoreDecision::Overwrite));
+
+        let decision = restore_decisions.wait(7);
+        assert!(restore_decisions.decide(7, RestoreDecision::KeepLiveSaves));
+        assert_eq!(decision.await.unwrap(), RestoreDecision::KeepLiveSaves);
+        // Each restore is decided once
+        assert!(!restore_decisions.decide(7, RestoreDecision::Abort));
+    }
+}
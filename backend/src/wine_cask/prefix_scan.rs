@@ -0,0 +1,344 @@
+use crate::app_id::CompatAppId;
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, WineCask};
+use crate::wine_cask::storage::directory_size;
+use crate::PeerMap;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+/// What is known about an app's prefix, sizes are only filled in by the second pass.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct PrefixInfo {
+    pub app_id: CompatAppId,
+    /// `None` when no library folder has a prefix for the app.
+    pub library_folder: Option<String>,
+    pub exists: bool,
+    /// Contents of the prefix's `version` file, written by Proton when it created or upgraded it.
+    pub proton_version: Option<String>,
+    pub modified_at: Option<u64>,
+    pub bytes: Option<u64>,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum PrefixWork {
+    /// Existence and version file, cheap enough to answer right away.
+    Metadata,
+    /// Walks the whole prefix, slow on microSD cards.
+    Size,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct PrefixScanItem {
+    pub app_id: CompatAppId,
+    pub library_folder: PathBuf,
+    pub work: PrefixWork,
+}
+
+/// Prefixes left to scan. Metadata of every prefix goes before any size, and within each pass the
+/// apps currently on screen go first.
+#[derive(Default, Debug)]
+pub struct PrefixScanQueue {
+    metadata: VecDeque<PrefixScanItem>,
+    sizes: VecDeque<PrefixScanItem>,
+    prioritized: HashSet<CompatAppId>,
+    discovered: HashSet<CompatAppId>,
+}
+
+impl PrefixScanQueue {
+    /// Queues both passes for every prefix found in `library_folders`.
+    pub fn discover(library_folders: &[PathBuf]) -> PrefixScanQueue {
+        let mut queue = PrefixScanQueue::default();
+        for library_folder in library_folders {
+            let Ok(entries) = fs::read_dir(library_folder.join("steamapps/compatdata")) else {
+                continue;
+            };
+            for entry in entries.filter_map(Result::ok) {
+                if let Ok(app_id) = CompatAppId::parse(&entry.file_name().to_string_lossy()) {
+                    queue.push(app_id, library_folder.clone());
+                }
+            }
+        }
+        queue
+    }
+
+    pub fn push(&mut self, app_id: CompatAppId, library_folder: PathBuf) {
+        self.discovered.insert(app_id);
+        for (work, queue) in [
+            (PrefixWork::Metadata, &mut self.metadata),
+            (PrefixWork::Size, &mut self.sizes),
+        ] {
+            queue.push_back(PrefixScanItem {
+                app_id,
+                library_folder: library_folder.clone(),
+                work,
+            });
+        }
+    }
+
+    /// Replaces the prioritized apps, returns the ones without a prefix in any library folder.
+    pub fn prioritize(&mut self, app_ids: &[CompatAppId]) -> Vec<CompatAppId> {
+        self.prioritized = app_ids.iter().copied().collect();
+        app_ids
+            .iter()
+            .filter(|app_id| !self.discovered.contains(app_id))
+            .copied()
+            .collect()
+    }
+
+    pub fn next_item(&mut self) -> Option<PrefixScanItem> {
+        for queue in [&mut self.metadata, &mut self.sizes] {
+            let index = queue
+                .iter()
+                .position(|item| self.prioritized.contains(&item.app_id))
+                .unwrap_or(0);
+            if let Some(item) = queue.remove(index) {
+                return Some(item);
+            }
+        }
+        None
+    }
+}
+
+/// Reads what is known about the prefix of `app_id` in `library_folder`.
+pub fn prefix_info(app_id: CompatAppId, library_folder: &Path, with_size: bool) -> PrefixInfo {
+    let prefix = library_folder
+        .join("steamapps/compatdata")
+        .join(app_id.to_string());
+    let exists = prefix.is_dir();
+    PrefixInfo {
+        app_id,
+        library_folder: Some(library_folder.to_string_lossy().to_string()),
+        exists,
+        proton_version: fs::read_to_string(prefix.join("version"))
+            .ok()
+            .map(|version| version.trim().to_string())
+            .filter(|version| !version.is_empty()),
+        modified_at: fs::metadata(prefix.join("pfx"))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs()),
+        bytes: (with_size && exists).then(|| directory_size(&prefix)),
+    }
+}
+
+/// A running scan, stopped by setting `cancelled` or once no frontend is connected anymore.
+#[derive(Clone, Default)]
+pub struct PrefixScan {
+    queue: Arc<Mutex<PrefixScanQueue>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl PrefixScan {
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+async fn broadcast_prefix(peer_map: &PeerMap, prefix: PrefixInfo) {
+    broadcast_to_peers(
+        peer_map,
+        &Request {
+            prefix: Some(prefix),
+            ..Request::new(RequestType::PrefixScanned)
+        },
+    )
+    .await;
+}
+
+/// Prioritizes the prefixes of `app_ids`, starting a scan of every prefix if none is running.
+/// Called whenever the apps visible in the frontend change.
+pub async fn prioritize_prefixes(
+    wine_cask: &Arc<WineCask>,
+    peer_map: &PeerMap,
+    app_ids: Vec<CompatAppId>,
+) {
+    let running = wine_cask.app_state.lock().await.prefix_scan.clone();
+    let (scan, started) = match running {
+        Some(scan) if !scan.is_cancelled() => (scan, false),
+        _ => {
+            let library_folders = wine_cask.mounted_library_folders().await;
+            let queue =
+                tokio::task::spawn_blocking(move || PrefixScanQueue::discover(&library_folders))
+                    .await
+                    .unwrap();
+            let scan = PrefixScan {
+                queue: Arc::new(Mutex::new(queue)),
+                ..PrefixScan::default()
+            };
+            wine_cask.app_state.lock().await.prefix_scan = Some(scan.clone());
+            (scan, true)
+        }
+    };
+
+    let missing = scan.queue.lock().unwrap().prioritize(&app_ids);
+    for app_id in missing {
+        broadcast_prefix(
+            peer_map,
+            PrefixInfo {
+                app_id,
+                library_folder: None,
+                exists: false,
+                proton_version: None,
+                modified_at: None,
+                bytes: None,
+            },
+        )
+        .await;
+    }
+
+    if started {
+        info!("Starting prefix scan");
+        let wine_cask = Arc::clone(wine_cask);
+        let peer_map = Arc::clone(peer_map);
+        tokio::spawn(async move { run_prefix_scan(&wine_cask, &peer_map, scan).await });
+    }
+}
+
+async fn run_prefix_scan(wine_cask: &WineCask, peer_map: &PeerMap, scan: PrefixScan) {
+    loop {
+        if scan.is_cancelled() || peer_map.lock().await.is_empty() {
+            info!("Prefix scan cancelled");
+            break;
+        }
+        let item = scan.queue.lock().unwrap().next_item();
+        let Some(item) = item else {
+            broadcast_to_peers(peer_map, &Request::new(RequestType::PrefixScanCompleted)).await;
+            break;
+        };
+        let with_size = item.work == PrefixWork::Size;
+        let prefix = tokio::task::spawn_blocking(move || {
+            prefix_info(item.app_id, &item.library_folder, with_size)
+        })
+        .await
+        .unwrap();
+        broadcast_prefix(peer_map, prefix).await;
+    }
+
+    let mut app_state = wine_cask.app_state.lock().await;
+    if app_state
+        .prefix_scan
+        .as_ref()
+        .is_some_and(|current| Arc::ptr_eq(&current.cancelled, &scan.cancelled))
+    {
+        app_state.prefix_scan = None;
+    }
+}
+
+impl WineCask {
+    /// Stops the running scan, e.g. once the frontend left the view that asked for it.
+    pub async fn cancel_prefix_scan(&self) {
+        if let Some(scan) = self.app_state.lock().await.prefix_scan.take() {
+            scan.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_id::AppId;
+    use tempfile::tempdir;
+
+    fn app(app_id: u32) -> CompatAppId {
+        CompatAppId::from(AppId::new(app_id).unwrap())
+    }
+
+    fn queue(app_ids: &[u32]) -> PrefixScanQueue {
+        let mut queue = PrefixScanQueue::default();
+        for app_id in app_ids {
+            queue.push(app(*app_id), PathBuf::from("/library"));
+        }
+        queue
+    }
+
+    fn drain(queue: &mut PrefixScanQueue, count: usize) -> Vec<(u64, PrefixWork)> {
+        (0..count)
+            .map_while(|_| queue.next_item())
+            .map(|item| (item.app_id.to_string().parse().unwrap(), item.work))
+            .collect()
+    }
+
+    #[test]
+    fn test_prioritized_prefixes_are_scanned_first() {
+        let mut queue = queue(&[10, 20, 30, 40]);
+        assert_eq!(queue.prioritize(&[app(30), app(50)]), vec![app(50)]);
+
+        assert_eq!(
+            drain(&mut queue, 8),
+            vec![
+                (30, PrefixWork::Metadata),
+                (10, PrefixWork::Metadata),
+                (20, PrefixWork::Metadata),
+                (40, PrefixWork::Metadata),
+                (30, PrefixWork::Size),
+                (10, PrefixWork::Size),
+                (20, PrefixWork::Size),
+                (40, PrefixWork::Size),
+            ]
+        );
+        assert_eq!(queue.next_item(), None);
+    }
+
+    #[test]
+    fn test_reprioritizing_mid_scan() {
+        let mut queue = queue(&[10, 20, 30, 40]);
+        queue.prioritize(&[app(40)]);
+        assert_eq!(
+            drain(&mut queue, 2),
+            vec![(40, PrefixWork::Metadata), (10, PrefixWork::Metadata)]
+        );
+
+        // Scrolled on, 40 is no longer visible
+        queue.prioritize(&[app(30), app(20)]);
+        assert_eq!(
+            drain(&mut queue, 4),
+            vec![
+                (20, PrefixWork::Metadata),
+                (30, PrefixWork::Metadata),
+                (20, PrefixWork::Size),
+                (30, PrefixWork::Size),
+            ]
+        );
+        assert_eq!(
+            drain(&mut queue, 4),
+            vec![(10, PrefixWork::Size), (40, PrefixWork::Size)]
+        );
+    }
+
+    #[test]
+    fn test_prefix_info() {
+        let library_folder = tempdir().unwrap();
+        let compatdata = library_folder.path().join("steamapps/compatdata");
+        fs::create_dir_all(compatdata.join("1245620/pfx")).unwrap();
+        fs::write(compatdata.join("1245620/version"), "GE-Proton9-20\n").unwrap();
+        fs::write(compatdata.join("1245620/pfx/system.reg"), vec![0; 300]).unwrap();
+        fs::create_dir_all(compatdata.join(".wine-cellar-trash")).unwrap();
+
+        let queue = PrefixScanQueue::discover(&[library_folder.path().to_path_buf()]);
+        assert_eq!(queue.metadata.len(), 1);
+
+        let metadata = prefix_info(app(1245620), library_folder.path(), false);
+        assert!(metadata.exists);
+        assert_eq!(metadata.proton_version.as_deref(), Some("GE-Proton9-20"));
+        assert!(metadata.modified_at.is_some());
+        assert_eq!(metadata.bytes, None);
+        assert_eq!(
+            prefix_info(app(1245620), library_folder.path(), true).bytes,
+            Some(314)
+        );
+
+        let missing = prefix_info(app(730), library_folder.path(), true);
+        assert!(!missing.exists);
+        assert_eq!(missing.bytes, None);
+    }
+}
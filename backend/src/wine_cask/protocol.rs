@@ -0,0 +1,150 @@
+use crate::wine_cask::app::{Request, RequestType};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Version of the messages exchanged with peers. The major version changes whenever messages
+/// change in a way the other side can't read anymore, the minor one when they are only added to.
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 0 };
+/// How long a peer has to answer the `Hello` sent when it connects.
+pub const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub struct ProtocolVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl Display for ProtocolVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Sent along with an `IncompatibleVersion` error, before the peer is disconnected.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct VersionMismatch {
+    pub backend: ProtocolVersion,
+    /// `None` if the peer predates versioning and didn't answer with a `Hello`.
+    pub peer: Option<ProtocolVersion>,
+}
+
+impl VersionMismatch {
+    /// The `IncompatibleVersion` error telling the peer why it's disconnected.
+    pub fn rejection(self) -> Request {
+        let message = match self.peer {
+            Some(peer) => format!(
+                "The backend speaks protocol {}, the peer {}",
+                self.backend, peer
+            ),
+            None => format!(
+                "The backend speaks protocol {}, the peer didn't say which it speaks",
+                self.backend
+            ),
+        };
+        Request {
+            app_error: Some(AppError::new(AppErrorCode::IncompatibleVersion, message)),
+            version_mismatch: Some(self),
+            ..Request::new(RequestType::Error)
+        }
+    }
+}
+
+/// First message sent to every peer, it answers with a `Hello` of its own.
+pub fn hello() -> Request {
+    Request {
+        protocol_version: Some(PROTOCOL_VERSION),
+        ..Request::new(RequestType::Hello)
+    }
+}
+
+/// Checks the peer's answer to `Hello`, it has to speak the same major version of the protocol.
+pub fn check_hello(answer: &Message) -> Result<ProtocolVersion, VersionMismatch> {
+    let peer = answer
+        .to_text()
+        .ok()
+        .and_then(|text| serde_json::from_str::<Request>(text).ok())
+        .filter(|request| request.r#type == RequestType::Hello)
+        .and_then(|request| request.protocol_version);
+    match peer {
+        Some(peer) if peer.major == PROTOCOL_VERSION.major => Ok(peer),
+        _ => Err(VersionMismatch {
+            backend: PROTOCOL_VERSION,
+            peer,
+        }),
+    }
+}
+
+/// Answer to a request of a type this backend doesn't know, likely sent by a newer peer.
+pub fn unknown_type_error(r#type: String) -> Request {
+    Request {
+        app_error: Some(AppError::new(
+            AppErrorCode::UnknownRequestType,
+            format!(
+                "The backend doesn't know {} requests, it speaks protocol {}",
+                r#type, PROTOCOL_VERSION
+            ),
+        )),
+        unknown_request_type: Some(r#type),
+        protocol_version: Some(PROTOCOL_VERSION),
+        ..Request::new(RequestType::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(protocol_version: Option<ProtocolVersion>) -> Message {
+        let request = Request {
+            protocol_version,
+            ..Request::new(RequestType::Hello)
+        };
+        Message::text(serde_json::to_string(&request).unwrap())
+    }
+
+    fn rejection(answer: &Message) -> (AppErrorCode, VersionMismatch) {
+        let rejection = check_hello(answer).err().unwrap().rejection();
+        (
+            rejection.app_error.unwrap().code,
+            rejection.version_mismatch.unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_peers_of_the_same_major_version_are_accepted() {
+        let newer = ProtocolVersion {
+            minor: PROTOCOL_VERSION.minor + 1,
+            ..PROTOCOL_VERSION
+        };
+        assert_eq!(check_hello(&answer(Some(newer))).ok(), Some(newer));
+        assert_eq!(
+            serde_json::to_value(hello()).unwrap()["protocol_version"],
+            serde_json::json!({ "major": 1, "minor": 0 })
+        );
+    }
+
+    #[test]
+    fn test_other_major_versions_are_rejected_with_both_versions() {
+        let older = ProtocolVersion { major: 0, minor: 7 };
+        let (code, mismatch) = rejection(&answer(Some(older)));
+        assert_eq!(code, AppErrorCode::IncompatibleVersion);
+        assert_eq!(
+            mismatch,
+            VersionMismatch {
+                backend: PROTOCOL_VERSION,
+                peer: Some(older),
+            }
+        );
+
+        // Peers from before versioning authenticate right away
+        let (code, mismatch) = rejection(&Message::text(
+            "{\"type\":\"Authenticate\",\"session_token\":\"9f86d081\"}",
+        ));
+        assert_eq!(code, AppErrorCode::IncompatibleVersion);
+        assert_eq!(mismatch.peer, None);
+    }
+}
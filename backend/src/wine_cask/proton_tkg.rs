@@ -0,0 +1,60 @@
+use crate::github_util::{Asset, Release};
+use crate::wine_cask::install::archive_stem;
+
+/// Builds listed at most, every push to wine-tkg-git publishes one.
+pub const MAX_LISTED_BUILDS: usize = 10;
+
+/// Whether the asset is a Proton build, releases also carry Wine builds.
+pub fn is_build_asset(asset: &Asset) -> bool {
+    // CI artifacts are zipped, releases are tar archives
+    asset.name.starts_with("proton_tkg") && archive_stem(&asset.name).is_some()
+}
+
+/// The most recent releases with a Proton build, stripped of every other asset so the build is
+/// what gets installed.
+pub fn listed_builds(releases: Vec<Release>) -> Vec<Release> {
+    releases
+        .into_iter()
+        .filter_map(|mut release| {
+            release.assets.retain(is_build_asset);
+            (!release.assets.is_empty()).then_some(release)
+        })
+        .take(MAX_LISTED_BUILDS)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::release_with_assets;
+
+    #[test]
+    fn test_listed_builds() {
+        let mut releases = vec![
+            release_with_assets(
+                "9.5.r0.g3c4f8d1d",
+                &[
+                    "wine-tkg-staging-9.5.r0.g3c4f8d1d.tar.zst",
+                    "proton_tkg_9.5.r0.g3c4f8d1d.tar",
+                    "proton_tkg_9.5.r0.g3c4f8d1d.log",
+                ],
+            ),
+            release_with_assets("9.4.r3.g0a1b2c3d", &["wine-tkg-9.4.r3.g0a1b2c3d.tar.zst"]),
+        ];
+        for build in 0..MAX_LISTED_BUILDS {
+            let tag_name = format!("9.3.r{}.g0000000", build);
+            let extension = if build % 2 == 0 { "tar.xz" } else { "zip" };
+            releases.push(release_with_assets(
+                &tag_name,
+                &[&format!("proton_tkg_{}.{}", tag_name, extension)],
+            ));
+        }
+
+        let builds = listed_builds(releases);
+        assert_eq!(builds.len(), MAX_LISTED_BUILDS);
+        assert_eq!(builds[0].tag_name, "9.5.r0.g3c4f8d1d");
+        assert_eq!(builds[0].assets.len(), 1);
+        assert_eq!(builds[0].assets[0].name, "proton_tkg_9.5.r0.g3c4f8d1d.tar");
+        assert_eq!(builds[1].tag_name, "9.3.r0.g0000000");
+    }
+}
@@ -0,0 +1,282 @@
+use crate::github_util::{Asset, Release};
+use crate::runtime_directory;
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::flavors::CompatibilityToolFlavor;
+use crate::written_by::stamped;
+use crate::PeerMap;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+/// Records which release asset produced an installed compatibility tool.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Provenance {
+    pub internal_name: String,
+    pub flavor: CompatibilityToolFlavor,
+    pub tag_name: String,
+    /// Naming scheme of the release, missing from provenance recorded by older versions.
+    #[serde(default)]
+    pub naming_scheme: Option<String>,
+    pub installed_at: u64,
+    pub source: ProvenanceSource,
+    /// Every installed file, `None` if generating the manifest was turned off in the settings.
+    pub files: Option<Vec<FileManifestEntry>>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ProvenanceSource {
+    pub asset_url: String,
+    pub asset_name: String,
+    pub size: u64,
+    pub checksum: Option<String>,
+    pub uploaded_at: String,
+    pub target_commitish: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct FileManifestEntry {
+    /// Path relative to the tool directory.
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Verification {
+    pub internal_name: String,
+    pub verified: bool,
+    pub mismatched: Vec<String>,
+    pub missing: Vec<String>,
+    pub added: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl Verification {
+    fn failed(internal_name: &str, error: String) -> Self {
+        Self {
+            internal_name: internal_name.to_string(),
+            verified: false,
+            mismatched: Vec::new(),
+            missing: Vec::new(),
+            added: Vec::new(),
+            error: Some(error),
+        }
+    }
+}
+
+impl Provenance {
+    pub fn load(internal_name: &str) -> Option<Provenance> {
+        let string = fs::read_to_string(provenance_file(internal_name)).ok()?;
+        serde_json::from_str(&string).ok()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let provenance_file = provenance_file(&self.internal_name);
+        if let Some(parent) = provenance_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(&stamped(self, "provenance", &provenance_file))?;
+        fs::write(provenance_file, json)
+    }
+}
+
+impl ProvenanceSource {
+    pub fn new(release: &Release, asset: &Asset, checksum: Option<String>) -> Self {
+        Self {
+            asset_url: asset.browser_download_url.clone(),
+            asset_name: asset.name.clone(),
+            size: asset.size,
+            checksum,
+            uploaded_at: asset.updated_at.clone(),
+            target_commitish: release.target_commitish.clone(),
+        }
+    }
+}
+
+pub fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Failed to calculate duration")
+        .as_secs()
+}
+
+/// Hashes every file below `directory`, sorted by path.
+pub fn generate_file_manifest(directory: &Path) -> io::Result<Vec<FileManifestEntry>> {
+    let mut files = Vec::new();
+    collect_files(directory, directory, &mut files)?;
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+fn collect_files(
+    root: &Path,
+    directory: &Path,
+    files: &mut Vec<FileManifestEntry>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry_path = entry?.path();
+        let metadata = fs::symlink_metadata(&entry_path)?;
+        if metadata.is_dir() {
+            collect_files(root, &entry_path, files)?;
+        } else if metadata.is_file() {
+            let mut hasher = Sha256::new();
+            io::copy(&mut File::open(&entry_path)?, &mut hasher)?;
+            files.push(FileManifestEntry {
+                path: entry_path
+                    .strip_prefix(root)
+                    .unwrap_or(&entry_path)
+                    .to_string_lossy()
+                    .to_string(),
+                size: metadata.len(),
+                sha256: format!("{:x}", hasher.finalize()),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Compares the installed files against the manifest recorded at install time.
+pub fn verify_files(
+    directory: &Path,
+    internal_name: &str,
+    files: &[FileManifestEntry],
+) -> Verification {
+    let installed = match generate_file_manifest(directory) {
+        Ok(installed) => installed,
+        Err(err) => {
+            return Verification::failed(
+                internal_name,
+                format!("Failed to hash installed files: {}", err),
+            )
+        }
+    };
+
+    let mut verification = Verification {
+        internal_name: internal_name.to_string(),
+        verified: false,
+        mismatched: Vec::new(),
+        missing: Vec::new(),
+        added: Vec::new(),
+        error: None,
+    };
+
+    for expected in files {
+        match installed.iter().find(|file| file.path == expected.path) {
+            Some(file) if file != expected => verification.mismatched.push(expected.path.clone()),
+            Some(_) => {}
+            None => verification.missing.push(expected.path.clone()),
+        }
+    }
+    verification.added = installed
+        .iter()
+        .filter(|file| !files.iter().any(|expected| expected.path == file.path))
+        .map(|file| file.path.clone())
+        .collect();
+    verification.verified = verification.mismatched.is_empty()
+        && verification.missing.is_empty()
+        && verification.added.is_empty();
+    verification
+}
+
+fn provenance_file(internal_name: &str) -> PathBuf {
+    runtime_directory()
+        .join("provenance")
+        .join(format!("{}.json", internal_name))
+}
+
+impl WineCask {
+    pub async fn get_tool_provenance(&self, peer_map: &PeerMap, internal_name: &str) {
+        match Provenance::load(internal_name) {
+            Some(provenance) => self.broadcast_provenance(peer_map, provenance).await,
+            None => {
+                let error_message = format!("No provenance recorded for {}", internal_name);
+                warn!("{}", error_message);
+                self.broadcast_notification(peer_map, &error_message).await;
+            }
+        }
+    }
+
+    pub async fn verify_installed_tool(&self, peer_map: &PeerMap, internal_name: &str) {
+        let tool_path = self
+            .app_state
+            .lock()
+            .await
+            .installed_compatibility_tools
+            .iter()
+            .find(|tool| tool.internal_name == internal_name)
+            .map(|tool| PathBuf::from(&tool.path));
+
+        let internal_name = internal_name.to_string();
+        let verification = match (tool_path, Provenance::load(&internal_name)) {
+            (
+                Some(tool_path),
+                Some(Provenance {
+                    files: Some(files), ..
+                }),
+            ) => tokio::task::spawn_blocking(move || {
+                verify_files(&tool_path, &internal_name, &files)
+            })
+            .await
+            .unwrap(),
+            (None, _) => Verification::failed(
+                &internal_name,
+                format!("Compatibility tool not found: {}", internal_name),
+            ),
+            (Some(_), _) => Verification::failed(
+                &internal_name,
+                format!("No file manifest recorded for {}", internal_name),
+            ),
+        };
+
+        if verification.verified {
+            info!("Verified {}", verification.internal_name);
+        } else {
+            error!(
+                "Verification of {} failed: {} mismatched, {} missing, {} added",
+                verification.internal_name,
+                verification.mismatched.len(),
+                verification.missing.len(),
+                verification.added.len()
+            );
+        }
+        self.broadcast_verification(peer_map, verification).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_verify_files_detects_tampering() {
+        let tool_dir = tempdir().unwrap();
+        fs::create_dir_all(tool_dir.path().join("files/bin")).unwrap();
+        fs::write(tool_dir.path().join("proton"), "#!/usr/bin/env python3").unwrap();
+        fs::write(tool_dir.path().join("files/bin/wine"), "wine").unwrap();
+        fs::write(tool_dir.path().join("version"), "1 GE-Proton8-25").unwrap();
+
+        let files = generate_file_manifest(tool_dir.path()).unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0].path, "files/bin/wine");
+        assert!(verify_files(tool_dir.path(), "GE-Proton8-25", &files).verified);
+
+        fs::write(
+            tool_dir.path().join("proton"),
+            "#!/usr/bin/env python3\n# patched",
+        )
+        .unwrap();
+        fs::remove_file(tool_dir.path().join("version")).unwrap();
+        fs::write(tool_dir.path().join("files/bin/dxvk.dll"), "").unwrap();
+
+        let verification = verify_files(tool_dir.path(), "GE-Proton8-25", &files);
+        assert!(!verification.verified);
+        assert_eq!(verification.mismatched, vec!["proton"]);
+        assert_eq!(verification.missing, vec!["version"]);
+        assert_eq!(verification.added, vec!["files/bin/dxvk.dll"]);
+    }
+}
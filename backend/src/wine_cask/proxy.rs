@@ -0,0 +1,307 @@
+use crate::wine_cask::clock::error_chain;
+use reqwest::Url;
+use std::env;
+use std::sync::Mutex;
+
+const USER_AGENT: &str = "FlashyReese/decky-wine-cellar";
+
+/// Proxy from the settings, kept here so clients can be built anywhere without the app state.
+static PROXY_URL: Mutex<Option<String>> = Mutex::new(None);
+
+/// Makes every client built afterwards use `proxy_url` instead of the proxy environment variables.
+pub fn set_proxy_url(proxy_url: Option<String>) {
+    *PROXY_URL.lock().unwrap() = proxy_url;
+}
+
+/// Proxy URLs may leave out the scheme, like they can in `http_proxy`.
+fn parse_proxy_url(proxy_url: &str) -> Result<Url, String> {
+    let proxy_url = proxy_url.trim();
+    let url = if proxy_url.contains("://") {
+        Url::parse(proxy_url)
+    } else {
+        Url::parse(&format!("http://{}", proxy_url))
+    }
+    .map_err(|err| format!("invalid_proxy: {}: {}", proxy_url, err))?;
+    match url.scheme() {
+        "http" | "https" if url.host().is_some() => Ok(url),
+        _ => Err(format!(
+            "invalid_proxy: {} isn't an http or https proxy",
+            proxy_url
+        )),
+    }
+}
+
+pub fn validate_proxy_url(proxy_url: Option<&str>) -> Result<(), String> {
+    match proxy_url {
+        Some(proxy_url) if !proxy_url.trim().is_empty() => parse_proxy_url(proxy_url).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// The proxy without its credentials, for messages.
+fn display_proxy(proxy: &Url) -> String {
+    let mut proxy = proxy.clone();
+    let _ = proxy.set_username("");
+    let _ = proxy.set_password(None);
+    proxy.to_string()
+}
+
+/// Which proxy each request goes through.
+#[derive(Clone, Default, Debug)]
+pub struct ProxyConfig {
+    /// Proxy from the settings, used for every request when set.
+    explicit: Option<Url>,
+    http: Option<Url>,
+    https: Option<Url>,
+    /// Hosts from `no_proxy` requests go to directly, along with their subdomains.
+    no_proxy: Vec<String>,
+}
+
+impl ProxyConfig {
+    /// The proxy from the settings, or the environment variables if there is none.
+    pub fn current() -> ProxyConfig {
+        let explicit = PROXY_URL.lock().unwrap().clone();
+        ProxyConfig::new(explicit.as_deref(), |name| env::var(name).ok())
+    }
+
+    /// Reads the environment through `var`. A proxy that isn't a valid URL is ignored, like
+    /// other programs ignore it.
+    pub fn new(explicit: Option<&str>, var: impl Fn(&str) -> Option<String>) -> ProxyConfig {
+        let explicit = explicit
+            .filter(|proxy_url| !proxy_url.trim().is_empty())
+            .and_then(|proxy_url| parse_proxy_url(proxy_url).ok());
+        let variable = |names: &[&str]| {
+            names
+                .iter()
+                .filter_map(|name| var(name))
+                .find(|value| !value.trim().is_empty())
+        };
+        let proxy = |names: &[&str]| variable(names).and_then(|value| parse_proxy_url(&value).ok());
+        let no_proxy = variable(&["no_proxy", "NO_PROXY"])
+            .unwrap_or_default()
+            .split(',')
+            .map(|host| host.trim().trim_start_matches('.').to_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect();
+        ProxyConfig {
+            explicit,
+            http: proxy(&["http_proxy", "HTTP_PROXY", "all_proxy", "ALL_PROXY"]),
+            https: proxy(&["https_proxy", "HTTPS_PROXY", "all_proxy", "ALL_PROXY"]),
+            no_proxy,
+        }
+    }
+
+    /// The proxy a request to `url` goes through, `None` if it goes directly.
+    pub fn proxy_for(&self, url: &Url) -> Option<Url> {
+        if let Some(explicit) = &self.explicit {
+            return Some(explicit.clone());
+        }
+        let host = url.host_str()?.to_lowercase();
+        let bypassed = self.no_proxy.iter().any(|pattern| {
+            pattern == "*"
+                || host == *pattern
+                || host
+                    .strip_suffix(pattern.as_str())
+                    .is_some_and(|subdomain| subdomain.ends_with('.'))
+        });
+        if bypassed {
+            return None;
+        }
+        match url.scheme() {
+            "http" => self.http.clone(),
+            "https" => self.https.clone(),
+            _ => None,
+        }
+    }
+
+    fn proxy(&self) -> reqwest::Proxy {
+        let config = self.clone();
+        reqwest::Proxy::custom(move |url| config.proxy_for(url))
+    }
+
+    pub fn client_builder(&self) -> reqwest::ClientBuilder {
+        reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .proxy(self.proxy())
+    }
+
+    pub fn blocking_client_builder(&self) -> reqwest::blocking::ClientBuilder {
+        reqwest::blocking::Client::builder()
+            .user_agent(USER_AGENT)
+            .proxy(self.proxy())
+    }
+
+    /// Formats `err` along with its causes, telling apart a proxy that can't be reached from the
+    /// server behind it.
+    pub fn describe_error(&self, err: &reqwest::Error) -> String {
+        match err.url().and_then(|url| self.proxy_for(url)) {
+            Some(proxy) if err.is_connect() => format!(
+                "Failed to connect to proxy {}: {}",
+                display_proxy(&proxy),
+                error_chain(err)
+            ),
+            _ => error_chain(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn config(explicit: Option<&str>, variables: &[(&str, &str)]) -> ProxyConfig {
+        let variables: HashMap<String, String> = variables
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        ProxyConfig::new(explicit, |name| variables.get(name).cloned())
+    }
+
+    /// Answers every request with `body`, recording the request lines it received.
+    fn stub_server(body: &'static str) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().filter_map(Result::ok) {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push(request_line.trim_end().to_string());
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+            }
+        });
+        (address, requests)
+    }
+
+    #[test]
+    fn test_proxy_is_chosen_by_scheme_and_no_proxy() {
+        let config = config(
+            None,
+            &[
+                ("HTTPS_PROXY", "proxy.corp:3128"),
+                ("http_proxy", "http://plain.corp:8080"),
+                ("no_proxy", "localhost, .internal.corp"),
+            ],
+        );
+        let proxy_for = |url: &str| {
+            config
+                .proxy_for(&Url::parse(url).unwrap())
+                .map(|proxy| proxy.to_string())
+        };
+        assert_eq!(
+            proxy_for("https://api.github.com/repos"),
+            Some("http://proxy.corp:3128/".to_string())
+        );
+        assert_eq!(
+            proxy_for("http://example.com/"),
+            Some("http://plain.corp:8080/".to_string())
+        );
+        assert_eq!(proxy_for("http://localhost:1337/"), None);
+        assert_eq!(proxy_for("https://mirror.internal.corp/"), None);
+        assert_eq!(proxy_for("https://internal.corp/"), None);
+        assert!(proxy_for("https://notinternal.corp/").is_some());
+    }
+
+    #[test]
+    fn test_settings_proxy_overrides_the_environment() {
+        let config = config(
+            Some("https://settings.corp:1080"),
+            &[("https_proxy", "proxy.corp:3128"), ("no_proxy", "*")],
+        );
+        assert_eq!(
+            config
+                .proxy_for(&Url::parse("https://api.github.com/").unwrap())
+                .unwrap()
+                .as_str(),
+            "https://settings.corp:1080/"
+        );
+        assert!(validate_proxy_url(Some("proxy.corp:3128")).is_ok());
+        assert!(validate_proxy_url(Some("")).is_ok());
+        assert!(validate_proxy_url(Some("socks5://proxy.corp")).is_err());
+        assert!(validate_proxy_url(Some("http://")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_routed_through_the_proxy() {
+        let (proxy, proxied) = stub_server("through the proxy");
+        let (direct, direct_requests) = stub_server("direct");
+        let config = config(
+            None,
+            &[
+                ("http_proxy", &format!("http://{}", proxy)),
+                ("no_proxy", "127.0.0.1"),
+            ],
+        );
+        let client = config.client_builder().build().unwrap();
+
+        let body = client
+            .get("http://releases.example/asset.tar.gz")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "through the proxy");
+        assert_eq!(
+            *proxied.lock().unwrap(),
+            vec!["GET http://releases.example/asset.tar.gz HTTP/1.1"]
+        );
+
+        let body = client
+            .get(format!("http://{}/asset.tar.gz", direct))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "direct");
+        assert_eq!(proxied.lock().unwrap().len(), 1);
+        assert_eq!(
+            *direct_requests.lock().unwrap(),
+            vec!["GET /asset.tar.gz HTTP/1.1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_proxy_is_named_in_errors() {
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = format!("http://user:secret@{}", closed.local_addr().unwrap());
+        drop(closed);
+        let config = config(Some(&proxy), &[]);
+        let client = config.client_builder().build().unwrap();
+
+        let err = client
+            .get("http://releases.example/asset.tar.gz")
+            .send()
+            .await
+            .unwrap_err();
+        let message = config.describe_error(&err);
+        assert!(
+            message.starts_with("Failed to connect to proxy http://127.0.0.1:"),
+            "{}",
+            message
+        );
+        assert!(!message.contains("secret"), "{}", message);
+    }
+}
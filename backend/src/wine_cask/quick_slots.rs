@@ -0,0 +1,356 @@
+use crate::app_id::{AppId, CompatAppId};
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::mappings::{MappingChange, MappingChanges};
+use crate::PeerMap;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// More slots than this don't fit a toggle.
+pub const MAX_QUICK_SLOTS: usize = 4;
+
+/// Tools an app can be switched between with one tap, persisted in the settings.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct QuickSlots {
+    pub app_id: AppId,
+    pub tools: Vec<String>,
+    /// Slot switched to last, `None` until one was.
+    pub active: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct QuickSlot {
+    pub compatibility_tool: String,
+    /// The tool was removed, switching to the slot fails until it's replaced.
+    pub dangling: bool,
+}
+
+/// Slots of a mapped app as the frontend renders them.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct QuickSlotState {
+    pub slots: Vec<QuickSlot>,
+    /// `None` if the mapping was changed some other way since the last switch.
+    pub active: Option<usize>,
+}
+
+impl QuickSlots {
+    /// The active slot if it still matches the app's mapping.
+    pub fn active_slot(&self, mapping: Option<&str>) -> Option<usize> {
+        self.active
+            .filter(|active| self.tools.get(*active).map(String::as_str) == mapping)
+    }
+
+    /// Slots along with whether their tool is still around, `available` are the tools installed
+    /// or shipped with Steam.
+    pub fn state(&self, mapping: Option<&str>, available: &HashSet<String>) -> QuickSlotState {
+        QuickSlotState {
+            slots: self
+                .tools
+                .iter()
+                .map(|compatibility_tool| QuickSlot {
+                    compatibility_tool: compatibility_tool.clone(),
+                    dangling: !available.contains(compatibility_tool),
+                })
+                .collect(),
+            active: self.active_slot(mapping),
+        }
+    }
+
+    /// The mapping change switching to slot `index`, or why it can't be switched to.
+    pub fn switch(
+        &self,
+        index: usize,
+        available: &HashSet<String>,
+    ) -> Result<MappingChange, AppError> {
+        let Some(compatibility_tool) = self.tools.get(index) else {
+            return Err(AppError::new(
+                AppErrorCode::NotFound,
+                format!("Quick slot {} of {} doesn't exist", index, self.app_id),
+            ));
+        };
+        if !available.contains(compatibility_tool) {
+            return Err(AppError::new(
+                AppErrorCode::NotFound,
+                format!(
+                    "Slot {} of {} uses {}, which isn't installed anymore",
+                    index, self.app_id, compatibility_tool
+                ),
+            ));
+        }
+        Ok(MappingChange {
+            app_id: CompatAppId::from(self.app_id),
+            compatibility_tool: Some(compatibility_tool.clone()),
+            clear_shader_cache: false,
+        })
+    }
+}
+
+/// Slots of every app that `removed` occupied, as `(app id, slot)` pairs.
+pub fn dangling_slots(quick_slots: &[QuickSlots], removed: &str) -> Vec<(AppId, usize)> {
+    quick_slots
+        .iter()
+        .flat_map(|slots| {
+            slots
+                .tools
+                .iter()
+                .enumerate()
+                .filter(|(_, compatibility_tool)| *compatibility_tool == removed)
+                .map(|(index, _)| (slots.app_id, index))
+        })
+        .collect()
+}
+
+impl WineCask {
+    /// Tools slots can be switched to.
+    async fn available_tools(&self) -> HashSet<String> {
+        let app_state = self.app_state.lock().await;
+        app_state
+            .installed_compatibility_tools
+            .iter()
+            .map(|tool| tool.internal_name.clone())
+            .chain(
+                app_state
+                    .available_compat_tools
+                    .iter()
+                    .flatten()
+                    .map(|tool| tool.str_tool_name.clone()),
+            )
+            .collect()
+    }
+
+    /// Refreshes the slots shown along with every mapping.
+    pub async fn update_quick_slot_states(&self) {
+        let available = self.available_tools().await;
+        let mut app_state = self.app_state.lock().await;
+        let app_state = &mut *app_state;
+        for mapping in &mut app_state.compatibility_tool_mappings {
+            mapping.quick_slots = mapping.app_id.app_id().and_then(|app_id| {
+                let slots = app_state
+                    .settings
+                    .quick_slots
+                    .iter()
+                    .find(|slots| slots.app_id == app_id)?;
+                Some(slots.state(Some(&mapping.compatibility_tool), &available))
+            });
+        }
+    }
+
+    /// Replaces the slots of an app, no tools removes them.
+    pub async fn set_quick_slots(&self, peer_map: &PeerMap, app_id: AppId, tools: Vec<String>) {
+        let mut unique: Vec<String> = Vec::new();
+        for tool in tools {
+            if !unique.contains(&tool) {
+                unique.push(tool);
+            }
+        }
+        if unique.len() > MAX_QUICK_SLOTS {
+            let app_error = AppError::new(
+                AppErrorCode::InvalidRequest,
+                format!(
+                    "{} can have at most {} quick slots",
+                    app_id, MAX_QUICK_SLOTS
+                ),
+            );
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error).await;
+            return;
+        }
+
+        let app_state = self.app_state.lock().await;
+        let mapping = app_state
+            .compatibility_tool_mappings
+            .iter()
+            .find(|mapping| mapping.app_id == CompatAppId::from(app_id))
+            .map(|mapping| mapping.compatibility_tool.clone());
+        let mut settings = app_state.settings.clone();
+        drop(app_state);
+        settings.quick_slots.retain(|slots| slots.app_id != app_id);
+        if !unique.is_empty() {
+            // The app may already use one of the slots
+            let active = unique
+                .iter()
+                .position(|tool| Some(tool) == mapping.as_ref());
+            settings.quick_slots.push(QuickSlots {
+                app_id,
+                tools: unique,
+                active,
+            });
+        }
+        self.update_settings(peer_map, settings).await;
+    }
+
+    /// Maps the app to the tool of one of its slots.
+    pub async fn switch_quick_slot(&self, peer_map: &PeerMap, app_id: AppId, index: usize) {
+        let slots = self
+            .app_state
+            .lock()
+            .await
+            .settings
+            .quick_slots
+            .iter()
+            .find(|slots| slots.app_id == app_id)
+            .cloned();
+        let change = match slots {
+            Some(slots) => slots.switch(index, &self.available_tools().await),
+            None => Err(AppError::new(
+                AppErrorCode::NotFound,
+                format!("{} has no quick slots", app_id),
+            )),
+        };
+        let change = match change {
+            Ok(change) => change,
+            Err(app_error) => {
+                error!("{}", app_error);
+                self.broadcast_app_error(peer_map, app_error).await;
+                return;
+            }
+        };
+
+        info!(
+            "Switching {} to quick slot {} ({})",
+            app_id,
+            index,
+            change.compatibility_tool.as_deref().unwrap_or_default()
+        );
+        let results = self
+            .set_compatibility_tool_mappings(
+                peer_map,
+                MappingChanges {
+                    changes: vec![change],
+                    atomic: true,
+                },
+            )
+            .await;
+        // Unchanged still means the slot's tool is the one in use
+        let switched = results
+            .iter()
+            .all(|result| result.applied || result.reason.as_deref() == Some("Unchanged"));
+        if !switched {
+            return;
+        }
+        let mut settings = self.app_state.lock().await.settings.clone();
+        if let Some(slots) = settings
+            .quick_slots
+            .iter_mut()
+            .find(|slots| slots.app_id == app_id)
+        {
+            slots.active = Some(index);
+        }
+        self.update_settings(peer_map, settings).await;
+    }
+
+    /// Warns about slots left without their tool after it was uninstalled.
+    pub async fn report_dangling_slots(&self, peer_map: &PeerMap, removed: &str) {
+        let dangling = dangling_slots(&self.app_state.lock().await.settings.quick_slots, removed);
+        if dangling.is_empty() {
+            return;
+        }
+        let warning_message = format!(
+            "Warning: dangling_quick_slot: {} was removed, it stays in the quick slots of {} until replaced",
+            removed,
+            dangling
+                .iter()
+                .map(|(app_id, index)| format!("{} (slot {})", app_id, index))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        warn!("{}", warning_message);
+        let app_ids = dangling
+            .iter()
+            .map(|(app_id, _)| CompatAppId::from(*app_id))
+            .collect();
+        self.broadcast_app_notification(peer_map, &warning_message, app_ids)
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wine_cask::settings::Settings;
+
+    fn app(app_id: u32) -> AppId {
+        AppId::new(app_id).unwrap()
+    }
+
+    fn slots() -> QuickSlots {
+        QuickSlots {
+            app_id: app(1245620),
+            tools: vec![
+                "GE-Proton9-20".to_string(),
+                "proton_experimental".to_string(),
+                "GE-Proton8-25".to_string(),
+            ],
+            active: Some(0),
+        }
+    }
+
+    fn available(tools: &[&str]) -> HashSet<String> {
+        tools.iter().map(|tool| tool.to_string()).collect()
+    }
+
+    #[test]
+    fn test_switching_maps_the_slot_tool() {
+        let available = available(&["GE-Proton9-20", "proton_experimental", "GE-Proton8-25"]);
+        let change = slots().switch(1, &available).unwrap();
+        assert_eq!(change.app_id, CompatAppId::from(app(1245620)));
+        assert_eq!(
+            change.compatibility_tool.as_deref(),
+            Some("proton_experimental")
+        );
+        assert_eq!(
+            slots().switch(3, &available).err(),
+            Some(AppError::new(
+                AppErrorCode::NotFound,
+                "Quick slot 3 of 1245620 doesn't exist"
+            ))
+        );
+
+        assert_eq!(slots().active_slot(Some("GE-Proton9-20")), Some(0));
+        // Changed outside of the slots
+        assert_eq!(slots().active_slot(Some("proton_8")), None);
+        assert_eq!(slots().active_slot(None), None);
+    }
+
+    #[test]
+    fn test_slots_persist_in_settings() {
+        let settings = Settings {
+            quick_slots: vec![slots()],
+            ..Settings::default()
+        };
+        let json = serde_json::to_string(&settings).unwrap();
+        let loaded: Settings = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.quick_slots, vec![slots()]);
+        // Settings written before quick slots existed
+        let older: Settings = serde_json::from_str("{\"unix_socket\": true}").unwrap();
+        assert!(older.quick_slots.is_empty());
+    }
+
+    #[test]
+    fn test_removed_tools_leave_dangling_slots() {
+        let other = QuickSlots {
+            app_id: app(292030),
+            tools: vec!["GE-Proton8-25".to_string(), "proton_8".to_string()],
+            active: None,
+        };
+        assert_eq!(
+            dangling_slots(&[slots(), other], "GE-Proton8-25"),
+            vec![(app(1245620), 2), (app(292030), 0)]
+        );
+
+        let available = available(&["GE-Proton9-20", "proton_experimental"]);
+        let state = slots().state(Some("GE-Proton9-20"), &available);
+        let dangling: Vec<bool> = state.slots.iter().map(|slot| slot.dangling).collect();
+        assert_eq!(dangling, vec![false, false, true]);
+        assert_eq!(state.active, Some(0));
+        assert!(slots()
+            .switch(2, &available)
+            .err()
+            .unwrap()
+            .message
+            .ends_with("which isn't installed anymore"));
+        // The other slots keep working
+        assert!(slots().switch(1, &available).is_ok());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::proxy::ProxyConfig;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Release listings come from here.
+const GITHUB_API_URL: &str = "https://api.github.com/";
+/// Release assets are served from here, `github.com` download links redirect to it.
+const GITHUB_CDN_URL: &str = "https://objects.githubusercontent.com/";
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Which parts of GitHub can be reached, some networks block the API but not the downloads.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum GitHubReachability {
+    Reachable,
+    /// Only cached releases can be listed, their assets still download.
+    ApiBlocked,
+    Offline,
+}
+
+impl GitHubReachability {
+    pub fn describe(self) -> &'static str {
+        match self {
+            GitHubReachability::Reachable => "GitHub is reachable",
+            GitHubReachability::ApiBlocked => {
+                "the GitHub API is blocked on this network, cached releases can still be installed"
+            }
+            GitHubReachability::Offline => "GitHub can't be reached, check the network connection",
+        }
+    }
+}
+
+/// Any answer counts, even an error status means the host could be reached.
+async fn answers(client: &reqwest::Client, proxy: &ProxyConfig, url: &str) -> bool {
+    match client.head(url).send().await {
+        Ok(_) => true,
+        Err(err) => {
+            debug!("{} can't be reached: {}", url, proxy.describe_error(&err));
+            false
+        }
+    }
+}
+
+pub async fn probe_reachability(api_url: &str, cdn_url: &str) -> GitHubReachability {
+    let proxy = ProxyConfig::current();
+    let client = proxy
+        .client_builder()
+        .timeout(PROBE_TIMEOUT)
+        .build()
+        .expect("Failed to create HTTP client");
+    let (api, cdn) = futures_util::join!(
+        answers(&client, &proxy, api_url),
+        answers(&client, &proxy, cdn_url)
+    );
+    match (api, cdn) {
+        (true, _) => GitHubReachability::Reachable,
+        (false, true) => GitHubReachability::ApiBlocked,
+        (false, false) => GitHubReachability::Offline,
+    }
+}
+
+impl WineCask {
+    /// Probes GitHub after the API failed, recording the outcome in the environment snapshot.
+    pub async fn check_github_reachability(&self) -> GitHubReachability {
+        let reachability = probe_reachability(GITHUB_API_URL, GITHUB_CDN_URL).await;
+        self.record_github_reachability(reachability).await;
+        reachability
+    }
+
+    /// Records the reachability seen by a request.
+    pub async fn record_github_reachability(&self, reachability: GitHubReachability) {
+        let (previous, snapshot) = {
+            let mut environment = self.environment.lock().unwrap();
+            let previous = environment.record_github_reachability(Some(reachability));
+            (previous, environment.snapshot().clone())
+        };
+        if previous.is_some_and(|previous| previous != reachability) {
+            info!("GitHub reachability changed: {}", reachability.describe());
+        }
+        self.app_state.lock().await.environment = snapshot;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serves `200 OK` to every request until the test ends.
+    fn answering_port() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for mut stream in listener.incoming().filter_map(Result::ok) {
+                let _ = stream.read(&mut [0; 1024]);
+                let _ = stream.write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                );
+            }
+        });
+        url
+    }
+
+    /// Accepts connections and closes them right away, like a firewall resetting them.
+    fn blocked_port() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().filter_map(Result::ok) {
+                drop(stream);
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_blocked_api_is_told_apart_from_being_offline() {
+        let (api, cdn) = (answering_port(), answering_port());
+        assert_eq!(
+            probe_reachability(&api, &cdn).await,
+            GitHubReachability::Reachable
+        );
+
+        let blocked = blocked_port();
+        assert_eq!(
+            probe_reachability(&blocked, &cdn).await,
+            GitHubReachability::ApiBlocked
+        );
+        assert_eq!(
+            probe_reachability(&blocked, &blocked_port()).await,
+            GitHubReachability::Offline
+        );
+    }
+}
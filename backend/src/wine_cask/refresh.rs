@@ -0,0 +1,280 @@
+use crate::wine_cask::activity::ActivitySource;
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::flavors::{CacheUse, CompatibilityToolFlavor};
+use crate::PeerMap;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Part of the state the frontend can ask to have re-read.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum RefreshScope {
+    Tools,
+    Apps,
+    Mappings,
+    /// Every flavor's releases, or only the requested flavor's.
+    Releases,
+    Prefixes,
+    Storage,
+}
+
+/// Cache or derived state recomputed by a refresh.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum RefreshStep {
+    /// Installed tools along with their releases, without fetching releases.
+    ScanTools,
+    /// Installed apps and the tools they use.
+    ScanApps,
+    /// Steam's mappings and the names of mapped apps.
+    ReadMappings,
+    /// Renews the release cache.
+    FetchReleases,
+    /// Disk usage, prefix sizes are part of it.
+    ComputeStorage,
+}
+
+impl RefreshStep {
+    fn updates_app_state(self) -> bool {
+        self != RefreshStep::ComputeStorage
+    }
+}
+
+/// Steps each scope runs, in order.
+const REFRESH_STEPS: [(RefreshScope, &[RefreshStep]); 6] = [
+    (RefreshScope::Tools, &[RefreshStep::ScanTools]),
+    (
+        RefreshScope::Apps,
+        &[RefreshStep::ScanApps, RefreshStep::ReadMappings],
+    ),
+    (RefreshScope::Mappings, &[RefreshStep::ReadMappings]),
+    (RefreshScope::Releases, &[RefreshStep::FetchReleases]),
+    (RefreshScope::Prefixes, &[RefreshStep::ComputeStorage]),
+    (RefreshScope::Storage, &[RefreshStep::ComputeStorage]),
+];
+
+pub fn refresh_steps(scope: RefreshScope) -> &'static [RefreshStep] {
+    REFRESH_STEPS
+        .iter()
+        .find(|(table_scope, _)| *table_scope == scope)
+        .map_or(&[], |(_, steps)| *steps)
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct RefreshKey {
+    pub scope: RefreshScope,
+    /// Only used by `Releases`.
+    pub flavor: Option<CompatibilityToolFlavor>,
+}
+
+impl RefreshKey {
+    pub fn new(scope: RefreshScope, flavor: Option<CompatibilityToolFlavor>) -> Self {
+        RefreshKey {
+            scope,
+            flavor: flavor.filter(|_| scope == RefreshScope::Releases),
+        }
+    }
+}
+
+/// Refreshes in flight along with the request ids waiting for them.
+#[derive(Default, Clone)]
+pub struct RefreshTracker {
+    in_flight: HashMap<RefreshKey, Vec<Option<String>>>,
+}
+
+impl RefreshTracker {
+    /// Returns whether the caller should run the refresh, otherwise `request_id` was attached to
+    /// the one already in flight.
+    pub fn begin(&mut self, key: RefreshKey, request_id: Option<String>) -> bool {
+        match self.in_flight.get_mut(&key) {
+            Some(waiting) => {
+                waiting.push(request_id);
+                false
+            }
+            None => {
+                self.in_flight.insert(key, vec![request_id]);
+                true
+            }
+        }
+    }
+
+    /// Returns the request ids that waited for the refresh.
+    pub fn finish(&mut self, key: &RefreshKey) -> Vec<Option<String>> {
+        self.in_flight.remove(key).unwrap_or_default()
+    }
+}
+
+impl WineCask {
+    /// Re-reads only the state of `scope`, then sends `RefreshCompleted` for every request that
+    /// asked for it while it ran.
+    pub async fn refresh(
+        &self,
+        peer_map: &PeerMap,
+        scope: RefreshScope,
+        flavor: Option<CompatibilityToolFlavor>,
+        request_id: Option<String>,
+    ) {
+        let key = RefreshKey::new(scope, flavor);
+        if !self
+            .app_state
+            .lock()
+            .await
+            .refreshes
+            .begin(key.clone(), request_id)
+        {
+            info!("Refresh of {:?} already in flight", key);
+            return;
+        }
+
+        let steps = refresh_steps(scope);
+        for step in steps {
+            self.run_refresh_step(peer_map, *step, &key).await;
+        }
+        if steps.iter().any(|step| step.updates_app_state()) {
+            self.broadcast_app_state(peer_map).await;
+        }
+
+        let waiting = self.app_state.lock().await.refreshes.finish(&key);
+        for request_id in waiting {
+            self.broadcast_refresh_completed(peer_map, &key, request_id)
+                .await;
+        }
+    }
+
+    async fn run_refresh_step(&self, peer_map: &PeerMap, step: RefreshStep, key: &RefreshKey) {
+        match step {
+            RefreshStep::ScanTools => {
+                if let Some(installed_compatibility_tools) = self.list_compatibility_tools() {
+                    self.app_state.lock().await.installed_compatibility_tools =
+                        installed_compatibility_tools;
+                }
+                let mut app_state = self.app_state.lock().await;
+                app_state.installed_runners = self.list_runners();
+                app_state.installed_components = self.list_components();
+                drop(app_state);
+                self.update_compatibility_tools_and_available_flavors()
+                    .await;
+                self.update_stranded_compatibility_tools().await;
+                self.update_filesystem_profiles().await;
+                self.record_tool_activity(ActivitySource::External).await;
+            }
+            RefreshStep::ScanApps => {
+                for compat_tool in &mut self.app_state.lock().await.installed_compatibility_tools {
+                    compat_tool.used_by_games = self
+                        .get_used_by_games(&compat_tool.display_name, &compat_tool.internal_name);
+                    compat_tool.used_by_apps = self
+                        .steam_util
+                        .get_applications_using_tool(&compat_tool.internal_name);
+                }
+            }
+            RefreshStep::ReadMappings => {
+                self.update_compatibility_tool_mappings(ActivitySource::External)
+                    .await;
+            }
+            RefreshStep::FetchReleases => {
+                match &key.flavor {
+                    Some(flavor) => self.refresh_flavor(peer_map, flavor.clone()).await,
+                    None => {
+                        let flavors = self.get_flavors(peer_map, CacheUse::Revalidate).await;
+                        self.app_state.lock().await.flavors = flavors;
+                    }
+                }
+                self.update_compatibility_tools_and_available_flavors()
+                    .await;
+                self.reassign_naming_schemes(peer_map).await;
+                self.clear_superseded_skips(peer_map).await;
+            }
+            RefreshStep::ComputeStorage => {
+                self.get_storage_breakdown(peer_map, true).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCOPES: [RefreshScope; 6] = [
+        RefreshScope::Tools,
+        RefreshScope::Apps,
+        RefreshScope::Mappings,
+        RefreshScope::Releases,
+        RefreshScope::Prefixes,
+        RefreshScope::Storage,
+    ];
+
+    #[test]
+    fn test_every_scope_has_steps() {
+        for scope in SCOPES {
+            assert!(!refresh_steps(scope).is_empty(), "{:?}", scope);
+            let listed = REFRESH_STEPS
+                .iter()
+                .filter(|(table_scope, _)| *table_scope == scope)
+                .count();
+            assert_eq!(listed, 1, "{:?}", scope);
+        }
+    }
+
+    #[test]
+    fn test_scopes_only_invalidate_their_own_state() {
+        assert_eq!(
+            refresh_steps(RefreshScope::Releases),
+            &[RefreshStep::FetchReleases]
+        );
+        for scope in SCOPES {
+            let steps = refresh_steps(scope);
+            // Only an app refresh rescans apps, only a release refresh hits the network
+            assert_eq!(
+                steps.contains(&RefreshStep::ScanApps),
+                scope == RefreshScope::Apps,
+                "{:?}",
+                scope
+            );
+            assert_eq!(
+                steps.contains(&RefreshStep::FetchReleases),
+                scope == RefreshScope::Releases,
+                "{:?}",
+                scope
+            );
+        }
+        assert!(refresh_steps(RefreshScope::Apps).contains(&RefreshStep::ReadMappings));
+        assert!(!refresh_steps(RefreshScope::Storage)
+            .iter()
+            .any(|step| step.updates_app_state()));
+    }
+
+    #[test]
+    fn test_redundant_refreshes_attach_to_the_one_in_flight() {
+        let mut tracker = RefreshTracker::default();
+        let releases = RefreshKey::new(RefreshScope::Releases, None);
+        let proton_ge = RefreshKey::new(
+            RefreshScope::Releases,
+            Some(CompatibilityToolFlavor::ProtonGE),
+        );
+
+        assert!(tracker.begin(releases.clone(), Some("1".to_string())));
+        assert!(!tracker.begin(releases.clone(), Some("2".to_string())));
+        // A single flavor and other scopes run on their own
+        assert!(tracker.begin(proton_ge.clone(), Some("3".to_string())));
+        assert!(tracker.begin(
+            RefreshKey::new(RefreshScope::Storage, None),
+            Some("4".to_string())
+        ));
+
+        assert_eq!(
+            tracker.finish(&releases),
+            vec![Some("1".to_string()), Some("2".to_string())]
+        );
+        assert_eq!(tracker.finish(&proton_ge), vec![Some("3".to_string())]);
+        // Finished refreshes don't swallow the next one
+        assert!(tracker.begin(releases, None));
+    }
+
+    #[test]
+    fn test_flavor_only_scopes_releases() {
+        assert_eq!(
+            RefreshKey::new(RefreshScope::Tools, Some(CompatibilityToolFlavor::ProtonGE)),
+            RefreshKey::new(RefreshScope::Tools, None)
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use crate::wine_cask::flavors::CompatibilityToolFlavor;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+/// Minimum system versions a flavor needs to run properly.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+#[serde(default)]
+pub struct Requirements {
+    pub steamos: Option<String>,
+    pub mesa: Option<String>,
+    pub kernel: Option<String>,
+}
+
+/// Versions detected on this system, `None` when they couldn't be determined.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+pub struct SystemVersions {
+    pub steamos: Option<String>,
+    pub mesa: Option<String>,
+    pub kernel: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FlavorRequirements {
+    flavor: CompatibilityToolFlavor,
+    requirements: Requirements,
+}
+
+impl Requirements {
+    /// Returns a warning for every requirement that isn't met or can't be verified.
+    pub fn unmet(&self, system_versions: &SystemVersions) -> Vec<String> {
+        self.check(system_versions, true)
+    }
+
+    /// Returns a warning for every requirement known not to be met.
+    pub fn violated(&self, system_versions: &SystemVersions) -> Vec<String> {
+        self.check(system_versions, false)
+    }
+
+    fn check(&self, system_versions: &SystemVersions, include_unverified: bool) -> Vec<String> {
+        [
+            ("SteamOS", &self.steamos, &system_versions.steamos),
+            ("Mesa", &self.mesa, &system_versions.mesa),
+            ("Kernel", &self.kernel, &system_versions.kernel),
+        ]
+        .into_iter()
+        .filter_map(|(component, required, found)| {
+            let required = required.as_ref()?;
+            match found {
+                Some(found) if compare_versions(found, required) == Ordering::Less => {
+                    Some(format!(
+                        "Requires {} {} or newer, found {}",
+                        component, required, found
+                    ))
+                }
+                Some(_) => None,
+                None if !include_unverified => None,
+                None => Some(format!(
+                    "Requires {} {} or newer, unable to determine installed version",
+                    component, required
+                )),
+            }
+        })
+        .collect()
+    }
+}
+
+impl SystemVersions {
+    /// Detects versions from the system files below `root`.
+    pub fn detect_in(root: &Path) -> SystemVersions {
+        SystemVersions {
+            steamos: detect_steamos_version(root),
+            mesa: detect_mesa_version(root),
+            kernel: fs::read_to_string(root.join("proc/sys/kernel/osrelease"))
+                .ok()
+                .map(|kernel| kernel.trim().to_string())
+                .filter(|kernel| !kernel.is_empty()),
+        }
+    }
+}
+
+/// Loads the curated flavor requirements, which can be refreshed by replacing the file.
+pub fn load_flavor_requirements(flavor: &CompatibilityToolFlavor) -> Requirements {
+    let requirements_file = PathBuf::from(
+        env::var("DECKY_PLUGIN_SETTINGS_DIR").unwrap_or("/tmp/decky-wine-cellar".to_string()),
+    )
+    .join("flavor_requirements.json");
+    if !requirements_file.exists() {
+        return Requirements::default();
+    }
+
+    match fs::read_to_string(&requirements_file)
+        .ok()
+        .and_then(|string| serde_json::from_str::<Vec<FlavorRequirements>>(&string).ok())
+    {
+        Some(flavor_requirements) => flavor_requirements
+            .into_iter()
+            .find(|entry| &entry.flavor == flavor)
+            .map(|entry| entry.requirements)
+            .unwrap_or_default(),
+        None => {
+            warn!("Failed to parse flavor requirements, ignoring them");
+            Requirements::default()
+        }
+    }
+}
+
+fn detect_steamos_version(root: &Path) -> Option<String> {
+    let os_release = fs::read_to_string(root.join("etc/os-release")).ok()?;
+    let value = |key: &str| {
+        os_release
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .map(|value| value.trim_matches('"').to_string())
+    };
+    if value("ID")? != "steamos" {
+        return None;
+    }
+    value("VERSION_ID")
+}
+
+fn detect_mesa_version(root: &Path) -> Option<String> {
+    // Pacman keeps one directory per installed package named `<name>-[<epoch>:]<version>-<release>`
+    if let Ok(entries) = fs::read_dir(root.join("var/lib/pacman/local")) {
+        for entry in entries.filter_map(Result::ok) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let package_version = ["mesa-", "vulkan-radeon-"]
+                .iter()
+                .find_map(|prefix| name.strip_prefix(prefix))
+                .filter(|version| version.starts_with(|c: char| c.is_ascii_digit()));
+            if let Some(package_version) = package_version {
+                let version = package_version
+                    .rsplit_once(':')
+                    .map_or(package_version, |(_, version)| version);
+                if let Some(version) = leading_version(version) {
+                    return Some(version);
+                }
+            }
+        }
+    }
+
+    // The RADV driver embeds its version as "Mesa <version>"
+    for library in [
+        "usr/lib/libvulkan_radeon.so",
+        "usr/lib64/libvulkan_radeon.so",
+    ] {
+        if let Ok(bytes) = fs::read(root.join(library)) {
+            let marker = b"Mesa ";
+            if let Some(version) = bytes
+                .windows(marker.len())
+                .enumerate()
+                .filter(|(_, window)| *window == marker)
+                .find_map(|(index, _)| {
+                    let rest = &bytes[index + marker.len()..];
+                    let end = rest
+                        .iter()
+                        .position(|b| !(b.is_ascii_digit() || *b == b'.'))
+                        .unwrap_or(rest.len());
+                    leading_version(&String::from_utf8_lossy(&rest[..end]))
+                })
+            {
+                return Some(version);
+            }
+        }
+    }
+
+    None
+}
+
+fn leading_version(string: &str) -> Option<String> {
+    let version: String = string
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+    let version = version.trim_end_matches('.');
+    if version.is_empty() || !version.contains('.') {
+        None
+    } else {
+        Some(version.to_string())
+    }
+}
+
+fn version_components(version: &str) -> Vec<u64> {
+    version
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map(|component| component.parse().unwrap_or(0))
+        .collect()
+}
+
+/// Compares dotted versions numerically, ignoring suffixes like `-valve9`.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let a = version_components(a);
+    let b = version_components(b);
+    for index in 0..a.len().max(b.len()) {
+        let ordering = a.get(index).unwrap_or(&0).cmp(b.get(index).unwrap_or(&0));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::{tempdir, TempDir};
+
+    fn create_test_root(os_release: &str) -> TempDir {
+        let root = tempdir().expect("Failed to create temporary directory");
+        fs::create_dir_all(root.path().join("etc")).unwrap();
+        fs::create_dir_all(root.path().join("proc/sys/kernel")).unwrap();
+        fs::write(root.path().join("etc/os-release"), os_release).unwrap();
+        fs::write(
+            root.path().join("proc/sys/kernel/osrelease"),
+            "6.1.52-valve9-1-neptune-61\n",
+        )
+        .unwrap();
+        root
+    }
+
+    #[test]
+    fn test_detect_steamos_with_pacman_mesa() {
+        let root = create_test_root(
+            "NAME=\"SteamOS\"\nID=steamos\nID_LIKE=arch\nVERSION_ID=3.5.7\nBUILD_ID=20231122.1\n",
+        );
+        let pacman_local = root.path().join("var/lib/pacman/local");
+        fs::create_dir_all(pacman_local.join("mesa-utils-8.5.0-3")).unwrap();
+        fs::create_dir_all(pacman_local.join("mesa-1:23.1.3.steamos_1-1")).unwrap();
+
+        let system_versions = SystemVersions::detect_in(root.path());
+        assert_eq!(system_versions.steamos.as_deref(), Some("3.5.7"));
+        assert_eq!(system_versions.mesa.as_deref(), Some("23.1.3"));
+        assert_eq!(
+            system_versions.kernel.as_deref(),
+            Some("6.1.52-valve9-1-neptune-61")
+        );
+    }
+
+    #[test]
+    fn test_detect_mesa_from_radv_library() {
+        let root = create_test_root("NAME=\"Arch Linux\"\nID=arch\n");
+        fs::create_dir_all(root.path().join("usr/lib")).unwrap();
+        fs::write(
+            root.path().join("usr/lib/libvulkan_radeon.so"),
+            b"\x7fELF\x00\x00radv\x00Mesa \x00Mesa 24.0.5\x00more",
+        )
+        .unwrap();
+
+        let system_versions = SystemVersions::detect_in(root.path());
+        assert_eq!(system_versions.steamos, None);
+        assert_eq!(system_versions.mesa.as_deref(), Some("24.0.5"));
+    }
+
+    #[test]
+    fn test_detect_unknown_mesa() {
+        let root = create_test_root("ID=steamos\nVERSION_ID=3.4\n");
+        assert_eq!(SystemVersions::detect_in(root.path()).mesa, None);
+    }
+
+    #[test]
+    fn test_unmet_requirements() {
+        let requirements = Requirements {
+            steamos: Some("3.5".to_string()),
+            mesa: Some("23.2".to_string()),
+            kernel: Some("6.1".to_string()),
+        };
+        let system_versions = SystemVersions {
+            steamos: Some("3.5.7".to_string()),
+            mesa: Some("23.1.3".to_string()),
+            kernel: None,
+        };
+
+        let unmet = requirements.unmet(&system_versions);
+        assert_eq!(unmet.len(), 2);
+        assert!(unmet[0].contains("Mesa 23.2"));
+        assert!(unmet[1].contains("unable to determine"));
+        assert_eq!(
+            requirements.violated(&system_versions),
+            vec![unmet[0].clone()]
+        );
+        assert!(Requirements::default().unmet(&system_versions).is_empty());
+    }
+
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(compare_versions("6.1.52-valve9", "6.1"), Ordering::Greater);
+        assert_eq!(compare_versions("3.4.11", "3.5"), Ordering::Less);
+        assert_eq!(compare_versions("23.1", "23.1.0"), Ordering::Equal);
+    }
+}
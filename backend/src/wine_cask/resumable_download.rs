@@ -0,0 +1,468 @@
+use crate::runtime_directory;
+use crate::wine_cask::checksum::ArchiveVerifier;
+use crate::written_by::stamped;
+use log::info;
+use reqwest::header::{CONTENT_RANGE, ETAG, IF_RANGE, RANGE};
+use reqwest::{Client, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+/// Times a download is started or resumed before the install gives up, unless the settings say
+/// otherwise.
+pub const DOWNLOAD_ATTEMPTS: u32 = 3;
+/// Wait before the first retry, dropped Wi-Fi connections take a moment to come back. Doubled
+/// for each retry after it.
+pub const RESUME_DELAY: Duration = Duration::from_secs(2);
+/// Longest wait between retries.
+const MAX_RESUME_DELAY: Duration = Duration::from_secs(60);
+/// Interrupted downloads not resumed for this long are deleted, each is hundreds of megabytes.
+const PARTIAL_DOWNLOAD_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What an interrupted download was downloading, next to its partial file.
+#[derive(Serialize, Deserialize)]
+struct PartialDownloadState {
+    url: String,
+    /// Resuming is only safe while the server still serves the same file.
+    etag: Option<String>,
+    total_size: u64,
+}
+
+/// A download kept on disk, so an interrupted one continues where it stopped.
+pub struct PartialDownload {
+    file: PathBuf,
+    state: PartialDownloadState,
+    /// Bytes of the file on disk.
+    pub downloaded: u64,
+    /// `None` until the first chunk after a request, which truncates the file when starting over.
+    writer: Option<File>,
+}
+
+pub fn downloads_directory() -> PathBuf {
+    runtime_directory().join("downloads")
+}
+
+/// Start and total size of a `Content-Range: bytes <start>-<end>/<total>` header.
+fn content_range(response: &Response) -> Option<(u64, u64)> {
+    let value = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+    Some((start.parse().ok()?, total.parse().ok()?))
+}
+
+fn response_etag(response: &Response) -> Option<String> {
+    let etag = response.headers().get(ETAG)?.to_str().ok()?;
+    Some(etag.to_string())
+}
+
+/// Whether a failed download is worth trying again.
+#[derive(PartialEq, Debug)]
+pub enum DownloadFailure {
+    /// Timeouts, lost connections and server errors, which pass.
+    Retryable,
+    /// Missing files and refused requests, which fail the same way every time.
+    Permanent,
+}
+
+pub fn classify_error(err: &reqwest::Error) -> DownloadFailure {
+    match err.status() {
+        Some(status)
+            if status.is_server_error()
+                || status == StatusCode::REQUEST_TIMEOUT
+                || status == StatusCode::TOO_MANY_REQUESTS =>
+        {
+            DownloadFailure::Retryable
+        }
+        Some(_) => DownloadFailure::Permanent,
+        // Invalid URLs and redirect loops won't fix themselves either
+        None if err.is_builder() || err.is_redirect() => DownloadFailure::Permanent,
+        None => DownloadFailure::Retryable,
+    }
+}
+
+/// Wait before retry number `retry`, starting at 1. `jitter` between 0 and 1 spreads the retries
+/// of installs that failed at the same time.
+pub fn retry_delay(retry: u32, jitter: f64) -> Duration {
+    let backoff = RESUME_DELAY.saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)));
+    backoff
+        .min(MAX_RESUME_DELAY)
+        .mul_f64(0.5 + jitter.clamp(0.0, 1.0) / 2.0)
+}
+
+/// A value between 0 and 1 for `retry_delay`, which only needs to differ between installs.
+pub fn jitter() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.subsec_nanos() as f64 / 1e9)
+}
+
+impl PartialDownload {
+    /// Picks up an earlier download of `url` in `directory`. Other downloads may be running at the
+    /// same time, only the ones abandoned long ago are deleted.
+    pub fn open(directory: &Path, url: &str) -> io::Result<PartialDownload> {
+        fs::create_dir_all(directory)?;
+        let name = format!("{:x}", Sha256::digest(url.as_bytes()));
+        let file = directory.join(format!("{}.part", &name[..16]));
+        let state_file = file.with_extension("json");
+        for entry in fs::read_dir(directory)?.filter_map(Result::ok) {
+            let abandoned = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| {
+                    modified
+                        .elapsed()
+                        .is_ok_and(|age| age > PARTIAL_DOWNLOAD_MAX_AGE)
+                });
+            if abandoned {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+
+        let state = fs::read_to_string(&state_file)
+            .ok()
+            .and_then(|string| serde_json::from_str::<PartialDownloadState>(&string).ok())
+            .filter(|state| state.url == url);
+        let downloaded = match &state {
+            Some(_) => fs::metadata(&file).map_or(0, |metadata| metadata.len()),
+            None => 0,
+        };
+        Ok(PartialDownload {
+            file,
+            state: state.unwrap_or(PartialDownloadState {
+                url: url.to_string(),
+                etag: None,
+                total_size: 0,
+            }),
+            downloaded,
+            writer: None,
+        })
+    }
+
+    /// Size of the whole file, 0 if the server didn't tell.
+    pub fn total_size(&self) -> u64 {
+        self.state.total_size
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.state.total_size == 0 || self.downloaded >= self.state.total_size
+    }
+
+    /// Requests the rest of the file, or all of it again if the server can't resume or the file
+    /// changed since.
+    pub async fn request(&mut self, client: &Client) -> reqwest::Result<Response> {
+        // Without an ETag there's no telling whether the bytes on disk are still the same file
+        if let Some(etag) = self.state.etag.clone().filter(|_| self.downloaded > 0) {
+            let response = client
+                .get(&self.state.url)
+                .header(RANGE, format!("bytes={}-", self.downloaded))
+                .header(IF_RANGE, &etag)
+                .send()
+                .await?
+                .error_for_status()?;
+            match response.status() {
+                StatusCode::PARTIAL_CONTENT => {
+                    let resumes = content_range(&response)
+                        .filter(|(start, _)| *start == self.downloaded)
+                        .filter(|_| response_etag(&response).is_none_or(|other| other == etag));
+                    if let Some((_, total_size)) = resumes {
+                        info!(
+                            "Resuming download of {} at {} of {} bytes",
+                            self.state.url, self.downloaded, total_size
+                        );
+                        self.state.total_size = total_size;
+                        return Ok(response);
+                    }
+                }
+                // The server ignored the range or the file changed, the body is all of it
+                _ => {
+                    info!("{} can't be resumed, downloading it again", self.state.url);
+                    self.restart(&response);
+                    return Ok(response);
+                }
+            }
+            info!("{} changed, downloading it again", self.state.url);
+        }
+
+        let response = client
+            .get(&self.state.url)
+            .send()
+            .await?
+            .error_for_status()?;
+        self.restart(&response);
+        Ok(response)
+    }
+
+    fn restart(&mut self, response: &Response) {
+        self.state.etag = response_etag(response);
+        self.state.total_size = response.content_length().unwrap_or(0);
+        self.downloaded = 0;
+        self.writer = None;
+    }
+
+    /// Hashes the bytes already on disk, for verifying the archive once it's complete.
+    pub fn verifier(&self) -> io::Result<ArchiveVerifier> {
+        let mut verifier = ArchiveVerifier::default();
+        if self.downloaded == 0 {
+            return Ok(verifier);
+        }
+        let mut file = File::open(&self.file)?.take(self.downloaded);
+        let mut buffer = vec![0; 1 << 16];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                return Ok(verifier);
+            }
+            verifier.update(&buffer[..read]);
+        }
+    }
+
+    pub fn append(&mut self, chunk: &[u8]) -> io::Result<()> {
+        let mut writer = match self.writer.take() {
+            Some(writer) => writer,
+            None => {
+                let writer = if self.downloaded == 0 {
+                    File::create(&self.file)?
+                } else {
+                    OpenOptions::new().append(true).open(&self.file)?
+                };
+                let state_file = self.file.with_extension("json");
+                fs::write(
+                    &state_file,
+                    serde_json::to_string(&stamped(&self.state, "partial_download", &state_file))?,
+                )?;
+                writer
+            }
+        };
+        writer.write_all(chunk)?;
+        self.downloaded += chunk.len() as u64;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    /// Closes the complete download, returning its file for extraction to stream from. The
+    /// caller deletes the file once done with it.
+    pub fn finish(mut self) -> io::Result<PathBuf> {
+        if let Some(writer) = self.writer.take() {
+            writer.sync_all()?;
+        }
+        let _ = fs::remove_file(self.file.with_extension("json"));
+        Ok(self.file)
+    }
+
+    /// Deletes the download, for cancelled installs and corrupted files.
+    pub fn discard(self) {
+        let _ = fs::remove_file(self.file.with_extension("json"));
+        let _ = fs::remove_file(&self.file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use sha2::Sha512;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use tempfile::tempdir;
+
+    const CUT_AT: usize = 4000;
+
+    fn body() -> Vec<u8> {
+        (0..10_000).map(|i| (i % 251) as u8).collect()
+    }
+
+    enum Reply {
+        /// All of the body, closing the connection after `cut_at` bytes if set.
+        Full {
+            etag: &'static str,
+            cut_at: Option<usize>,
+        },
+        /// The requested range of the body.
+        Ranged { etag: &'static str },
+        /// An error status without a body.
+        Status(u16),
+    }
+
+    /// Answers one connection per reply, in order. Returns the URL and the `Range` header each
+    /// request was sent with.
+    fn serve(replies: Vec<Reply>) -> (String, Arc<Mutex<Vec<Option<String>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/GE-Proton9-21.tar.gz",
+            listener.local_addr().unwrap()
+        );
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let ranges_clone = ranges.clone();
+        thread::spawn(move || {
+            let body = body();
+            for (reply, stream) in replies.into_iter().zip(listener.incoming()) {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut byte = [0];
+                while !request.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+                    request.push(byte[0]);
+                }
+                let request = String::from_utf8(request).unwrap();
+                let range = request.lines().find_map(|line| {
+                    let (name, value) = line.split_once(": ")?;
+                    name.eq_ignore_ascii_case("range")
+                        .then(|| value.to_string())
+                });
+                let start: Option<usize> = range.as_deref().and_then(|range| {
+                    range
+                        .strip_prefix("bytes=")?
+                        .strip_suffix('-')?
+                        .parse()
+                        .ok()
+                });
+                ranges_clone.lock().unwrap().push(range);
+
+                let (head, sent) = match (reply, start) {
+                    (Reply::Status(status), _) => (
+                        format!("HTTP/1.1 {} Error\r\nContent-Length: 0\r\n", status),
+                        &body[..0],
+                    ),
+                    (Reply::Ranged { etag }, Some(start)) => (
+                        format!(
+                            "HTTP/1.1 206 Partial Content\r\nETag: {}\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n",
+                            etag,
+                            start,
+                            body.len() - 1,
+                            body.len(),
+                            body.len() - start
+                        ),
+                        &body[start..],
+                    ),
+                    (Reply::Ranged { etag }, None) | (Reply::Full { etag, cut_at: None }, _) => (
+                        format!("HTTP/1.1 200 OK\r\nETag: {}\r\nContent-Length: {}\r\n", etag, body.len()),
+                        &body[..],
+                    ),
+                    (Reply::Full { etag, cut_at: Some(cut_at) }, _) => (
+                        format!("HTTP/1.1 200 OK\r\nETag: {}\r\nContent-Length: {}\r\n", etag, body.len()),
+                        &body[..cut_at],
+                    ),
+                };
+                let _ = stream.write_all(format!("{}Connection: close\r\n\r\n", head).as_bytes());
+                let _ = stream.write_all(sent);
+            }
+        });
+        (url, ranges)
+    }
+
+    /// Streams the response to disk, returns whether the connection held until the end.
+    async fn download(partial: &mut PartialDownload, client: &Client) -> bool {
+        let mut body = partial.request(client).await.unwrap().bytes_stream();
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(chunk) => partial.append(&chunk).unwrap(),
+                Err(_) => return false,
+            }
+        }
+        partial.is_complete()
+    }
+
+    /// Downloads `url` in a fresh `PartialDownload` for each attempt, like a restarted install.
+    async fn download_twice(directory: &Path, url: &str) -> Vec<u8> {
+        let client = Client::new();
+        let mut partial = PartialDownload::open(directory, url).unwrap();
+        assert!(!download(&mut partial, &client).await);
+        assert_eq!(partial.downloaded, CUT_AT as u64);
+
+        let mut partial = PartialDownload::open(directory, url).unwrap();
+        assert_eq!(partial.downloaded, CUT_AT as u64);
+        assert!(download(&mut partial, &client).await);
+        // Bytes from both attempts are hashed
+        let checksum = partial.verifier().unwrap().finish(None).unwrap();
+        assert_eq!(checksum, format!("{:x}", Sha512::digest(body())));
+        let file = partial.finish().unwrap();
+        let bytes = fs::read(&file).unwrap();
+        fs::remove_file(file).unwrap();
+        assert_eq!(fs::read_dir(directory).unwrap().count(), 0);
+        bytes
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_download_is_resumed() {
+        let directory = tempdir().unwrap();
+        let (url, ranges) = serve(vec![
+            Reply::Full {
+                etag: "\"v1\"",
+                cut_at: Some(CUT_AT),
+            },
+            Reply::Ranged { etag: "\"v1\"" },
+        ]);
+
+        assert_eq!(download_twice(directory.path(), &url).await, body());
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            [None, Some(format!("bytes={}-", CUT_AT))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_retried() {
+        let directory = tempdir().unwrap();
+        let (url, _) = serve(vec![Reply::Status(502), Reply::Status(404)]);
+        let client = Client::new();
+        let mut partial = PartialDownload::open(directory.path(), &url).unwrap();
+
+        let err = partial.request(&client).await.unwrap_err();
+        assert_eq!(classify_error(&err), DownloadFailure::Retryable);
+        let err = partial.request(&client).await.unwrap_err();
+        assert_eq!(classify_error(&err), DownloadFailure::Permanent);
+        // Nobody answers anymore
+        let err = partial.request(&client).await.unwrap_err();
+        assert_eq!(classify_error(&err), DownloadFailure::Retryable);
+    }
+
+    #[test]
+    fn test_retry_delay_backs_off() {
+        assert_eq!(retry_delay(1, 1.0), RESUME_DELAY);
+        assert_eq!(retry_delay(2, 1.0), RESUME_DELAY * 2);
+        assert_eq!(retry_delay(3, 0.0), RESUME_DELAY * 2);
+        assert_eq!(retry_delay(20, 1.0), MAX_RESUME_DELAY);
+        assert!((0.0..1.0).contains(&jitter()));
+    }
+
+    #[tokio::test]
+    async fn test_download_starts_over_when_it_cannot_resume() {
+        // The server ignores the range
+        let directory = tempdir().unwrap();
+        let (url, ranges) = serve(vec![
+            Reply::Full {
+                etag: "\"v1\"",
+                cut_at: Some(CUT_AT),
+            },
+            Reply::Full {
+                etag: "\"v1\"",
+                cut_at: None,
+            },
+        ]);
+        assert_eq!(download_twice(directory.path(), &url).await, body());
+        assert_eq!(ranges.lock().unwrap().len(), 2);
+
+        // The file changed since the first attempt
+        let directory = tempdir().unwrap();
+        let (url, ranges) = serve(vec![
+            Reply::Full {
+                etag: "\"v1\"",
+                cut_at: Some(CUT_AT),
+            },
+            Reply::Ranged { etag: "\"v2\"" },
+            Reply::Full {
+                etag: "\"v2\"",
+                cut_at: None,
+            },
+        ]);
+        assert_eq!(download_twice(directory.path(), &url).await, body());
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            [None, Some(format!("bytes={}-", CUT_AT)), None]
+        );
+    }
+}
@@ -0,0 +1,732 @@
+use crate::app_id::CompatAppId;
+use crate::github_util::{Asset, Release};
+use crate::wine_cask::app::{Task, TaskType, WineCask};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::flavors::{CompatibilityToolFlavor, SteamCompatibilityTool};
+use crate::wine_cask::history::{
+    all_records, record, HistoryOutcome, HistoryRecord, HistoryTaskType, HistoryTrigger,
+};
+use crate::wine_cask::install::Install;
+use crate::wine_cask::install_manifest::ManagedInstall;
+use crate::wine_cask::install_target::all_installed;
+use crate::wine_cask::mappings::{MappingChange, MappingChanges};
+use crate::wine_cask::provenance::{current_timestamp, verify_files, Provenance};
+use crate::wine_cask::recursive_delete_dir_entry;
+use crate::PeerMap;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Days the tools an update replaced are kept unless the settings say otherwise.
+pub const DEFAULT_ROLLBACK_GRACE_DAYS: u64 = 7;
+
+/// An app an update moved to the new release, along with the tool it used before.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct MigratedMapping {
+    pub app_id: CompatAppId,
+    pub previous: String,
+}
+
+/// A tool an update replaced and uninstalled into the trash.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ReplacedTool {
+    pub internal_name: String,
+    pub display_name: String,
+    pub flavor: CompatibilityToolFlavor,
+    /// Directory the tool was installed to, rolling back puts it there again.
+    pub path: String,
+    /// Release the tool was installed from, `None` if the plugin didn't install it.
+    pub tag_name: Option<String>,
+    /// Where the tool is kept, `None` if uninstalling it failed.
+    pub trash: Option<PathBuf>,
+    /// Where the tool came from, rolling back reinstalls it from there once the trash is gone.
+    pub provenance: Option<Provenance>,
+    pub mappings: Vec<MigratedMapping>,
+}
+
+impl ReplacedTool {
+    pub fn new(
+        tool: &SteamCompatibilityTool,
+        trash: Option<PathBuf>,
+        mappings: Vec<MigratedMapping>,
+    ) -> Self {
+        Self {
+            internal_name: tool.internal_name.clone(),
+            display_name: tool.display_name.clone(),
+            flavor: tool.flavor.clone(),
+            path: tool.path.clone(),
+            tag_name: tool.version.clone(),
+            trash,
+            provenance: Provenance::load(&tool.internal_name),
+            mappings,
+        }
+    }
+}
+
+/// How to go back to the tools an update with migration replaced.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct RollbackPlan {
+    /// Internal name of the release the apps were moved to.
+    pub updated: String,
+    pub replaced: Vec<ReplacedTool>,
+    /// The replaced tools are deleted from the trash at the first startup after this timestamp.
+    pub kept_until: u64,
+}
+
+/// How a replaced tool is brought back.
+enum Restore {
+    /// Reinstalled since, only its apps have to be moved back.
+    Installed,
+    /// Moved back from the trash, its files matching its manifest.
+    FromTrash(PathBuf),
+    /// Installed again from the release asset it came from.
+    Reinstall(Box<Provenance>),
+}
+
+/// Whether the history has a successful rollback of record `history_id`.
+fn rolled_back(records: &[HistoryRecord], history_id: u64) -> bool {
+    records.iter().any(|record| {
+        record.task_type == HistoryTaskType::Rollback
+            && record.rolls_back == Some(history_id)
+            && record.outcome == HistoryOutcome::Succeeded
+    })
+}
+
+/// Trash entries kept for rolling back updates whose grace period didn't end yet, cleanups leave
+/// them alone.
+pub fn kept_in_trash(records: &[HistoryRecord], now: u64) -> Vec<PathBuf> {
+    records
+        .iter()
+        .filter_map(|record| record.rollback.as_ref())
+        .filter(|rollback| rollback.kept_until > now)
+        .flat_map(|rollback| &rollback.replaced)
+        .filter_map(|replaced| replaced.trash.clone())
+        .collect()
+}
+
+/// Deletes the tools kept in the trash for updates whose grace period ended.
+pub fn purge_expired_rollbacks(records: &[HistoryRecord], now: u64) {
+    let expired = records
+        .iter()
+        .filter_map(|record| record.rollback.as_ref())
+        .filter(|rollback| rollback.kept_until <= now)
+        .flat_map(|rollback| &rollback.replaced)
+        .filter_map(|replaced| replaced.trash.as_ref())
+        .filter(|trash| trash.exists());
+    for trash in expired {
+        match recursive_delete_dir_entry(trash) {
+            Ok(()) => info!("Deleted {}, its rollback expired", trash.display()),
+            Err(err) => warn!("Failed to delete {}: {}", trash.display(), err),
+        }
+    }
+}
+
+/// Checks how `tool` can be brought back without changing anything yet.
+fn check_restore(tool: &ReplacedTool, installed: &HashSet<String>) -> Result<Restore, AppError> {
+    if installed.contains(&tool.internal_name) {
+        return Ok(Restore::Installed);
+    }
+    let files = tool
+        .provenance
+        .as_ref()
+        .and_then(|provenance| provenance.files.as_ref());
+    let trash = tool.trash.as_ref().filter(|trash| trash.is_dir());
+    if let (Some(trash), Some(files)) = (trash, files) {
+        let verification = verify_files(trash, &tool.internal_name, files);
+        if let Some(err) = verification.error {
+            return Err(AppError::new(
+                AppErrorCode::Internal,
+                format!("Failed to check the copy of {}: {}", tool.display_name, err),
+            ));
+        }
+        if !verification.verified {
+            return Err(AppError::new(
+                AppErrorCode::LocalChanges,
+                format!(
+                    "The copy of {} in the trash changed, {} files differ from its manifest",
+                    tool.display_name,
+                    verification.mismatched.len()
+                        + verification.missing.len()
+                        + verification.added.len()
+                ),
+            ));
+        }
+        if Path::new(&tool.path).exists() {
+            return Err(AppError::new(
+                AppErrorCode::InvalidRequest,
+                format!("{} is in the way of {}", tool.path, tool.display_name),
+            ));
+        }
+        return Ok(Restore::FromTrash(trash.clone()));
+    }
+    match &tool.provenance {
+        Some(provenance) => Ok(Restore::Reinstall(Box::new(provenance.clone()))),
+        None => Err(AppError::new(
+            AppErrorCode::NotFound,
+            format!(
+                "{} is gone from the trash and wasn't installed from a release",
+                tool.display_name
+            ),
+        )),
+    }
+}
+
+/// The release a tool was installed from, as far as its provenance tells.
+fn provenance_release(provenance: &Provenance) -> Release {
+    Release {
+        url: String::new(),
+        id: 0,
+        draft: false,
+        prerelease: false,
+        name: provenance.tag_name.clone(),
+        tag_name: provenance.tag_name.clone(),
+        target_commitish: provenance.source.target_commitish.clone(),
+        assets: vec![Asset {
+            url: provenance.source.asset_url.clone(),
+            id: 0,
+            name: provenance.source.asset_name.clone(),
+            content_type: String::new(),
+            state: "uploaded".to_string(),
+            size: provenance.source.size,
+            download_count: 0,
+            created_at: String::new(),
+            updated_at: provenance.source.uploaded_at.clone(),
+            browser_download_url: provenance.source.asset_url.clone(),
+        }],
+        created_at: String::new(),
+        published_at: String::new(),
+        tarball_url: String::new(),
+        body: String::new(),
+    }
+}
+
+impl WineCask {
+    /// Goes back to the tools the update of history record `history_id` replaced, moving their
+    /// apps back and skipping the release it installed. Records how it went in the history.
+    pub async fn rollback_update(&self, peer_map: &PeerMap, history_id: u64) {
+        let started = Instant::now();
+        let records = all_records();
+        let Some(update) = records
+            .iter()
+            .find(|record| record.id == history_id && history_id != 0)
+        else {
+            let app_error = AppError::new(
+                AppErrorCode::NotFound,
+                format!("No history record {}", history_id),
+            );
+            error!("{}", app_error);
+            self.broadcast_app_error(peer_map, app_error).await;
+            return;
+        };
+
+        let result = match self.roll_back(peer_map, &records, update).await {
+            Ok(message) => {
+                info!("{}", message);
+                self.broadcast_notification(peer_map, &message).await;
+                Ok(HistoryOutcome::Succeeded)
+            }
+            Err(app_error) => {
+                error!("{}", app_error);
+                let error_code = app_error.code;
+                self.broadcast_app_error(peer_map, app_error).await;
+                Err(Some(error_code))
+            }
+        };
+        let mut history_record = HistoryRecord::new(
+            HistoryTaskType::Rollback,
+            update.flavor.clone(),
+            update.version.clone(),
+            started.elapsed(),
+            result,
+            HistoryTrigger::Manual,
+        );
+        history_record.rolls_back = Some(history_id);
+        record(&history_record);
+    }
+
+    async fn roll_back(
+        &self,
+        peer_map: &PeerMap,
+        records: &[HistoryRecord],
+        update: &HistoryRecord,
+    ) -> Result<String, AppError> {
+        let Some(rollback) = update.rollback.clone() else {
+            return Err(AppError::new(
+                AppErrorCode::InvalidRequest,
+                format!("{} didn't move any apps to roll back", update.version),
+            ));
+        };
+        if rolled_back(records, update.id) {
+            return Err(AppError::new(
+                AppErrorCode::InvalidRequest,
+                format!("{} was already rolled back", update.version),
+            ));
+        }
+
+        // Every copy is checked before anything changes, so a bad one leaves the update as it is
+        let installed: HashSet<String> = all_installed(&*self.app_state.lock().await)
+            .into_iter()
+            .map(|tool| tool.internal_name)
+            .collect();
+        let replaced = rollback.replaced.clone();
+        let restores = tokio::task::spawn_blocking(move || {
+            replaced
+                .iter()
+                .map(|tool| check_restore(tool, &installed))
+                .collect::<Result<Vec<Restore>, AppError>>()
+        })
+        .await
+        .map_err(|err| AppError::internal(err.to_string()))??;
+
+        let mappings = self
+            .steam_util
+            .get_compatibility_tools_mappings()
+            .map_err(|err| {
+                AppError::from(err).context("Failed to get compatibility tools mappings")
+            })?;
+        // Apps moved to another tool since the update keep it
+        let moved_back = |tool: &ReplacedTool| -> Vec<MappingChange> {
+            tool.mappings
+                .iter()
+                .filter(|migrated| mappings.get(&migrated.app_id) == Some(&rollback.updated))
+                .map(|migrated| MappingChange {
+                    app_id: migrated.app_id,
+                    compatibility_tool: Some(migrated.previous.clone()),
+                    clear_shader_cache: false,
+                })
+                .collect()
+        };
+
+        // Mappings can only point at installed tools, so the copies in the trash go back first
+        let mut changes = Vec::new();
+        let mut restored = Vec::new();
+        let mut reinstalls = Vec::new();
+        for (tool, restore) in rollback.replaced.iter().zip(restores) {
+            match restore {
+                Restore::Installed => changes.extend(moved_back(tool)),
+                Restore::FromTrash(trash) => {
+                    if let Err(err) = fs::rename(&trash, &tool.path) {
+                        self.undo_restores(peer_map, &restored).await;
+                        return Err(AppError::from(err)
+                            .context(format!("Failed to restore {}", tool.display_name)));
+                    }
+                    info!("Restored {} from {}", tool.display_name, trash.display());
+                    if let Some(tag_name) = &tool.tag_name {
+                        self.app_state
+                            .lock()
+                            .await
+                            .install_manifest
+                            .record(ManagedInstall {
+                                path: tool.path.clone(),
+                                flavor: tool.flavor.clone(),
+                                tag_name: tag_name.clone(),
+                                installed_at: current_timestamp(),
+                            });
+                    }
+                    changes.extend(moved_back(tool));
+                    restored.push((trash, tool));
+                }
+                Restore::Reinstall(provenance) => reinstalls.push((tool, provenance)),
+            }
+        }
+        self.sync_backend_with_installed_compat_tools().await;
+        self.broadcast_app_state(peer_map).await;
+
+        if !changes.is_empty() {
+            let results = self
+                .set_compatibility_tool_mappings(
+                    peer_map,
+                    MappingChanges {
+                        changes,
+                        atomic: true,
+                    },
+                )
+                .await;
+            if let Some(result) = results.iter().find(|result| !result.applied) {
+                // The write is atomic, putting the copies back in the trash leaves the update
+                // as it was
+                self.undo_restores(peer_map, &restored).await;
+                return Err(AppError::new(
+                    AppErrorCode::InvalidRequest,
+                    format!(
+                        "Failed to move app {} back: {}",
+                        result.app_id,
+                        result.reason.as_deref().unwrap_or_default()
+                    ),
+                ));
+            }
+        }
+        for (_, tool) in &restored {
+            if let Some(provenance) = &tool.provenance {
+                if let Err(err) = provenance.save() {
+                    warn!("Failed to save provenance: {}", err);
+                }
+            }
+        }
+
+        let mut reinstalling = Vec::new();
+        for (tool, provenance) in reinstalls {
+            info!(
+                "{} is gone from the trash, reinstalling it from {}",
+                tool.display_name, provenance.source.asset_url
+            );
+            let install = Install {
+                flavor: provenance.flavor.clone(),
+                release: provenance_release(&provenance),
+                ignore_network_cap: false,
+                ignore_disk_space: false,
+                background: false,
+                accept_local_changes_loss: false,
+                copy_install: false,
+                replaces: Vec::new(),
+                migrate_mappings: false,
+                max_size: None,
+                local_path: None,
+                restart_steam: false,
+                trigger: HistoryTrigger::Manual,
+            };
+            let task = Task {
+                install: Some(install),
+                ..Task::new(TaskType::InstallCompatibilityTool)
+            };
+            if !self.add_to_task_queue(task, peer_map).await {
+                return Err(AppError::new(
+                    AppErrorCode::InvalidRequest,
+                    format!("Failed to queue the reinstall of {}", tool.display_name),
+                ));
+            }
+            // Moved back once the reinstall finished
+            self.app_state
+                .lock()
+                .await
+                .pending_mappings
+                .extend(moved_back(tool));
+            reinstalling.push(tool.display_name.clone());
+        }
+        self.set_release_skipped(
+            peer_map,
+            update.flavor.clone(),
+            update.version.clone(),
+            true,
+        )
+        .await;
+
+        Ok(if reinstalling.is_empty() {
+            format!("Rolled back {}", update.version)
+        } else {
+            format!(
+                "Rolled back {}, reinstalling {}",
+                update.version,
+                reinstalling.join(", ")
+            )
+        })
+    }
+
+    /// Moves the copies a failed rollback restored back into the trash.
+    async fn undo_restores(&self, peer_map: &PeerMap, restored: &[(PathBuf, &ReplacedTool)]) {
+        if restored.is_empty() {
+            return;
+        }
+        for (trash, tool) in restored {
+            match fs::rename(&tool.path, trash) {
+                Ok(()) => {
+                    info!("Moved {} back to {}", tool.display_name, trash.display());
+                    self.app_state
+                        .lock()
+                        .await
+                        .install_manifest
+                        .forget(&tool.path);
+                }
+                Err(err) => error!(
+                    "Failed to move {} back to the trash: {}",
+                    tool.display_name, err
+                ),
+            }
+        }
+        self.sync_backend_with_installed_compat_tools().await;
+        self.broadcast_app_state(peer_map).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_id::AppId;
+    use crate::test_fixtures::{asset, release, steam_root};
+    use crate::wine_cask::generate_compatibility_tool_vdf;
+    use crate::wine_cask::history::TEST_HISTORY;
+    use crate::wine_cask::outbox::Outbox;
+    use crate::wine_cask::permissions::PermissionSet;
+    use crate::wine_cask::skipped_releases::SkippedRelease;
+    use crate::wine_cask::startup::Startup;
+    use crate::wine_cask::subscriptions::SubscriptionSet;
+    use crate::{Peer, PeerAddr};
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use tempfile::tempdir;
+    use tokio::sync::Mutex;
+
+    fn tool_archive(name: &str) -> Vec<u8> {
+        let directory = tempdir().unwrap();
+        let tool = directory.path().join(name);
+        fs::create_dir_all(tool.join("files/bin")).unwrap();
+        generate_compatibility_tool_vdf(tool.join("compatibilitytool.vdf"), name, name);
+        fs::write(tool.join("files/bin/wine"), name).unwrap();
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::fast(),
+        ));
+        builder.append_dir_all(name, &tool).unwrap();
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn serve(archives: HashMap<String, Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for mut stream in listener.incoming().filter_map(Result::ok) {
+                let mut request = Vec::new();
+                let mut byte = [0];
+                while !request.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                    request.push(byte[0]);
+                }
+                let request = String::from_utf8_lossy(&request).to_string();
+                let path = request
+                    .split(' ')
+                    .nth(1)
+                    .unwrap_or("/")
+                    .trim_start_matches('/');
+                let response = match archives.get(path) {
+                    Some(body) => {
+                        let mut response = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            body.len()
+                        )
+                        .into_bytes();
+                        response.extend_from_slice(body);
+                        response
+                    }
+                    None => {
+                        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_vec()
+                    }
+                };
+                let _ = stream.write_all(&response);
+            }
+        });
+        url
+    }
+
+    /// A release whose only asset is the archive `serve` hands out for it.
+    fn served_release(url: &str, tag_name: &str, size: u64) -> Release {
+        let name = format!("{}.tar.gz", tag_name);
+        Release {
+            assets: vec![Asset {
+                browser_download_url: format!("{}/{}", url, name),
+                content_type: "application/gzip".to_string(),
+                state: "uploaded".to_string(),
+                size,
+                ..asset(&name)
+            }],
+            ..release(tag_name)
+        }
+    }
+
+    fn install(url: &str, tag_name: &str, size: u64, migrate_from: Option<&str>) -> Install {
+        Install {
+            flavor: CompatibilityToolFlavor::ProtonGE,
+            release: served_release(url, tag_name, size),
+            ignore_network_cap: true,
+            ignore_disk_space: true,
+            background: false,
+            accept_local_changes_loss: false,
+            copy_install: false,
+            replaces: migrate_from.into_iter().map(str::to_string).collect(),
+            migrate_mappings: migrate_from.is_some(),
+            max_size: None,
+            local_path: None,
+            restart_steam: false,
+            trigger: HistoryTrigger::Manual,
+        }
+    }
+
+    fn last_record(task_type: HistoryTaskType) -> HistoryRecord {
+        all_records()
+            .into_iter()
+            .rev()
+            .find(|record| record.task_type == task_type)
+            .unwrap()
+    }
+
+    fn mapped_tool(wine_cask: &WineCask) -> String {
+        let mappings = wine_cask
+            .steam_util
+            .get_compatibility_tools_mappings()
+            .unwrap();
+        mappings[&CompatAppId::from(AppId::new(1245620).unwrap())].clone()
+    }
+
+    #[tokio::test]
+    async fn test_rollback_update() {
+        let _history = TEST_HISTORY.lock().await;
+        let steam = tempdir().unwrap();
+        let runtime = tempdir().unwrap();
+        steam_root(steam.path());
+        let old = tool_archive("GE-Proton9-20");
+        let new = tool_archive("GE-Proton9-21");
+        let (old_size, new_size) = (old.len() as u64, new.len() as u64);
+        let url = serve(HashMap::from([
+            ("GE-Proton9-20.tar.gz".to_string(), old),
+            ("GE-Proton9-21.tar.gz".to_string(), new),
+        ]));
+        let peer_map: PeerMap = Arc::new(Mutex::new(HashMap::new()));
+        peer_map.lock().await.insert(
+            PeerAddr::Tcp("127.0.0.1:8887".parse().unwrap()),
+            Peer {
+                outbox: Arc::new(Outbox::default()),
+                permissions: PermissionSet::all(),
+                subscriptions: SubscriptionSet::default(),
+            },
+        );
+        let steam_directory = steam.path().to_path_buf();
+        let wine_cask = Startup::new()
+            .initialize(&peer_map, || vec![steam_directory], runtime.path())
+            .await;
+        {
+            let mut app_state = wine_cask.app_state.lock().await;
+            app_state.available_compat_tools = Some(Vec::new());
+            app_state.settings.feature_flags.write_steam_config = true;
+        }
+        let old_tool = steam.path().join("compatibilitytools.d/GE-Proton9-20");
+
+        wine_cask
+            .install_compatibility_tool(
+                900001,
+                install(&url, "GE-Proton9-20", old_size, None),
+                &peer_map,
+            )
+            .await;
+        assert!(old_tool.is_dir());
+
+        // Updating moves the app to the new release and keeps the old one in the trash
+        wine_cask
+            .install_compatibility_tool(
+                900002,
+                install(&url, "GE-Proton9-21", new_size, Some("GE-Proton9-20")),
+                &peer_map,
+            )
+            .await;
+        assert!(!old_tool.exists());
+        assert_eq!(mapped_tool(&wine_cask), "GE-Proton9-21");
+        let update = last_record(HistoryTaskType::Install);
+        let rollback = update.rollback.clone().unwrap();
+        assert_eq!(rollback.updated, "GE-Proton9-21");
+        let trash = rollback.replaced[0].trash.clone().unwrap();
+        assert!(trash.is_dir());
+        assert_eq!(
+            kept_in_trash(&all_records(), current_timestamp()),
+            vec![trash.clone()]
+        );
+
+        // A copy that changed in the trash is refused before any mapping changes
+        fs::write(trash.join("files/bin/wine"), "changed").unwrap();
+        wine_cask.rollback_update(&peer_map, update.id).await;
+        let refused = last_record(HistoryTaskType::Rollback);
+        assert_eq!(refused.error_code, Some(AppErrorCode::LocalChanges));
+        assert_eq!(mapped_tool(&wine_cask), "GE-Proton9-21");
+        assert!(!old_tool.exists());
+
+        fs::write(trash.join("files/bin/wine"), "GE-Proton9-20").unwrap();
+
+        // A mapping write that fails puts the restored copy back in the trash
+        wine_cask
+            .app_state
+            .lock()
+            .await
+            .settings
+            .feature_flags
+            .write_steam_config = false;
+        wine_cask.rollback_update(&peer_map, update.id).await;
+        let failed = last_record(HistoryTaskType::Rollback);
+        assert_eq!(failed.outcome, HistoryOutcome::Failed);
+        assert!(!old_tool.exists());
+        assert!(trash.join("files/bin/wine").is_file());
+        assert_eq!(mapped_tool(&wine_cask), "GE-Proton9-21");
+        wine_cask
+            .app_state
+            .lock()
+            .await
+            .settings
+            .feature_flags
+            .write_steam_config = true;
+
+        wine_cask.rollback_update(&peer_map, update.id).await;
+        let rolled_back = last_record(HistoryTaskType::Rollback);
+        assert_eq!(rolled_back.outcome, HistoryOutcome::Succeeded);
+        assert_eq!(rolled_back.rolls_back, Some(update.id));
+        assert!(old_tool.join("files/bin/wine").is_file());
+        assert!(!trash.exists());
+        assert_eq!(mapped_tool(&wine_cask), "GE-Proton9-20");
+        let skipped = SkippedRelease {
+            flavor: CompatibilityToolFlavor::ProtonGE,
+            tag_name: "GE-Proton9-21".to_string(),
+        };
+        assert!(wine_cask
+            .app_state
+            .lock()
+            .await
+            .settings
+            .skipped_releases
+            .contains(&skipped));
+
+        // Once the trash entry is gone the old release is reinstalled from its provenance
+        wine_cask
+            .install_compatibility_tool(
+                900003,
+                install(&url, "GE-Proton9-21", new_size, Some("GE-Proton9-20")),
+                &peer_map,
+            )
+            .await;
+        assert_eq!(mapped_tool(&wine_cask), "GE-Proton9-21");
+        let update = last_record(HistoryTaskType::Install);
+        let trash = update.rollback.as_ref().unwrap().replaced[0]
+            .trash
+            .clone()
+            .unwrap();
+        fs::remove_dir_all(&trash).unwrap();
+        wine_cask.rollback_update(&peer_map, update.id).await;
+        assert!(!old_tool.exists());
+        let task = wine_cask.next_runnable_task(&HashSet::new()).await.unwrap();
+        let reinstall = task.install.unwrap();
+        assert_eq!(reinstall.release.tag_name, "GE-Proton9-20");
+        assert_eq!(
+            reinstall.release.assets[0].browser_download_url,
+            format!("{}/GE-Proton9-20.tar.gz", url)
+        );
+        wine_cask
+            .install_compatibility_tool(task.id, reinstall, &peer_map)
+            .await;
+        assert!(old_tool.join("files/bin/wine").is_file());
+        assert_eq!(mapped_tool(&wine_cask), "GE-Proton9-20");
+
+        // Rolling back twice is refused
+        wine_cask.rollback_update(&peer_map, update.id).await;
+        let refused = last_record(HistoryTaskType::Rollback);
+        assert_eq!(refused.error_code, Some(AppErrorCode::InvalidRequest));
+
+        wine_cask
+            .set_release_skipped(
+                &peer_map,
+                CompatibilityToolFlavor::ProtonGE,
+                "GE-Proton9-21".to_string(),
+                false,
+            )
+            .await;
+    }
+}
@@ -0,0 +1,162 @@
+use crate::app_id::{AppId, CompatAppId};
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, WineCask};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::PeerMap;
+use log::warn;
+use std::collections::HashMap;
+
+/// A game Steam runs, `name` is the app id when the name isn't known.
+#[derive(PartialEq, Clone, Debug)]
+pub struct RunningGame {
+    pub app_id: AppId,
+    pub name: String,
+}
+
+impl RunningGame {
+    /// The `OperationBlockedGameRunning` error refusing `operation`, e.g. `uninstall GE-Proton9-20`.
+    pub fn blocks(&self, operation: &str) -> AppError {
+        AppError::new(
+            AppErrorCode::OperationBlockedGameRunning,
+            format!("Can't {} while {} is running", operation, self.name),
+        )
+    }
+}
+
+/// Whether the running `app_id` uses one of `internal_names`, apps without a mapping of their own
+/// use the default tool.
+pub fn uses_tool(
+    mappings: &HashMap<CompatAppId, String>,
+    app_id: AppId,
+    internal_names: &[String],
+) -> bool {
+    mappings
+        .get(&CompatAppId::from(app_id))
+        .or_else(|| mappings.get(&CompatAppId::DEFAULT))
+        .is_some_and(|internal_name| internal_names.contains(internal_name))
+}
+
+/// Whether changing the mapping of `target` changes the tool the running `app_id` uses.
+pub fn mapping_affects(
+    mappings: &HashMap<CompatAppId, String>,
+    target: CompatAppId,
+    app_id: AppId,
+) -> bool {
+    let app_id = CompatAppId::from(app_id);
+    target == app_id || (target == CompatAppId::DEFAULT && !mappings.contains_key(&app_id))
+}
+
+impl WineCask {
+    /// Lists the games Steam runs. Detection is best effort, when it fails the peers are warned
+    /// and nothing is considered running.
+    pub async fn running_games(&self, peer_map: &PeerMap) -> Vec<RunningGame> {
+        let steam_util = self.steam_util.clone();
+        let detected = tokio::task::spawn_blocking(move || steam_util.get_running_app_ids())
+            .await
+            .unwrap();
+        let app_ids = match detected {
+            Ok(app_ids) => app_ids,
+            Err(err) => {
+                let message = format!(
+                    "Warning: Couldn't tell whether games are running, going ahead: {}",
+                    err
+                );
+                warn!("{}", message);
+                let response = Request {
+                    notification: Some(message),
+                    running_games_unknown: Some(true),
+                    ..Request::new(RequestType::Notification)
+                };
+                broadcast_to_peers(peer_map, &response).await;
+                return Vec::new();
+            }
+        };
+        if app_ids.is_empty() {
+            return Vec::new();
+        }
+
+        let mut names = self.app_name_resolver.lock().unwrap().cached_names();
+        if let Ok(installed_games) = self.steam_util.list_installed_games() {
+            names.extend(
+                installed_games
+                    .into_iter()
+                    .map(|game| (game.app_id, game.name)),
+            );
+        }
+        app_ids
+            .into_iter()
+            .map(|app_id| RunningGame {
+                app_id,
+                name: names.remove(&app_id).unwrap_or_else(|| app_id.to_string()),
+            })
+            .collect()
+    }
+
+    /// Refuses `operation` while a game `affected` picks is running.
+    pub async fn refuse_while_running(
+        &self,
+        peer_map: &PeerMap,
+        operation: &str,
+        affected: impl Fn(AppId) -> bool,
+    ) -> Result<(), AppError> {
+        match self
+            .running_games(peer_map)
+            .await
+            .into_iter()
+            .find(|game| affected(game.app_id))
+        {
+            Some(game) => Err(game.blocks(operation)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(app_id: u32) -> AppId {
+        AppId::new(app_id).unwrap()
+    }
+
+    #[test]
+    fn test_unmapped_games_use_the_default_tool() {
+        let mappings = HashMap::from([
+            (CompatAppId::DEFAULT, "GE-Proton9-20".to_string()),
+            (CompatAppId::from(app(730)), "proton_8".to_string()),
+        ]);
+        let removed = ["GE-Proton9-20".to_string()];
+        assert!(uses_tool(&mappings, app(1245620), &removed));
+        assert!(!uses_tool(&mappings, app(730), &removed));
+
+        assert!(mapping_affects(
+            &mappings,
+            CompatAppId::DEFAULT,
+            app(1245620)
+        ));
+        assert!(!mapping_affects(&mappings, CompatAppId::DEFAULT, app(730)));
+        assert!(mapping_affects(
+            &mappings,
+            CompatAppId::from(app(730)),
+            app(730)
+        ));
+        assert!(!mapping_affects(
+            &mappings,
+            CompatAppId::from(app(730)),
+            app(1245620)
+        ));
+    }
+
+    #[test]
+    fn test_blocked_operations_name_the_game() {
+        let game = RunningGame {
+            app_id: app(1245620),
+            name: "ELDEN RING".to_string(),
+        };
+        let app_error = game.blocks("uninstall GE-Proton9-20");
+        assert_eq!(app_error.code, AppErrorCode::OperationBlockedGameRunning);
+        assert_eq!(
+            app_error.message,
+            "Can't uninstall GE-Proton9-20 while ELDEN RING is running"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use crate::wine_cask::app::{Request, RequestType};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+/// File in the runtime directory holding the token, the plugin hands it to the frontend.
+pub const SESSION_TOKEN_FILE: &str = "session-token";
+/// How long a peer has to authenticate after connecting.
+pub const AUTHENTICATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Secret peers prove they may control the backend with, a new one every time it starts.
+#[derive(Clone)]
+pub struct SessionToken(Arc<String>);
+
+impl SessionToken {
+    pub fn new(token: String) -> Self {
+        SessionToken(Arc::new(token))
+    }
+
+    pub fn generate() -> io::Result<Self> {
+        let mut bytes = [0; 32];
+        File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+        let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        Ok(SessionToken::new(token))
+    }
+
+    /// Writes the token readable by the plugin's user alone, replacing the one of the last run.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = path.with_extension("tmp");
+        let _ = fs::remove_file(&temp_path);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&temp_path)?;
+        file.write_all(self.0.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    }
+
+    /// Compares in constant time, so the token can't be guessed byte by byte.
+    pub fn matches(&self, candidate: &str) -> bool {
+        let token = self.0.as_bytes();
+        let candidate = candidate.as_bytes();
+        token.len() == candidate.len()
+            && token
+                .iter()
+                .zip(candidate)
+                .fold(0, |difference, (a, b)| difference | (a ^ b))
+                == 0
+    }
+}
+
+/// What a peer's first message means for its connection.
+#[derive(PartialEq, Debug)]
+pub enum Authentication {
+    Authenticated,
+    /// Allowed by `allow_unauthenticated_peers`, the message is handled like any other.
+    Unauthenticated(Message),
+    /// Sent to the peer before it's disconnected.
+    Rejected(AppError),
+}
+
+/// Checks that the first message of a peer is an `Authenticate` carrying the session token.
+pub fn authenticate(
+    first_message: Message,
+    session_token: &SessionToken,
+    allow_unauthenticated: bool,
+) -> Authentication {
+    let request = first_message
+        .to_text()
+        .ok()
+        .and_then(|text| serde_json::from_str::<Request>(text).ok());
+    let Some(request) = request.filter(|request| request.r#type == RequestType::Authenticate)
+    else {
+        if allow_unauthenticated {
+            return Authentication::Unauthenticated(first_message);
+        }
+        return Authentication::Rejected(AppError::new(
+            AppErrorCode::Unauthenticated,
+            "The first message has to be an Authenticate request",
+        ));
+    };
+    match request.session_token {
+        Some(token) if session_token.matches(&token) => Authentication::Authenticated,
+        Some(_) => Authentication::Rejected(AppError::new(
+            AppErrorCode::Unauthenticated,
+            "Wrong session token, it changes every time the backend starts",
+        )),
+        None => Authentication::Rejected(AppError::new(
+            AppErrorCode::Unauthenticated,
+            "The Authenticate request has no session token",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    fn authenticate_request(session_token: Option<&str>) -> Message {
+        let request = Request {
+            session_token: session_token.map(str::to_string),
+            ..Request::new(RequestType::Authenticate)
+        };
+        Message::text(serde_json::to_string(&request).unwrap())
+    }
+
+    fn is_rejected(authentication: &Authentication) -> bool {
+        matches!(
+            authentication,
+            Authentication::Rejected(AppError {
+                code: AppErrorCode::Unauthenticated,
+                ..
+            })
+        )
+    }
+
+    #[test]
+    fn test_peers_authenticate_with_the_session_token() {
+        let session_token = SessionToken::generate().unwrap();
+        assert_eq!(
+            authenticate(
+                authenticate_request(Some(&session_token.0)),
+                &session_token,
+                false
+            ),
+            Authentication::Authenticated
+        );
+        assert!(is_rejected(&authenticate(
+            authenticate_request(Some("0123456789abcdef")),
+            &session_token,
+            false
+        )));
+        assert!(is_rejected(&authenticate(
+            authenticate_request(None),
+            &session_token,
+            false
+        )));
+    }
+
+    #[test]
+    fn test_other_first_messages_need_the_grace_setting() {
+        let session_token = SessionToken::generate().unwrap();
+        let request_state = Message::text("{\"type\":\"RequestState\"}");
+        assert!(is_rejected(&authenticate(
+            request_state.clone(),
+            &session_token,
+            false
+        )));
+        assert_eq!(
+            authenticate(request_state.clone(), &session_token, true),
+            Authentication::Unauthenticated(request_state)
+        );
+        // A wrong token is rejected even with the grace setting
+        assert!(is_rejected(&authenticate(
+            authenticate_request(Some("0123456789abcdef")),
+            &session_token,
+            true
+        )));
+    }
+
+    #[test]
+    fn test_tokens_rotate_on_restart() {
+        let runtime_directory = tempdir().unwrap();
+        let path = runtime_directory.path().join(SESSION_TOKEN_FILE);
+        let first_run = SessionToken::generate().unwrap();
+        first_run.write(&path).unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        let second_run = SessionToken::generate().unwrap();
+        second_run.write(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), *second_run.0);
+        assert!(is_rejected(&authenticate(
+            authenticate_request(Some(&first_run.0)),
+            &second_run,
+            false
+        )));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::wine_cask::feature_flags::FeatureFlags;
+use crate::wine_cask::flavors::CompatibilityToolFlavor;
+use crate::wine_cask::permissions::AccessToken;
+use crate::wine_cask::quick_slots::QuickSlots;
+use crate::wine_cask::skipped_releases::SkippedRelease;
+use crate::written_by::stamped;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::{env, fs, io};
+
+/// Backend settings persisted in the plugin settings directory.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Settings {
+    /// Maximum number of bytes of release assets to download per calendar month, unlimited if `None`.
+    pub monthly_network_cap: Option<u64>,
+    /// Look up names of mapped apps without a local manifest on the Steam store.
+    ///
+    /// Off by default since it sends those app ids to store.steampowered.com, answers are cached so each app is only looked up once.
+    pub resolve_app_names: bool,
+    pub feature_flags: FeatureFlags,
+    /// Skip hashing every extracted file during installs, which makes installed tools unverifiable.
+    pub skip_file_manifest: bool,
+    /// Always extract one file at a time, even on storage that handles parallel writes well.
+    pub force_serial_extraction: bool,
+    /// Install with lowered CPU and disk priority while a game is running.
+    pub background_while_gaming: bool,
+    /// Download speed limit in bytes per second for installs in background mode, unlimited if `None`.
+    pub background_download_limit: Option<u64>,
+    /// Download speed limit in KiB/s for every install, unlimited if 0. Changes apply to
+    /// downloads already running.
+    pub download_rate_limit: u64,
+    /// Times a download is started or resumed before the install gives up, 3 if `None`.
+    pub download_attempts: Option<u32>,
+    /// Also listen on a unix domain socket in the runtime directory, applies after a restart.
+    pub unix_socket: bool,
+    /// Releases not offered as updates, each is dropped once a newer release replaces it.
+    pub skipped_releases: Vec<SkippedRelease>,
+    /// Tokens other clients can connect with for a restricted set of permissions.
+    pub access_tokens: Vec<AccessToken>,
+    /// Accept peers that don't authenticate with the session token, for frontends from before
+    /// it was required.
+    pub allow_unauthenticated_peers: bool,
+    /// Tools each app can be switched between with one tap.
+    pub quick_slots: Vec<QuickSlots>,
+    /// Steam installation to operate on, the first one found if `None` or no longer there.
+    pub steam_directory: Option<String>,
+    /// Write config.vdf while Steam is running, Steam may overwrite the changes when it exits.
+    pub write_while_steam_running: bool,
+    /// Flavors whose downloads aren't checked against a published checksum, for flavors that
+    /// don't publish one.
+    pub skip_checksum_flavors: Vec<CompatibilityToolFlavor>,
+    /// Flavors whose downloads aren't checked against the release's signature.
+    pub skip_signature_flavors: Vec<CompatibilityToolFlavor>,
+    /// Tasks run at once, 2 if `None`. Tasks for the same release always run one after another.
+    pub concurrent_tasks: Option<usize>,
+    /// Personal access token sent with GitHub API requests, raising the rate limit from 60 to
+    /// 5000 requests an hour.
+    pub github_token: Option<String>,
+    /// List Proton-Tkg builds, nightlies of wine-tkg-git that aren't tested like releases.
+    pub proton_tkg_builds: bool,
+    /// Allow installs from plain http URLs, whose downloads can be tampered with on the way.
+    pub direct_install_allow_http: bool,
+    /// Largest archive an install from a URL downloads in bytes, 2 GiB if `None`.
+    pub direct_install_max_size: Option<u64>,
+    /// Proxy every request goes through, overriding the `http_proxy`, `https_proxy` and
+    /// `no_proxy` environment variables. The environment is used if `None`.
+    pub proxy_url: Option<String>,
+    /// Check for new releases of the installed tools on a schedule, while no game is running.
+    pub update_checks: bool,
+    /// Hours between update checks, 24 if `None`.
+    pub update_check_interval_hours: Option<u64>,
+    /// Queue the updates an update check finds instead of only telling about them.
+    pub auto_queue_updates: bool,
+    /// Paths below a prefix's `pfx` whose files are compared before a backup is restored over
+    /// it, Documents, AppData and Saved Games of `steamuser` if `None`.
+    pub save_paths: Option<Vec<String>>,
+    /// Days the tools an update moved apps away from stay in the trash to roll back to, 7 if
+    /// `None`. Rolling back reinstalls them afterwards.
+    pub rollback_grace_days: Option<u64>,
+}
+
+impl Settings {
+    pub fn load() -> Settings {
+        let settings_file = settings_file();
+        if !settings_file.exists() {
+            info!("No settings file found, using defaults");
+            return Settings::default();
+        }
+
+        let mut settings: Settings = match fs::read_to_string(&settings_file) {
+            Ok(string) => serde_json::from_str(&string).unwrap_or_else(|err| {
+                warn!("Failed to parse settings file, using defaults: {}", err);
+                Settings::default()
+            }),
+            Err(err) => {
+                error!("Failed to read settings file, using defaults: {}", err);
+                Settings::default()
+            }
+        };
+        // Hand-edited tokens could grant writes without authentication
+        settings
+            .access_tokens
+            .retain(|access_token| match access_token.validate() {
+                Ok(_) => true,
+                Err(err) => {
+                    warn!("Ignoring access token: {}", err);
+                    false
+                }
+            });
+        settings
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let settings_file = settings_file();
+        if let Some(parent) = settings_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(&stamped(self, "settings", &settings_file))?;
+        fs::write(settings_file, json)
+    }
+}
+
+fn settings_file() -> PathBuf {
+    PathBuf::from(
+        env::var("DECKY_PLUGIN_SETTINGS_DIR").unwrap_or("/tmp/decky-wine-cellar".to_string()),
+    )
+    .join("wine-cask-settings.json")
+}
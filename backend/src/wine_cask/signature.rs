@@ -0,0 +1,202 @@
+use crate::github_util::Asset;
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::flavors::{signing_key, SigningKey};
+use crate::wine_cask::install::{Install, QueueCompatibilityTool};
+use crate::wine_cask::network_usage::NetworkTraffic;
+use crate::wine_cask::proxy::ProxyConfig;
+use crate::PeerMap;
+use log::warn;
+use ring::signature::{UnparsedPublicKey, ED25519};
+
+#[derive(PartialEq, Debug)]
+pub enum SignatureError {
+    /// The archive wasn't signed with the flavor's key, or was changed after it was.
+    Invalid(String),
+    /// The signature couldn't be downloaded.
+    Unverifiable(String),
+}
+
+/// The signature asset released alongside the archive, e.g. `GE-Proton9-21.tar.gz.sig`.
+pub fn signature_asset<'a>(
+    assets: &'a [Asset],
+    archive: &Asset,
+    key: &SigningKey,
+) -> Option<&'a Asset> {
+    key.signature_suffixes.iter().find_map(|suffix| {
+        let name = format!("{}{}", archive.name, suffix);
+        assets.iter().find(|asset| asset.name == name)
+    })
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|start| u8::from_str_radix(hex.get(start..start + 2)?, 16).ok())
+        .collect()
+}
+
+/// Checks the Ed25519 `signature` of the archive with SHA-512 digest `checksum` against
+/// `public_key`. Signature assets hold the signature of the digest as 128 hex digits.
+pub fn verify_signature(
+    public_key: &[u8; 32],
+    signature: &str,
+    checksum: &str,
+) -> Result<(), SignatureError> {
+    let signature = decode_hex(signature.trim())
+        .ok_or_else(|| SignatureError::Invalid("The signature isn't hex encoded".to_string()))?;
+    let digest = decode_hex(checksum)
+        .ok_or_else(|| SignatureError::Invalid(format!("Malformed checksum {}", checksum)))?;
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(&digest, &signature)
+        .map_err(|_| {
+            SignatureError::Invalid(
+                "The archive isn't signed with the flavor's key or was changed since".to_string(),
+            )
+        })
+}
+
+impl WineCask {
+    /// Checks the archive with SHA-512 digest `checksum` against the signature released with
+    /// it, if its flavor signs its releases.
+    pub async fn verify_release_signature(
+        &self,
+        peer_map: &PeerMap,
+        install: &Install,
+        queue_compatibility_tool: &QueueCompatibilityTool,
+        checksum: &str,
+    ) -> Result<(), SignatureError> {
+        let Some(key) = signing_key(&install.flavor) else {
+            return Ok(());
+        };
+        let skipped = self
+            .app_state
+            .lock()
+            .await
+            .settings
+            .skip_signature_flavors
+            .contains(&install.flavor);
+        if skipped {
+            return Ok(());
+        }
+        let assets = &install.release.assets;
+        let signature_asset = assets
+            .iter()
+            .find(|asset| asset.browser_download_url == queue_compatibility_tool.url)
+            .and_then(|archive| signature_asset(assets, archive, key));
+        let Some(signature_asset) = signature_asset else {
+            // Releases from before the flavor started signing them
+            let warning_message = format!(
+                "{} has no published signature, the download can't be verified",
+                queue_compatibility_tool.name
+            );
+            warn!("{}", warning_message);
+            self.broadcast_notification(peer_map, &format!("Warning: {}", warning_message))
+                .await;
+            return Ok(());
+        };
+
+        let proxy = ProxyConfig::current();
+        let signature = async {
+            proxy
+                .client_builder()
+                .build()?
+                .get(&signature_asset.browser_download_url)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        }
+        .await
+        .map_err(|err| {
+            SignatureError::Unverifiable(format!(
+                "Failed to download {}: {}",
+                signature_asset.name,
+                proxy.describe_error(&err)
+            ))
+        })?;
+        self.record_network_usage(NetworkTraffic::Asset, signature.len() as u64)
+            .await;
+        verify_signature(&key.public_key, &signature, checksum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha512};
+
+    /// Made for these tests only from a fixed seed.
+    const TEST_KEY: [u8; 32] = [
+        154, 27, 150, 16, 89, 143, 41, 148, 45, 50, 65, 189, 84, 200, 24, 11, 92, 225, 37, 235,
+        145, 45, 14, 27, 78, 46, 126, 172, 183, 61, 131, 84,
+    ];
+    const SIGNED_ARCHIVE: &[u8] = b"GE-Proton9-21/proton\n";
+    const SIGNATURE: &str = "e50abfdf02ce7670f40410b809a55c9d81782aa0276975becb24f12c14092b1b\
+        b75c5f4e9b1e550046ae7d300f80f9e463a441af3aafa16b4c72a4090a4fd005\n";
+
+    fn asset(name: &str) -> Asset {
+        Asset {
+            url: String::new(),
+            id: 0,
+            name: name.to_string(),
+            content_type: String::new(),
+            state: String::new(),
+            size: 0,
+            download_count: 0,
+            created_at: String::new(),
+            updated_at: String::new(),
+            browser_download_url: String::new(),
+        }
+    }
+
+    fn checksum(archive: &[u8]) -> String {
+        format!("{:x}", Sha512::digest(archive))
+    }
+
+    #[test]
+    fn test_signature_asset() {
+        let key = SigningKey {
+            signature_suffixes: &[".sig", ".ed25519"],
+            public_key: TEST_KEY,
+        };
+        let assets = [
+            asset("GE-Proton9-21.tar.gz"),
+            asset("GE-Proton9-21.tar.gz.ed25519"),
+            asset("GE-Proton9-20.tar.gz.sig"),
+        ];
+        assert_eq!(
+            signature_asset(&assets, &assets[0], &key).unwrap().name,
+            "GE-Proton9-21.tar.gz.ed25519"
+        );
+        assert!(signature_asset(&assets, &asset("GE-Proton9-22.tar.gz"), &key).is_none());
+    }
+
+    #[test]
+    fn test_tampered_archive_fails_verification() {
+        assert_eq!(
+            verify_signature(&TEST_KEY, SIGNATURE, &checksum(SIGNED_ARCHIVE)),
+            Ok(())
+        );
+
+        let mut tampered = SIGNED_ARCHIVE.to_vec();
+        tampered[0] ^= 1;
+        assert!(matches!(
+            verify_signature(&TEST_KEY, SIGNATURE, &checksum(&tampered)),
+            Err(SignatureError::Invalid(_))
+        ));
+        let mut other_key = TEST_KEY;
+        other_key[0] ^= 1;
+        assert!(matches!(
+            verify_signature(&other_key, SIGNATURE, &checksum(SIGNED_ARCHIVE)),
+            Err(SignatureError::Invalid(_))
+        ));
+        assert!(matches!(
+            verify_signature(&TEST_KEY, "not a signature", &checksum(SIGNED_ARCHIVE)),
+            Err(SignatureError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::github_util::Release;
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::flavors::CompatibilityToolFlavor;
+use crate::PeerMap;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+/// A release the user doesn't want to be offered, e.g. because of a known regression.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct SkippedRelease {
+    pub flavor: CompatibilityToolFlavor,
+    pub tag_name: String,
+}
+
+pub fn skipped_tags<'a>(
+    skipped_releases: &'a [SkippedRelease],
+    flavor: &CompatibilityToolFlavor,
+) -> Vec<&'a str> {
+    skipped_releases
+        .iter()
+        .filter(|skipped| skipped.flavor == *flavor)
+        .map(|skipped| skipped.tag_name.as_str())
+        .collect()
+}
+
+/// Newest release that isn't skipped, `releases` are newest first like GitHub lists them.
+pub fn latest_release<'a>(releases: &'a [Release], skipped_tags: &[&str]) -> Option<&'a Release> {
+    releases
+        .iter()
+        .find(|release| !skipped_tags.contains(&release.tag_name.as_str()))
+}
+
+/// Skipped tags older than the latest release, which already replaces them.
+pub fn superseded_skips(releases: &[Release], skipped_tags: &[&str]) -> Vec<String> {
+    let Some(latest) = latest_release(releases, skipped_tags) else {
+        return Vec::new();
+    };
+    releases
+        .iter()
+        .skip_while(|release| release.tag_name != latest.tag_name)
+        .filter(|release| skipped_tags.contains(&release.tag_name.as_str()))
+        .map(|release| release.tag_name.clone())
+        .collect()
+}
+
+impl WineCask {
+    pub async fn set_release_skipped(
+        &self,
+        peer_map: &PeerMap,
+        flavor: CompatibilityToolFlavor,
+        tag_name: String,
+        skipped: bool,
+    ) {
+        let mut settings = self.app_state.lock().await.settings.clone();
+        settings
+            .skipped_releases
+            .retain(|skipped| skipped.flavor != flavor || skipped.tag_name != tag_name);
+        if skipped {
+            settings
+                .skipped_releases
+                .push(SkippedRelease { flavor, tag_name });
+        }
+        self.update_settings(peer_map, settings).await;
+    }
+
+    /// Drops skips once a newer release replaced them, so the skip list doesn't grow forever.
+    pub async fn clear_superseded_skips(&self, peer_map: &PeerMap) {
+        let app_state = self.app_state.lock().await;
+        let mut settings = app_state.settings.clone();
+        let superseded: Vec<SkippedRelease> = app_state
+            .flavors
+            .iter()
+            .flat_map(|flavor| {
+                let skipped_tags = skipped_tags(&settings.skipped_releases, &flavor.flavor);
+                superseded_skips(&flavor.releases, &skipped_tags)
+                    .into_iter()
+                    .map(|tag_name| SkippedRelease {
+                        flavor: flavor.flavor.clone(),
+                        tag_name,
+                    })
+            })
+            .collect();
+        drop(app_state);
+        if superseded.is_empty() {
+            return;
+        }
+
+        for skipped in &superseded {
+            info!(
+                "No longer skipping {} {}, a newer release replaced it",
+                skipped.flavor, skipped.tag_name
+            );
+        }
+        settings
+            .skipped_releases
+            .retain(|skipped| !superseded.contains(skipped));
+        self.update_settings(peer_map, settings).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::release;
+
+    fn releases(tag_names: &[&str]) -> Vec<Release> {
+        tag_names.iter().map(|tag_name| release(tag_name)).collect()
+    }
+
+    #[test]
+    fn test_latest_release_skips_dismissed_tags() {
+        let releases = releases(&["GE-Proton9-22", "GE-Proton9-21", "GE-Proton9-20"]);
+        assert_eq!(
+            latest_release(&releases, &[]).unwrap().tag_name,
+            "GE-Proton9-22"
+        );
+        assert_eq!(
+            latest_release(&releases, &["GE-Proton9-22", "GE-Proton9-21"])
+                .unwrap()
+                .tag_name,
+            "GE-Proton9-20"
+        );
+        assert!(latest_release(
+            &releases,
+            &["GE-Proton9-22", "GE-Proton9-21", "GE-Proton9-20"]
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_skips_are_cleared_once_superseded() {
+        let skipped = ["GE-Proton9-22", "GE-Proton9-21", "GE-Proton9-18"];
+        // The two newest releases regressed, nothing replaced them yet
+        let before = releases(&[
+            "GE-Proton9-22",
+            "GE-Proton9-21",
+            "GE-Proton9-20",
+            "GE-Proton9-19",
+            "GE-Proton9-18",
+        ]);
+        assert_eq!(superseded_skips(&before, &skipped), vec!["GE-Proton9-18"]);
+
+        let after = releases(&[
+            "GE-Proton9-23",
+            "GE-Proton9-22",
+            "GE-Proton9-21",
+            "GE-Proton9-20",
+        ]);
+        assert_eq!(
+            superseded_skips(&after, &skipped),
+            vec!["GE-Proton9-22", "GE-Proton9-21"]
+        );
+        // Everything skipped, nothing can supersede anything
+        assert!(superseded_skips(&releases(&["GE-Proton9-22"]), &skipped).is_empty());
+    }
+
+    #[test]
+    fn test_skips_only_apply_to_their_flavor() {
+        let skipped_releases = vec![
+            SkippedRelease {
+                flavor: CompatibilityToolFlavor::ProtonGE,
+                tag_name: "GE-Proton9-22".to_string(),
+            },
+            SkippedRelease {
+                flavor: CompatibilityToolFlavor::Luxtorpeda,
+                tag_name: "v70.0".to_string(),
+            },
+        ];
+        assert_eq!(
+            skipped_tags(&skipped_releases, &CompatibilityToolFlavor::ProtonGE),
+            vec!["GE-Proton9-22"]
+        );
+        assert!(skipped_tags(&skipped_releases, &CompatibilityToolFlavor::Boxtron).is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum SnapshotChange<K, V> {
+    Added { key: K, value: V },
+    Removed { key: K, value: V },
+    Changed { key: K, old: V, new: V },
+}
+
+/// Compares two snapshots of keyed state, returning the removed and changed entries followed by
+/// the added ones, each in key order.
+pub fn diff_snapshots<K: Ord + Clone, V: PartialEq + Clone>(
+    old: &BTreeMap<K, V>,
+    new: &BTreeMap<K, V>,
+) -> Vec<SnapshotChange<K, V>> {
+    let mut changes = Vec::new();
+    for (key, old_value) in old {
+        match new.get(key) {
+            Some(new_value) if new_value != old_value => changes.push(SnapshotChange::Changed {
+                key: key.clone(),
+                old: old_value.clone(),
+                new: new_value.clone(),
+            }),
+            Some(_) => {}
+            None => changes.push(SnapshotChange::Removed {
+                key: key.clone(),
+                value: old_value.clone(),
+            }),
+        }
+    }
+    for (key, new_value) in new {
+        if !old.contains_key(key) {
+            changes.push(SnapshotChange::Added {
+                key: key.clone(),
+                value: new_value.clone(),
+            });
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(entries: &[(u64, &str)]) -> BTreeMap<u64, String> {
+        entries
+            .iter()
+            .map(|(key, value)| (*key, value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_snapshots() {
+        let old = snapshot(&[
+            (730, "GE-Proton9-20"),
+            (1245620, "GE-Proton9-20"),
+            (1145360, "proton_experimental"),
+        ]);
+        let new = snapshot(&[
+            (730, "GE-Proton9-20"),
+            (1245620, "GE-Proton9-21"),
+            (3228583970, "luxtorpeda"),
+        ]);
+
+        assert_eq!(
+            diff_snapshots(&old, &new),
+            vec![
+                SnapshotChange::Removed {
+                    key: 1145360,
+                    value: "proton_experimental".to_string()
+                },
+                SnapshotChange::Changed {
+                    key: 1245620,
+                    old: "GE-Proton9-20".to_string(),
+                    new: "GE-Proton9-21".to_string()
+                },
+                SnapshotChange::Added {
+                    key: 3228583970,
+                    value: "luxtorpeda".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_identical_snapshots() {
+        let old = snapshot(&[(730, "GE-Proton9-20")]);
+        assert!(diff_snapshots(&old, &old.clone()).is_empty());
+        assert!(diff_snapshots(&BTreeMap::<u64, String>::new(), &BTreeMap::new()).is_empty());
+    }
+}
@@ -0,0 +1,474 @@
+use crate::steam_util::SteamUtil;
+use crate::wine_cask::activity::{ActivityLog, ActivitySource};
+use crate::wine_cask::app::{
+    broadcast_to_peers, AppState, Request, RequestType, UpdaterState, WineCask,
+};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::app_names::AppNameResolver;
+use crate::wine_cask::clock::RefreshSchedule;
+use crate::wine_cask::compat_data::CompatDataListings;
+use crate::wine_cask::environment::{Environment, SystemProbe};
+use crate::wine_cask::error_aggregation::ErrorAggregator;
+use crate::wine_cask::file_watcher::OwnWrites;
+use crate::wine_cask::history::{all_records, open_history};
+use crate::wine_cask::install_manifest::InstallManifest;
+use crate::wine_cask::local_changes::LocalChangesCache;
+use crate::wine_cask::mutation_guard::reconcile;
+use crate::wine_cask::network_usage::NetworkUsage;
+use crate::wine_cask::outbox::{send_to_peer, BroadcastCounters};
+use crate::wine_cask::plans::PlanStore;
+use crate::wine_cask::prefix_backup::RestoreDecisions;
+use crate::wine_cask::provenance::current_timestamp;
+use crate::wine_cask::proxy::set_proxy_url;
+use crate::wine_cask::refresh::RefreshTracker;
+use crate::wine_cask::rollback::purge_expired_rollbacks;
+use crate::wine_cask::settings::Settings;
+use crate::wine_cask::state_delta::StateBroadcasts;
+use crate::wine_cask::steam_overrides::SteamOverrides;
+use crate::wine_cask::tool_inspection::{apply_inspections, ToolInspector};
+use crate::wine_cask::undo::UndoStack;
+use crate::written_by::open_mutation_log;
+use crate::{PeerAddr, PeerMap};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Mutex;
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum StartupStage {
+    DiscoverSteam,
+    LoadSettings,
+    LoadCaches,
+    /// Finish or roll back file operations interrupted by a crash or cancellation.
+    ReconcileJournal,
+    ScanTools,
+    ScanApps,
+}
+
+/// Stages in the order they run.
+pub const STARTUP_STAGES: [StartupStage; 6] = [
+    StartupStage::DiscoverSteam,
+    StartupStage::LoadSettings,
+    StartupStage::LoadCaches,
+    StartupStage::ReconcileJournal,
+    StartupStage::ScanTools,
+    StartupStage::ScanApps,
+];
+
+impl Display for StartupStage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            StartupStage::DiscoverSteam => write!(f, "discover_steam"),
+            StartupStage::LoadSettings => write!(f, "load_settings"),
+            StartupStage::LoadCaches => write!(f, "load_caches"),
+            StartupStage::ReconcileJournal => write!(f, "reconcile_journal"),
+            StartupStage::ScanTools => write!(f, "scan_tools"),
+            StartupStage::ScanApps => write!(f, "scan_apps"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct CompletedStartupStage {
+    pub stage: StartupStage,
+    pub duration_ms: u64,
+    /// Time since the backend started when the stage completed.
+    pub elapsed_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct StartupProgress {
+    pub completed: Vec<CompletedStartupStage>,
+    /// `None` once every stage completed.
+    pub pending: Option<StartupStage>,
+}
+
+/// Tracks initialization so requests can be answered while the backend is still starting.
+pub struct Startup {
+    started: Instant,
+    progress: Mutex<StartupProgress>,
+    wine_cask: Mutex<Option<Arc<WineCask>>>,
+}
+
+impl Default for Startup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Startup {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            progress: Mutex::new(StartupProgress {
+                completed: Vec::new(),
+                pending: Some(STARTUP_STAGES[0]),
+            }),
+            wine_cask: Mutex::new(None),
+        }
+    }
+
+    pub async fn progress(&self) -> StartupProgress {
+        self.progress.lock().await.clone()
+    }
+
+    /// Returns the `WineCask` once every stage completed.
+    pub async fn wine_cask(&self) -> Option<Arc<WineCask>> {
+        self.wine_cask.lock().await.clone()
+    }
+
+    async fn run_stage<F: Future>(
+        &self,
+        peer_map: &PeerMap,
+        stage: StartupStage,
+        future: F,
+    ) -> F::Output {
+        let stage_started = Instant::now();
+        let output = future.await;
+
+        let mut progress = self.progress.lock().await;
+        let completed = CompletedStartupStage {
+            stage,
+            duration_ms: stage_started.elapsed().as_millis() as u64,
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+        };
+        info!(
+            "Startup stage {} took {} ms, {} ms since start",
+            stage, completed.duration_ms, completed.elapsed_ms
+        );
+        progress.completed.push(completed);
+        progress.pending = STARTUP_STAGES
+            .iter()
+            .position(|pending| *pending == stage)
+            .and_then(|index| STARTUP_STAGES.get(index + 1))
+            .copied();
+        let response = Request {
+            startup_progress: Some(progress.clone()),
+            ..Request::new(RequestType::StartupProgress)
+        };
+        drop(progress);
+        broadcast_to_peers(peer_map, &response).await;
+        output
+    }
+
+    /// Answers a request that arrived before startup finished with a `NotReady` error, only the
+    /// peer that sent it is answered.
+    pub async fn reject_not_ready(&self, peer_map: &PeerMap, addr: PeerAddr) {
+        let progress = self.progress().await;
+        let app_error = AppError::new(
+            AppErrorCode::NotReady,
+            match progress.pending {
+                Some(stage) => format!("Waiting for {}", stage),
+                None => "Finishing startup".to_string(),
+            },
+        );
+        warn!("{}: {}", addr, app_error);
+        send_to_peer(
+            peer_map,
+            addr,
+            &Request {
+                app_error: Some(app_error),
+                ..Request::new(RequestType::Error)
+            },
+        )
+        .await;
+        send_to_peer(
+            peer_map,
+            addr,
+            &Request {
+                startup_progress: Some(progress),
+                ..Request::new(RequestType::StartupProgress)
+            },
+        )
+        .await;
+    }
+
+    /// Runs every startup stage, broadcasting a `StartupProgress` event after each of them.
+    pub async fn initialize(
+        &self,
+        peer_map: &PeerMap,
+        find_steam_directories: impl FnOnce() -> Vec<PathBuf>,
+        runtime_directory: &Path,
+    ) -> Arc<WineCask> {
+        let steam_directories = self
+            .run_stage(peer_map, StartupStage::DiscoverSteam, async {
+                find_steam_directories()
+            })
+            .await;
+        // Todo: Handle if no steam folder is found, although this should never happen
+        let steam_util = SteamUtil::new(
+            steam_directories
+                .first()
+                .cloned()
+                .expect("Failed to find a Steam directory"),
+        );
+        let (settings, network_usage, environment) = self
+            .run_stage(peer_map, StartupStage::LoadSettings, async {
+                let mut environment =
+                    Environment::new(Box::new(SystemProbe::new(PathBuf::from("/"))));
+                environment.sample(Instant::now());
+                (
+                    Settings::load(),
+                    NetworkUsage::load(&chrono::Local::now()),
+                    environment,
+                )
+            })
+            .await;
+        // Before anything goes on the network
+        set_proxy_url(settings.proxy_url.clone());
+        info!(
+            "Network usage for {}: {} asset bytes, {} metadata bytes",
+            network_usage.month, network_usage.asset_bytes, network_usage.metadata_bytes
+        );
+        let (activity_log, undo_stack, app_name_resolver, tool_inspector, install_manifest) = self
+            .run_stage(peer_map, StartupStage::LoadCaches, async {
+                open_mutation_log(runtime_directory.join("mutation_log.jsonl"));
+                open_history(runtime_directory.join("history.jsonl"));
+                (
+                    ActivityLog::load(runtime_directory.join("activity.json")),
+                    UndoStack::load(runtime_directory.join("undo_stack.json")),
+                    AppNameResolver::with_steam_store(),
+                    ToolInspector::load(runtime_directory.join("tool_inspection.json")),
+                    InstallManifest::load(runtime_directory.join("install_manifest.json")),
+                )
+            })
+            .await;
+        self.run_stage(peer_map, StartupStage::ReconcileJournal, async {
+            reconcile(&runtime_directory.join("journal"));
+            purge_expired_rollbacks(&all_records(), current_timestamp());
+        })
+        .await;
+
+        let wine_cask = Arc::new(WineCask {
+            steam_util,
+            app_state: Arc::new(Mutex::new(AppState {
+                available_flavors: Vec::new(),
+                installed_compatibility_tools: Vec::new(),
+                installed_runners: Vec::new(),
+                installed_components: Vec::new(),
+                compatibility_tool_mappings: Vec::new(),
+                in_progress: Vec::new(),
+                task_queue: VecDeque::new(),
+                updater_state: UpdaterState::Idle,
+                updater_last_check: None,
+                github_rate_limit_reset: None,
+                settings,
+                network_usage,
+                environment: environment.snapshot().clone(),
+                stranded_compatibility_tools: Vec::new(),
+                startup_stages: Vec::new(),
+                available_compat_tools: None,
+                flavors: Vec::new(),
+                storage_breakdown: None,
+                reported_pickup_problems: HashSet::new(),
+                steam_overrides: SteamOverrides::default(),
+                restart_required: false,
+                filesystem_profiles: Vec::new(),
+                broadcast_counters: BroadcastCounters::default(),
+                steam_installations: steam_directories
+                    .iter()
+                    .map(|steam_directory| steam_directory.to_string_lossy().to_string())
+                    .collect(),
+                inspection_progress: None,
+                available_updates: Vec::new(),
+                prefix_restores: Vec::new(),
+                clock_checked: None,
+                release_refresh: RefreshSchedule::default(),
+                error_aggregator: ErrorAggregator::default(),
+                refreshes: RefreshTracker::default(),
+                prefix_scan: None,
+                local_changes: LocalChangesCache::default(),
+                install_manifest,
+                pending_mappings: Vec::new(),
+                plans: PlanStore::default(),
+                compat_data_listings: CompatDataListings::default(),
+                tool_inspector,
+                restore_decisions: RestoreDecisions::default(),
+            })),
+            app_name_resolver: Arc::new(std::sync::Mutex::new(app_name_resolver)),
+            activity_log: Arc::new(Mutex::new(activity_log)),
+            undo_stack: Arc::new(Mutex::new(undo_stack)),
+            environment: Arc::new(std::sync::Mutex::new(environment)),
+            state_broadcasts: Arc::new(Mutex::new(StateBroadcasts::default())),
+            own_writes: OwnWrites::default(),
+        });
+
+        // Only the quick listing, deep inspection runs in the background once startup finished
+        self.run_stage(peer_map, StartupStage::ScanTools, async {
+            wine_cask.sweep_install_temp_directories();
+            wine_cask.regenerate_virtual_tool_shims();
+            let mut installed_compatibility_tools = wine_cask.list_compatibility_tools().unwrap();
+            let mut app_state = wine_cask.app_state.lock().await;
+            apply_inspections(
+                &mut installed_compatibility_tools,
+                &app_state.tool_inspector,
+            );
+            app_state.installed_compatibility_tools = installed_compatibility_tools;
+            app_state.installed_runners = wine_cask.list_runners();
+            app_state.installed_components = wine_cask.list_components();
+            drop(app_state);
+            wine_cask.reconcile_install_manifest().await;
+            wine_cask.update_stranded_compatibility_tools().await;
+            wine_cask.update_filesystem_profiles().await;
+        })
+        .await;
+        self.run_stage(peer_map, StartupStage::ScanApps, async {
+            wine_cask
+                .update_compatibility_tool_mappings(ActivitySource::External)
+                .await;
+        })
+        .await;
+
+        wine_cask.app_state.lock().await.startup_stages = self.progress().await.completed;
+        *self.wine_cask.lock().await = Some(wine_cask.clone());
+        info!(
+            "Startup finished in {} ms",
+            self.started.elapsed().as_millis()
+        );
+        wine_cask
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wine_cask::generate_compatibility_tool_vdf;
+    use crate::wine_cask::history::TEST_HISTORY;
+    use crate::wine_cask::outbox::Outbox;
+    use crate::wine_cask::permissions::PermissionSet;
+    use crate::wine_cask::subscriptions::SubscriptionSet;
+    use crate::{Peer, PeerAddr};
+    use futures_util::FutureExt;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    const TOOLS: usize = 40;
+    const APPS: usize = 500;
+
+    fn create_large_library(root: &Path) {
+        let steamapps = root.join("steamapps");
+        fs::create_dir_all(root.join("config")).unwrap();
+        fs::create_dir_all(&steamapps).unwrap();
+        for index in 0..TOOLS {
+            let tool = root.join(format!("compatibilitytools.d/GE-Proton9-{}", index));
+            fs::create_dir_all(&tool).unwrap();
+            let name = format!("GE-Proton9-{}", index);
+            generate_compatibility_tool_vdf(tool.join("compatibilitytool.vdf"), &name, &name);
+        }
+
+        let mut mappings = String::new();
+        for app_id in 1..=APPS {
+            fs::write(
+                steamapps.join(format!("appmanifest_{}.acf", app_id)),
+                format!(
+                    "\"AppState\"\n{{\n\"appid\" \"{}\"\n\"name\" \"Game {}\"\n}}\n",
+                    app_id, app_id
+                ),
+            )
+            .unwrap();
+            mappings.push_str(&format!(
+                "\"{}\"\n{{\n\"name\" \"GE-Proton9-{}\"\n\"config\" \"\"\n\"priority\" \"250\"\n}}\n",
+                app_id,
+                app_id % TOOLS
+            ));
+        }
+        fs::write(
+            root.join("config/config.vdf"),
+            format!(
+                "\"InstallConfigStore\"\n{{\n\"Software\"\n{{\n\"Valve\"\n{{\n\"Steam\"\n{{\n\"CompatToolMapping\"\n{{\n{}}}\n}}\n}}\n}}\n}}\n",
+                mappings
+            ),
+        )
+        .unwrap();
+        fs::write(
+            steamapps.join("libraryfolders.vdf"),
+            format!(
+                "\"libraryfolders\"\n{{\n\"0\"\n{{\n\"path\" \"{}\"\n}}\n}}\n",
+                root.display()
+            ),
+        )
+        .unwrap();
+    }
+
+    fn received(outbox: &Outbox) -> Vec<Request> {
+        let mut requests = Vec::new();
+        while let Some(Some(message)) = outbox.next().now_or_never() {
+            requests.push(serde_json::from_str(message.to_text().unwrap()).unwrap());
+        }
+        requests
+    }
+
+    #[tokio::test]
+    async fn test_startup_reports_progress_and_rejects_early_requests() {
+        let _history = TEST_HISTORY.lock().await;
+        let steam_root = tempdir().unwrap();
+        let runtime_directory = tempdir().unwrap();
+        create_large_library(steam_root.path());
+
+        let peer_map: PeerMap = Arc::new(Mutex::new(HashMap::new()));
+        let outbox = Arc::new(Outbox::default());
+        let addr = PeerAddr::Tcp("127.0.0.1:8887".parse().unwrap());
+        peer_map.lock().await.insert(
+            addr,
+            Peer {
+                outbox: outbox.clone(),
+                permissions: PermissionSet::all(),
+                subscriptions: SubscriptionSet::default(),
+            },
+        );
+        let startup = Startup::new();
+
+        // Early requests are answered right away instead of waiting for startup
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            startup.reject_not_ready(&peer_map, addr),
+        )
+        .await
+        .unwrap();
+        let early = received(&outbox);
+        assert_eq!(
+            early[0].app_error,
+            Some(AppError::new(
+                AppErrorCode::NotReady,
+                "Waiting for discover_steam"
+            ))
+        );
+        assert_eq!(early[1].r#type, RequestType::StartupProgress);
+        assert!(startup.wine_cask().await.is_none());
+
+        let steam_directory = steam_root.path().to_path_buf();
+        let wine_cask = startup
+            .initialize(
+                &peer_map,
+                || vec![steam_directory],
+                runtime_directory.path(),
+            )
+            .await;
+
+        let events: Vec<StartupProgress> = received(&outbox)
+            .into_iter()
+            .filter_map(|request| request.startup_progress)
+            .collect();
+        assert_eq!(events.len(), STARTUP_STAGES.len());
+        for (index, event) in events.iter().enumerate() {
+            assert_eq!(event.completed.len(), index + 1);
+            assert_eq!(event.completed[index].stage, STARTUP_STAGES[index]);
+            assert_eq!(event.pending, STARTUP_STAGES.get(index + 1).copied());
+        }
+
+        assert!(startup.wine_cask().await.is_some());
+        let app_state = wine_cask.app_state.lock().await;
+        assert_eq!(app_state.installed_compatibility_tools.len(), TOOLS);
+        assert_eq!(app_state.compatibility_tool_mappings.len(), APPS);
+        assert!(!app_state.compatibility_tool_mappings[0].unresolved);
+        assert_eq!(app_state.startup_stages, events.last().unwrap().completed);
+    }
+}
@@ -0,0 +1,325 @@
+use crate::wine_cask::app::AppState;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Sections listing installed tools, sent as the tools that changed rather than as a whole.
+const TOOL_SECTIONS: [&str; 3] = [
+    "installed_compatibility_tools",
+    "installed_runners",
+    "installed_components",
+];
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct AddedTool {
+    /// Position of the tool in the new list.
+    pub index: usize,
+    pub tool: Value,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(tag = "kind")]
+pub enum StateDelta {
+    /// A field of the state replaced as a whole, e.g. `in_progress` as installs make progress.
+    SectionChanged {
+        section: String,
+        value: Value,
+    },
+    /// Tools that showed up, in the order of their index.
+    ToolsAdded {
+        section: String,
+        tools: Vec<AddedTool>,
+    },
+    /// Tools whose entry changed, matched by path.
+    ToolsChanged {
+        section: String,
+        tools: Vec<Value>,
+    },
+    ToolsRemoved {
+        section: String,
+        paths: Vec<String>,
+    },
+}
+
+/// What changed between two revisions of the state, peers that don't have `base_revision` need
+/// a snapshot instead.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct StateDeltas {
+    pub base_revision: u64,
+    pub revision: u64,
+    pub deltas: Vec<StateDelta>,
+}
+
+/// The state as last sent to the peers, later broadcasts only carry what changed since.
+#[derive(Default)]
+pub struct StateBroadcasts {
+    pub revision: u64,
+    pub last: Option<AppState>,
+}
+
+fn tool_path(tool: &Value) -> Option<&str> {
+    tool.get("path")?.as_str()
+}
+
+fn has_duplicates(paths: &[&str]) -> bool {
+    paths
+        .iter()
+        .enumerate()
+        .any(|(index, path)| paths[..index].contains(path))
+}
+
+/// Changes of one tool list, `None` if the list has to be sent as a whole because tools were
+/// reordered or can't be told apart by their path.
+fn diff_tools(section: &str, old: &[Value], new: &[Value]) -> Option<Vec<StateDelta>> {
+    let old_paths: Vec<&str> = old.iter().map(tool_path).collect::<Option<_>>()?;
+    let new_paths: Vec<&str> = new.iter().map(tool_path).collect::<Option<_>>()?;
+    if has_duplicates(&old_paths) || has_duplicates(&new_paths) {
+        return None;
+    }
+    let kept_in_old_order: Vec<&str> = old_paths
+        .iter()
+        .filter(|path| new_paths.contains(path))
+        .copied()
+        .collect();
+    let kept_in_new_order: Vec<&str> = new_paths
+        .iter()
+        .filter(|path| old_paths.contains(path))
+        .copied()
+        .collect();
+    if kept_in_old_order != kept_in_new_order {
+        return None;
+    }
+
+    let removed: Vec<String> = old_paths
+        .iter()
+        .filter(|path| !new_paths.contains(path))
+        .map(|path| path.to_string())
+        .collect();
+    let changed: Vec<Value> = new
+        .iter()
+        .filter(|tool| {
+            old.iter()
+                .any(|old_tool| tool_path(old_tool) == tool_path(tool) && old_tool != *tool)
+        })
+        .cloned()
+        .collect();
+    let added: Vec<AddedTool> = new
+        .iter()
+        .zip(&new_paths)
+        .enumerate()
+        .filter(|(_, (_, path))| !old_paths.contains(path))
+        .map(|(index, (tool, _))| AddedTool {
+            index,
+            tool: tool.clone(),
+        })
+        .collect();
+
+    let mut deltas = Vec::new();
+    if !removed.is_empty() {
+        deltas.push(StateDelta::ToolsRemoved {
+            section: section.to_string(),
+            paths: removed,
+        });
+    }
+    if !changed.is_empty() {
+        deltas.push(StateDelta::ToolsChanged {
+            section: section.to_string(),
+            tools: changed,
+        });
+    }
+    if !added.is_empty() {
+        deltas.push(StateDelta::ToolsAdded {
+            section: section.to_string(),
+            tools: added,
+        });
+    }
+    Some(deltas)
+}
+
+/// Changes turning the serialized state `old` into `new`, applied in order.
+pub fn diff_states(old: &Value, new: &Value) -> Vec<StateDelta> {
+    let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+        return Vec::new();
+    };
+    let mut deltas = Vec::new();
+    for (section, value) in new {
+        let old_value = old.get(section);
+        if old_value == Some(value) {
+            continue;
+        }
+        let tool_deltas = match (old_value.and_then(Value::as_array), value.as_array()) {
+            (Some(old_tools), Some(new_tools)) if TOOL_SECTIONS.contains(&section.as_str()) => {
+                diff_tools(section, old_tools, new_tools)
+            }
+            _ => None,
+        };
+        match tool_deltas {
+            Some(tool_deltas) => deltas.extend(tool_deltas),
+            None => deltas.push(StateDelta::SectionChanged {
+                section: section.clone(),
+                value: value.clone(),
+            }),
+        }
+    }
+    deltas
+}
+
+fn tool_section<'a>(state: &'a mut Value, section: &str) -> Result<&'a mut Vec<Value>, String> {
+    state
+        .get_mut(section)
+        .and_then(Value::as_array_mut)
+        .ok_or_else(|| format!("{} isn't a list of tools", section))
+}
+
+/// Applies deltas to a serialized state the way peers do.
+pub fn apply_deltas(state: &mut Value, deltas: &[StateDelta]) -> Result<(), String> {
+    for delta in deltas {
+        match delta {
+            StateDelta::SectionChanged { section, value } => {
+                let state = state
+                    .as_object_mut()
+                    .ok_or_else(|| "The state isn't an object".to_string())?;
+                state.insert(section.clone(), value.clone());
+            }
+            StateDelta::ToolsAdded { section, tools } => {
+                let list = tool_section(state, section)?;
+                for added in tools {
+                    if added.index > list.len() {
+                        return Err(format!("{} has no index {}", section, added.index));
+                    }
+                    list.insert(added.index, added.tool.clone());
+                }
+            }
+            StateDelta::ToolsChanged { section, tools } => {
+                let list = tool_section(state, section)?;
+                for tool in tools {
+                    let entry = list
+                        .iter_mut()
+                        .find(|entry| tool_path(entry) == tool_path(tool))
+                        .ok_or_else(|| format!("{} has no tool {:?}", section, tool_path(tool)))?;
+                    *entry = tool.clone();
+                }
+            }
+            StateDelta::ToolsRemoved { section, paths } => {
+                tool_section(state, section)?.retain(|tool| {
+                    !tool_path(tool).is_some_and(|path| paths.iter().any(|removed| removed == path))
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn tool(name: &str, used_by_games: &[&str]) -> Value {
+        json!({
+            "path": format!("/compatibilitytools.d/{}", name),
+            "internal_name": name,
+            "used_by_games": used_by_games,
+        })
+    }
+
+    fn state(tools: Vec<Value>, in_progress: Value) -> Value {
+        json!({
+            "installed_compatibility_tools": tools,
+            "installed_runners": [],
+            "installed_components": [],
+            "in_progress": in_progress,
+            "updater_state": "Idle",
+        })
+    }
+
+    /// Replays the deltas between each pair of revisions, starting from the first.
+    fn replay(revisions: &[Value]) -> Value {
+        let mut reconstructed = revisions[0].clone();
+        for pair in revisions.windows(2) {
+            let deltas = diff_states(&pair[0], &pair[1]);
+            // Deltas go over the wire
+            let deltas: Vec<StateDelta> =
+                serde_json::from_str(&serde_json::to_string(&deltas).unwrap()).unwrap();
+            apply_deltas(&mut reconstructed, &deltas).unwrap();
+            assert_eq!(reconstructed, pair[1]);
+        }
+        reconstructed
+    }
+
+    #[test]
+    fn test_only_changed_sections_are_sent() {
+        let old = state(vec![tool("GE-Proton9-20", &[])], json!([]));
+        let new = state(
+            vec![tool("GE-Proton9-20", &[])],
+            json!([{"name": "GE-Proton9-21", "progress": 40}]),
+        );
+        assert_eq!(
+            diff_states(&old, &new),
+            [StateDelta::SectionChanged {
+                section: "in_progress".to_string(),
+                value: json!([{"name": "GE-Proton9-21", "progress": 40}]),
+            }]
+        );
+        assert!(diff_states(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_tools_are_sent_as_additions_and_removals() {
+        let old = state(
+            vec![tool("GE-Proton9-20", &[]), tool("GE-Proton9-21", &[])],
+            json!([]),
+        );
+        let new = state(
+            vec![
+                tool("GE-Proton9-21", &["ELDEN RING"]),
+                tool("GE-Proton9-22", &[]),
+            ],
+            json!([]),
+        );
+        let deltas = diff_states(&old, &new);
+        assert_eq!(
+            deltas,
+            [
+                StateDelta::ToolsRemoved {
+                    section: "installed_compatibility_tools".to_string(),
+                    paths: vec!["/compatibilitytools.d/GE-Proton9-20".to_string()],
+                },
+                StateDelta::ToolsChanged {
+                    section: "installed_compatibility_tools".to_string(),
+                    tools: vec![tool("GE-Proton9-21", &["ELDEN RING"])],
+                },
+                StateDelta::ToolsAdded {
+                    section: "installed_compatibility_tools".to_string(),
+                    tools: vec![AddedTool {
+                        index: 1,
+                        tool: tool("GE-Proton9-22", &[]),
+                    }],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replayed_deltas_reconstruct_the_state() {
+        let revisions = [
+            state(vec![], json!([])),
+            state(vec![], json!([{"name": "GE-Proton9-22", "progress": 10}])),
+            state(
+                vec![tool("GE-Proton9-22", &[])],
+                json!([{"name": "GE-Proton9-22", "progress": 100}]),
+            ),
+            state(
+                vec![tool("GE-Proton9-20", &[]), tool("GE-Proton9-22", &[])],
+                json!([]),
+            ),
+            // Reordered, sent as the whole list
+            state(
+                vec![tool("GE-Proton9-22", &[]), tool("GE-Proton9-20", &[])],
+                json!([]),
+            ),
+            state(vec![tool("GE-Proton9-22", &["ELDEN RING"])], json!([])),
+            state(vec![], json!([])),
+        ];
+        assert_eq!(replay(&revisions), revisions[revisions.len() - 1]);
+    }
+}
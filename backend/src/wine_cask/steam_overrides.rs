@@ -0,0 +1,151 @@
+use crate::app_id::AppId;
+use crate::appinfo::{find_app, AppInfoError, KeyValue};
+use log::warn;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// "Steam Play 2.0 Manifests", the app whose appinfo lists the tools Valve assigns to games.
+const STEAM_PLAY_MANIFESTS: u32 = 891390;
+/// Tools Valve force-assigns to individual games, ignoring the mapping the user chose.
+const FORCED_TOOLS: [&str; 1] = ["proton_hotfix"];
+
+/// Reads the games Valve forces a hotfix tool on from the contents of appinfo.vdf.
+pub fn read_steam_overrides(appinfo: &[u8]) -> Result<HashMap<AppId, String>, AppInfoError> {
+    let Some(manifests) = find_app(appinfo, STEAM_PLAY_MANIFESTS)? else {
+        return Ok(HashMap::new());
+    };
+    let Some(app_mappings) = manifests.find("app_mappings") else {
+        return Ok(HashMap::new());
+    };
+    Ok(app_mappings
+        .entries()
+        .iter()
+        .filter_map(|(_, mapping)| {
+            let tool = mapping.get("tool").and_then(KeyValue::as_str)?;
+            let app_id = mapping.get("appid").and_then(KeyValue::as_u64)?;
+            let app_id = AppId::new(u32::try_from(app_id).ok()?).ok()?;
+            FORCED_TOOLS
+                .contains(&tool)
+                .then(|| (app_id, tool.to_string()))
+        })
+        .collect())
+}
+
+/// Forced tools along with the appinfo.vdf they were read from, so they are only re-read after
+/// Steam updated it.
+#[derive(Default, Clone)]
+pub struct SteamOverrides {
+    fingerprint: Option<(SystemTime, u64)>,
+    overrides: HashMap<AppId, String>,
+}
+
+impl SteamOverrides {
+    /// Re-reads `appinfo` if it changed since the last call, returns whether it did.
+    pub fn refresh(&mut self, appinfo: &Path) -> bool {
+        let fingerprint = fs::metadata(appinfo)
+            .ok()
+            .and_then(|metadata| Some((metadata.modified().ok()?, metadata.len())));
+        if fingerprint == self.fingerprint {
+            return false;
+        }
+        self.fingerprint = fingerprint;
+        self.overrides = match fs::read(appinfo) {
+            Ok(bytes) => read_steam_overrides(&bytes).unwrap_or_else(|err| {
+                warn!("Failed to read Steam's tool overrides: {}", err);
+                HashMap::new()
+            }),
+            // Missing until Steam downloaded app info for the first time
+            Err(_) => HashMap::new(),
+        };
+        true
+    }
+
+    pub fn get(&self, app_id: AppId) -> Option<&String> {
+        self.overrides.get(&app_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::appinfo::fixtures::{build_appinfo, object, string};
+    use tempfile::tempdir;
+
+    fn manifests(app_mappings: &[(u32, &str)]) -> (u32, KeyValue) {
+        let app_mappings: Vec<(String, KeyValue)> = app_mappings
+            .iter()
+            .enumerate()
+            .map(|(index, (app_id, tool))| {
+                (
+                    index.to_string(),
+                    object(&[
+                        ("appid", string(&app_id.to_string())),
+                        ("tool", string(tool)),
+                        ("comment", string("")),
+                    ]),
+                )
+            })
+            .collect();
+        (
+            STEAM_PLAY_MANIFESTS,
+            object(&[(
+                "appinfo",
+                object(&[(
+                    "extended",
+                    object(&[("app_mappings", KeyValue::Object(app_mappings))]),
+                )]),
+            )]),
+        )
+    }
+
+    #[test]
+    fn test_hotfix_titles_are_detected() {
+        let appinfo = build_appinfo(
+            0x0756_4429,
+            &[
+                (730, object(&[("appinfo", object(&[]))])),
+                manifests(&[(1245620, "proton_hotfix"), (1091500, "proton_8")]),
+            ],
+        );
+        let overrides = read_steam_overrides(&appinfo).unwrap();
+        let overridden = AppId::new(1245620).unwrap();
+        let normal = AppId::new(1091500).unwrap();
+        assert_eq!(overrides.get(&overridden).unwrap(), "proton_hotfix");
+        assert_eq!(overrides.get(&normal), None);
+    }
+
+    #[test]
+    fn test_overrides_refresh_when_appinfo_changes() {
+        let temp_dir = tempdir().unwrap();
+        let appinfo = temp_dir.path().join("appinfo.vdf");
+        let app_id = AppId::new(1245620).unwrap();
+
+        // Nothing to read before Steam downloaded app info
+        let mut steam_overrides = SteamOverrides::default();
+        assert!(!steam_overrides.refresh(&appinfo));
+        assert_eq!(steam_overrides.get(app_id), None);
+
+        fs::write(
+            &appinfo,
+            build_appinfo(0x0756_4428, &[manifests(&[(1245620, "proton_hotfix")])]),
+        )
+        .unwrap();
+        assert!(steam_overrides.refresh(&appinfo));
+        assert_eq!(steam_overrides.get(app_id).unwrap(), "proton_hotfix");
+        assert!(!steam_overrides.refresh(&appinfo));
+
+        // Valve lifted the override
+        fs::write(
+            &appinfo,
+            build_appinfo(
+                0x0756_4428,
+                &[manifests(&[(1245620, "proton_9"), (730, "proton_9")])],
+            ),
+        )
+        .unwrap();
+        assert!(steam_overrides.refresh(&appinfo));
+        assert_eq!(steam_overrides.get(app_id), None);
+    }
+}
@@ -0,0 +1,353 @@
+use crate::steam_util::SteamUtil;
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::filesystems::{detect_filesystem, FilesystemProfile};
+use crate::wine_cask::flavors::SteamClientCompatToolInfo;
+use crate::wine_cask::migration::read_install_path;
+use crate::wine_cask::provenance::Provenance;
+use crate::PeerMap;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use std::{fmt, fs};
+
+/// Reason Steam won't offer a tool in the compatibility tool picker.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum PickerRuleFailure {
+    UnreadableVdf(String),
+    /// `to_oslist` doesn't include linux.
+    WrongTargetOs(String),
+    /// The install path from compatibilitytool.vdf doesn't exist.
+    MissingInstallPath(String),
+    MissingToolManifest,
+    /// Another tool uses the same internal name and only one of them is listed.
+    DuplicateInternalName(String),
+}
+
+impl Display for PickerRuleFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PickerRuleFailure::UnreadableVdf(err) => {
+                write!(f, "compatibilitytool.vdf can't be read: {}", err)
+            }
+            PickerRuleFailure::WrongTargetOs(to_os_list) => {
+                write!(f, "to_oslist is \"{}\" instead of linux", to_os_list)
+            }
+            PickerRuleFailure::MissingInstallPath(install_path) => {
+                write!(f, "install_path {} doesn't exist", install_path)
+            }
+            PickerRuleFailure::MissingToolManifest => write!(f, "toolmanifest.vdf is missing"),
+            PickerRuleFailure::DuplicateInternalName(path) => {
+                write!(f, "{} uses the same internal name", path)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum PickupStatus {
+    /// Steam lists the tool as an available compatibility tool.
+    Detected,
+    /// Steam hasn't restarted since the tool was installed.
+    AwaitingRestart,
+    /// The picker rules already explain why Steam can't list the tool.
+    Invisible(Vec<PickerRuleFailure>),
+    /// Steam restarted after the install and still doesn't list the tool.
+    NotDetected,
+}
+
+/// Checks the requirements Steam has before offering a tool in the picker, `profile` describes the
+/// filesystem of the tools directory.
+pub fn check_picker_rules(
+    tool_directory: &Path,
+    other_tools: &[&Path],
+    profile: &FilesystemProfile,
+) -> Vec<PickerRuleFailure> {
+    let vdf_path = tool_directory.join("compatibilitytool.vdf");
+    let tools = match SteamUtil::new(tool_directory.to_path_buf())
+        .read_compatibility_tools_from_vdf_path(&vdf_path)
+    {
+        Ok(tools) => tools,
+        Err(err) => return vec![PickerRuleFailure::UnreadableVdf(err.to_string())],
+    };
+
+    let mut failures = Vec::new();
+    for tool in &tools {
+        if !tool.to_os_list.split(',').any(|os| os.trim() == "linux") {
+            failures.push(PickerRuleFailure::WrongTargetOs(tool.to_os_list.clone()));
+        }
+    }
+    let install_path = read_install_path(&vdf_path).unwrap_or_else(|| ".".to_string());
+    let install_directory = tool_directory.join(&install_path);
+    if !install_directory.is_dir() {
+        failures.push(PickerRuleFailure::MissingInstallPath(install_path));
+    } else if !install_directory.join("toolmanifest.vdf").exists() {
+        failures.push(PickerRuleFailure::MissingToolManifest);
+    }
+    for other_tool in other_tools {
+        // Paths differing only in case are the tool itself on case-insensitive filesystems
+        if profile.names_collide(
+            &other_tool.to_string_lossy(),
+            &tool_directory.to_string_lossy(),
+        ) {
+            continue;
+        }
+        let duplicate = SteamUtil::new(other_tool.to_path_buf())
+            .read_compatibility_tools_from_vdf_path(&other_tool.join("compatibilitytool.vdf"))
+            .is_ok_and(|others| {
+                others.iter().any(|other| {
+                    tools
+                        .iter()
+                        .any(|tool| other.internal_name == tool.internal_name)
+                })
+            });
+        if duplicate {
+            failures.push(PickerRuleFailure::DuplicateInternalName(
+                other_tool.display().to_string(),
+            ));
+        }
+    }
+    failures
+}
+
+pub fn listed_by_steam(
+    internal_name: &str,
+    available_compat_tools: &[SteamClientCompatToolInfo],
+) -> bool {
+    available_compat_tools
+        .iter()
+        .any(|tool| tool.str_tool_name == internal_name)
+}
+
+/// Returns when the tool was installed, from its provenance or the age of its VDF.
+pub fn installed_at(tool_directory: &Path, provenance: Option<&Provenance>) -> Option<u64> {
+    provenance
+        .map(|provenance| provenance.installed_at)
+        .or_else(|| {
+            fs::metadata(tool_directory.join("compatibilitytool.vdf"))
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs())
+        })
+}
+
+pub fn pickup_status(
+    listed: bool,
+    rule_failures: Vec<PickerRuleFailure>,
+    steam_started_at: Option<u64>,
+    installed_at: Option<u64>,
+) -> PickupStatus {
+    if listed {
+        PickupStatus::Detected
+    } else if !rule_failures.is_empty() {
+        PickupStatus::Invisible(rule_failures)
+    } else {
+        match (steam_started_at, installed_at) {
+            (Some(steam_started_at), Some(installed_at)) if steam_started_at > installed_at => {
+                PickupStatus::NotDetected
+            }
+            // Without evidence of a restart the missing entry is expected
+            _ => PickupStatus::AwaitingRestart,
+        }
+    }
+}
+
+impl WineCask {
+    /// Compares the installed tools against the tools Steam offers and warns once per tool about
+    /// tools Steam should list but doesn't.
+    pub async fn check_steam_pickup(&self, peer_map: &PeerMap) {
+        let app_state = self.app_state.lock().await;
+        let Some(available_compat_tools) = app_state.available_compat_tools.clone() else {
+            return;
+        };
+        let tools: Vec<(String, String, String)> = app_state
+            .installed_compatibility_tools
+            .iter()
+            .map(|tool| {
+                (
+                    tool.internal_name.clone(),
+                    tool.display_name.clone(),
+                    tool.path.clone(),
+                )
+            })
+            .collect();
+        drop(app_state);
+
+        let (environment, _) = self.sample_environment().await;
+        let steam_started_at = environment.steam_started_at;
+        let profile = detect_filesystem(&self.steam_util.get_steam_compatibility_tools_directory());
+        let mut warnings = Vec::new();
+        for (internal_name, display_name, path) in &tools {
+            let tool_directory = Path::new(path);
+            let other_tools: Vec<&Path> = tools
+                .iter()
+                .map(|(_, _, other)| Path::new(other))
+                .filter(|other| *other != tool_directory)
+                .collect();
+            let listed = listed_by_steam(internal_name, &available_compat_tools);
+            let rule_failures = if listed {
+                Vec::new()
+            } else {
+                check_picker_rules(tool_directory, &other_tools, &profile)
+            };
+            let status = pickup_status(
+                listed,
+                rule_failures,
+                steam_started_at,
+                installed_at(tool_directory, Provenance::load(internal_name).as_ref()),
+            );
+
+            let mut app_state = self.app_state.lock().await;
+            let warning = match status {
+                PickupStatus::Detected | PickupStatus::AwaitingRestart => {
+                    app_state.reported_pickup_problems.remove(internal_name);
+                    None
+                }
+                PickupStatus::Invisible(failures) => Some(format!(
+                    "Warning: Steam can't list {}: {}",
+                    display_name,
+                    failures
+                        .iter()
+                        .map(|failure| failure.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )),
+                PickupStatus::NotDetected => Some(format!(
+                    "Warning: tool_not_detected_by_steam: {} isn't listed after Steam restarted, \
+                     although no picker rule failed, check its compatibilitytool.vdf",
+                    display_name
+                )),
+            };
+            if let Some(warning) = warning {
+                if app_state
+                    .reported_pickup_problems
+                    .insert(internal_name.clone())
+                {
+                    warnings.push(warning);
+                }
+            }
+        }
+
+        for warning in warnings {
+            warn!("{}", warning);
+            self.broadcast_notification(peer_map, &warning).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wine_cask::generate_compatibility_tool_vdf;
+    use tempfile::tempdir;
+
+    fn create_tool(directory: &Path, internal_name: &str) {
+        fs::create_dir_all(directory).unwrap();
+        generate_compatibility_tool_vdf(
+            directory.join("compatibilitytool.vdf"),
+            internal_name,
+            internal_name,
+        );
+        fs::write(directory.join("toolmanifest.vdf"), "\"manifest\" {}").unwrap();
+    }
+
+    #[test]
+    fn test_picker_rules() {
+        let compatibility_tools = tempdir().unwrap();
+        let valid = compatibility_tools.path().join("GE-Proton9-21");
+        let duplicate = compatibility_tools.path().join("GE-Proton9-21-copy");
+        let no_manifest = compatibility_tools.path().join("GE-Proton9-20");
+        create_tool(&valid, "GE-Proton9-21");
+        create_tool(&duplicate, "GE-Proton9-21");
+        create_tool(&no_manifest, "GE-Proton9-20");
+        fs::remove_file(no_manifest.join("toolmanifest.vdf")).unwrap();
+        let ext4 = FilesystemProfile::for_mount("ext4", "rw");
+
+        assert!(check_picker_rules(&valid, &[&no_manifest], &ext4).is_empty());
+        assert_eq!(
+            check_picker_rules(&valid, &[&duplicate], &ext4),
+            vec![PickerRuleFailure::DuplicateInternalName(
+                duplicate.display().to_string()
+            )]
+        );
+        assert_eq!(
+            check_picker_rules(&no_manifest, &[], &ext4),
+            vec![PickerRuleFailure::MissingToolManifest]
+        );
+
+        let vdf = fs::read_to_string(valid.join("compatibilitytool.vdf"))
+            .unwrap()
+            .replace("\"linux\"", "\"windows\"");
+        fs::write(valid.join("compatibilitytool.vdf"), vdf).unwrap();
+        assert_eq!(
+            check_picker_rules(&valid, &[], &ext4),
+            vec![PickerRuleFailure::WrongTargetOs("windows".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_detection_follows_case_sensitivity() {
+        let compatibility_tools = tempdir().unwrap();
+        let tool = compatibility_tools.path().join("GE-Proton9-21");
+        create_tool(&tool, "GE-Proton9-21");
+        // Steam reported the path in a different case than the listing
+        let same_tool = compatibility_tools.path().join("ge-proton9-21");
+        create_tool(&same_tool, "GE-Proton9-21");
+
+        assert_eq!(
+            check_picker_rules(
+                &tool,
+                &[&same_tool],
+                &FilesystemProfile::for_mount("ext4", "rw")
+            ),
+            vec![PickerRuleFailure::DuplicateInternalName(
+                same_tool.display().to_string()
+            )]
+        );
+        assert!(check_picker_rules(
+            &tool,
+            &[&same_tool],
+            &FilesystemProfile::for_mount("exfat", "rw")
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_pickup_status() {
+        assert_eq!(
+            pickup_status(true, vec![], Some(200), Some(100)),
+            PickupStatus::Detected
+        );
+        assert_eq!(
+            pickup_status(false, vec![], Some(200), Some(100)),
+            PickupStatus::NotDetected
+        );
+        assert_eq!(
+            pickup_status(false, vec![], Some(100), Some(200)),
+            PickupStatus::AwaitingRestart
+        );
+        assert_eq!(
+            pickup_status(false, vec![], None, Some(200)),
+            PickupStatus::AwaitingRestart
+        );
+        // Tools the rules mark invisible never get the generic warning
+        assert_eq!(
+            pickup_status(
+                false,
+                vec![PickerRuleFailure::MissingToolManifest],
+                Some(200),
+                Some(100)
+            ),
+            PickupStatus::Invisible(vec![PickerRuleFailure::MissingToolManifest])
+        );
+    }
+
+    #[test]
+    fn test_installed_at_falls_back_to_vdf_age() {
+        let tool = tempdir().unwrap();
+        create_tool(tool.path(), "GE-Proton9-21");
+        assert!(installed_at(tool.path(), None).is_some_and(|installed_at| installed_at > 0));
+    }
+}
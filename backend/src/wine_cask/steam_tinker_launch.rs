@@ -0,0 +1,142 @@
+use crate::wine_cask::generate_compatibility_tool_vdf;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// SteamTinkerLaunch is always installed under this name, whatever its version.
+pub const INTERNAL_NAME: &str = "SteamTinkerLaunch";
+const DISPLAY_NAME: &str = "Steam Tinker Launch";
+const SCRIPT: &str = "steamtinkerlaunch";
+const TOOL_MANIFEST: &str = r#""manifest"
+{
+  "commandline" "/steamtinkerlaunch run"
+  "commandline_waitforexitandrun" "/steamtinkerlaunch waitforexitandrun"
+}
+"#;
+
+/// Source tarball of a tag, releases have no archive to install.
+pub fn tarball_url(tag_name: &str) -> String {
+    format!(
+        "https://codeload.github.com/sonic2kk/steamtinkerlaunch/tar.gz/refs/tags/{}",
+        tag_name
+    )
+}
+
+/// Version the script reports, e.g. `v14.0`, from its `PROGVERS` assignment.
+pub fn script_version(script: &str) -> Option<String> {
+    script
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("PROGVERS="))
+        .map(|value| {
+            value
+                .trim()
+                .trim_matches(|c| c == '"' || c == '\'')
+                .to_string()
+        })
+        .filter(|version| !version.is_empty())
+}
+
+/// Version of the installed script, which updates itself without going through us.
+pub fn installed_version(tool_path: &Path) -> Option<String> {
+    script_version(&fs::read_to_string(tool_path.join(SCRIPT)).ok()?)
+}
+
+/// Turns the source tarball extracted into `directory` into a tool Steam picks up, returning the
+/// directory of the tool.
+pub fn lay_out(directory: &Path) -> io::Result<PathBuf> {
+    // The tarball's directory is named after the repository and the tag
+    let source = fs::read_dir(directory)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .find(|path| path.join(SCRIPT).is_file())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No {} script in the archive", SCRIPT),
+            )
+        })?;
+    let tool = directory.join(INTERNAL_NAME);
+    fs::rename(source, &tool)?;
+    fs::set_permissions(tool.join(SCRIPT), fs::Permissions::from_mode(0o755))?;
+    generate_compatibility_tool_vdf(
+        tool.join("compatibilitytool.vdf"),
+        INTERNAL_NAME,
+        DISPLAY_NAME,
+    );
+    fs::write(tool.join("toolmanifest.vdf"), TOOL_MANIFEST)?;
+    Ok(tool)
+}
+
+/// Removes the `~/.config/steamtinkerlaunch` symlink the script creates, a real directory holds
+/// the user's configuration and is kept. Returns whether there was a symlink.
+pub fn remove_config_symlink(home: &Path) -> io::Result<bool> {
+    let config = home.join(".config").join(SCRIPT);
+    match fs::symlink_metadata(&config) {
+        Ok(metadata) if metadata.is_symlink() => fs::remove_file(config).map(|_| true),
+        Ok(_) => Ok(false),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_script_version() {
+        let script = "#!/usr/bin/env bash\n\nPROGNAME=\"SteamTinkerLaunch\"\nPROGVERS=\"v14.0\"\nPROGCMD=\"${0##*/}\"\n";
+        assert_eq!(script_version(script).as_deref(), Some("v14.0"));
+        assert_eq!(script_version("PROGVERS=''\n"), None);
+        assert_eq!(script_version("#!/usr/bin/env bash\n"), None);
+    }
+
+    #[test]
+    fn test_tarball_is_laid_out_as_a_tool() {
+        let directory = tempdir().unwrap();
+        let source = directory.path().join("sonic2kk-steamtinkerlaunch-1a2b3c4");
+        fs::create_dir_all(source.join("lang")).unwrap();
+        fs::write(source.join(SCRIPT), "PROGVERS=\"v14.0\"\n").unwrap();
+
+        let tool = lay_out(directory.path()).unwrap();
+        assert_eq!(tool, directory.path().join(INTERNAL_NAME));
+        assert!(!source.exists());
+        assert!(tool.join("lang").is_dir());
+        assert_eq!(installed_version(&tool).as_deref(), Some("v14.0"));
+        let mode = fs::metadata(tool.join(SCRIPT))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o111, 0o111);
+        let compatibility_tool = fs::read_to_string(tool.join("compatibilitytool.vdf")).unwrap();
+        assert!(compatibility_tool.contains("\"SteamTinkerLaunch\""));
+        assert!(tool.join("toolmanifest.vdf").is_file());
+
+        let empty = tempdir().unwrap();
+        assert_eq!(
+            lay_out(empty.path()).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_only_the_config_symlink_is_removed() {
+        let home = tempdir().unwrap();
+        assert!(!remove_config_symlink(home.path()).unwrap());
+
+        let target = home.path().join("stl");
+        fs::create_dir_all(&target).unwrap();
+        fs::create_dir_all(home.path().join(".config")).unwrap();
+        symlink(&target, home.path().join(".config").join(SCRIPT)).unwrap();
+        assert!(remove_config_symlink(home.path()).unwrap());
+        assert!(!home.path().join(".config").join(SCRIPT).exists());
+        assert!(target.is_dir());
+
+        fs::create_dir_all(home.path().join(".config").join(SCRIPT)).unwrap();
+        assert!(!remove_config_symlink(home.path()).unwrap());
+        assert!(home.path().join(".config").join(SCRIPT).is_dir());
+    }
+}
@@ -0,0 +1,616 @@
+use crate::app_id::{AppId, CompatAppId};
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, WineCask};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::mutation_guard::{delete_dir_guarded, journal_directory};
+use crate::wine_cask::network_usage::format_bytes;
+use crate::wine_cask::provenance::current_timestamp;
+use crate::PeerMap;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, Metadata};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// Number of prefixes and shader caches listed per library folder.
+const TOP_CONSUMERS: usize = 10;
+/// Seconds a computed breakdown is reused, walking every library folder is slow on microSD cards.
+const CACHE_TTL: u64 = 5 * 60;
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct StorageBreakdown {
+    pub library_folders: Vec<LibraryFolderUsage>,
+    pub computed_at: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct LibraryFolderUsage {
+    pub path: String,
+    pub common_bytes: u64,
+    pub compatdata_bytes: u64,
+    pub shadercache_bytes: u64,
+    pub downloading_bytes: u64,
+    /// Largest prefixes, biggest first.
+    pub top_prefixes: Vec<AppUsage>,
+    /// Largest shader caches, biggest first.
+    pub top_shader_caches: Vec<AppUsage>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct AppUsage {
+    pub app_id: CompatAppId,
+    /// App name, or the app id if no name could be found.
+    pub name: String,
+    pub bytes: u64,
+}
+
+/// Size of an app's prefix in one library folder.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct PrefixSize {
+    pub app_id: CompatAppId,
+    /// App name, or the app id if no name could be found.
+    pub name: String,
+    pub library_folder: String,
+    pub bytes: u64,
+}
+
+/// Calls `visit` with the path and metadata of every file below `path`, symlinks are neither
+/// followed nor visited.
+fn visit_files(path: &Path, visit: &mut impl FnMut(&Path, &Metadata)) {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return;
+    };
+    if metadata.is_symlink() {
+        return;
+    }
+    if !metadata.is_dir() {
+        visit(path, &metadata);
+        return;
+    }
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.filter_map(Result::ok) {
+            visit_files(&entry.path(), visit);
+        }
+    }
+}
+
+/// Total size of the files below `path` without following symlinks, 0 if it doesn't exist. Like
+/// du, files hard linked more than once below `path` are counted once.
+pub fn directory_size(path: &Path) -> u64 {
+    let mut size = 0;
+    let mut linked: HashSet<(u64, u64)> = HashSet::new();
+    visit_files(path, &mut |_, metadata| {
+        if metadata.nlink() < 2 || linked.insert((metadata.dev(), metadata.ino())) {
+            size += metadata.len();
+        }
+    });
+    size
+}
+
+/// Sizes of the per-app directories in `path`, biggest first.
+fn all_app_usages(path: &Path, names: &HashMap<CompatAppId, String>) -> Vec<AppUsage> {
+    let mut app_usages: Vec<AppUsage> = fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter_map(|entry| {
+                    let app_id = CompatAppId::parse(&entry.file_name().to_string_lossy()).ok()?;
+                    Some(AppUsage {
+                        app_id,
+                        name: names
+                            .get(&app_id)
+                            .cloned()
+                            .unwrap_or_else(|| app_id.to_string()),
+                        bytes: directory_size(&entry.path()),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    app_usages.sort_by_key(|app_usage| Reverse(app_usage.bytes));
+    app_usages
+}
+
+/// Sizes of the largest per-app directories in `path`, biggest first, along with their total.
+fn app_usages(path: &Path, names: &HashMap<CompatAppId, String>) -> (u64, Vec<AppUsage>) {
+    let mut app_usages = all_app_usages(path, names);
+    let total = app_usages.iter().map(|app_usage| app_usage.bytes).sum();
+    app_usages.truncate(TOP_CONSUMERS);
+    (total, app_usages)
+}
+
+pub fn library_folder_usage(
+    library_folder: &Path,
+    names: &HashMap<CompatAppId, String>,
+) -> LibraryFolderUsage {
+    let steam_apps = library_folder.join("steamapps");
+    let (compatdata_bytes, top_prefixes) = app_usages(&steam_apps.join("compatdata"), names);
+    let (shadercache_bytes, top_shader_caches) = app_usages(&steam_apps.join("shadercache"), names);
+    LibraryFolderUsage {
+        path: library_folder.to_string_lossy().to_string(),
+        common_bytes: directory_size(&steam_apps.join("common")),
+        compatdata_bytes,
+        shadercache_bytes,
+        downloading_bytes: directory_size(&steam_apps.join("downloading")),
+        top_prefixes,
+        top_shader_caches,
+    }
+}
+
+/// Computes the usage of every library folder, one thread per folder since they are usually on
+/// different drives.
+pub fn storage_breakdown(
+    library_folders: &[PathBuf],
+    names: &HashMap<CompatAppId, String>,
+) -> StorageBreakdown {
+    let library_folders = thread::scope(|scope| {
+        let handles: Vec<_> = library_folders
+            .iter()
+            .map(|library_folder| scope.spawn(|| library_folder_usage(library_folder, names)))
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().ok())
+            .collect()
+    });
+    StorageBreakdown {
+        library_folders,
+        computed_at: current_timestamp(),
+    }
+}
+
+/// Sizes of every prefix in `library_folders`, biggest first.
+pub fn prefix_sizes(
+    library_folders: &[PathBuf],
+    names: &HashMap<CompatAppId, String>,
+) -> Vec<PrefixSize> {
+    let mut prefix_sizes: Vec<PrefixSize> = thread::scope(|scope| {
+        let handles: Vec<_> = library_folders
+            .iter()
+            .map(|library_folder| {
+                scope.spawn(|| {
+                    all_app_usages(&library_folder.join("steamapps/compatdata"), names)
+                        .into_iter()
+                        .map(|app_usage| PrefixSize {
+                            app_id: app_usage.app_id,
+                            name: app_usage.name,
+                            library_folder: library_folder.to_string_lossy().to_string(),
+                            bytes: app_usage.bytes,
+                        })
+                        .collect::<Vec<PrefixSize>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().ok())
+            .flatten()
+            .collect()
+    });
+    prefix_sizes.sort_by_key(|prefix_size| Reverse(prefix_size.bytes));
+    prefix_sizes
+}
+
+/// Shader cache Steam downloaded or built for an app, across every library folder.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ShaderCacheStatus {
+    pub app_id: AppId,
+    pub bytes: u64,
+    /// Fossilize databases (`.foz`) Steam replays to pre-compile pipelines for one tool.
+    pub fossilize_databases: u32,
+    pub fossilize_bytes: u64,
+    /// Whether the cache was cleared along with the change that invalidated it.
+    pub cleared: bool,
+}
+
+/// Returns `None` if the app has no shader cache in any library folder.
+pub fn shader_cache_status(
+    library_folders: &[PathBuf],
+    app_id: AppId,
+) -> Option<ShaderCacheStatus> {
+    let shader_caches: Vec<PathBuf> = library_folders
+        .iter()
+        .map(|library_folder| {
+            library_folder
+                .join("steamapps")
+                .join("shadercache")
+                .join(app_id.to_string())
+        })
+        .filter(|shader_cache| shader_cache.is_dir())
+        .collect();
+    if shader_caches.is_empty() {
+        return None;
+    }
+    let mut status = ShaderCacheStatus {
+        app_id,
+        bytes: 0,
+        fossilize_databases: 0,
+        fossilize_bytes: 0,
+        cleared: false,
+    };
+    for shader_cache in shader_caches {
+        visit_files(&shader_cache, &mut |path, metadata| {
+            status.bytes += metadata.len();
+            if path.extension().is_some_and(|extension| extension == "foz") {
+                status.fossilize_databases += 1;
+                status.fossilize_bytes += metadata.len();
+            }
+        });
+    }
+    Some(status)
+}
+
+/// Returns the pids of processes launched by Steam for the app, found through their environment.
+pub fn running_app_processes(proc_root: &Path, app_id: AppId) -> Vec<u32> {
+    let needle = format!("SteamAppId={}", app_id);
+    let Ok(entries) = fs::read_dir(proc_root) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let pid: u32 = entry.file_name().to_string_lossy().parse().ok()?;
+            // Processes of other users can't be read, which is fine since Steam runs as the user
+            let environ = fs::read(entry.path().join("environ")).ok()?;
+            environ
+                .split(|byte| *byte == 0)
+                .any(|variable| variable == needle.as_bytes())
+                .then_some(pid)
+        })
+        .collect()
+}
+
+/// Removes `shadercache/<app_id>` from every library folder, returning the number of bytes freed.
+pub fn clear_shader_cache(
+    library_folders: &[PathBuf],
+    app_id: AppId,
+    proc_root: &Path,
+    journal_directory: &Path,
+) -> Result<u64, AppError> {
+    let running = running_app_processes(proc_root, app_id);
+    if !running.is_empty() {
+        return Err(AppError::new(
+            AppErrorCode::OperationBlockedGameRunning,
+            format!(
+                "App {} is running (pid {}), close it before clearing its shader cache",
+                app_id,
+                running
+                    .iter()
+                    .map(|pid| pid.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        ));
+    }
+
+    let mut freed = 0;
+    for library_folder in library_folders {
+        let shader_cache = library_folder
+            .join("steamapps")
+            .join("shadercache")
+            .join(app_id.to_string());
+        if !shader_cache.is_dir() {
+            continue;
+        }
+        let bytes = directory_size(&shader_cache);
+        delete_dir_guarded(journal_directory, &shader_cache).map_err(|err| {
+            AppError::from(err).context(format!(
+                "Failed to delete shader cache {}",
+                shader_cache.display()
+            ))
+        })?;
+        freed += bytes;
+    }
+    Ok(freed)
+}
+
+impl WineCask {
+    fn app_names(&self) -> HashMap<CompatAppId, String> {
+        let mut names: HashMap<CompatAppId, String> = self
+            .steam_util
+            .list_installed_apps()
+            .unwrap_or_else(|err| {
+                warn!("Failed to get list of installed apps: {}", err);
+                Vec::new()
+            })
+            .into_iter()
+            .map(|app| (CompatAppId::from(app.app_id), app.name))
+            .collect();
+        // Only use names resolved earlier, the breakdown shouldn't wait on the Steam store
+        for (app_id, name) in self.app_name_resolver.lock().unwrap().cached_names() {
+            names.entry(CompatAppId::from(app_id)).or_insert(name);
+        }
+        names
+    }
+
+    pub async fn get_storage_breakdown(&self, peer_map: &PeerMap, refresh: bool) {
+        let cached = self.app_state.lock().await.storage_breakdown.clone();
+        let storage_breakdown = match cached {
+            Some(cached) if !refresh && cached.computed_at + CACHE_TTL > current_timestamp() => {
+                cached
+            }
+            _ => {
+                let library_folders = self.mounted_library_folders().await;
+                let names = self.app_names();
+                let storage_breakdown = tokio::task::spawn_blocking(move || {
+                    storage_breakdown(&library_folders, &names)
+                })
+                .await
+                .unwrap();
+                self.app_state.lock().await.storage_breakdown = Some(storage_breakdown.clone());
+                storage_breakdown
+            }
+        };
+        self.broadcast_storage_breakdown(peer_map, storage_breakdown)
+            .await;
+    }
+
+    /// Sends back the size of every prefix, walking them all takes a while on microSD cards.
+    pub async fn get_prefix_sizes(&self, peer_map: &PeerMap) {
+        let library_folders = self.mounted_library_folders().await;
+        let names = self.app_names();
+        let prefix_sizes =
+            tokio::task::spawn_blocking(move || prefix_sizes(&library_folders, &names))
+                .await
+                .unwrap();
+        info!("Measured {} prefixes", prefix_sizes.len());
+        broadcast_to_peers(
+            peer_map,
+            &Request {
+                prefix_sizes: Some(prefix_sizes),
+                ..Request::new(RequestType::PrefixSizes)
+            },
+        )
+        .await;
+    }
+
+    /// Drops the cached breakdown after we changed something on disk.
+    pub async fn invalidate_storage_breakdown(&self) {
+        self.app_state.lock().await.storage_breakdown = None;
+    }
+
+    /// Returns whether the cache was cleared.
+    pub async fn clear_shader_cache(&self, peer_map: &PeerMap, app_id: AppId) -> bool {
+        let library_folders = self.mounted_library_folders().await;
+        let result = tokio::task::spawn_blocking(move || {
+            clear_shader_cache(
+                &library_folders,
+                app_id,
+                Path::new("/proc"),
+                &journal_directory(),
+            )
+        })
+        .await
+        .unwrap();
+        match result {
+            Ok(freed) => {
+                let message = format!(
+                    "Cleared shader cache of {}, freed {}",
+                    app_id,
+                    format_bytes(freed)
+                );
+                info!("{}", message);
+                self.invalidate_storage_breakdown().await;
+                self.broadcast_app_notification(peer_map, &message, vec![app_id.into()])
+                    .await;
+                true
+            }
+            Err(app_error) => {
+                error!("{}", app_error);
+                self.broadcast_app_error(peer_map, app_error).await;
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn write_file(path: &Path, size: usize) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, vec![0u8; size]).unwrap();
+    }
+
+    #[test]
+    fn test_library_folder_usage() {
+        let library_folder = tempdir().unwrap();
+        let steam_apps = library_folder.path().join("steamapps");
+        write_file(&steam_apps.join("common/ELDEN RING/eldenring.exe"), 1000);
+        write_file(&steam_apps.join("compatdata/1245620/pfx/system.reg"), 300);
+        write_file(
+            &steam_apps.join("compatdata/3228583970/pfx/system.reg"),
+            200,
+        );
+        write_file(
+            &steam_apps.join("shadercache/1245620/fozpipelinesv6/steamapp_pipeline_cache.foz"),
+            50,
+        );
+        write_file(&steam_apps.join("downloading/730/csgo.exe"), 10);
+        // Not an app, must not show up as a consumer
+        fs::create_dir_all(steam_apps.join("compatdata/.wine-cellar-trash")).unwrap();
+
+        let names = HashMap::from([(CompatAppId::new(1245620).unwrap(), "ELDEN RING".to_string())]);
+        let usage = library_folder_usage(library_folder.path(), &names);
+        assert_eq!(usage.common_bytes, 1000);
+        assert_eq!(usage.compatdata_bytes, 500);
+        assert_eq!(usage.shadercache_bytes, 50);
+        assert_eq!(usage.downloading_bytes, 10);
+        assert_eq!(usage.top_prefixes.len(), 2);
+        assert_eq!(usage.top_prefixes[0].name, "ELDEN RING");
+        assert_eq!(usage.top_prefixes[1].name, "3228583970");
+        assert_eq!(usage.top_shader_caches[0].bytes, 50);
+    }
+
+    #[test]
+    fn test_directory_size_counts_links_once() {
+        let tool = tempdir().unwrap();
+        write_file(&tool.path().join("files/lib/wine/d3d11.dll"), 400);
+        write_file(&tool.path().join("files/share/default_pfx/user.reg"), 100);
+        // Proton links the default prefix to its own libraries
+        fs::hard_link(
+            tool.path().join("files/lib/wine/d3d11.dll"),
+            tool.path().join("files/share/default_pfx/d3d11.dll"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(
+            tool.path().join("files/lib"),
+            tool.path().join("files/share/lib"),
+        )
+        .unwrap();
+
+        assert_eq!(directory_size(tool.path()), 500);
+        // Links to files outside the directory still count
+        assert_eq!(
+            directory_size(&tool.path().join("files/share/default_pfx")),
+            500
+        );
+    }
+
+    #[test]
+    fn test_prefix_sizes_of_every_library_folder() {
+        let internal = tempdir().unwrap();
+        let sd_card = tempdir().unwrap();
+        write_file(
+            &internal
+                .path()
+                .join("steamapps/compatdata/1245620/pfx/system.reg"),
+            300,
+        );
+        write_file(
+            &sd_card
+                .path()
+                .join("steamapps/compatdata/292030/pfx/system.reg"),
+            700,
+        );
+        write_file(
+            &sd_card
+                .path()
+                .join("steamapps/compatdata/730/pfx/system.reg"),
+            100,
+        );
+
+        let names = HashMap::from([(CompatAppId::new(1245620).unwrap(), "ELDEN RING".to_string())]);
+        let prefix_sizes = prefix_sizes(
+            &[internal.path().to_path_buf(), sd_card.path().to_path_buf()],
+            &names,
+        );
+        assert_eq!(
+            prefix_sizes
+                .iter()
+                .map(|prefix_size| (prefix_size.name.as_str(), prefix_size.bytes))
+                .collect::<Vec<_>>(),
+            [("292030", 700), ("ELDEN RING", 300), ("730", 100)]
+        );
+        assert_eq!(
+            prefix_sizes[1].library_folder,
+            internal.path().to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn test_clear_shader_cache_refuses_while_running() {
+        let temp_dir = tempdir().unwrap();
+        let library_folder = temp_dir.path().join("library");
+        let proc_root = temp_dir.path().join("proc");
+        let shader_cache = library_folder.join("steamapps/shadercache/1245620");
+        let journal = temp_dir.path().join("journal");
+        write_file(
+            &shader_cache.join("fozpipelinesv6/steamapp_pipeline_cache.foz"),
+            50,
+        );
+        fs::create_dir_all(proc_root.join("4242")).unwrap();
+        fs::write(
+            proc_root.join("4242/environ"),
+            b"HOME=/home/deck\0SteamAppId=1245620\0SteamGameId=1245620\0",
+        )
+        .unwrap();
+
+        let app_id = AppId::new(1245620).unwrap();
+        let library_folders = vec![library_folder];
+        let error = clear_shader_cache(&library_folders, app_id, &proc_root, &journal).unwrap_err();
+        assert_eq!(error.code, AppErrorCode::OperationBlockedGameRunning);
+        assert!(error.message.contains("4242"));
+        assert!(shader_cache.exists());
+
+        fs::remove_dir_all(proc_root.join("4242")).unwrap();
+        assert_eq!(
+            clear_shader_cache(&library_folders, app_id, &proc_root, &journal).unwrap(),
+            50
+        );
+        assert!(!shader_cache.exists());
+    }
+
+    #[test]
+    fn test_shader_cache_status() {
+        let temp_dir = tempdir().unwrap();
+        let internal = temp_dir.path().join("internal");
+        let sd_card = temp_dir.path().join("sd_card");
+        let app_id = AppId::new(1245620).unwrap();
+        write_file(
+            &internal
+                .join("steamapps/shadercache/1245620/fozpipelinesv6/steamapp_pipeline_cache.foz"),
+            50,
+        );
+        write_file(
+            &internal.join(
+                "steamapps/shadercache/1245620/fozpipelinesv6/steamapprun_pipeline_cache.foz",
+            ),
+            20,
+        );
+        write_file(
+            &sd_card.join("steamapps/shadercache/1245620/DXVK_state_cache/eldenring.dxvk-cache"),
+            30,
+        );
+        // Another app's cache doesn't count
+        write_file(
+            &sd_card.join("steamapps/shadercache/730/fozpipelinesv6/steamapp_pipeline_cache.foz"),
+            1000,
+        );
+
+        let library_folders = vec![internal.clone(), sd_card.clone()];
+        assert_eq!(
+            shader_cache_status(&library_folders, app_id),
+            Some(ShaderCacheStatus {
+                app_id,
+                bytes: 100,
+                fossilize_databases: 2,
+                fossilize_bytes: 70,
+                cleared: false,
+            })
+        );
+
+        // Caches without a fossilize database are still reported
+        let status = shader_cache_status(&[sd_card], app_id).unwrap();
+        assert_eq!(status.bytes, 30);
+        assert_eq!(status.fossilize_databases, 0);
+
+        assert_eq!(
+            shader_cache_status(&library_folders, AppId::new(292030).unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_running_app_processes_matches_exact_app_id() {
+        let proc_root = tempdir().unwrap();
+        fs::create_dir_all(proc_root.path().join("100")).unwrap();
+        fs::write(
+            proc_root.path().join("100/environ"),
+            b"SteamAppId=12456200\0",
+        )
+        .unwrap();
+        fs::create_dir_all(proc_root.path().join("self")).unwrap();
+        fs::write(
+            proc_root.path().join("self/environ"),
+            b"SteamAppId=1245620\0",
+        )
+        .unwrap();
+
+        assert!(running_app_processes(proc_root.path(), AppId::new(1245620).unwrap()).is_empty());
+    }
+}
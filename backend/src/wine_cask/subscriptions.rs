@@ -0,0 +1,303 @@
+use crate::wine_cask::app::{AppState, Request, RequestType, UpdaterState, WineCask};
+use crate::wine_cask::permissions::filter_for_peer;
+use crate::{PeerAddr, PeerMap};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Activity events sent along when a peer subscribes to logs.
+const LOGS_SNAPSHOT_LENGTH: usize = 50;
+
+/// Categories of messages a peer can subscribe to.
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Subscription {
+    /// Queued and running tasks and how they ended.
+    Tasks,
+    /// Installed tools, what uses them and what is known about them.
+    InstalledTools,
+    /// Flavors and their releases.
+    AvailableFlavors,
+    Settings,
+    /// Notifications, errors and the activity feed.
+    Logs,
+}
+
+impl Subscription {
+    pub const ALL: [Subscription; 5] = [
+        Subscription::Tasks,
+        Subscription::InstalledTools,
+        Subscription::AvailableFlavors,
+        Subscription::Settings,
+        Subscription::Logs,
+    ];
+}
+
+/// What a peer subscribed to, everything until it says otherwise.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(transparent)]
+pub struct SubscriptionSet(BTreeSet<Subscription>);
+
+impl Default for SubscriptionSet {
+    fn default() -> Self {
+        SubscriptionSet(Subscription::ALL.into_iter().collect())
+    }
+}
+
+impl SubscriptionSet {
+    pub fn new(subscriptions: impl IntoIterator<Item = Subscription>) -> Self {
+        SubscriptionSet(subscriptions.into_iter().collect())
+    }
+
+    pub fn includes(&self, subscription: Subscription) -> bool {
+        self.0.contains(&subscription)
+    }
+
+    /// Subscriptions of `self` that `other` doesn't have.
+    pub fn difference(&self, other: &SubscriptionSet) -> SubscriptionSet {
+        SubscriptionSet(self.0.difference(&other.0).copied().collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Subscriptions a message belongs to, messages in none are sent to every peer.
+fn subscriptions_of(request: &Request) -> &'static [Subscription] {
+    match request.r#type {
+        RequestType::TaskCancelled
+        | RequestType::UpdateSummary
+        | RequestType::UninstallBlocked
+        | RequestType::InsufficientDiskSpace => &[Subscription::Tasks],
+        RequestType::ToolProvenance
+        | RequestType::Verification
+        | RequestType::ToolInspected
+        | RequestType::InspectionCompleted
+        | RequestType::UpdatesAvailable => &[Subscription::InstalledTools],
+        RequestType::RefreshCompleted => &[Subscription::AvailableFlavors],
+        RequestType::Notification
+        | RequestType::Activity
+        | RequestType::MutationLog
+        | RequestType::History => &[Subscription::Logs],
+        // Errors ending a task are also task progress
+        RequestType::Error
+            if request
+                .app_error
+                .as_ref()
+                .is_some_and(|app_error| app_error.task_id.is_some()) =>
+        {
+            &[Subscription::Tasks, Subscription::Logs]
+        }
+        RequestType::Error => &[Subscription::Logs],
+        _ => &[],
+    }
+}
+
+/// Clears the sections of the state a peer didn't subscribe to.
+fn filter_app_state(app_state: &mut AppState, subscriptions: &SubscriptionSet) {
+    if !subscriptions.includes(Subscription::Tasks) {
+        app_state.in_progress.clear();
+        app_state.task_queue.clear();
+        app_state.prefix_restores.clear();
+        app_state.updater_state = UpdaterState::Idle;
+        app_state.updater_last_check = None;
+    }
+    if !subscriptions.includes(Subscription::InstalledTools) {
+        app_state.installed_compatibility_tools.clear();
+        app_state.installed_runners.clear();
+        app_state.installed_components.clear();
+        app_state.compatibility_tool_mappings.clear();
+        app_state.stranded_compatibility_tools.clear();
+        app_state.inspection_progress = None;
+        app_state.available_updates.clear();
+    }
+    if !subscriptions.includes(Subscription::AvailableFlavors) {
+        app_state.available_flavors.clear();
+        app_state.github_rate_limit_reset = None;
+    }
+    if !subscriptions.includes(Subscription::Settings) {
+        app_state.settings = Default::default();
+        app_state.steam_installations.clear();
+        app_state.restart_required = false;
+    }
+}
+
+/// Returns what a peer with `subscriptions` gets of a message, `None` if nothing.
+///
+/// Runs before the permissions filter, which diffs state deltas, so unsubscribed sections never
+/// show up as changes.
+pub fn filter_for_subscriptions(
+    request: &Request,
+    subscriptions: &SubscriptionSet,
+) -> Option<Request> {
+    let needed = subscriptions_of(request);
+    if !needed.is_empty()
+        && !needed
+            .iter()
+            .any(|subscription| subscriptions.includes(*subscription))
+    {
+        return None;
+    }
+    let mut request = request.clone();
+    if let Some(app_state) = &mut request.app_state {
+        filter_app_state(app_state, subscriptions);
+    }
+    if let Some(base_app_state) = &mut request.base_app_state {
+        filter_app_state(base_app_state, subscriptions);
+    }
+    Some(request)
+}
+
+impl WineCask {
+    /// Replaces what a peer subscribed to, acknowledged with the current state of what it newly
+    /// subscribed to.
+    pub async fn subscribe(
+        &self,
+        peer_map: &PeerMap,
+        addr: PeerAddr,
+        subscriptions: SubscriptionSet,
+    ) {
+        let mut peers = peer_map.lock().await;
+        let Some(peer) = peers.get_mut(&addr) else {
+            return;
+        };
+        let added = subscriptions.difference(&peer.subscriptions);
+        peer.subscriptions = subscriptions.clone();
+        let outbox = peer.outbox.clone();
+        let permissions = peer.permissions.clone();
+        drop(peers);
+        info!("{} subscribed to {:?}", addr, subscriptions);
+
+        let mut acknowledgement = Request {
+            subscriptions: Some(subscriptions.clone()),
+            ..Request::new(RequestType::Subscribed)
+        };
+        if !added.is_empty() {
+            acknowledgement.app_state = Some(self.app_state.lock().await.clone());
+        }
+        if added.includes(Subscription::Logs) {
+            acknowledgement.activity = Some(
+                self.activity_log
+                    .lock()
+                    .await
+                    .get_activity(LOGS_SNAPSHOT_LENGTH, None),
+            );
+        }
+        let acknowledgement = filter_for_subscriptions(&acknowledgement, &subscriptions)
+            .and_then(|acknowledgement| filter_for_peer(&acknowledgement, &permissions));
+        if let Some(acknowledgement) = acknowledgement {
+            let message = Message::text(serde_json::to_string(&acknowledgement).unwrap());
+            outbox.push(RequestType::Subscribed, message).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wine_cask::app_error::{AppError, AppErrorCode};
+    use serde_json::json;
+
+    fn subscribed(subscriptions: &[Subscription], request: &Request) -> bool {
+        filter_for_subscriptions(
+            request,
+            &SubscriptionSet::new(subscriptions.iter().copied()),
+        )
+        .is_some()
+    }
+
+    #[test]
+    fn test_new_peers_get_everything() {
+        let subscriptions = SubscriptionSet::default();
+        for subscription in Subscription::ALL {
+            assert!(subscriptions.includes(subscription));
+        }
+        assert_eq!(
+            serde_json::to_value(SubscriptionSet::new([Subscription::InstalledTools])).unwrap(),
+            serde_json::json!(["installed_tools"])
+        );
+    }
+
+    #[test]
+    fn test_messages_go_to_their_subscribers() {
+        let task_error = Request {
+            app_error: Some(
+                AppError::internal("Failed to prepare a temporary directory").for_task(1),
+            ),
+            ..Request::new(RequestType::Error)
+        };
+        assert!(subscribed(&[Subscription::Tasks], &task_error));
+        assert!(subscribed(&[Subscription::Logs], &task_error));
+        let error = Request {
+            app_error: Some(AppError::new(AppErrorCode::VdfParse, "Unexpected }")),
+            ..Request::new(RequestType::Error)
+        };
+        assert!(!subscribed(&[Subscription::Tasks], &error));
+
+        let notification = Request::new(RequestType::Notification);
+        assert!(!subscribed(&[Subscription::Settings], &notification));
+        assert!(subscribed(&[Subscription::Logs], &notification));
+        // Answers to requests and the handshake reach everyone
+        assert!(subscribed(
+            &[],
+            &Request::new(RequestType::StorageBreakdown)
+        ));
+        assert!(subscribed(&[], &Request::new(RequestType::Permissions)));
+    }
+
+    fn app_state() -> AppState {
+        serde_json::from_value(json!({
+            "available_flavors": [],
+            "installed_compatibility_tools": [],
+            "installed_runners": [],
+            "installed_components": [],
+            "compatibility_tool_mappings": [],
+            "in_progress": [],
+            "task_queue": [],
+            "updater_state": "Checking",
+            "updater_last_check": 1_750_000_000,
+            "github_rate_limit_reset": null,
+            "settings": {},
+            "network_usage": {},
+            "stranded_compatibility_tools": [],
+            "startup_stages": [],
+            "restart_required": true,
+            "filesystem_profiles": [],
+            "broadcast_counters": { "dropped": 0, "coalesced": 0, "evictions": 0 },
+            "steam_installations": ["/home/deck/.steam/root"],
+            "inspection_progress": null,
+            "available_updates": [],
+            "prefix_restores": [],
+            "environment": {
+                "on_battery": null,
+                "metered_network": null,
+                "steam_started_at": null,
+                "running_game": null,
+                "game_mode": null,
+                "system_versions": {"steamos": null, "mesa": null, "kernel": null},
+                "clock_skew": null,
+                "github_reachability": null
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_unsubscribed_sections_are_left_out_of_the_state() {
+        let request = Request {
+            app_state: Some(app_state()),
+            ..Request::new(RequestType::UpdateState)
+        };
+
+        let filtered =
+            filter_for_subscriptions(&request, &SubscriptionSet::new([Subscription::Tasks]))
+                .unwrap()
+                .app_state
+                .unwrap();
+        assert_eq!(filtered.updater_last_check, Some(1_750_000_000));
+        assert!(!filtered.restart_required);
+        assert!(filtered.steam_installations.is_empty());
+    }
+}
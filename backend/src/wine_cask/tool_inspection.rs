@@ -0,0 +1,489 @@
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, WineCask};
+use crate::wine_cask::background::run_constrained;
+use crate::wine_cask::flavors::SteamCompatibilityTool;
+use crate::wine_cask::local_changes::{detect_local_changes, directory_mtime, LocalChanges};
+use crate::wine_cask::provenance::Provenance;
+use crate::wine_cask::storage::directory_size;
+use crate::written_by::stamped;
+use crate::PeerMap;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use std::{fs, io};
+
+/// Translation layers Proton builds ship next to Wine.
+const COMPONENTS: [&str; 3] = ["dxvk", "vkd3d-proton", "nvapi"];
+/// Directories of a tool the components are found in, depending on how it was built.
+const COMPONENT_DIRECTORIES: [&str; 3] = ["files/lib/wine", "files/lib64/wine", "dist/lib/wine"];
+
+/// What deep inspection found out about an installed tool, too slow for the listing itself.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ToolInspection {
+    pub bytes: u64,
+    /// Translation layers the tool ships, e.g. `dxvk`.
+    pub components: Vec<String>,
+    /// Installed by something else, e.g. ProtonUp, so there is no provenance to manage it by.
+    pub adoptable: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub struct InspectionProgress {
+    /// Tools with an inspection, some of them may be inspected again if they changed since.
+    pub inspected: usize,
+    pub total: usize,
+    pub paused: bool,
+}
+
+/// An inspection along with what is only needed to tell whether it is still current.
+#[derive(Serialize, Deserialize, Clone)]
+struct InspectedTool {
+    /// Newest modification time of the tool's directories when it was inspected.
+    modified_at: u64,
+    inspection: ToolInspection,
+    local_changes: Option<LocalChanges>,
+}
+
+/// Every finished inspection, saved after each tool so a restart resumes with the tools that
+/// weren't inspected yet.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ToolInspector {
+    paused: bool,
+    inspected: HashMap<String, InspectedTool>,
+    #[serde(skip)]
+    file: PathBuf,
+    #[serde(skip)]
+    running: Arc<AtomicBool>,
+}
+
+impl ToolInspector {
+    pub fn load(file: PathBuf) -> ToolInspector {
+        let tool_inspector = fs::read_to_string(&file)
+            .ok()
+            .and_then(|string| serde_json::from_str(&string).ok())
+            .unwrap_or_default();
+        ToolInspector {
+            file,
+            ..tool_inspector
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            &self.file,
+            serde_json::to_string(&stamped(self, "tool_inspection", &self.file))?,
+        )
+    }
+
+    /// Last inspection of `internal_name`, without checking whether it's still current.
+    pub fn last(&self, internal_name: &str) -> Option<(&ToolInspection, Option<&LocalChanges>)> {
+        self.inspected
+            .get(internal_name)
+            .map(|inspected| (&inspected.inspection, inspected.local_changes.as_ref()))
+    }
+}
+
+fn modified_at(path: &Path) -> Option<u64> {
+    directory_mtime(path)?
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs())
+}
+
+/// Walks the whole tool, `None` if it's gone.
+fn inspect_tool(path: &Path, provenance: Option<Provenance>) -> Option<InspectedTool> {
+    let modified_at = modified_at(path)?;
+    let components = COMPONENTS
+        .iter()
+        .filter(|component| {
+            COMPONENT_DIRECTORIES
+                .iter()
+                .any(|directory| path.join(directory).join(component).is_dir())
+        })
+        .map(|component| component.to_string())
+        .collect();
+    let adoptable = provenance.is_none();
+    let local_changes = provenance.and_then(|provenance| {
+        let files = provenance.files?;
+        detect_local_changes(path, &files, provenance.installed_at)
+            .map_err(|err| {
+                warn!(
+                    "Failed to check {} for local changes: {}",
+                    path.display(),
+                    err
+                )
+            })
+            .ok()
+    });
+    Some(InspectedTool {
+        modified_at,
+        inspection: ToolInspection {
+            bytes: directory_size(path),
+            components,
+            adoptable,
+        },
+        local_changes,
+    })
+}
+
+/// Fills in the last inspection of every listed tool, called whenever the tools are listed.
+pub fn apply_inspections(tools: &mut [SteamCompatibilityTool], tool_inspector: &ToolInspector) {
+    for tool in tools {
+        let Some((inspection, local_changes)) = tool_inspector.last(&tool.internal_name) else {
+            continue;
+        };
+        tool.inspection = Some(inspection.clone());
+        if tool.modified_since_install.is_none() {
+            tool.modified_since_install =
+                local_changes.filter(|changes| !changes.is_empty()).cloned();
+        }
+    }
+}
+
+/// Inspects every installed tool in the background, unless paused.
+pub fn start_tool_inspection(wine_cask: &Arc<WineCask>, peer_map: &PeerMap) {
+    let wine_cask = Arc::clone(wine_cask);
+    let peer_map = Arc::clone(peer_map);
+    tokio::spawn(async move { run_tool_inspection(&wine_cask, &peer_map).await });
+}
+
+/// Inspects the tools not inspected since they last changed, one at a time with lowered
+/// priority, until every tool is done or the inspection is paused.
+pub async fn run_tool_inspection(wine_cask: &WineCask, peer_map: &PeerMap) {
+    let running = wine_cask
+        .app_state
+        .lock()
+        .await
+        .tool_inspector
+        .running
+        .clone();
+    if running.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    inspect_tools(wine_cask, peer_map).await;
+    running.store(false, Ordering::SeqCst);
+}
+
+async fn inspect_tools(wine_cask: &WineCask, peer_map: &PeerMap) {
+    let tools = {
+        let mut app_state = wine_cask.app_state.lock().await;
+        let tools = app_state.installed_compatibility_tools.clone();
+        let tool_inspector = &mut app_state.tool_inspector;
+        // Uninstalled since the last run
+        tool_inspector.inspected.retain(|internal_name, _| {
+            tools
+                .iter()
+                .any(|tool| tool.internal_name == *internal_name)
+        });
+        tools
+    };
+    let total = tools.len();
+    info!("Inspecting {} tools", total);
+
+    for tool in tools {
+        let path = PathBuf::from(&tool.path);
+        let progress = {
+            let mut app_state = wine_cask.app_state.lock().await;
+            let progress = InspectionProgress {
+                inspected: app_state.tool_inspector.inspected.len(),
+                total,
+                paused: app_state.tool_inspector.paused,
+            };
+            app_state.inspection_progress = Some(progress);
+            progress
+        };
+        if progress.paused {
+            info!(
+                "Tool inspection paused with {} of {} tools inspected",
+                progress.inspected, total
+            );
+            wine_cask.broadcast_app_state(peer_map).await;
+            return;
+        }
+
+        let known = wine_cask
+            .app_state
+            .lock()
+            .await
+            .tool_inspector
+            .inspected
+            .get(&tool.internal_name)
+            .map(|inspected| inspected.modified_at);
+        let current = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || modified_at(&path))
+                .await
+                .unwrap()
+        };
+        if known.is_some() && known == current {
+            continue;
+        }
+
+        let constraints = wine_cask.select_task_constraints(true).await;
+        let provenance = Provenance::load(&tool.internal_name);
+        let Some(inspected) = tokio::task::spawn_blocking(move || {
+            run_constrained(constraints.as_ref(), || inspect_tool(&path, provenance))
+        })
+        .await
+        .unwrap() else {
+            continue;
+        };
+
+        let dirty = inspected
+            .local_changes
+            .as_ref()
+            .is_some_and(|local_changes| !local_changes.is_empty());
+        let progress = {
+            let mut app_state = wine_cask.app_state.lock().await;
+            for installed in &mut app_state.installed_compatibility_tools {
+                if installed.internal_name == tool.internal_name {
+                    installed.inspection = Some(inspected.inspection.clone());
+                    installed.modified_since_install =
+                        inspected.local_changes.clone().filter(|_| dirty);
+                }
+            }
+            let tool_inspector = &mut app_state.tool_inspector;
+            tool_inspector
+                .inspected
+                .insert(tool.internal_name.clone(), inspected.clone());
+            if let Err(err) = tool_inspector.save() {
+                warn!("Failed to save tool inspections: {}", err);
+            }
+            let progress = InspectionProgress {
+                inspected: tool_inspector.inspected.len(),
+                ..progress
+            };
+            app_state.inspection_progress = Some(progress);
+            progress
+        };
+        broadcast_to_peers(
+            peer_map,
+            &Request {
+                internal_name: Some(tool.internal_name.clone()),
+                tool_inspection: Some(inspected.inspection),
+                inspection_progress: Some(progress),
+                ..Request::new(RequestType::ToolInspected)
+            },
+        )
+        .await;
+        // The dirty flag only reaches the frontend with the tool entries
+        if dirty {
+            wine_cask.broadcast_app_state(peer_map).await;
+        }
+    }
+
+    info!("Inspected every tool");
+    wine_cask.app_state.lock().await.inspection_progress = Some(InspectionProgress {
+        inspected: total,
+        total,
+        paused: false,
+    });
+    broadcast_to_peers(peer_map, &Request::new(RequestType::InspectionCompleted)).await;
+    wine_cask.broadcast_app_state(peer_map).await;
+}
+
+impl WineCask {
+    /// Pausing stops the inspection after the tool being inspected, and stays in effect across
+    /// restarts for users who want no background I/O at all.
+    pub async fn set_inspection_paused(&self, peer_map: &PeerMap, paused: bool) {
+        {
+            let mut app_state = self.app_state.lock().await;
+            app_state.tool_inspector.paused = paused;
+            if let Err(err) = app_state.tool_inspector.save() {
+                warn!("Failed to save tool inspections: {}", err);
+            }
+            if let Some(progress) = &mut app_state.inspection_progress {
+                progress.paused = paused;
+            }
+        }
+        info!(
+            "Tool inspection {}",
+            if paused { "paused" } else { "resumed" }
+        );
+        self.broadcast_app_state(peer_map).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wine_cask::generate_compatibility_tool_vdf;
+    use crate::wine_cask::outbox::Outbox;
+    use crate::wine_cask::permissions::PermissionSet;
+    use crate::wine_cask::startup::Startup;
+    use crate::wine_cask::subscriptions::SubscriptionSet;
+    use crate::{Peer, PeerAddr};
+    use futures_util::FutureExt;
+    use std::collections::HashSet;
+    use tempfile::tempdir;
+    use tokio::sync::Mutex;
+
+    const TOOLS: usize = 30;
+
+    fn create_tools(root: &Path) {
+        fs::create_dir_all(root.join("config")).unwrap();
+        fs::create_dir_all(root.join("steamapps")).unwrap();
+        fs::write(
+            root.join("config/config.vdf"),
+            "\"InstallConfigStore\"\n{\n}\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("steamapps/libraryfolders.vdf"),
+            format!(
+                "\"libraryfolders\"\n{{\n\"0\"\n{{\n\"path\" \"{}\"\n}}\n}}\n",
+                root.display()
+            ),
+        )
+        .unwrap();
+        for index in 0..TOOLS {
+            let name = format!("Inspection-Test-{}", index);
+            let tool = root.join("compatibilitytools.d").join(&name);
+            fs::create_dir_all(tool.join("files/lib/wine/dxvk")).unwrap();
+            fs::write(tool.join("files/lib/wine/dxvk/d3d11.dll"), vec![0; 100]).unwrap();
+            generate_compatibility_tool_vdf(tool.join("compatibilitytool.vdf"), &name, &name);
+        }
+    }
+
+    fn connect(peer_map: &PeerMap) -> Arc<Outbox> {
+        let outbox = Arc::new(Outbox::default());
+        peer_map.try_lock().unwrap().insert(
+            PeerAddr::Tcp("127.0.0.1:8887".parse().unwrap()),
+            Peer {
+                outbox: outbox.clone(),
+                permissions: PermissionSet::all(),
+                subscriptions: SubscriptionSet::default(),
+            },
+        );
+        outbox
+    }
+
+    fn inspected(outbox: &Outbox) -> Vec<Request> {
+        let mut requests = Vec::new();
+        while let Some(Some(message)) = outbox.next().now_or_never() {
+            let request: Request = serde_json::from_str(message.to_text().unwrap()).unwrap();
+            if request.r#type == RequestType::ToolInspected {
+                requests.push(request);
+            }
+        }
+        requests
+    }
+
+    #[tokio::test]
+    async fn test_inspection_streams_results_and_resumes_after_restart() {
+        let steam_root = tempdir().unwrap();
+        let runtime_directory = tempdir().unwrap();
+        create_tools(steam_root.path());
+        let peer_map: PeerMap = Arc::new(Mutex::new(HashMap::new()));
+        let outbox = connect(&peer_map);
+        let steam_directory = steam_root.path().to_path_buf();
+
+        let wine_cask = Startup::new()
+            .initialize(
+                &peer_map,
+                || vec![steam_directory.clone()],
+                runtime_directory.path(),
+            )
+            .await;
+        // The quick listing is complete before anything was inspected
+        {
+            let app_state = wine_cask.app_state.lock().await;
+            assert_eq!(app_state.installed_compatibility_tools.len(), TOOLS);
+            assert!(app_state
+                .installed_compatibility_tools
+                .iter()
+                .all(|tool| tool.inspection.is_none()));
+            assert!(app_state.inspection_progress.is_none());
+        }
+
+        // Pause once a third of the tools came in, as if the user asked for no background I/O
+        let inspection = tokio::spawn({
+            let wine_cask = wine_cask.clone();
+            let peer_map = peer_map.clone();
+            async move { run_tool_inspection(&wine_cask, &peer_map).await }
+        });
+        let mut first_run = Vec::new();
+        while first_run.len() < TOOLS / 3 {
+            tokio::task::yield_now().await;
+            first_run.extend(inspected(&outbox));
+        }
+        wine_cask.set_inspection_paused(&peer_map, true).await;
+        inspection.await.unwrap();
+        first_run.extend(inspected(&outbox));
+        assert!(first_run.len() < TOOLS);
+        for (index, request) in first_run.iter().enumerate() {
+            let progress = request.inspection_progress.unwrap();
+            assert_eq!((progress.inspected, progress.total), (index + 1, TOOLS));
+            let tool = steam_root
+                .path()
+                .join("compatibilitytools.d")
+                .join(request.internal_name.as_ref().unwrap());
+            let inspection = request.tool_inspection.as_ref().unwrap();
+            assert_eq!(
+                inspection.bytes,
+                100 + fs::metadata(tool.join("compatibilitytool.vdf"))
+                    .unwrap()
+                    .len()
+            );
+            assert_eq!(inspection.components, vec!["dxvk".to_string()]);
+            assert!(inspection.adoptable);
+        }
+        // Results land in the tool entries as they are computed
+        let inspected_tools = wine_cask
+            .app_state
+            .lock()
+            .await
+            .installed_compatibility_tools
+            .iter()
+            .filter(|tool| tool.inspection.is_some())
+            .count();
+        assert_eq!(inspected_tools, first_run.len());
+        drop(wine_cask);
+
+        // A restart keeps the inspected tools and the pause
+        let wine_cask = Startup::new()
+            .initialize(
+                &peer_map,
+                || vec![steam_directory.clone()],
+                runtime_directory.path(),
+            )
+            .await;
+        inspected(&outbox);
+        let inspected_tools = wine_cask
+            .app_state
+            .lock()
+            .await
+            .installed_compatibility_tools
+            .iter()
+            .filter(|tool| tool.inspection.is_some())
+            .count();
+        assert_eq!(inspected_tools, first_run.len());
+        run_tool_inspection(&wine_cask, &peer_map).await;
+        assert!(inspected(&outbox).is_empty());
+
+        wine_cask.set_inspection_paused(&peer_map, false).await;
+        run_tool_inspection(&wine_cask, &peer_map).await;
+        let second_run = inspected(&outbox);
+        assert_eq!(first_run.len() + second_run.len(), TOOLS);
+        let names: HashSet<String> = first_run
+            .iter()
+            .chain(&second_run)
+            .map(|request| request.internal_name.clone().unwrap())
+            .collect();
+        assert_eq!(names.len(), TOOLS);
+        assert_eq!(
+            wine_cask.app_state.lock().await.inspection_progress,
+            Some(InspectionProgress {
+                inspected: TOOLS,
+                total: TOOLS,
+                paused: false
+            })
+        );
+    }
+}
@@ -0,0 +1,528 @@
+use crate::app_id::CompatAppId;
+use crate::wine_cask::activity::ActivitySource;
+use crate::wine_cask::app::WineCask;
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
+use crate::wine_cask::provenance::current_timestamp;
+use crate::wine_cask::running_games::mapping_affects;
+use crate::written_by::stamped;
+use crate::PeerMap;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::{fmt, fs, io};
+
+/// Number of entries kept on disk, older entries are dropped first.
+const MAX_ENTRIES: usize = 50;
+
+/// A change made through the plugin, along with the value it replaced.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum UndoOperation {
+    SetMapping {
+        app_id: CompatAppId,
+        /// `None` when the app had no mapping before.
+        previous: Option<String>,
+        applied: String,
+    },
+    ClearMapping {
+        app_id: CompatAppId,
+        previous: String,
+    },
+    /// Changed the tool used for every app without a mapping of its own.
+    SetDefaultTool {
+        previous: Option<String>,
+        applied: String,
+    },
+}
+
+impl UndoOperation {
+    /// Describes a mapping change, `None` if it doesn't change anything.
+    pub fn new(
+        app_id: CompatAppId,
+        previous: Option<String>,
+        applied: Option<String>,
+    ) -> Option<UndoOperation> {
+        if previous == applied {
+            return None;
+        }
+        Some(match applied {
+            Some(applied) if app_id == CompatAppId::DEFAULT => {
+                UndoOperation::SetDefaultTool { previous, applied }
+            }
+            Some(applied) => UndoOperation::SetMapping {
+                app_id,
+                previous,
+                applied,
+            },
+            None => UndoOperation::ClearMapping {
+                app_id,
+                previous: previous?,
+            },
+        })
+    }
+
+    pub fn target(&self) -> CompatAppId {
+        match self {
+            UndoOperation::SetMapping { app_id, .. }
+            | UndoOperation::ClearMapping { app_id, .. } => *app_id,
+            UndoOperation::SetDefaultTool { .. } => CompatAppId::DEFAULT,
+        }
+    }
+
+    pub fn previous(&self) -> Option<&str> {
+        match self {
+            UndoOperation::SetMapping { previous, .. }
+            | UndoOperation::SetDefaultTool { previous, .. } => previous.as_deref(),
+            UndoOperation::ClearMapping { previous, .. } => Some(previous),
+        }
+    }
+
+    /// The mapping the target had right after the change.
+    pub fn applied(&self) -> Option<&str> {
+        match self {
+            UndoOperation::SetMapping { applied, .. }
+            | UndoOperation::SetDefaultTool { applied, .. } => Some(applied),
+            UndoOperation::ClearMapping { .. } => None,
+        }
+    }
+}
+
+impl Display for UndoOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            UndoOperation::SetMapping {
+                app_id, applied, ..
+            } => write!(f, "mapping {} to {}", app_id, applied),
+            UndoOperation::ClearMapping { app_id, .. } => {
+                write!(f, "clearing the mapping of {}", app_id)
+            }
+            UndoOperation::SetDefaultTool { applied, .. } => {
+                write!(f, "setting the default tool to {}", applied)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct UndoEntry {
+    pub id: u64,
+    pub timestamp: u64,
+    pub operation: UndoOperation,
+    /// Set once the target was changed outside the plugin, undoing would overwrite that change.
+    pub invalidated: bool,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum UndoError {
+    Empty,
+    /// The entry was dropped from the stack without being reverted.
+    ChangedExternally(UndoEntry),
+    /// Reverting failed, the entry is kept.
+    Failed(UndoEntry, String),
+}
+
+impl Display for UndoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            UndoError::Empty => write!(f, "Nothing to undo"),
+            UndoError::ChangedExternally(entry) => write!(
+                f,
+                "Can't undo {}, it was changed outside the plugin since",
+                entry.operation
+            ),
+            UndoError::Failed(entry, err) => {
+                write!(f, "Failed to undo {}: {}", entry.operation, err)
+            }
+        }
+    }
+}
+
+/// Persisted stack of changes made through the plugin, newest last.
+#[derive(Serialize, Deserialize, Default)]
+pub struct UndoStack {
+    next_id: u64,
+    entries: Vec<UndoEntry>,
+    #[serde(skip)]
+    file: PathBuf,
+}
+
+impl UndoStack {
+    pub fn load(file: PathBuf) -> UndoStack {
+        let undo_stack = fs::read_to_string(&file)
+            .ok()
+            .and_then(|string| serde_json::from_str(&string).ok())
+            .unwrap_or_default();
+        UndoStack { file, ..undo_stack }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            &self.file,
+            serde_json::to_string(&stamped(self, "undo_stack", &self.file))?,
+        )
+    }
+
+    pub fn push(&mut self, operation: UndoOperation, timestamp: u64) {
+        self.entries.push(UndoEntry {
+            id: self.next_id,
+            timestamp,
+            operation,
+            invalidated: false,
+        });
+        self.next_id += 1;
+        if self.entries.len() > MAX_ENTRIES {
+            let excess = self.entries.len() - MAX_ENTRIES;
+            self.entries.drain(..excess);
+        }
+    }
+
+    /// Returns the entries, newest first.
+    pub fn entries(&self) -> Vec<UndoEntry> {
+        self.entries.iter().rev().cloned().collect()
+    }
+
+    /// Invalidates every entry of a target whose mapping no longer is what the plugin last set it
+    /// to, returns whether any entry was invalidated.
+    pub fn invalidate_external_changes(
+        &mut self,
+        mappings: &BTreeMap<CompatAppId, String>,
+    ) -> bool {
+        // Older entries of a target were overwritten by the newest one, only it shows what we wrote
+        let mut seen = HashSet::new();
+        let changed_targets: HashSet<CompatAppId> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| seen.insert(entry.operation.target()))
+            .filter(|entry| {
+                !entry.invalidated
+                    && mappings.get(&entry.operation.target()).map(String::as_str)
+                        != entry.operation.applied()
+            })
+            .map(|entry| entry.operation.target())
+            .collect();
+        let mut invalidated = false;
+        for entry in &mut self.entries {
+            if changed_targets.contains(&entry.operation.target()) && !entry.invalidated {
+                entry.invalidated = true;
+                invalidated = true;
+            }
+        }
+        invalidated
+    }
+
+    /// Reverts the newest entry through `apply`, which sets a target to the given mapping.
+    pub fn undo_last(
+        &mut self,
+        apply: impl FnOnce(CompatAppId, Option<&str>) -> Result<(), String>,
+    ) -> Result<UndoEntry, UndoError> {
+        let entry = self.entries.last().cloned().ok_or(UndoError::Empty)?;
+        if entry.invalidated {
+            self.entries.pop();
+            return Err(UndoError::ChangedExternally(entry));
+        }
+        apply(entry.operation.target(), entry.operation.previous())
+            .map_err(|err| UndoError::Failed(entry.clone(), err))?;
+        self.entries.pop();
+        Ok(entry)
+    }
+}
+
+impl WineCask {
+    pub async fn record_undo_operation(&self, operation: UndoOperation) {
+        let mut undo_stack = self.undo_stack.lock().await;
+        undo_stack.push(operation, current_timestamp());
+        if let Err(err) = undo_stack.save() {
+            warn!("Failed to save undo stack: {}", err);
+        }
+    }
+
+    /// Compares the undo stack against the current mappings, called whenever they are re-read.
+    pub async fn invalidate_undo_entries(&self) {
+        let mappings: BTreeMap<CompatAppId, String> = self
+            .app_state
+            .lock()
+            .await
+            .compatibility_tool_mappings
+            .iter()
+            .map(|mapping| (mapping.app_id, mapping.compatibility_tool.clone()))
+            .collect();
+        let mut undo_stack = self.undo_stack.lock().await;
+        if undo_stack.invalidate_external_changes(&mappings) {
+            if let Err(err) = undo_stack.save() {
+                warn!("Failed to save undo stack: {}", err);
+            }
+        }
+    }
+
+    pub async fn get_undo_stack(&self, peer_map: &PeerMap) {
+        let undo_stack = self.undo_stack.lock().await.entries();
+        self.broadcast_undo_stack(peer_map, undo_stack).await;
+    }
+
+    pub async fn undo_last(&self, peer_map: &PeerMap) {
+        // Catch changes made in Steam since the mappings were last read
+        self.update_compatibility_tool_mappings(ActivitySource::External)
+            .await;
+
+        let target = self
+            .undo_stack
+            .lock()
+            .await
+            .entries()
+            .first()
+            .map(|entry| entry.operation.target());
+        if let Some(target) = target {
+            let mappings: HashMap<CompatAppId, String> = self
+                .app_state
+                .lock()
+                .await
+                .compatibility_tool_mappings
+                .iter()
+                .map(|mapping| (mapping.app_id, mapping.compatibility_tool.clone()))
+                .collect();
+            if let Err(app_error) = self
+                .refuse_while_running(peer_map, "undo the last change", |app_id| {
+                    mapping_affects(&mappings, target, app_id)
+                })
+                .await
+            {
+                error!("{}", app_error);
+                self.broadcast_app_error(peer_map, app_error).await;
+                return;
+            }
+        }
+
+        let force = self
+            .app_state
+            .lock()
+            .await
+            .settings
+            .write_while_steam_running;
+        let mut undo_stack = self.undo_stack.lock().await;
+        let result = undo_stack.undo_last(|app_id, compatibility_tool| {
+            match compatibility_tool {
+                Some(compatibility_tool) => self.steam_util.set_compatibility_tool_mapping(
+                    app_id,
+                    compatibility_tool,
+                    force,
+                ),
+                None => self
+                    .steam_util
+                    .remove_compatibility_tool_mapping(app_id, force),
+            }
+            .map(|_| {
+                self.own_writes
+                    .record(&self.steam_util.get_config_vdf_path())
+            })
+            .map_err(|err| err.to_string())
+        });
+        if let Err(err) = undo_stack.save() {
+            warn!("Failed to save undo stack: {}", err);
+        }
+        let entries = undo_stack.entries();
+        drop(undo_stack);
+
+        match result {
+            Ok(entry) => {
+                let message = format!("Undid {}, restart Steam to apply", entry.operation);
+                info!("{}", message);
+                self.app_state.lock().await.restart_required = true;
+                self.update_compatibility_tool_mappings(ActivitySource::Task)
+                    .await;
+                self.broadcast_app_notification(peer_map, &message, vec![entry.operation.target()])
+                    .await;
+            }
+            Err(err) => {
+                let code = match err {
+                    UndoError::Empty | UndoError::ChangedExternally(_) => AppErrorCode::NotFound,
+                    UndoError::Failed(..) => AppErrorCode::Internal,
+                };
+                let app_error = AppError::new(code, err.to_string());
+                error!("{}", app_error);
+                self.broadcast_app_error(peer_map, app_error).await;
+            }
+        }
+        self.broadcast_undo_stack(peer_map, entries).await;
+        self.broadcast_app_state(peer_map).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_id::AppId;
+    use tempfile::tempdir;
+
+    fn app(app_id: u32) -> CompatAppId {
+        CompatAppId::from(AppId::new(app_id).unwrap())
+    }
+
+    // Applies a change to `mappings` and records it, like setting a mapping through the plugin
+    fn change(
+        undo_stack: &mut UndoStack,
+        mappings: &mut BTreeMap<CompatAppId, String>,
+        app_id: CompatAppId,
+        compatibility_tool: Option<&str>,
+    ) {
+        let previous = match compatibility_tool {
+            Some(compatibility_tool) => mappings.insert(app_id, compatibility_tool.to_string()),
+            None => mappings.remove(&app_id),
+        };
+        let operation =
+            UndoOperation::new(app_id, previous, compatibility_tool.map(str::to_string)).unwrap();
+        undo_stack.push(operation, 100);
+    }
+
+    fn undo(
+        undo_stack: &mut UndoStack,
+        mappings: &mut BTreeMap<CompatAppId, String>,
+    ) -> Result<UndoEntry, UndoError> {
+        undo_stack.invalidate_external_changes(mappings);
+        undo_stack.undo_last(|app_id, compatibility_tool| {
+            match compatibility_tool {
+                Some(compatibility_tool) => mappings.insert(app_id, compatibility_tool.to_string()),
+                None => mappings.remove(&app_id),
+            };
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_undo_mapping_change() {
+        let mut undo_stack = UndoStack::default();
+        let mut mappings = BTreeMap::from([(app(1245620), "GE-Proton9-20".to_string())]);
+        change(
+            &mut undo_stack,
+            &mut mappings,
+            app(1245620),
+            Some("GE-Proton9-21"),
+        );
+        change(
+            &mut undo_stack,
+            &mut mappings,
+            app(730),
+            Some("GE-Proton9-21"),
+        );
+
+        let entry = undo(&mut undo_stack, &mut mappings).unwrap();
+        assert_eq!(entry.operation.target(), app(730));
+        assert!(!mappings.contains_key(&app(730)));
+
+        undo(&mut undo_stack, &mut mappings).unwrap();
+        assert_eq!(mappings[&app(1245620)], "GE-Proton9-20");
+        assert_eq!(undo(&mut undo_stack, &mut mappings), Err(UndoError::Empty));
+    }
+
+    #[test]
+    fn test_refuses_to_undo_after_external_modification() {
+        let mut undo_stack = UndoStack::default();
+        let mut mappings = BTreeMap::new();
+        change(
+            &mut undo_stack,
+            &mut mappings,
+            app(730),
+            Some("GE-Proton9-20"),
+        );
+        change(
+            &mut undo_stack,
+            &mut mappings,
+            app(730),
+            Some("GE-Proton9-21"),
+        );
+        change(
+            &mut undo_stack,
+            &mut mappings,
+            app(440),
+            Some("GE-Proton9-21"),
+        );
+        change(&mut undo_stack, &mut mappings, app(440), None);
+
+        // Changed in Steam's own settings, both entries of the app are stale
+        mappings.insert(app(730), "proton_experimental".to_string());
+
+        for _ in 0..2 {
+            let entry = undo(&mut undo_stack, &mut mappings).unwrap();
+            assert_eq!(entry.operation.target(), app(440));
+        }
+        assert!(!mappings.contains_key(&app(440)));
+        for _ in 0..2 {
+            assert!(matches!(
+                undo(&mut undo_stack, &mut mappings),
+                Err(UndoError::ChangedExternally(entry)) if entry.operation.target() == app(730)
+            ));
+        }
+        assert_eq!(mappings[&app(730)], "proton_experimental");
+        assert_eq!(undo(&mut undo_stack, &mut mappings), Err(UndoError::Empty));
+    }
+
+    #[test]
+    fn test_stack_order_across_operations_survives_restarts() {
+        let temp_dir = tempdir().unwrap();
+        let file = temp_dir.path().join("undo_stack.json");
+        let mut undo_stack = UndoStack::load(file.clone());
+        let mut mappings = BTreeMap::from([(app(730), "GE-Proton9-20".to_string())]);
+        change(
+            &mut undo_stack,
+            &mut mappings,
+            CompatAppId::DEFAULT,
+            Some("proton_experimental"),
+        );
+        change(&mut undo_stack, &mut mappings, app(730), None);
+        change(
+            &mut undo_stack,
+            &mut mappings,
+            app(440),
+            Some("GE-Proton9-21"),
+        );
+        undo_stack.save().unwrap();
+
+        let mut undo_stack = UndoStack::load(file);
+        let operations: Vec<UndoOperation> = undo_stack
+            .entries()
+            .into_iter()
+            .map(|entry| entry.operation)
+            .collect();
+        assert_eq!(
+            operations,
+            vec![
+                UndoOperation::SetMapping {
+                    app_id: app(440),
+                    previous: None,
+                    applied: "GE-Proton9-21".to_string(),
+                },
+                UndoOperation::ClearMapping {
+                    app_id: app(730),
+                    previous: "GE-Proton9-20".to_string(),
+                },
+                UndoOperation::SetDefaultTool {
+                    previous: None,
+                    applied: "proton_experimental".to_string(),
+                },
+            ]
+        );
+
+        for _ in 0..3 {
+            undo(&mut undo_stack, &mut mappings).unwrap();
+        }
+        assert_eq!(
+            mappings,
+            BTreeMap::from([(app(730), "GE-Proton9-20".to_string())])
+        );
+
+        // Ids keep increasing and only the newest entries are kept
+        for _ in 0..MAX_ENTRIES + 5 {
+            undo_stack.push(
+                UndoOperation::new(app(730), None, Some("GE-Proton9-21".to_string())).unwrap(),
+                200,
+            );
+        }
+        let entries = undo_stack.entries();
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0].id, (3 + MAX_ENTRIES + 5 - 1) as u64);
+    }
+}
@@ -1,31 +1,114 @@
-use crate::wine_cask::app::WineCask;
+use crate::app_id::CompatAppId;
+use crate::wine_cask::activity::ActivitySource;
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, WineCask};
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
 use crate::wine_cask::flavors::{CompatibilityToolFlavor, SteamCompatibilityTool};
-use crate::wine_cask::recursive_delete_dir_entry;
+use crate::wine_cask::history::{
+    record, HistoryOutcome, HistoryRecord, HistoryTaskType, HistoryTrigger,
+};
+use crate::wine_cask::install_target::{all_installed, user_home};
+use crate::wine_cask::mappings::{MappingChange, MappingChanges};
+use crate::wine_cask::mutation_guard::{
+    delete_dir_guarded, journal_directory, keep_dir_in_trash, trash_dir_guarded,
+};
+use crate::wine_cask::open_files::{find_processes_using, ProcessUsage};
+use crate::wine_cask::running_games::uses_tool;
+use crate::wine_cask::steam_tinker_launch;
 use crate::PeerMap;
-use log::error;
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Uninstall {
     pub flavor: CompatibilityToolFlavor,
     pub steam_compatibility_tool: SteamCompatibilityTool,
+    /// Uninstall even if processes still use the tool, its files are deleted on the next startup,
+    /// or games are still mapped to it.
+    #[serde(default)]
+    pub force: bool,
+    /// Uninstall even if the tool was modified since it was installed.
+    #[serde(default)]
+    pub accept_local_changes_loss: bool,
+    /// Tool the apps still mapped to the uninstalled one are moved to when forced.
+    #[serde(default)]
+    pub replacement: Option<String>,
+    /// Keep the tool in the trash instead of deleting it, for tools an update moved apps away
+    /// from.
+    #[serde(skip)]
+    pub keep_in_trash: bool,
+}
+
+/// Apps, the default tool included, whose mapping refers to one of `internal_names`.
+pub fn mapped_app_ids(
+    mappings: &HashMap<CompatAppId, String>,
+    internal_names: &[String],
+) -> Vec<CompatAppId> {
+    let mut app_ids: Vec<CompatAppId> = mappings
+        .iter()
+        .filter(|(_, mapped)| internal_names.contains(mapped))
+        .map(|(app_id, _)| *app_id)
+        .collect();
+    app_ids.sort();
+    app_ids
 }
 
 impl WineCask {
-    // Asynchronously uninstall a compatibility tool
+    /// Uninstalls a compatibility tool and records how it went in the history. Returns where the
+    /// tool is kept if it was kept in the trash.
     pub async fn uninstall_compatibility_tool(
         &self,
-        steam_compatibility_tool: SteamCompatibilityTool,
+        uninstall: Uninstall,
+        trigger: HistoryTrigger,
+        peer_map: &PeerMap,
+    ) -> Option<PathBuf> {
+        let started = Instant::now();
+        let steam_compatibility_tool = &uninstall.steam_compatibility_tool;
+        let flavor = steam_compatibility_tool.flavor.clone();
+        let version = steam_compatibility_tool
+            .github_release
+            .as_ref()
+            .map(|release| release.tag_name.clone())
+            .unwrap_or_else(|| steam_compatibility_tool.display_name.clone());
+        let (result, trash) = match self.uninstall_tool(uninstall, peer_map).await {
+            Ok(trash) => (Ok(HistoryOutcome::Succeeded), trash),
+            Err(app_error) => {
+                error!("{}", app_error);
+                let error_code = app_error.code;
+                self.broadcast_app_error(peer_map, app_error).await;
+                (Err(Some(error_code)), None)
+            }
+        };
+        record(&HistoryRecord::new(
+            HistoryTaskType::Uninstall,
+            flavor,
+            version,
+            started.elapsed(),
+            result,
+            trigger,
+        ));
+        trash
+    }
+
+    async fn uninstall_tool(
+        &self,
+        uninstall: Uninstall,
         peer_map: &PeerMap,
-    ) {
+    ) -> Result<Option<PathBuf>, AppError> {
+        let Uninstall {
+            steam_compatibility_tool,
+            force,
+            accept_local_changes_loss,
+            replacement,
+            keep_in_trash,
+            ..
+        } = uninstall;
         // Validate that the compatibility tool is installed for security reason we don't want to delete something else.
         // Find the compatibility tool to uninstall
-        let matching_tools: Vec<SteamCompatibilityTool> = self
-            .app_state
-            .lock()
-            .await
-            .installed_compatibility_tools
+        let installed = all_installed(&*self.app_state.lock().await);
+        let matching_tools: Vec<SteamCompatibilityTool> = installed
             .iter()
             .filter(|tool| {
                 tool.path == steam_compatibility_tool.path
@@ -37,40 +120,264 @@ impl WineCask {
 
         // Handle cases when no matching tool is found
         if matching_tools.is_empty() {
-            let error_message = format!(
-                "Compatibility tool not found: {}",
-                steam_compatibility_tool.display_name
-            );
-            error!("{}", error_message);
-            self.broadcast_notification(peer_map, &error_message).await;
-            return;
+            return Err(AppError::new(
+                AppErrorCode::NotFound,
+                format!(
+                    "Compatibility tool not found: {}",
+                    steam_compatibility_tool.display_name
+                ),
+            ));
         }
 
         // Handle cases when multiple matching tools are found
         if matching_tools.len() != 1 {
-            let error_message = format!(
+            return Err(AppError::internal(format!(
                 "Invalid number of matching tools found: {}",
                 matching_tools.len()
-            );
-            error!("{}", error_message);
-            self.broadcast_notification(peer_map, &error_message).await;
-            return;
+            )));
         }
 
         // Get the tool to uninstall (only one at this point)
         let tool_to_uninstall = &matching_tools[0];
 
-        // Uninstall the compatibility tool by deleting its directory
+        if tool_to_uninstall.official {
+            return Err(AppError::new(
+                AppErrorCode::ManagedBySteam,
+                format!(
+                    "{} is installed by Steam, uninstall it from the Steam library",
+                    tool_to_uninstall.display_name
+                ),
+            ));
+        }
+
+        if let Some(refusal) = self
+            .refuse_local_changes_loss(tool_to_uninstall, accept_local_changes_loss)
+            .await
+        {
+            self.broadcast_app_state(peer_map).await;
+            return Err(refusal);
+        }
+
+        // Multi-runner packages declare several tools in one directory, they all go with it
+        let removed_names: Vec<String> = installed
+            .iter()
+            .filter(|tool| tool.path == tool_to_uninstall.path)
+            .map(|tool| tool.internal_name.clone())
+            .collect();
+        if removed_names.len() > 1 {
+            info!(
+                "Uninstalling {} removes {} from Steam",
+                tool_to_uninstall.display_name,
+                removed_names.join(", ")
+            );
+        }
+
+        // Steam falls back to its default for apps mapped to a missing tool, without telling anyone
+        let mappings = self
+            .steam_util
+            .get_compatibility_tools_mappings()
+            .unwrap_or_else(|err| {
+                warn!("Failed to get compatibility tools mappings: {}", err);
+                HashMap::new()
+            });
+        let operation = format!("uninstall {}", tool_to_uninstall.display_name);
+        self.refuse_while_running(peer_map, &operation, |app_id| {
+            uses_tool(&mappings, app_id, &removed_names)
+        })
+        .await?;
+        let app_ids = mapped_app_ids(&mappings, &removed_names);
+        if !app_ids.is_empty() && !force {
+            let app_error = AppError::new(
+                AppErrorCode::ToolInUse,
+                format!(
+                    "{} is still mapped to {} apps",
+                    tool_to_uninstall.display_name,
+                    app_ids.len()
+                ),
+            );
+            let response = Request {
+                internal_name: Some(tool_to_uninstall.internal_name.clone()),
+                app_ids: Some(app_ids),
+                ..Request::new(RequestType::UninstallBlocked)
+            };
+            broadcast_to_peers(peer_map, &response).await;
+            return Err(app_error);
+        }
+        if let Some(replacement) = replacement.filter(|_| !app_ids.is_empty()) {
+            info!(
+                "Moving {} apps from {} to {}",
+                app_ids.len(),
+                tool_to_uninstall.display_name,
+                replacement
+            );
+            let changes = MappingChanges {
+                changes: app_ids
+                    .iter()
+                    .map(|app_id| MappingChange {
+                        app_id: *app_id,
+                        compatibility_tool: Some(replacement.clone()),
+                        clear_shader_cache: false,
+                    })
+                    .collect(),
+                atomic: true,
+            };
+            let results = self
+                .set_compatibility_tool_mappings(peer_map, changes)
+                .await;
+            if results.iter().any(|result| !result.applied) {
+                return Err(AppError::new(
+                    AppErrorCode::ToolInUse,
+                    format!(
+                        "Failed to move apps to {}, keeping {}",
+                        replacement, tool_to_uninstall.display_name
+                    ),
+                ));
+            }
+        }
+
+        // Deleting files Steam or a game still has open can fail halfway, check for users first
         let directory_path = PathBuf::from(&tool_to_uninstall.path);
-        if let Err(e) = recursive_delete_dir_entry(&directory_path) {
-            let error_message = format!("Error during uninstallation: {}", e);
-            error!("{}", error_message);
-            self.broadcast_notification(peer_map, &error_message).await;
-            return;
+        let directory_path_clone = directory_path.clone();
+        let processes: Vec<ProcessUsage> = tokio::task::spawn_blocking(move || {
+            find_processes_using(Path::new("/proc"), &directory_path_clone)
+        })
+        .await
+        .map_err(|err| AppError::internal(err.to_string()))?;
+        let result = if processes.is_empty() && keep_in_trash {
+            keep_dir_in_trash(&directory_path).map(Some)
+        } else if processes.is_empty() {
+            delete_dir_guarded(&journal_directory(), &directory_path).map(|_| None)
+        } else if force {
+            warn!(
+                "{} is in use, deleting it on the next startup",
+                tool_to_uninstall.display_name
+            );
+            trash_dir_guarded(&journal_directory(), &directory_path).map(|_| None)
+        } else {
+            return Err(AppError::new(
+                AppErrorCode::ToolInUse,
+                format!(
+                    "{} is in use by {}",
+                    tool_to_uninstall.display_name,
+                    processes
+                        .iter()
+                        .map(|process| process.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+            ));
+        };
+
+        // Uninstall the compatibility tool by deleting its directory
+        let trash = result.map_err(|err| AppError::from(err).context("Failed to uninstall"))?;
+        self.app_state
+            .lock()
+            .await
+            .install_manifest
+            .forget(&tool_to_uninstall.path);
+
+        // SteamTinkerLaunch links its configuration into the home directory
+        if removed_names
+            .iter()
+            .any(|name| name == steam_tinker_launch::INTERNAL_NAME)
+        {
+            if let Some(home) = user_home() {
+                match steam_tinker_launch::remove_config_symlink(&home) {
+                    Ok(true) => info!("Removed the SteamTinkerLaunch configuration symlink"),
+                    Ok(false) => {}
+                    Err(err) => warn!(
+                        "Failed to remove the SteamTinkerLaunch configuration symlink: {}",
+                        err
+                    ),
+                }
+            }
         }
 
         // Update the app state to reflect the uninstalled tool and broadcast changes
         self.sync_backend_with_installed_compat_tools().await;
+        self.record_tool_activity(ActivitySource::Task).await;
         self.broadcast_app_state(peer_map).await;
+        for removed_name in &removed_names {
+            self.report_dangling_slots(peer_map, removed_name).await;
+        }
+        Ok(trash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_id::AppId;
+    use crate::steam_util::SteamUtil;
+    use std::fs;
+    use tempfile::tempdir;
+
+    const CONFIG_VDF: &str = r#""InstallConfigStore"
+{
+	"Software"
+	{
+		"Valve"
+		{
+			"Steam"
+			{
+				"CompatToolMapping"
+				{
+					"0"
+					{
+						"name"		"GE-Proton9-20"
+						"config"		""
+						"priority"		"75"
+					}
+					"1245620"
+					{
+						"name"		"GE-Proton9-20"
+						"config"		""
+						"priority"		"250"
+					}
+					"292030"
+					{
+						"name"		"proton_8"
+						"config"		""
+						"priority"		"250"
+					}
+					"730"
+					{
+						"name"		"luxtorpeda"
+						"config"		""
+						"priority"		"250"
+					}
+				}
+			}
+		}
+	}
+}
+"#;
+
+    #[test]
+    fn test_mapped_app_ids() {
+        let root = tempdir().unwrap();
+        fs::create_dir_all(root.path().join("config")).unwrap();
+        fs::write(root.path().join("config/config.vdf"), CONFIG_VDF).unwrap();
+        let mappings = SteamUtil::new(root.path().to_path_buf())
+            .get_compatibility_tools_mappings()
+            .unwrap();
+
+        let app_ids = mapped_app_ids(&mappings, &["GE-Proton9-20".to_string()]);
+        assert_eq!(
+            app_ids,
+            [
+                CompatAppId::DEFAULT,
+                CompatAppId::from(AppId::new(1245620).unwrap())
+            ]
+        );
+
+        // Every tool of a multi-runner package counts
+        let app_ids = mapped_app_ids(
+            &mappings,
+            &["luxtorpeda".to_string(), "boxtron".to_string()],
+        );
+        assert_eq!(app_ids, [CompatAppId::from(AppId::new(730).unwrap())]);
+
+        assert!(mapped_app_ids(&mappings, &["GE-Proton9-21".to_string()]).is_empty());
     }
 }
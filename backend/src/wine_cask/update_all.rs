@@ -0,0 +1,596 @@
+use crate::app_id::CompatAppId;
+use crate::github_util::Release;
+use crate::wine_cask::app::{
+    broadcast_to_peers, CompatibilityToolMapping, Request, RequestType, Task, TaskType, WineCask,
+};
+use crate::wine_cask::flavors::{CompatibilityToolFlavor, Flavor, SteamCompatibilityTool};
+use crate::wine_cask::history::{note_rollback, HistoryTrigger};
+use crate::wine_cask::install::Install;
+use crate::wine_cask::install_target::all_installed;
+use crate::wine_cask::mappings::{MappingChange, MappingChanges};
+use crate::wine_cask::provenance::current_timestamp;
+use crate::wine_cask::rollback::{
+    MigratedMapping, ReplacedTool, RollbackPlan, DEFAULT_ROLLBACK_GRACE_DAYS,
+};
+use crate::wine_cask::skipped_releases::{latest_release, skipped_tags, SkippedRelease};
+use crate::wine_cask::uninstall::{mapped_app_ids, Uninstall};
+use crate::PeerMap;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Official Proton releases this many major versions behind the newest one installed are outdated.
+const OUTDATED_OFFICIAL_PROTON_MAJORS: u32 = 2;
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct UpdateAll {
+    /// Uninstall the updated tools once the new release is installed, unless games are still
+    /// mapped to them.
+    #[serde(default)]
+    pub uninstall_superseded: bool,
+    /// Move the apps mapped to the updated tools to the new release and uninstall them, keeping
+    /// them in the trash to roll back to for the grace period.
+    #[serde(default)]
+    pub migrate_mappings: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct QueuedUpdate {
+    pub flavor: CompatibilityToolFlavor,
+    pub tag_name: String,
+    /// Display names of the installed tools the release updates.
+    pub updates: Vec<String>,
+}
+
+/// What updating every installed tool did with each of them.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+pub struct UpdateSummary {
+    pub queued: Vec<QueuedUpdate>,
+    /// Display names of tools whose latest release is already installed or queued.
+    pub current: Vec<String>,
+    /// Display names of tools the user put there themselves, left alone.
+    pub unmanaged: Vec<String>,
+    /// Apps mapped to an official Proton release far behind the newest one installed, Steam
+    /// updates the releases but never moves apps between them.
+    #[serde(default)]
+    pub outdated_official_mappings: Vec<OutdatedOfficialMapping>,
+}
+
+/// An app mapped to an official Proton release far behind the newest one installed.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct OutdatedOfficialMapping {
+    pub app_id: CompatAppId,
+    pub name: String,
+    /// Display name of the release the app is mapped to.
+    pub compatibility_tool: String,
+    /// Display name of the newest official release installed.
+    pub newest: String,
+}
+
+/// The latest release of a flavor along with the installed tools it updates.
+pub struct PlannedUpdate {
+    pub flavor: CompatibilityToolFlavor,
+    pub release: Release,
+    pub superseded: Vec<SteamCompatibilityTool>,
+}
+
+/// Plans installing the latest release of every flavor with an outdated tool installed, the
+/// outdated tools of one flavor share the install.
+pub fn plan_updates(
+    installed: &[SteamCompatibilityTool],
+    flavors: &[Flavor],
+    skipped_releases: &[SkippedRelease],
+    queued: &[Install],
+) -> (Vec<PlannedUpdate>, UpdateSummary) {
+    let mut planned: Vec<PlannedUpdate> = Vec::new();
+    let mut summary = UpdateSummary::default();
+    for tool in installed {
+        // Steam updates its own releases
+        if tool.official {
+            continue;
+        }
+        let flavor = flavors
+            .iter()
+            .find(|flavor| flavor.flavor == tool.flavor)
+            .filter(|_| tool.managed);
+        let Some(flavor) = flavor else {
+            summary.unmanaged.push(tool.display_name.clone());
+            continue;
+        };
+        let skipped_tags = skipped_tags(skipped_releases, &flavor.flavor);
+        let Some(latest) = latest_release(&flavor.releases, &skipped_tags) else {
+            summary.current.push(tool.display_name.clone());
+            continue;
+        };
+        let latest_installed = installed.iter().any(|installed| {
+            installed.flavor == flavor.flavor
+                && installed.version.as_ref() == Some(&latest.tag_name)
+        });
+        let latest_queued = queued.iter().any(|install| {
+            install.flavor == flavor.flavor && install.release.tag_name == latest.tag_name
+        });
+        if latest_installed || latest_queued {
+            summary.current.push(tool.display_name.clone());
+            continue;
+        }
+
+        match planned
+            .iter_mut()
+            .find(|update| update.flavor == flavor.flavor)
+        {
+            // Tools of a multi-runner package share the directory that gets uninstalled
+            Some(update)
+                if update
+                    .superseded
+                    .iter()
+                    .any(|other| other.path == tool.path) => {}
+            Some(update) => update.superseded.push(tool.clone()),
+            None => planned.push(PlannedUpdate {
+                flavor: flavor.flavor.clone(),
+                release: latest.clone(),
+                superseded: vec![tool.clone()],
+            }),
+        }
+    }
+    (planned, summary)
+}
+
+/// Major version of an official Proton release, `None` for Experimental and Hotfix.
+fn official_major_version(tool: &SteamCompatibilityTool) -> Option<u32> {
+    tool.version
+        .as_deref()?
+        .split(['.', '-'])
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Finds the apps mapped to an official Proton release at least
+/// `OUTDATED_OFFICIAL_PROTON_MAJORS` major versions behind the newest one installed.
+pub fn outdated_official_mappings(
+    installed: &[SteamCompatibilityTool],
+    mappings: &[CompatibilityToolMapping],
+) -> Vec<OutdatedOfficialMapping> {
+    let official: Vec<(&SteamCompatibilityTool, u32)> = installed
+        .iter()
+        .filter(|tool| tool.official)
+        .filter_map(|tool| Some((tool, official_major_version(tool)?)))
+        .collect();
+    let Some((newest, newest_major)) = official.iter().max_by_key(|(_, major)| *major) else {
+        return Vec::new();
+    };
+    mappings
+        .iter()
+        .filter_map(|mapping| {
+            let (tool, _) = official.iter().find(|(tool, major)| {
+                tool.internal_name == mapping.compatibility_tool
+                    && major + OUTDATED_OFFICIAL_PROTON_MAJORS <= *newest_major
+            })?;
+            Some(OutdatedOfficialMapping {
+                app_id: mapping.app_id,
+                name: mapping.name.clone(),
+                compatibility_tool: tool.display_name.clone(),
+                newest: newest.display_name.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Apps whose mapping still refers to `tool`.
+fn mapped_apps(mappings: &HashMap<CompatAppId, String>, tool: &SteamCompatibilityTool) -> usize {
+    mappings
+        .values()
+        .filter(|mapped| **mapped == tool.internal_name || **mapped == tool.display_name)
+        .count()
+}
+
+impl WineCask {
+    pub async fn update_all_compatibility_tools(
+        &self,
+        peer_map: &PeerMap,
+        update_all: UpdateAll,
+        trigger: HistoryTrigger,
+    ) {
+        let app_state = self.app_state.lock().await;
+        let queued: Vec<Install> = app_state
+            .task_queue
+            .iter()
+            .filter_map(|task| task.install.clone())
+            .collect();
+        let (planned, mut summary) = plan_updates(
+            &all_installed(&app_state),
+            &app_state.flavors,
+            &app_state.settings.skipped_releases,
+            &queued,
+        );
+        summary.outdated_official_mappings = outdated_official_mappings(
+            &app_state.installed_compatibility_tools,
+            &app_state.compatibility_tool_mappings,
+        );
+        drop(app_state);
+
+        if let Some(outdated) = summary.outdated_official_mappings.first() {
+            let warning_message = format!(
+                "{} apps are mapped to official Proton releases far behind {}, e.g. {} to {}",
+                summary.outdated_official_mappings.len(),
+                outdated.newest,
+                outdated.name,
+                outdated.compatibility_tool
+            );
+            warn!("{}", warning_message);
+            let app_ids = summary
+                .outdated_official_mappings
+                .iter()
+                .map(|outdated| outdated.app_id)
+                .collect();
+            self.broadcast_app_notification(
+                peer_map,
+                &format!("Warning: {}", warning_message),
+                app_ids,
+            )
+            .await;
+        }
+
+        for update in planned {
+            let replaces = if update_all.uninstall_superseded || update_all.migrate_mappings {
+                update
+                    .superseded
+                    .iter()
+                    .map(|tool| tool.internal_name.clone())
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            let task = Task {
+                id: 0,
+                r#type: TaskType::InstallCompatibilityTool,
+                install: Some(Install {
+                    flavor: update.flavor.clone(),
+                    release: update.release.clone(),
+                    ignore_network_cap: false,
+                    ignore_disk_space: false,
+                    background: false,
+                    accept_local_changes_loss: false,
+                    copy_install: false,
+                    replaces,
+                    migrate_mappings: update_all.migrate_mappings,
+                    max_size: None,
+                    local_path: None,
+                    restart_steam: false,
+                    trigger,
+                }),
+                uninstall: None,
+                migrate: None,
+                mapping: None,
+                mappings: None,
+                import: None,
+                direct_install: None,
+                update_all: None,
+                local_file: None,
+                delete_compat_data: None,
+                restore_prefix_backup: None,
+            };
+            // A refused install was already reported
+            if self.add_to_task_queue(task, peer_map).await {
+                summary.queued.push(QueuedUpdate {
+                    flavor: update.flavor,
+                    tag_name: update.release.tag_name,
+                    updates: update
+                        .superseded
+                        .into_iter()
+                        .map(|tool| tool.display_name)
+                        .collect(),
+                });
+            }
+        }
+
+        info!(
+            "Updating all tools: {} releases queued, {} tools current, {} unmanaged",
+            summary.queued.len(),
+            summary.current.len(),
+            summary.unmanaged.len()
+        );
+        let response = Request {
+            update_summary: Some(summary),
+            ..Request::new(RequestType::UpdateSummary)
+        };
+        broadcast_to_peers(peer_map, &response).await;
+    }
+
+    /// Uninstalls the tools an install replaces once it succeeded, keeping those games are still
+    /// mapped to. A migrating install moves the games to the new release first and keeps the
+    /// replaced tools in the trash, noting how to roll back for the install's history record.
+    pub async fn uninstall_superseded(&self, peer_map: &PeerMap, task_id: u64, install: &Install) {
+        let installed = all_installed(&*self.app_state.lock().await);
+        let updated = installed.iter().find(|tool| {
+            tool.flavor == install.flavor
+                && tool.version.as_ref() == Some(&install.release.tag_name)
+        });
+        let Some(updated) = updated else {
+            warn!(
+                "{} isn't installed, keeping the tools it replaces",
+                install.release.tag_name
+            );
+            return;
+        };
+        let mappings = match self.steam_util.get_compatibility_tools_mappings() {
+            Ok(mappings) => mappings,
+            Err(err) => {
+                warn!(
+                    "Failed to get compatibility tools mappings, keeping superseded tools: {}",
+                    err
+                );
+                return;
+            }
+        };
+
+        let mut replaced = Vec::new();
+        for internal_name in &install.replaces {
+            let Some(tool) = installed
+                .iter()
+                .find(|tool| tool.internal_name == *internal_name)
+            else {
+                continue;
+            };
+            let uninstall = Uninstall {
+                flavor: tool.flavor.clone(),
+                steam_compatibility_tool: tool.clone(),
+                force: false,
+                accept_local_changes_loss: false,
+                replacement: None,
+                keep_in_trash: install.migrate_mappings,
+            };
+            if install.migrate_mappings {
+                let Some(migrated) = self
+                    .migrate_mappings(peer_map, tool, updated, &installed, &mappings)
+                    .await
+                else {
+                    continue;
+                };
+                info!(
+                    "Uninstalling {} into the trash, superseded by {}",
+                    tool.display_name, install.release.tag_name
+                );
+                let trash = self
+                    .uninstall_compatibility_tool(uninstall, install.trigger, peer_map)
+                    .await;
+                replaced.push(ReplacedTool::new(tool, trash, migrated));
+                continue;
+            }
+            let mapped_apps = mapped_apps(&mappings, tool);
+            if mapped_apps > 0 {
+                let warning_message = format!(
+                    "{} is still mapped to {} apps, keeping it",
+                    tool.display_name, mapped_apps
+                );
+                warn!("{}", warning_message);
+                self.broadcast_notification(peer_map, &format!("Warning: {}", warning_message))
+                    .await;
+                continue;
+            }
+            info!(
+                "Uninstalling {}, superseded by {}",
+                tool.display_name, install.release.tag_name
+            );
+            self.uninstall_compatibility_tool(uninstall, install.trigger, peer_map)
+                .await;
+        }
+
+        if !replaced.is_empty() {
+            let grace_days = self
+                .app_state
+                .lock()
+                .await
+                .settings
+                .rollback_grace_days
+                .unwrap_or(DEFAULT_ROLLBACK_GRACE_DAYS);
+            note_rollback(
+                task_id,
+                RollbackPlan {
+                    updated: updated.internal_name.clone(),
+                    replaced,
+                    kept_until: current_timestamp() + grace_days * 24 * 60 * 60,
+                },
+            );
+        }
+    }
+
+    /// Moves the apps mapped to `tool`, or another tool of its package, to `updated`. Returns
+    /// the moved apps along with what they were mapped to, `None` if they couldn't be moved.
+    async fn migrate_mappings(
+        &self,
+        peer_map: &PeerMap,
+        tool: &SteamCompatibilityTool,
+        updated: &SteamCompatibilityTool,
+        installed: &[SteamCompatibilityTool],
+        mappings: &HashMap<CompatAppId, String>,
+    ) -> Option<Vec<MigratedMapping>> {
+        let package_names: Vec<String> = installed
+            .iter()
+            .filter(|installed| installed.path == tool.path)
+            .map(|installed| installed.internal_name.clone())
+            .collect();
+        let migrated: Vec<MigratedMapping> = mapped_app_ids(mappings, &package_names)
+            .into_iter()
+            .map(|app_id| MigratedMapping {
+                app_id,
+                previous: mappings[&app_id].clone(),
+            })
+            .collect();
+        if migrated.is_empty() {
+            return Some(migrated);
+        }
+
+        info!(
+            "Moving {} apps from {} to {}",
+            migrated.len(),
+            tool.display_name,
+            updated.display_name
+        );
+        let changes = MappingChanges {
+            changes: migrated
+                .iter()
+                .map(|migrated| MappingChange {
+                    app_id: migrated.app_id,
+                    compatibility_tool: Some(updated.internal_name.clone()),
+                    clear_shader_cache: false,
+                })
+                .collect(),
+            atomic: true,
+        };
+        let results = self
+            .set_compatibility_tool_mappings(peer_map, changes)
+            .await;
+        if results.iter().any(|result| !result.applied) {
+            let warning_message = format!(
+                "Failed to move apps from {} to {}, keeping it",
+                tool.display_name, updated.display_name
+            );
+            warn!("{}", warning_message);
+            self.broadcast_notification(peer_map, &format!("Warning: {}", warning_message))
+                .await;
+            return None;
+        }
+        Some(migrated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_id::AppId;
+    use crate::test_fixtures::{release, tool};
+    use crate::wine_cask::requirements::Requirements;
+
+    fn flavor(flavor: CompatibilityToolFlavor, tag_names: &[&str]) -> Flavor {
+        Flavor {
+            flavor,
+            releases: tag_names.iter().map(|tag_name| release(tag_name)).collect(),
+            requirements: Requirements::default(),
+            requirement_warnings: Vec::new(),
+            latest_release: None,
+            skipped_releases: Vec::new(),
+            stale_since: None,
+        }
+    }
+
+    #[test]
+    fn test_outdated_tools_of_a_flavor_share_an_update() {
+        let installed = [
+            tool(CompatibilityToolFlavor::ProtonGE, "GE-Proton9-20"),
+            tool(CompatibilityToolFlavor::ProtonGE, "GE-Proton9-21"),
+            tool(CompatibilityToolFlavor::Luxtorpeda, "v68.0.0"),
+            tool(CompatibilityToolFlavor::Unknown, "proton-custom"),
+        ];
+        let flavors = [
+            flavor(
+                CompatibilityToolFlavor::ProtonGE,
+                &["GE-Proton9-22", "GE-Proton9-21", "GE-Proton9-20"],
+            ),
+            flavor(CompatibilityToolFlavor::Luxtorpeda, &["v68.0.0"]),
+        ];
+
+        let (planned, summary) = plan_updates(&installed, &flavors, &[], &[]);
+        assert_eq!(planned.len(), 1);
+        assert_eq!(planned[0].release.tag_name, "GE-Proton9-22");
+        let superseded: Vec<&str> = planned[0]
+            .superseded
+            .iter()
+            .map(|tool| tool.internal_name.as_str())
+            .collect();
+        assert_eq!(superseded, ["GE-Proton9-20", "GE-Proton9-21"]);
+        assert_eq!(summary.current, ["v68.0.0"]);
+        assert_eq!(summary.unmanaged, ["proton-custom"]);
+
+        // Skipped releases aren't updated to, queued ones aren't queued twice
+        let skipped = [SkippedRelease {
+            flavor: CompatibilityToolFlavor::ProtonGE,
+            tag_name: "GE-Proton9-22".to_string(),
+        }];
+        let (planned, summary) = plan_updates(&installed, &flavors, &skipped, &[]);
+        assert!(planned.is_empty());
+        assert_eq!(summary.current.len(), 3);
+        let queued = [Install {
+            flavor: CompatibilityToolFlavor::ProtonGE,
+            release: release("GE-Proton9-22"),
+            ignore_network_cap: false,
+            ignore_disk_space: false,
+            background: false,
+            accept_local_changes_loss: false,
+            copy_install: false,
+            replaces: Vec::new(),
+            migrate_mappings: false,
+            max_size: None,
+            local_path: None,
+            restart_steam: false,
+            trigger: HistoryTrigger::Manual,
+        }];
+        assert!(plan_updates(&installed, &flavors, &[], &queued)
+            .0
+            .is_empty());
+    }
+
+    #[test]
+    fn test_apps_on_outdated_official_proton_are_found() {
+        let official = |internal_name: &str, version: &str| SteamCompatibilityTool {
+            path: format!("/steamapps/common/{}", internal_name),
+            display_name: format!("Proton {}", version),
+            version: Some(version.to_string()),
+            official: true,
+            ..tool(CompatibilityToolFlavor::Unknown, internal_name)
+        };
+        let installed = [
+            official("proton_9", "9.0-2"),
+            official("proton_7", "7.0-6"),
+            official("proton_8", "8.0-5"),
+            official("proton_experimental", "experimental-9.0-20240701"),
+            tool(CompatibilityToolFlavor::ProtonGE, "GE-Proton7-55"),
+        ];
+        let mapping = |app_id: u32, compatibility_tool: &str| CompatibilityToolMapping {
+            app_id: CompatAppId::from(AppId::new(app_id).unwrap()),
+            name: app_id.to_string(),
+            compatibility_tool: compatibility_tool.to_string(),
+            unresolved: false,
+            steam_override: None,
+            quick_slots: None,
+        };
+        let mappings = [
+            mapping(1245620, "proton_7"),
+            mapping(292030, "proton_8"),
+            mapping(1091500, "proton_experimental"),
+            mapping(730, "GE-Proton7-55"),
+        ];
+
+        assert_eq!(
+            outdated_official_mappings(&installed, &mappings),
+            [OutdatedOfficialMapping {
+                app_id: CompatAppId::from(AppId::new(1245620).unwrap()),
+                name: "1245620".to_string(),
+                compatibility_tool: "Proton 7.0-6".to_string(),
+                newest: "Proton 9.0-2".to_string(),
+            }]
+        );
+        // Official releases are left to Steam
+        let (planned, summary) = plan_updates(&installed, &[], &[], &[]);
+        assert!(planned.is_empty());
+        assert_eq!(summary.unmanaged, ["GE-Proton7-55"]);
+    }
+
+    #[test]
+    fn test_mapped_tools_are_counted() {
+        let mappings = HashMap::from([
+            (
+                CompatAppId::from(AppId::new(1245620).unwrap()),
+                "GE-Proton9-20".to_string(),
+            ),
+            (
+                CompatAppId::from(AppId::new(292030).unwrap()),
+                "proton_8".to_string(),
+            ),
+        ]);
+        let superseded = tool(CompatibilityToolFlavor::ProtonGE, "GE-Proton9-20");
+        assert_eq!(mapped_apps(&mappings, &superseded), 1);
+        let unused = tool(CompatibilityToolFlavor::ProtonGE, "GE-Proton9-21");
+        assert_eq!(mapped_apps(&mappings, &unused), 0);
+    }
+}
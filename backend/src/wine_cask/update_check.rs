@@ -0,0 +1,241 @@
+use crate::runtime_directory;
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, WineCask};
+use crate::wine_cask::feature_flags::Feature;
+use crate::wine_cask::flavors::CompatibilityToolFlavor;
+use crate::wine_cask::history::HistoryTrigger;
+use crate::wine_cask::install::Install;
+use crate::wine_cask::install_target::all_installed;
+use crate::wine_cask::provenance::current_timestamp;
+use crate::wine_cask::refresh::RefreshScope;
+use crate::wine_cask::settings::Settings;
+use crate::wine_cask::update_all::{plan_updates, PlannedUpdate, UpdateAll};
+use crate::PeerMap;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/// How often the scheduler wakes up to see whether a check is due.
+const UPDATE_CHECK_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Hours between checks if the settings don't say.
+const DEFAULT_UPDATE_CHECK_INTERVAL_HOURS: u64 = 24;
+
+/// An installed tool with a newer release of its flavor out.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct AvailableUpdate {
+    pub flavor: CompatibilityToolFlavor,
+    /// Display name of the installed tool.
+    pub name: String,
+    pub installed_version: String,
+    pub newest_version: String,
+}
+
+/// When the last scheduled check ran, kept so reloading the plugin doesn't check again.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+#[serde(default)]
+pub struct UpdateCheckState {
+    /// Unix timestamp of the last check, `None` if there never was one.
+    pub last_check: Option<u64>,
+}
+
+impl UpdateCheckState {
+    pub fn load() -> UpdateCheckState {
+        let state_file = update_check_file();
+        if !state_file.is_file() {
+            return UpdateCheckState::default();
+        }
+        fs::read_to_string(&state_file)
+            .ok()
+            .and_then(|string| serde_json::from_str(&string).ok())
+            .unwrap_or_else(|| {
+                warn!("Update check file is corrupted, checking as if never checked");
+                UpdateCheckState::default()
+            })
+    }
+
+    pub fn save(&self) {
+        let json = serde_json::to_string(self).unwrap();
+        if let Err(err) = fs::write(update_check_file(), json) {
+            error!("Failed to persist the last update check: {}", err);
+        }
+    }
+}
+
+fn update_check_file() -> PathBuf {
+    runtime_directory().join("update_check.json")
+}
+
+/// Whether the scheduled check is due at `now`, never when update checks are off. A last check
+/// in the future means the clock was turned back, which doesn't postpone checks.
+pub fn update_check_due(settings: &Settings, last_check: Option<u64>, now: u64) -> bool {
+    if !settings.update_checks {
+        return false;
+    }
+    let interval_hours = settings
+        .update_check_interval_hours
+        .unwrap_or(DEFAULT_UPDATE_CHECK_INTERVAL_HOURS)
+        .max(1);
+    match last_check {
+        Some(last_check) if last_check <= now => now - last_check >= interval_hours * 60 * 60,
+        _ => true,
+    }
+}
+
+/// One entry per installed tool an update was planned for.
+pub fn available_updates(planned: &[PlannedUpdate]) -> Vec<AvailableUpdate> {
+    planned
+        .iter()
+        .flat_map(|update| {
+            update.superseded.iter().map(|tool| AvailableUpdate {
+                flavor: update.flavor.clone(),
+                name: tool.display_name.clone(),
+                installed_version: tool.version.clone().unwrap_or_default(),
+                newest_version: update.release.tag_name.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Checks for updates of the installed tools whenever a check is due, for as long as the backend
+/// runs.
+pub async fn run_update_checks(wine_cask: Arc<WineCask>, peer_map: PeerMap) {
+    let mut update_check_state = UpdateCheckState::load();
+    let mut interval = tokio::time::interval(UPDATE_CHECK_POLL_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let now = current_timestamp();
+        let app_state = wine_cask.app_state.lock().await;
+        if !update_check_due(&app_state.settings, update_check_state.last_check, now) {
+            continue;
+        }
+        // Checks wait for the game to end and for the rate limit to reset
+        if app_state.environment.running_game.is_some()
+            || app_state
+                .github_rate_limit_reset
+                .is_some_and(|reset_at| reset_at > now)
+        {
+            continue;
+        }
+        drop(app_state);
+        // Nobody would hear about updates, peers that connect later get the last result
+        if peer_map.lock().await.is_empty() {
+            continue;
+        }
+
+        update_check_state.last_check = Some(now);
+        update_check_state.save();
+        wine_cask.check_for_updates(&peer_map).await;
+    }
+}
+
+impl WineCask {
+    /// Revalidates the releases of every flavor, then lets the peers know which installed tools
+    /// have a newer release, or queues those releases if the settings say so.
+    pub async fn check_for_updates(&self, peer_map: &PeerMap) {
+        self.refresh(peer_map, RefreshScope::Releases, None, None)
+            .await;
+
+        let mut app_state = self.app_state.lock().await;
+        let queued: Vec<Install> = app_state
+            .task_queue
+            .iter()
+            .filter_map(|task| task.install.clone())
+            .collect();
+        let (planned, _) = plan_updates(
+            &all_installed(&app_state),
+            &app_state.flavors,
+            &app_state.settings.skipped_releases,
+            &queued,
+        );
+        let updates = available_updates(&planned);
+        // Queuing is updating all tools, which the feature flag may have turned off
+        let auto_queue = app_state.settings.auto_queue_updates
+            && app_state
+                .settings
+                .feature_flags
+                .is_enabled(Feature::AutoUpdate);
+        app_state.available_updates = updates.clone();
+        drop(app_state);
+        info!("Update check found {} updates", updates.len());
+        self.broadcast_app_state(peer_map).await;
+        if updates.is_empty() {
+            return;
+        }
+
+        if auto_queue {
+            self.update_all_compatibility_tools(
+                peer_map,
+                UpdateAll::default(),
+                HistoryTrigger::AutoUpdate,
+            )
+            .await;
+        } else {
+            let response = Request {
+                available_updates: Some(updates),
+                ..Request::new(RequestType::UpdatesAvailable)
+            };
+            broadcast_to_peers(peer_map, &response).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{release, tool};
+
+    const HOUR: u64 = 60 * 60;
+
+    #[test]
+    fn test_checks_are_due_once_per_interval() {
+        let now = 1_750_000_000;
+        let mut settings = Settings::default();
+        assert!(!update_check_due(&settings, None, now));
+
+        settings.update_checks = true;
+        assert!(update_check_due(&settings, None, now));
+        assert!(!update_check_due(&settings, Some(now - 23 * HOUR), now));
+        assert!(update_check_due(&settings, Some(now - 24 * HOUR), now));
+        // The clock was turned back since the last check
+        assert!(update_check_due(&settings, Some(now + HOUR), now));
+
+        settings.update_check_interval_hours = Some(6);
+        assert!(update_check_due(&settings, Some(now - 6 * HOUR), now));
+        settings.update_check_interval_hours = Some(0);
+        assert!(!update_check_due(&settings, Some(now - HOUR / 2), now));
+    }
+
+    #[test]
+    fn test_every_outdated_tool_is_listed() {
+        let planned = [PlannedUpdate {
+            flavor: CompatibilityToolFlavor::ProtonGE,
+            release: release("GE-Proton9-22"),
+            superseded: vec![
+                tool(CompatibilityToolFlavor::ProtonGE, "GE-Proton9-20"),
+                tool(CompatibilityToolFlavor::ProtonGE, "GE-Proton9-21"),
+            ],
+        }];
+        assert_eq!(
+            available_updates(&planned),
+            [
+                AvailableUpdate {
+                    flavor: CompatibilityToolFlavor::ProtonGE,
+                    name: "GE-Proton9-20".to_string(),
+                    installed_version: "GE-Proton9-20".to_string(),
+                    newest_version: "GE-Proton9-22".to_string(),
+                },
+                AvailableUpdate {
+                    flavor: CompatibilityToolFlavor::ProtonGE,
+                    name: "GE-Proton9-21".to_string(),
+                    installed_version: "GE-Proton9-21".to_string(),
+                    newest_version: "GE-Proton9-22".to_string(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,728 @@
+use crate::wine_cask::app::Request;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Request types known to this backend, listed in `validation_error`s for unknown types.
+pub const REQUEST_TYPES: [&str; 71] = [
+    "RequestState",
+    "UpdateState",
+    "Notification",
+    "Task",
+    "UpdateSettings",
+    "GetToolProvenance",
+    "ToolProvenance",
+    "VerifyInstalledTool",
+    "Verification",
+    "GetActivity",
+    "Activity",
+    "GetStorageBreakdown",
+    "StorageBreakdown",
+    "ClearShaderCache",
+    "StartupProgress",
+    "ValidationError",
+    "GetUndoStack",
+    "UndoStack",
+    "UndoLast",
+    "Refresh",
+    "RefreshCompleted",
+    "GetMutationLog",
+    "MutationLog",
+    "SkipRelease",
+    "UnskipRelease",
+    "PrioritizePrefixes",
+    "CancelPrefixScan",
+    "PrefixScanned",
+    "PrefixScanCompleted",
+    "CheckLocalChanges",
+    "Permissions",
+    "SetQuickSlots",
+    "SwitchQuickSlot",
+    "CreatePlan",
+    "Plan",
+    "ExecutePlan",
+    "PlanExecuted",
+    "PauseInspection",
+    "ResumeInspection",
+    "ToolInspected",
+    "InspectionCompleted",
+    "SelectSteamInstallation",
+    "CancelTask",
+    "TaskCancelled",
+    "UpdateSummary",
+    "UninstallBlocked",
+    "ForceRefresh",
+    "InsufficientDiskSpace",
+    "UpdatesAvailable",
+    "AdoptTool",
+    "ListOrphanedCompatData",
+    "OrphanedCompatData",
+    "GetPrefixSizes",
+    "PrefixSizes",
+    "RequestSnapshot",
+    "StateDelta",
+    "Error",
+    "Authenticate",
+    "Subscribe",
+    "Subscribed",
+    "Hello",
+    "GetInstalledApps",
+    "InstalledApps",
+    "GetShortcutDetails",
+    "ShortcutDetails",
+    "SetShortcutLaunchOptions",
+    "GetHistory",
+    "History",
+    "ResolveRestoreConflict",
+    "CreateVirtualTool",
+    "RollbackUpdate",
+];
+
+pub const TASK_TYPES: [&str; 13] = [
+    "CheckForFlavorUpdates",
+    "InstallCompatibilityTool",
+    "CancelCompatibilityToolInstall",
+    "UninstallCompatibilityTool",
+    "MigrateCompatibilityTools",
+    "SetCompatibilityToolMapping",
+    "SetCompatibilityToolMappings",
+    "ImportCompatibilityToolMappings",
+    "InstallFromUrl",
+    "UpdateAllCompatibilityTools",
+    "InstallFromLocalFile",
+    "DeleteCompatData",
+    "RestorePrefixBackup",
+];
+
+/// Expected shape of a JSON value, the subset of JSON schema the protocol needs.
+pub enum Schema {
+    String,
+    /// Non-negative integer.
+    Integer,
+    Boolean,
+    Enum(&'static [&'static str]),
+    Array(&'static Schema),
+    Object(&'static [Field]),
+}
+
+pub struct Field {
+    pub name: &'static str,
+    pub schema: &'static Schema,
+    /// Optional fields may be missing or `null`.
+    pub required: bool,
+}
+
+const fn required(name: &'static str, schema: &'static Schema) -> Field {
+    Field {
+        name,
+        schema,
+        required: true,
+    }
+}
+
+const fn optional(name: &'static str, schema: &'static Schema) -> Field {
+    Field {
+        name,
+        schema,
+        required: false,
+    }
+}
+
+const FLAVOR: Schema = Schema::Enum(&[
+    "Unknown",
+    "ProtonGE",
+    "SteamTinkerLaunch",
+    "Luxtorpeda",
+    "Boxtron",
+    "Roberta",
+    "ProtonTkg",
+    "WineGE",
+    "Dxvk",
+    "Vkd3dProton",
+]);
+
+const ASSET: Schema = Schema::Object(&[
+    required("url", &Schema::String),
+    required("id", &Schema::Integer),
+    required("name", &Schema::String),
+    required("content_type", &Schema::String),
+    required("state", &Schema::String),
+    required("size", &Schema::Integer),
+    required("download_count", &Schema::Integer),
+    required("created_at", &Schema::String),
+    required("updated_at", &Schema::String),
+    required("browser_download_url", &Schema::String),
+]);
+
+const RELEASE: Schema = Schema::Object(&[
+    required("url", &Schema::String),
+    required("id", &Schema::Integer),
+    required("draft", &Schema::Boolean),
+    required("prerelease", &Schema::Boolean),
+    required("name", &Schema::String),
+    required("tag_name", &Schema::String),
+    optional("target_commitish", &Schema::String),
+    required("assets", &Schema::Array(&ASSET)),
+    required("created_at", &Schema::String),
+    required("published_at", &Schema::String),
+    required("tarball_url", &Schema::String),
+    required("body", &Schema::String),
+]);
+
+const INSTALL: Schema = Schema::Object(&[
+    required("flavor", &FLAVOR),
+    required("release", &RELEASE),
+    optional("ignore_network_cap", &Schema::Boolean),
+    optional("ignore_disk_space", &Schema::Boolean),
+    optional("background", &Schema::Boolean),
+    optional("accept_local_changes_loss", &Schema::Boolean),
+    optional("copy_install", &Schema::Boolean),
+    optional("replaces", &Schema::Array(&Schema::String)),
+    optional("migrate_mappings", &Schema::Boolean),
+    optional("restart_steam", &Schema::Boolean),
+]);
+
+const STEAM_COMPATIBILITY_TOOL: Schema = Schema::Object(&[
+    required("path", &Schema::String),
+    required("display_name", &Schema::String),
+    required("internal_name", &Schema::String),
+    required("used_by_games", &Schema::Array(&Schema::String)),
+    required("requires_restart", &Schema::Boolean),
+    required("supports_32bit", &Schema::Boolean),
+    required("flavor", &FLAVOR),
+    optional("github_release", &RELEASE),
+]);
+
+const UNINSTALL: Schema = Schema::Object(&[
+    required("flavor", &FLAVOR),
+    required("steam_compatibility_tool", &STEAM_COMPATIBILITY_TOOL),
+    optional("force", &Schema::Boolean),
+    optional("accept_local_changes_loss", &Schema::Boolean),
+    optional("replacement", &Schema::String),
+]);
+
+const MIGRATE: Schema = Schema::Object(&[
+    required("from_root", &Schema::String),
+    required("tool_names", &Schema::Array(&Schema::String)),
+]);
+
+const MAPPING: Schema = Schema::Object(&[
+    required("app_id", &Schema::Integer),
+    optional("compatibility_tool", &Schema::String),
+    optional("clear_shader_cache", &Schema::Boolean),
+]);
+
+const MAPPINGS: Schema = Schema::Object(&[
+    required("changes", &Schema::Array(&MAPPING)),
+    optional("atomic", &Schema::Boolean),
+]);
+
+const MAPPING_IMPORT: Schema = Schema::Object(&[
+    required("format", &Schema::Enum(&["ProtonUpQt", "Csv"])),
+    required("content", &Schema::String),
+    optional("install_missing", &Schema::Boolean),
+]);
+
+const DIRECT_INSTALL: Schema = Schema::Object(&[
+    required("url", &Schema::String),
+    optional("flavor", &FLAVOR),
+    optional("tag_name", &Schema::String),
+]);
+
+const LOCAL_FILE: Schema = Schema::Object(&[
+    required("path", &Schema::String),
+    optional("flavor", &FLAVOR),
+    optional("tag_name", &Schema::String),
+]);
+
+const DELETE_COMPAT_DATA: Schema = Schema::Object(&[
+    required("confirmation_token", &Schema::Integer),
+    required("app_ids", &Schema::Array(&Schema::Integer)),
+]);
+
+const RESTORE_PREFIX_BACKUP: Schema = Schema::Object(&[
+    required("app_id", &Schema::Integer),
+    required("path", &Schema::String),
+]);
+
+const UPDATE_ALL: Schema = Schema::Object(&[
+    optional("uninstall_superseded", &Schema::Boolean),
+    optional("migrate_mappings", &Schema::Boolean),
+]);
+
+const TASK: Schema = Schema::Object(&[
+    required("type", &Schema::Enum(&TASK_TYPES)),
+    optional("id", &Schema::Integer),
+    optional("install", &INSTALL),
+    optional("uninstall", &UNINSTALL),
+    optional("migrate", &MIGRATE),
+    optional("mapping", &MAPPING),
+    optional("mappings", &MAPPINGS),
+    optional("import", &MAPPING_IMPORT),
+    optional("direct_install", &DIRECT_INSTALL),
+    optional("update_all", &UPDATE_ALL),
+    optional("local_file", &LOCAL_FILE),
+    optional("delete_compat_data", &DELETE_COMPAT_DATA),
+    optional("restore_prefix_backup", &RESTORE_PREFIX_BACKUP),
+]);
+
+const REQUEST_STATE: Schema = Schema::Object(&[required(
+    "available_compat_tools",
+    &Schema::Array(&Schema::Object(&[
+        required("strToolName", &Schema::String),
+        required("strDisplayName", &Schema::String),
+    ])),
+)]);
+
+const REFRESH: Schema = Schema::Object(&[
+    required(
+        "refresh_scope",
+        &Schema::Enum(&[
+            "Tools", "Apps", "Mappings", "Releases", "Prefixes", "Storage",
+        ]),
+    ),
+    optional("flavor", &FLAVOR),
+    optional("request_id", &Schema::String),
+]);
+
+const SKIP_RELEASE: Schema = Schema::Object(&[
+    required("flavor", &FLAVOR),
+    required("tag_name", &Schema::String),
+]);
+
+const SET_QUICK_SLOTS: Schema = Schema::Object(&[
+    required("app_id", &Schema::Integer),
+    required("quick_slots", &Schema::Array(&Schema::String)),
+]);
+
+const SWITCH_QUICK_SLOT: Schema = Schema::Object(&[
+    required("app_id", &Schema::Integer),
+    required("slot", &Schema::Integer),
+]);
+
+const CREATE_PLAN: Schema = Schema::Object(&[required(
+    "plan_kind",
+    &Schema::Enum(&["Cleanup", "OrphanPrefixes"]),
+)]);
+
+const EXECUTE_PLAN: Schema = Schema::Object(&[
+    required("plan_id", &Schema::Integer),
+    optional("excluded_action_ids", &Schema::Array(&Schema::Integer)),
+]);
+
+const SELECT_STEAM_INSTALLATION: Schema =
+    Schema::Object(&[required("steam_directory", &Schema::String)]);
+
+const CANCEL_TASK: Schema = Schema::Object(&[required("task_id", &Schema::Integer)]);
+
+const ROLLBACK_UPDATE: Schema = Schema::Object(&[required("history_id", &Schema::Integer)]);
+
+const RESOLVE_RESTORE_CONFLICT: Schema = Schema::Object(&[
+    required("task_id", &Schema::Integer),
+    required(
+        "restore_decision",
+        &Schema::Enum(&["KeepLiveSaves", "Overwrite", "Abort"]),
+    ),
+]);
+
+const AUTHENTICATE: Schema = Schema::Object(&[required("session_token", &Schema::String)]);
+
+const PROTOCOL_VERSION: Schema = Schema::Object(&[
+    required("major", &Schema::Integer),
+    required("minor", &Schema::Integer),
+]);
+
+const HELLO: Schema = Schema::Object(&[required("protocol_version", &PROTOCOL_VERSION)]);
+
+const SUBSCRIBE: Schema = Schema::Object(&[required(
+    "subscriptions",
+    &Schema::Array(&Schema::Enum(&[
+        "tasks",
+        "installed_tools",
+        "available_flavors",
+        "settings",
+        "logs",
+    ])),
+)]);
+
+const CREATE_VIRTUAL_TOOL: Schema = Schema::Object(&[required(
+    "virtual_tool",
+    &Schema::Object(&[
+        required("name", &Schema::String),
+        required("base_tool", &Schema::String),
+    ]),
+)]);
+
+const ADOPT_TOOL: Schema = Schema::Object(&[required("internal_name", &Schema::String)]);
+
+const PRIORITIZE_PREFIXES: Schema =
+    Schema::Object(&[required("app_ids", &Schema::Array(&Schema::Integer))]);
+
+/// A field that doesn't match the protocol, `pointer` is a JSON pointer into the request.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ValidationError {
+    pub pointer: String,
+    pub expected: String,
+}
+
+fn escape_pointer_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+fn describe(schema: &Schema) -> String {
+    match schema {
+        Schema::String => "string".to_string(),
+        Schema::Integer => "non-negative integer".to_string(),
+        Schema::Boolean => "boolean".to_string(),
+        Schema::Enum(values) => format!("one of {}", values.join(", ")),
+        Schema::Array(_) => "array".to_string(),
+        Schema::Object(_) => "object".to_string(),
+    }
+}
+
+pub fn validate(value: &Value, schema: &Schema, pointer: &str, errors: &mut Vec<ValidationError>) {
+    let valid = match (schema, value) {
+        (Schema::String, Value::String(_)) | (Schema::Boolean, Value::Bool(_)) => true,
+        (Schema::Integer, value) => value.as_u64().is_some(),
+        (Schema::Enum(values), Value::String(string)) => values.contains(&string.as_str()),
+        (Schema::Array(items), Value::Array(array)) => {
+            for (index, item) in array.iter().enumerate() {
+                validate(item, items, &format!("{}/{}", pointer, index), errors);
+            }
+            true
+        }
+        (Schema::Object(fields), Value::Object(object)) => {
+            for field in fields.iter() {
+                let field_pointer = format!("{}/{}", pointer, escape_pointer_token(field.name));
+                match object.get(field.name) {
+                    None | Some(Value::Null) if !field.required => {}
+                    None => errors.push(ValidationError {
+                        pointer: field_pointer,
+                        expected: format!("required {}", describe(field.schema)),
+                    }),
+                    Some(value) => validate(value, field.schema, &field_pointer, errors),
+                }
+            }
+            true
+        }
+        _ => false,
+    };
+    if !valid {
+        errors.push(ValidationError {
+            pointer: pointer.to_string(),
+            expected: describe(schema),
+        });
+    }
+}
+
+/// Validates a task request, including the payload its task type needs.
+fn validate_task(task: &Value, errors: &mut Vec<ValidationError>) {
+    validate(task, &TASK, "/task", errors);
+    let payload = match task.get("type").and_then(Value::as_str) {
+        Some("InstallCompatibilityTool" | "CancelCompatibilityToolInstall") => {
+            Some(("install", &INSTALL))
+        }
+        Some("UninstallCompatibilityTool") => Some(("uninstall", &UNINSTALL)),
+        Some("MigrateCompatibilityTools") => Some(("migrate", &MIGRATE)),
+        Some("SetCompatibilityToolMapping") => Some(("mapping", &MAPPING)),
+        Some("SetCompatibilityToolMappings") => Some(("mappings", &MAPPINGS)),
+        Some("ImportCompatibilityToolMappings") => Some(("import", &MAPPING_IMPORT)),
+        Some("InstallFromUrl") => Some(("direct_install", &DIRECT_INSTALL)),
+        Some("InstallFromLocalFile") => Some(("local_file", &LOCAL_FILE)),
+        Some("DeleteCompatData") => Some(("delete_compat_data", &DELETE_COMPAT_DATA)),
+        Some("RestorePrefixBackup") => Some(("restore_prefix_backup", &RESTORE_PREFIX_BACKUP)),
+        _ => None,
+    };
+    if let Some((name, schema)) = payload {
+        if task.get(name).filter(|value| !value.is_null()).is_none() {
+            errors.push(ValidationError {
+                pointer: format!("/task/{}", name),
+                expected: format!("required {}", describe(schema)),
+            });
+        }
+    }
+}
+
+/// Type of a request this backend doesn't know, `None` if it knows the type or there is none.
+pub fn unknown_request_type(message: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(message).ok()?;
+    let r#type = value.get("type")?.as_str()?;
+    (!REQUEST_TYPES.contains(&r#type)).then(|| r#type.to_string())
+}
+
+/// Parses a message from the frontend, checking its shape before any handler runs.
+pub fn validate_message(message: &str) -> Result<Request, Vec<ValidationError>> {
+    let value: Value = serde_json::from_str(message).map_err(|err| {
+        vec![ValidationError {
+            pointer: String::new(),
+            expected: format!("JSON object ({})", err),
+        }]
+    })?;
+
+    let mut errors = Vec::new();
+    match value.get("type").and_then(Value::as_str) {
+        Some(r#type) if REQUEST_TYPES.contains(&r#type) => {
+            if r#type == "RequestState" {
+                validate(&value, &REQUEST_STATE, "", &mut errors);
+            }
+            if r#type == "Refresh" {
+                validate(&value, &REFRESH, "", &mut errors);
+            }
+            if r#type == "SkipRelease" || r#type == "UnskipRelease" {
+                validate(&value, &SKIP_RELEASE, "", &mut errors);
+            }
+            if r#type == "PrioritizePrefixes" {
+                validate(&value, &PRIORITIZE_PREFIXES, "", &mut errors);
+            }
+            if r#type == "SetQuickSlots" {
+                validate(&value, &SET_QUICK_SLOTS, "", &mut errors);
+            }
+            if r#type == "SwitchQuickSlot" {
+                validate(&value, &SWITCH_QUICK_SLOT, "", &mut errors);
+            }
+            if r#type == "CreatePlan" {
+                validate(&value, &CREATE_PLAN, "", &mut errors);
+            }
+            if r#type == "ExecutePlan" {
+                validate(&value, &EXECUTE_PLAN, "", &mut errors);
+            }
+            if r#type == "SelectSteamInstallation" {
+                validate(&value, &SELECT_STEAM_INSTALLATION, "", &mut errors);
+            }
+            if r#type == "CancelTask" {
+                validate(&value, &CANCEL_TASK, "", &mut errors);
+            }
+            if r#type == "RollbackUpdate" {
+                validate(&value, &ROLLBACK_UPDATE, "", &mut errors);
+            }
+            if r#type == "ResolveRestoreConflict" {
+                validate(&value, &RESOLVE_RESTORE_CONFLICT, "", &mut errors);
+            }
+            if r#type == "Authenticate" {
+                validate(&value, &AUTHENTICATE, "", &mut errors);
+            }
+            if r#type == "Subscribe" {
+                validate(&value, &SUBSCRIBE, "", &mut errors);
+            }
+            if r#type == "Hello" {
+                validate(&value, &HELLO, "", &mut errors);
+            }
+            if r#type == "CreateVirtualTool" {
+                validate(&value, &CREATE_VIRTUAL_TOOL, "", &mut errors);
+            }
+            if r#type == "AdoptTool" {
+                validate(&value, &ADOPT_TOOL, "", &mut errors);
+            }
+            if r#type == "Task" {
+                match value.get("task") {
+                    Some(task) if !task.is_null() => validate_task(task, &mut errors),
+                    _ => errors.push(ValidationError {
+                        pointer: "/task".to_string(),
+                        expected: format!("required {}", describe(&TASK)),
+                    }),
+                }
+            }
+        }
+        _ => errors.push(ValidationError {
+            pointer: "/type".to_string(),
+            expected: format!("one of {}", REQUEST_TYPES.join(", ")),
+        }),
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // Payloads without a schema are only checked by serde
+    serde_json::from_value(value).map_err(|err| {
+        vec![ValidationError {
+            pointer: String::new(),
+            expected: err.to_string(),
+        }]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wine_cask::app::{RequestType, TaskType};
+    use crate::wine_cask::refresh::RefreshScope;
+    use serde_json::json;
+
+    fn install_request() -> Value {
+        json!({
+            "type": "Task",
+            "task": {
+                "type": "InstallCompatibilityTool",
+                "install": {
+                    "flavor": "ProtonGE",
+                    "release": {
+                        "url": "https://api.github.com/repos/GloriousEggroll/proton-ge-custom/releases/1",
+                        "id": 1,
+                        "draft": false,
+                        "prerelease": false,
+                        "name": "GE-Proton9-21",
+                        "tag_name": "GE-Proton9-21",
+                        "assets": [{
+                            "url": "https://api.github.com/repos/GloriousEggroll/proton-ge-custom/releases/assets/2",
+                            "id": 2,
+                            "name": "GE-Proton9-21.tar.gz",
+                            "content_type": "application/gzip",
+                            "state": "uploaded",
+                            "size": 1024,
+                            "download_count": 0,
+                            "created_at": "2024-12-01T00:00:00Z",
+                            "updated_at": "2024-12-01T00:00:00Z",
+                            "browser_download_url": "https://github.com/GloriousEggroll/proton-ge-custom/releases/download/GE-Proton9-21/GE-Proton9-21.tar.gz"
+                        }],
+                        "created_at": "2024-12-01T00:00:00Z",
+                        "published_at": "2024-12-01T00:00:00Z",
+                        "tarball_url": "https://api.github.com/repos/GloriousEggroll/proton-ge-custom/tarball/GE-Proton9-21",
+                        "body": ""
+                    }
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_valid_install_request_is_parsed() {
+        let request = validate_message(&install_request().to_string()).unwrap();
+        assert_eq!(request.r#type, RequestType::Task);
+        assert!(request.task.unwrap().r#type == TaskType::InstallCompatibilityTool);
+    }
+
+    #[test]
+    fn test_missing_field_is_reported_with_pointer() {
+        let mut request = install_request();
+        request["task"]["install"]["release"]
+            .as_object_mut()
+            .unwrap()
+            .remove("tag_name");
+        request["task"]["install"]["release"]["assets"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("browser_download_url");
+
+        assert_eq!(
+            validate_message(&request.to_string()).err().unwrap(),
+            vec![
+                ValidationError {
+                    pointer: "/task/install/release/tag_name".to_string(),
+                    expected: "required string".to_string(),
+                },
+                ValidationError {
+                    pointer: "/task/install/release/assets/0/browser_download_url".to_string(),
+                    expected: "required string".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wrong_type_is_reported_with_pointer() {
+        let mut request = install_request();
+        request["task"]["install"]["release"]["id"] = json!("1");
+        request["task"]["install"]["flavor"] = json!("Proton");
+
+        assert_eq!(
+            validate_message(&request.to_string()).err().unwrap(),
+            vec![
+                ValidationError {
+                    pointer: "/task/install/flavor".to_string(),
+                    expected: "one of Unknown, ProtonGE, SteamTinkerLaunch, Luxtorpeda, Boxtron, \
+                        Roberta, ProtonTkg, WineGE, Dxvk, Vkd3dProton"
+                        .to_string(),
+                },
+                ValidationError {
+                    pointer: "/task/install/release/id".to_string(),
+                    expected: "non-negative integer".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_payload_and_unknown_types() {
+        let errors =
+            validate_message(r#"{"type": "Task", "task": {"type": "UninstallCompatibilityTool"}}"#)
+                .err()
+                .unwrap();
+        assert_eq!(errors[0].pointer, "/task/uninstall");
+
+        let errors = validate_message(r#"{"type": "InstallTool"}"#)
+            .err()
+            .unwrap();
+        assert_eq!(errors[0].pointer, "/type");
+        assert!(errors[0].expected.contains("RequestState, UpdateState"));
+        assert_eq!(
+            unknown_request_type(r#"{"type": "InstallTool"}"#).as_deref(),
+            Some("InstallTool")
+        );
+        assert_eq!(unknown_request_type(r#"{"type": "Task"}"#), None);
+        assert_eq!(unknown_request_type(r#"{"type": 7}"#), None);
+
+        assert_eq!(validate_message("{").err().unwrap()[0].pointer, "");
+    }
+
+    #[test]
+    fn test_request_state_needs_available_compat_tools() {
+        let request = validate_message(
+            r#"{"type": "RequestState", "available_compat_tools": [{"strToolName": "proton_9", "strDisplayName": "Proton 9.0"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(request.available_compat_tools.unwrap().len(), 1);
+
+        let errors = validate_message(r#"{"type": "RequestState"}"#)
+            .err()
+            .unwrap();
+        assert_eq!(errors[0].pointer, "/available_compat_tools");
+        let errors = validate_message(
+            r#"{"type": "RequestState", "available_compat_tools": [{"strToolName": "proton_9"}]}"#,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            errors[0].pointer,
+            "/available_compat_tools/0/strDisplayName"
+        );
+    }
+
+    #[test]
+    fn test_refresh_needs_a_scope() {
+        let request = validate_message(
+            r#"{"type": "Refresh", "refresh_scope": "Releases", "flavor": "ProtonGE", "request_id": "7"}"#,
+        )
+        .unwrap();
+        assert_eq!(request.refresh_scope, Some(RefreshScope::Releases));
+        assert_eq!(request.request_id.as_deref(), Some("7"));
+
+        let errors = validate_message(r#"{"type": "Refresh"}"#).err().unwrap();
+        assert_eq!(errors[0].pointer, "/refresh_scope");
+        let errors = validate_message(r#"{"type": "Refresh", "refresh_scope": "Icons"}"#)
+            .err()
+            .unwrap();
+        assert_eq!(errors[0].pointer, "/refresh_scope");
+    }
+
+    #[test]
+    fn test_prioritize_prefixes_needs_app_ids() {
+        let request =
+            validate_message(r#"{"type": "PrioritizePrefixes", "app_ids": [1245620, 3228583970]}"#)
+                .unwrap();
+        assert_eq!(request.app_ids.unwrap().len(), 2);
+
+        let errors = validate_message(r#"{"type": "PrioritizePrefixes", "app_ids": [730, "440"]}"#)
+            .err()
+            .unwrap();
+        assert_eq!(errors[0].pointer, "/app_ids/1");
+    }
+
+    #[test]
+    fn test_known_types_match_the_protocol() {
+        for r#type in REQUEST_TYPES {
+            serde_json::from_value::<RequestType>(json!(r#type)).unwrap();
+        }
+        for r#type in TASK_TYPES {
+            serde_json::from_value::<TaskType>(json!(r#type)).unwrap();
+        }
+    }
+}
@@ -1,67 +1,704 @@
-use crate::steam_util::CompatibilityTool;
 use crate::wine_cask::app::WineCask;
+use crate::wine_cask::app_error::{AppError, AppErrorCode};
 use crate::wine_cask::generate_compatibility_tool_vdf;
+use crate::wine_cask::names::{sanitize_name, NameKind};
+use crate::PeerMap;
+use keyvalues_parser::Vdf;
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt::Write;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Version of the generated shims, bump it whenever `generate_shim` changes so existing virtual
+/// tools get the new shim on the next startup.
+pub const SHIM_VERSION: u32 = 2;
+/// Makes a shim print the tool it would run instead of running it.
+pub const SELFTEST_FLAG: &str = "--wine-cellar-selftest";
+/// Kept in the virtual tool's directory, so it goes along when the tool is moved or uninstalled.
+const METADATA_FILE: &str = "wine-cask-metadata.json";
+
+/// What a virtual tool runs and how, everything its shim is generated from.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct VirtualToolSettings {
+    /// Display name, the internal and directory name are derived from it.
+    pub name: String,
+    /// Internal name of the tool it runs.
+    pub base_tool: String,
+    /// Exported before the base tool runs, e.g. `PROTON_ENABLE_NVAPI` set to `1`.
+    #[serde(default)]
+    pub environment: BTreeMap<String, String>,
+}
 
 // Internal only
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct VirtualCompatibilityToolMetadata {
     r#virtual: bool,
+    /// Display name of the tool it runs.
     virtual_original: String,
+    settings: VirtualToolSettings,
+    /// Directory of the tool it runs.
+    base_path: PathBuf,
+    /// Version of the shim last generated, older ones are regenerated on startup.
+    #[serde(default)]
+    shim_version: u32,
+    /// Output of the last self-test, `None` if it passed.
+    #[serde(default)]
+    selftest_failure: Option<String>,
 }
 
-impl WineCask {
-    fn lookup_virtual_compatibility_tool_metadata(
-        &self,
-        compat_tool: &CompatibilityTool,
-    ) -> VirtualCompatibilityToolMetadata {
-        let metadata_file = compat_tool.path.join("wine-cask-metadata.json"); // fixme: Store in runtime data dir instead
-        if metadata_file.exists() && metadata_file.is_file() {
-            let metadata = fs::read_to_string(metadata_file).unwrap();
-            let metadata: VirtualCompatibilityToolMetadata =
-                serde_json::from_str(&metadata).unwrap();
-            metadata
+/// Sent along with a virtual tool in the listing.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct VirtualToolStatus {
+    /// Display name of the tool it runs.
+    pub base_tool: String,
+    /// Output of the failed self-test, `None` if the shim works.
+    pub broken: Option<String>,
+}
+
+/// The parts of a `toolmanifest.vdf` a shim depends on.
+#[derive(PartialEq, Debug)]
+pub struct ToolManifest {
+    /// File the `commandline` runs, e.g. `proton` for `/proton %verb%`.
+    pub entry: String,
+    /// Runtime Steam runs the tool in, e.g. 1628350 for the sniper container.
+    pub require_tool_appid: Option<u32>,
+}
+
+/// Reads the manifest of the tool in `tool`, `None` if it has none or it doesn't run a file of
+/// the tool.
+pub fn read_tool_manifest(tool: &Path) -> Option<ToolManifest> {
+    let manifest = fs::read_to_string(tool.join("toolmanifest.vdf")).ok()?;
+    let vdf = Vdf::parse(&manifest).ok()?;
+    let manifest = vdf.value.get_obj()?;
+    let entry = |key: &str| {
+        manifest
+            .get(key)
+            .and_then(|value| value.first())
+            .and_then(|value| value.get_str())
+    };
+    let entry_file = entry("commandline")?
+        .split_whitespace()
+        .next()?
+        .trim_start_matches('/');
+    if entry_file.is_empty() || entry_file.contains('/') {
+        return None;
+    }
+    Some(ToolManifest {
+        entry: entry_file.to_string(),
+        require_tool_appid: entry("require_tool_appid").and_then(|appid| appid.parse().ok()),
+    })
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Whether `name` can be exported from a shell script.
+pub fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Generates the script that exports the settings and runs the base tool with the arguments
+/// Steam passed, verb included.
+///
+/// Inside Steam's runtime container the tool directories can be mounted at other paths than on
+/// the host, so shims of tools that need a runtime look for the base tool by the paths Steam
+/// passes in `STEAM_COMPAT_TOOL_PATHS`: among them, or next to one of them like in
+/// `compatibilitytools.d` or `steamapps/common`. The path on the host is the fallback.
+pub fn generate_shim(
+    settings: &VirtualToolSettings,
+    base_path: &Path,
+    manifest: &ToolManifest,
+) -> String {
+    let mut shim = String::new();
+    writeln!(shim, "#!/bin/sh").unwrap();
+    writeln!(
+        shim,
+        "# Generated by Wine Cellar for the virtual tool {}, shim version {}",
+        settings.name.replace('\n', " "),
+        SHIM_VERSION
+    )
+    .unwrap();
+    for (name, value) in &settings.environment {
+        writeln!(shim, "export {}={}", name, shell_quote(value)).unwrap();
+    }
+    writeln!(
+        shim,
+        "target={}",
+        shell_quote(&base_path.join(&manifest.entry).to_string_lossy())
+    )
+    .unwrap();
+    if manifest.require_tool_appid.is_some() {
+        let base_directory = base_path.file_name().unwrap_or_default().to_string_lossy();
+        writeln!(shim, "base_directory={}", shell_quote(&base_directory)).unwrap();
+        writeln!(shim, "entry={}", shell_quote(&manifest.entry)).unwrap();
+        shim.push_str(
+            r#"set -f
+old_ifs=$IFS
+IFS=:
+for tool_path in $STEAM_COMPAT_TOOL_PATHS; do
+    if [ "${tool_path##*/}" = "$base_directory" ]; then
+        candidate=$tool_path/$entry
+    else
+        candidate=${tool_path%/*}/$base_directory/$entry
+    fi
+    if [ -x "$candidate" ]; then
+        target=$candidate
+        break
+    fi
+done
+IFS=$old_ifs
+set +f
+"#,
+        );
+    }
+    writeln!(shim, "if [ \"$1\" = {} ]; then", shell_quote(SELFTEST_FLAG)).unwrap();
+    shim.push_str(
+        r#"    if [ -x "$target" ]; then
+        printf '%s\n' "$target"
+        exit 0
+    fi
+    printf '%s is missing or not executable\n' "$target" >&2
+    exit 1
+fi
+exec "$target" "$@"
+"#,
+    );
+    shim
+}
+
+fn read_metadata(tool: &Path) -> Option<VirtualCompatibilityToolMetadata> {
+    let metadata = fs::read_to_string(tool.join(METADATA_FILE)).ok()?;
+    serde_json::from_str(&metadata)
+        .map_err(|err| warn!("Ignoring the metadata of {}: {}", tool.display(), err))
+        .ok()
+        .filter(|metadata: &VirtualCompatibilityToolMetadata| metadata.r#virtual)
+}
+
+fn write_metadata(tool: &Path, metadata: &VirtualCompatibilityToolMetadata) -> io::Result<()> {
+    fs::write(
+        tool.join(METADATA_FILE),
+        serde_json::to_string_pretty(metadata)?,
+    )
+}
+
+/// What the listing shows of the tool in `tool`, `None` unless it's a virtual tool.
+pub fn virtual_tool_status(tool: &Path) -> Option<VirtualToolStatus> {
+    read_metadata(tool).map(|metadata| VirtualToolStatus {
+        base_tool: metadata.virtual_original,
+        broken: metadata.selftest_failure,
+    })
+}
+
+/// Runs the shim in self-test mode with `tool_paths` as `STEAM_COMPAT_TOOL_PATHS`, returning the
+/// tool it resolved or its output if it failed.
+fn run_self_test(shim: &Path, tool_paths: Option<&str>) -> Result<PathBuf, String> {
+    let mut command = Command::new(shim);
+    command
+        .arg(SELFTEST_FLAG)
+        .env_clear()
+        .env("PATH", "/usr/bin:/bin");
+    if let Some(tool_paths) = tool_paths {
+        command.env("STEAM_COMPAT_TOOL_PATHS", tool_paths);
+    }
+    let output = command.output().map_err(|err| err.to_string())?;
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{}{}", stdout, stderr).trim().to_string());
+    }
+    Ok(PathBuf::from(stdout))
+}
+
+/// Checks that the shim of the virtual tool in `tool` resolves the base tool, both on the host
+/// and in a simulated container that mounts the tool directories at another path.
+pub fn self_test(tool: &Path, base_path: &Path, manifest: &ToolManifest) -> Result<(), String> {
+    let shim = tool.join(&manifest.entry);
+    let expected = base_path
+        .join(&manifest.entry)
+        .canonicalize()
+        .map_err(|err| format!("{}: {}", base_path.join(&manifest.entry).display(), err))?;
+    let resolves = |target: &Path| target.canonicalize().is_ok_and(|target| target == expected);
+
+    let target = run_self_test(&shim, None).map_err(|output| format!("On the host: {}", output))?;
+    if !resolves(&target) {
+        return Err(format!(
+            "On the host: runs {} instead of {}",
+            target.display(),
+            expected.display()
+        ));
+    }
+
+    let (Some(tools_directory), Some(tool_name)) = (tool.parent(), tool.file_name()) else {
+        return Ok(());
+    };
+    let mount = std::env::temp_dir().join(format!(
+        "wine-cellar-selftest-{}-{}",
+        std::process::id(),
+        tool_name.to_string_lossy()
+    ));
+    let _ = fs::remove_dir_all(&mount);
+    let result = container_self_test(
+        &shim,
+        &mount,
+        tools_directory,
+        tool_name,
+        base_path,
+        manifest,
+    )
+    .and_then(|target| {
+        if resolves(&target) {
+            Ok(())
         } else {
-            VirtualCompatibilityToolMetadata {
-                r#virtual: false,
-                virtual_original: "".to_string(),
+            Err(format!(
+                "runs {} instead of {}",
+                target.display(),
+                expected.display()
+            ))
+        }
+    });
+    let _ = fs::remove_dir_all(&mount);
+    result.map_err(|output| format!("In the container: {}", output))
+}
+
+/// Runs the shim with the tool directories mounted at `mount`, like the container does, and
+/// Steam passing the tool's own directory as seen in there. Returns the tool it resolved.
+fn container_self_test(
+    shim: &Path,
+    mount: &Path,
+    tools_directory: &Path,
+    tool_name: &OsStr,
+    base_path: &Path,
+    manifest: &ToolManifest,
+) -> Result<PathBuf, String> {
+    fs::create_dir_all(mount).map_err(|err| err.to_string())?;
+    let mounted_tools = mount.join("compatibilitytools.d");
+    symlink(tools_directory, &mounted_tools).map_err(|err| err.to_string())?;
+    let tool_paths = mounted_tools.join(tool_name);
+    let target = run_self_test(shim, Some(&tool_paths.to_string_lossy()))?;
+    let sibling = base_path.parent() == Some(tools_directory);
+    if manifest.require_tool_appid.is_some() && sibling && !target.starts_with(mount) {
+        return Err(format!(
+            "runs {} by its path on the host, which the container may not have",
+            target.display()
+        ));
+    }
+    Ok(target)
+}
+
+/// Writes the shim, manifest and metadata of a virtual tool into `tool` and self-tests it,
+/// returning the metadata written.
+pub fn write_virtual_tool(
+    tool: &Path,
+    settings: VirtualToolSettings,
+    base_path: &Path,
+    base_display_name: &str,
+) -> io::Result<VirtualCompatibilityToolMetadata> {
+    let manifest = read_tool_manifest(base_path).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} has no toolmanifest.vdf running one of its files",
+                base_display_name
+            ),
+        )
+    })?;
+    fs::create_dir_all(tool)?;
+    // Steam reads the runtime the tool needs from it, so the shim runs where the base tool would
+    fs::copy(
+        base_path.join("toolmanifest.vdf"),
+        tool.join("toolmanifest.vdf"),
+    )?;
+    let shim = tool.join(&manifest.entry);
+    fs::write(&shim, generate_shim(&settings, base_path, &manifest))?;
+    fs::set_permissions(&shim, fs::Permissions::from_mode(0o755))?;
+    let metadata = VirtualCompatibilityToolMetadata {
+        r#virtual: true,
+        virtual_original: base_display_name.to_string(),
+        settings,
+        base_path: base_path.to_path_buf(),
+        shim_version: SHIM_VERSION,
+        selftest_failure: self_test(tool, base_path, &manifest).err(),
+    };
+    write_metadata(tool, &metadata)?;
+    Ok(metadata)
+}
+
+/// Regenerates the shims of the virtual tools in `compatibility_tools_directory` that an older
+/// version generated, keeping their settings. Returns the tools regenerated.
+pub fn regenerate_outdated_shims(compatibility_tools_directory: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(compatibility_tools_directory) else {
+        return Vec::new();
+    };
+    let mut regenerated = Vec::new();
+    for tool in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+        let Some(metadata) = read_metadata(&tool) else {
+            continue;
+        };
+        if metadata.shim_version >= SHIM_VERSION {
+            continue;
+        }
+        let result = write_virtual_tool(
+            &tool,
+            metadata.settings.clone(),
+            &metadata.base_path,
+            &metadata.virtual_original,
+        );
+        match result {
+            Ok(written) => {
+                if let Some(failure) = &written.selftest_failure {
+                    warn!(
+                        "Regenerated shim of {} is broken: {}",
+                        tool.display(),
+                        failure
+                    );
+                }
+                regenerated.push(written.settings.name);
+            }
+            Err(err) => {
+                // Listed as broken until the base tool is back and the shim can be regenerated
+                warn!(
+                    "Failed to regenerate the shim of {}: {}",
+                    tool.display(),
+                    err
+                );
+                let broken = VirtualCompatibilityToolMetadata {
+                    selftest_failure: Some(err.to_string()),
+                    ..metadata
+                };
+                if let Err(err) = write_metadata(&tool, &broken) {
+                    error!("Failed to mark {} as broken: {}", tool.display(), err);
+                }
             }
         }
     }
+    regenerated
+}
 
-    fn create_virtual_compatibility_tool(&self, name: &str, virtual_original_path: PathBuf) {
-        let path = self
+impl WineCask {
+    /// Gives the shims of virtual tools from older versions the current generator's fixes.
+    pub fn regenerate_virtual_tool_shims(&self) {
+        let regenerated =
+            regenerate_outdated_shims(&self.steam_util.get_steam_compatibility_tools_directory());
+        if !regenerated.is_empty() {
+            info!("Regenerated the shims of virtual tools {:?}", regenerated);
+        }
+    }
+
+    async fn prepare_virtual_tool(
+        &self,
+        settings: &VirtualToolSettings,
+    ) -> Result<(PathBuf, PathBuf, String), AppError> {
+        if let Some(name) = settings
+            .environment
+            .keys()
+            .find(|name| !is_variable_name(name))
+        {
+            return Err(AppError::new(
+                AppErrorCode::InvalidRequest,
+                format!("{} isn't a valid environment variable name", name),
+            ));
+        }
+        let base = self
+            .app_state
+            .lock()
+            .await
+            .installed_compatibility_tools
+            .iter()
+            .find(|tool| tool.internal_name == settings.base_tool)
+            .map(|tool| (PathBuf::from(&tool.path), tool.display_name.clone()))
+            .ok_or_else(|| {
+                AppError::new(
+                    AppErrorCode::NotFound,
+                    format!("{} isn't installed", settings.base_tool),
+                )
+            })?;
+        let tool = self
             .steam_util
             .get_steam_compatibility_tools_directory()
-            .join(name);
-        if path.exists() {
-            // todo: already exist
+            .join(sanitize_name(NameKind::Internal, &settings.name));
+        if fs::symlink_metadata(&tool).is_ok() {
+            return Err(AppError::new(
+                AppErrorCode::InvalidRequest,
+                format!("{} already exists", tool.display()),
+            ));
         }
+        Ok((tool, base.0, base.1))
+    }
 
-        fs::create_dir(&path).expect("TODO: panic message");
-        fs::copy(virtual_original_path, &path).expect("TODO: panic message");
+    /// Creates a tool that runs another one with its own environment.
+    pub async fn create_virtual_tool(&self, peer_map: &PeerMap, settings: VirtualToolSettings) {
+        let (tool, base_path, base_display_name) = match self.prepare_virtual_tool(&settings).await
+        {
+            Ok(prepared) => prepared,
+            Err(app_error) => {
+                error!("{}", app_error);
+                self.broadcast_app_error(peer_map, app_error).await;
+                return;
+            }
+        };
+        let name = settings.name.clone();
+        let written = {
+            let tool = tool.clone();
+            tokio::task::spawn_blocking(move || {
+                let metadata =
+                    write_virtual_tool(&tool, settings.clone(), &base_path, &base_display_name)?;
+                generate_compatibility_tool_vdf(
+                    tool.join("compatibilitytool.vdf"),
+                    &sanitize_name(NameKind::Internal, &settings.name),
+                    &settings.name,
+                );
+                Ok::<_, io::Error>(metadata)
+            })
+            .await
+            .unwrap_or_else(|err| Err(io::Error::other(err)))
+        };
+        let metadata = match written {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                let app_error = AppError::from(err)
+                    .context(format!("Failed to create the virtual tool {}", name));
+                error!("{}", app_error);
+                // A tool without its shim would only fail once a game runs it
+                if let Err(err) = fs::remove_dir_all(&tool) {
+                    warn!("Failed to remove {}: {}", tool.display(), err);
+                }
+                self.broadcast_app_error(peer_map, app_error).await;
+                return;
+            }
+        };
 
-        // Generate virtual compat tool vdf
-        let compat_tool_vdf_path = path.join("compatibilitytool.vdf");
-        let virtual_original = self
-            .steam_util
-            .read_compatibility_tool_from_vdf_path(&compat_tool_vdf_path)
-            .unwrap()
-            .display_name;
-        generate_compatibility_tool_vdf(compat_tool_vdf_path, &name.replace(' ', "-"), name);
-
-        // Create virtual compat tool metadata
-        let metadata_file = path.join("wine-cask-metadata.json");
-        let metadata = VirtualCompatibilityToolMetadata {
-            r#virtual: true,
-            virtual_original,
+        let message = match &metadata.selftest_failure {
+            Some(failure) => {
+                warn!("Self-test of the virtual tool {} failed: {}", name, failure);
+                format!("Created {}, but its self-test failed: {}", name, failure)
+            }
+            None => {
+                info!(
+                    "Created the virtual tool {} running {}",
+                    name, metadata.virtual_original
+                );
+                format!("Created {}", name)
+            }
         };
+        self.update_compatibility_tools_and_available_flavors()
+            .await;
+        self.broadcast_app_state(peer_map).await;
+        self.broadcast_notification(peer_map, &message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const SNIPER_MANIFEST: &str = r#""manifest"
+{
+  "version" "2"
+  "commandline" "/proton %verb%"
+  "require_tool_appid" "1628350"
+  "use_sessions" "1"
+}"#;
+
+    /// A base tool whose `proton` prints its arguments and `DXVK_HUD`.
+    fn base_tool(tools: &Path, name: &str, manifest: &str) -> PathBuf {
+        let base = tools.join(name);
+        fs::create_dir_all(&base).unwrap();
+        fs::write(base.join("toolmanifest.vdf"), manifest).unwrap();
+        let proton = base.join("proton");
         fs::write(
-            metadata_file,
-            serde_json::to_string_pretty(&metadata).unwrap(),
+            &proton,
+            "#!/bin/sh\nfor arg in \"$@\"; do printf '[%s]' \"$arg\"; done\nprintf ' %s\\n' \"$DXVK_HUD\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&proton, fs::Permissions::from_mode(0o755)).unwrap();
+        base
+    }
+
+    fn settings() -> VirtualToolSettings {
+        VirtualToolSettings {
+            name: "GE-Proton9-20 HUD".to_string(),
+            base_tool: "GE-Proton9-20".to_string(),
+            environment: BTreeMap::from([("DXVK_HUD".to_string(), "fps, 'it''s'".to_string())]),
+        }
+    }
+
+    #[test]
+    fn test_reads_the_runtime_the_tool_needs() {
+        let dir = tempdir().unwrap();
+        let base = base_tool(dir.path(), "GE-Proton9-20", SNIPER_MANIFEST);
+        assert_eq!(
+            read_tool_manifest(&base),
+            Some(ToolManifest {
+                entry: "proton".to_string(),
+                require_tool_appid: Some(1628350),
+            })
+        );
+        fs::write(
+            base.join("toolmanifest.vdf"),
+            r#""manifest" { "commandline" "/proton %verb%" }"#,
+        )
+        .unwrap();
+        assert_eq!(read_tool_manifest(&base).unwrap().require_tool_appid, None);
+        assert!(is_variable_name("PROTON_ENABLE_NVAPI"));
+        assert!(!is_variable_name("1X"));
+        assert!(!is_variable_name("A;B"));
+    }
+
+    #[test]
+    fn test_shim_passes_the_verb_and_arguments_through() {
+        let dir = tempdir().unwrap();
+        let tools = dir.path().join("compatibilitytools.d");
+        let base = base_tool(&tools, "GE-Proton9-20", SNIPER_MANIFEST);
+        let tool = tools.join("GE-Proton9-20-HUD");
+        let metadata = write_virtual_tool(&tool, settings(), &base, "GE-Proton9-20").unwrap();
+        assert_eq!(metadata.selftest_failure, None);
+        assert_eq!(
+            fs::read_to_string(tool.join("toolmanifest.vdf")).unwrap(),
+            SNIPER_MANIFEST
+        );
+
+        // Inside the container the tools are only reachable through the paths Steam passes
+        let mount = dir.path().join("container");
+        fs::create_dir_all(&mount).unwrap();
+        symlink(&tools, mount.join("compatibilitytools.d")).unwrap();
+        let output = Command::new(tool.join("proton"))
+            .args(["waitforexitandrun", "/game/Game.exe", "-dx11", "with space"])
+            .env(
+                "STEAM_COMPAT_TOOL_PATHS",
+                format!(
+                    "{}:/run/SteamLinuxRuntime_sniper",
+                    mount
+                        .join("compatibilitytools.d/GE-Proton9-20-HUD")
+                        .display()
+                ),
+            )
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "[waitforexitandrun][/game/Game.exe][-dx11][with space] fps, 'it''s'\n"
+        );
+        let target = run_self_test(
+            &tool.join("proton"),
+            Some(
+                &mount
+                    .join("compatibilitytools.d/GE-Proton9-20-HUD")
+                    .to_string_lossy(),
+            ),
+        )
+        .unwrap();
+        assert!(target.starts_with(&mount));
+    }
+
+    #[test]
+    fn test_shim_without_a_runtime_runs_the_host_path() {
+        let dir = tempdir().unwrap();
+        let tools = dir.path().join("compatibilitytools.d");
+        let base = base_tool(
+            &tools,
+            "GE-Proton9-20",
+            r#""manifest" { "commandline" "/proton %verb%" }"#,
+        );
+        let tool = tools.join("GE-Proton9-20-HUD");
+        let metadata = write_virtual_tool(&tool, settings(), &base, "GE-Proton9-20").unwrap();
+        assert_eq!(metadata.selftest_failure, None);
+        let shim = fs::read_to_string(tool.join("proton")).unwrap();
+        assert!(!shim.contains("STEAM_COMPAT_TOOL_PATHS"));
+        assert_eq!(
+            run_self_test(&tool.join("proton"), None).unwrap(),
+            base.join("proton")
+        );
+    }
+
+    #[test]
+    fn test_failed_self_test_marks_the_tool_broken() {
+        let dir = tempdir().unwrap();
+        let tools = dir.path().join("compatibilitytools.d");
+        let base = base_tool(&tools, "GE-Proton9-20", SNIPER_MANIFEST);
+        let tool = tools.join("GE-Proton9-20-HUD");
+        write_virtual_tool(&tool, settings(), &base, "GE-Proton9-20").unwrap();
+        assert_eq!(
+            virtual_tool_status(&tool),
+            Some(VirtualToolStatus {
+                base_tool: "GE-Proton9-20".to_string(),
+                broken: None,
+            })
+        );
+
+        fs::set_permissions(base.join("proton"), fs::Permissions::from_mode(0o644)).unwrap();
+        let manifest = read_tool_manifest(&base).unwrap();
+        let failure = self_test(&tool, &base, &manifest).unwrap_err();
+        assert_eq!(
+            failure,
+            format!(
+                "On the host: {} is missing or not executable",
+                base.join("proton").display()
+            )
+        );
+        assert_eq!(virtual_tool_status(&base), None);
+    }
+
+    #[test]
+    fn test_outdated_shims_are_regenerated_with_their_settings() {
+        let dir = tempdir().unwrap();
+        let tools = dir.path().join("compatibilitytools.d");
+        let base = base_tool(&tools, "GE-Proton9-20", SNIPER_MANIFEST);
+        let tool = tools.join("GE-Proton9-20-HUD");
+        let metadata = write_virtual_tool(&tool, settings(), &base, "GE-Proton9-20").unwrap();
+        // A shim from before the container support, exec'ing the host path through python3
+        fs::write(
+            tool.join("proton"),
+            format!(
+                "#!/bin/sh\nexec python3 {} \"$@\"\n",
+                base.join("proton").display()
+            ),
+        )
+        .unwrap();
+        write_metadata(
+            &tool,
+            &VirtualCompatibilityToolMetadata {
+                shim_version: 1,
+                ..metadata
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            regenerate_outdated_shims(&tools),
+            vec!["GE-Proton9-20 HUD".to_string()]
+        );
+        let regenerated = read_metadata(&tool).unwrap();
+        assert_eq!(regenerated.shim_version, SHIM_VERSION);
+        assert_eq!(regenerated.settings, settings());
+        let shim = fs::read_to_string(tool.join("proton")).unwrap();
+        assert!(shim.contains("STEAM_COMPAT_TOOL_PATHS"));
+        assert!(shim.contains("export DXVK_HUD='fps, '\\''it'\\'''\\''s'\\'''"));
+        // Up to date shims are left alone
+        assert_eq!(regenerate_outdated_shims(&tools), Vec::<String>::new());
+
+        // Without its base tool the virtual tool is listed as broken
+        fs::remove_dir_all(&base).unwrap();
+        write_metadata(
+            &tool,
+            &VirtualCompatibilityToolMetadata {
+                shim_version: 1,
+                ..regenerated
+            },
         )
         .unwrap();
+        assert_eq!(regenerate_outdated_shims(&tools), Vec::<String>::new());
+        assert!(virtual_tool_status(&tool).unwrap().broken.is_some());
     }
 }
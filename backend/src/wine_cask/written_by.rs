@@ -0,0 +1,112 @@
+use crate::wine_cask::app::{broadcast_to_peers, Request, RequestType, WineCask};
+use crate::written_by::{mutation_log_file, read_mutation_log};
+use crate::PeerMap;
+
+impl WineCask {
+    pub async fn get_mutation_log(&self, peer_map: &PeerMap, limit: usize) {
+        let mutation_log = mutation_log_file()
+            .map(|log_file| read_mutation_log(&log_file, limit))
+            .unwrap_or_default();
+        broadcast_to_peers(
+            peer_map,
+            &Request {
+                mutation_log: Some(mutation_log),
+                ..Request::new(RequestType::MutationLog)
+            },
+        )
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app_id::{AppId, CompatAppId};
+    use crate::wine_cask::activity::{ActivityChange, ActivityLog, ActivitySource};
+    use crate::wine_cask::flavors::CompatibilityToolFlavor;
+    use crate::wine_cask::provenance::{Provenance, ProvenanceSource};
+    use crate::wine_cask::undo::{UndoOperation, UndoStack};
+    use crate::written_by::{stamped, WrittenBy};
+    use serde_json::Value;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn written_by(file: &Path) -> WrittenBy {
+        let value: Value = serde_json::from_str(&fs::read_to_string(file).unwrap()).unwrap();
+        serde_json::from_value(value["written_by"].clone()).unwrap()
+    }
+
+    #[test]
+    fn test_artifacts_of_an_install_are_stamped() {
+        let temp_dir = tempdir().unwrap();
+        let provenance_file = temp_dir.path().join("provenance/GE-Proton9-20.json");
+        let activity_file = temp_dir.path().join("activity.json");
+        let undo_file = temp_dir.path().join("undo_stack.json");
+
+        // What an install followed by mapping the tool to a game persists
+        let provenance = Provenance {
+            internal_name: "GE-Proton9-20".to_string(),
+            flavor: CompatibilityToolFlavor::ProtonGE,
+            tag_name: "GE-Proton9-20".to_string(),
+            naming_scheme: Some("ge-proton".to_string()),
+            installed_at: 0,
+            source: ProvenanceSource {
+                asset_url: String::new(),
+                asset_name: "GE-Proton9-20.tar.gz".to_string(),
+                size: 0,
+                checksum: None,
+                uploaded_at: String::new(),
+                target_commitish: String::new(),
+            },
+            files: None,
+        };
+        fs::create_dir_all(provenance_file.parent().unwrap()).unwrap();
+        fs::write(
+            &provenance_file,
+            serde_json::to_string_pretty(&stamped(&provenance, "provenance", &provenance_file))
+                .unwrap(),
+        )
+        .unwrap();
+        let mut activity_log = ActivityLog::load(activity_file.clone());
+        activity_log.record(
+            ActivitySource::Task,
+            ActivityChange::ToolInstalled {
+                internal_name: "GE-Proton9-20".to_string(),
+                display_name: "GE-Proton9-20".to_string(),
+            },
+            0,
+        );
+        activity_log.save().unwrap();
+        let mut undo_stack = UndoStack::load(undo_file.clone());
+        undo_stack.push(
+            UndoOperation::SetMapping {
+                app_id: CompatAppId::from(AppId::new(1245620).unwrap()),
+                previous: None,
+                applied: "GE-Proton9-20".to_string(),
+            },
+            0,
+        );
+        undo_stack.save().unwrap();
+
+        let stamps: Vec<WrittenBy> = [&provenance_file, &activity_file, &undo_file]
+            .into_iter()
+            .map(|file| written_by(file))
+            .collect();
+        for (stamp, artifact) in stamps
+            .iter()
+            .zip(["provenance", "activity_log", "undo_stack"])
+        {
+            assert_eq!(stamp.version, env!("CARGO_PKG_VERSION"));
+            assert_eq!(stamp.artifact, artifact);
+        }
+        assert!(stamps[0].mutation < stamps[1].mutation);
+        assert!(stamps[1].mutation < stamps[2].mutation);
+        assert_eq!(stamps[2].path, undo_file.to_string_lossy());
+
+        // The stamps don't get in the way of reading the artifacts
+        let loaded: Provenance =
+            serde_json::from_str(&fs::read_to_string(&provenance_file).unwrap()).unwrap();
+        assert_eq!(loaded.tag_name, "GE-Proton9-20");
+        assert_eq!(UndoStack::load(undo_file).entries().len(), 1);
+    }
+}
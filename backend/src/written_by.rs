@@ -0,0 +1,172 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+const BACKEND_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Stamps kept in the mutation log, older ones are dropped on startup.
+const MAX_ENTRIES: usize = 1000;
+
+/// Stamp added to every persisted artifact, telling which backend version wrote it and in which
+/// order relative to every other write.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct WrittenBy {
+    pub version: String,
+    /// Increases with every persisted write, across restarts.
+    pub mutation: u64,
+    pub timestamp: u64,
+    /// Kind of artifact, e.g. `provenance` or `settings`.
+    pub artifact: String,
+    pub path: String,
+}
+
+/// Serializes like `value` with a `written_by` field added, readers of older files ignore it.
+#[derive(Serialize)]
+pub struct Stamped<'a, T> {
+    #[serde(flatten)]
+    value: &'a T,
+    written_by: WrittenBy,
+}
+
+pub fn stamped<'a, T: Serialize>(value: &'a T, artifact: &str, path: &Path) -> Stamped<'a, T> {
+    Stamped {
+        value,
+        written_by: WrittenBy::next(artifact, path),
+    }
+}
+
+struct Mutations {
+    last: u64,
+    /// `None` until startup opened the log, stamps are still counted.
+    log_file: Option<PathBuf>,
+}
+
+static MUTATIONS: Mutex<Mutations> = Mutex::new(Mutations {
+    last: 0,
+    log_file: None,
+});
+
+impl WrittenBy {
+    /// Stamps a write of `artifact` to `path` and appends the stamp to the mutation log.
+    pub fn next(artifact: &str, path: &Path) -> WrittenBy {
+        let mut mutations = MUTATIONS.lock().unwrap();
+        mutations.last += 1;
+        let written_by = WrittenBy {
+            version: BACKEND_VERSION.to_string(),
+            mutation: mutations.last,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Failed to calculate duration")
+                .as_secs(),
+            artifact: artifact.to_string(),
+            path: path.to_string_lossy().to_string(),
+        };
+        if let Some(log_file) = &mutations.log_file {
+            if let Err(err) = append_mutation(log_file, &written_by) {
+                warn!("Failed to append to mutation log: {}", err);
+            }
+        }
+        written_by
+    }
+}
+
+/// Continues the counter of the mutation log in `log_file`, every later stamp is appended to it.
+pub fn open_mutation_log(log_file: PathBuf) {
+    let entries = read_mutation_log(&log_file, usize::MAX);
+    if entries.len() > MAX_ENTRIES {
+        let kept = &entries[entries.len() - MAX_ENTRIES..];
+        if let Err(err) = write_mutation_log(&log_file, kept) {
+            warn!("Failed to truncate mutation log: {}", err);
+        }
+    }
+    let mut mutations = MUTATIONS.lock().unwrap();
+    if let Some(last) = entries.last() {
+        mutations.last = mutations.last.max(last.mutation);
+    }
+    mutations.log_file = Some(log_file);
+}
+
+fn append_mutation(log_file: &Path, written_by: &WrittenBy) -> io::Result<()> {
+    if let Some(parent) = log_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)?;
+    writeln!(file, "{}", serde_json::to_string(written_by)?)
+}
+
+fn write_mutation_log(log_file: &Path, entries: &[WrittenBy]) -> io::Result<()> {
+    let mut lines = String::new();
+    for entry in entries {
+        lines.push_str(&serde_json::to_string(entry)?);
+        lines.push('\n');
+    }
+    let temporary_file = log_file.with_extension("tmp");
+    fs::write(&temporary_file, lines)?;
+    fs::rename(temporary_file, log_file)
+}
+
+/// Returns the newest `limit` stamps in the order they were made, skipping unreadable lines.
+pub fn read_mutation_log(log_file: &Path, limit: usize) -> Vec<WrittenBy> {
+    let Ok(log) = fs::read_to_string(log_file) else {
+        return Vec::new();
+    };
+    let mut entries: Vec<WrittenBy> = log
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    entries.sort_by_key(|entry| entry.mutation);
+    let excess = entries.len().saturating_sub(limit);
+    entries.drain(..excess);
+    entries
+}
+
+/// The mutation log startup opened, `None` before that.
+pub fn mutation_log_file() -> Option<PathBuf> {
+    MUTATIONS.lock().unwrap().log_file.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_mutation_log_is_read_in_order() {
+        let temp_dir = tempdir().unwrap();
+        let log_file = temp_dir.path().join("mutation_log.jsonl");
+        let stamp = |mutation: u64, artifact: &str| WrittenBy {
+            version: "1.0.0".to_string(),
+            mutation,
+            timestamp: mutation * 10,
+            artifact: artifact.to_string(),
+            path: String::new(),
+        };
+        for written_by in [
+            stamp(1, "settings"),
+            stamp(3, "undo_stack"),
+            stamp(2, "provenance"),
+        ] {
+            append_mutation(&log_file, &written_by).unwrap();
+        }
+        fs::write(
+            &log_file,
+            fs::read_to_string(&log_file).unwrap() + "{\"truncated\n",
+        )
+        .unwrap();
+
+        let entries = read_mutation_log(&log_file, 2);
+        assert_eq!(
+            entries,
+            vec![stamp(2, "provenance"), stamp(3, "undo_stack")]
+        );
+        assert_eq!(read_mutation_log(&log_file, 10).len(), 3);
+        assert!(read_mutation_log(&temp_dir.path().join("missing"), 10).is_empty());
+    }
+}
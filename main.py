@@ -46,6 +46,12 @@ class Plugin:
             self.backend_proc.kill()
         self.backend_proc = subprocess.Popen([PARENT_DIR + "/bin/backend"])
 
+    # The backend writes a new one every time it starts, only this user can read it
+    async def get_session_token(self):
+        path = os.path.join(decky_plugin.DECKY_PLUGIN_RUNTIME_DIR, "session-token")
+        with open(path) as file:
+            return file.read()
+
     async def settings_read(self):
         logger.info('Reading settings')
         return settings.read()
@@ -0,0 +1,18 @@
+import { SteamApp } from "../types";
+
+// Apps mapped to a tool, greyed out once they're no longer installed
+function UsedByApps({ apps }: { apps: SteamApp[] }) {
+  return (
+    <span>
+      {"Used by: "}
+      {apps.map((app, index) => (
+        <span key={app.app_id} style={{ opacity: app.installed ? 1 : 0.5 }}>
+          {app.name}
+          {index < apps.length - 1 && ", "}
+        </span>
+      ))}
+    </span>
+  );
+}
+
+export default UsedByApps;
@@ -66,7 +66,13 @@ function SystemInformation({
               ? formatDistanceToNow(
                   fromUnixTime(appState.updater_last_check!),
                 ) + " ago"
-              : "Never")
+              : "Never") +
+            (appState.github_rate_limit_reset != null
+              ? ", rate limited by GitHub for another " +
+                formatDistanceToNow(
+                  fromUnixTime(appState.github_rate_limit_reset!),
+                )
+              : "")
           }
           bottomSeparator={"none"}
         >
@@ -90,6 +96,25 @@ function SystemInformation({
               ? "Check For Updates"
               : "Checking..."}
           </DialogButton>
+          <DialogButton
+            disabled={appState.updater_state == UpdaterState.Checking}
+            onClick={() => {
+              if (socket && socket.readyState === WebSocket.OPEN) {
+                const response: Request = {
+                  type: RequestType.Task,
+                  task: {
+                    type: TaskType.UpdateAllCompatibilityTools,
+                    update_all: { uninstall_superseded: false },
+                  },
+                };
+                socket.send(JSON.stringify(response));
+              } else {
+                error("WebSocket not alive...");
+              }
+            }}
+          >
+            Update All
+          </DialogButton>
         </Field>
       )}
     </Focusable>
@@ -12,8 +12,10 @@ import {
   showModal,
 } from "decky-frontend-lib";
 import { FaEllipsisH } from "react-icons/fa";
+import { formatDistanceToNow, fromUnixTime } from "date-fns";
 import {
   AppState,
+  CompatibilityToolFlavor,
   Flavor,
   GitHubRelease,
   QueueCompatibilityToolState,
@@ -24,8 +26,14 @@ import {
 } from "../types";
 import { error } from "../utils/logger";
 import ChangeLogModal from "../components/changeLogModal";
+import UsedByApps from "../components/usedByApps";
 import { RestartSteamClient } from "../utils/steamUtils";
 
+const formatBytes = (bytes: number): string =>
+  bytes >= 1024 * 1024 * 1024
+    ? (bytes / (1024 * 1024 * 1024)).toFixed(1) + " GiB"
+    : (bytes / (1024 * 1024)).toFixed(1) + " MiB";
+
 export default function FlavorTab({
   appState,
   flavor,
@@ -79,7 +87,9 @@ export default function FlavorTab({
         strTitle={
           "Steam Applications using " + steamCompatibilityTool.display_name
         }
-        strDescription={steamCompatibilityTool.used_by_games.join(", ")}
+        strDescription={
+          <UsedByApps apps={steamCompatibilityTool.used_by_apps} />
+        }
         strOKButtonText={"OK"}
       />,
     );
@@ -124,18 +134,20 @@ export default function FlavorTab({
   const handleViewChangeLog = (gitHubRelease: GitHubRelease) =>
     showModal(<ChangeLogModal release={gitHubRelease} />);
 
+  // Runners and components are listed from the directories they're installed to
+  const installedTools = appState.installed_compatibility_tools
+    .concat(appState.installed_runners, appState.installed_components)
+    .filter((t) => t.flavor == flavor.flavor);
+
   return (
     <DialogBody>
-      {appState.installed_compatibility_tools.filter(
-        (t) => t.flavor == flavor.flavor,
-      ).length != 0 && (
+      {installedTools.length != 0 && (
         <DialogControlsSection>
           <DialogControlsSectionHeader>Installed</DialogControlsSectionHeader>
           <ul style={{ listStyleType: "none" }}>
-            {appState.installed_compatibility_tools
-              .filter((t) => t.flavor == flavor.flavor)
-              .map((steamCompatibilityTool: SteamCompatibilityTool) => {
-                const isQueued = appState.in_progress !== null;
+            {installedTools.map(
+              (steamCompatibilityTool: SteamCompatibilityTool) => {
+                const isQueued = appState.in_progress.length != 0;
                 return (
                   <li
                     style={{
@@ -149,8 +161,12 @@ export default function FlavorTab({
                       {steamCompatibilityTool.display_name}{" "}
                       {steamCompatibilityTool.requires_restart &&
                         "(Requires Restart)"}
-                      {steamCompatibilityTool.used_by_games.length != 0 &&
-                        "(Used By Games)"}
+                      {steamCompatibilityTool.used_by_apps.length != 0 &&
+                        "(Used By Games)"}{" "}
+                      {steamCompatibilityTool.inspection != null &&
+                        "(" +
+                          formatBytes(steamCompatibilityTool.inspection.bytes) +
+                          ")"}
                     </span>
                     <Focusable
                       style={{
@@ -177,7 +193,7 @@ export default function FlavorTab({
                               >
                                 Uninstall
                               </MenuItem>
-                              {steamCompatibilityTool.used_by_games.length !=
+                              {steamCompatibilityTool.used_by_apps.length !=
                                 0 && (
                                 <MenuItem
                                   onSelected={() => {}}
@@ -227,7 +243,8 @@ export default function FlavorTab({
                     </Focusable>
                   </li>
                 );
-              })}
+              },
+            )}
           </ul>
         </DialogControlsSection>
       )}
@@ -235,6 +252,10 @@ export default function FlavorTab({
         <DialogControlsSection>
           <DialogControlsSectionHeader>
             Not Installed
+            {flavor.stale_since != null &&
+              " (last updated " +
+                formatDistanceToNow(fromUnixTime(flavor.stale_since)) +
+                " ago)"}
           </DialogControlsSectionHeader>
           <ul>
             {flavor.releases.map((release) => {
@@ -248,9 +269,12 @@ export default function FlavorTab({
                     (install) =>
                       install != null && install.release.url == release.url,
                   ).length == 1;
-              const isInProgress = appState.in_progress !== null;
-              const isItemInProgress =
-                isInProgress && appState.in_progress?.name === release.tag_name;
+              const inProgress = appState.in_progress.find(
+                (inProgress) =>
+                  inProgress.flavor == flavor.flavor &&
+                  inProgress.name === release.tag_name,
+              );
+              const isItemInProgress = inProgress !== undefined;
               return (
                 <li
                   style={{
@@ -262,6 +286,9 @@ export default function FlavorTab({
                 >
                   <span>
                     {release.tag_name}
+                    {/* Builds aren't versioned, tell them apart by date */}
+                    {flavor.flavor == CompatibilityToolFlavor.ProtonTkg &&
+                      " (built " + release.published_at.slice(0, 10) + ")"}
                     {isQueued && " (In Queue)"}
                   </span>
                   {isItemInProgress && (
@@ -273,12 +300,35 @@ export default function FlavorTab({
                       }}
                     >
                       <ProgressBarWithInfo
-                        nProgress={appState.in_progress?.progress}
+                        nProgress={inProgress?.progress}
                         indeterminate={
-                          appState.in_progress?.state ==
-                          QueueCompatibilityToolState.Extracting
+                          inProgress?.state ==
+                            QueueCompatibilityToolState.Verifying ||
+                          inProgress?.state ==
+                            QueueCompatibilityToolState.Finalizing
+                        }
+                        sOperationText={
+                          inProgress?.state ==
+                          QueueCompatibilityToolState.ChecksumFailed
+                            ? "Download corrupted, retrying"
+                            : inProgress?.state ==
+                                  QueueCompatibilityToolState.Retrying &&
+                                inProgress.retry != null
+                              ? "Retrying (" +
+                                inProgress.retry.attempt +
+                                "/" +
+                                inProgress.retry.attempts +
+                                ")"
+                              : inProgress?.bytes_per_second != null
+                              ? inProgress.state +
+                                " (" +
+                                (
+                                  inProgress.bytes_per_second /
+                                  (1024 * 1024)
+                                ).toFixed(1) +
+                                " MiB/s)"
+                              : inProgress?.state
                         }
-                        sOperationText={appState.in_progress?.state}
                         bottomSeparator="none"
                       />
                     </div>
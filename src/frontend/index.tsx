@@ -1,14 +1,32 @@
 import { SidebarNavigation, SidebarNavigationPage } from "decky-frontend-lib";
 
 import { useEffect, useState } from "react";
-import { AppState, Request, RequestType } from "../types";
+import {
+  AppState,
+  CompatibilityToolFlavor,
+  PROTOCOL_VERSION,
+  Request,
+  RequestType,
+} from "../types";
 import { log } from "../utils/logger";
+import { applyStateDeltas } from "../utils/stateDeltas";
+import { BackendCtx } from "../utils/pythonBackendHelper";
 import { v4 as uuidv4 } from "uuid";
 import FlavorTab from "./flavorTab";
 import ManagerTab from "./manager";
 import { GetGlobalCompatTools } from "../utils/steamUtils";
 import About from "./about";
 
+// Titles of flavors whose own name doesn't say what they are
+const FLAVOR_TITLES: Partial<Record<CompatibilityToolFlavor, string>> = {
+  [CompatibilityToolFlavor.Boxtron]: "Boxtron (DOSBox)",
+  [CompatibilityToolFlavor.Roberta]: "Roberta (ScummVM)",
+  [CompatibilityToolFlavor.ProtonTkg]: "Proton-Tkg",
+  [CompatibilityToolFlavor.WineGE]: "Wine-GE (Lutris, Heroic)",
+  [CompatibilityToolFlavor.Dxvk]: "DXVK",
+  [CompatibilityToolFlavor.Vkd3dProton]: "vkd3d-proton",
+};
+
 export default function ManagePage() {
   const [appState, setAppState] = useState<AppState | undefined>();
 
@@ -23,6 +41,20 @@ export default function ManagePage() {
     socket.onopen = async () => {
       log("WebSocket connection established. Unique Identifier:", uniqueId); // Log the unique identifier on connection open
 
+      // Nothing else is accepted before these
+      socket.send(
+        JSON.stringify({
+          type: RequestType.Hello,
+          protocol_version: PROTOCOL_VERSION,
+        }),
+      );
+      socket.send(
+        JSON.stringify({
+          type: RequestType.Authenticate,
+          session_token: await BackendCtx.getSessionToken(),
+        }),
+      );
+
       const tools = await GetGlobalCompatTools();
 
       const response: Request = {
@@ -31,16 +63,45 @@ export default function ManagePage() {
       };
 
       socket.send(JSON.stringify(response));
+      socket.send(JSON.stringify({ type: RequestType.RequestSnapshot }));
+      awaitingSnapshot = true;
     };
 
+    // Revision of the state shown, deltas only apply on top of it
+    let revision: number | undefined;
+    let awaitingSnapshot = false;
+
     socket.onmessage = async (event) => {
       //log("Received message from server:", event.data);
       const response: Request = JSON.parse(event.data);
       if (response.type == RequestType.UpdateState) {
         if (response.app_state != null) {
           setAppState(response.app_state);
+          revision = response.revision;
+          awaitingSnapshot = false;
           log("Received app state update");
         }
+      } else if (
+        response.type == RequestType.StateDelta &&
+        response.state_delta != null
+      ) {
+        const stateDelta = response.state_delta;
+        if (revision != null && stateDelta.base_revision == revision) {
+          setAppState((appState) =>
+            appState != null
+              ? applyStateDeltas(appState, stateDelta.deltas)
+              : appState,
+          );
+          revision = stateDelta.revision;
+        } else if (
+          (revision == null || stateDelta.revision > revision) &&
+          !awaitingSnapshot
+        ) {
+          // Missed a revision
+          log("Requesting app state snapshot after revision", revision);
+          socket.send(JSON.stringify({ type: RequestType.RequestSnapshot }));
+          awaitingSnapshot = true;
+        }
       }
     };
 
@@ -70,7 +131,7 @@ export default function ManagePage() {
     // Flavor pages
     appState.available_flavors.forEach((flavor) => {
       pages.push({
-        title: flavor.flavor,
+        title: FLAVOR_TITLES[flavor.flavor] ?? flavor.flavor,
         content: (
           <FlavorTab appState={appState} flavor={flavor} socket={socket} />
         ),
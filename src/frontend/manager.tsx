@@ -11,6 +11,7 @@ import {
   showModal,
 } from "decky-frontend-lib";
 import { FaEllipsisH } from "react-icons/fa";
+import UsedByApps from "../components/usedByApps";
 import {
   AppState,
   CompatibilityToolFlavor,
@@ -53,7 +54,7 @@ export default function ManagerTab({
     showModal(
       <ConfirmModal
         strTitle={"Steam Applications using " + release.display_name}
-        strDescription={release.used_by_games.join(", ")}
+        strDescription={<UsedByApps apps={release.used_by_apps} />}
         strOKButtonText={"OK"}
       />,
     );
@@ -93,9 +94,10 @@ export default function ManagerTab({
                 >
                   <span>
                     {steamCompatibilityTool.display_name}
+                    {steamCompatibilityTool.official && " (Steam)"}
                     {steamCompatibilityTool.requires_restart &&
                       " (Requires Restart)"}
-                    {steamCompatibilityTool.used_by_games.length != 0 &&
+                    {steamCompatibilityTool.used_by_apps.length != 0 &&
                       " (Used By Games)"}
                   </span>
                   <Focusable
@@ -116,15 +118,18 @@ export default function ManagerTab({
                       onClick={(e: MouseEvent) =>
                         showContextMenu(
                           <Menu label="Runner Actions">
-                            <MenuItem
-                              onSelected={() => {}}
-                              onClick={() => {
-                                handleUninstallModal(steamCompatibilityTool);
-                              }}
-                            >
-                              Uninstall
-                            </MenuItem>
-                            {steamCompatibilityTool.used_by_games.length !=
+                            {/* Steam uninstalls its own Proton releases */}
+                            {!steamCompatibilityTool.official && (
+                              <MenuItem
+                                onSelected={() => {}}
+                                onClick={() => {
+                                  handleUninstallModal(steamCompatibilityTool);
+                                }}
+                              >
+                                Uninstall
+                              </MenuItem>
+                            )}
+                            {steamCompatibilityTool.used_by_apps.length !=
                               0 && (
                               <MenuItem
                                 onSelected={() => {}}
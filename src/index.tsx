@@ -11,6 +11,7 @@ import { VFC } from "react";
 
 import ManagePage from "./frontend";
 import { forceCloseToastsWebSocket, setupToasts } from "./utils/toasts";
+import { BackendCtx } from "./utils/pythonBackendHelper";
 import { GiCellarBarrels } from "react-icons/gi";
 
 const Content: VFC<{ serverAPI: ServerAPI }> = ({}) => {
@@ -32,6 +33,7 @@ const Content: VFC<{ serverAPI: ServerAPI }> = ({}) => {
 };
 
 export default definePlugin((serverApi: ServerAPI) => {
+  BackendCtx.initialize(serverApi);
   setupToasts(serverApi);
   serverApi.routerHook.addRoute("/wine-cellar", () => {
     return <ManagePage />;
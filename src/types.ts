@@ -7,6 +7,7 @@ export type GitHubRelease = {
   prerelease: boolean;
   name: String;
   tag_name: String;
+  target_commitish: String;
   assets: Asset[];
   created_at: String;
   published_at: String;
@@ -29,16 +30,475 @@ export type Asset = {
 export type AppState = {
   available_flavors: Flavor[];
   installed_compatibility_tools: SteamCompatibilityTool[];
-  in_progress?: QueueCompatibilityTool;
+  // Wine versions installed for Lutris and Heroic, Steam doesn't use them
+  installed_runners: SteamCompatibilityTool[];
+  // DXVK and vkd3d-proton versions kept to drop into prefixes
+  installed_components: SteamCompatibilityTool[];
+  compatibility_tool_mappings: CompatibilityToolMapping[];
+  // Installs currently running, several run at once
+  in_progress: QueueCompatibilityTool[];
   task_queue: Task[];
   updater_state: UpdaterState;
   updater_last_check?: number;
+  // When the GitHub rate limit the last release check ran into resets
+  github_rate_limit_reset?: number;
+  settings: Settings;
+  network_usage: NetworkUsage;
+  environment: EnvironmentSnapshot;
+  stranded_compatibility_tools: StrandedCompatibilityTool[];
+  startup_stages: CompletedStartupStage[];
+  restart_required: boolean;
+  filesystem_profiles: PathFilesystem[];
+  // Messages peers missed because they didn't keep up, since startup
+  broadcast_counters: BroadcastCounters;
+  // Every Steam installation found, the one in use first
+  steam_installations: string[];
+  // Background inspection of the installed tools, missing until it started
+  inspection_progress?: InspectionProgress;
+  // Updates the last scheduled update check found
+  available_updates: AvailableUpdate[];
+  // Prefix backups being restored, including the ones waiting for a decision
+  prefix_restores: PrefixRestore[];
+};
+
+export type InspectionProgress = {
+  inspected: number;
+  total: number;
+  paused: boolean;
+};
+
+export type BroadcastCounters = {
+  dropped: number;
+  coalesced: number;
+  evictions: number;
+};
+
+export type EnvironmentSnapshot = {
+  on_battery?: boolean;
+  metered_network?: boolean;
+  steam_started_at?: number;
+  running_game?: number;
+  game_mode?: boolean;
+  system_versions: Requirements;
+  // Seconds the system clock is ahead, only set when it breaks certificate validation
+  clock_skew?: number;
+  // Set once a request to GitHub failed or went through
+  github_reachability?: GitHubReachability;
+};
+
+export enum GitHubReachability {
+  Reachable = "Reachable",
+  // Only cached releases can be listed, their assets still download
+  ApiBlocked = "ApiBlocked",
+  Offline = "Offline",
+}
+
+export type PathFilesystem = {
+  path: string;
+  profile: FilesystemProfile;
+  // Whether the library folder can be read, false e.g. while its SD card is taken out
+  mounted: boolean;
+};
+
+export type FilesystemProfile = {
+  fs_type?: string;
+  symlinks: boolean;
+  unix_permissions: boolean;
+  case_sensitive: boolean;
+};
+
+export type StrandedCompatibilityTool = {
+  root: string;
+  directory_name: string;
+  internal_name: string;
+  display_name: string;
+};
+
+export type CompatibilityToolMapping = {
+  app_id: number;
+  name: string;
+  compatibility_tool: string;
+  unresolved: boolean;
+  // Tool Steam forces on the app regardless of the mapping, e.g. proton_hotfix
+  steam_override?: string;
+  // Tools the app can be switched between, if any were set
+  quick_slots?: QuickSlotState;
+};
+
+export type QuickSlotState = {
+  slots: QuickSlot[];
+  // Unset if the mapping was changed some other way since the last switch
+  active?: number;
+};
+
+export type QuickSlot = {
+  compatibility_tool: string;
+  // The tool was removed, switching to the slot fails until it's replaced
+  dangling: boolean;
+};
+
+export type QuickSlots = {
+  app_id: number;
+  tools: string[];
+  active?: number;
+};
+
+export type Settings = {
+  monthly_network_cap?: number;
+  resolve_app_names: boolean;
+  feature_flags: FeatureFlags;
+  skip_file_manifest: boolean;
+  force_serial_extraction: boolean;
+  background_while_gaming: boolean;
+  background_download_limit?: number;
+  // Download speed limit in KiB/s for every install, 0 for unlimited
+  download_rate_limit: number;
+  // Times a download is started or resumed before the install gives up, 3 if unset
+  download_attempts?: number;
+  unix_socket: boolean;
+  // Releases not offered as updates, each is dropped once a newer release replaces it
+  skipped_releases: SkippedRelease[];
+  // Tokens other clients can connect with for a restricted set of permissions
+  access_tokens: AccessToken[];
+  // Tools each app can be switched between with one tap
+  quick_slots: QuickSlots[];
+  // Steam installation to operate on, the first one found if missing or no longer there
+  steam_directory?: string;
+  // Write config.vdf while Steam is running, Steam may overwrite the changes when it exits
+  write_while_steam_running: boolean;
+  // Flavors whose downloads aren't checked against a published checksum
+  skip_checksum_flavors: CompatibilityToolFlavor[];
+  // Flavors whose downloads aren't checked against the release's signature
+  skip_signature_flavors: CompatibilityToolFlavor[];
+  // Tasks run at once, 2 if missing
+  concurrent_tasks?: number;
+  // Personal access token sent with GitHub API requests, raising the rate limit
+  github_token?: string;
+  // List Proton-Tkg builds, nightlies that aren't tested like releases
+  proton_tkg_builds: boolean;
+  // Allow installs from plain http URLs
+  direct_install_allow_http: boolean;
+  // Largest archive an install from a URL downloads in bytes, 2 GiB if missing
+  direct_install_max_size?: number;
+  // Proxy every request goes through, the proxy environment variables are used if missing
+  proxy_url?: string;
+  // Check for new releases of the installed tools on a schedule, while no game is running
+  update_checks: boolean;
+  // Hours between update checks, 24 if missing
+  update_check_interval_hours?: number;
+  // Queue the updates an update check finds instead of only telling about them
+  auto_queue_updates: boolean;
+  // Paths below a prefix's pfx compared before a backup is restored over it, Documents, AppData and Saved Games if missing
+  save_paths?: string[];
+  // Days the tools an update moved apps away from stay in the trash to roll back to, 7 if missing
+  rollback_grace_days?: number;
+};
+
+export type AccessToken = {
+  name: string;
+  token: string;
+  permissions: Permission[];
+};
+
+export type Permission =
+  | "read_tools"
+  | "read_queue"
+  | "read_apps"
+  | "read_prefixes"
+  | "control_tasks"
+  | "write_config";
+
+// Categories of broadcast messages a peer can subscribe to
+export type Subscription =
+  | "tasks"
+  | "installed_tools"
+  | "available_flavors"
+  | "settings"
+  | "logs";
+
+export type SkippedRelease = {
+  flavor: CompatibilityToolFlavor;
+  tag_name: string;
+};
+
+export type FeatureFlags = {
+  write_steam_config: boolean;
+  auto_update: boolean;
+  dedup: boolean;
+  external_commands: boolean;
+  network_recommendations: boolean;
+};
+
+export type NetworkUsage = {
+  month: string;
+  metadata_bytes: number;
+  asset_bytes: number;
+};
+
+export type SteamApp = {
+  app_id: number;
+  name: string;
+  // Whether the app has an appmanifest, mappings outlive uninstalled apps
+  installed: boolean;
+};
+
+export enum InstallState {
+  FullyInstalled = "FullyInstalled",
+  // Installed, but an update has to be downloaded before it can be launched
+  UpdatePending = "UpdatePending",
+  // Space is reserved for the files, which aren't downloaded yet
+  Preallocating = "Preallocating",
+  Uninstalling = "Uninstalling",
+  Other = "Other",
+}
+
+export enum AppKind {
+  Game = "Game",
+  // A compatibility tool, runtime or redistributable
+  Tool = "Tool",
+}
+
+export type InstalledApp = {
+  app_id: number;
+  name: string;
+  // Directory in steamapps/common the app is installed to
+  installdir: string;
+  // Library folder the appmanifest is in
+  library_path: string;
+  size_on_disk: number;
+  state_flags: number;
+  install_state: InstallState;
+  kind: AppKind;
+};
+
+// A non-Steam shortcut of one Steam account
+export type ShortcutDetails = {
+  // Directory of the account in userdata
+  user_id: number;
+  shortcut_id: number;
+  app_name: string;
+  exe: string;
+  launch_options: string;
 };
 
 export type Task = {
+  // Assigned by the backend once the task is queued, CancelTask refers to it
+  id?: number;
   type: TaskType;
   install?: Install;
   uninstall?: Uninstall;
+  migrate?: Migrate;
+  mapping?: MappingChange;
+  mappings?: MappingChanges;
+  import?: MappingImport;
+  direct_install?: DirectInstall;
+  update_all?: UpdateAll;
+  local_file?: LocalFileInstall;
+  delete_compat_data?: DeleteCompatData;
+  restore_prefix_backup?: RestorePrefixBackup;
+};
+
+// A prefix left behind by an app that was uninstalled or a shortcut that was removed
+export type OrphanedCompatData = {
+  app_id: number;
+  name?: string;
+  path: string;
+  // Bytes on disk
+  size: number;
+};
+
+// Orphaned prefixes, deleting any of them needs the listing's token
+export type CompatDataListing = {
+  confirmation_token: number;
+  expires_at: number;
+  prefixes: OrphanedCompatData[];
+};
+
+export type DeleteCompatData = {
+  // Token of the listing the prefixes were picked from
+  confirmation_token: number;
+  // Apps or shortcuts whose prefixes are deleted
+  app_ids: number[];
+};
+
+// A backup of an app's compatdata directory to restore over its prefix
+export type RestorePrefixBackup = {
+  app_id: number;
+  // Absolute path of a tar archive of the compatdata directory, compressed or not
+  path: string;
+};
+
+export enum PrefixRestoreState {
+  Comparing = "Comparing",
+  // The prefix has newer saves, nothing is restored until a RestoreDecision is sent
+  WaitingForDecision = "WaitingForDecision",
+  Restoring = "Restoring",
+}
+
+export type PrefixRestore = {
+  // Task id ResolveRestoreConflict refers to
+  id: number;
+  app_id: number;
+  state: PrefixRestoreState;
+  // Saves restoring the backup would lose
+  conflicts: SaveConflict[];
+};
+
+// A save in the prefix that is newer than the one in the backup, or missing from it
+export type SaveConflict = {
+  // Relative to the compatdata directory
+  path: string;
+  live_modified: number;
+  live_size: number;
+  // Missing if the backup doesn't have the file
+  backup_modified?: number;
+  backup_size?: number;
+};
+
+export enum RestoreDecision {
+  // Restore everything but the save paths
+  KeepLiveSaves = "KeepLiveSaves",
+  Overwrite = "Overwrite",
+  Abort = "Abort",
+}
+
+export type UpdateAll = {
+  // Uninstall the updated tools once the new release is installed, unless games are still mapped to them
+  uninstall_superseded?: boolean;
+  // Move the apps mapped to the updated tools to the new release and uninstall them, keeping them in the trash to roll back to
+  migrate_mappings?: boolean;
+};
+
+// A filesystem without room for an install
+export type DiskSpaceShortage = {
+  // Directory on the filesystem that's short on space
+  path: string;
+  required_bytes: number;
+  available_bytes: number;
+};
+
+// What updating every installed tool did with each of them
+export type UpdateSummary = {
+  queued: QueuedUpdate[];
+  // Tools whose latest release is already installed or queued
+  current: string[];
+  // Tools the user put there themselves, left alone
+  unmanaged: string[];
+  // Apps mapped to an official Proton release far behind the newest one installed, Steam updates
+  // the releases but never moves apps between them
+  outdated_official_mappings: OutdatedOfficialMapping[];
+};
+
+// An app mapped to an official Proton release far behind the newest one installed
+export type OutdatedOfficialMapping = {
+  app_id: number;
+  name: string;
+  // Display name of the release the app is mapped to
+  compatibility_tool: string;
+  // Display name of the newest official release installed
+  newest: string;
+};
+
+// An installed tool with a newer release of its flavor out
+export type AvailableUpdate = {
+  flavor: CompatibilityToolFlavor;
+  name: string;
+  installed_version: string;
+  newest_version: string;
+};
+
+export type QueuedUpdate = {
+  flavor: CompatibilityToolFlavor;
+  tag_name: string;
+  // Display names of the installed tools the release updates
+  updates: string[];
+};
+
+// An asset URL pasted by the user, for when the release listing can't be fetched
+export type DirectInstall = {
+  url: string;
+  // Override what is inferred from the URL, Unknown installs a fork from any host
+  flavor?: CompatibilityToolFlavor;
+  tag_name?: string;
+};
+
+// An archive already on disk, e.g. on an SD card, installed without downloading
+export type LocalFileInstall = {
+  // Absolute path, the archive type is told by its contents
+  path: string;
+  // Override what is inferred from the file name
+  flavor?: CompatibilityToolFlavor;
+  tag_name?: string;
+};
+
+export type MappingChange = {
+  // 0 changes the default tool
+  app_id: number;
+  compatibility_tool?: string;
+  // Clear the app's shader cache right away, the new tool can't use it
+  clear_shader_cache?: boolean;
+};
+
+export type MappingChanges = {
+  changes: MappingChange[];
+  // Apply none of the changes unless every one of them is valid
+  atomic?: boolean;
+};
+
+export enum MappingImportFormat {
+  // One "<app id> [name]: <tool>" line per game
+  ProtonUpQt = "ProtonUpQt",
+  Csv = "Csv",
+}
+
+export type MappingImport = {
+  format: MappingImportFormat;
+  content: string;
+  // Queue installs of missing tools, their mappings are applied once installed
+  install_missing?: boolean;
+};
+
+export type ImportDisposition =
+  | "Applied"
+  | "AlreadySet"
+  | {
+      ToolMissing: {
+        flavor?: CompatibilityToolFlavor;
+        release?: string;
+        queued: boolean;
+      };
+    }
+  | "AppUnknown"
+  | { Invalid: string };
+
+export type ImportRowResult = {
+  line: number;
+  app_id?: number;
+  compatibility_tool?: string;
+  disposition: ImportDisposition;
+};
+
+export type WrittenBy = {
+  version: string;
+  // Increases with every persisted write, across restarts
+  mutation: number;
+  timestamp: number;
+  artifact: string;
+  path: string;
+};
+
+export type MappingChangeResult = {
+  app_id: number;
+  compatibility_tool?: string;
+  applied: boolean;
+  reason?: string;
+};
+
+export type ConfigWrite = {
+  // Times config.vdf changed between reading and replacing it
+  retries: number;
+  // Whether changes made meanwhile, usually by Steam, were kept
+  merged_foreign_change: boolean;
 };
 
 export enum TaskType {
@@ -46,11 +506,33 @@ export enum TaskType {
   InstallCompatibilityTool = "InstallCompatibilityTool",
   CancelCompatibilityToolInstall = "CancelCompatibilityToolInstall",
   UninstallCompatibilityTool = "UninstallCompatibilityTool",
+  MigrateCompatibilityTools = "MigrateCompatibilityTools",
+  SetCompatibilityToolMapping = "SetCompatibilityToolMapping",
+  SetCompatibilityToolMappings = "SetCompatibilityToolMappings",
+  ImportCompatibilityToolMappings = "ImportCompatibilityToolMappings",
+  InstallFromUrl = "InstallFromUrl",
+  UpdateAllCompatibilityTools = "UpdateAllCompatibilityTools",
+  InstallFromLocalFile = "InstallFromLocalFile",
+  DeleteCompatData = "DeleteCompatData",
+  RestorePrefixBackup = "RestorePrefixBackup",
 }
 
 export type Flavor = {
   flavor: CompatibilityToolFlavor;
   releases: GitHubRelease[];
+  requirements: Requirements;
+  requirement_warnings: string[];
+  // Newest release that wasn't skipped, installed or not
+  latest_release?: string;
+  skipped_releases: string[];
+  // When the cached releases were fetched, set when they're shown because fetching failed
+  stale_since?: number;
+};
+
+export type Requirements = {
+  steamos?: string;
+  mesa?: string;
+  kernel?: string;
 };
 
 export type Request = {
@@ -58,17 +540,481 @@ export type Request = {
   task?: Task;
   available_compat_tools?: CompatToolInfo[];
   notification?: string;
+  // Set on the notification of a finished install
+  steam_restart_required?: boolean;
+  // Set on the warning sent when an operation went ahead without knowing whether games run
+  running_games_unknown?: boolean;
   app_state?: AppState;
+  settings?: Settings;
+  internal_name?: string;
+  provenance?: Provenance;
+  verification?: Verification;
+  activity_query?: ActivityQuery;
+  activity?: ActivityEvent[];
+  storage_breakdown?: StorageBreakdown;
+  app_id?: number;
+  refresh?: boolean;
+  startup_progress?: StartupProgress;
+  validation_errors?: ValidationError[];
+  undo_stack?: UndoEntry[];
+  error_group?: ErrorGroup;
+  app_error?: AppError;
+  // Sent with Authenticate as the first message of a connection
+  session_token?: string;
+  // Categories of messages the peer wants, or got with Subscribed
+  subscriptions?: Subscription[];
+  // Exchanged with Hello when connecting
+  protocol_version?: ProtocolVersion;
+  // Sent with an IncompatibleVersion error
+  version_mismatch?: VersionMismatch;
+  // Type of the request an UnknownRequestType error answers
+  unknown_request_type?: string;
+  request_id?: string;
+  refresh_scope?: RefreshScope;
+  flavor?: CompatibilityToolFlavor;
+  shader_cache?: ShaderCacheStatus;
+  mapping_results?: MappingChangeResult[];
+  // How the write of config.vdf behind mapping_results went
+  config_write?: ConfigWrite;
+  mapping_import?: ImportRowResult[];
+  // Maximum number of entries to send back
+  limit?: number;
+  mutation_log?: WrittenBy[];
+  tag_name?: string;
+  // Apps visible in the frontend, scanned before every other prefix
+  app_ids?: number[];
+  prefix?: PrefixInfo;
+  // What this connection may do, sent once it connected
+  permissions?: Permission[];
+  // Tools of the quick slots of app_id, in slot order
+  quick_slots?: string[];
+  slot?: number;
+  plan_kind?: PlanKind;
+  plan?: Plan;
+  plan_id?: number;
+  // Actions of the plan left out when executing it
+  excluded_action_ids?: number[];
+  // Outcome of every action of an executed plan
+  plan_results?: ActionResult[];
+  tool_inspection?: ToolInspection;
+  inspection_progress?: InspectionProgress;
+  steam_directory?: string;
+  task_id?: number;
+  // Sent with ResolveRestoreConflict for the restore task_id
+  restore_decision?: RestoreDecision;
+  virtual_tool?: VirtualToolSettings;
+  update_summary?: UpdateSummary;
+  disk_space?: DiskSpaceShortage;
+  available_updates?: AvailableUpdate[];
+  compat_data_listing?: CompatDataListing;
+  // Size of every prefix, biggest first
+  prefix_sizes?: PrefixSize[];
+  // Apps with an appmanifest, with their size, library folder and install state
+  installed_apps?: InstalledApp[];
+  shortcut_id?: number;
+  // Sent with SetShortcutLaunchOptions, e.g. PROTON_ENABLE_NVAPI=1 %command%
+  launch_options?: string;
+  // The shortcut in every account that has it
+  shortcut_details?: ShortcutDetails[];
+  history_query?: HistoryQuery;
+  // Sent with RollbackUpdate for the update's history record
+  history_id?: number;
+  // Finished installs and uninstalls, newest first
+  history?: HistoryPage;
+  // Revision of the state an UpdateState snapshot carries
+  revision?: number;
+  state_delta?: StateDeltas;
+};
+
+// What changed between two revisions of the state, peers without base_revision need a snapshot
+export type StateDeltas = {
+  base_revision: number;
+  revision: number;
+  deltas: StateDelta[];
+};
+
+export type ToolSection =
+  | "installed_compatibility_tools"
+  | "installed_runners"
+  | "installed_components";
+
+export type StateDelta =
+  // A field of the state replaced as a whole, e.g. in_progress as installs make progress
+  | { kind: "SectionChanged"; section: string; value: any }
+  // Tools that showed up, in the order of their index
+  | {
+      kind: "ToolsAdded";
+      section: ToolSection;
+      tools: { index: number; tool: SteamCompatibilityTool }[];
+    }
+  // Tools whose entry changed, matched by path
+  | {
+      kind: "ToolsChanged";
+      section: ToolSection;
+      tools: SteamCompatibilityTool[];
+    }
+  | { kind: "ToolsRemoved"; section: ToolSection; paths: string[] };
+
+export enum PlanKind {
+  Cleanup = "Cleanup",
+  OrphanPrefixes = "OrphanPrefixes",
+}
+
+export enum ActionKind {
+  DeleteTrash = "DeleteTrash",
+  DeletePrefix = "DeletePrefix",
+  DeleteShaderCache = "DeleteShaderCache",
+}
+
+export type PlannedAction = {
+  id: number;
+  kind: ActionKind;
+  target: string;
+  app_id?: number;
+  reclaimed_bytes: number;
+  // What could be lost, shown next to the action
+  risks: string[];
+  // Actions that have to succeed before this one runs
+  depends_on: number[];
+};
+
+export type Plan = {
+  id: number;
+  kind: PlanKind;
+  created_at: number;
+  expires_at: number;
+  actions: PlannedAction[];
+};
+
+export type ActionOutcome =
+  | "Done"
+  | "Excluded"
+  | { Skipped: string }
+  | { Failed: string };
+
+export type ActionResult = {
+  action_id: number;
+  outcome: ActionOutcome;
+  reclaimed_bytes: number;
+};
+
+export type PrefixInfo = {
+  app_id: number;
+  library_folder?: string;
+  exists: boolean;
+  proton_version?: string;
+  modified_at?: number;
+  bytes?: number;
+};
+
+export enum RefreshScope {
+  Tools = "Tools",
+  Apps = "Apps",
+  Mappings = "Mappings",
+  Releases = "Releases",
+  Prefixes = "Prefixes",
+  Storage = "Storage",
+}
+
+export enum ErrorSource {
+  Network = "Network",
+  Filesystem = "Filesystem",
+  Steam = "Steam",
+}
+
+export type ErrorGroup = {
+  code: string;
+  source: ErrorSource;
+  error_code: AppErrorCode;
+  message: string;
+  count: number;
+  targets: string[];
+  summary: boolean;
+};
+
+export enum AppErrorCode {
+  NetworkError = "NetworkError",
+  DiskFull = "DiskFull",
+  ChecksumMismatch = "ChecksumMismatch",
+  SignatureInvalid = "SignatureInvalid",
+  SteamNotFound = "SteamNotFound",
+  VdfParse = "VdfParse",
+  Internal = "Internal",
+  Unauthenticated = "Unauthenticated",
+  IncompatibleVersion = "IncompatibleVersion",
+  UnknownRequestType = "UnknownRequestType",
+  OperationBlockedGameRunning = "OperationBlockedGameRunning",
+  OperationBlockedSteamRunning = "OperationBlockedSteamRunning",
+  NotReady = "NotReady",
+  InvalidRequest = "InvalidRequest",
+  Forbidden = "Forbidden",
+  FeatureDisabled = "FeatureDisabled",
+  NotFound = "NotFound",
+  ToolInUse = "ToolInUse",
+  LocalChanges = "LocalChanges",
+  UnsupportedFilesystem = "UnsupportedFilesystem",
+  ManagedBySteam = "ManagedBySteam",
+  NetworkCapReached = "NetworkCapReached",
+}
+
+export type ProtocolVersion = {
+  major: number;
+  minor: number;
+};
+
+// Version of the messages this frontend speaks, the backend turns it away if the major version
+// differs from its own
+export const PROTOCOL_VERSION: ProtocolVersion = { major: 1, minor: 0 };
+
+export type VersionMismatch = {
+  backend: ProtocolVersion;
+  // Missing if the peer didn't say which version it speaks
+  peer: ProtocolVersion | null;
+};
+
+export type AppError = {
+  code: AppErrorCode;
+  message: string;
+  // The task that failed, if the failure ended one
+  task_id?: number;
+};
+
+export type ValidationError = {
+  pointer: string;
+  expected: string;
+};
+
+export enum StartupStage {
+  DiscoverSteam = "DiscoverSteam",
+  LoadSettings = "LoadSettings",
+  LoadCaches = "LoadCaches",
+  ReconcileJournal = "ReconcileJournal",
+  ScanTools = "ScanTools",
+  ScanApps = "ScanApps",
+}
+
+export type CompletedStartupStage = {
+  stage: StartupStage;
+  duration_ms: number;
+  elapsed_ms: number;
+};
+
+export type StartupProgress = {
+  completed: CompletedStartupStage[];
+  pending?: StartupStage;
+};
+
+export type StorageBreakdown = {
+  library_folders: LibraryFolderUsage[];
+  computed_at: number;
+};
+
+export type LibraryFolderUsage = {
+  path: string;
+  common_bytes: number;
+  compatdata_bytes: number;
+  shadercache_bytes: number;
+  downloading_bytes: number;
+  top_prefixes: AppUsage[];
+  top_shader_caches: AppUsage[];
+};
+
+export type ShaderCacheStatus = {
+  app_id: number;
+  bytes: number;
+  fossilize_databases: number;
+  fossilize_bytes: number;
+  cleared: boolean;
+};
+
+export type AppUsage = {
+  app_id: number;
+  name: string;
+  bytes: number;
+};
+
+// Size of an app's prefix in one library folder
+export type PrefixSize = {
+  app_id: number;
+  name: string;
+  library_folder: string;
+  bytes: number;
+};
+
+export type HistoryTrigger = "Manual" | "UpdateAll" | "AutoUpdate";
+
+export type HistoryRecord = {
+  // Identifies the record, 0 for records written by older versions
+  id: number;
+  // When the task ended
+  timestamp: number;
+  task_type: "Install" | "Uninstall" | "Rollback";
+  flavor: CompatibilityToolFlavor;
+  // Tag of the release installed, or name of the tool uninstalled
+  version: string;
+  duration_ms: number;
+  outcome: "Succeeded" | "Failed" | "Cancelled";
+  error_code?: AppErrorCode;
+  trigger: HistoryTrigger;
+  // How to go back to the tools an update moved apps away from
+  rollback?: RollbackPlan;
+  // Id of the update a rollback went back from
+  rolls_back?: number;
+};
+
+export type RollbackPlan = {
+  // Internal name of the release the apps were moved to
+  updated: string;
+  replaced: ReplacedTool[];
+  // The replaced tools are deleted from the trash at the first startup after this timestamp
+  kept_until: number;
+};
+
+export type ReplacedTool = {
+  internal_name: string;
+  display_name: string;
+  flavor: CompatibilityToolFlavor;
+  path: string;
+  tag_name?: string;
+  // Where the tool is kept, unset if uninstalling it failed
+  trash?: string;
+  provenance?: Provenance;
+  mappings: MigratedMapping[];
+};
+
+export type MigratedMapping = {
+  app_id: number;
+  previous: string;
+};
+
+export type HistoryPage = {
+  records: HistoryRecord[];
+  total: number;
+};
+
+export type HistoryQuery = {
+  limit: number;
+  // Newest records skipped, used for paging
+  offset?: number;
+};
+
+export type ActivityQuery = {
+  limit: number;
+  before?: number;
+};
+
+export type ActivityEvent = {
+  timestamp: number;
+  source: ActivitySource;
+  change: ActivityChange;
+};
+
+export enum ActivitySource {
+  Task = "Task",
+  External = "External",
+}
+
+export type ActivityChange =
+  | { ToolInstalled: { internal_name: string; display_name: string } }
+  | { ToolRemoved: { internal_name: string; display_name: string } }
+  | {
+      ToolRenamed: {
+        internal_name: string;
+        old_display_name: string;
+        display_name: string;
+      };
+    }
+  | {
+      MappingChanged: {
+        app_id: number;
+        app_name: string;
+        from?: string;
+        to?: string;
+      };
+    };
+
+export type UndoEntry = {
+  id: number;
+  timestamp: number;
+  operation: UndoOperation;
+  invalidated: boolean;
+};
+
+export type UndoOperation =
+  | { SetMapping: { app_id: number; previous?: string; applied: string } }
+  | { ClearMapping: { app_id: number; previous: string } }
+  | { SetDefaultTool: { previous?: string; applied: string } };
+
+export type Provenance = {
+  internal_name: string;
+  flavor: CompatibilityToolFlavor;
+  tag_name: string;
+  naming_scheme?: string;
+  installed_at: number;
+  source: ProvenanceSource;
+  files?: FileManifestEntry[];
+};
+
+export type ProvenanceSource = {
+  asset_url: string;
+  asset_name: string;
+  size: number;
+  checksum?: string;
+  uploaded_at: string;
+  target_commitish: string;
+};
+
+export type FileManifestEntry = {
+  path: string;
+  size: number;
+  sha256: string;
+};
+
+export type Verification = {
+  internal_name: string;
+  verified: boolean;
+  mismatched: string[];
+  missing: string[];
+  added: string[];
+  error?: string;
 };
 
 export type Install = {
   flavor: CompatibilityToolFlavor;
   release: GitHubRelease;
+  ignore_network_cap?: boolean;
+  // Install even if the release doesn't seem to fit on disk
+  ignore_disk_space?: boolean;
+  background?: boolean;
+  accept_local_changes_loss?: boolean;
+  // Copy linked files instead of creating symlinks, for tools directories on NTFS or exFAT
+  copy_install?: boolean;
+  // Internal names of the tools uninstalled once this release is installed
+  replaces?: string[];
+  // Move the apps mapped to the replaced tools to this release, keeping the replaced tools in the trash to roll back to
+  migrate_mappings?: boolean;
+  // Largest archive downloaded in bytes, set for installs from URLs
+  max_size?: number;
+  // Archive on disk installed instead of downloading the release
+  local_path?: string;
+  // Restart Steam once installed so it lists the tool, unless a game is running
+  restart_steam?: boolean;
+  // What queued the install, kept in the history
+  trigger?: HistoryTrigger;
+};
+
+export type Migrate = {
+  from_root: string;
+  tool_names: string[];
 };
 
 export type Uninstall = {
   flavor: CompatibilityToolFlavor;
   steam_compatibility_tool: SteamCompatibilityTool;
+  force?: boolean;
+  accept_local_changes_loss?: boolean;
+  // Tool the apps still mapped to the uninstalled one are moved to when forced
+  replacement?: string;
 };
 
 export type SteamCompatibilityTool = {
@@ -78,17 +1024,81 @@ export type SteamCompatibilityTool = {
   internal_name: string;
   display_name: string;
   used_by_games: string[];
+  // Apps mapped to the tool, including those no longer installed
+  used_by_apps: SteamApp[];
   requires_restart: boolean;
+  supports_32bit: boolean;
   flavor: CompatibilityToolFlavor;
   github_release?: GitHubRelease;
+  modified_since_install?: LocalChanges;
+  // Missing until the tool was inspected in the background
+  inspection?: ToolInspection;
+  // Installed by the plugin or matching a release of its flavor, only managed tools are updated
+  managed: boolean;
+  // Release a managed tool was installed from, or the build of an official Proton release
+  version?: string;
+  // One of Valve's Proton releases in a library folder, Steam installs, updates and uninstalls it
+  official: boolean;
+  // Set for virtual tools, which run another tool with their own settings
+  virtual?: VirtualToolStatus;
+};
+
+export type VirtualToolStatus = {
+  // Display name of the tool it runs
+  base_tool: string;
+  // Output of the failed self-test, missing if the shim works
+  broken?: string;
+};
+
+// What a virtual tool runs and how
+export type VirtualToolSettings = {
+  // Display name, the internal and directory name are derived from it
+  name: string;
+  // Internal name of the tool it runs
+  base_tool: string;
+  // Exported before the base tool runs
+  environment?: Record<string, string>;
+};
+
+export type ToolInspection = {
+  // Size on disk, hard links counted once
+  bytes: number;
+  // Translation layers the tool ships, e.g. dxvk
+  components: string[];
+  // Installed by something else, e.g. ProtonUp
+  adoptable: boolean;
+};
+
+export type LocalChanges = {
+  changed: string[];
+  added: string[];
+  removed: string[];
 };
 
 export type QueueCompatibilityTool = {
+  // Id of the task installing it, tells apart the installs running at once
+  id: number;
   flavor: CompatibilityToolFlavor;
   name: string;
   url: string;
   state: QueueCompatibilityToolState;
+  size: number;
   progress: number;
+  constraints?: TaskConstraints;
+  // Download speed over the last second, while downloading
+  bytes_per_second?: number;
+  // Set once a download attempt failed and it's tried again
+  retry?: DownloadRetry;
+};
+export type DownloadRetry = {
+  attempt: number;
+  attempts: number;
+};
+export type TaskConstraints = {
+  niceness: number;
+  idle_io: boolean;
+  download_limit?: number;
+  running_game?: number;
 };
 
 export enum UpdaterState {
@@ -99,15 +1109,28 @@ export enum UpdaterState {
 export enum CompatibilityToolFlavor {
   Unknown = "Unknown",
   ProtonGE = "ProtonGE",
-  //SteamTinkerLaunch = "SteamTinkerLaunch",
+  SteamTinkerLaunch = "SteamTinkerLaunch",
   Luxtorpeda = "Luxtorpeda",
   Boxtron = "Boxtron",
+  Roberta = "Roberta",
+  ProtonTkg = "ProtonTkg",
+  WineGE = "WineGE",
+  Dxvk = "Dxvk",
+  Vkd3dProton = "Vkd3dProton",
 }
 
 export enum QueueCompatibilityToolState {
   Extracting = "Extracting",
   Downloading = "Downloading",
   Waiting = "Waiting",
+  // The download didn't match the published checksum and is downloaded again
+  ChecksumFailed = "ChecksumFailed",
+  // Waiting to try the download again after it failed
+  Retrying = "Retrying",
+  // Checking the archive against its published checksum
+  Verifying = "Verifying",
+  // Extracted, the tool is being checked and moved into place
+  Finalizing = "Finalizing",
 }
 
 export enum RequestType {
@@ -115,4 +1138,71 @@ export enum RequestType {
   RequestState = "RequestState",
   UpdateState = "UpdateState",
   Notification = "Notification",
+  UpdateSettings = "UpdateSettings",
+  GetToolProvenance = "GetToolProvenance",
+  ToolProvenance = "ToolProvenance",
+  VerifyInstalledTool = "VerifyInstalledTool",
+  Verification = "Verification",
+  GetActivity = "GetActivity",
+  Activity = "Activity",
+  GetStorageBreakdown = "GetStorageBreakdown",
+  StorageBreakdown = "StorageBreakdown",
+  ClearShaderCache = "ClearShaderCache",
+  StartupProgress = "StartupProgress",
+  ValidationError = "ValidationError",
+  GetUndoStack = "GetUndoStack",
+  UndoStack = "UndoStack",
+  UndoLast = "UndoLast",
+  Refresh = "Refresh",
+  RefreshCompleted = "RefreshCompleted",
+  GetMutationLog = "GetMutationLog",
+  MutationLog = "MutationLog",
+  SkipRelease = "SkipRelease",
+  UnskipRelease = "UnskipRelease",
+  PrioritizePrefixes = "PrioritizePrefixes",
+  CancelPrefixScan = "CancelPrefixScan",
+  PrefixScanned = "PrefixScanned",
+  PrefixScanCompleted = "PrefixScanCompleted",
+  CheckLocalChanges = "CheckLocalChanges",
+  Permissions = "Permissions",
+  SetQuickSlots = "SetQuickSlots",
+  SwitchQuickSlot = "SwitchQuickSlot",
+  CreatePlan = "CreatePlan",
+  Plan = "Plan",
+  ExecutePlan = "ExecutePlan",
+  PlanExecuted = "PlanExecuted",
+  PauseInspection = "PauseInspection",
+  ResumeInspection = "ResumeInspection",
+  ToolInspected = "ToolInspected",
+  InspectionCompleted = "InspectionCompleted",
+  SelectSteamInstallation = "SelectSteamInstallation",
+  CancelTask = "CancelTask",
+  TaskCancelled = "TaskCancelled",
+  UpdateSummary = "UpdateSummary",
+  UninstallBlocked = "UninstallBlocked",
+  ForceRefresh = "ForceRefresh",
+  InsufficientDiskSpace = "InsufficientDiskSpace",
+  UpdatesAvailable = "UpdatesAvailable",
+  AdoptTool = "AdoptTool",
+  ListOrphanedCompatData = "ListOrphanedCompatData",
+  OrphanedCompatData = "OrphanedCompatData",
+  GetPrefixSizes = "GetPrefixSizes",
+  PrefixSizes = "PrefixSizes",
+  RequestSnapshot = "RequestSnapshot",
+  StateDelta = "StateDelta",
+  Error = "Error",
+  Authenticate = "Authenticate",
+  Subscribe = "Subscribe",
+  Subscribed = "Subscribed",
+  Hello = "Hello",
+  GetInstalledApps = "GetInstalledApps",
+  InstalledApps = "InstalledApps",
+  GetShortcutDetails = "GetShortcutDetails",
+  ShortcutDetails = "ShortcutDetails",
+  SetShortcutLaunchOptions = "SetShortcutLaunchOptions",
+  GetHistory = "GetHistory",
+  History = "History",
+  ResolveRestoreConflict = "ResolveRestoreConflict",
+  CreateVirtualTool = "CreateVirtualTool",
+  RollbackUpdate = "RollbackUpdate",
 }
@@ -26,4 +26,9 @@ export class BackendCtx {
   static async commitSettings() {
     return await this.bridge("settings_commit");
   }
+
+  // The backend makes a new one every time it starts
+  static async getSessionToken(): Promise<string> {
+    return await this.bridge("get_session_token");
+  }
 }
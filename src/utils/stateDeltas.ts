@@ -0,0 +1,36 @@
+import { AppState, StateDelta } from "../types";
+
+// Applies the deltas of one revision the way the backend diffed them, in order
+export const applyStateDeltas = (
+  appState: AppState,
+  deltas: StateDelta[],
+): AppState => {
+  const state: any = { ...appState };
+  for (const delta of deltas) {
+    switch (delta.kind) {
+      case "SectionChanged":
+        state[delta.section] = delta.value;
+        break;
+      case "ToolsRemoved":
+        state[delta.section] = state[delta.section].filter(
+          (tool: { path: string }) => !delta.paths.includes(tool.path),
+        );
+        break;
+      case "ToolsChanged":
+        state[delta.section] = state[delta.section].map(
+          (tool: { path: string }) =>
+            delta.tools.find((changed) => changed.path == tool.path) ?? tool,
+        );
+        break;
+      case "ToolsAdded": {
+        const tools = [...state[delta.section]];
+        for (const added of delta.tools) {
+          tools.splice(added.index, 0, added.tool);
+        }
+        state[delta.section] = tools;
+        break;
+      }
+    }
+  }
+  return state;
+};
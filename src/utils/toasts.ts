@@ -1,11 +1,48 @@
 import { ServerAPI, ToastData } from "decky-frontend-lib";
 import { log, error } from "./logger";
-import { Request, RequestType } from "../types";
+import {
+  AppErrorCode,
+  PROTOCOL_VERSION,
+  Request,
+  RequestType,
+} from "../types";
 import { v4 as uuidv4 } from "uuid"; // Import UUID v4
+import { BackendCtx } from "./pythonBackendHelper";
 
 let shouldReconnect = true; // Global flag to control reconnection
 let socket: WebSocket | null = null; // Global WebSocket reference
 
+// What the user can do about each kind of failure
+const errorHints: Record<AppErrorCode, string> = {
+  [AppErrorCode.NetworkError]: "Check the connection and try again",
+  [AppErrorCode.DiskFull]: "Free up some space and try again",
+  [AppErrorCode.ChecksumMismatch]: "The download was corrupted, try again",
+  [AppErrorCode.SteamNotFound]: "Make sure Steam is installed",
+  [AppErrorCode.VdfParse]: "A Steam file couldn't be read, restart Steam",
+  [AppErrorCode.Internal]: "See the plugin's log for details",
+  [AppErrorCode.Unauthenticated]: "Reload the plugin to reconnect",
+  [AppErrorCode.IncompatibleVersion]:
+    "Reload the plugin so its frontend and backend match",
+  [AppErrorCode.UnknownRequestType]:
+    "Reload the plugin so its frontend and backend match",
+  [AppErrorCode.OperationBlockedGameRunning]: "Close the game and try again",
+  [AppErrorCode.OperationBlockedSteamRunning]: "Exit Steam and try again",
+  [AppErrorCode.NotReady]: "Wait for the plugin to finish starting",
+  [AppErrorCode.InvalidRequest]:
+    "Reload the plugin so its frontend and backend match",
+  [AppErrorCode.Forbidden]: "This connection isn't allowed to do that",
+  [AppErrorCode.FeatureDisabled]: "Turn the feature on in the settings first",
+  [AppErrorCode.NotFound]: "Refresh the list and try again",
+  [AppErrorCode.ToolInUse]: "Move the apps using it to another tool first",
+  [AppErrorCode.LocalChanges]:
+    "Back up your changes, then allow discarding them",
+  [AppErrorCode.UnsupportedFilesystem]:
+    "Allow installing copies instead of symlinks in the settings",
+  [AppErrorCode.ManagedBySteam]: "Change it from the Steam library",
+  [AppErrorCode.NetworkCapReached]:
+    "Raise the monthly network cap or wait for next month",
+};
+
 export const setupToasts = (serverAPI: ServerAPI): void => {
   const setupWebsocket = (): void => {
     if (!shouldReconnect) {
@@ -15,8 +52,27 @@ export const setupToasts = (serverAPI: ServerAPI): void => {
     socket = new WebSocket("ws://localhost:8887");
     const uniqueId = uuidv4(); // Generate a unique identifier using UUID
 
-    socket.onopen = (): void => {
+    socket.onopen = async (): Promise<void> => {
       log("WebSocket connection established. Unique Identifier: ", uniqueId);
+      socket?.send(
+        JSON.stringify({
+          type: RequestType.Hello,
+          protocol_version: PROTOCOL_VERSION,
+        }),
+      );
+      socket?.send(
+        JSON.stringify({
+          type: RequestType.Authenticate,
+          session_token: await BackendCtx.getSessionToken(),
+        }),
+      );
+      // Only what is shown as toasts, not the state the plugin's page renders
+      socket?.send(
+        JSON.stringify({
+          type: RequestType.Subscribe,
+          subscriptions: ["tasks", "installed_tools", "logs"],
+        }),
+      );
     };
 
     socket.onmessage = (e: MessageEvent): void => {
@@ -25,13 +81,53 @@ export const setupToasts = (serverAPI: ServerAPI): void => {
         if (response.notification != null && response.notification != "") {
           let toastData: ToastData = {
             title: "Wine Cellar",
-            body: response.notification,
+            body: response.steam_restart_required
+              ? response.notification + ". Restart Steam to use it"
+              : response.notification,
             showToast: true,
           };
 
           serverAPI.toaster.toast(toastData);
           log("Received backend notification: " + response.notification);
         }
+      } else if (
+        response.type == RequestType.Error &&
+        response.app_error != null
+      ) {
+        const appError = response.app_error;
+        serverAPI.toaster.toast({
+          title: "Wine Cellar: Error",
+          body: appError.message + ". " + errorHints[appError.code],
+          showToast: true,
+        });
+        log("Received backend error: " + appError.code, appError.message);
+      } else if (
+        response.type == RequestType.UpdateSummary &&
+        response.update_summary != null
+      ) {
+        const summary = response.update_summary;
+        serverAPI.toaster.toast({
+          title: "Wine Cellar",
+          body:
+            summary.queued.length == 0
+              ? "Every compatibility tool is up to date"
+              : "Updating to " +
+                summary.queued.map((queued) => queued.tag_name).join(", "),
+          showToast: true,
+        });
+      } else if (
+        response.type == RequestType.UpdatesAvailable &&
+        response.available_updates != null
+      ) {
+        serverAPI.toaster.toast({
+          title: "Wine Cellar",
+          body:
+            "Updates available: " +
+            response.available_updates
+              .map((update) => update.name + " → " + update.newest_version)
+              .join(", "),
+          showToast: true,
+        });
       }
     };
 